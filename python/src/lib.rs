@@ -0,0 +1,362 @@
+// Python bindings for the rusty-neat simulation engine. The module exposes two functions:
+//
+//     simulate(reference, ...) runs a full simulation and writes the usual output files,
+//         returning a dict with the paths written.
+//     simulate_arrays(reference, ...) runs the simulation in memory and returns the reads and
+//         truth variants as numpy arrays.
+//
+// Both take an optional seed string, so results are reproducible from Python the same way they
+// are from the command line.
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray1};
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use neat_core::utils::make_reads::Strand;
+use neat_core::utils::nucleotides::u8_to_base;
+use neat_core::utils::quality_scores::QualityScoreModel;
+use neat_core::{SimulatedSample, Simulation, SimulationBuilder};
+
+#[allow(clippy::too_many_arguments)]
+fn builder_from_args(
+    reference: &str,
+    coverage: usize,
+    read_length: usize,
+    mutation_rate: f64,
+    somatic_mutation_rate: Option<f64>,
+    ploidy: usize,
+    fragment_mean: Option<f64>,
+    fragment_st_dev: Option<f64>,
+    seed: Option<&str>,
+) -> PyResult<SimulationBuilder> {
+    let mut builder = Simulation::builder()
+        .reference(reference)
+        .coverage(coverage)
+        .read_length(read_length)
+        .mutation_rate(mutation_rate)
+        .ploidy(ploidy);
+    if let Some(rate) = somatic_mutation_rate {
+        builder = builder.somatic_mutation_rate(rate);
+    }
+    match (fragment_mean, fragment_st_dev) {
+        (Some(mean), Some(st_dev)) => builder = builder.paired_ended(mean, st_dev),
+        (None, None) => {},
+        _ => return Err(PyRuntimeError::new_err(
+            "fragment_mean and fragment_st_dev must be given together for paired ended reads"
+        )),
+    }
+    if let Some(seed) = seed {
+        builder = builder.rng_seed(seed);
+    }
+    Ok(builder)
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    reference,
+    *,
+    coverage = 10,
+    read_length = 150,
+    mutation_rate = 0.001,
+    somatic_mutation_rate = None,
+    ploidy = 2,
+    fragment_mean = None,
+    fragment_st_dev = None,
+    seed = None,
+    output_dir = None,
+    output_prefix = "neat_out",
+    produce_fastq = true,
+    produce_fasta = false,
+    produce_vcf = true,
+    produce_bam = false,
+    overwrite_output = false,
+))]
+#[allow(clippy::too_many_arguments)]
+fn simulate<'py>(
+    py: Python<'py>,
+    reference: &str,
+    coverage: usize,
+    read_length: usize,
+    mutation_rate: f64,
+    somatic_mutation_rate: Option<f64>,
+    ploidy: usize,
+    fragment_mean: Option<f64>,
+    fragment_st_dev: Option<f64>,
+    seed: Option<&str>,
+    output_dir: Option<&str>,
+    output_prefix: &str,
+    produce_fastq: bool,
+    produce_fasta: bool,
+    produce_vcf: bool,
+    produce_bam: bool,
+    overwrite_output: bool,
+) -> PyResult<Bound<'py, PyDict>> {
+    // Runs a simulation that writes files, exactly like the command line tool.
+    let mut builder = builder_from_args(
+        reference,
+        coverage,
+        read_length,
+        mutation_rate,
+        somatic_mutation_rate,
+        ploidy,
+        fragment_mean,
+        fragment_st_dev,
+        seed,
+    )?
+        .output_prefix(output_prefix)
+        .produce_fastq(produce_fastq)
+        .produce_fasta(produce_fasta)
+        .produce_vcf(produce_vcf)
+        .produce_bam(produce_bam)
+        .overwrite_output(overwrite_output);
+    if let Some(output_dir) = output_dir {
+        builder = builder.output_dir(output_dir);
+    }
+    // The simulation doesn't touch Python objects, so other Python threads can run meanwhile.
+    let output = py.detach(|| builder.run())
+        .map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+
+    let result = PyDict::new(py);
+    let fastq_files: Vec<String> = output.fastq_files
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    result.set_item("fastq_files", fastq_files)?;
+    result.set_item("manifest_file", output.manifest_file.map(|path| path.display().to_string()))?;
+    result.set_item("fasta_file", output.fasta_file.map(|path| path.display().to_string()))?;
+    result.set_item("vcf_file", output.vcf_file.map(|path| path.display().to_string()))?;
+    result.set_item("bam_file", output.bam_file.map(|path| path.display().to_string()))?;
+    result.set_item("summary_file", output.summary_file.map(|path| path.display().to_string()))?;
+    result.set_item("num_reads", output.num_reads)?;
+    Ok(result)
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    reference,
+    *,
+    coverage = 10,
+    read_length = 150,
+    mutation_rate = 0.001,
+    somatic_mutation_rate = None,
+    ploidy = 2,
+    fragment_mean = None,
+    fragment_st_dev = None,
+    seed = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn simulate_arrays<'py>(
+    py: Python<'py>,
+    reference: &str,
+    coverage: usize,
+    read_length: usize,
+    mutation_rate: f64,
+    somatic_mutation_rate: Option<f64>,
+    ploidy: usize,
+    fragment_mean: Option<f64>,
+    fragment_st_dev: Option<f64>,
+    seed: Option<&str>,
+) -> PyResult<Bound<'py, PyDict>> {
+    // Runs a simulation in memory. Reads come back as a (num_reads x read_length) uint8 array of
+    // ASCII bases (shorter reads padded with N) and a matching array of phred scores, plus one
+    // entry per read in each of the per-read arrays. Variants come back as parallel arrays.
+    let builder = builder_from_args(
+        reference,
+        coverage,
+        read_length,
+        mutation_rate,
+        somatic_mutation_rate,
+        ploidy,
+        fragment_mean,
+        fragment_st_dev,
+        seed,
+    )?;
+    // The simulation doesn't touch Python objects, so other Python threads can run meanwhile.
+    let arrays = py.detach(|| {
+        let sample = builder
+            .build()
+            .and_then(|simulation| simulation.generate(&QualityScoreModel::default_model()))
+            .map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+        sample_arrays(&sample, read_length)
+    })?;
+
+    let result = PyDict::new(py);
+    result.set_item("contig_names", arrays.contig_names)?;
+    result.set_item("read_names", arrays.read_names)?;
+    result.set_item("sequences", arrays.sequences.into_pyarray(py))?;
+    result.set_item("qualities", arrays.qualities.into_pyarray(py))?;
+    result.set_item("lengths", PyArray1::from_vec(py, arrays.lengths))?;
+    result.set_item("contigs", PyArray1::from_vec(py, arrays.contigs))?;
+    result.set_item("starts", PyArray1::from_vec(py, arrays.starts))?;
+    result.set_item("mates", PyArray1::from_vec(py, arrays.mates))?;
+    result.set_item("reverse", PyArray1::from_vec(py, arrays.reverse))?;
+    result.set_item("variant_contigs", PyArray1::from_vec(py, arrays.variant_contigs))?;
+    result.set_item("variant_positions", PyArray1::from_vec(py, arrays.variant_positions))?;
+    result.set_item("variant_refs", PyArray1::from_vec(py, arrays.variant_refs))?;
+    result.set_item("variant_alts", PyArray1::from_vec(py, arrays.variant_alts))?;
+    result.set_item("variant_somatic", PyArray1::from_vec(py, arrays.variant_somatic))?;
+    Ok(result)
+}
+
+struct SampleArrays {
+    // The arrays simulate_arrays hands back, before they are turned into numpy arrays. Each
+    // field is returned under its own name.
+    contig_names: Vec<String>,
+    read_names: Vec<String>,
+    sequences: Array2<u8>,
+    qualities: Array2<u8>,
+    lengths: Vec<u64>,
+    contigs: Vec<u64>,
+    starts: Vec<u64>,
+    mates: Vec<u8>,
+    reverse: Vec<bool>,
+    variant_contigs: Vec<u64>,
+    variant_positions: Vec<u64>,
+    variant_refs: Vec<u8>,
+    variant_alts: Vec<u8>,
+    variant_somatic: Vec<bool>,
+}
+
+fn sample_arrays(sample: &SimulatedSample, read_length: usize) -> PyResult<SampleArrays> {
+    // Lays the reads and variants of a sample out as arrays, a row or entry per read or variant.
+    let num_reads = sample.reads.len();
+    let mut sequences: Vec<u8> = vec![b'N'; num_reads * read_length];
+    let mut qualities: Vec<u8> = vec![0; num_reads * read_length];
+    let mut lengths: Vec<u64> = Vec::with_capacity(num_reads);
+    let mut contigs: Vec<u64> = Vec::with_capacity(num_reads);
+    let mut starts: Vec<u64> = Vec::with_capacity(num_reads);
+    let mut mates: Vec<u8> = Vec::with_capacity(num_reads);
+    let mut reverse: Vec<bool> = Vec::with_capacity(num_reads);
+    let mut read_names: Vec<String> = Vec::with_capacity(num_reads);
+    for (row, read) in sample.reads.iter().enumerate() {
+        let offset = row * read_length;
+        for (column, (base, quality)) in read.seq.iter().zip(&read.quals).enumerate() {
+            sequences[offset + column] = u8_to_base(*base) as u8;
+            qualities[offset + column] = *quality as u8;
+        }
+        lengths.push(read.seq.len() as u64);
+        contigs.push(
+            sample.contig_order.iter().position(|name| *name == read.contig).unwrap() as u64
+        );
+        starts.push(read.start as u64);
+        mates.push(read.mate);
+        reverse.push(read.strand == Strand::Reverse);
+        read_names.push(read.name.clone());
+    }
+    let shape = (num_reads, read_length);
+    let sequences = Array2::from_shape_vec(shape, sequences)
+        .map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+    let qualities = Array2::from_shape_vec(shape, qualities)
+        .map_err(|error| PyRuntimeError::new_err(error.to_string()))?;
+
+    let mut variant_contigs: Vec<u64> = Vec::new();
+    let mut variant_positions: Vec<u64> = Vec::new();
+    let mut variant_refs: Vec<u8> = Vec::new();
+    let mut variant_alts: Vec<u8> = Vec::new();
+    let mut variant_somatic: Vec<bool> = Vec::new();
+    for (index, contig) in sample.contig_order.iter().enumerate() {
+        for variant in sample.variants.get(contig).into_iter().flatten() {
+            variant_contigs.push(index as u64);
+            variant_positions.push(variant.position as u64);
+            variant_refs.push(u8_to_base(variant.reference) as u8);
+            variant_alts.push(u8_to_base(variant.alt) as u8);
+            variant_somatic.push(variant.is_somatic());
+        }
+    }
+    Ok(SampleArrays {
+        contig_names: sample.contig_order.clone(),
+        read_names,
+        sequences,
+        qualities,
+        lengths,
+        contigs,
+        starts,
+        mates,
+        reverse,
+        variant_contigs,
+        variant_positions,
+        variant_refs,
+        variant_alts,
+        variant_somatic,
+    })
+}
+
+#[pymodule]
+fn rusty_neat(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(simulate, module)?)?;
+    module.add_function(wrap_pyfunction!(simulate_arrays, module)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn h1n1_builder(
+        fragment_mean: Option<f64>,
+        fragment_st_dev: Option<f64>,
+    ) -> PyResult<SimulationBuilder> {
+        builder_from_args(
+            "../test_data/H1N1.fa",
+            2,
+            100,
+            0.001,
+            Some(0.001),
+            2,
+            fragment_mean,
+            fragment_st_dev,
+            Some("Hello Cruel World"),
+        )
+    }
+
+    #[test]
+    fn test_builder_from_args() {
+        let simulation = h1n1_builder(Some(250.0), Some(10.0)).unwrap().build().unwrap();
+        let config = simulation.config();
+        assert_eq!((config.coverage, config.read_len, config.ploidy), (2, 100, 2));
+        assert_eq!(config.somatic_mutation_rate, Some(0.001));
+        assert!(config.paired_ended);
+        assert_eq!((config.fragment_mean, config.fragment_st_dev), (Some(250.0), Some(10.0)));
+        assert_eq!(config.rng_seed.as_deref(), Some("Hello Cruel World"));
+        assert!(h1n1_builder(Some(250.0), None).is_err());
+    }
+
+    #[test]
+    fn test_sample_arrays() {
+        let sample = h1n1_builder(Some(250.0), Some(10.0))
+            .unwrap()
+            .build()
+            .unwrap()
+            .generate(&QualityScoreModel::default_model())
+            .unwrap();
+        let arrays = sample_arrays(&sample, 100).unwrap();
+        let num_reads = sample.reads.len();
+        assert!(num_reads > 0);
+        let sequences: &Array2<u8> = &arrays.sequences;
+        let qualities: &Array2<u8> = &arrays.qualities;
+        assert_eq!(sequences.dim(), (num_reads, 100));
+        assert_eq!(qualities.dim(), (num_reads, 100));
+        assert!(sequences.iter().all(|base| b"ACGTN".contains(base)));
+        let per_read = [
+            arrays.read_names.len(),
+            arrays.lengths.len(),
+            arrays.contigs.len(),
+            arrays.starts.len(),
+            arrays.mates.len(),
+            arrays.reverse.len(),
+        ];
+        assert!(per_read.iter().all(|length| *length == num_reads));
+        assert!(arrays.mates.iter().all(|mate| [1, 2].contains(mate)));
+        assert_eq!(arrays.contig_names, sample.contig_order);
+        let num_variants: usize = sample.variants.values().map(|variants| variants.len()).sum();
+        let per_variant = [
+            arrays.variant_contigs.len(),
+            arrays.variant_positions.len(),
+            arrays.variant_refs.len(),
+            arrays.variant_alts.len(),
+            arrays.variant_somatic.len(),
+        ];
+        assert!(per_variant.iter().all(|length| *length == num_variants));
+    }
+}
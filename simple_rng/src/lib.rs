@@ -178,34 +178,57 @@ pub struct DiscreteDistribution {
 }
 
 impl DiscreteDistribution {
-    pub fn new <T>(w_vec: &Vec<T>, degenerate: bool) -> Self
+    pub fn new <T>(w_vec: &[T], degenerate: bool) -> Self
         where
-            f64: From<T>, T: Copy,
             T: Into<f64> + Copy,
     {
-        // let's first convert weights to f64
-        let mut w_vec_64: Vec<f64> = Vec::with_capacity(w_vec.len());
-        for number in w_vec {
-            w_vec_64.push(f64::from(*number).into());
-        }
-        let cumulative_probability = if !degenerate {
-            let sum_weights: f64 = w_vec_64.iter().sum();
-            let mut normalized_weights = Vec::with_capacity(w_vec.len());
-            // we no longer need the w_vec_64 after this, so we consume it
-            for weight in w_vec_64 {
-                normalized_weights.push(weight / sum_weights);
-            }
-            cumulative_sum(&mut normalized_weights)
-        } else {
-            vec![1.0]
+        // A degenerate distribution always samples index 0, whatever the weights.
+        let mut distribution = DiscreteDistribution {
+            degenerate,
+            cumulative_probability: vec![1.0],
         };
+        if !degenerate {
+            distribution.update_weights(w_vec);
+        }
+        distribution
+    }
 
-        DiscreteDistribution {
-            degenerate,
-            cumulative_probability,
+    pub fn update_weights<T>(&mut self, w_vec: &[T])
+        where
+            T: Into<f64> + Copy,
+    {
+        // Rebuilds the distribution from new weights, in place, reusing the cumulative vector.
+        // The weights needn't be normalized (e.g., counts with a pseudo-count added to each), and
+        // a degenerate distribution stops being one.
+        let sum_weights: f64 = w_vec.iter().map(|weight| (*weight).into()).sum();
+        self.degenerate = false;
+        self.cumulative_probability.clear();
+        let mut acc = 0.0;
+        for weight in w_vec {
+            acc += (*weight).into() / sum_weights;
+            self.cumulative_probability.push(acc);
         }
     }
 
+    pub fn cumulative_probability(&self) -> &[f64] {
+        // The cumulative probability of each index, ending at 1.
+        &self.cumulative_probability
+    }
+
+    pub fn weights(&self) -> Vec<f64> {
+        // The normalized weight (probability) of each index. A degenerate distribution has a
+        // single index, with all the weight.
+        let mut previous = 0.0;
+        self.cumulative_probability
+            .iter()
+            .map(|cumulative| {
+                let weight = cumulative - previous;
+                previous = *cumulative;
+                weight
+            })
+            .collect()
+    }
+
     pub fn sample(&self, rng: &mut Rng) -> usize {
         // returns a random index for the distribution, based on cumulative probability
         // This is basically an icdf for a discrete distribution
@@ -227,16 +250,6 @@ impl DiscreteDistribution {
     }
 }
 
-fn cumulative_sum(a: &mut Vec<f64>) -> Vec<f64> {
-    let mut acc = 0.0;
-    let mut cumvec = Vec::with_capacity(a.len());
-    for x in a {
-        acc += *x;
-        cumvec.push(acc);
-    };
-    cumvec
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -259,6 +272,26 @@ mod tests {
         assert_eq!(x, 1);
     }
 
+    #[test]
+    fn test_discrete_distribution_weights() {
+        let weights: Vec<u32> = vec![1, 3, 0, 4];
+        let mut d = DiscreteDistribution::new(&weights, false);
+        assert_eq!(d.weights(), vec![0.125, 0.375, 0.0, 0.5]);
+        assert_eq!(d.cumulative_probability(), &[0.125, 0.5, 0.5, 1.0]);
+        // Smoothing with a pseudo-count gives the empty index a chance
+        let smoothed: Vec<f64> = d.weights().iter().map(|weight| weight + 0.25).collect();
+        d.update_weights(&smoothed);
+        assert_eq!(d.weights(), vec![0.1875, 0.3125, 0.125, 0.375]);
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Weights".to_string()]);
+        let draws = (0..1000).filter(|_| d.sample(&mut rng) == 2).count();
+        assert!((80..170).contains(&draws), "{}", draws);
+
+        let mut degenerate = DiscreteDistribution::new(&weights, true);
+        assert_eq!(degenerate.weights(), vec![1.0]);
+        degenerate.update_weights(&[0.0, 1.0]);
+        assert_eq!(degenerate.sample(&mut rng), 1);
+    }
+
     #[test]
     fn test_gen_bool() {
         let mut rng = Rng::new_from_seed(vec![
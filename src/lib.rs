@@ -0,0 +1,19 @@
+// neat_core is the simulation engine behind the rusty-neat binary. Everything the command line
+// tool can do is reachable from here, so other Rust tools (and tests) can drive a simulation
+// directly instead of shelling out to the binary.
+//
+// The simplest entry point is the Simulation builder:
+//
+//     let output = Simulation::builder()
+//         .reference("test_data/H1N1.fa")
+//         .coverage(5)
+//         .run()?;
+//
+// For finer control, the individual steps (read_fasta, mutate_fasta, generate_reads, and the
+// writers) live under utils.
+pub mod utils;
+pub mod simulation;
+
+pub use simulation::{Simulation, SimulationBuilder, SimulatedSample};
+pub use utils::errors::NeatError;
+pub use utils::runner::RunOutput;
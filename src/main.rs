@@ -8,40 +8,62 @@ extern crate serde;
 extern crate simple_rng;
 extern crate statrs;
 
-mod utils;
-
-use chrono::Utc;
 use std::fs::File;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::process;
 use clap::{Parser};
 use log::*;
 use simplelog::*;
-use utils::cli;
-use utils::config::{read_config_yaml, build_config_from_args};
-use utils::file_tools::check_parent;
-use utils::runner::run_neat;
-use simple_rng::Rng;
+use neat_core::utils::cli;
+use neat_core::utils::config::{read_config_yaml_checked, build_config_from_args};
+use neat_core::utils::demo::demo_config;
+use neat_core::utils::errors::{write_error_report, NeatError, EXIT_INTERNAL};
+use neat_core::utils::file_tools::check_parent;
+use neat_core::utils::quality_scores::compress_quality_model;
+use neat_core::utils::runner::{
+    create_rng, resolve_seed, run_neat, run_replicates, run_sweep, run_timepoints,
+};
+use neat_core::utils::self_check::{self_test, verify_determinism};
 
-fn main() {
+fn report_failure(report_path: Option<&Path>, class: &str, exit_code: i32, message: &str) -> ! {
+    // Logs the failure, writes the error report if one was asked for, and exits with the code
+    // for this class of failure.
+    error!("Neat encountered a problem: {}", message);
+    if let Some(path) = report_path {
+        if let Err(error) = write_error_report(path, class, exit_code, message) {
+            error!("Could not write error report {}: {}", path.display(), error);
+        }
+    }
+    process::exit(exit_code)
+}
 
-    info!("Begin processing");
-    // parse the arguments from the command line
-    let args = cli::Cli::parse();
-    // log filter
-    let level_filter = match args.log_level.to_lowercase().as_str() {
-        "trace" => LevelFilter::Trace,
-        "debug" => LevelFilter::Debug,
-        "info" => LevelFilter::Info,
-        "warn" => LevelFilter::Warn,
-        "error" => LevelFilter::Error,
-        "off" => LevelFilter::Off,
-        _ => panic!(
-            "Unknown log level, please set to one of \
-            Trace, Debug, Info, Warn, Error, or Off (case insensitive)."
-        )
-    };
+fn fail(error: NeatError, report_path: Option<&Path>) -> ! {
+    report_failure(report_path, error.class(), error.exit_code(), &error.to_string())
+}
+
+fn level_filter(log_level: &str) -> Result<LevelFilter, NeatError> {
+    match log_level.to_lowercase().as_str() {
+        "trace" => Ok(LevelFilter::Trace),
+        "debug" => Ok(LevelFilter::Debug),
+        "info" => Ok(LevelFilter::Info),
+        "warn" => Ok(LevelFilter::Warn),
+        "error" => Ok(LevelFilter::Error),
+        "off" => Ok(LevelFilter::Off),
+        _ => Err(NeatError::Config(format!(
+            "Unknown log level {}, please set to one of \
+            Trace, Debug, Info, Warn, Error, or Off (case insensitive).", log_level
+        ))),
+    }
+}
+
+fn init_logging(log_level: &str, log_dest: &str) -> Result<(), NeatError> {
+    // Sets up the logger for the run, to the terminal and to the log file.
+    let level_filter = level_filter(log_level)?;
     // Check that the parent dir exists
-    let log_destination = check_parent(&args.log_dest).unwrap();
-    // Set up the logger for the run
+    let log_destination = check_parent(log_dest)?;
+    let log_file = File::create(log_destination)?;
     CombinedLogger::init(vec![
         TermLogger::new(
             level_filter,
@@ -50,47 +72,117 @@ fn main() {
             ColorChoice::Always,
         ),
         SimpleLogger::new(LevelFilter::Trace, Config::default()),
-        WriteLogger::new(
-            level_filter,
-            Config::default(),
-            File::create(log_destination).unwrap(),
-        )
-    ]).unwrap();
+        WriteLogger::new(level_filter, Config::default(), log_file),
+    ]).map_err(|error| NeatError::Io(io::Error::other(error)))
+}
+
+fn main() {
+
+    info!("Begin processing");
+    // parse the arguments from the command line
+    let args = cli::Cli::parse();
+    // The logger isn't set up yet, so a problem setting it up is printed instead.
+    if let Err(error) = init_logging(&args.log_level, &args.log_dest) {
+        eprintln!("Neat encountered a problem: {}", error);
+        process::exit(error.exit_code())
+    }
+    // Subcommands do their own thing and exit.
+    if let Some(command) = &args.command {
+        let result = match command {
+            cli::Command::VerifyDeterminism { config, threads } => {
+                info!("Checking determinism of {}", config);
+                read_config_yaml_checked(config.clone(), args.strict_config)
+                    .and_then(|config| verify_determinism(&config, *threads))
+                    .map(|_| ())
+            },
+            cli::Command::SelfTest { temp_dir } => self_test(temp_dir.as_deref().map(Path::new)),
+            cli::Command::CompressQualityModel { input, output, min_fraction, bits } => {
+                compress_quality_model(input, output, *min_fraction, *bits)
+            },
+        };
+        if let Err(error) = result {
+            fail(error, None)
+        }
+        return
+    }
+    // Until the config is read, the command line is our best guess at where output should go.
+    let mut report_path = args.error_report.then(|| {
+        let output_dir = if args.output_dir.is_empty() { "." } else { &args.output_dir };
+        Path::new(output_dir).join(format!("{}_error.json", args.output_file_prefix))
+    });
+    // Replicates apply whichever way the configuration is given.
+    let replicates = args.replicates;
     // set up the config struct based on whether there was an input config. Input config
     // overrides any other inputs.
-    let config = if args.config != "" {
+    let mut config = if args.demo {
+        let output_dir = if args.output_dir.is_empty() { "neat_demo" } else { &args.output_dir };
+        info!("Running the demo into {}", output_dir);
+        demo_config(Path::new(output_dir))
+    } else if args.config != "" {
         info!("Using Configuration file input: {}", &args.config);
-        read_config_yaml(args.config)
+        read_config_yaml_checked(args.config, args.strict_config)
     } else {
         info!("Using command line arguments.");
         debug!("Command line args: {:?}", &args);
         build_config_from_args(args)
+    }.unwrap_or_else(|error| fail(error, report_path.as_deref()));
+    if report_path.is_some() {
+        report_path = Some(config.output_dir.join(format!("{}_error.json", config.output_prefix)));
+    }
+    if replicates.is_some() && !config.sweep.is_empty() {
+        fail(
+            NeatError::Config("--replicates can't be combined with a sweep.".to_string()),
+            report_path.as_deref(),
+        )
+    }
+    if replicates.is_some() && config.timepoints.is_some() {
+        fail(
+            NeatError::Config("--replicates can't be combined with timepoints.".to_string()),
+            report_path.as_deref(),
+        )
+    }
+    // run the generate reads main script. A panic is a bug, but we still want it reported (and
+    // staged output cleaned up) like any other failure.
+    let run = move || match replicates {
+        Some(replicates) => run_replicates(&config, replicates).map(|_| ()),
+        None if !config.sweep.is_empty() => run_sweep(&config).map(|_| ()),
+        None if config.timepoints.is_some() => run_timepoints(&config).map(|_| ()),
+        None => {
+            // Generate the RNG used for this run. If no seed was given, we generate one using the
+            // current time
+            resolve_seed(&mut config);
+            let mut rng = create_rng(config.rng_seed.as_deref());
+            run_neat(config, &mut rng).map(|_| ())
+        },
     };
-    // Generate the RNG used for this run. If not we generate a random seed using the current time
-    let mut seed_vec: Vec<String> = Vec::new();
-    if !config.rng_seed.is_none() {
-        // Force read it as a string, hopefully
-        let raw_seed = config.rng_seed.clone().unwrap().to_string();
-        for seed_term in raw_seed.split_whitespace() {
-            seed_vec.push(seed_term.to_string());
-        }
-        info!("Seed string to regenerate these exact results: {}", raw_seed);
-    } else {
-        // since no seed was provided, we'll use the datetime stamp
-        info!(
-            "No rng seed provided, using timestamp (and space-separated list of words with simple \
-            characters will also work as a key)"
-        );
-        let timestamp = Utc::now().format("%Y %m %d %H %M %S %f").to_string();
-        for item in timestamp.split_whitespace() {
-            seed_vec.push(item.to_string());
+    match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(Ok(_)) => (),
+        Ok(Err(error)) => fail(error, report_path.as_deref()),
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            report_failure(
+                report_path.as_deref(),
+                "internal",
+                EXIT_INTERNAL,
+                &format!("internal error (please report this): {}", message),
+            )
         }
-        info!("Seed string to regenerate these exact results: {}", timestamp);
     }
-    let mut rng: Rng = Rng::new_from_seed(seed_vec);
-    // run the generate reads main script
-    run_neat(config, &mut rng).unwrap_or_else(|error| {
-        panic!("Neat encountered a problem: {:?}", error)
-    })
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_logging_errors() {
+        assert_eq!(level_filter("WARN").unwrap(), LevelFilter::Warn);
+        let bad_level = init_logging("loud", "neat_test.log");
+        assert!(matches!(bad_level, Err(NeatError::Config(_))));
+        let bad_dest = init_logging("info", "no_such_dir/neat_test.log");
+        assert!(matches!(bad_dest, Err(NeatError::Io(_))));
+    }
+}
@@ -0,0 +1,853 @@
+// A builder-style front end for running a simulation from code. It fills in a ConfigBuilder,
+// so the defaults and checks are exactly those the command line tool uses.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use simple_rng::Rng;
+use super::utils::config::{ConfigBuilder, RunConfiguration};
+use super::utils::errors::NeatError;
+use super::utils::fastq_tools::ReadNameFormat;
+use super::utils::heteroplasmy::heteroplasmic_sites;
+use super::utils::index_reads::SampleIndex;
+use super::utils::make_reads::SimulatedRead;
+use super::utils::mutate::haplotype_sequences;
+use super::utils::nucleotides::NucModel;
+use super::utils::ploidy::Sex;
+use super::utils::read_shards::{
+    contig_seeds, generate_contig_reads, select_contig, ContigSequences,
+};
+use super::utils::quality_scores::QualityScoreModel;
+use super::utils::runner::{
+    create_rng, ffpe_artifacts, fill_reference_gaps, load_annotations, load_capture_model,
+    load_checked_reference, load_coverage_bam, load_coverage_profile, load_error_profile,
+    load_known_sites, load_low_complexity, load_model_bundle, load_ploidy_map,
+    load_sample_haplotypes, load_signatures, load_targets, mutate_reference, resolve_seed,
+    run_neat, RunOutput,
+};
+use super::utils::variants::Variant;
+
+#[derive(Debug)]
+pub struct SimulatedSample {
+    // The in-memory result of a simulation: the truth variants and the reads, with nothing
+    // written to disk.
+    //
+    // contig_order: Contig names in reference order.
+    // variants: Every variant added, keyed by contig name.
+    // reads: Every read generated, mates back to back, in generation order.
+    pub contig_order: Vec<String>,
+    pub variants: HashMap<String, Vec<Variant>>,
+    pub reads: Vec<SimulatedRead>,
+}
+
+pub struct Simulation {
+    config: RunConfiguration,
+}
+
+impl Simulation {
+    pub fn builder() -> SimulationBuilder {
+        SimulationBuilder::new()
+    }
+
+    pub fn config(&self) -> &RunConfiguration {
+        &self.config
+    }
+
+    pub fn run(mut self) -> Result<RunOutput, NeatError> {
+        // Runs the simulation, seeding the rng from the configured seed (or the clock if there
+        // wasn't one).
+        resolve_seed(&mut self.config);
+        let mut rng = create_rng(self.config.rng_seed.as_deref());
+        run_neat(Box::new(self.config), &mut rng)
+    }
+
+    pub fn generate(
+        mut self,
+        quality_score_model: &QualityScoreModel,
+    ) -> Result<SimulatedSample, NeatError> {
+        // Runs the simulation in memory. Output settings are ignored; the reads and variants are
+        // handed back instead. A model bundle's quality model takes the place of
+        // quality_score_model.
+        let models = load_model_bundle(&mut self.config)?;
+        let quality_score_model = models.quality.as_ref().unwrap_or(quality_score_model);
+        quality_score_model.check_shape("the quality score model")?;
+        let germline_model = models.mutation.clone().unwrap_or_else(NucModel::new);
+        let mut rng = create_rng(self.config.rng_seed.as_deref());
+        let (fasta_map, contig_order) = load_checked_reference(&self.config)?;
+        let coverage_bam = load_coverage_bam(&mut self.config, &fasta_map)?;
+        let filled_reference =
+            fill_reference_gaps(&self.config, &fasta_map, &contig_order, &mut rng);
+        let fasta_map = filled_reference.map_or(fasta_map, |(filled_map, _)| Box::new(filled_map));
+        let annotations = load_annotations(&self.config)?;
+        let ploidy_map = load_ploidy_map(&self.config, &fasta_map)?;
+        let signatures = load_signatures(&self.config)?;
+        let known_sites = load_known_sites(&self.config, &fasta_map)?;
+        let variants = match self.config.sample_haplotypes.is_empty() {
+            true => mutate_reference(
+                &self.config,
+                &fasta_map,
+                &contig_order,
+                annotations.as_ref(),
+                &ploidy_map,
+                signatures.as_ref(),
+                known_sites.as_ref(),
+                &germline_model,
+                &mut rng,
+            ),
+            false => load_sample_haplotypes(
+                &self.config, &fasta_map, &contig_order, annotations.as_ref()
+            )?,
+        }.variants;
+        let targets = load_targets(&self.config, &fasta_map)?;
+        let coverage_profile =
+            load_coverage_profile(&self.config, &fasta_map, coverage_bam.as_ref())?;
+        let low_complexity = load_low_complexity(&self.config, &fasta_map)?;
+        let error_profile = load_error_profile(&self.config, &models)?;
+        let ploidy = ploidy_map.max_copies();
+        let haplotypes = haplotype_sequences(&fasta_map, &variants, ploidy, true);
+        let germline_haplotypes = self.config.tumor_fraction.map(|_| {
+            haplotype_sequences(&fasta_map, &variants, ploidy, false)
+        });
+        // Same per-contig rngs as run(), so a seed gives the same reads either way (see
+        // test_generate_matches_run).
+        let mut seeds = contig_seeds(&contig_order, &mut rng);
+        let only_contig = select_contig(&self.config, &contig_order, &mut seeds)?;
+        let capture_model = load_capture_model(&self.config, &fasta_map, &contig_order, &seeds)?;
+        let ffpe = ffpe_artifacts(&self.config, &fasta_map, &contig_order, &seeds);
+        let heteroplasmy = heteroplasmic_sites(&variants);
+        let sequences = ContigSequences {
+            reference: &fasta_map,
+            haplotypes: &haplotypes,
+            order: &contig_order,
+            ploidy_map: &ploidy_map,
+            heteroplasmy: Some(&heteroplasmy).filter(|sites| !sites.is_empty()),
+            germline: germline_haplotypes.as_deref(),
+            targets: targets.as_ref(),
+            coverage_profile: coverage_profile.as_ref(),
+            capture_model: capture_model.as_ref(),
+            low_complexity: low_complexity.as_ref(),
+            error_profile: error_profile.as_ref(),
+            ffpe: ffpe.as_ref(),
+            fragment_lengths: coverage_bam
+                .as_ref()
+                .and_then(|bam_profile| bam_profile.fragment_lengths.as_ref()),
+            variants: None,
+        };
+        let mut reads: Vec<SimulatedRead> = Vec::new();
+        for (contig_index, seed) in seeds.into_iter().enumerate() {
+            if only_contig.is_some_and(|index| index != contig_index) {
+                continue
+            }
+            let mut contig_rng = Rng::new_from_seed(seed);
+            reads.extend(generate_contig_reads(
+                &self.config,
+                &sequences,
+                contig_index,
+                &mut contig_rng,
+                quality_score_model,
+            )?);
+        }
+        Ok(SimulatedSample {
+            contig_order,
+            variants: *variants,
+            reads,
+        })
+    }
+}
+
+pub struct SimulationBuilder {
+    config_builder: ConfigBuilder,
+}
+
+impl SimulationBuilder {
+    pub fn new() -> Self {
+        SimulationBuilder {
+            config_builder: ConfigBuilder::new(),
+        }
+    }
+
+    pub fn reference(mut self, reference: &str) -> Self {
+        self.config_builder.reference = Some(reference.to_string());
+        self
+    }
+
+    pub fn read_length(mut self, read_length: usize) -> Self {
+        self.config_builder.read_len = read_length;
+        self
+    }
+
+    pub fn coverage(mut self, coverage: usize) -> Self {
+        self.config_builder.coverage = coverage;
+        self
+    }
+
+    pub fn mutation_rate(mut self, mutation_rate: f64) -> Self {
+        self.config_builder.mutation_rate = mutation_rate;
+        self
+    }
+
+    pub fn somatic_mutation_rate(mut self, somatic_mutation_rate: f64) -> Self {
+        self.config_builder.somatic_mutation_rate = Some(somatic_mutation_rate);
+        self
+    }
+
+    pub fn ploidy(mut self, ploidy: usize) -> Self {
+        self.config_builder.ploidy = ploidy;
+        self
+    }
+
+    pub fn contig_ploidy(mut self, contig_ploidy: &[(String, usize)]) -> Self {
+        self.config_builder.contig_ploidy = contig_ploidy.to_vec();
+        self
+    }
+
+    pub fn snp_distance(mut self, snp_distance: usize) -> Self {
+        // Simulate a haploid clone exactly this many SNPs from the reference (needs ploidy 1).
+        self.config_builder.snp_distance = Some(snp_distance);
+        self
+    }
+
+    pub fn plasmid_copy_number(mut self, copy_numbers: &[(String, f64)]) -> Self {
+        self.config_builder.plasmid_copy_number = copy_numbers.to_vec();
+        self
+    }
+
+    pub fn quasispecies(mut self, frequencies: &[f64], diversity: f64) -> Self {
+        // Simulate a viral quasispecies: a haplotype per frequency, the first being the
+        // reference, differing at about this fraction of their bases.
+        self.config_builder.quasispecies_frequencies = frequencies.to_vec();
+        self.config_builder.quasispecies_diversity = diversity;
+        self
+    }
+
+    pub fn sample_haplotypes(mut self, haplotype_fastas: &[&str]) -> Self {
+        // Generate reads from these haplotypes (on the reference's coordinates) instead of
+        // simulating variants.
+        self.config_builder.sample_haplotypes =
+            haplotype_fastas.iter().map(|path| path.to_string()).collect();
+        self
+    }
+
+    pub fn genotype_frequencies(mut self, frequencies: &[(usize, f64)]) -> Self {
+        self.config_builder.genotype_frequencies = frequencies.to_vec();
+        self
+    }
+
+    pub fn allele_frequency(mut self, allele_frequency: f64) -> Self {
+        self.config_builder.allele_frequency = Some(allele_frequency);
+        self
+    }
+
+    pub fn sample_sex(mut self, sample_sex: Sex) -> Self {
+        self.config_builder.sample_sex = sample_sex;
+        self
+    }
+
+    pub fn mt_copy_number(mut self, mt_copy_number: f64) -> Self {
+        self.config_builder.mt_copy_number = mt_copy_number;
+        self
+    }
+
+    pub fn heteroplasmy_fractions(mut self, fractions: &[(f64, f64)]) -> Self {
+        self.config_builder.heteroplasmy_fractions = fractions.to_vec();
+        self
+    }
+
+    pub fn par_bed(mut self, par_bed: &str) -> Self {
+        self.config_builder.par_bed = Some(par_bed.to_string());
+        self
+    }
+
+    pub fn paired_ended(mut self, fragment_mean: f64, fragment_st_dev: f64) -> Self {
+        self.config_builder.paired_ended = true;
+        self.config_builder.fragment_mean = Some(fragment_mean);
+        self.config_builder.fragment_st_dev = Some(fragment_st_dev);
+        self
+    }
+
+    pub fn fragment_bounds(mut self, fragment_min: usize, fragment_max: usize) -> Self {
+        // The shortest and longest fragments allowed in paired ended mode.
+        self.config_builder.fragment_min = Some(fragment_min);
+        self.config_builder.fragment_max = Some(fragment_max);
+        self
+    }
+
+    pub fn singleton_rate(mut self, singleton_rate: f64) -> Self {
+        self.config_builder.singleton_rate = Some(singleton_rate);
+        self
+    }
+
+    pub fn discarded_pair_rate(mut self, discarded_pair_rate: f64) -> Self {
+        self.config_builder.discarded_pair_rate = Some(discarded_pair_rate);
+        self
+    }
+
+    pub fn adapter_read_through(mut self, adapter_read_through: bool) -> Self {
+        self.config_builder.adapter_read_through = adapter_read_through;
+        self
+    }
+
+    pub fn duplex_family_size(mut self, duplex_family_size: usize) -> Self {
+        self.config_builder.duplex_family_size = Some(duplex_family_size);
+        self
+    }
+
+    pub fn cell_free_fragments(mut self, cell_free_fragments: bool) -> Self {
+        self.config_builder.cell_free_fragments = cell_free_fragments;
+        self
+    }
+
+    pub fn tumor_fraction(mut self, tumor_fraction: f64) -> Self {
+        self.config_builder.tumor_fraction = Some(tumor_fraction);
+        self
+    }
+
+    pub fn target_bed(mut self, target_bed: &str) -> Self {
+        self.config_builder.target_bed = Some(target_bed.to_string());
+        self
+    }
+
+    pub fn capture_model(
+        mut self,
+        shoulder: usize,
+        efficiency_st_dev: f64,
+        gc_dropout: f64,
+    ) -> Self {
+        // Models hybrid capture of the targets: coverage shoulders this many bases past each
+        // edge, targets varying in efficiency, and dropout of fragments far from the best GC.
+        self.config_builder.capture_model = true;
+        self.config_builder.capture_shoulder = shoulder;
+        self.config_builder.capture_efficiency_st_dev = efficiency_st_dev;
+        self.config_builder.capture_gc_dropout = gc_dropout;
+        self
+    }
+
+    pub fn coverage_bam(mut self, coverage_bam: &str) -> Self {
+        // Reads follow the depth and fragment lengths of this bam of a real sample.
+        self.config_builder.coverage_bam = Some(coverage_bam.to_string());
+        self
+    }
+
+    pub fn coverage_profile(mut self, coverage_profile: &str) -> Self {
+        self.config_builder.coverage_profile = Some(coverage_profile.to_string());
+        self
+    }
+
+    pub fn somatic_signatures(mut self, somatic_signatures: &str, exposures: &[(&str, f64)]) -> Self {
+        // A table of SBS signatures and the exposure of each one to mix into the somatic layer's
+        // spectrum.
+        self.config_builder.somatic_signatures = Some(somatic_signatures.to_string());
+        self.config_builder.signature_exposures = exposures
+            .iter()
+            .map(|(name, exposure)| (name.to_string(), *exposure))
+            .collect();
+        self
+    }
+
+    pub fn known_sites(mut self, known_sites_vcf: &str, known_site_fraction: f64) -> Self {
+        // A vcf of known sites (e.g. dbSNP) and the fraction of germline variants to put at them.
+        self.config_builder.known_sites_vcf = Some(known_sites_vcf.to_string());
+        self.config_builder.known_site_fraction = known_site_fraction;
+        self
+    }
+
+    pub fn gene_annotation(mut self, gene_annotation: &str) -> Self {
+        self.config_builder.gene_annotation = Some(gene_annotation.to_string());
+        self
+    }
+
+    pub fn region_mutation_rates(mut self, exon: f64, intron: f64, intergenic: f64) -> Self {
+        // Germline mutation rates for the exons, introns and intergenic regions of the gene
+        // annotation.
+        self.config_builder.exon_mutation_rate = Some(exon);
+        self.config_builder.intron_mutation_rate = Some(intron);
+        self.config_builder.intergenic_mutation_rate = Some(intergenic);
+        self
+    }
+
+    pub fn preset(mut self, preset: &str) -> Result<Self, NeatError> {
+        // Sets the defaults of a kind of run (e.g. "cfdna"). Call it before the settings it
+        // should leave alone, since it overwrites whatever was set before it.
+        self.config_builder.apply_preset(preset)?;
+        Ok(self)
+    }
+
+    pub fn reference_cache(mut self, reference_cache: &str) -> Self {
+        self.config_builder.reference_cache = Some(PathBuf::from(reference_cache));
+        self
+    }
+
+    pub fn sequence_dictionary(mut self, sequence_dictionary: &str) -> Self {
+        self.config_builder.sequence_dictionary = Some(sequence_dictionary.to_string());
+        self
+    }
+
+    pub fn primary_assembly_only(mut self, primary_assembly_only: bool) -> Self {
+        self.config_builder.primary_assembly_only = primary_assembly_only;
+        self
+    }
+
+    pub fn only_contig(mut self, contig: &str, contig_seed: Option<&str>) -> Self {
+        // Writes (or generates) the output of this contig only, the same as a full run's, using
+        // contig_seed (as a full run logs it) for its reads if given.
+        self.config_builder.only_contig = Some(contig.to_string());
+        self.config_builder.contig_seed = contig_seed.map(str::to_string);
+        self
+    }
+
+    pub fn region(mut self, region: &str) -> Self {
+        // Simulates this region (contig:start-end) only, keeping the contig's coordinates.
+        self.config_builder.region = Some(region.to_string());
+        self
+    }
+
+    pub fn fill_gaps(mut self, fill_gaps: bool) -> Self {
+        // Fills the reference's gaps with synthetic telomere and satellite repeats.
+        self.config_builder.fill_gaps = fill_gaps;
+        self
+    }
+
+    pub fn produce_fastq(mut self, produce_fastq: bool) -> Self {
+        self.config_builder.produce_fastq = produce_fastq;
+        self
+    }
+
+    pub fn produce_fasta(mut self, produce_fasta: bool) -> Self {
+        self.config_builder.produce_fasta = produce_fasta;
+        self
+    }
+
+    pub fn haplotype_fasta(mut self, haplotype_fasta: bool) -> Self {
+        self.config_builder.haplotype_fasta = haplotype_fasta;
+        self
+    }
+
+    pub fn produce_edit_list(mut self, produce_edit_list: bool) -> Self {
+        self.config_builder.produce_edit_list = produce_edit_list;
+        self
+    }
+
+    pub fn produce_vcf(mut self, produce_vcf: bool) -> Self {
+        self.config_builder.produce_vcf = produce_vcf;
+        self
+    }
+
+    pub fn produce_benchmark_files(mut self, produce_benchmark_files: bool) -> Self {
+        self.config_builder.produce_benchmark_files = produce_benchmark_files;
+        self
+    }
+
+    pub fn bgzip_truth_files(mut self, bgzip_truth_files: bool) -> Self {
+        self.config_builder.bgzip_truth_files = bgzip_truth_files;
+        self
+    }
+
+    pub fn validate_truth(mut self, validate_truth: bool) -> Self {
+        self.config_builder.validate_truth = validate_truth;
+        self
+    }
+
+    pub fn liftover_chain(mut self, liftover_chain: &str) -> Self {
+        // Also write the truth vcf lifted over to another assembly by this chain file.
+        self.config_builder.liftover_chain = Some(liftover_chain.to_string());
+        self
+    }
+
+    pub fn produce_bam(mut self, produce_bam: bool) -> Self {
+        self.config_builder.produce_bam = produce_bam;
+        self
+    }
+
+    pub fn kmer_spectrum(mut self, k: usize) -> Self {
+        // Also write a histogram of the reads' k-mers of this length.
+        self.config_builder.kmer_spectrum = Some(k);
+        self
+    }
+
+    pub fn truth_pileup(mut self, truth_pileup: bool) -> Self {
+        // Also write the depth and base counts of the reads along the reference.
+        self.config_builder.truth_pileup = truth_pileup;
+        self
+    }
+
+    pub fn genotype_calls(mut self, genotype_calls: bool) -> Self {
+        // Also give the truth vcf the depths and genotype qualities of the reads.
+        self.config_builder.genotype_calls = genotype_calls;
+        self
+    }
+
+    pub fn rng_seed(mut self, rng_seed: &str) -> Self {
+        self.config_builder.rng_seed = Some(rng_seed.to_string());
+        self
+    }
+
+    pub fn random_seed_words(mut self, num_words: usize) -> Self {
+        // Seeds a run without an rng_seed with this many random words, instead of the clock.
+        self.config_builder.random_seed_words = Some(num_words);
+        self
+    }
+
+    pub fn overwrite_output(mut self, overwrite_output: bool) -> Self {
+        self.config_builder.overwrite_output = overwrite_output;
+        self
+    }
+
+    pub fn minimum_mutations(mut self, minimum_mutations: usize) -> Self {
+        self.config_builder.minimum_mutations = Some(minimum_mutations);
+        self
+    }
+
+    pub fn output_dir(mut self, output_dir: &str) -> Self {
+        self.config_builder.output_dir = PathBuf::from(output_dir);
+        self
+    }
+
+    pub fn output_prefix(mut self, output_prefix: &str) -> Self {
+        self.config_builder.output_prefix = output_prefix.to_string();
+        self
+    }
+
+    pub fn read_name_format(mut self, read_name_format: ReadNameFormat) -> Self {
+        self.config_builder.read_name_format = read_name_format;
+        self
+    }
+
+    pub fn duplicate_rate(mut self, duplicate_rate: f64) -> Self {
+        self.config_builder.duplicate_rate = Some(duplicate_rate);
+        self
+    }
+
+    pub fn optical_duplicates(mut self, fraction: f64, pixel_distance: usize) -> Self {
+        // The fraction of duplicates that are optical, and how close they are to the original.
+        self.config_builder.optical_duplicate_fraction = fraction;
+        self.config_builder.optical_duplicate_distance = pixel_distance;
+        self
+    }
+
+    pub fn tile_width(mut self, tile_width: usize) -> Self {
+        self.config_builder.tile_width = tile_width;
+        self
+    }
+
+    pub fn sample_index(mut self, sample_index: SampleIndex) -> Self {
+        self.config_builder.sample_index = Some(sample_index);
+        self
+    }
+
+    pub fn index_hopping(mut self, pool: Vec<SampleIndex>, hopping_rate: f64) -> Self {
+        // The other libraries in the pool, and the fraction of templates that read their index.
+        self.config_builder.index_pool = pool;
+        self.config_builder.index_hopping_rate = Some(hopping_rate);
+        self
+    }
+
+    pub fn read_name_prefix(mut self, read_name_prefix: &str) -> Self {
+        self.config_builder.read_name_prefix = read_name_prefix.to_string();
+        self
+    }
+
+    pub fn read_comment_tag(mut self, tag: &str) -> Self {
+        // Can be called more than once to add several tags.
+        self.config_builder.read_comment_tags.push(tag.to_string());
+        self
+    }
+
+    pub fn truth_comment_tags(mut self, truth_comment_tags: bool) -> Self {
+        self.config_builder.truth_comment_tags = truth_comment_tags;
+        self
+    }
+
+    pub fn quality_offset(mut self, quality_offset: u32) -> Self {
+        self.config_builder.quality_offset = quality_offset;
+        self
+    }
+
+    pub fn low_complexity_errors(
+        mut self,
+        error_fold: f64,
+        low_complexity_bed: Option<&str>,
+    ) -> Self {
+        // Raises the error rate of read bases in homopolymers and tandem repeats error_fold
+        // times, in the regions of the bed if one is given and otherwise in those found in the
+        // reference.
+        self.config_builder.low_complexity_error_fold = Some(error_fold);
+        self.config_builder.low_complexity_bed = low_complexity_bed.map(str::to_string);
+        self
+    }
+
+    pub fn max_quality(mut self, max_quality: u32) -> Self {
+        self.config_builder.max_quality = Some(max_quality);
+        self
+    }
+
+    pub fn quality_crash_rate(mut self, crash_rate: f64) -> Self {
+        // This fraction of reads has its quality crash to Q2 partway through the read.
+        self.config_builder.quality_crash_rate = Some(crash_rate);
+        self
+    }
+
+    pub fn qc_fail_rate(mut self, qc_fail_rate: f64) -> Self {
+        // This fraction of templates fails QC, with low quality reads flagged as failures.
+        self.config_builder.qc_fail_rate = Some(qc_fail_rate);
+        self
+    }
+
+    pub fn molecule_error_rate(mut self, error_rate: f64) -> Self {
+        // This fraction of each fragment's bases is damaged before sequencing, so overlapping
+        // mates share those errors.
+        self.config_builder.molecule_error_rate = Some(error_rate);
+        self
+    }
+
+    pub fn ffpe_artifacts(mut self, rate: f64, fraction: f64) -> Self {
+        // This fraction of bases are FFPE artifact sites, each carried by about this fraction of
+        // the fragments over it.
+        self.config_builder.ffpe_artifact_rate = Some(rate);
+        self.config_builder.ffpe_artifact_fraction = fraction;
+        self
+    }
+
+    pub fn error_profile(mut self, error_profile: &str) -> Self {
+        // Errors ramp up along the reads by cycle, as the table of rates says.
+        self.config_builder.error_profile = Some(error_profile.to_string());
+        self
+    }
+
+    pub fn model_dir(mut self, model_dir: &str) -> Self {
+        // Uses the matched set of models in this directory (see utils/model_dir.rs).
+        self.config_builder.model_dir = Some(model_dir.to_string());
+        self
+    }
+
+    pub fn quality_trim_threshold(mut self, threshold: u32) -> Self {
+        // Marks in each fastq header where trimming bases below this quality should cut the read.
+        self.config_builder.quality_trim_threshold = Some(threshold);
+        self
+    }
+
+    pub fn global_shuffle(mut self, global_shuffle: bool) -> Self {
+        self.config_builder.global_shuffle = global_shuffle;
+        self
+    }
+
+    pub fn sort_reads(mut self, sort_reads: bool) -> Self {
+        self.config_builder.sort_reads = sort_reads;
+        self
+    }
+
+    pub fn sample_name(mut self, sample_name: &str) -> Self {
+        self.config_builder.sample_name = Some(sample_name.to_string());
+        self
+    }
+
+    pub fn library(mut self, library: &str) -> Self {
+        self.config_builder.library = Some(library.to_string());
+        self
+    }
+
+    pub fn platform(mut self, platform: &str) -> Self {
+        self.config_builder.platform = platform.to_uppercase();
+        self
+    }
+
+    pub fn platform_unit(mut self, platform_unit: &str) -> Self {
+        self.config_builder.platform_unit = Some(platform_unit.to_string());
+        self
+    }
+
+    pub fn instrument(mut self, instrument: &str) -> Self {
+        self.config_builder.instrument = instrument.to_string();
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config_builder.threads = threads;
+        self
+    }
+
+    pub fn max_memory(mut self, max_memory: usize) -> Self {
+        // A memory budget in bytes. The run uses fewer threads if it has to, to stay under it.
+        self.config_builder.max_memory = Some(max_memory);
+        self
+    }
+
+    pub fn throttle_mb_per_sec(mut self, throttle_mb_per_sec: f64) -> Self {
+        // Holds the run's writes to this rate, for runs on shared nodes.
+        self.config_builder.throttle_mb_per_sec = Some(throttle_mb_per_sec);
+        self
+    }
+
+    pub fn temp_dir(mut self, temp_dir: &str) -> Self {
+        self.config_builder.temp_dir = Some(PathBuf::from(temp_dir));
+        self
+    }
+
+    pub fn build(self) -> Result<Simulation, NeatError> {
+        // Runs the same checks as the command line tool, then locks in the configuration.
+        self.config_builder.check_and_print_config()?;
+        Ok(Simulation { config: self.config_builder.build() })
+    }
+
+    pub fn run(self) -> Result<RunOutput, NeatError> {
+        self.build()?.run()
+    }
+}
+
+impl Default for SimulationBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use super::super::utils::fasta_tools::read_fasta;
+    use super::super::utils::low_complexity::find_all_low_complexity;
+    use super::super::utils::nucleotides::u8_to_base;
+
+    #[test]
+    fn test_builder_requires_reference() {
+        assert!(Simulation::builder().coverage(5).build().is_err());
+    }
+
+    #[test]
+    fn test_simulation_run() {
+        let output = Simulation::builder()
+            .reference("test_data/H1N1.fa")
+            .coverage(2)
+            .read_length(100)
+            .produce_vcf(true)
+            .minimum_mutations(2)
+            .rng_seed("Hello Cruel World")
+            .output_dir("simulation_test")
+            .run()
+            .unwrap();
+        assert_eq!(output.fastq_files.len(), 1);
+        assert!(output.fastq_files[0].is_file());
+        assert!(output.vcf_file.unwrap().is_file());
+        assert!(output.num_reads > 0);
+        assert!(output.variants.values().all(|variants| variants.len() >= 2));
+        fs::remove_dir_all("simulation_test").unwrap();
+    }
+
+    #[test]
+    fn test_simulation_generate() {
+        let sample = Simulation::builder()
+            .reference("test_data/H1N1.fa")
+            .coverage(1)
+            .read_length(100)
+            .paired_ended(250.0, 10.0)
+            .minimum_mutations(1)
+            .rng_seed("Hello Cruel World")
+            .build()
+            .unwrap()
+            .generate(&QualityScoreModel::new())
+            .unwrap();
+        assert_eq!(sample.contig_order[0], "H1N1_HA");
+        assert!(!sample.reads.is_empty());
+        assert!(sample.reads.iter().any(|read| read.mate == 2));
+        assert!(sample.variants.values().all(|variants| !variants.is_empty()));
+    }
+
+    #[test]
+    fn test_simulation_low_complexity_errors() {
+        // Reads only differ from the reference at variants and in low-complexity regions.
+        let sample = Simulation::builder()
+            .reference("test_data/H1N1.fa")
+            .coverage(2)
+            .read_length(100)
+            .low_complexity_errors(1000.0, None)
+            .rng_seed("Hello Homopolymers")
+            .build()
+            .unwrap()
+            .generate(&QualityScoreModel::new())
+            .unwrap();
+        let (reference, _) = read_fasta("test_data/H1N1.fa").unwrap();
+        let low_complexity = find_all_low_complexity(&reference);
+        let mut errors = 0;
+        for read in &sample.reads {
+            let contig_reference = &reference[&read.contig];
+            let variants = &sample.variants[&read.contig];
+            for (offset, base) in read.seq.iter().enumerate() {
+                let position = read.start + offset;
+                if *base == contig_reference[position]
+                    || variants.iter().any(|variant| variant.position == position) {
+                    continue
+                }
+                errors += 1;
+                assert!(low_complexity[&read.contig]
+                    .iter()
+                    .any(|(start, end)| (*start..*end).contains(&position)));
+            }
+        }
+        assert!(errors > 0);
+    }
+
+    #[test]
+    fn test_simulation_reproducible() {
+        // Same seed, same sample, including across contigs and layers.
+        let generate = || {
+            Simulation::builder()
+                .reference("test_data/H1N1.fa")
+                .coverage(1)
+                .read_length(100)
+                .paired_ended(250.0, 10.0)
+                .minimum_mutations(1)
+                .somatic_mutation_rate(0.001)
+                .rng_seed("Hello Cruel World")
+                .build()
+                .unwrap()
+                .generate(&QualityScoreModel::new())
+                .unwrap()
+        };
+        let first = generate();
+        let second = generate();
+        assert_eq!(first.variants, second.variants);
+        assert_eq!(first.reads, second.reads);
+    }
+
+    #[test]
+    fn test_generate_matches_run() {
+        // The same seed gives the same variants and reads in memory as on disk.
+        let builder = || {
+            Simulation::builder()
+                .reference("test_data/H1N1.fa")
+                .coverage(2)
+                .read_length(100)
+                .paired_ended(250.0, 10.0)
+                .minimum_mutations(1)
+                .somatic_mutation_rate(0.001)
+                .rng_seed("Hello Cruel World")
+        };
+        let output = builder().output_dir("simulation_test_matches_run").run().unwrap();
+        let sample = builder()
+            .build()
+            .unwrap()
+            .generate(&QualityScoreModel::default_model())
+            .unwrap();
+        assert_eq!(output.variants, sample.variants);
+        // The run shuffles the reads within each contig, so they are compared as sets.
+        let mut written: Vec<(Vec<u8>, Vec<u32>)> = Vec::new();
+        for fastq in &output.fastq_files {
+            let text = fs::read_to_string(fastq).unwrap();
+            let lines: Vec<&str> = text.lines().collect();
+            for record in lines.chunks(4) {
+                let quals = record[3].bytes().map(|qual| (qual - 33) as u32).collect();
+                written.push((record[1].as_bytes().to_vec(), quals));
+            }
+        }
+        let mut generated: Vec<(Vec<u8>, Vec<u32>)> = sample.reads
+            .iter()
+            .map(|read| {
+                let seq = read.seq.iter().map(|base| u8_to_base(*base) as u8).collect();
+                (seq, read.quals.clone())
+            })
+            .collect();
+        assert!(!written.is_empty());
+        written.sort();
+        generated.sort();
+        assert_eq!(written, generated);
+        fs::remove_dir_all("simulation_test_matches_run").unwrap();
+    }
+}
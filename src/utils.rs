@@ -9,5 +9,42 @@ pub mod vcf_tools;
 pub mod nucleotides;
 pub mod runner;
 pub mod quality_scores;
-pub mod read_old_data;
-pub mod read_models;
\ No newline at end of file
+pub mod variants;
+pub mod read_shards;
+pub mod checksums;
+pub mod self_check;
+pub mod demo;
+pub mod errors;
+pub mod bgzf;
+pub mod bam_tools;
+pub mod tabix;
+pub mod bed_tools;
+pub mod gff_tools;
+pub mod ploidy;
+pub mod summary;
+pub mod reference_cache;
+pub mod two_bit;
+pub mod provenance;
+pub mod coverage_profile;
+pub mod signatures;
+pub mod known_sites;
+pub mod genotype_quality;
+pub mod capture;
+pub mod low_complexity;
+pub mod memory_budget;
+pub mod heteroplasmy;
+pub mod truth_check;
+pub mod index_reads;
+pub mod error_profile;
+pub mod ffpe;
+pub mod model_dir;
+pub mod kmer_spectrum;
+pub mod bam_profile;
+pub mod gap_fill;
+pub mod pileup;
+pub mod quasispecies;
+pub mod longitudinal;
+pub mod sample_haplotypes;
+pub mod liftover;
+pub mod throttle;
+pub mod edit_list;
@@ -0,0 +1,244 @@
+// Coverage taken from a real bam, for "digital twins" of a sample: reads with the depth along the
+// genome and the fragment lengths of the real data, but drawn from the simulated genome, so their
+// variants are known. The bam is read once, record by record (its BGZF blocks are gzip members,
+// so no index is needed), tallying the aligned bases of each window of the genome and the
+// template length of each properly paired fragment. Unmapped, secondary, supplementary, duplicate
+// and QC failed records are skipped, as depth tools skip them. The windows then make a coverage
+// profile (see coverage_profile.rs), and the fragment lengths are drawn from as they were seen.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use flate2::bufread::MultiGzDecoder;
+use log::{info, warn};
+use super::coverage_profile::DepthWindows;
+use super::errors::NeatError;
+use super::make_reads::FragmentLengths;
+
+// The width of the windows depth is measured over. Narrow enough to follow GC and mappability
+// dips, wide enough that a window's depth isn't just a handful of reads.
+pub const BAM_PROFILE_WINDOW: usize = 1000;
+// Records with any of these flags don't count: unmapped, secondary, QC failed, duplicate and
+// supplementary.
+const SKIPPED_FLAGS: u16 = 0x4 | 0x100 | 0x200 | 0x400 | 0x800;
+const PROPER_PAIR: u16 = 0x2;
+// The fixed fields at the start of a record, after its size.
+const RECORD_FIELDS: usize = 32;
+
+pub struct BamProfile {
+    // windows: The mean depth of each BAM_PROFILE_WINDOW window of the contigs the bam and the
+    //     reference share.
+    // fragment_lengths: The template lengths of the properly paired fragments, if there were any.
+    // alignments: The number of records counted.
+    pub windows: DepthWindows,
+    pub fragment_lengths: Option<FragmentLengths>,
+    pub alignments: usize,
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_block(reader: &mut impl Read, length: usize) -> io::Result<Vec<u8>> {
+    let mut block = vec![0; length];
+    reader.read_exact(&mut block)?;
+    Ok(block)
+}
+
+pub fn read_bam_profile(
+    path: &str,
+    contig_lengths: &HashMap<String, usize>,
+) -> Result<BamProfile, NeatError> {
+    // Takes:
+    // path: The bam. It needn't be sorted or indexed.
+    // contig_lengths: The length of each contig of the reference. Records on other contigs are
+    //     skipped, and bases past the end of a contig are dropped.
+    // Returns:
+    // The depth windows and fragment lengths of the bam, or an error if it isn't one.
+    let bad_bam = |problem: &str| NeatError::BamParse(format!("{}: {}", path, problem));
+    let mut reader = BufReader::new(MultiGzDecoder::new(BufReader::new(File::open(path)?)));
+    let header = read_block(&mut reader, 8).map_err(|_| bad_bam("not a bam"))?;
+    if &header[..4] != b"BAM\x01" {
+        return Err(bad_bam("not a bam"))
+    }
+    read_block(&mut reader, read_u32(&header, 4) as usize)?;
+    let num_references = read_u32(&read_block(&mut reader, 4)?, 0) as usize;
+    // The bases counted in each window, for the bam's contigs that are in the reference
+    let mut bases: Vec<Option<(String, usize, Vec<u64>)>> = Vec::with_capacity(num_references);
+    for _ in 0..num_references {
+        let name_length = read_u32(&read_block(&mut reader, 4)?, 0) as usize;
+        let name = read_block(&mut reader, name_length)?;
+        let name = String::from_utf8_lossy(&name[..name_length.saturating_sub(1)]).to_string();
+        let bam_length = read_u32(&read_block(&mut reader, 4)?, 0) as usize;
+        bases.push(contig_lengths.get(&name).map(|length| {
+            if *length != bam_length {
+                warn!(
+                    "{} is {} bp in {} but {} bp in the reference",
+                    name, bam_length, path, length
+                );
+            }
+            (name, *length, vec![0; length.div_ceil(BAM_PROFILE_WINDOW)])
+        }));
+    }
+    if bases.iter().all(Option::is_none) {
+        return Err(bad_bam("has no contigs of the reference"))
+    }
+
+    let mut fragment_counts: BTreeMap<usize, u64> = BTreeMap::new();
+    let mut alignments = 0;
+    let mut size = [0; 4];
+    loop {
+        match reader.read_exact(&mut size) {
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            result => result?,
+        }
+        let record = read_block(&mut reader, u32::from_le_bytes(size) as usize)?;
+        if record.len() < RECORD_FIELDS {
+            return Err(bad_bam("truncated record"))
+        }
+        let flag = read_u16(&record, 14);
+        let reference_id = read_i32(&record, 0);
+        if flag & SKIPPED_FLAGS != 0 || reference_id < 0 {
+            continue
+        }
+        let Some(Some((_, length, windows))) = bases.get_mut(reference_id as usize) else {
+            continue
+        };
+        alignments += 1;
+        let cigar_start = RECORD_FIELDS + record[8] as usize;
+        let num_operations = read_u16(&record, 12) as usize;
+        if record.len() < cigar_start + 4 * num_operations {
+            return Err(bad_bam("truncated record"))
+        }
+        let mut position = read_i32(&record, 4).max(0) as usize;
+        for operation in 0..num_operations {
+            let cigar = read_u32(&record, cigar_start + 4 * operation);
+            let operation_length = (cigar >> 4) as usize;
+            match cigar & 0xf {
+                // M, = and X cover the reference
+                0 | 7 | 8 => {
+                    let end = (position + operation_length).min(*length);
+                    let mut start = position;
+                    while start < end {
+                        let window = start / BAM_PROFILE_WINDOW;
+                        let window_end = ((window + 1) * BAM_PROFILE_WINDOW).min(end);
+                        windows[window] += (window_end - start) as u64;
+                        start = window_end;
+                    }
+                    position += operation_length;
+                },
+                // D and N skip over it
+                2 | 3 => position += operation_length,
+                _ => (),
+            }
+        }
+        // Each pair once, from the mate with the positive template length
+        let template_length = read_i32(&record, 28);
+        if flag & PROPER_PAIR != 0 && template_length > 0 {
+            *fragment_counts.entry(template_length as usize).or_insert(0) += 1;
+        }
+    }
+
+    let mut depth_windows = DepthWindows::new();
+    for (contig, length, windows) in bases.into_iter().flatten() {
+        let spans = windows
+            .iter()
+            .enumerate()
+            .map(|(window, bases)| {
+                let start = window * BAM_PROFILE_WINDOW;
+                let end = (start + BAM_PROFILE_WINDOW).min(length);
+                (start, end, *bases as f64 / (end - start) as f64)
+            })
+            .collect();
+        depth_windows.insert(contig, spans);
+    }
+    let fragment_lengths = FragmentLengths::from_counts(&fragment_counts);
+    info!(
+        "Read {} alignments from {}, with {} fragment lengths",
+        alignments, path, fragment_counts.values().sum::<u64>()
+    );
+    Ok(BamProfile { windows: depth_windows, fragment_lengths, alignments })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    fn record(reference_id: i32, position: i32, flag: u16, cigar: &[u32], tlen: i32) -> Vec<u8> {
+        let mut fields = Vec::new();
+        fields.extend(reference_id.to_le_bytes());
+        fields.extend(position.to_le_bytes());
+        // Name length, mapq and bin
+        fields.extend([2, 60, 0, 0]);
+        fields.extend((cigar.len() as u16).to_le_bytes());
+        fields.extend(flag.to_le_bytes());
+        // No sequence, and the mate fields
+        fields.extend(0u32.to_le_bytes());
+        fields.extend((-1i32).to_le_bytes());
+        fields.extend((-1i32).to_le_bytes());
+        fields.extend(tlen.to_le_bytes());
+        fields.extend(b"r\0");
+        for operation in cigar {
+            fields.extend(operation.to_le_bytes());
+        }
+        let mut block = (fields.len() as u32).to_le_bytes().to_vec();
+        block.extend(fields);
+        block
+    }
+
+    #[test]
+    fn test_read_bam_profile() {
+        let mut bam = b"BAM\x01".to_vec();
+        bam.extend(0u32.to_le_bytes());
+        bam.extend(2u32.to_le_bytes());
+        for (name, length) in [(&b"chr1\0"[..], 2500u32), (&b"chrUn\0"[..], 100)] {
+            bam.extend((name.len() as u32).to_le_bytes());
+            bam.extend(name);
+            bam.extend(length.to_le_bytes());
+        }
+        // 100M at 950, spanning the first two windows; 50M 500N 50M at 1900, the second half in
+        // the last window; a duplicate, and a record on a contig the reference doesn't have
+        bam.extend(record(0, 950, 0x1 | 0x2 | 0x40, &[100 << 4], 300));
+        bam.extend(record(0, 1900, 0x1 | 0x2 | 0x80, &[50 << 4, (500 << 4) | 3, 50 << 4], -300));
+        bam.extend(record(0, 0, 0x400, &[100 << 4], 0));
+        bam.extend(record(1, 0, 0, &[100 << 4], 0));
+        // Written as two gzip members, as BGZF blocks are
+        let path = "test_data/test_profile.bam";
+        let mut file = File::create(path).unwrap();
+        for part in [&bam[..40], &bam[40..]] {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(part).unwrap();
+            file.write_all(&encoder.finish().unwrap()).unwrap();
+        }
+        drop(file);
+        let contig_lengths = HashMap::from([("chr1".to_string(), 2500)]);
+        let profile = read_bam_profile(path, &contig_lengths);
+        std::fs::remove_file(path).unwrap();
+        let profile = profile.unwrap();
+        assert_eq!(profile.alignments, 2);
+        assert_eq!(
+            profile.windows["chr1"],
+            vec![(0, 1000, 0.05), (1000, 2000, 0.1), (2000, 2500, 0.1)]
+        );
+        let lengths = profile.fragment_lengths.unwrap();
+        assert_eq!((lengths.mean, lengths.st_dev), (300.0, 0.0));
+
+        let path = "test_data/test_not_a.bam";
+        std::fs::write(path, "not a bam").unwrap();
+        let result = read_bam_profile(path, &contig_lengths);
+        std::fs::remove_file(path).unwrap();
+        assert!(result.is_err());
+    }
+}
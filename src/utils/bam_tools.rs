@@ -0,0 +1,555 @@
+// Writes simulated reads as a BAM file aligned to the reference, using the truth positions of the
+// reads. Variants are SNPs only, so the mutated contigs line up base for base with the reference
+// and every read aligns as a single match block at the position it was drawn from. Adapter bases
+// a read ran into past the end of its fragment are soft clipped. The NM, MD and AS tags describe
+// how the aligned bases differ from the original reference. Duplex reads also carry their UMIs
+// (RX) and true source molecule (MI), as fgbio names them.
+//
+// A BAM file is a series of BGZF blocks holding the binary header and records described in the
+// SAM spec (https://samtools.github.io/hts-specs/SAMv1.pdf). Blocks can be concatenated, so shards
+// of records compressed separately can be joined with a plain copy.
+
+use std::io;
+use std::io::Write;
+use super::bgzf::reg2bin;
+use super::fastq_tools::reverse_complement;
+use super::make_reads::{SimulatedRead, Strand};
+use super::nucleotides::u8_to_base;
+use super::provenance::Provenance;
+
+// SAM flags
+const FLAG_PAIRED: u16 = 0x1;
+const FLAG_PROPER_PAIR: u16 = 0x2;
+const FLAG_REVERSE: u16 = 0x10;
+const FLAG_MATE_REVERSE: u16 = 0x20;
+const FLAG_FIRST_IN_PAIR: u16 = 0x40;
+const FLAG_SECOND_IN_PAIR: u16 = 0x80;
+const FLAG_QC_FAIL: u16 = 0x200;
+// Every read is placed exactly where it came from.
+const TRUTH_MAPQ: u8 = 60;
+const CIGAR_MATCH: u32 = 0;
+const CIGAR_SOFT_CLIP: u32 = 4;
+// Alignment scores use bwa mem's defaults, so AS values look like what an aligner would report.
+const MATCH_SCORE: i32 = 1;
+const MISMATCH_PENALTY: i32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadGroup {
+    // The @RG line of the header. Every record is tagged with its id.
+    //
+    // id: ID, unique within the file (and across files that will be merged).
+    // sample: SM, the sample the reads came from.
+    // library: LB, the library the reads were sequenced from.
+    // platform: PL, the sequencing technology (ILLUMINA, ONT, PACBIO and so on).
+    // platform_unit: PU, the flowcell/lane/barcode, if known.
+    pub id: String,
+    pub sample: String,
+    pub library: String,
+    pub platform: String,
+    pub platform_unit: Option<String>,
+}
+
+impl ReadGroup {
+    fn header_line(&self) -> String {
+        let mut line = format!(
+            "@RG\tID:{}\tSM:{}\tLB:{}\tPL:{}", self.id, self.sample, self.library, self.platform
+        );
+        if let Some(platform_unit) = &self.platform_unit {
+            line += &format!("\tPU:{}", platform_unit);
+        }
+        line
+    }
+}
+
+pub fn write_bam_header(
+    writer: &mut impl Write,
+    contig_order: &[String],
+    contig_lengths: &[usize],
+    read_group: &ReadGroup,
+    provenance: &Provenance,
+    coordinate_sorted: bool,
+) -> io::Result<()> {
+    // Takes:
+    // writer: Where to write the header, usually a BgzfWriter.
+    // contig_order: Contig names, in reference order. Records refer to contigs by their index
+    //     in this list.
+    // contig_lengths: The length of each contig.
+    // read_group: The read group all of the records belong to.
+    // provenance: The seed and config hash of the run, for the description of the @PG line.
+    // coordinate_sorted: Whether the records will be sorted by position (SO:coordinate).
+    // Returns:
+    // Error if there is a problem or else nothing.
+    let sort_order = if coordinate_sorted { "coordinate" } else { "unsorted" };
+    let mut text = format!("@HD\tVN:1.6\tSO:{}\n", sort_order);
+    for (contig, length) in contig_order.iter().zip(contig_lengths) {
+        text += &format!("@SQ\tSN:{}\tLN:{}\n", contig, length);
+    }
+    text += &format!("{}\n", read_group.header_line());
+    text += &format!(
+        "@PG\tID:rusty-neat\tPN:rusty-neat\tVN:{}\tDS:{}\n",
+        provenance.version, provenance.program_description()
+    );
+    writer.write_all(b"BAM\x01")?;
+    writer.write_all(&(text.len() as u32).to_le_bytes())?;
+    writer.write_all(text.as_bytes())?;
+    writer.write_all(&(contig_order.len() as u32).to_le_bytes())?;
+    for (contig, length) in contig_order.iter().zip(contig_lengths) {
+        writer.write_all(&(contig.len() as u32 + 1).to_le_bytes())?;
+        writer.write_all(contig.as_bytes())?;
+        writer.write_all(&[0])?;
+        writer.write_all(&(*length as u32).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn base_to_nibble(base: u8) -> u8 {
+    // BAM packs bases into 4 bits, in the order =ACMGRSVTWYHKDBN.
+    match base {
+        0 => 1,
+        1 => 2,
+        2 => 4,
+        3 => 8,
+        _ => 15,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MateFields {
+    // The fields of a record that depend on its mate.
+    //
+    // flag: The pairing flags (paired, proper pair, mate reverse, first/second in pair).
+    // next_reference_id / next_position: Where the mate aligns (RNEXT/PNEXT), or -1.
+    // template_length: TLEN, positive for the leftmost read of the pair and negative for the
+    //     rightmost.
+    flag: u16,
+    next_reference_id: i32,
+    next_position: i32,
+    template_length: i32,
+}
+
+fn mate_fields(
+    read: &SimulatedRead,
+    mate: Option<&SimulatedRead>,
+    reference_id: i32,
+) -> MateFields {
+    let mate = match mate {
+        Some(mate) => mate,
+        // Single ended reads, and paired reads whose mate was lost. No record is written for a
+        // lost mate, so its read is left a singleton, without pairing flags.
+        None => return MateFields {
+            flag: 0,
+            next_reference_id: -1,
+            next_position: -1,
+            template_length: 0,
+        },
+    };
+    let mut flag = FLAG_PAIRED;
+    flag |= if read.mate == 1 { FLAG_FIRST_IN_PAIR } else { FLAG_SECOND_IN_PAIR };
+    if mate.strand == Strand::Reverse {
+        flag |= FLAG_MATE_REVERSE;
+    }
+    // Proper pairs face each other: the forward read starts at or before the reverse read.
+    let (forward, reverse) = match (read.strand, mate.strand) {
+        (Strand::Forward, Strand::Reverse) => (Some(read), Some(mate)),
+        (Strand::Reverse, Strand::Forward) => (Some(mate), Some(read)),
+        _ => (None, None),
+    };
+    if let (Some(forward), Some(reverse)) = (forward, reverse) {
+        if forward.contig == reverse.contig && forward.start <= reverse.start {
+            flag |= FLAG_PROPER_PAIR;
+        }
+    }
+    // TLEN runs from the leftmost base of the pair to the rightmost. When both reads start at the
+    // same place, read 1 counts as the leftmost.
+    let template_length = if read.contig == mate.contig {
+        let left = read.start.min(mate.start);
+        let right = (read.start + read.aligned_length()).max(mate.start + mate.aligned_length());
+        let length = (right - left) as i32;
+        let leftmost = read.start < mate.start || (read.start == mate.start && read.mate == 1);
+        if leftmost { length } else { -length }
+    } else {
+        0
+    };
+    MateFields {
+        flag,
+        next_reference_id: reference_id,
+        next_position: mate.start as i32,
+        template_length,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct AlignmentTags {
+    // How a read differs from the reference where it aligns.
+    //
+    // edit_distance: NM, the number of mismatched bases (there are no indels).
+    // mismatches: MD, the runs of matching bases and the reference base at each mismatch.
+    // score: AS, the alignment score.
+    edit_distance: i32,
+    mismatches: String,
+    score: i32,
+}
+
+fn alignment_tags(bases: &[u8], reference: &[u8]) -> AlignmentTags {
+    // Compares a read, along the reference, with the reference bases it aligns to. Like samtools
+    // calmd, an N in either one counts as a mismatch.
+    let mut edit_distance = 0;
+    let mut mismatches = String::new();
+    let mut run = 0;
+    for (base, reference_base) in bases.iter().zip(reference) {
+        if base == reference_base && *base < 4 {
+            run += 1;
+        } else {
+            edit_distance += 1;
+            mismatches += &format!("{}{}", run, u8_to_base(*reference_base));
+            run = 0;
+        }
+    }
+    mismatches += &run.to_string();
+    let matches = bases.len() as i32 - edit_distance;
+    AlignmentTags {
+        edit_distance,
+        mismatches,
+        score: matches * MATCH_SCORE - edit_distance * MISMATCH_PENALTY,
+    }
+}
+
+fn write_bam_record(
+    writer: &mut impl Write,
+    read: &SimulatedRead,
+    mate: Option<&SimulatedRead>,
+    reference_id: i32,
+    reference: &[u8],
+    read_group_id: &str,
+) -> io::Result<()> {
+    // BAM stores reads along the reference, so reverse strand reads are flipped back.
+    let (seq, quals) = match read.strand {
+        Strand::Forward => (read.seq.clone(), read.quals.clone()),
+        Strand::Reverse => (
+            reverse_complement(&read.seq),
+            read.quals.iter().rev().copied().collect(),
+        ),
+    };
+    let mut fields = mate_fields(read, mate, reference_id);
+    if read.strand == Strand::Reverse {
+        fields.flag |= FLAG_REVERSE;
+    }
+    if read.qc_fail {
+        fields.flag |= FLAG_QC_FAIL;
+    }
+    // The adapter is at the end of the read as sequenced, which is the left end along the
+    // reference for reverse reads.
+    let aligned_length = read.aligned_length();
+    let match_op = (aligned_length as u32) << 4 | CIGAR_MATCH;
+    let clip_op = (read.adapter_length as u32) << 4 | CIGAR_SOFT_CLIP;
+    let (cigar, aligned) = match (read.adapter_length, read.strand) {
+        (0, _) => (vec![match_op], &seq[..]),
+        (_, Strand::Forward) => (vec![match_op, clip_op], &seq[..aligned_length]),
+        (_, Strand::Reverse) => (vec![clip_op, match_op], &seq[read.adapter_length..]),
+    };
+    let mut record: Vec<u8> = Vec::new();
+    record.extend_from_slice(&reference_id.to_le_bytes());
+    record.extend_from_slice(&(read.start as i32).to_le_bytes());
+    record.push(read.name.len() as u8 + 1);
+    record.push(TRUTH_MAPQ);
+    record.extend_from_slice(&reg2bin(read.start, read.start + aligned_length).to_le_bytes());
+    record.extend_from_slice(&(cigar.len() as u16).to_le_bytes());
+    record.extend_from_slice(&fields.flag.to_le_bytes());
+    record.extend_from_slice(&(seq.len() as u32).to_le_bytes());
+    record.extend_from_slice(&fields.next_reference_id.to_le_bytes());
+    record.extend_from_slice(&fields.next_position.to_le_bytes());
+    record.extend_from_slice(&fields.template_length.to_le_bytes());
+    record.extend_from_slice(read.name.as_bytes());
+    record.push(0);
+    for operation in &cigar {
+        record.extend_from_slice(&operation.to_le_bytes());
+    }
+    for pair in seq.chunks(2) {
+        let low = pair.get(1).map(|base| base_to_nibble(*base)).unwrap_or(0);
+        record.push(base_to_nibble(pair[0]) << 4 | low);
+    }
+    record.extend(quals.iter().map(|quality| (*quality).min(93) as u8));
+    let tags = alignment_tags(aligned, &reference[read.start..read.start + aligned_length]);
+    record.extend_from_slice(b"NMi");
+    record.extend_from_slice(&tags.edit_distance.to_le_bytes());
+    record.extend_from_slice(b"MDZ");
+    record.extend_from_slice(tags.mismatches.as_bytes());
+    record.push(0);
+    record.extend_from_slice(b"ASi");
+    record.extend_from_slice(&tags.score.to_le_bytes());
+    record.extend_from_slice(b"RGZ");
+    record.extend_from_slice(read_group_id.as_bytes());
+    record.push(0);
+    if let Some(duplex) = &read.duplex {
+        record.extend_from_slice(b"RXZ");
+        record.extend_from_slice(duplex.umi.as_bytes());
+        record.push(0);
+        record.extend_from_slice(b"MIZ");
+        record.extend_from_slice(duplex.molecule_id.as_bytes());
+        record.push(0);
+    }
+    writer.write_all(&(record.len() as u32).to_le_bytes())?;
+    writer.write_all(&record)
+}
+
+pub fn write_bam_records(
+    writer: &mut impl Write,
+    templates: &[&[SimulatedRead]],
+    reference_id: i32,
+    reference: &[u8],
+    read_group_id: &str,
+) -> io::Result<()> {
+    // Takes:
+    // writer: Where to write the records, usually a BgzfWriter.
+    // templates: The reads to write, one slice per template (mate 1, then mate 2). In paired
+    //     ended runs, a template holding one read is a pair that lost its other mate, and the
+    //     read is written as a singleton.
+    // reference_id: The index of the reads' contig in the header.
+    // reference: The (unmutated) reference sequence of that contig, for the NM, MD and AS tags.
+    // read_group_id: The read group the records belong to.
+    // Returns:
+    // Error if there is a problem or else nothing.
+    for (read, mate) in template_records(templates) {
+        write_bam_record(writer, read, mate, reference_id, reference, read_group_id)?;
+    }
+    Ok(())
+}
+
+pub fn write_sorted_bam_records(
+    writer: &mut impl Write,
+    templates: &[&[SimulatedRead]],
+    reference_id: i32,
+    reference: &[u8],
+    read_group_id: &str,
+) -> io::Result<()> {
+    // Like write_bam_records, but with the records in order of position rather than with mates
+    // together, for bams marked SO:coordinate. Records at the same position keep their order.
+    let mut records = template_records(templates);
+    records.sort_by_key(|(read, _)| read.start);
+    for (read, mate) in records {
+        write_bam_record(writer, read, mate, reference_id, reference, read_group_id)?;
+    }
+    Ok(())
+}
+
+fn template_records<'a>(
+    templates: &[&'a [SimulatedRead]],
+) -> Vec<(&'a SimulatedRead, Option<&'a SimulatedRead>)> {
+    // Each read of the templates, in order, with its mate if it still has one.
+    let mut records = Vec::new();
+    for template in templates {
+        match template {
+            [read1, read2] => {
+                records.push((read1, Some(read2)));
+                records.push((read2, Some(read1)));
+            },
+            _ => records.extend(template.iter().map(|read| (read, None))),
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::MultiGzDecoder;
+    use super::super::bgzf::{write_bgzf_eof, BgzfWriter};
+
+    fn test_read(mate: u8, start: usize, length: usize, strand: Strand) -> SimulatedRead {
+        SimulatedRead {
+            name: "read1".to_string(),
+            strand,
+            mate,
+            ..SimulatedRead::for_test("chr1", start, vec![0; length])
+        }
+    }
+
+    #[test]
+    fn test_mate_fields() {
+        let read1 = test_read(1, 100, 50, Strand::Forward);
+        let read2 = test_read(2, 200, 50, Strand::Reverse);
+        let fields1 = mate_fields(&read1, Some(&read2), 0);
+        let fields2 = mate_fields(&read2, Some(&read1), 0);
+        assert_eq!(fields1, MateFields {
+            flag: FLAG_PAIRED | FLAG_PROPER_PAIR | FLAG_MATE_REVERSE | FLAG_FIRST_IN_PAIR,
+            next_reference_id: 0,
+            next_position: 200,
+            template_length: 150,
+        });
+        assert_eq!(fields2, MateFields {
+            flag: FLAG_PAIRED | FLAG_PROPER_PAIR | FLAG_SECOND_IN_PAIR,
+            next_reference_id: 0,
+            next_position: 100,
+            template_length: -150,
+        });
+        // Pointing away from each other isn't a proper pair
+        let outward = test_read(2, 50, 50, Strand::Reverse);
+        assert_eq!(mate_fields(&read1, Some(&outward), 0).flag & FLAG_PROPER_PAIR, 0);
+        assert_eq!(mate_fields(&read1, Some(&outward), 0).template_length, -100);
+        // Single ended reads, and reads whose mate was lost, are singletons
+        for read in [&read1, &read2] {
+            assert_eq!(mate_fields(read, None, 0), MateFields {
+                flag: 0,
+                next_reference_id: -1,
+                next_position: -1,
+                template_length: 0,
+            });
+        }
+    }
+
+    #[test]
+    fn test_alignment_tags() {
+        let reference = vec![0, 1, 2, 3, 0, 1, 2, 3];
+        assert_eq!(alignment_tags(&reference, &reference), AlignmentTags {
+            edit_distance: 0,
+            mismatches: "8".to_string(),
+            score: 8,
+        });
+        let read = vec![3, 1, 2, 3, 0, 4, 2, 0];
+        assert_eq!(alignment_tags(&read, &reference), AlignmentTags {
+            edit_distance: 3,
+            mismatches: "0A4C1T0".to_string(),
+            score: 5 - 12,
+        });
+    }
+
+    #[test]
+    fn test_write_bam() {
+        let read1 = test_read(1, 0, 5, Strand::Forward);
+        let mut read2 = test_read(2, 10, 5, Strand::Reverse);
+        read2.seq = vec![0, 1, 2, 3, 4];
+        let mut writer = BgzfWriter::new(Vec::new());
+        let read_group = ReadGroup {
+            id: "normal".to_string(),
+            sample: "normal".to_string(),
+            library: "normal_lib".to_string(),
+            platform: "ILLUMINA".to_string(),
+            platform_unit: Some("NEATSIM01.1".to_string()),
+        };
+        let provenance = Provenance {
+            seed: "Hello BAM".to_string(),
+            version: "0.0.0",
+            config_hash: 0xabc,
+        };
+        write_bam_header(
+            &mut writer, &["chr1".to_string()], &[100], &read_group, &provenance, false
+        ).unwrap();
+        let reference = vec![0; 100];
+        let template = [read1, read2];
+        write_bam_records(&mut writer, &[&template[..]], 0, &reference, "normal").unwrap();
+        let mut bam = writer.finish().unwrap();
+        write_bgzf_eof(&mut bam).unwrap();
+
+        let mut data = Vec::new();
+        MultiGzDecoder::new(&bam[..]).read_to_end(&mut data).unwrap();
+        assert_eq!(&data[..4], b"BAM\x01");
+        let text_length = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        let text = String::from_utf8(data[8..8 + text_length].to_vec()).unwrap();
+        assert!(text.contains("@SQ\tSN:chr1\tLN:100\n"));
+        assert!(text.contains("@RG\tID:normal\tSM:normal\tLB:normal_lib\tPL:ILLUMINA\tPU:NEATSIM01.1\n"));
+        assert!(text.contains("@PG\tID:rusty-neat\tPN:rusty-neat\tVN:0.0.0\tDS:seed=Hello BAM; config_hash=0000000000000abc\n"));
+        // magic, l_text, text, n_ref, l_name, "chr1\0", l_ref
+        let first_record = 8 + text_length + 4 + 4 + 5 + 4;
+        let record = &data[first_record + 4..];
+        assert_eq!(i32::from_le_bytes(record[4..8].try_into().unwrap()), 0);
+        assert_eq!(u16::from_le_bytes(record[14..16].try_into().unwrap()), 0x63);
+        assert_eq!(i32::from_le_bytes(record[28..32].try_into().unwrap()), 15);
+        // The second record is read 2, stored along the reference: reverse complement of ACGTN
+        let second = first_record + 4 + u32::from_le_bytes(
+            data[first_record..first_record + 4].try_into().unwrap()
+        ) as usize;
+        let record = &data[second + 4..];
+        assert_eq!(u16::from_le_bytes(record[14..16].try_into().unwrap()), 0x93);
+        assert_eq!(i32::from_le_bytes(record[28..32].try_into().unwrap()), -15);
+        // name "read1\0" then one cigar op, then the packed sequence
+        let seq_start = 32 + 6 + 4;
+        assert_eq!(&record[seq_start..seq_start + 3], &[0xf1, 0x24, 0x80]);
+        // Against a reference of all A's, the N and the C, G and T are mismatches
+        let tags = &record[seq_start + 3 + 5..];
+        assert_eq!(&tags[..7], b"NMi\x04\0\0\0");
+        assert_eq!(&tags[7..20], b"MDZ0A1A0A0A0\0");
+        assert_eq!(&tags[20..27], b"ASi\xf1\xff\xff\xff");
+        assert_eq!(&tags[27..], b"RGZnormal\0");
+    }
+
+    #[test]
+    fn test_write_bam_soft_clips() {
+        // A 3 base fragment read with 5 base reads: 2 adapter bases on each
+        let mut read1 = test_read(1, 10, 5, Strand::Forward);
+        read1.adapter_length = 2;
+        read1.qc_fail = true;
+        let mut read2 = test_read(2, 10, 5, Strand::Reverse);
+        read2.adapter_length = 2;
+        read2.seq = vec![3, 3, 3, 0, 2];
+        let fields = mate_fields(&read1, Some(&read2), 0);
+        assert_eq!(fields.template_length, 3);
+        let mut records = Vec::new();
+        let template = [read1, read2];
+        write_bam_records(&mut records, &[&template[..]], 0, &[0; 20], "normal").unwrap();
+        // block_size, then the fixed fields up to n_cigar_op
+        let record = &records[4..];
+        assert_eq!(u16::from_le_bytes(record[12..14].try_into().unwrap()), 2);
+        assert_ne!(u16::from_le_bytes(record[14..16].try_into().unwrap()) & FLAG_QC_FAIL, 0);
+        let cigar_start = 32 + 6;
+        // 3M2S
+        assert_eq!(&record[cigar_start..cigar_start + 8], &[0x30, 0, 0, 0, 0x24, 0, 0, 0]);
+        let second = 4 + u32::from_le_bytes(records[..4].try_into().unwrap()) as usize;
+        let record = &records[second + 4..];
+        assert_eq!(u16::from_le_bytes(record[14..16].try_into().unwrap()) & FLAG_QC_FAIL, 0);
+        // 2S3M, with the adapter (reverse complement CT) first along the reference
+        assert_eq!(&record[cigar_start..cigar_start + 8], &[0x24, 0, 0, 0, 0x30, 0, 0, 0]);
+        assert_eq!(&record[cigar_start + 8..cigar_start + 11], &[0x28, 0x11, 0x10]);
+        // Only the aligned bases (AAA) are compared to the reference
+        let tags = &record[cigar_start + 8 + 3 + 5..];
+        assert_eq!(&tags[..7], b"NMi\0\0\0\0");
+        assert_eq!(&tags[7..12], b"MDZ3\0");
+    }
+
+    #[test]
+    fn test_write_sorted_bam_records() {
+        let late = [test_read(1, 50, 5, Strand::Forward), test_read(2, 80, 5, Strand::Reverse)];
+        let early = [test_read(1, 10, 5, Strand::Forward), test_read(2, 60, 5, Strand::Reverse)];
+        let templates: Vec<&[SimulatedRead]> = vec![&late, &early];
+        let positions = |records: &[u8]| {
+            let mut positions = Vec::new();
+            let mut offset = 0;
+            while offset < records.len() {
+                let size = u32::from_le_bytes(records[offset..offset + 4].try_into().unwrap());
+                let record = &records[offset + 4..];
+                positions.push(i32::from_le_bytes(record[4..8].try_into().unwrap()));
+                offset += 4 + size as usize;
+            }
+            positions
+        };
+        let mut records = Vec::new();
+        write_bam_records(&mut records, &templates, 0, &[0; 100], "normal").unwrap();
+        assert_eq!(positions(&records), vec![50, 80, 10, 60]);
+        let mut records = Vec::new();
+        write_sorted_bam_records(&mut records, &templates, 0, &[0; 100], "normal").unwrap();
+        assert_eq!(positions(&records), vec![10, 50, 60, 80]);
+    }
+    #[test]
+    fn test_lost_mate_record() {
+        // A pair, then read 2 of a pair that lost read 1
+        let pair = [test_read(1, 10, 5, Strand::Forward), test_read(2, 60, 5, Strand::Reverse)];
+        let lost = [test_read(2, 80, 5, Strand::Reverse)];
+        let templates: Vec<&[SimulatedRead]> = vec![&pair, &lost];
+        let mut records = Vec::new();
+        write_bam_records(&mut records, &templates, 0, &[0; 100], "normal").unwrap();
+        let mut offset = 0;
+        let mut fields = Vec::new();
+        while offset < records.len() {
+            let size = u32::from_le_bytes(records[offset..offset + 4].try_into().unwrap());
+            let record = &records[offset + 4..];
+            let flag = u16::from_le_bytes(record[14..16].try_into().unwrap());
+            let next_reference_id = i32::from_le_bytes(record[20..24].try_into().unwrap());
+            fields.push((flag, next_reference_id));
+            offset += 4 + size as usize;
+        }
+        assert_eq!(fields.len(), 3);
+        assert!(fields[..2].iter().all(|(flag, next)| flag & FLAG_PAIRED != 0 && *next == 0));
+        // The survivor is written as a singleton, not as half of a pair
+        assert_eq!(fields[2], (FLAG_REVERSE, -1));
+    }
+}
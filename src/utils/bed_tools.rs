@@ -0,0 +1,191 @@
+// Reads target regions from bed files, and writes bed files describing the simulated regions of
+// the reference. Regions are 0-based, half open [start, end) spans, as in the bed format.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use super::errors::NeatError;
+
+pub type Regions = HashMap<String, Vec<(usize, usize)>>;
+
+pub fn merge_regions(regions: &mut Vec<(usize, usize)>) {
+    // Sorts the regions and joins any that overlap or touch.
+    regions.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(regions.len());
+    for &(start, end) in regions.iter() {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    *regions = merged;
+}
+
+pub fn read_bed(path: &str) -> Result<Regions, NeatError> {
+    // Takes:
+    // path: A bed file. Only the first three columns are used; header, track and comment lines
+    //     are skipped.
+    // Returns:
+    // The regions of each contig, sorted and merged, or an error if the file can't be read or a
+    // line doesn't parse.
+    let text = fs::read_to_string(path)?;
+    let mut regions = Regions::new();
+    for (line_number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || ["#", "track", "browser"].iter().any(|skip| line.starts_with(skip)) {
+            continue
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let bad_line = || NeatError::BedParse(
+            format!("{} line {}: {:?}", path, line_number + 1, line)
+        );
+        if fields.len() < 3 {
+            return Err(bad_line())
+        }
+        let start: usize = fields[1].trim().parse().map_err(|_| bad_line())?;
+        let end: usize = fields[2].trim().parse().map_err(|_| bad_line())?;
+        if end <= start {
+            return Err(bad_line())
+        }
+        regions.entry(fields[0].to_string()).or_default().push((start, end));
+    }
+    for contig_regions in regions.values_mut() {
+        merge_regions(contig_regions);
+    }
+    Ok(regions)
+}
+
+pub fn pad_regions(regions: &Regions, padding: usize, contig_lengths: &HashMap<String, usize>) -> Regions {
+    // Widens every region by padding on each side, without going past the ends of its contig.
+    // Regions on contigs that aren't in contig_lengths are dropped.
+    let mut padded = Regions::new();
+    for (contig, contig_regions) in regions {
+        let length = match contig_lengths.get(contig) {
+            Some(length) => *length,
+            None => continue,
+        };
+        let mut widened: Vec<(usize, usize)> = contig_regions
+            .iter()
+            .map(|(start, end)| (start.saturating_sub(padding), (end + padding).min(length)))
+            .filter(|(start, end)| start < end)
+            .collect();
+        merge_regions(&mut widened);
+        padded.insert(contig.clone(), widened);
+    }
+    padded
+}
+
+pub fn intersect_regions(
+    first: &[(usize, usize)],
+    second: &[(usize, usize)],
+) -> Vec<(usize, usize)> {
+    // The spans covered by both lists of sorted, merged regions.
+    let mut overlaps = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < first.len() && j < second.len() {
+        let start = first[i].0.max(second[j].0);
+        let end = first[i].1.min(second[j].1);
+        if start < end {
+            overlaps.push((start, end));
+        }
+        if first[i].1 < second[j].1 { i += 1 } else { j += 1 }
+    }
+    overlaps
+}
+
+pub fn non_n_regions(sequence: &[u8]) -> Vec<(usize, usize)> {
+    // Returns the [start, end) spans of the sequence that aren't N (4), in order.
+    let mut regions = Vec::new();
+    let mut region_start: Option<usize> = None;
+    for (index, base) in sequence.iter().enumerate() {
+        match (region_start, *base == 4) {
+            (None, false) => region_start = Some(index),
+            (Some(start), true) => {
+                regions.push((start, index));
+                region_start = None;
+            },
+            _ => {},
+        }
+    }
+    if let Some(start) = region_start {
+        regions.push((start, sequence.len()));
+    }
+    regions
+}
+
+pub fn write_confident_regions(
+    writer: &mut impl Write,
+    sequences: &HashMap<String, Vec<u8>>,
+    contig_order: &[String],
+    min_contig_length: usize,
+    targets: Option<&Regions>,
+) -> io::Result<()> {
+    // Takes:
+    // writer: Where to write the bed.
+    // sequences: The reference sequences, keyed by contig name.
+    // contig_order: Contig names in reference order.
+    // min_contig_length: Contigs shorter than this (no reads fit on them) are left out.
+    // targets: For panel runs, the target regions. Only bases inside them are written.
+    // Returns:
+    // Error if there is a problem or else nothing.
+    //
+    // Every base outside an N run could have been mutated and is covered by reads, so these are
+    // the regions a benchmark against the truth vcf can be confident in.
+    for contig in contig_order {
+        let sequence = &sequences[contig];
+        if sequence.len() < min_contig_length {
+            continue
+        }
+        let mut regions = non_n_regions(sequence);
+        if let Some(targets) = targets {
+            regions = intersect_regions(&regions, targets.get(contig).map_or(&[], Vec::as_slice));
+        }
+        for (start, end) in regions {
+            writeln!(writer, "{}\t{}\t{}", contig, start, end)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_confident_regions() {
+        let sequences = HashMap::from([
+            ("chr1".to_string(), vec![4, 4, 0, 1, 4, 2, 3, 3]),
+            ("chr2".to_string(), vec![0, 1]),
+            ("chr3".to_string(), vec![0, 1, 2, 3, 0, 4]),
+        ]);
+        let order = vec!["chr1".to_string(), "chr2".to_string(), "chr3".to_string()];
+        let mut bed = Vec::new();
+        write_confident_regions(&mut bed, &sequences, &order, 3, None).unwrap();
+        assert_eq!(
+            String::from_utf8(bed).unwrap(),
+            "chr1\t2\t4\nchr1\t5\t8\nchr3\t0\t5\n"
+        );
+        let targets = Regions::from([("chr1".to_string(), vec![(3, 6)])]);
+        let mut bed = Vec::new();
+        write_confident_regions(&mut bed, &sequences, &order, 3, Some(&targets)).unwrap();
+        assert_eq!(String::from_utf8(bed).unwrap(), "chr1\t3\t4\nchr1\t5\t6\n");
+    }
+
+    #[test]
+    fn test_read_bed() {
+        let path = "test_data/test_targets.bed";
+        fs::write(path, "track name=panel\nchr2\t100\t200\tEGFR\nchr1\t50\t60\nchr2\t150\t250\n").unwrap();
+        let regions = read_bed(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(regions["chr1"], vec![(50, 60)]);
+        assert_eq!(regions["chr2"], vec![(100, 250)]);
+        let lengths = HashMap::from([("chr1".to_string(), 70)]);
+        let padded = pad_regions(&regions, 20, &lengths);
+        assert_eq!(padded, Regions::from([("chr1".to_string(), vec![(30, 70)])]));
+
+        fs::write("test_data/test_bad.bed", "chr1\t60\t50\n").unwrap();
+        let result = read_bed("test_data/test_bad.bed");
+        fs::remove_file("test_data/test_bad.bed").unwrap();
+        assert!(matches!(result, Err(NeatError::BedParse(_))));
+    }
+}
@@ -0,0 +1,130 @@
+// BGZF is the blocked gzip format used by BAM files and tabix-indexed text files: a series of
+// gzip members, each holding at most 64 KB of data, with the compressed size of the member stored
+// in a BC extra field. Any position in the file can be addressed by a virtual offset, the offset
+// of a block in the file shifted up 16 bits plus the offset of the position within the block.
+
+use std::io;
+use std::io::Write;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+
+// Most uncompressed data in one block. Chosen (as htslib does) so that the compressed block always
+// fits in the 64 KB limit, even when the data doesn't compress.
+const MAX_BLOCK_DATA: usize = 0xff00;
+// gzip header with the BC extra field, minus the block size, and the gzip trailer.
+const BGZF_HEADER: [u8; 16] = [
+    0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 0x06, 0x00, b'B', b'C', 0x02, 0x00
+];
+const BGZF_HEADER_SIZE: usize = 18;
+const BGZF_FOOTER_SIZE: usize = 8;
+// An empty block marks the end of the file.
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0, 0, 0, 0, 0, 0xff, 0x06, 0x00, b'B', b'C', 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+pub struct BgzfWriter<W: Write> {
+    // Compresses everything written to it into BGZF blocks. Call finish (and then write_bgzf_eof
+    // for a complete file) when done; dropping the writer loses any buffered data.
+    inner: W,
+    buffer: Vec<u8>,
+    // Compressed bytes written so far, i.e. where the next block will start.
+    block_offset: u64,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W) -> Self {
+        BgzfWriter { inner, buffer: Vec::with_capacity(MAX_BLOCK_DATA), block_offset: 0 }
+    }
+
+    pub fn virtual_offset(&self) -> u64 {
+        // The virtual offset of the next byte to be written.
+        self.block_offset << 16 | self.buffer.len() as u64
+    }
+
+    fn write_block(&mut self, data_length: usize) -> io::Result<()> {
+        // Compresses the first data_length bytes of the buffer into one block.
+        let data: Vec<u8> = self.buffer.drain(..data_length).collect();
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data)?;
+        let compressed = encoder.finish()?;
+        let mut crc = Crc::new();
+        crc.update(&data);
+        let block_size = BGZF_HEADER_SIZE + compressed.len() + BGZF_FOOTER_SIZE;
+        self.inner.write_all(&BGZF_HEADER)?;
+        self.inner.write_all(&((block_size - 1) as u16).to_le_bytes())?;
+        self.inner.write_all(&compressed)?;
+        self.inner.write_all(&crc.sum().to_le_bytes())?;
+        self.inner.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.block_offset += block_size as u64;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        // Writes out whatever is still buffered and hands back the underlying writer.
+        self.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        while self.buffer.len() >= MAX_BLOCK_DATA {
+            self.write_block(MAX_BLOCK_DATA)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_block(self.buffer.len())?;
+        }
+        self.inner.flush()
+    }
+}
+
+pub fn write_bgzf_eof(writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(&BGZF_EOF)
+}
+
+pub fn reg2bin(start: usize, end: usize) -> u16 {
+    // The bin for a record covering [start, end), as computed in the SAM spec (section 5.3).
+    let end = end.max(start + 1) - 1;
+    // (shift, first bin) for each level, from the smallest (16 KB) bins up
+    for (shift, first_bin) in [(14, 4681), (17, 585), (20, 73), (23, 9), (26, 1)] {
+        if start >> shift == end >> shift {
+            return (first_bin + (start >> shift)) as u16
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::MultiGzDecoder;
+
+    #[test]
+    fn test_reg2bin() {
+        assert_eq!(reg2bin(0, 100), 4681);
+        assert_eq!(reg2bin(16380, 16390), 585);
+        assert_eq!(reg2bin(0, 1 << 29), 0);
+    }
+
+    #[test]
+    fn test_bgzf_round_trip() {
+        let data: Vec<u8> = (0..200_000).map(|index| (index % 251) as u8).collect();
+        let mut writer = BgzfWriter::new(Vec::new());
+        writer.write_all(&data[..10]).unwrap();
+        assert_eq!(writer.virtual_offset(), 10);
+        writer.write_all(&data[10..]).unwrap();
+        let mut compressed = writer.finish().unwrap();
+        write_bgzf_eof(&mut compressed).unwrap();
+        assert!(compressed.ends_with(&BGZF_EOF));
+        let mut decompressed = Vec::new();
+        MultiGzDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
@@ -0,0 +1,173 @@
+// A hybrid capture model for panel and exome runs. Real capture doesn't cover targets as hard
+// edged blocks: fragments hanging off a target are pulled down too, so coverage tails off over a
+// shoulder past each edge; some probes capture much better than others, so targets vary in depth;
+// and fragments far from the GC content the probes like hybridize poorly and drop out. Each
+// fragment is kept with the product of those three, relative to the best target of its contig,
+// which it is covered to first.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use simple_rng::{NormalDistribution, Rng};
+use super::bed_tools::Regions;
+use super::nucleotides::is_gc;
+
+// Targets capture at most this many times better than average. The efficiencies are log-normal,
+// and every contig is covered to its best target, so a rare outlier would multiply the work.
+const MAX_CAPTURE_EFFICIENCY: f64 = 4.0;
+
+// The GC content fragments capture best at, and how quickly capture falls off away from it (the
+// standard deviation of the curve at a dropout of 1).
+const GC_OPTIMUM: f64 = 0.45;
+const GC_DROPOUT_WIDTH: f64 = 0.15;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CaptureModel {
+    // targets: The (start, end, efficiency) of each target of each contig, sorted and merged.
+    // shoulder: How far past a target edge, in bases, fragments are still captured. Capture falls
+    //     smoothly from full at the edge to nothing at this distance.
+    // gc_dropout: How strongly capture falls off away from GC_OPTIMUM. 0 turns it off.
+    targets: HashMap<String, Vec<(usize, usize, f64)>>,
+    shoulder: usize,
+    gc_dropout: f64,
+}
+
+impl CaptureModel {
+    pub fn new(
+        targets: &Regions,
+        shoulder: usize,
+        efficiency_st_dev: f64,
+        gc_dropout: f64,
+        contig_order: &[String],
+        rng: &mut Rng,
+    ) -> Self {
+        // Takes:
+        // targets: The unpadded target regions of each contig, sorted and merged.
+        // shoulder: See above.
+        // efficiency_st_dev: The spread of the targets' efficiencies, as the standard deviation of
+        //     their logarithm. The efficiencies average 1. 0 makes every target the same.
+        // gc_dropout: See above.
+        // contig_order: The contigs in reference order, which the efficiencies are drawn in.
+        // rng: random number generator for the efficiencies.
+        // Returns:
+        // The capture model.
+        // Centered so that the efficiencies, not their logarithms, average 1
+        let distribution = (efficiency_st_dev > 0.0).then(|| {
+            NormalDistribution::new(-efficiency_st_dev.powi(2) / 2.0, efficiency_st_dev)
+        });
+        let mut efficient_targets = HashMap::new();
+        for contig in contig_order {
+            let Some(regions) = targets.get(contig) else { continue };
+            let regions_with_efficiency: Vec<(usize, usize, f64)> = regions
+                .iter()
+                .map(|(start, end)| {
+                    let efficiency = distribution
+                        .as_ref()
+                        .map_or(1.0, |distribution| distribution.sample(rng).exp());
+                    (*start, *end, efficiency.min(MAX_CAPTURE_EFFICIENCY))
+                })
+                .collect();
+            efficient_targets.insert(contig.clone(), regions_with_efficiency);
+        }
+        CaptureModel { targets: efficient_targets, shoulder, gc_dropout }
+    }
+
+    pub fn max_efficiency(&self, contig: &str) -> f64 {
+        // The efficiency of the contig's best target, 0 if it has none.
+        self.targets
+            .get(contig)
+            .map(|spans| spans.iter().map(|(_, _, efficiency)| *efficiency).fold(0.0, f64::max))
+            .unwrap_or(0.0)
+    }
+
+    fn shoulder_rate(&self, distance: usize) -> f64 {
+        // How well a fragment centered this far outside a target is captured: a cosine falling
+        // from 1 at the edge to 0 at the end of the shoulder.
+        match distance {
+            0 => 1.0,
+            distance if distance < self.shoulder => {
+                0.5 * (1.0 + (PI * distance as f64 / self.shoulder as f64).cos())
+            },
+            _ => 0.0,
+        }
+    }
+
+    pub fn position_rate(&self, contig: &str, position: usize) -> f64 {
+        // The capture of a fragment centered at this position, from the nearest targets on
+        // either side, relative to an average target.
+        let Some(spans) = self.targets.get(contig) else { return 0.0 };
+        let index = spans.partition_point(|(start, _, _)| *start <= position);
+        let before = index.checked_sub(1).map(|index| spans[index]).map(|(_, end, efficiency)| {
+            efficiency * self.shoulder_rate((position + 1).saturating_sub(end))
+        });
+        let after = spans.get(index).map(|(start, _, efficiency)| {
+            efficiency * self.shoulder_rate(start - position)
+        });
+        before.unwrap_or(0.0).max(after.unwrap_or(0.0))
+    }
+
+    pub fn gc_rate(&self, fragment: &[u8]) -> f64 {
+        // How well a fragment with this sequence hybridizes: 1 at GC_OPTIMUM, falling off as a
+        // bell curve either side. N's don't count towards the GC content.
+        let called = fragment.iter().filter(|base| **base < 4).count();
+        if self.gc_dropout <= 0.0 || called == 0 {
+            return 1.0
+        }
+        let gc = fragment.iter().filter(|base| is_gc(**base)).count() as f64 / called as f64;
+        (-self.gc_dropout * (gc - GC_OPTIMUM).powi(2) / (2.0 * GC_DROPOUT_WIDTH.powi(2))).exp()
+    }
+
+    pub fn fragment_rate(
+        &self,
+        contig: &str,
+        (start, end): (usize, usize),
+        sequence: &[u8],
+    ) -> f64 {
+        // The capture of the fragment at [start, end) of the contig's sequence.
+        self.position_rate(contig, (start + end) / 2) * self.gc_rate(&sequence[start..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_model() {
+        let targets = Regions::from([("chr1".to_string(), vec![(100, 200), (1000, 1100)])]);
+        let contig_order = vec!["chr1".to_string(), "chr2".to_string()];
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Capture".to_string()]);
+        let even = CaptureModel::new(&targets, 50, 0.0, 0.0, &contig_order, &mut rng);
+        assert_eq!(even.max_efficiency("chr1"), 1.0);
+        assert_eq!(even.max_efficiency("chr2"), 0.0);
+        assert_eq!(even.position_rate("chr1", 150), 1.0);
+        // Halfway down the shoulder on either side, then nothing
+        assert!((even.position_rate("chr1", 224) - 0.5).abs() < 1e-9);
+        assert!((even.position_rate("chr1", 975) - 0.5).abs() < 1e-9);
+        assert_eq!(even.position_rate("chr1", 600), 0.0);
+        assert_eq!(even.position_rate("chr2", 150), 0.0);
+
+        let varied = CaptureModel::new(&targets, 50, 0.5, 0.0, &contig_order, &mut rng);
+        let efficiencies: Vec<f64> = varied.targets["chr1"].iter().map(|target| target.2).collect();
+        assert_ne!(efficiencies[0], efficiencies[1]);
+        assert!(efficiencies.iter().all(|efficiency| *efficiency <= MAX_CAPTURE_EFFICIENCY));
+        assert_eq!(varied.position_rate("chr1", 150), efficiencies[0]);
+    }
+
+    #[test]
+    fn test_gc_rate() {
+        let targets = Regions::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "GC".to_string()]);
+        let model = CaptureModel::new(&targets, 0, 0.0, 1.0, &[], &mut rng);
+        let balanced = [0, 1, 2, 3, 0, 1, 2, 3, 0, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 3];
+        let gc_rich = [1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 1, 2, 0, 3];
+        let at_rich = [0, 3, 0, 3, 0, 3, 0, 3, 0, 3, 0, 3, 0, 3, 0, 3, 0, 1, 2, 3];
+        // 40%, 90% and 10% GC
+        assert!(model.gc_rate(&balanced) > 0.9);
+        assert!(model.gc_rate(&gc_rich) < 0.02);
+        assert!(model.gc_rate(&at_rich) < 0.1);
+        assert_eq!(model.gc_rate(&[4, 4, 4]), 1.0);
+        // No dropout, no effect
+        let flat = CaptureModel::new(&targets, 0, 0.0, 0.0, &[], &mut rng);
+        assert_eq!(flat.gc_rate(&gc_rich), 1.0);
+    }
+}
@@ -0,0 +1,98 @@
+// Stable checksums for output files. These need to give the same answer on every platform and
+// every version of Rust (they are compared against values stored in the binary), so we use our own
+// FNV-1a rather than std's hasher, which makes no such promise.
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fnv1a {
+    state: u64,
+}
+
+impl Fnv1a {
+    pub fn new() -> Self {
+        Fnv1a { state: FNV_OFFSET_BASIS }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn checksum_file(path: &Path) -> io::Result<u64> {
+    // FNV-1a of the whole file, read in chunks.
+    let mut file = File::open(path)?;
+    let mut hasher = Fnv1a::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(hasher.finish())
+}
+
+pub fn checksum_file_skipping(path: &Path, skip_prefixes: &[&str]) -> io::Result<u64> {
+    // FNV-1a of the file, leaving out any line that starts with one of skip_prefixes. Used for
+    // outputs that record where they were made (like the reference path in a vcf header), which
+    // shouldn't change the checksum.
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Fnv1a::new();
+    let mut line: Vec<u8> = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        if !skip_prefixes.iter().any(|prefix| line.starts_with(prefix.as_bytes())) {
+            hasher.update(&line);
+        }
+        line.clear();
+    }
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a_known_values() {
+        // Reference values for 64 bit FNV-1a
+        assert_eq!(Fnv1a::new().finish(), 0xcbf29ce484222325);
+        let mut hasher = Fnv1a::new();
+        hasher.update(b"a");
+        assert_eq!(hasher.finish(), 0xaf63dc4c8601ec8c);
+        let mut hasher = Fnv1a::new();
+        hasher.update(b"foo");
+        assert_eq!(hasher.finish(), 0xdcb27518fed9d577);
+    }
+
+    #[test]
+    fn test_checksum_file() {
+        let path = Path::new("test_data/H1N1.fa");
+        let mut hasher = Fnv1a::new();
+        hasher.update(&std::fs::read(path).unwrap());
+        assert_eq!(checksum_file(path).unwrap(), hasher.finish());
+        assert_eq!(checksum_file_skipping(path, &[]).unwrap(), hasher.finish());
+        // H1N1.fa has a header line per contig, so skipping them changes the checksum.
+        assert_ne!(checksum_file_skipping(path, &[">"]).unwrap(), hasher.finish());
+    }
+}
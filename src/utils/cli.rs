@@ -4,7 +4,7 @@
 // features, which are handled separately. Either way, these options are read into a configuration
 // struct that holds the variables for the run. Logging, meanwhile, is handled separately,
 // outside run configuration parsing.
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::env;
 
 #[derive(Parser, Debug)]
@@ -16,14 +16,64 @@ pub struct Cli {
 
     config <String> = the full path to a configuration yaml file. If entered, it will override
         all other command line inputs. No default.
+    strict_config <bool> = Stop on keys the config file doesn't recognize, instead of warning about
+        them.
 
     reference <String> = The relative path or full path to the reference file. Must be in fasta
         file format. Default "data/H1N1.fa"
     output_dir <String> = The directory where output files will be written. If nothing is entered,
         it will write output files to the current working directory.
     output_file_prefix <String> = output files will start with this name. Default = neat_out
+    demo <bool> = Run a small built in simulation into output_dir (default ./neat_demo). Needs no
+        other input.
+    error_report <bool> = On failure, write <output_dir>/<output_file_prefix>_error.json describing
+        what went wrong, alongside a distinct exit code per failure class.
+    read_name_format <String> = simple or illumina. Default = simple
+    read_name_prefix <String> = Start of simple read names. Default = neat_generated
+    read_comment_tag <String> = A SAM-style tag (like BC:Z:ACGTAC) to add to every fastq header.
+        Can be given more than once.
+    truth_comment_tags <bool> = Add each read's contig, position and strand to its fastq header.
+    global_shuffle <bool> = Shuffle reads across contigs, not just within each one.
+    sort_reads <bool> = Write reads in order of position instead of shuffled, and a coordinate
+        sorted bam.
+    quality_offset <int> = Ascii offset of fastq quality scores, 33 or 64 (or any other printable
+        offset). Default = 33
+    max_quality <int> = Cap on the quality scores written to fastq files.
+    sample_name <String> = Sample name (SM) of the bam read group. Default = tumor for runs with a
+        somatic mutation rate, normal otherwise
+    sample_sex <String> = male, female or auto. The sex chromosomes get the copies of that sex,
+        with diploid pseudoautosomal regions in males. Default = auto, male if the reference has
+        an unmasked Y
+    library <String> = Library (LB) of the bam read group. Default = <sample_name>_lib
+    platform <String> = Platform (PL) of the bam read group, one the SAM spec lists.
+        Default = ILLUMINA
+    platform_unit <String> = Platform unit (PU) of the bam read group. One like
+        <flowcell>.<lane>[.<barcode>] also sets the flowcell and lane of Illumina read names.
+    instrument <String> = Instrument ID of Illumina read names. Default = NEAT
+    threads <usize> = The number of worker threads for read generation. Default = 1
+    max_memory <String> = A memory budget for the run, like 16G or 512M. Fewer threads are used,
+        and reads shuffled across contigs wait on disk, to stay under it.
+    throttle_mb_per_sec <f64> = Write output files no faster than this many MB a second, so runs
+        on shared nodes leave I/O for everyone else.
+    temp_dir <String> = Directory where output is staged while being written. Defaults to the
+        output directory.
+    sequence_dictionary <String> = A .dict or .fai for the reference. The run stops early if the
+        reference doesn't match it.
+    primary_assembly_only <bool> = Skip the alt, random, chrUn, HLA and decoy contigs of the
+        reference.
+    only_contig <String> = Write output for this contig only, the same as a full run's output of
+        it, to regenerate it without the rest of the genome.
+    contig_seed <String> = The seed of only_contig's reads, as logged by a full run.
+    region <String> = Simulate only this region (contig:start-end, 1-based), keeping its
+        coordinates in the outputs. Read straight from the reference if it has a .fai index.
+    reference_cache <String> = File to cache the parsed reference in, and to read it back from on
+        later runs (rebuilt whenever the fasta changes).
     read_length <usize> = the length of the reads in the output fastq file. Default = 150
     coverage <usize> = The average depth per read of the fastq files. Default = 10
+    replicates <usize> = Run the simulation this many times, with seeds derived from the run's seed
+        (<seed>.rep1, <seed>.rep2, ...), writing each replicate into <output_dir>/rep<n>.
+    random_seed_words <usize> = Seed the run with this many words from a word list, logged at the
+        start, instead of the clock.
 
     The following commands are independent of the config and not affected by it one way or another:
     log_level <String> = Set a log level for the run. Everything at and above the level chosen will
@@ -31,11 +81,21 @@ pub struct Cli {
         https://docs.rs/simplelog/latest/simplelog/enum.Level.html
     log_dest <String> = Full path filename where to write the log. The default is current working
         dir, filename "neat_out.log," which is set during config parsing.
+
+    Subcommands (with no subcommand, rusty-neat runs a simulation from the options above):
+    verify-determinism = run a configuration twice with the same seed and compare the output.
+    self-test = run the built in test cases and compare their output with known checksums.
+    compress-quality-model = prune, quantize and gzip a quality score model.
      */
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(short='C', long="configuration_yaml", default_value_t=String::new(),
     help="Enter a full path and filename to a configuration file. \
     This will override most other options")]
     pub config: String,
+    #[arg(long="strict-config", global=true, help="Fail on unknown keys in the configuration file instead of warning")]
+    pub strict_config: bool,
 
     // All of these arguments are overridden by the config file
     #[arg(short='r', long="reference", default_value_t=String::from(""))]
@@ -44,18 +104,101 @@ pub struct Cli {
     pub output_dir: String,
     #[arg(short='f', long="output_file_prefix", default_value_t=String::from("neat_out"))]
     pub output_file_prefix: String,
+    #[arg(long="demo", help="Run a quick simulation on a built in reference (into -o, default ./neat_demo)")]
+    pub demo: bool,
+    #[arg(long="error-report", help="On failure, write <prefix>_error.json in the output dir")]
+    pub error_report: bool,
+    #[arg(short='t', long="threads", default_value_t = 1)]
+    pub threads: usize,
+    #[arg(long="max-memory", help="Memory budget for the run, e.g. 16G; may use fewer threads")]
+    pub max_memory: Option<String>,
+    #[arg(long="throttle-mb-per-sec", help="Write output no faster than this many MB a second")]
+    pub throttle_mb_per_sec: Option<f64>,
+    #[arg(long="read-name-format", default_value_t=String::from("simple"), help="simple or illumina")]
+    pub read_name_format: String,
+    #[arg(long="read-name-prefix", default_value_t=String::from("neat_generated"))]
+    pub read_name_prefix: String,
+    #[arg(long="read-comment-tag", help="SAM-style tag for every fastq header, e.g. BC:Z:ACGTAC")]
+    pub read_comment_tags: Vec<String>,
+    #[arg(long="truth-tags", help="Add XC (contig), XP (position) and XS (strand) tags to fastq headers")]
+    pub truth_comment_tags: bool,
+    #[arg(long="global-shuffle", help="Shuffle reads across contigs, not just within each one")]
+    pub global_shuffle: bool,
+    #[arg(long="sort-reads", help="Write reads sorted by position, and a coordinate sorted bam")]
+    pub sort_reads: bool,
+    #[arg(long="quality-offset", default_value_t=33, help="Ascii offset of fastq quality scores (33 or 64)")]
+    pub quality_offset: u32,
+    #[arg(long="max-quality", help="Highest quality score written to fastq files")]
+    pub max_quality: Option<u32>,
+    #[arg(long="sample-name", help="Sample name for the bam read group (default tumor or normal)")]
+    pub sample_name: Option<String>,
+    #[arg(long="sample-sex", help="male, female or auto; the copies of the sex chromosomes")]
+    pub sample_sex: Option<String>,
+    #[arg(long="library", help="Library for the bam read group")]
+    pub library: Option<String>,
+    #[arg(long="platform", help="Platform for the bam read group (default ILLUMINA)")]
+    pub platform: Option<String>,
+    #[arg(long="platform-unit", help="Platform unit for the bam read group")]
+    pub platform_unit: Option<String>,
+    #[arg(long="instrument", help="Instrument ID for Illumina read names (default NEAT)")]
+    pub instrument: Option<String>,
+    #[arg(long="temp-dir", default_value_t=String::new())]
+    pub temp_dir: String,
+    #[arg(long="reference-cache", help="Cache the parsed reference in this file, and reuse it on later runs")]
+    pub reference_cache: Option<String>,
+    #[arg(long="sequence-dictionary", help="A .dict or .fai the reference must match")]
+    pub sequence_dictionary: Option<String>,
+    #[arg(long="primary-assembly-only", help="Skip alt, random, chrUn, HLA and decoy contigs")]
+    pub primary_assembly_only: bool,
+    #[arg(long="only-contig", help="Write output for this contig only, as a full run would")]
+    pub only_contig: Option<String>,
+    #[arg(long="contig-seed", help="Seed of the --only-contig contig's reads, as a full run logs")]
+    pub contig_seed: Option<String>,
+    #[arg(long="region", help="Simulate only this region (chr:start-end), keeping its coordinates")]
+    pub region: Option<String>,
     #[arg(short='l', long="read_len", default_value_t = 150)]
     pub read_length: usize,
     #[arg(short='c', long="coverage", default_value_t = 10)]
     pub coverage: usize,
     #[arg(short='m', long="minimum-mutations")]
     pub minimum_mutations: Option<u64>,
+    #[arg(long="replicates", help="Run this many replicates, each with its own derived seed, into <output_dir>/rep<n>")]
+    pub replicates: Option<usize>,
+    #[arg(long="random-seed-words", help="Seed the run with this many random words instead of the clock")]
+    pub random_seed_words: Option<usize>,
 
     // These options relate to the logging features and are not overridden by a config
-    #[arg(long="log-level", default_value_t=String::from("Trace"), help="Enter one of Trace, Debug, Info, Warn, Error, Off")]
+    #[arg(long="log-level", global=true, default_value_t=String::from("Trace"), help="Enter one of Trace, Debug, Info, Warn, Error, Off")]
     pub log_level: String,
-    #[arg(long="log-dest", default_value_t=env::current_dir().unwrap().display().to_string() + "neat_out.log", help="Full path and name to log file")]
+    #[arg(long="log-dest", global=true, default_value_t=env::current_dir().unwrap().join("neat_out.log").display().to_string(), help="Full path and name to log file")]
     pub log_dest: String,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[command(about="Run a configuration twice with the same seed and check the output matches")]
+    VerifyDeterminism {
+        #[arg(short='C', long="config", help="Configuration yaml to check")]
+        config: String,
+        #[arg(short='t', long="threads", help="Use this many threads for the second run")]
+        threads: Option<usize>,
+    },
+    #[command(about="Run built in simulations and check their output against known checksums")]
+    SelfTest {
+        #[arg(long="temp-dir", help="Directory for the test runs (default: system temp dir)")]
+        temp_dir: Option<String>,
+    },
+    #[command(about="Prune, quantize and gzip a quality score model to shrink it")]
+    CompressQualityModel {
+        #[arg(short='i', long="input", help="Model json, as written by rusty-neat")]
+        input: String,
+        #[arg(short='o', long="output", help="Where to write the compressed model (.json.gz)")]
+        output: String,
+        #[arg(long="min-fraction", default_value_t=0.0001, help="Drop weights under this fraction of their row")]
+        min_fraction: f64,
+        #[arg(long="bits", default_value_t=8, help="Bits each weight is quantized to")]
+        bits: u32,
+    },
+}
+
 // Tests are handled in other places.
\ No newline at end of file
@@ -10,8 +10,67 @@ use std::path::{Path, PathBuf};
 use serde_yaml::Value;
 use super::cli::Cli;
 use super::file_tools::check_create_dir;
+use super::errors::NeatError;
+use super::bam_tools::ReadGroup;
+use super::make_reads::DEFAULT_FRAGMENT_BOUNDS;
+use super::memory_budget::parse_memory_size;
+use super::ploidy::Sex;
+use super::variants::GenotypeModel;
+use super::index_reads::{IndexModel, SampleIndex};
+use super::ffpe::DEFAULT_ARTIFACT_FRACTION;
+use super::model_dir::read_model_bundle;
+use super::fasta_tools::parse_region;
+use super::kmer_spectrum::MAX_KMER_SIZE;
+use super::fastq_tools::{
+    check_comment_tag, check_quality_encoding, FastqFormat, FlowcellGeometry, IlluminaRun,
+    ReadNameFormat, DEFAULT_TILE_WIDTH, ILLUMINA_INSTRUMENT, SANGER_QUALITY_OFFSET,
+};
 
-#[derive(Debug)]
+// The lowest tumor fraction allowed (0.1%). Below this, the expected number of reads carrying a
+// somatic variant is too small to mean much even at ctDNA depths.
+const MIN_TUMOR_FRACTION: f64 = 0.001;
+
+// The shortest reads allowed. Shorter ones are too short to place anywhere unique, and leave no
+// room for the quality model's cycles.
+const MIN_READ_LEN: usize = 20;
+
+// The read group platforms (PL) the SAM spec allows.
+const DEFAULT_PLATFORM: &str = "ILLUMINA";
+const PLATFORMS: [&str; 12] = [
+    "CAPILLARY", "DNBSEQ", "ELEMENT", "HELICOS", "ILLUMINA", "IONTORRENT", "LS454", "ONT",
+    "PACBIO", "SINGULAR", "SOLID", "ULTIMA",
+];
+
+// The fraction of germline variants put at known sites, when there are some. Nearly all the SNVs
+// of a real genome are already in dbSNP, and the rest are the novel ones a caller has to find.
+const DEFAULT_KNOWN_SITE_FRACTION: f64 = 0.9;
+
+// Copies of the mitochondrial genome per cell. Blood and most tissues used for sequencing carry a
+// few hundred, which puts chrM at about a hundred times the depth of the autosomes.
+const DEFAULT_MT_COPY_NUMBER: f64 = 200.0;
+
+// How far apart two haplotypes of a quasispecies are, as a fraction of their bases. Intra-host
+// diversity of RNA viruses like HIV and HCV is typically a few tenths of a percent to a few
+// percent.
+const DEFAULT_QUASISPECIES_DIVERSITY: f64 = 0.01;
+
+// SNPs each new clone of a longitudinal series gains, and how far the clones' shares drift
+// between timepoints (the standard deviation of the logarithm of their fitness).
+const DEFAULT_TIMEPOINT_MUTATIONS: usize = 20;
+const DEFAULT_TIMEPOINT_DRIFT: f64 = 0.5;
+
+// Capture model defaults, roughly those of an exome kit: coverage tails off over a couple hundred
+// bases past each target, most targets are within a factor of two of the average depth, and the
+// most GC-rich and GC-poor fragments largely drop out.
+const DEFAULT_CAPTURE_SHOULDER: usize = 200;
+const DEFAULT_CAPTURE_EFFICIENCY_ST_DEV: f64 = 0.35;
+const DEFAULT_CAPTURE_GC_DROPOUT: f64 = 1.0;
+
+// Picard's default OPTICAL_DUPLICATE_PIXEL_DISTANCE, right for unpatterned flowcells. Patterned
+// ones (HiSeq X, NovaSeq) call for about 2500.
+const DEFAULT_OPTICAL_DUPLICATE_DISTANCE: usize = 100;
+
+#[derive(Debug, Clone)]
 pub struct RunConfiguration {
     // This struct holds all the parameters for this particular run. It is derived from input either
     // from a configuration file or from command line inputs. This is not built directly in the code,
@@ -20,68 +79,512 @@ pub struct RunConfiguration {
     // reference: The path to the reference for the run.
     // read_len: The length of reads in the output fastq.
     // coverage: The average depth of coverage for the output fastq file.
-    // mutation_rate: The rate of mutation for the file.
+    // mutation_rate: The rate of mutation for the file. This is the germline layer.
+    // somatic_mutation_rate: If set, a second (somatic) layer of mutations is applied at this rate
+    // on top of the germline variants, using a somatic substitution spectrum.
+    // somatic_signatures: A table of SBS signatures (96 trinucleotide channels, COSMIC style). If
+    // set, the somatic layer draws its substitutions from a mix of them instead.
+    // signature_exposures: The signatures of somatic_signatures to mix, each with its weight.
     // ploidy: The number of copies of each chromosome in the target organism. Mutation process will
     // be replicated this number of times.
+    // contig_ploidy: (contig, copies) pairs giving some contigs a copy number other than ploidy,
+    // for aneuploid samples like trisomies or tumors that have gained or lost chromosomes.
+    // snp_distance: For a clonal (haploid) isolate, exactly how many SNPs it has from the
+    // reference, spread evenly over the genome. Used instead of mutation_rate for the germline
+    // layer.
+    // plasmid_copy_number: (contig, copies) pairs, reading each plasmid contig this many times
+    // deeper than the chromosome.
+    // quasispecies_frequencies: For a viral quasispecies, the relative frequency of each
+    // haplotype, the first being the reference. Sets ploidy to the number of haplotypes.
+    // quasispecies_diversity: The mean fraction of bases two haplotypes of the quasispecies differ
+    // at. Used instead of mutation_rate for the germline layer.
+    // timepoints: For a longitudinal series, how many samples to take from the evolving
+    // population, each into its own subdirectory of output_dir (t1, t2, ...).
+    // timepoint_mutations: The SNPs each new clone of the series gains.
+    // timepoint_drift: How far the clones' shares drift between timepoints.
+    // sample_haplotypes: Fastas of the sample's haplotypes, one per copy of the genome, on the
+    // reference's coordinates. Their differences from the reference are used as the sample's
+    // variants instead of simulating any, and ploidy is set to their number.
+    // genotype_frequencies: (dosage, weight) pairs, the weight of a germline variant being on that
+    // many copies (1 for het and 2 for hom-alt in a diploid). Dosages past the copies of a site
+    // count as all of them.
+    // allele_frequency: Draw germline dosages from Hardy-Weinberg proportions for an allele at
+    // this frequency instead. Without either, germline variants are nearly all on one copy.
     // paired_ended: If the run will be in paired-ended mode.
     // fragment_mean: Mean size of the fragments in paired-ended mode.
     // fragment_st_dev: Standard deviation of the fragment mean describing the sample set to sample
     // fragments from.
+    // fragment_min, fragment_max: In paired-ended mode, the shortest and longest fragments allowed.
+    // Lengths drawn outside them are drawn again.
+    // singleton_rate: In paired-ended mode, the fraction of pairs that lose one mate, leaving the
+    // other as a singleton.
+    // discarded_pair_rate: In paired-ended mode, the fraction of pairs lost entirely.
+    // adapter_read_through: In paired-ended mode, reads from fragments shorter than the read length
+    // continue into the adapter instead of stopping at the end of the fragment.
+    // duplex_family_size: In paired-ended mode, makes every fragment a UMI-tagged molecule read this
+    // many times from each strand, each copy with its own sequencing errors.
+    // cell_free_fragments: In paired-ended mode, draw fragment lengths from the nucleosomal pattern
+    // of cell-free DNA (peaks near 167 and 334 bp and a 10 bp ladder below 167) instead of a normal
+    // distribution.
+    // ffpe_artifact_rate: In paired-ended mode, the fraction of bases that are FFPE deamination
+    // artifact sites (only C and G bases are kept). Their reads show C>T or G>A from one strand
+    // only, and the sites are written to an artifacts vcf (see ffpe.rs).
+    // ffpe_artifact_fraction: The fraction of fragments of the mean length over an artifact site
+    // that carry it, 0.05 by default. Shorter fragments carry it more often.
+    // tumor_fraction: With somatic mutations, the fraction of fragments that come from the tumor.
+    // The rest come from normal cells and carry only the germline variants.
+    // target_bed: If set, only these regions (padded a little on each side) are covered, each to
+    // the full coverage, as in a capture panel.
+    // capture_model: With target_bed, model hybrid capture instead of covering the targets as
+    // blocks: coverage shoulders past the target edges, per-target efficiency and GC dropout.
+    // capture_shoulder: How far past a target edge fragments are still captured, in bases.
+    // capture_efficiency_st_dev: The spread of the targets' capture efficiencies (the standard
+    // deviation of their logarithm). 0 makes every target the same.
+    // capture_gc_dropout: How strongly fragments far from ~45% GC drop out. 0 turns it off.
+    // low_complexity_error_fold: If set, read bases from homopolymers and tandem repeats get this
+    // many times the error rate of their quality scores (the scores are lowered to match).
+    // low_complexity_bed: The low-complexity regions to use for low_complexity_error_fold. If not
+    // set, they are found in the reference.
+    // quality_crash_rate: If set, the fraction of reads whose quality crashes partway through their
+    // second half: every base after that scores Q2 and gets errors to match.
+    // qc_fail_rate: If set, the fraction of templates that fail the sequencer's quality filter.
+    // Their reads have low quality scores throughout, with errors to match, and are marked as QC
+    // failures (flag 0x200 in the bam, Y in the filter field of Illumina read names).
+    // molecule_error_rate: If set, the fraction of each fragment's bases damaged before it is
+    // sequenced. Both reads of a pair show the damage where they overlap, unlike sequencing
+    // errors, which are drawn for each read on its own.
+    // error_profile: A table of substitution rates by cycle (see error_profile.rs). Each read base
+    // gets errors at its cycle's rate, and a quality score no higher than that rate stands for.
+    // model_dir: A directory holding a matched set of models for a platform (see model_dir.rs).
+    // Its quality and mutation models replace the built in ones, and its error and fragment
+    // models are used where error_profile and fragment_mean or fragment_st_dev aren't set.
+    // coverage_profile: A bedgraph of the depth wanted along the genome. Reads follow its shape,
+    // scaled to average the coverage.
+    // coverage_bam: A bam of a real sample. Reads follow the shape of its depth along the genome,
+    // like a coverage profile, and paired reads get its fragment lengths, so the run is a twin of
+    // the sample with the simulated variants in it.
+    // gene_annotation: A GFF3 or GTF of the reference's genes. Variants in a transcript are marked
+    // in the vcf with the gene and their effect on it (synonymous, missense, ...).
+    // exon_mutation_rate, intron_mutation_rate, intergenic_mutation_rate: Germline mutation rates
+    // for each kind of region of the gene annotation. Those not set use mutation_rate.
+    // known_sites_vcf: A vcf of known sites, such as dbSNP. Some of the germline SNVs are put at
+    // its sites, with one of their alleles, and carry their IDs into the truth vcf.
+    // known_site_fraction: With known_sites_vcf, the fraction of germline variants put at known
+    // sites.
     // produce_fastq: True or false on whether to produce an output fastq file.
-    // produce_fasta: True or false on whether to produce an output fasta file, 1 per ploid.
+    // produce_fasta: True or false on whether to produce an output fasta file, with every variant
+    // on a single sequence.
+    // haplotype_fasta: True or false on whether to produce a fasta file for each copy of the
+    // genome, with only the variants on that copy.
+    // produce_edit_list: True or false on whether to write the mutated fasta as a binary list of
+    // edits to the reference (see edit_list.rs).
     // produce_vcf: True or false on whether to produce an output VCF file, with genotyped variants.
     // produce_bam: True or false on whether to produce an output BAM file, which will be aligned to
     // the reference.
+    // kmer_spectrum: If set, the k of a k-mer spectrum of the reads, written as they are, so genome
+    // size and heterozygosity estimators can be run on it without counting the fastq.
+    // truth_pileup: True or false on whether to write the depth and base counts of the reads at
+    // each position, from where they were drawn, as a bedgraph and a table of non-reference counts.
+    // genotype_calls: True or false on whether to give every truth vcf record the DP, AD, GQ and
+    // PL of the reads written over it (see genotype_quality.rs).
+    // produce_benchmark_files: True or false on whether to produce the files hap.py and vcfeval
+    // need to benchmark against this run: the truth vcf bgzipped with a tabix index, and a bed of
+    // the confident (simulated, non-N) regions.
+    // bgzip_truth_files: Write the truth vcf and bed bgzipped, each with a tabix index, instead of
+    // as plain text.
+    // validate_truth: Check a sample of the truth vcf records against the reference and the
+    // mutated sequences once it is written, and fail the run if any disagree.
+    // liftover_chain: A chain file from the reference to another assembly. The truth vcf is also
+    // written lifted over to that assembly.
+    // rng_seed: The seed, in its canonical form (see canonical_seed). Runs without one are seeded
+    // from the clock.
+    // random_seed_words: Seed runs without one with this many words drawn from a word list,
+    // instead of the clock, so the seed is easy to note down and pass on.
     // overwrite_output: if true, will overwrite output. If false will error and exit you attempt to
     // overwrite files with the same name.
     // output_dir: The directory, relative or absolute, path to the directory to place output.
     // output_prefix: The name to use for the output files.
+    // read_name_format: Simple (neat_generated_...) or Illumina (Casava 1.8 style) read names.
+    // read_name_prefix: Start of simple read names, "neat_generated" by default.
+    // duplicate_rate: The fraction of templates that are duplicates of another fragment's template.
+    // optical_duplicate_fraction: Of the duplicates, the fraction that are optical (their cluster
+    // is beside the original's) rather than PCR duplicates. 0 by default.
+    // optical_duplicate_distance: How many pixels from the original an optical duplicate's cluster
+    // is at most, 100 by default (Picard's OPTICAL_DUPLICATE_PIXEL_DISTANCE).
+    // tile_width: The x positions in a row of a flowcell tile, for Illumina read names.
+    // sample_index: The sample's index, i7 or i7+i5. Writes its index reads to I1 (and I2) fastqs
+    // and puts it in Illumina read names.
+    // index_pool: The indexes of the other libraries in the pool, for index hopping.
+    // index_hopping_rate: The fraction of templates that read a pool library's index instead.
+    // read_comment_tags: SAM-style tags (e.g. BC:Z:ACGTAC) added to every fastq header.
+    // truth_comment_tags: Add each read's origin (contig, position, strand) to its fastq header.
+    // quality_offset: Ascii offset for fastq quality scores, 33 by default (64 for legacy data).
+    // max_quality: Highest quality score to write; higher scores are capped to this.
+    // quality_trim_threshold: If set, add to each fastq header (XT:i:) how much of the read a
+    // trimmer cutting low quality bases (below this) off its end should keep.
+    // global_shuffle: Shuffle reads across contigs, not just within each one, so the fastq isn't
+    // grouped by contig. The shuffle goes through bucket files, so memory use stays bounded.
+    // sort_reads: Write the reads of each contig in order of position instead of shuffled, and the
+    // bam sorted by coordinate.
+    // sample_name: The sample (SM) of the bam read group. Defaults to "tumor" for runs with somatic
+    // mutations and "normal" otherwise, so a tumor/normal pair made from two runs with the same
+    // seed (which share their germline variants) can be told apart by callers like Mutect2.
+    // sample_sex: The sex chromosomes follow it: two X in a female, one X and one Y in a male,
+    // with the pseudoautosomal regions diploid. Auto (the default) makes the sample male if the
+    // reference has a Y with any unmasked sequence, and female otherwise.
+    // mt_copy_number: Copies of the mitochondrial genome (chrM or MT) per cell. It is simulated
+    // as a single sequence read at mt_copy_number times the depth of one copy of an autosome.
+    // heteroplasmy_fractions: (fraction, weight) pairs. If any are given, each mitochondrial
+    // variant is on one of the fractions of the mitochondrial copies, drawn by weight, instead of
+    // on all of them.
+    // par_bed: The pseudoautosomal regions of X and Y, for male samples on references other than
+    // GRCh37 and GRCh38.
+    // library: The library (LB) of the read group. Defaults to <sample_name>_lib.
+    // platform: The platform (PL) of the read group. Defaults to ILLUMINA.
+    // platform_unit: The platform unit (PU) of the read group, if any. An Illumina-style
+    // <flowcell>.<lane>[.<barcode>] one also sets the flowcell and lane of Illumina read names.
+    // instrument: The instrument ID of Illumina read names. Defaults to NEAT.
+    // threads: The number of worker threads used to generate reads. Output is identical no matter
+    // how many are used.
+    // max_memory: A budget for the run's memory, in bytes. Fewer threads are used, and the shuffle
+    // across contigs holds fewer reads at once, to stay under it (see memory_budget.rs).
+    // throttle_mb_per_sec: A cap on the rate the run writes its files at, in MB a second, for runs
+    // on shared nodes (see throttle.rs). The output is the same either way.
+    // temp_dir: Where output is staged while it is being written. Defaults to output_dir. On
+    // clusters, point this at scratch space rather than a small tmpfs.
+    // sequence_dictionary: A .dict or .fai for the reference. If set, the reference must match it
+    // (same contigs, order and lengths) or the run stops before doing any work.
+    // primary_assembly_only: Leave out the alt, random, unplaced (chrUn), HLA and decoy contigs of
+    // the reference, simulating only the primary assembly.
+    // only_contig: Write output for this contig only, to regenerate it without the rest of the
+    // genome. Everything else is simulated as in a full run (the contig keeps its place in the
+    // reference order and its seed), so the contig's output is the same as the full run's.
+    // contig_seed: The seed of only_contig's reads, as logged by a full run. Defaults to the one
+    // derived from the run's seed.
+    // region: Simulate this region (contig:start-end, 1-based and inclusive) only, for quick runs
+    // at one locus. Only its bases are read from the reference (straight from them, if it has a
+    // samtools faidx index), and the rest of its contig is N, so outputs keep the contig's
+    // coordinates.
+    // fill_gaps: Fill the gaps of the reference (runs of at least 50 N) with synthetic telomere and
+    // satellite repeats before simulating, so they get variants and reads like the rest of it. A
+    // bed of what was filled and the filled reference are written with the other outputs.
+    // reference_cache: If set, the parsed reference is cached in this file and read back from it
+    // on later runs, as long as the fasta hasn't changed.
+    // sweep: For parameter sweeps, the configuration of every point of the sweep. Empty for a
+    // single run.
     pub reference: String,
     pub read_len: usize,
     pub coverage: usize,
     pub mutation_rate: f64,
+    pub somatic_mutation_rate: Option<f64>,
+    pub somatic_signatures: Option<String>,
+    pub signature_exposures: Vec<(String, f64)>,
     pub ploidy: usize,
+    pub contig_ploidy: Vec<(String, usize)>,
+    pub snp_distance: Option<usize>,
+    pub plasmid_copy_number: Vec<(String, f64)>,
+    pub quasispecies_frequencies: Vec<f64>,
+    pub quasispecies_diversity: f64,
+    pub timepoints: Option<usize>,
+    pub timepoint_mutations: usize,
+    pub timepoint_drift: f64,
+    pub sample_haplotypes: Vec<String>,
+    pub genotype_frequencies: Vec<(usize, f64)>,
+    pub allele_frequency: Option<f64>,
     pub paired_ended: bool,
     pub fragment_mean: Option<f64>,
     pub fragment_st_dev: Option<f64>,
+    pub fragment_min: Option<usize>,
+    pub fragment_max: Option<usize>,
+    pub singleton_rate: Option<f64>,
+    pub discarded_pair_rate: Option<f64>,
+    pub adapter_read_through: bool,
+    pub duplex_family_size: Option<usize>,
+    pub cell_free_fragments: bool,
+    pub ffpe_artifact_rate: Option<f64>,
+    pub ffpe_artifact_fraction: f64,
+    pub tumor_fraction: Option<f64>,
+    pub target_bed: Option<String>,
+    pub capture_model: bool,
+    pub capture_shoulder: usize,
+    pub capture_efficiency_st_dev: f64,
+    pub capture_gc_dropout: f64,
+    pub low_complexity_error_fold: Option<f64>,
+    pub low_complexity_bed: Option<String>,
+    pub quality_crash_rate: Option<f64>,
+    pub qc_fail_rate: Option<f64>,
+    pub molecule_error_rate: Option<f64>,
+    pub error_profile: Option<String>,
+    pub model_dir: Option<String>,
+    pub coverage_profile: Option<String>,
+    pub coverage_bam: Option<String>,
+    pub gene_annotation: Option<String>,
+    pub exon_mutation_rate: Option<f64>,
+    pub intron_mutation_rate: Option<f64>,
+    pub intergenic_mutation_rate: Option<f64>,
+    pub known_sites_vcf: Option<String>,
+    pub known_site_fraction: f64,
     pub produce_fastq: bool,
     pub produce_fasta: bool,
+    pub haplotype_fasta: bool,
+    pub produce_edit_list: bool,
     pub produce_vcf:  bool,
     pub produce_bam: bool,
+    pub kmer_spectrum: Option<usize>,
+    pub truth_pileup: bool,
+    pub genotype_calls: bool,
+    pub produce_benchmark_files: bool,
+    pub bgzip_truth_files: bool,
+    pub validate_truth: bool,
+    pub liftover_chain: Option<String>,
     pub rng_seed: Option<String>,
+    pub random_seed_words: Option<usize>,
     pub overwrite_output: bool,
     pub minimum_mutations: Option<usize>,
     pub output_dir: PathBuf,
     pub output_prefix: String,
+    pub read_name_format: ReadNameFormat,
+    pub read_name_prefix: String,
+    pub duplicate_rate: Option<f64>,
+    pub optical_duplicate_fraction: f64,
+    pub optical_duplicate_distance: usize,
+    pub tile_width: usize,
+    pub sample_index: Option<SampleIndex>,
+    pub index_pool: Vec<SampleIndex>,
+    pub index_hopping_rate: Option<f64>,
+    pub read_comment_tags: Vec<String>,
+    pub truth_comment_tags: bool,
+    pub quality_offset: u32,
+    pub max_quality: Option<u32>,
+    pub quality_trim_threshold: Option<u32>,
+    pub global_shuffle: bool,
+    pub sort_reads: bool,
+    pub sample_name: String,
+    pub sample_sex: Sex,
+    pub mt_copy_number: f64,
+    pub heteroplasmy_fractions: Vec<(f64, f64)>,
+    pub par_bed: Option<String>,
+    pub library: String,
+    pub platform: String,
+    pub platform_unit: Option<String>,
+    pub instrument: String,
+    pub threads: usize,
+    pub max_memory: Option<usize>,
+    pub throttle_mb_per_sec: Option<f64>,
+    pub temp_dir: Option<PathBuf>,
+    pub reference_cache: Option<PathBuf>,
+    pub sequence_dictionary: Option<String>,
+    pub primary_assembly_only: bool,
+    pub only_contig: Option<String>,
+    pub contig_seed: Option<String>,
+    pub region: Option<String>,
+    pub fill_gaps: bool,
+    pub sweep: Vec<SweepPoint>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepPoint {
+    // One combination of the swept settings.
+    //
+    // label: The swept settings and their values, e.g. coverage-10_mutation_rate-0.01. This is
+    //     also the name of the point's output directory.
+    // config: The configuration for this point.
+    pub label: String,
+    pub config: RunConfiguration,
 }
+
 #[allow(dead_code)]
 impl RunConfiguration {
     // The purpose of this function is to redirect you to the ConfigBuilder
     pub fn build() -> ConfigBuilder {
         ConfigBuilder::new()
     }
+
+    pub fn fastq_format(&self) -> FastqFormat {
+        // The fastq output settings for this run.
+        FastqFormat {
+            name_format: self.read_name_format,
+            comment_tags: self.read_comment_tags.clone(),
+            truth_tags: self.truth_comment_tags,
+            quality_offset: self.quality_offset,
+            max_quality: self.max_quality,
+            trim_threshold: self.quality_trim_threshold,
+            sample_index: self.sample_index.as_ref().map(SampleIndex::to_string),
+        }
+    }
+
+    pub fn index_model(&self) -> Option<IndexModel> {
+        // How index reads are made, for runs that write them.
+        Some(IndexModel {
+            sample: self.sample_index.clone()?,
+            pool: self.index_pool.clone(),
+            hopping_rate: self.index_hopping_rate.unwrap_or(0.0),
+        })
+    }
+
+    pub fn flowcell_geometry(&self) -> FlowcellGeometry {
+        // Where clusters go in Illumina read names. Clusters are only spaced out to leave room
+        // for optical duplicates in runs that simulate duplicates.
+        FlowcellGeometry {
+            tile_width: self.tile_width,
+            optical_distance: self.duplicate_rate.map(|_| self.optical_duplicate_distance),
+        }
+    }
+
+    pub fn fragment_bounds(&self) -> (usize, usize) {
+        // The shortest and longest fragments allowed, at least a base and unbounded if not set.
+        (
+            self.fragment_min.unwrap_or(DEFAULT_FRAGMENT_BOUNDS.0),
+            self.fragment_max.unwrap_or(DEFAULT_FRAGMENT_BOUNDS.1),
+        )
+    }
+
+    pub fn has_region_rates(&self) -> bool {
+        // Whether the germline rate differs between the regions of the gene annotation.
+        self.exon_mutation_rate.is_some()
+            || self.intron_mutation_rate.is_some()
+            || self.intergenic_mutation_rate.is_some()
+    }
+
+    pub fn genotype_model(&self) -> Option<GenotypeModel> {
+        // How many copies germline variants go on, if the run sets it.
+        match self.allele_frequency {
+            Some(frequency) => Some(GenotypeModel::HardyWeinberg(frequency)),
+            None if !self.genotype_frequencies.is_empty() => {
+                Some(GenotypeModel::Dosages(self.genotype_frequencies.clone()))
+            },
+            None => None,
+        }
+    }
+
+    pub fn read_group(&self) -> ReadGroup {
+        // The bam read group for this run. Its ID is the sample name, so the groups stay distinct
+        // when bams from different samples are merged.
+        ReadGroup {
+            id: self.sample_name.clone(),
+            sample: self.sample_name.clone(),
+            library: self.library.clone(),
+            platform: self.platform.clone(),
+            platform_unit: self.platform_unit.clone(),
+        }
+    }
+
+    pub fn illumina_run(&self) -> IlluminaRun {
+        // The instrument, flowcell and lane of Illumina read names, matching the read group.
+        IlluminaRun::new(&self.instrument, self.platform_unit.as_deref())
+    }
 }
 
 // The config builder allows us to construct a config in multiple different ways, depending
 // on the input.
 pub struct ConfigBuilder {
     pub(crate) reference: Option<String>,
-    read_len: usize,
-    coverage: usize,
-    mutation_rate: f64,
-    ploidy: usize,
-    paired_ended: bool,
-    fragment_mean: Option<f64>,
-    fragment_st_dev: Option<f64>,
-    produce_fastq: bool,
+    pub(crate) read_len: usize,
+    pub(crate) coverage: usize,
+    pub(crate) mutation_rate: f64,
+    pub(crate) somatic_mutation_rate: Option<f64>,
+    pub(crate) somatic_signatures: Option<String>,
+    pub(crate) signature_exposures: Vec<(String, f64)>,
+    pub(crate) ploidy: usize,
+    pub(crate) contig_ploidy: Vec<(String, usize)>,
+    pub(crate) snp_distance: Option<usize>,
+    pub(crate) plasmid_copy_number: Vec<(String, f64)>,
+    pub(crate) quasispecies_frequencies: Vec<f64>,
+    pub(crate) quasispecies_diversity: f64,
+    pub(crate) timepoints: Option<usize>,
+    pub(crate) timepoint_mutations: usize,
+    pub(crate) timepoint_drift: f64,
+    pub(crate) sample_haplotypes: Vec<String>,
+    pub(crate) genotype_frequencies: Vec<(usize, f64)>,
+    pub(crate) allele_frequency: Option<f64>,
+    pub(crate) paired_ended: bool,
+    pub(crate) fragment_mean: Option<f64>,
+    pub(crate) fragment_st_dev: Option<f64>,
+    pub(crate) fragment_min: Option<usize>,
+    pub(crate) fragment_max: Option<usize>,
+    pub(crate) singleton_rate: Option<f64>,
+    pub(crate) discarded_pair_rate: Option<f64>,
+    pub(crate) adapter_read_through: bool,
+    pub(crate) duplex_family_size: Option<usize>,
+    pub(crate) cell_free_fragments: bool,
+    pub(crate) ffpe_artifact_rate: Option<f64>,
+    pub(crate) ffpe_artifact_fraction: f64,
+    pub(crate) tumor_fraction: Option<f64>,
+    pub(crate) target_bed: Option<String>,
+    pub(crate) capture_model: bool,
+    pub(crate) capture_shoulder: usize,
+    pub(crate) capture_efficiency_st_dev: f64,
+    pub(crate) capture_gc_dropout: f64,
+    pub(crate) low_complexity_error_fold: Option<f64>,
+    pub(crate) low_complexity_bed: Option<String>,
+    pub(crate) quality_crash_rate: Option<f64>,
+    pub(crate) qc_fail_rate: Option<f64>,
+    pub(crate) molecule_error_rate: Option<f64>,
+    pub(crate) error_profile: Option<String>,
+    pub(crate) model_dir: Option<String>,
+    pub(crate) coverage_profile: Option<String>,
+    pub(crate) coverage_bam: Option<String>,
+    pub(crate) gene_annotation: Option<String>,
+    pub(crate) exon_mutation_rate: Option<f64>,
+    pub(crate) intron_mutation_rate: Option<f64>,
+    pub(crate) intergenic_mutation_rate: Option<f64>,
+    pub(crate) known_sites_vcf: Option<String>,
+    pub(crate) known_site_fraction: f64,
+    pub(crate) produce_fastq: bool,
     pub(crate) produce_fasta: bool,
+    pub(crate) haplotype_fasta: bool,
+    pub(crate) produce_edit_list: bool,
     pub(crate) produce_vcf:  bool,
-    produce_bam: bool,
-    rng_seed: Option<String>,
-    overwrite_output: bool,
+    pub(crate) produce_bam: bool,
+    pub(crate) kmer_spectrum: Option<usize>,
+    pub(crate) truth_pileup: bool,
+    pub(crate) genotype_calls: bool,
+    pub(crate) produce_benchmark_files: bool,
+    pub(crate) bgzip_truth_files: bool,
+    pub(crate) validate_truth: bool,
+    pub(crate) liftover_chain: Option<String>,
+    pub(crate) rng_seed: Option<String>,
+    pub(crate) random_seed_words: Option<usize>,
+    pub(crate) overwrite_output: bool,
     pub(crate) minimum_mutations: Option<usize>,
     pub(crate) output_dir: PathBuf,
-    output_prefix: String,
+    pub(crate) output_prefix: String,
+    pub(crate) read_name_format: ReadNameFormat,
+    pub(crate) read_name_prefix: String,
+    pub(crate) duplicate_rate: Option<f64>,
+    pub(crate) optical_duplicate_fraction: f64,
+    pub(crate) optical_duplicate_distance: usize,
+    pub(crate) tile_width: usize,
+    pub(crate) sample_index: Option<SampleIndex>,
+    pub(crate) index_pool: Vec<SampleIndex>,
+    pub(crate) index_hopping_rate: Option<f64>,
+    pub(crate) read_comment_tags: Vec<String>,
+    pub(crate) truth_comment_tags: bool,
+    pub(crate) quality_offset: u32,
+    pub(crate) max_quality: Option<u32>,
+    pub(crate) quality_trim_threshold: Option<u32>,
+    pub(crate) global_shuffle: bool,
+    pub(crate) sort_reads: bool,
+    pub(crate) sample_name: Option<String>,
+    pub(crate) sample_sex: Sex,
+    pub(crate) mt_copy_number: f64,
+    pub(crate) heteroplasmy_fractions: Vec<(f64, f64)>,
+    pub(crate) par_bed: Option<String>,
+    pub(crate) library: Option<String>,
+    pub(crate) platform: String,
+    pub(crate) platform_unit: Option<String>,
+    pub(crate) instrument: String,
+    pub(crate) threads: usize,
+    pub(crate) max_memory: Option<usize>,
+    pub(crate) throttle_mb_per_sec: Option<f64>,
+    pub(crate) temp_dir: Option<PathBuf>,
+    pub(crate) reference_cache: Option<PathBuf>,
+    pub(crate) sequence_dictionary: Option<String>,
+    pub(crate) primary_assembly_only: bool,
+    pub(crate) only_contig: Option<String>,
+    pub(crate) contig_seed: Option<String>,
+    pub(crate) region: Option<String>,
+    pub(crate) fill_gaps: bool,
 }
 
 impl ConfigBuilder {
@@ -92,37 +595,769 @@ impl ConfigBuilder {
             read_len: 150,
             coverage: 10,
             mutation_rate: 0.001,
+            somatic_mutation_rate: None,
+            somatic_signatures: None,
+            signature_exposures: Vec::new(),
             ploidy: 2,
+            contig_ploidy: Vec::new(),
+            snp_distance: None,
+            plasmid_copy_number: Vec::new(),
+            quasispecies_frequencies: Vec::new(),
+            quasispecies_diversity: DEFAULT_QUASISPECIES_DIVERSITY,
+            timepoints: None,
+            timepoint_mutations: DEFAULT_TIMEPOINT_MUTATIONS,
+            timepoint_drift: DEFAULT_TIMEPOINT_DRIFT,
+            sample_haplotypes: Vec::new(),
+            genotype_frequencies: Vec::new(),
+            allele_frequency: None,
             paired_ended: false,
             fragment_mean: None,
             fragment_st_dev: None,
+            fragment_min: None,
+            fragment_max: None,
+            singleton_rate: None,
+            discarded_pair_rate: None,
+            adapter_read_through: false,
+            duplex_family_size: None,
+            cell_free_fragments: false,
+            ffpe_artifact_rate: None,
+            ffpe_artifact_fraction: DEFAULT_ARTIFACT_FRACTION,
+            tumor_fraction: None,
+            target_bed: None,
+            capture_model: false,
+            capture_shoulder: DEFAULT_CAPTURE_SHOULDER,
+            capture_efficiency_st_dev: DEFAULT_CAPTURE_EFFICIENCY_ST_DEV,
+            capture_gc_dropout: DEFAULT_CAPTURE_GC_DROPOUT,
+            low_complexity_error_fold: None,
+            low_complexity_bed: None,
+            quality_crash_rate: None,
+            qc_fail_rate: None,
+            molecule_error_rate: None,
+            error_profile: None,
+            model_dir: None,
+            coverage_profile: None,
+            coverage_bam: None,
+            gene_annotation: None,
+            exon_mutation_rate: None,
+            intron_mutation_rate: None,
+            intergenic_mutation_rate: None,
+            known_sites_vcf: None,
+            known_site_fraction: DEFAULT_KNOWN_SITE_FRACTION,
             produce_fastq: true,
             produce_fasta: false,
+            haplotype_fasta: false,
+            produce_edit_list: false,
             produce_vcf: false,
             produce_bam: false,
+            kmer_spectrum: None,
+            truth_pileup: false,
+            genotype_calls: false,
+            produce_benchmark_files: false,
+            bgzip_truth_files: false,
+            validate_truth: false,
+            liftover_chain: None,
             rng_seed: None,
+            random_seed_words: None,
             overwrite_output: false,
             minimum_mutations: None,
             output_dir: env::current_dir().unwrap(),
             output_prefix: String::from("neat_out"),
+            read_name_format: ReadNameFormat::Simple,
+            read_name_prefix: String::from("neat_generated"),
+            duplicate_rate: None,
+            optical_duplicate_fraction: 0.0,
+            optical_duplicate_distance: DEFAULT_OPTICAL_DUPLICATE_DISTANCE,
+            tile_width: DEFAULT_TILE_WIDTH,
+            sample_index: None,
+            index_pool: Vec::new(),
+            index_hopping_rate: None,
+            read_comment_tags: Vec::new(),
+            truth_comment_tags: false,
+            quality_offset: SANGER_QUALITY_OFFSET,
+            max_quality: None,
+            quality_trim_threshold: None,
+            global_shuffle: false,
+            sort_reads: false,
+            sample_name: None,
+            sample_sex: Sex::Auto,
+            mt_copy_number: DEFAULT_MT_COPY_NUMBER,
+            heteroplasmy_fractions: Vec::new(),
+            par_bed: None,
+            library: None,
+            platform: DEFAULT_PLATFORM.to_string(),
+            platform_unit: None,
+            instrument: ILLUMINA_INSTRUMENT.to_string(),
+            threads: 1,
+            max_memory: None,
+            throttle_mb_per_sec: None,
+            temp_dir: None,
+            reference_cache: None,
+            sequence_dictionary: None,
+            primary_assembly_only: false,
+            only_contig: None,
+            contig_seed: None,
+            region: None,
+            fill_gaps: false,
         }
     }
 
-    pub fn check_and_print_config(&self) {
+    pub fn check_and_print_config(&self) -> Result<(), NeatError> {
         // This does a final check of the configuration for valid items. It will print info
         // message of the items, to work as a record and to assist in debugging any issues that
         // come up.
+        self.check_reads()?;
+        self.check_mutation_rates()?;
+        self.check_targets_and_errors()?;
+        let (fragment_mean, fragment_st_dev) = self.check_models_and_coverage()?;
+        self.check_variant_sites()?;
+        self.check_haplotypes()?;
+        self.check_genotypes()?;
+        self.check_library()?;
+        self.check_run_settings()?;
+        let file_prefix = self.output_dir.join(&self.output_prefix).display().to_string();
+
+        // No point in running if we aren't producing files
+        let produces_files = self.produce_fastq
+            | self.produce_fasta
+            | self.haplotype_fasta
+            | self.produce_edit_list
+            | self.produce_vcf
+            | self.produce_bam
+            | self.produce_benchmark_files;
+        if !produces_files {
+            return Err(NeatError::Config(
+                "All file types set to false, no files would be produced.".to_string()
+            ))
+        }
+
+        self.check_paired_reads(fragment_mean, fragment_st_dev, &file_prefix)?;
+        self.check_outputs(&file_prefix)?;
+        self.check_seeds()
+    }
+
+    fn check_reads(&self) -> Result<(), NeatError> {
+        // The reference, read length and coverage.
         if self.reference.is_none() {
-            panic!("No reference was specified.")
+            return Err(NeatError::Config("No reference was specified.".to_string()))
         }
         info!(
             "Running rusty-neat to generate reads on {} with...", self.reference.clone().unwrap()
         );
+        // Values out of range would go straight into the simulation's math, and either panic
+        // partway through the run or quietly simulate nonsense.
+        if self.read_len < MIN_READ_LEN {
+            return Err(NeatError::Config(format!(
+                "read_len must be at least {}, got {}", MIN_READ_LEN, self.read_len
+            )))
+        }
         info!("  >read length: {}", self.read_len);
+        if self.coverage == 0 {
+            return Err(NeatError::Config("coverage must be at least 1.".to_string()))
+        }
         info!("  >coverage: {}", self.coverage);
+        Ok(())
+    }
+
+    fn check_mutation_rates(&self) -> Result<(), NeatError> {
+        // The germline and somatic mutation rates, and the tumor sample's signatures.
+        if !(0.0..=1.0).contains(&self.mutation_rate) {
+            return Err(NeatError::Config(format!(
+                "mutation_rate must be between 0 and 1, got {}", self.mutation_rate
+            )))
+        }
         info!("  >mutation rate: {}", self.mutation_rate);
+        if let Some(somatic_rate) = self.somatic_mutation_rate {
+            if !(0.0..=1.0).contains(&somatic_rate) {
+                return Err(NeatError::Config(format!(
+                    "somatic_mutation_rate must be between 0 and 1, got {}", somatic_rate
+                )))
+            }
+            info!("  >somatic mutation rate: {}", somatic_rate);
+        }
+        if let Some(somatic_signatures) = &self.somatic_signatures {
+            if self.somatic_mutation_rate.is_none() {
+                return Err(NeatError::Config(
+                    "somatic_signatures needs a somatic_mutation_rate.".to_string()
+                ))
+            }
+            if !Path::new(somatic_signatures).is_file() {
+                return Err(NeatError::Config(
+                    format!("Somatic signatures not found: {}", somatic_signatures)
+                ))
+            }
+            if self.signature_exposures.is_empty() {
+                return Err(NeatError::Config(
+                    "somatic_signatures needs signature_exposures.".to_string()
+                ))
+            }
+            info!("  >somatic signatures: {}", somatic_signatures);
+        } else if !self.signature_exposures.is_empty() {
+            return Err(NeatError::Config(
+                "signature_exposures needs somatic_signatures.".to_string()
+            ))
+        }
+        for (name, exposure) in &self.signature_exposures {
+            if !exposure.is_finite() || *exposure < 0.0 {
+                return Err(NeatError::Config(format!(
+                    "signature exposures can't be negative, got {} for {}", exposure, name
+                )))
+            }
+            info!("  >exposure to {}: {}", name, exposure);
+        }
+        if let Some(tumor_fraction) = self.tumor_fraction {
+            if self.somatic_mutation_rate.is_none() {
+                return Err(NeatError::Config(
+                    "tumor_fraction needs a somatic_mutation_rate.".to_string()
+                ))
+            }
+            if !(MIN_TUMOR_FRACTION..=1.0).contains(&tumor_fraction) {
+                return Err(NeatError::Config(format!(
+                    "tumor_fraction must be between {} and 1, got {}",
+                    MIN_TUMOR_FRACTION, tumor_fraction
+                )))
+            }
+            info!("  >tumor fraction: {}", tumor_fraction);
+        }
+        Ok(())
+    }
+
+    fn check_targets_and_errors(&self) -> Result<(), NeatError> {
+        // Target regions, and the settings that raise the sequencing error rate.
+        if let Some(target_bed) = &self.target_bed {
+            if !Path::new(target_bed).is_file() {
+                return Err(NeatError::Config(format!("Target bed not found: {}", target_bed)))
+            }
+            info!("  >targets: {}", target_bed);
+        }
+        if self.capture_model {
+            if self.target_bed.is_none() {
+                return Err(NeatError::Config("capture_model needs a target_bed.".to_string()))
+            }
+            if self.capture_efficiency_st_dev < 0.0 || self.capture_gc_dropout < 0.0 {
+                return Err(NeatError::Config(
+                    "capture_efficiency_st_dev and capture_gc_dropout can't be negative."
+                        .to_string()
+                ))
+            }
+            info!(
+                "  >capture model: {} bp shoulders, efficiency spread {}, GC dropout {}",
+                self.capture_shoulder, self.capture_efficiency_st_dev, self.capture_gc_dropout
+            );
+        }
+        if let Some(error_fold) = self.low_complexity_error_fold {
+            if error_fold < 1.0 {
+                return Err(NeatError::Config(format!(
+                    "low_complexity_error_fold must be at least 1, got {}", error_fold
+                )))
+            }
+            info!("  >error rate in low-complexity regions raised {} fold", error_fold);
+        }
+        if let Some(low_complexity_bed) = &self.low_complexity_bed {
+            if self.low_complexity_error_fold.is_none() {
+                return Err(NeatError::Config(
+                    "low_complexity_bed needs a low_complexity_error_fold.".to_string()
+                ))
+            }
+            if !Path::new(low_complexity_bed).is_file() {
+                return Err(NeatError::Config(
+                    format!("Low-complexity bed not found: {}", low_complexity_bed)
+                ))
+            }
+            info!("  >low-complexity regions: {}", low_complexity_bed);
+        }
+        if let Some(crash_rate) = self.quality_crash_rate {
+            if !(0.0..=1.0).contains(&crash_rate) {
+                return Err(NeatError::Config(format!(
+                    "quality_crash_rate must be between 0 and 1, got {}", crash_rate
+                )))
+            }
+            info!("  >quality crashes in {} of reads", crash_rate);
+        }
+        if let Some(qc_fail_rate) = self.qc_fail_rate {
+            if !(0.0..=1.0).contains(&qc_fail_rate) {
+                return Err(NeatError::Config(format!(
+                    "qc_fail_rate must be between 0 and 1, got {}", qc_fail_rate
+                )))
+            }
+            info!("  >{} of reads fail QC", qc_fail_rate);
+        }
+        if let Some(error_rate) = self.molecule_error_rate {
+            if !(0.0..=1.0).contains(&error_rate) {
+                return Err(NeatError::Config(format!(
+                    "molecule_error_rate must be between 0 and 1, got {}", error_rate
+                )))
+            }
+            // A duplex molecule's strands are read separately, and damage is on only one of them,
+            // which this doesn't model.
+            if self.duplex_family_size.is_some() {
+                return Err(NeatError::Config(
+                    "molecule_error_rate can't be used with duplex_family_size.".to_string()
+                ))
+            }
+            info!("  >molecule errors at {} of fragment bases", error_rate);
+        }
+        if let Some(error_profile) = &self.error_profile {
+            if !Path::new(error_profile).is_file() {
+                return Err(NeatError::Config(
+                    format!("Error profile not found: {}", error_profile)
+                ))
+            }
+            info!("  >error profile: {}", error_profile);
+        }
+        Ok(())
+    }
+
+    fn check_models_and_coverage(&self) -> Result<(Option<f64>, Option<f64>), NeatError> {
+        // The model bundle and coverage sources.
+        // Returns: The fragment mean and standard deviation, from the config or the bundle.
+        // The bundle is read here to check it, and again at the start of each run, so it can be
+        // changed between runs.
+        let bundle = match &self.model_dir {
+            Some(model_dir) => {
+                let bundle = read_model_bundle(model_dir)?;
+                bundle.log_models(model_dir);
+                Some(bundle)
+            },
+            None => None,
+        };
+        let bundle_fragment = bundle.and_then(|bundle| bundle.fragment);
+        let fragment_mean = self.fragment_mean.or(bundle_fragment.map(|fragment| fragment.mean));
+        let fragment_st_dev =
+            self.fragment_st_dev.or(bundle_fragment.map(|fragment| fragment.st_dev));
+        let fragment_shape = [
+            ("fragment_mean", fragment_mean),
+            ("fragment_st_dev", fragment_st_dev),
+        ];
+        for (name, value) in fragment_shape {
+            if let Some(value) = value.filter(|value| value.is_nan() || *value <= 0.0) {
+                return Err(NeatError::Config(format!(
+                    "{} must be more than 0, got {}", name, value
+                )))
+            }
+        }
+        if let Some(coverage_profile) = &self.coverage_profile {
+            let lowercase = coverage_profile.to_lowercase();
+            if lowercase.ends_with(".bw") || lowercase.ends_with(".bigwig") {
+                return Err(NeatError::Config(format!(
+                    "BigWig coverage profiles aren't supported, convert {} to a bedgraph first \
+                    (e.g. with bigWigToBedGraph)", coverage_profile
+                )))
+            }
+            if !Path::new(coverage_profile).is_file() {
+                return Err(NeatError::Config(
+                    format!("Coverage profile not found: {}", coverage_profile)
+                ))
+            }
+            info!("  >coverage profile: {}", coverage_profile);
+        }
+        // The bam is only read when the run starts, as it can be large.
+        if let Some(coverage_bam) = &self.coverage_bam {
+            if !Path::new(coverage_bam).is_file() {
+                return Err(NeatError::Config(format!("Coverage bam not found: {}", coverage_bam)))
+            }
+            if self.coverage_profile.is_some() {
+                return Err(NeatError::Config(
+                    "coverage_bam and coverage_profile can't be used together.".to_string()
+                ))
+            }
+            if self.cell_free_fragments {
+                return Err(NeatError::Config(
+                    "coverage_bam can't be used with cell_free_fragments.".to_string()
+                ))
+            }
+            info!("  >coverage and fragment lengths from: {}", coverage_bam);
+        }
+        Ok((fragment_mean, fragment_st_dev))
+    }
+
+    fn check_variant_sites(&self) -> Result<(), NeatError> {
+        // Where the variants go: region rates, known sites, copies of each contig and plasmid.
+        if let Some(gene_annotation) = &self.gene_annotation {
+            if !Path::new(gene_annotation).is_file() {
+                return Err(NeatError::Config(
+                    format!("Gene annotation not found: {}", gene_annotation)
+                ))
+            }
+            info!("  >gene annotation: {}", gene_annotation);
+        }
+        let region_rates = [
+            ("exon_mutation_rate", self.exon_mutation_rate),
+            ("intron_mutation_rate", self.intron_mutation_rate),
+            ("intergenic_mutation_rate", self.intergenic_mutation_rate),
+        ];
+        for (name, rate) in region_rates {
+            if let Some(rate) = rate {
+                if self.gene_annotation.is_none() {
+                    return Err(NeatError::Config(format!("{} needs a gene_annotation.", name)))
+                }
+                if !(0.0..=1.0).contains(&rate) {
+                    return Err(NeatError::Config(format!(
+                        "{} must be between 0 and 1, got {}", name, rate
+                    )))
+                }
+                info!("  >{}: {}", name, rate);
+            }
+        }
+        if let Some(known_sites_vcf) = &self.known_sites_vcf {
+            if !Path::new(known_sites_vcf).is_file() {
+                return Err(NeatError::Config(
+                    format!("Known sites vcf not found: {}", known_sites_vcf)
+                ))
+            }
+            if !(0.0..=1.0).contains(&self.known_site_fraction) {
+                return Err(NeatError::Config(format!(
+                    "known_site_fraction must be between 0 and 1, got {}", self.known_site_fraction
+                )))
+            }
+            info!("  >known sites: {}", known_sites_vcf);
+            info!("  >fraction of germline variants at known sites: {}", self.known_site_fraction);
+        }
+        if self.ploidy == 0 {
+            return Err(NeatError::Config("ploidy must be at least 1.".to_string()))
+        }
         info!("  >ploidy: {}", self.ploidy);
+        for (contig, copies) in &self.contig_ploidy {
+            info!("  >copies of {}: {}", contig, copies);
+        }
+        if let Some(snp_distance) = self.snp_distance {
+            // The distance is between one clone and the reference, so a copy each.
+            if self.ploidy != 1 {
+                return Err(NeatError::Config(format!(
+                    "snp_distance is for haploid clonal isolates, so needs ploidy 1, got {}",
+                    self.ploidy
+                )))
+            }
+            if self.known_sites_vcf.is_some() || self.exon_mutation_rate.is_some()
+                || self.intron_mutation_rate.is_some() || self.intergenic_mutation_rate.is_some()
+            {
+                return Err(NeatError::Config(
+                    "snp_distance can't be used with known_sites_vcf or region mutation rates."
+                        .to_string()
+                ))
+            }
+            info!("  >SNPs from the reference: {} (instead of the mutation rate)", snp_distance);
+        }
+        for (contig, copy_number) in &self.plasmid_copy_number {
+            if !copy_number.is_finite() || *copy_number <= 0.0 {
+                return Err(NeatError::Config(format!(
+                    "plasmid_copy_number of {} must be positive, got {}", contig, copy_number
+                )))
+            }
+            info!("  >plasmid copy number of {}: {}", contig, copy_number);
+        }
+        Ok(())
+    }
+
+    fn check_haplotypes(&self) -> Result<(), NeatError> {
+        // Haplotypes that replace the simulated copies: quasispecies, timepoints or the
+        // sample's own.
+        if !self.quasispecies_frequencies.is_empty() {
+            if self.quasispecies_frequencies.len() < 2 {
+                return Err(NeatError::Config(
+                    "quasispecies_frequencies needs at least two haplotypes.".to_string()
+                ))
+            }
+            if let Some(frequency) = self.quasispecies_frequencies
+                .iter()
+                .find(|frequency| !frequency.is_finite() || **frequency <= 0.0)
+            {
+                return Err(NeatError::Config(format!(
+                    "quasispecies_frequencies must be positive, got {}", frequency
+                )))
+            }
+            if !(self.quasispecies_diversity > 0.0 && self.quasispecies_diversity < 1.0) {
+                return Err(NeatError::Config(format!(
+                    "quasispecies_diversity must be between 0 and 1, got {}",
+                    self.quasispecies_diversity
+                )))
+            }
+            // The haplotypes take the place of the copies, and their SNPs of the mutation rate.
+            let conflicting = !self.contig_ploidy.is_empty() || self.snp_distance.is_some()
+                || self.somatic_mutation_rate.is_some() || !self.genotype_frequencies.is_empty()
+                || self.allele_frequency.is_some() || self.known_sites_vcf.is_some()
+                || self.exon_mutation_rate.is_some() || self.intron_mutation_rate.is_some()
+                || self.intergenic_mutation_rate.is_some();
+            if conflicting {
+                return Err(NeatError::Config(
+                    "quasispecies_frequencies can't be used with contig_ploidy, snp_distance, \
+                    somatic_mutation_rate, genotype_frequencies, allele_frequency, \
+                    known_sites_vcf or region mutation rates.".to_string()
+                ))
+            }
+            info!(
+                "  >quasispecies haplotype frequencies: {:?} (one copy each)",
+                self.quasispecies_frequencies
+            );
+            info!("  >quasispecies diversity: {}", self.quasispecies_diversity);
+        }
+        if let Some(timepoints) = self.timepoints {
+            if timepoints < 2 {
+                return Err(NeatError::Config(format!(
+                    "A longitudinal series needs at least two timepoints, got {}", timepoints
+                )))
+            }
+            if self.timepoint_mutations == 0 {
+                return Err(NeatError::Config(
+                    "timepoint_mutations must be at least 1.".to_string()
+                ))
+            }
+            if !self.timepoint_drift.is_finite() || self.timepoint_drift < 0.0 {
+                return Err(NeatError::Config(format!(
+                    "timepoint_drift can't be negative, got {}", self.timepoint_drift
+                )))
+            }
+            // The clones of the series are the haplotypes of a quasispecies, with the same limits.
+            let conflicting = !self.quasispecies_frequencies.is_empty()
+                || !self.contig_ploidy.is_empty() || self.snp_distance.is_some()
+                || self.somatic_mutation_rate.is_some() || !self.genotype_frequencies.is_empty()
+                || self.allele_frequency.is_some() || self.known_sites_vcf.is_some()
+                || self.exon_mutation_rate.is_some() || self.intron_mutation_rate.is_some()
+                || self.intergenic_mutation_rate.is_some();
+            if conflicting {
+                return Err(NeatError::Config(
+                    "timepoints can't be used with quasispecies_frequencies, contig_ploidy, \
+                    snp_distance, somatic_mutation_rate, genotype_frequencies, \
+                    allele_frequency, known_sites_vcf or region mutation rates.".to_string()
+                ))
+            }
+            info!(
+                "  >longitudinal series: {} timepoints, {} SNPs per new clone, drift {}",
+                timepoints, self.timepoint_mutations, self.timepoint_drift
+            );
+        }
+        if !self.sample_haplotypes.is_empty() {
+            let missing = self.sample_haplotypes.iter().find(|path| !Path::new(path).is_file());
+            if let Some(missing) = missing {
+                return Err(NeatError::Config(format!("Sample haplotype not found: {}", missing)))
+            }
+            // The haplotypes are the sample, so nothing that simulates its variants applies.
+            let conflicting = !self.quasispecies_frequencies.is_empty()
+                || self.timepoints.is_some() || !self.contig_ploidy.is_empty()
+                || self.snp_distance.is_some() || self.somatic_mutation_rate.is_some()
+                || !self.genotype_frequencies.is_empty() || self.allele_frequency.is_some()
+                || self.known_sites_vcf.is_some() || !self.heteroplasmy_fractions.is_empty()
+                || self.exon_mutation_rate.is_some() || self.intron_mutation_rate.is_some()
+                || self.intergenic_mutation_rate.is_some() || self.fill_gaps;
+            if conflicting {
+                return Err(NeatError::Config(
+                    "sample_haplotypes can't be used with options that simulate variants \
+                    (quasispecies_frequencies, timepoints, contig_ploidy, snp_distance, \
+                    somatic_mutation_rate, genotype_frequencies, allele_frequency, \
+                    known_sites_vcf, heteroplasmy_fractions, region mutation rates) or with \
+                    fill_gaps.".to_string()
+                ))
+            }
+            for path in &self.sample_haplotypes {
+                info!("  >sample haplotype: {} (instead of simulated variants)", path);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_genotypes(&self) -> Result<(), NeatError> {
+        // Which copies the variants land on, and the sex chromosomes and mitochondria.
+        if !self.genotype_frequencies.is_empty() && self.allele_frequency.is_some() {
+            return Err(NeatError::Config(
+                "Set genotype_frequencies or allele_frequency, not both.".to_string()
+            ))
+        }
+        for (dosage, weight) in &self.genotype_frequencies {
+            let valid = *dosage > 0 && weight.is_finite() && *weight >= 0.0;
+            if !valid {
+                return Err(NeatError::Config(format!(
+                    "genotype frequencies need dosages of at least 1 copy with non-negative \
+                    weights, got {} with weight {}", dosage, weight
+                )))
+            }
+            info!("  >weight of variants on {} copies: {}", dosage, weight);
+        }
+        if !self.genotype_frequencies.is_empty()
+            && self.genotype_frequencies.iter().all(|(_, weight)| *weight == 0.0) {
+            return Err(NeatError::Config(
+                "genotype_frequencies needs a dosage with some weight.".to_string()
+            ))
+        }
+        if let Some(frequency) = self.allele_frequency {
+            if frequency.is_nan() || frequency <= 0.0 || frequency > 1.0 {
+                return Err(NeatError::Config(format!(
+                    "allele_frequency must be in (0, 1], got {}", frequency
+                )))
+            }
+            info!("  >allele frequency: {}", frequency);
+        }
+        info!("  >sample sex: {:?}", self.sample_sex);
+        if self.mt_copy_number.is_nan() || self.mt_copy_number <= 0.0 {
+            return Err(NeatError::Config(format!(
+                "mt_copy_number must be positive, got {}", self.mt_copy_number
+            )))
+        }
+        info!("  >mitochondrial copy number: {}", self.mt_copy_number);
+        for (fraction, weight) in &self.heteroplasmy_fractions {
+            let valid = *fraction > 0.0 && *fraction <= 1.0 && weight.is_finite() && *weight >= 0.0;
+            if !valid {
+                return Err(NeatError::Config(format!(
+                    "heteroplasmy fractions must be in (0, 1] with non-negative weights, got \
+                    {} with weight {}", fraction, weight
+                )))
+            }
+            info!("  >heteroplasmic fraction {}: weight {}", fraction, weight);
+        }
+        if !self.heteroplasmy_fractions.is_empty()
+            && self.heteroplasmy_fractions.iter().all(|(_, weight)| *weight == 0.0) {
+            return Err(NeatError::Config(
+                "heteroplasmy_fractions needs a fraction with some weight.".to_string()
+            ))
+        }
+        if let Some(par_bed) = &self.par_bed {
+            if self.sample_sex == Sex::Female {
+                return Err(NeatError::Config("par_bed is only used for male samples.".to_string()))
+            }
+            if !Path::new(par_bed).is_file() {
+                return Err(NeatError::Config(format!("PAR bed not found: {}", par_bed)))
+            }
+            info!("  >pseudoautosomal regions: {}", par_bed);
+        }
+        Ok(())
+    }
+
+    fn check_library(&self) -> Result<(), NeatError> {
+        // Read names, duplicates, indexes and the fastq encoding and read group.
         info!("  >paired ended: {}", self.paired_ended);
+        if self.read_name_prefix.is_empty() || self.read_name_prefix.contains(char::is_whitespace) {
+            return Err(NeatError::Config(format!(
+                "read_name_prefix must be non-empty with no spaces: {:?}", self.read_name_prefix
+            )))
+        }
+        if let Some(rate) = self.duplicate_rate {
+            if !(0.0..1.0).contains(&rate) {
+                return Err(NeatError::Config(
+                    "duplicate_rate must be at least 0 and below 1.".to_string()
+                ))
+            }
+            if self.duplex_family_size.is_some() {
+                return Err(NeatError::Config(
+                    "duplicate_rate can't be used with duplex_family_size.".to_string()
+                ))
+            }
+            if !(0.0..=1.0).contains(&self.optical_duplicate_fraction) {
+                return Err(NeatError::Config(
+                    "optical_duplicate_fraction must be between 0 and 1.".to_string()
+                ))
+            }
+            let spacing = 2 * self.optical_duplicate_distance + 1;
+            if self.optical_duplicate_distance == 0 || self.tile_width < spacing {
+                return Err(NeatError::Config(format!(
+                    "optical_duplicate_distance must be at least 1, and tile_width at least twice \
+                    it plus one ({}).", spacing
+                )))
+            }
+            info!(
+                "  >duplicates: {} of templates, {} of them optical (within {} pixels)",
+                rate, self.optical_duplicate_fraction, self.optical_duplicate_distance
+            );
+        } else if self.optical_duplicate_fraction != 0.0 {
+            return Err(NeatError::Config(
+                "optical_duplicate_fraction needs duplicate_rate.".to_string()
+            ))
+        }
+        if self.tile_width == 0 {
+            return Err(NeatError::Config("tile_width must be at least 1.".to_string()))
+        }
+        if let Some(sample_index) = &self.sample_index {
+            if let Some(other) = self.index_pool
+                .iter()
+                .find(|other| other.is_dual() != sample_index.is_dual() || *other == sample_index)
+            {
+                return Err(NeatError::Config(format!(
+                    "index_pool entry {} must be a different index, single or dual like {}.",
+                    other, sample_index
+                )))
+            }
+            if let Some(rate) = self.index_hopping_rate {
+                if !(0.0..=1.0).contains(&rate) || self.index_pool.is_empty() {
+                    return Err(NeatError::Config(
+                        "index_hopping_rate must be between 0 and 1, with an index_pool to hop to."
+                            .to_string()
+                    ))
+                }
+            }
+            info!(
+                "  >sample index: {} ({} other libraries in the pool, hopping rate {})",
+                sample_index, self.index_pool.len(), self.index_hopping_rate.unwrap_or(0.0)
+            );
+        } else if !self.index_pool.is_empty() || self.index_hopping_rate.is_some() {
+            return Err(NeatError::Config(
+                "index_pool and index_hopping_rate need a sample_index.".to_string()
+            ))
+        }
+        for tag in &self.read_comment_tags {
+            check_comment_tag(tag)?;
+            info!("  >fastq comment tag: {}", tag);
+        }
+        check_quality_encoding(self.quality_offset, self.max_quality)?;
+        if self.quality_offset != SANGER_QUALITY_OFFSET {
+            info!("  >fastq quality offset: {}", self.quality_offset);
+        }
+        if let Some(max_quality) = self.max_quality {
+            info!("  >fastq quality capped at: {}", max_quality);
+        }
+        if let Some(threshold) = self.quality_trim_threshold {
+            info!("  >fastq trim positions at quality: {}", threshold);
+        }
+        if self.global_shuffle {
+            info!("  >shuffling reads across contigs");
+        }
+        if self.sort_reads {
+            if self.global_shuffle {
+                return Err(NeatError::Config(
+                    "sort_reads and global_shuffle can't both be set.".to_string()
+                ))
+            }
+            info!("  >sorting reads by position");
+        }
+        let read_group_fields = [&self.sample_name, &self.library, &self.platform_unit];
+        for value in read_group_fields.into_iter().flatten() {
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                return Err(NeatError::Config(format!(
+                    "Read group values must be non-empty with no spaces: {:?}", value
+                )))
+            }
+        }
+        if !PLATFORMS.contains(&self.platform.as_str()) {
+            return Err(NeatError::Config(format!(
+                "Unknown platform {}, expected one of {}", self.platform, PLATFORMS.join(", ")
+            )))
+        }
+        if self.instrument.is_empty()
+            || self.instrument.contains(|c: char| c.is_whitespace() || c == ':')
+        {
+            return Err(NeatError::Config(format!(
+                "The instrument must be non-empty with no spaces or colons: {:?}", self.instrument
+            )))
+        }
+        Ok(())
+    }
+
+    fn check_run_settings(&self) -> Result<(), NeatError> {
+        // Threads, memory, output locations and which part of the reference is run.
+        if self.threads == 0 {
+            return Err(NeatError::Config("threads must be at least 1.".to_string()))
+        }
+        info!("  >threads: {}", self.threads);
+        if let Some(max_memory) = self.max_memory {
+            if max_memory == 0 {
+                return Err(NeatError::Config("max_memory must be more than 0.".to_string()))
+            }
+            info!("  >memory budget: {} MB", max_memory >> 20);
+        }
+        if let Some(throttle_mb_per_sec) = self.throttle_mb_per_sec {
+            if !(throttle_mb_per_sec > 0.0 && throttle_mb_per_sec.is_finite()) {
+                return Err(NeatError::Config(
+                    "throttle_mb_per_sec must be more than 0.".to_string()
+                ))
+            }
+            info!("  >writing at most {} MB a second", throttle_mb_per_sec);
+        }
         if self.overwrite_output {
             warn!("Overwriting any existing files.")
         }
@@ -133,219 +1368,1386 @@ impl ConfigBuilder {
         // This check may be overkill, but here it is. Let's make sure we ended up with something
         if !output_path.as_path().is_dir() {
             warn!("Output directory is not a directory: {:?}", self.output_dir.display());
-            check_create_dir(output_path);
+            check_create_dir(output_path)?;
         }
-        let file_prefix = format!("{}/{}", self.output_dir.display(), self.output_prefix);
-
-        // No point in running if we aren't producing files
-        if !(self.produce_fastq | self.produce_fasta | self.produce_vcf | self.produce_bam) {
-            panic!("All file types set to false, no files would be produced.");
+        if let Some(dictionary) = &self.sequence_dictionary {
+            if !Path::new(dictionary).is_file() {
+                return Err(NeatError::Config(
+                    format!("Sequence dictionary not found: {}", dictionary)
+                ))
+            }
+            info!("  >checking the reference against: {}", dictionary);
         }
+        if self.primary_assembly_only {
+            info!("  >simulating the primary assembly only");
+        }
+        if let Some(contig) = &self.only_contig {
+            info!("  >writing output for {} only", contig);
+        }
+        if let Some(seed) = &self.contig_seed {
+            if self.only_contig.is_none() {
+                return Err(NeatError::Config("contig_seed needs only_contig.".to_string()))
+            }
+            if seed.split_whitespace().next().is_none() {
+                return Err(NeatError::Config("contig_seed needs at least one word.".to_string()))
+            }
+            info!("  >contig seed: {}", canonical_seed(seed));
+        }
+        if let Some(region) = &self.region {
+            parse_region(region)?;
+            if self.only_contig.is_some() {
+                return Err(NeatError::Config(
+                    "region and only_contig can't be used together.".to_string()
+                ))
+            }
+            info!("  >simulating {} only", region);
+        }
+        if self.fill_gaps {
+            if self.region.is_some() {
+                // The rest of the region's contig is N, and would all be filled.
+                return Err(NeatError::Config(
+                    "fill_gaps and region can't be used together.".to_string()
+                ))
+            }
+            info!("  >filling reference gaps with synthetic telomere and satellite repeats");
+        }
+        if let Some(reference_cache) = &self.reference_cache {
+            info!("  >reference cache: {}", reference_cache.display());
+        }
+        if let Some(temp_dir) = &self.temp_dir {
+            info!("  >staging output in: {}", temp_dir.display());
+            check_create_dir(temp_dir)?;
+        }
+        Ok(())
+    }
 
+    fn check_paired_reads(
+        &self,
+        fragment_mean: Option<f64>,
+        fragment_st_dev: Option<f64>,
+        file_prefix: &str,
+    ) -> Result<(), NeatError> {
+        // Fragment lengths and what can happen to the pairs, which need paired ended reads.
         if self.paired_ended {
-            if self.fragment_mean.is_none() | self.fragment_st_dev.is_none() {
-                panic!(
+            // A coverage bam's fragment lengths are used if it has any.
+            if (fragment_mean.is_none() | fragment_st_dev.is_none()) && self.coverage_bam.is_none()
+            {
+                return Err(NeatError::Config(
                     "Paired ended is set to true, but fragment mean \
-                    and standard deviation were not set."
+                    and standard deviation were not set.".to_string()
+                ))
+            }
+            if let Some(fragment_mean) = fragment_mean.filter(|_| self.coverage_bam.is_none()) {
+                // Reads from fragments shorter than themselves stop at the fragment's end, unless
+                // they read through into the adapters.
+                if fragment_mean <= self.read_len as f64 && !self.adapter_read_through {
+                    return Err(NeatError::Config(format!(
+                        "fragment_mean ({}) must be longer than read_len ({}), or most reads \
+                        would be cut short; set adapter_read_through to read into the adapters",
+                        fragment_mean, self.read_len
+                    )))
+                }
+                if let Some(warning) = fragment_warning(self.read_len, fragment_mean) {
+                    warn!("{}", warning);
+                }
+            }
+            let pair_loss = [
+                ("singleton_rate", self.singleton_rate),
+                ("discarded_pair_rate", self.discarded_pair_rate),
+            ];
+            for (name, rate) in pair_loss {
+                if let Some(rate) = rate {
+                    if !(0.0..=1.0).contains(&rate) {
+                        return Err(NeatError::Config(format!(
+                            "{} must be between 0 and 1, got {}", name, rate
+                        )))
+                    }
+                    info!("\t> {}: {}", name, rate);
+                }
+            }
+            let fragment_min = self.fragment_min.unwrap_or(DEFAULT_FRAGMENT_BOUNDS.0);
+            let fragment_max = self.fragment_max.unwrap_or(DEFAULT_FRAGMENT_BOUNDS.1);
+            if fragment_min == 0 || fragment_max < fragment_min {
+                return Err(NeatError::Config(format!(
+                    "fragment_min must be at least 1 and no more than fragment_max, got {} and {}",
+                    fragment_min, fragment_max
+                )))
+            }
+            if let Some(fragment_min) = self.fragment_min {
+                info!("\t> shortest fragment: {}", fragment_min);
+            }
+            if let Some(fragment_max) = self.fragment_max {
+                info!("\t> longest fragment: {}", fragment_max);
+            }
+            if self.singleton_rate.unwrap_or(0.0) + self.discarded_pair_rate.unwrap_or(0.0) > 1.0 {
+                return Err(NeatError::Config(
+                    "singleton_rate and discarded_pair_rate add up to more than 1.".to_string()
+                ))
+            }
+            if self.adapter_read_through {
+                info!("\t> reading through into adapters");
+            }
+            if let Some(family_size) = self.duplex_family_size {
+                if family_size == 0 {
+                    return Err(NeatError::Config(
+                        "duplex_family_size must be at least 1.".to_string()
+                    ))
+                }
+                info!("\t> duplex families of {} read pairs per strand", family_size);
+            }
+            if self.cell_free_fragments {
+                info!("\t> cell-free DNA fragment lengths");
+            }
+            if let Some(rate) = self.ffpe_artifact_rate {
+                let in_range = |value: f64| value > 0.0 && value <= 1.0;
+                if !in_range(rate) || !in_range(self.ffpe_artifact_fraction) {
+                    return Err(NeatError::Config(
+                        "ffpe_artifact_rate and ffpe_artifact_fraction must be above 0 and at \
+                        most 1.".to_string()
+                    ))
+                }
+                if self.duplex_family_size.is_some() {
+                    return Err(NeatError::Config(
+                        "ffpe_artifact_rate can't be used with duplex_family_size.".to_string()
+                    ))
+                }
+                info!(
+                    "\t> FFPE artifacts at {} of bases, in {} of fragments",
+                    rate, self.ffpe_artifact_fraction
                 );
             }
             if self.produce_fastq {
-                info!("\t> fragment mean: {}", self.fragment_mean.unwrap());
-                info!("\t> fragment standard deviation: {}", self.fragment_st_dev.unwrap());
+                match (&self.coverage_bam, fragment_mean, fragment_st_dev) {
+                    (None, Some(fragment_mean), Some(fragment_st_dev)) => {
+                        info!("\t> fragment mean: {}", fragment_mean);
+                        info!("\t> fragment standard deviation: {}", fragment_st_dev);
+                    },
+                    _ => info!("\t> fragment lengths from the coverage bam"),
+                }
                 info!("Producing fastq files:\n\t> {}_r1.fastq\n\t {}_r2.fastq",
                     file_prefix, file_prefix
-                )
+                );
+                if self.singleton_rate.is_some() {
+                    info!("\t> {}_singletons.fastq", file_prefix)
+                }
             } else {
                 info!("Producing fastq file:\n\t> {}_r1.fastq", file_prefix)
             }
+        } else if self.fragment_min.is_some() || self.fragment_max.is_some() {
+            return Err(NeatError::Config(
+                "fragment_min and fragment_max need paired ended reads.".to_string()
+            ))
+        } else if self.singleton_rate.is_some() || self.discarded_pair_rate.is_some() {
+            return Err(NeatError::Config(
+                "singleton_rate and discarded_pair_rate need paired ended reads.".to_string()
+            ))
+        } else if self.duplex_family_size.is_some() {
+            return Err(NeatError::Config(
+                "duplex_family_size needs paired ended reads.".to_string()
+            ))
+        } else if self.cell_free_fragments {
+            return Err(NeatError::Config(
+                "cell_free_fragments needs paired ended reads.".to_string()
+            ))
+        } else if self.ffpe_artifact_rate.is_some() {
+            return Err(NeatError::Config(
+                "ffpe_artifact_rate needs paired ended reads.".to_string()
+            ))
         }
+        Ok(())
+    }
+
+    fn check_outputs(&self, file_prefix: &str) -> Result<(), NeatError> {
+        // The output files, and the checks made on them.
         if self.produce_fasta {
             info!("Producing fasta file: {}.fasta", file_prefix);
         }
+        if self.haplotype_fasta {
+            info!("Producing a fasta file per copy of the genome: {}_hap<N>.fasta", file_prefix);
+        }
+        if self.produce_edit_list {
+            info!("Producing the edits to the reference: {}.edits", file_prefix);
+        }
+        // Tabular truth files are bgzipped and indexed on request
+        let (gz, index) = match self.bgzip_truth_files {
+            true => (".gz", ", with a tabix index"),
+            false => ("", ""),
+        };
         if self.produce_vcf {
-            info!("Producing vcf file: {}.vcf", file_prefix)
+            info!("Producing vcf file: {}.vcf{}{}", file_prefix, gz, index);
+            if self.ffpe_artifact_rate.is_some() {
+                info!("\t> FFPE artifact sites: {}_ffpe_artifacts.vcf", file_prefix)
+            }
         }
         if self.produce_bam {
-            info!("Produce bam file: {}.bam", file_prefix)
+            info!("Produce bam file: {}.bam", file_prefix);
+            info!("\t> sample: {}", self.sample_name());
         }
-        if self.rng_seed.is_some() {
-            info!("Using rng seed: {}", self.rng_seed.clone().unwrap())
+        if let Some(k) = self.kmer_spectrum {
+            if !(1..=MAX_KMER_SIZE).contains(&k) {
+                return Err(NeatError::Config(format!(
+                    "kmer_spectrum must be from 1 to {}, got {}", MAX_KMER_SIZE, k
+                )))
+            }
+            // The reads are only counted as they are written.
+            if !self.produce_fastq && !self.produce_bam {
+                return Err(NeatError::Config(
+                    "kmer_spectrum needs produce_fastq or produce_bam.".to_string()
+                ))
+            }
+            info!("Producing {}-mer spectrum: {}_kmer_histo.txt", k, file_prefix);
+        }
+        if self.truth_pileup {
+            if !self.produce_fastq && !self.produce_bam {
+                return Err(NeatError::Config(
+                    "truth_pileup needs produce_fastq or produce_bam.".to_string()
+                ))
+            }
+            info!(
+                "Producing truth pileup: {}_depth.bedgraph, {}_alleles.tsv",
+                file_prefix, file_prefix
+            );
+        }
+        if self.genotype_calls {
+            if !self.produce_fastq && !self.produce_bam {
+                return Err(NeatError::Config(
+                    "genotype_calls counts the reads, so needs produce_fastq or produce_bam."
+                        .to_string()
+                ))
+            }
+            if !self.produce_vcf && !self.produce_benchmark_files {
+                return Err(NeatError::Config(
+                    "genotype_calls needs produce_vcf or produce_benchmark_files.".to_string()
+                ))
+            }
+            info!("  >truth vcf with DP, AD, GQ and PL from the reads");
+        }
+        if self.produce_benchmark_files {
+            info!(
+                "Producing benchmark files: {}.vcf.gz, {}.vcf.gz.tbi, {}_confident.bed{}{}",
+                file_prefix, file_prefix, file_prefix, gz, index
+            )
+        }
+        if self.validate_truth {
+            if !self.produce_vcf && !self.produce_benchmark_files {
+                return Err(NeatError::Config(
+                    "validate_truth checks the truth vcf, so needs produce_vcf or \
+                    produce_benchmark_files.".to_string()
+                ))
+            }
+            info!("Checking the truth vcf against the reference once it is written");
+        }
+        if let Some(liftover_chain) = &self.liftover_chain {
+            if !Path::new(liftover_chain).is_file() {
+                return Err(NeatError::Config(
+                    format!("Liftover chain file not found: {}", liftover_chain)
+                ))
+            }
+            if !self.produce_vcf {
+                return Err(NeatError::Config(
+                    "liftover_chain lifts the truth vcf, so needs produce_vcf.".to_string()
+                ))
+            }
+            info!(
+                "Producing the truth vcf lifted over by {}: {}_lifted.vcf",
+                liftover_chain, file_prefix
+            );
+        }
+        Ok(())
+    }
+
+    fn check_seeds(&self) -> Result<(), NeatError> {
+        // The rng seed, given or drawn.
+        if let Some(seed) = &self.rng_seed {
+            if seed.split_whitespace().next().is_none() {
+                return Err(NeatError::Config("rng_seed needs at least one word.".to_string()))
+            }
+            info!("Using rng seed: {}", canonical_seed(seed))
+        }
+        if let Some(words) = self.random_seed_words {
+            if words == 0 {
+                return Err(NeatError::Config(
+                    "random_seed_words must be at least 1.".to_string()
+                ))
+            }
+            if self.rng_seed.is_some() {
+                return Err(NeatError::Config(
+                    "random_seed_words can't be used with rng_seed.".to_string()
+                ))
+            }
+            info!("Seeding the run with {} random words", words)
+        }
+        Ok(())
+    }
+
+    pub(crate) fn apply_preset(&mut self, preset: &str) -> Result<(), NeatError> {
+        // Sets the defaults of a kind of run. Anything set afterwards overrides them.
+        //
+        // cfdna: Cell-free DNA. Paired ended 2x150 reads from short, nucleosomal fragments that
+        //     read through into the adapters, at the very high depth of a ctDNA panel.
+        // mutations: Only the mutation engine. No reads, just the mutated fasta and its edits to
+        //     the reference, for driving other read simulators.
+        match preset {
+            "cfdna" => {
+                self.paired_ended = true;
+                self.read_len = 150;
+                self.fragment_mean = Some(167.0);
+                self.fragment_st_dev = Some(10.0);
+                self.cell_free_fragments = true;
+                self.adapter_read_through = true;
+                self.coverage = 2000;
+                Ok(())
+            },
+            "mutations" => {
+                self.produce_fastq = false;
+                self.produce_bam = false;
+                self.produce_fasta = true;
+                self.produce_edit_list = true;
+                Ok(())
+            },
+            _ => Err(NeatError::Config(format!("Unknown preset: {}", preset))),
+        }
+    }
+
+    fn sample_name(&self) -> String {
+        // The configured sample name, or the default for this kind of run.
+        match (&self.sample_name, self.somatic_mutation_rate) {
+            (Some(sample_name), _) => sample_name.clone(),
+            (None, Some(_)) => String::from("tumor"),
+            (None, None) => String::from("normal"),
         }
     }
 
     // Function to build the actual configuration.
     pub fn build(self) -> RunConfiguration {
+        let sample_name = self.sample_name();
+        let library = self.library.unwrap_or_else(|| format!("{}_lib", sample_name));
         RunConfiguration {
             reference: self.reference.unwrap(),
             read_len: self.read_len,
             coverage: self.coverage,
             mutation_rate: self.mutation_rate,
-            ploidy: self.ploidy,
+            somatic_mutation_rate: self.somatic_mutation_rate,
+            somatic_signatures: self.somatic_signatures,
+            signature_exposures: self.signature_exposures,
+            // Each haplotype of a quasispecies, or of the sample, is a copy of the genome
+            ploidy: match self.quasispecies_frequencies.len().max(self.sample_haplotypes.len()) {
+                0 => self.ploidy,
+                haplotypes => haplotypes,
+            },
+            contig_ploidy: self.contig_ploidy,
+            snp_distance: self.snp_distance,
+            plasmid_copy_number: self.plasmid_copy_number,
+            quasispecies_frequencies: self.quasispecies_frequencies,
+            quasispecies_diversity: self.quasispecies_diversity,
+            timepoints: self.timepoints,
+            timepoint_mutations: self.timepoint_mutations,
+            timepoint_drift: self.timepoint_drift,
+            sample_haplotypes: self.sample_haplotypes,
+            genotype_frequencies: self.genotype_frequencies,
+            allele_frequency: self.allele_frequency,
             paired_ended: self.paired_ended,
             fragment_mean: self.fragment_mean,
             fragment_st_dev: self.fragment_st_dev,
+            fragment_min: self.fragment_min,
+            fragment_max: self.fragment_max,
+            singleton_rate: self.singleton_rate,
+            discarded_pair_rate: self.discarded_pair_rate,
+            adapter_read_through: self.adapter_read_through,
+            duplex_family_size: self.duplex_family_size,
+            cell_free_fragments: self.cell_free_fragments,
+            ffpe_artifact_rate: self.ffpe_artifact_rate,
+            ffpe_artifact_fraction: self.ffpe_artifact_fraction,
+            tumor_fraction: self.tumor_fraction,
+            target_bed: self.target_bed,
+            capture_model: self.capture_model,
+            capture_shoulder: self.capture_shoulder,
+            capture_efficiency_st_dev: self.capture_efficiency_st_dev,
+            capture_gc_dropout: self.capture_gc_dropout,
+            low_complexity_error_fold: self.low_complexity_error_fold,
+            low_complexity_bed: self.low_complexity_bed,
+            quality_crash_rate: self.quality_crash_rate,
+            qc_fail_rate: self.qc_fail_rate,
+            molecule_error_rate: self.molecule_error_rate,
+            error_profile: self.error_profile,
+            model_dir: self.model_dir,
+            coverage_profile: self.coverage_profile,
+            coverage_bam: self.coverage_bam,
+            gene_annotation: self.gene_annotation,
+            exon_mutation_rate: self.exon_mutation_rate,
+            intron_mutation_rate: self.intron_mutation_rate,
+            intergenic_mutation_rate: self.intergenic_mutation_rate,
+            known_sites_vcf: self.known_sites_vcf,
+            known_site_fraction: self.known_site_fraction,
             produce_fastq: self.produce_fastq,
             produce_fasta: self.produce_fasta,
+            haplotype_fasta: self.haplotype_fasta,
+            produce_edit_list: self.produce_edit_list,
             produce_vcf: self.produce_vcf,
             produce_bam: self.produce_bam,
-            rng_seed: self.rng_seed,
+            kmer_spectrum: self.kmer_spectrum,
+            truth_pileup: self.truth_pileup,
+            genotype_calls: self.genotype_calls,
+            produce_benchmark_files: self.produce_benchmark_files,
+            bgzip_truth_files: self.bgzip_truth_files,
+            validate_truth: self.validate_truth,
+            liftover_chain: self.liftover_chain,
+            rng_seed: self.rng_seed.as_deref().map(canonical_seed),
+            random_seed_words: self.random_seed_words,
             overwrite_output: self.overwrite_output,
             minimum_mutations: self.minimum_mutations,
             output_dir: self.output_dir,
             output_prefix: self.output_prefix,
+            read_name_format: self.read_name_format,
+            read_name_prefix: self.read_name_prefix,
+            duplicate_rate: self.duplicate_rate,
+            optical_duplicate_fraction: self.optical_duplicate_fraction,
+            optical_duplicate_distance: self.optical_duplicate_distance,
+            tile_width: self.tile_width,
+            sample_index: self.sample_index,
+            index_pool: self.index_pool,
+            index_hopping_rate: self.index_hopping_rate,
+            read_comment_tags: self.read_comment_tags,
+            truth_comment_tags: self.truth_comment_tags,
+            quality_offset: self.quality_offset,
+            max_quality: self.max_quality,
+            quality_trim_threshold: self.quality_trim_threshold,
+            global_shuffle: self.global_shuffle,
+            sort_reads: self.sort_reads,
+            sample_name,
+            sample_sex: self.sample_sex,
+            mt_copy_number: self.mt_copy_number,
+            heteroplasmy_fractions: self.heteroplasmy_fractions,
+            par_bed: self.par_bed,
+            library,
+            platform: self.platform,
+            platform_unit: self.platform_unit,
+            instrument: self.instrument,
+            threads: self.threads,
+            max_memory: self.max_memory,
+            throttle_mb_per_sec: self.throttle_mb_per_sec,
+            temp_dir: self.temp_dir,
+            reference_cache: self.reference_cache,
+            sequence_dictionary: self.sequence_dictionary,
+            primary_assembly_only: self.primary_assembly_only,
+            only_contig: self.only_contig,
+            contig_seed: self.contig_seed.as_deref().map(canonical_seed),
+            region: self.region,
+            fill_gaps: self.fill_gaps,
+            sweep: Vec::new(),
         }
     }
 }
 
-fn generate_error(key: &str, key_type: &str, value: &Value) -> String {
-    format!("Input {} could not be converted to {}: {:?}", key, key_type, value)
+pub fn canonical_seed(seed: &str) -> String {
+    // The canonical form of a seed: its words, separated by single spaces. The rng is seeded from
+    // the words, so every spelling of a seed gives the same run, and this is the one recorded.
+    // Integer seeds are the string of their decimal digits, so 42 and "42" are the same seed.
+    seed.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+pub fn fragment_warning(read_len: usize, fragment_mean: f64) -> Option<String> {
+    // Mates overlap when their fragment is shorter than both reads together. Short inserts are
+    // real, but most mates overlapping is more often a read_len or fragment_mean set wrong.
+    (fragment_mean < 2.0 * read_len as f64).then(|| format!(
+        "fragment_mean ({}) is less than twice read_len ({}), so most mates will overlap",
+        fragment_mean, read_len
+    ))
+}
+
+fn generate_error(key: &str, key_type: &str, value: &Value) -> NeatError {
+    NeatError::Config(format!("Input {} could not be converted to {}: {:?}", key, key_type, value))
+}
+
+pub fn read_config_yaml(yaml: String) -> Result<Box<RunConfiguration>, NeatError> {
+    // Reads a configuration file, warning about any keys it doesn't know.
+    read_config_yaml_checked(yaml, false)
 }
 
-pub fn read_config_yaml<'d>(yaml: String) -> Box<RunConfiguration> {
+pub fn read_config_yaml_checked(
+    yaml: String,
+    strict_config: bool,
+) -> Result<Box<RunConfiguration>, NeatError> {
     // Reads an input configuration file from yaml using the serde package. Then sets the parameters
-    // based on the inputs. A "." value means to use the default value.
+    // based on the inputs. A "." value means to use the default value. Keys the configuration
+    // doesn't have are warned about, or stop the run if strict_config is set.
 
     // Opens file for reading
-    let f = fs::File::open(yaml);
-    let file = match f {
-        Ok(l) => l,
-        Err(error) => panic!("Problem reading the config file: {}", error),
-    };
+    let file = fs::File::open(&yaml).map_err(|error| {
+        NeatError::Config(format!("Problem reading the config file {}: {}", yaml, error))
+    })?;
     // Uses serde_yaml to read the file into a HashMap
-    let scrape_config: HashMap<String, Value> = serde_yaml::from_reader(file)
-        .expect("Could not read values");
+    let scrape_config: HashMap<String, Value> = serde_yaml::from_reader(file)?;
+    check_config_keys(&scrape_config, strict_config)?;
+    let config_builder = builder_from_yaml(&scrape_config)?;
+    config_builder.check_and_print_config()?;
+    let mut config = config_builder.build();
+    if let Some(sweep) = scrape_config.get("sweep") {
+        if sweep.as_str() != Some(".") {
+            config.sweep = sweep_points(&scrape_config, sweep, &config.output_dir)?;
+        }
+    }
+    if config.timepoints.is_some() && !config.sweep.is_empty() {
+        return Err(NeatError::Config("timepoints can't be combined with a sweep.".to_string()))
+    }
+    Ok(Box::new(config))
+}
+
+fn builder_from_yaml(scrape_config: &HashMap<String, Value>) -> Result<ConfigBuilder, NeatError> {
     // Create the config builder then update any items from the configuration file in the
     // configuration object and returns it.
     let mut config_builder = ConfigBuilder::new();
-    for (key, value) in scrape_config {
-        match key.as_str() {
-            // Too extra checks needed are for reference. Everything else can be
-            // easily skipped with a value of "."
-            "reference" => {
-                let reference_path = Path::new(value.as_str().unwrap());
-                if !reference_path.is_file() {
-                    panic!("Reference file not found: {}", value.as_str().unwrap())
-                } else {
-                    // Okay, serde Value is weird for strings
-                    config_builder.reference = value
-                        .as_str()
-                        .unwrap()
-                        .to_string()
-                        .into();
-                }
-            },
-            _ => {
-                match &value.as_str() {
-                    Some(".") => continue,
-                    _ => match key.as_str() {
-                        "read_len" => {
-                            config_builder.read_len = value.as_u64()
-                                .expect(&generate_error(
-                                    &key, "integer", &value
-                                ))
-                            as usize
-                        },
-                        "coverage" => {
-                            config_builder.coverage = value.as_u64()
-                                .expect(&generate_error(
-                                    &key, "integer", &value
-                                ))
+    // A preset only sets defaults, so it goes first whatever its place in the file.
+    if let Some(preset) = scrape_config.get("preset") {
+        if preset.as_str() != Some(".") {
+            let preset = preset.as_str().ok_or_else(|| generate_error("preset", "string", preset))?;
+            config_builder.apply_preset(preset)?;
+        }
+    }
+    // Unknown keys have been reported by check_config_keys.
+    for (key, value) in scrape_config.iter().filter(|(key, _)| config_keys().any(|k| k == *key)) {
+        set_config_value(&mut config_builder, key, value)?;
+    }
+    Ok(config_builder)
+}
+
+// The template sets every key a configuration file can, so it is the list of them. Any other key
+// is taken for a typo and reported (see check_config_keys), so a new setting goes in the template
+// and in set_config_value.
+const CONFIG_TEMPLATE: &str = include_str!("../../config/simple_template.yml");
+
+fn config_keys() -> impl Iterator<Item = &'static str> {
+    // The keys of the template, in its order.
+    CONFIG_TEMPLATE
+        .lines()
+        .filter_map(|line| line.split_once(':').map(|(key, _)| key))
+        .filter(|key| !key.is_empty() && !key.starts_with('#'))
+}
+
+fn edit_distance(first: &str, second: &str) -> usize {
+    // The number of single character insertions, deletions and substitutions that turn first
+    // into second.
+    let second: Vec<char> = second.chars().collect();
+    let mut previous: Vec<usize> = (0..=second.len()).collect();
+    for (index, first_char) in first.chars().enumerate() {
+        let mut current = vec![index + 1];
+        for (second_index, second_char) in second.iter().enumerate() {
+            let substitution = previous[second_index] + usize::from(first_char != *second_char);
+            let deletion = previous[second_index + 1] + 1;
+            let insertion = current[second_index] + 1;
+            current.push(substitution.min(deletion).min(insertion));
+        }
+        previous = current;
+    }
+    previous[second.len()]
+}
+
+fn nearest_config_key(key: &str) -> Option<&'static str> {
+    // The valid key closest to a misspelt one, if any is close enough to be what was meant.
+    config_keys()
+        .map(|valid_key| (edit_distance(key, valid_key), valid_key))
+        .min()
+        .filter(|(distance, _)| *distance <= (key.len() / 3).max(1))
+        .map(|(_, valid_key)| valid_key)
+}
+
+fn check_config_keys(
+    scrape_config: &HashMap<String, Value>,
+    strict_config: bool,
+) -> Result<(), NeatError> {
+    // Takes:
+    // scrape_config: The configuration file, as read.
+    // strict_config: Whether unknown keys are an error rather than a warning.
+    // Returns:
+    // An error naming the unknown keys (with the nearest valid key to each) for strict configs
+    // that have any, or else nothing, after warning about each of them.
+    let mut unknown: Vec<String> = scrape_config
+        .keys()
+        .filter(|key| !config_keys().any(|valid_key| valid_key == key.as_str()))
+        .map(|key| match nearest_config_key(key) {
+            Some(nearest) => format!("{} (did you mean {}?)", key, nearest),
+            None => key.clone(),
+        })
+        .collect();
+    if unknown.is_empty() {
+        return Ok(())
+    }
+    unknown.sort();
+    if strict_config {
+        return Err(NeatError::Config(format!(
+            "Unknown keys in the config file: {}", unknown.join(", ")
+        )))
+    }
+    for key in unknown {
+        warn!("Ignoring unknown key in the config file: {}", key);
+    }
+    Ok(())
+}
+
+fn set_config_value(
+    config_builder: &mut ConfigBuilder,
+    key: &str,
+    value: &Value,
+) -> Result<(), NeatError> {
+    // Sets one setting from the configuration file. A key of the template with no setting here is
+    // an error, so that the two can't drift apart.
+    match key {
+        // Too extra checks needed are for reference. Everything else can be
+        // easily skipped with a value of "."
+        "reference" => {
+            // Okay, serde Value is weird for strings
+            let reference = value.as_str()
+                .ok_or_else(|| generate_error(key, "string", value))?;
+            if !Path::new(reference).is_file() {
+                return Err(NeatError::Config(
+                    format!("Reference file not found: {}", reference)
+                ))
+            }
+            config_builder.reference = reference.to_string().into();
+        },
+        _ => {
+            match &value.as_str() {
+                Some(".") => {},
+                _ => match key {
+                    "read_len" => {
+                        config_builder.read_len = value.as_u64()
+                            .ok_or_else(|| generate_error(
+                                key, "integer", value
+                            ))?
+                        as usize
+                    },
+                    "coverage" => {
+                        config_builder.coverage = value.as_u64()
+                            .ok_or_else(|| generate_error(
+                                key, "integer", value
+                            ))?
+                        as usize
+                    },
+                    "mutation_rate" => {
+                        config_builder.mutation_rate = value.as_f64()
+                            .ok_or_else(|| generate_error(
+                                key, "float", value
+                            ))?
+                    }
+                    "somatic_mutation_rate" => {
+                        config_builder.somatic_mutation_rate = value.as_f64()
+                            .ok_or_else(|| generate_error(
+                                key, "float", value
+                            ))?
+                            .into() // to make it an option
+                    },
+                    "ploidy" => {
+                        config_builder.ploidy = value.as_u64()
+                            .ok_or_else(|| generate_error(
+                                key, "integer", value
+                            ))?
+                        as usize
+                    },
+                    "contig_ploidy" => {
+                        // A mapping of contig names to their copies. Names like 1 or 21 are
+                        // read by yaml as numbers, so those are taken too.
+                        let contigs = value.as_mapping().ok_or_else(|| generate_error(
+                            key, "mapping of contigs to copies", value
+                        ))?;
+                        config_builder.contig_ploidy = contigs
+                            .iter()
+                            .map(|(contig, copies)| {
+                                let name = contig.as_str().map(str::to_string)
+                                    .or_else(|| contig.as_u64().map(|number| number.to_string()));
+                                match (name, copies.as_u64()) {
+                                    (Some(name), Some(copies)) => Ok((name, copies as usize)),
+                                    (None, _) => Err(generate_error(key, "string", contig)),
+                                    _ => Err(generate_error(key, "integer", copies)),
+                                }
+                            })
+                            .collect::<Result<Vec<(String, usize)>, NeatError>>()?
+                    },
+                    "snp_distance" => {
+                        config_builder.snp_distance = Some(value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as usize)
+                    },
+                    "plasmid_copy_number" => {
+                        // A mapping of contig names to their copies, as for contig_ploidy
+                        let contigs = value.as_mapping().ok_or_else(|| generate_error(
+                            key, "mapping of contigs to copies", value
+                        ))?;
+                        config_builder.plasmid_copy_number = contigs
+                            .iter()
+                            .map(|(contig, copies)| {
+                                let name = contig.as_str().map(str::to_string)
+                                    .or_else(|| contig.as_u64().map(|number| number.to_string()));
+                                match (name, copies.as_f64()) {
+                                    (Some(name), Some(copies)) => Ok((name, copies)),
+                                    (None, _) => Err(generate_error(key, "string", contig)),
+                                    _ => Err(generate_error(key, "float", copies)),
+                                }
+                            })
+                            .collect::<Result<Vec<(String, f64)>, NeatError>>()?
+                    },
+                    "quasispecies_frequencies" => {
+                        config_builder.quasispecies_frequencies = value.as_sequence()
+                            .ok_or_else(|| generate_error(key, "list of floats", value))?
+                            .iter()
+                            .map(|frequency| frequency.as_f64()
+                                .ok_or_else(|| generate_error(key, "float", frequency)))
+                            .collect::<Result<Vec<f64>, NeatError>>()?
+                    },
+                    "quasispecies_diversity" => {
+                        config_builder.quasispecies_diversity = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                    },
+                    "timepoints" => {
+                        config_builder.timepoints = Some(value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as usize)
+                    },
+                    "timepoint_mutations" => {
+                        config_builder.timepoint_mutations = value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
                             as usize
-                        },
-                        "mutation_rate" => {
-                            config_builder.mutation_rate = value.as_f64()
-                                .expect(&generate_error(
-                                    &key, "float", &value
-                                ))
+                    },
+                    "timepoint_drift" => {
+                        config_builder.timepoint_drift = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                    },
+                    "sample_haplotypes" => {
+                        // Either a single fasta or a list of them
+                        let paths = match value.as_sequence() {
+                            Some(paths) => paths.iter().collect(),
+                            None => vec![value],
+                        };
+                        config_builder.sample_haplotypes = paths
+                            .into_iter()
+                            .map(|path| path.as_str()
+                                .map(String::from)
+                                .ok_or_else(|| generate_error(key, "string", path)))
+                            .collect::<Result<Vec<String>, NeatError>>()?
+                    },
+                    "genotype_frequencies" => {
+                        // A mapping of dosages to their weights
+                        let dosages = value.as_mapping().ok_or_else(|| generate_error(
+                            key, "mapping of dosages to weights", value
+                        ))?;
+                        config_builder.genotype_frequencies = dosages
+                            .iter()
+                            .map(|(dosage, weight)| match (dosage.as_u64(), weight.as_f64()) {
+                                (Some(dosage), Some(weight)) => Ok((dosage as usize, weight)),
+                                (None, _) => Err(generate_error(key, "integer", dosage)),
+                                _ => Err(generate_error(key, "float", weight)),
+                            })
+                            .collect::<Result<Vec<(usize, f64)>, NeatError>>()?
+                    },
+                    "allele_frequency" => {
+                        config_builder.allele_frequency = Some(value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?)
+                    },
+                    "paired_ended" => {
+                        config_builder.paired_ended = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "fragment_mean" => {
+                        config_builder.fragment_mean = value.as_f64()
+                            .ok_or_else(|| generate_error(
+                                key, "float", value
+                            ))?
+                            .into() // to make it an option
+                    },
+                    "fragment_min" => {
+                        config_builder.fragment_min = Some(value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as usize)
+                    },
+                    "fragment_max" => {
+                        config_builder.fragment_max = Some(value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as usize)
+                    },
+                    "fragment_st_dev" => {
+                        config_builder.fragment_st_dev = value.as_f64()
+                            .ok_or_else(|| generate_error(
+                                key, "float", value
+                            ))?
+                            .into() // to make it an option
+                    },
+                    "singleton_rate" => {
+                        config_builder.singleton_rate = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                            .into() // to make it an option
+                    },
+                    "discarded_pair_rate" => {
+                        config_builder.discarded_pair_rate = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                            .into() // to make it an option
+                    },
+                    "adapter_read_through" => {
+                        config_builder.adapter_read_through = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    // Applied before the other keys, in builder_from_yaml.
+                    "preset" => {},
+                    "cell_free_fragments" => {
+                        config_builder.cell_free_fragments = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "tumor_fraction" => {
+                        config_builder.tumor_fraction = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                            .into() // to make it an option
+                    },
+                    "target_bed" => {
+                        config_builder.target_bed = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "capture_model" => {
+                        config_builder.capture_model = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "capture_shoulder" => {
+                        config_builder.capture_shoulder = value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))? as usize
+                    },
+                    "capture_efficiency_st_dev" => {
+                        config_builder.capture_efficiency_st_dev = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                    },
+                    "capture_gc_dropout" => {
+                        config_builder.capture_gc_dropout = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                    },
+                    "low_complexity_error_fold" => {
+                        config_builder.low_complexity_error_fold = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                            .into() // to make it an option
+                    },
+                    "low_complexity_bed" => {
+                        config_builder.low_complexity_bed = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "quality_crash_rate" => {
+                        config_builder.quality_crash_rate = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                            .into() // to make it an option
+                    },
+                    "qc_fail_rate" => {
+                        config_builder.qc_fail_rate = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                            .into() // to make it an option
+                    },
+                    "molecule_error_rate" => {
+                        config_builder.molecule_error_rate = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                            .into() // to make it an option
+                    },
+                    "error_profile" => {
+                        config_builder.error_profile = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "model_dir" => {
+                        config_builder.model_dir = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "somatic_signatures" => {
+                        config_builder.somatic_signatures = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "signature_exposures" => {
+                        // A mapping of signature names to their weights
+                        let exposures = value.as_mapping().ok_or_else(|| generate_error(
+                            key, "mapping of signatures to exposures", value
+                        ))?;
+                        config_builder.signature_exposures = exposures
+                            .iter()
+                            .map(|(name, exposure)| match (name.as_str(), exposure.as_f64()) {
+                                (Some(name), Some(exposure)) => Ok((name.to_string(), exposure)),
+                                _ => Err(generate_error(key, "float", exposure)),
+                            })
+                            .collect::<Result<Vec<(String, f64)>, NeatError>>()?
+                    },
+                    "coverage_bam" => {
+                        config_builder.coverage_bam = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "coverage_profile" => {
+                        config_builder.coverage_profile = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "gene_annotation" => {
+                        config_builder.gene_annotation = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "known_sites_vcf" => {
+                        config_builder.known_sites_vcf = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "known_site_fraction" => {
+                        config_builder.known_site_fraction = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                    },
+                    "exon_mutation_rate" => {
+                        config_builder.exon_mutation_rate = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                            .into() // to make it an option
+                    },
+                    "intron_mutation_rate" => {
+                        config_builder.intron_mutation_rate = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                            .into() // to make it an option
+                    },
+                    "intergenic_mutation_rate" => {
+                        config_builder.intergenic_mutation_rate = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                            .into() // to make it an option
+                    },
+                    "duplex_family_size" => {
+                        config_builder.duplex_family_size = Some(value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as usize)
+                    },
+                    "produce_fastq" => {
+                        config_builder.produce_fastq = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "produce_fasta" => {
+                        config_builder.produce_fasta = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "haplotype_fasta" => {
+                        config_builder.haplotype_fasta = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "produce_edit_list" => {
+                        config_builder.produce_edit_list = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "liftover_chain" => {
+                        config_builder.liftover_chain = Some(value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string())
+                    },
+                    "produce_vcf" => {
+                        config_builder.produce_vcf = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "produce_benchmark_files" => {
+                        config_builder.produce_benchmark_files = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "validate_truth" => {
+                        config_builder.validate_truth = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "bgzip_truth_files" => {
+                        config_builder.bgzip_truth_files = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "produce_bam" => {
+                        config_builder.produce_bam = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "kmer_spectrum" => {
+                        config_builder.kmer_spectrum = Some(value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as usize)
+                    },
+                    "truth_pileup" => {
+                        config_builder.truth_pileup = value.as_bool()
+                            .ok_or_else(|| generate_error(key, "boolean", value))?
+                    },
+                    "genotype_calls" => {
+                        config_builder.genotype_calls = value.as_bool()
+                            .ok_or_else(|| generate_error(key, "boolean", value))?
+                    },
+                    "rng_seed" => {
+                        // Either a string or an integer, which is kept as its digits
+                        config_builder.rng_seed = match value {
+                            Value::Number(number) if number.is_i64() || number.is_u64() => {
+                                number.to_string()
+                            },
+                            _ => value.as_str()
+                                .ok_or_else(|| generate_error(key, "string or integer", value))?
+                                .to_string(),
                         }
-                        "ploidy" => {
-                            config_builder.ploidy = value.as_u64()
-                                .expect(&generate_error(
-                                    &key, "integer", &value
-                                ))
+                        .into() // to make it an option
+                    },
+                    "random_seed_words" => {
+                        config_builder.random_seed_words = Some(value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as usize)
+                    },
+                    "overwrite_output" => {
+                        config_builder.overwrite_output = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "minimum_mutations" => {
+                        config_builder.minimum_mutations = Some(value.as_u64()
+                            .ok_or_else(|| generate_error(
+                                key, "Valid integer", value
+                            ))? as usize)
+                    },
+                    "output_dir" => {
+                        let output_path = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?;
+                        config_builder.output_dir = PathBuf::from(output_path);
+                    },
+                    "output_prefix" => {
+                        config_builder.output_prefix = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                    },
+                    "read_name_format" => {
+                        config_builder.read_name_format = ReadNameFormat::parse(
+                            value.as_str()
+                                .ok_or_else(|| generate_error(key, "string", value))?
+                        )?
+                    },
+                    "ffpe_artifact_rate" => {
+                        config_builder.ffpe_artifact_rate = Some(value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?)
+                    },
+                    "ffpe_artifact_fraction" => {
+                        config_builder.ffpe_artifact_fraction = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                    },
+                    "duplicate_rate" => {
+                        config_builder.duplicate_rate = Some(value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?)
+                    },
+                    "optical_duplicate_fraction" => {
+                        config_builder.optical_duplicate_fraction = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                    },
+                    "optical_duplicate_distance" => {
+                        config_builder.optical_duplicate_distance = value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
                             as usize
-                        },
-                        "paired_ended" => {
-                            config_builder.paired_ended = value.as_bool()
-                                .expect(&generate_error(
-                                    &key, "boolean", &value
-                                ))
-                        },
-                        "fragment_mean" => {
-                            config_builder.fragment_mean = value.as_f64()
-                                .expect(&generate_error(
-                                    &key, "float", &value
-                                ))
-                                .into() // to make it an option
-                        },
-                        "fragment_st_dev" => {
-                            config_builder.fragment_st_dev = value.as_f64()
-                                .expect(&generate_error(
-                                    &key, "float", &value
-                                ))
-                                .into() // to make it an option
-                        },
-                        "produce_fastq" => {
-                            config_builder.produce_fastq = value.as_bool()
-                                .expect(&generate_error(
-                                    &key, "boolean", &value
-                                ))
-                        },
-                        "produce_fasta" => {
-                            config_builder.produce_fasta = value.as_bool()
-                                .expect(&generate_error(
-                                    &key, "boolean", &value
-                                ))
-                        },
-                        "produce_vcf" => {
-                            config_builder.produce_vcf = value.as_bool()
-                                .expect(&generate_error(
-                                    &key, "boolean", &value
-                                ))
-                        },
-                        "produce_bam" => {
-                            config_builder.produce_bam = value.as_bool()
-                                .expect(&generate_error(
-                                    &key, "boolean", &value
-                                ))
-                        },
-                        "rng_seed" => {
-                            config_builder.rng_seed = value
-                                .as_str()
-                                .unwrap()
-                                .to_string()
-                                .into() // to make it an option
-                        },
-                        "overwrite_output" => {
-                            config_builder.overwrite_output = value.as_bool()
-                                .expect(&generate_error(
-                                    &key, "boolean", &value
-                                ))
-                        },
-                        "minimum_mutations" => {
-                            config_builder.minimum_mutations = Some(value.as_u64()
-                                .expect(&generate_error(
-                                    &key, "Valid integer", &value
-                                )) as usize)
-                        },
-                        "output_dir" => {
-                            let output_path = value.as_str().unwrap().to_string();
-                            config_builder.output_dir = PathBuf::from(output_path);
-                        },
-                        "output_prefix" => {
-                            config_builder.output_prefix = value.as_str().unwrap().to_string()
-                        },
-                        _ => continue,
-                    }
+                    },
+                    "tile_width" => {
+                        config_builder.tile_width = value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as usize
+                    },
+                    "read_name_prefix" => {
+                        config_builder.read_name_prefix = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                    },
+                    "sample_index" => {
+                        config_builder.sample_index = Some(SampleIndex::parse(value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?)?)
+                    },
+                    "index_pool" => {
+                        // Either a single index or a list of them
+                        let indexes = match value.as_sequence() {
+                            Some(indexes) => indexes.iter().collect(),
+                            None => vec![value],
+                        };
+                        config_builder.index_pool = indexes
+                            .into_iter()
+                            .map(|index| SampleIndex::parse(index.as_str()
+                                .ok_or_else(|| generate_error(key, "string", index))?))
+                            .collect::<Result<Vec<SampleIndex>, NeatError>>()?
+                    },
+                    "index_hopping_rate" => {
+                        config_builder.index_hopping_rate = Some(value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?)
+                    },
+                    "read_comment_tags" => {
+                        // Either a single tag or a list of them
+                        config_builder.read_comment_tags = match value.as_sequence() {
+                            Some(tags) => tags.iter()
+                                .map(|tag| tag.as_str()
+                                    .map(String::from)
+                                    .ok_or_else(|| generate_error(key, "string", tag)))
+                                .collect::<Result<Vec<String>, NeatError>>()?,
+                            None => vec![value.as_str()
+                                .ok_or_else(|| generate_error(key, "string", value))?
+                                .to_string()],
+                        }
+                    },
+                    "truth_comment_tags" => {
+                        config_builder.truth_comment_tags = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "quality_offset" => {
+                        config_builder.quality_offset = value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as u32
+                    },
+                    "max_quality" => {
+                        config_builder.max_quality = Some(value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as u32)
+                    },
+                    "quality_trim_threshold" => {
+                        config_builder.quality_trim_threshold = Some(value.as_u64()
+                            .ok_or_else(|| generate_error(key, "integer", value))?
+                            as u32)
+                    },
+                    "global_shuffle" => {
+                        config_builder.global_shuffle = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "sort_reads" => {
+                        config_builder.sort_reads = value.as_bool()
+                            .ok_or_else(|| generate_error(
+                                key, "boolean", value
+                            ))?
+                    },
+                    "sample_sex" => {
+                        let sample_sex = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?;
+                        config_builder.sample_sex = Sex::from_name(sample_sex)?;
+                    },
+                    "mt_copy_number" => {
+                        config_builder.mt_copy_number = value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?
+                    },
+                    "heteroplasmy_fractions" => {
+                        // A mapping of fractions to their weights
+                        let fractions = value.as_mapping().ok_or_else(|| generate_error(
+                            key, "mapping of fractions to weights", value
+                        ))?;
+                        config_builder.heteroplasmy_fractions = fractions
+                            .iter()
+                            .map(|(fraction, weight)| match (fraction.as_f64(), weight.as_f64()) {
+                                (Some(fraction), Some(weight)) => Ok((fraction, weight)),
+                                (None, _) => Err(generate_error(key, "float", fraction)),
+                                _ => Err(generate_error(key, "float", weight)),
+                            })
+                            .collect::<Result<Vec<(f64, f64)>, NeatError>>()?
+                    },
+                    "par_bed" => {
+                        config_builder.par_bed = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "sample_name" => {
+                        config_builder.sample_name = Some(value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string())
+                    },
+                    "library" => {
+                        config_builder.library = Some(value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string())
+                    },
+                    "platform" => {
+                        config_builder.platform = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_uppercase()
+                    },
+                    "platform_unit" => {
+                        config_builder.platform_unit = Some(value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string())
+                    },
+                    "instrument" => {
+                        config_builder.instrument = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                    },
+                    "threads" => {
+                        config_builder.threads = value.as_u64()
+                            .ok_or_else(|| generate_error(
+                                key, "integer", value
+                            ))?
+                        as usize
+                    },
+                    "max_memory" => {
+                        // Either a number of bytes or a size like 16G
+                        config_builder.max_memory = match value.as_u64() {
+                            Some(bytes) => Some(bytes as usize),
+                            None => Some(parse_memory_size(value.as_str()
+                                .ok_or_else(|| generate_error(key, "size", value))?
+                            )?),
+                        }
+                    },
+                    "throttle_mb_per_sec" => {
+                        config_builder.throttle_mb_per_sec = Some(value.as_f64()
+                            .ok_or_else(|| generate_error(key, "float", value))?)
+                    },
+                    "sequence_dictionary" => {
+                        config_builder.sequence_dictionary = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "primary_assembly_only" => {
+                        config_builder.primary_assembly_only = value.as_bool()
+                            .ok_or_else(|| generate_error(key, "boolean", value))?
+                    },
+                    "only_contig" => {
+                        config_builder.only_contig = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "contig_seed" => {
+                        config_builder.contig_seed = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "region" => {
+                        config_builder.region = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?
+                            .to_string()
+                            .into() // to make it an option
+                    },
+                    "fill_gaps" => {
+                        config_builder.fill_gaps = value.as_bool()
+                            .ok_or_else(|| generate_error(key, "boolean", value))?
+                    },
+                    "reference_cache" => {
+                        let cache_path = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?;
+                        config_builder.reference_cache = Some(PathBuf::from(cache_path));
+                    },
+                    "temp_dir" => {
+                        let temp_path = value.as_str()
+                            .ok_or_else(|| generate_error(key, "string", value))?;
+                        config_builder.temp_dir = Some(PathBuf::from(temp_path));
+                    },
+                    // Read once the rest of the config is built, by sweep_points.
+                    "sweep" => {},
+                    _ => return Err(NeatError::Config(format!("{} has no setting", key))),
                 }
             }
         }
     }
-    let _ = &config_builder.check_and_print_config();
-    Box::new(config_builder.build())
+    Ok(())
+}
+
+// The settings a sweep can vary. Everything else (the reference, where output goes) is shared by
+// every point of the sweep.
+const SWEEP_KEYS: [&str; 12] = [
+    "read_len",
+    "coverage",
+    "mutation_rate",
+    "somatic_mutation_rate",
+    "ploidy",
+    "fragment_mean",
+    "fragment_st_dev",
+    "singleton_rate",
+    "discarded_pair_rate",
+    "duplex_family_size",
+    "tumor_fraction",
+    "minimum_mutations",
+];
+
+fn value_label(value: &Value) -> String {
+    // How a swept value appears in the name of its point's output directory.
+    match value {
+        Value::Number(number) => number.to_string(),
+        Value::String(string) => string.clone(),
+        other => format!("{:?}", other),
+    }
+}
+
+fn sweep_points(
+    scrape_config: &HashMap<String, Value>,
+    sweep: &Value,
+    output_dir: &Path,
+) -> Result<Vec<SweepPoint>, NeatError> {
+    // Takes:
+    // scrape_config: Everything in the configuration file.
+    // sweep: The sweep setting, a mapping of setting names to lists of values.
+    // output_dir: The output directory of the run.
+    // Returns:
+    // One configuration per combination of the swept values (the first setting changing slowest),
+    // each writing to a subdirectory of output_dir named for its values, e.g.
+    // coverage-10_mutation_rate-0.01. Every point is checked like any other configuration.
+    let axes = sweep.as_mapping()
+        .filter(|axes| !axes.is_empty())
+        .ok_or_else(|| generate_error("sweep", "mapping of settings to lists of values", sweep))?;
+    let mut points: Vec<(String, HashMap<String, Value>)> = vec![(String::new(), scrape_config.clone())];
+    for (key, values) in axes {
+        let key = key.as_str().filter(|key| SWEEP_KEYS.contains(key)).ok_or_else(|| {
+            NeatError::Config(format!(
+                "Can't sweep over {:?}, only over: {}", key, SWEEP_KEYS.join(", ")
+            ))
+        })?;
+        let values = values.as_sequence()
+            .filter(|values| !values.is_empty())
+            .ok_or_else(|| generate_error(key, "non-empty list", values))?;
+        points = points
+            .into_iter()
+            .flat_map(|(label, settings)| values.iter().map(move |value| {
+                let mut point_settings = settings.clone();
+                point_settings.insert(key.to_string(), value.clone());
+                let name = format!("{}-{}", key, value_label(value));
+                match label.is_empty() {
+                    true => (name, point_settings),
+                    false => (format!("{}_{}", label, name), point_settings),
+                }
+            }))
+            .collect();
+    }
+    info!("Sweeping over {} configurations", points.len());
+    points
+        .into_iter()
+        .map(|(label, settings)| {
+            let mut config_builder = builder_from_yaml(&settings)?;
+            config_builder.output_dir = output_dir.join(&label);
+            config_builder.check_and_print_config()?;
+            Ok(SweepPoint { label, config: config_builder.build() })
+        })
+        .collect()
 }
 
-pub fn build_config_from_args(args: Cli) -> Box<RunConfiguration> {
+pub fn build_config_from_args(args: Cli) -> Result<Box<RunConfiguration>, NeatError> {
     // Takes in a bunch of args from a clap CLI and builds a config based on that. More CLI options
     // will need additional items entered here. To add them to the config, so they can be implemented.
 
@@ -355,31 +2757,65 @@ pub fn build_config_from_args(args: Cli) -> Box<RunConfiguration> {
     if args.reference != "".to_string() {
         config_builder.reference = args.reference.into();
     } else {
-        panic!("No reference specified");
+        return Err(NeatError::Config("No reference specified".to_string()))
     }
     // The default value works directly for the config builder and CLI handles the type checking
     config_builder.read_len = args.read_length;
     config_builder.coverage = args.coverage;
     // default is empty string, in which case the config builder controls the default
     if args.output_dir == "" {
-        config_builder.output_dir = env::current_dir().expect(
-            "Error finding current directory. Please specify --output-dir (-o) option."
-        )
+        config_builder.output_dir = env::current_dir().map_err(|error| NeatError::Config(format!(
+            "Error finding current directory ({}). Please specify --output-dir (-o) option.",
+            error
+        )))?
     } else {
         let output_path = Path::new(&args.output_dir);
-        check_create_dir(output_path);
+        check_create_dir(output_path)?;
         config_builder.output_dir = PathBuf::from(output_path);
     };
     // If this is unset, sets the default value of "neat_out" by CLI
     config_builder.output_prefix = args.output_file_prefix;
+    config_builder.threads = args.threads;
+    config_builder.max_memory = args.max_memory.as_deref().map(parse_memory_size).transpose()?;
+    config_builder.throttle_mb_per_sec = args.throttle_mb_per_sec;
+    config_builder.read_name_format = ReadNameFormat::parse(&args.read_name_format)?;
+    config_builder.read_name_prefix = args.read_name_prefix;
+    config_builder.read_comment_tags = args.read_comment_tags;
+    config_builder.truth_comment_tags = args.truth_comment_tags;
+    config_builder.quality_offset = args.quality_offset;
+    config_builder.max_quality = args.max_quality;
+    config_builder.global_shuffle = args.global_shuffle;
+    config_builder.sort_reads = args.sort_reads;
+    config_builder.sample_name = args.sample_name;
+    if let Some(sample_sex) = &args.sample_sex {
+        config_builder.sample_sex = Sex::from_name(sample_sex)?;
+    }
+    config_builder.library = args.library;
+    if let Some(platform) = args.platform {
+        config_builder.platform = platform.to_uppercase();
+    }
+    config_builder.platform_unit = args.platform_unit;
+    if let Some(instrument) = args.instrument {
+        config_builder.instrument = instrument;
+    }
+    if !args.temp_dir.is_empty() {
+        config_builder.temp_dir = Some(PathBuf::from(args.temp_dir));
+    }
+    config_builder.reference_cache = args.reference_cache.map(PathBuf::from);
+    config_builder.sequence_dictionary = args.sequence_dictionary;
+    config_builder.primary_assembly_only = args.primary_assembly_only;
+    config_builder.only_contig = args.only_contig;
+    config_builder.contig_seed = args.contig_seed;
+    config_builder.region = args.region;
+    config_builder.random_seed_words = args.random_seed_words;
     // To set a minimum mutation rate, such as for debugging, or for small datasets, use this
     if !args.minimum_mutations.is_none() {
         let input_min_muts = args.minimum_mutations.unwrap() as usize;
         config_builder.minimum_mutations = Some(input_min_muts);
     }
     // Wraps things in a Box to move this object to the heap
-    let _ = &config_builder.check_and_print_config();
-    Box::new(config_builder.build())
+    config_builder.check_and_print_config()?;
+    Ok(Box::new(config_builder.build()))
 }
 
 #[cfg(test)]
@@ -393,19 +2829,110 @@ mod tests {
             read_len: 100,
             coverage: 22,
             mutation_rate: 0.09,
+            somatic_mutation_rate: Some(0.000001),
+            somatic_signatures: None,
+            signature_exposures: Vec::new(),
             ploidy: 3,
+            contig_ploidy: Vec::new(),
+            snp_distance: None,
+            plasmid_copy_number: Vec::new(),
+            quasispecies_frequencies: Vec::new(),
+            quasispecies_diversity: DEFAULT_QUASISPECIES_DIVERSITY,
+            timepoints: None,
+            timepoint_mutations: DEFAULT_TIMEPOINT_MUTATIONS,
+            timepoint_drift: DEFAULT_TIMEPOINT_DRIFT,
+            sample_haplotypes: Vec::new(),
+            genotype_frequencies: Vec::new(),
+            allele_frequency: None,
             paired_ended: true,
             fragment_mean: Option::from(333.0),
             fragment_st_dev: Option::from(33.0),
+            fragment_min: None,
+            fragment_max: None,
+            singleton_rate: Some(0.01),
+            discarded_pair_rate: None,
+            adapter_read_through: false,
+            duplex_family_size: None,
+            cell_free_fragments: false,
+            ffpe_artifact_rate: None,
+            ffpe_artifact_fraction: DEFAULT_ARTIFACT_FRACTION,
+            tumor_fraction: None,
+            target_bed: None,
+            capture_model: false,
+            capture_shoulder: 200,
+            capture_efficiency_st_dev: 0.35,
+            capture_gc_dropout: 1.0,
+            low_complexity_error_fold: None,
+            low_complexity_bed: None,
+            quality_crash_rate: None,
+            qc_fail_rate: None,
+            molecule_error_rate: None,
+            error_profile: None,
+            model_dir: None,
+            coverage_profile: None,
+            coverage_bam: None,
+            gene_annotation: None,
+            exon_mutation_rate: None,
+            intron_mutation_rate: None,
+            intergenic_mutation_rate: None,
+            known_sites_vcf: None,
+            known_site_fraction: 0.9,
             produce_fastq: false,
             produce_bam: true,
+            kmer_spectrum: None,
+            truth_pileup: false,
+            genotype_calls: false,
+            produce_benchmark_files: false,
+            bgzip_truth_files: false,
+            validate_truth: false,
+            liftover_chain: None,
             produce_fasta: true,
+            haplotype_fasta: false,
+            produce_edit_list: false,
             produce_vcf: true,
             rng_seed: None,
+            random_seed_words: None,
             overwrite_output: true,
             minimum_mutations: None,
             output_dir: PathBuf::from("/my/my"),
-            output_prefix: String::from("Hey.hey")
+            output_prefix: String::from("Hey.hey"),
+            read_name_format: ReadNameFormat::Illumina,
+            read_name_prefix: String::from("neat_generated"),
+            duplicate_rate: None,
+            optical_duplicate_fraction: 0.0,
+            optical_duplicate_distance: 100,
+            tile_width: 25000,
+            sample_index: None,
+            index_pool: Vec::new(),
+            index_hopping_rate: None,
+            read_comment_tags: vec![String::from("BC:Z:ACGTAC")],
+            truth_comment_tags: true,
+            quality_offset: 64,
+            max_quality: Some(40),
+            quality_trim_threshold: None,
+            global_shuffle: false,
+            sort_reads: false,
+            sample_name: String::from("tumor"),
+            sample_sex: Sex::Auto,
+            mt_copy_number: 200.0,
+            heteroplasmy_fractions: Vec::new(),
+            par_bed: None,
+            library: String::from("tumor_lib"),
+            platform: String::from("ILLUMINA"),
+            platform_unit: None,
+            instrument: String::from("NEAT"),
+            threads: 4,
+            max_memory: None,
+            throttle_mb_per_sec: None,
+            temp_dir: None,
+            reference_cache: None,
+            sequence_dictionary: None,
+            primary_assembly_only: false,
+            only_contig: None,
+            contig_seed: None,
+            region: None,
+            fill_gaps: false,
+            sweep: Vec::new(),
         };
 
         println!("{:?}", test_configuration);
@@ -413,6 +2940,7 @@ mod tests {
         assert_eq!(test_configuration.read_len, 100);
         assert_eq!(test_configuration.coverage, 22);
         assert_eq!(test_configuration.mutation_rate, 0.09);
+        assert_eq!(test_configuration.somatic_mutation_rate, Some(0.000001));
         assert_eq!(test_configuration.ploidy, 3);
         assert_eq!(test_configuration.paired_ended, true);
         assert_eq!(test_configuration.fragment_mean.unwrap(), 333.0);
@@ -437,29 +2965,27 @@ mod tests {
     #[test]
     fn test_read_config_yaml() {
         let yaml = String::from("config/neat_test.yml");
-        let test_config = read_config_yaml(yaml);
+        let test_config = read_config_yaml(yaml).unwrap();
         assert_eq!(test_config.reference, "test_data/ecoli.fa".to_string());
         assert_eq!(test_config.coverage, 3);
     }
 
     #[test]
-    #[should_panic]
     fn test_bad_yaml() {
         let yaml = String::from("fake_file.yml");
-        read_config_yaml(yaml);
+        assert!(matches!(read_config_yaml(yaml), Err(NeatError::Config(_))));
     }
 
     #[test]
-    #[should_panic]
     fn test_missing_ref() {
         let yaml = String::from("config/simple_template.yml");
-        read_config_yaml(yaml);
+        assert!(matches!(read_config_yaml(yaml), Err(NeatError::Config(_))));
     }
 
     #[test]
     fn test_creates_out_dir() {
         let yaml = String::from("config/neat_test_bad.yml");
-        read_config_yaml(yaml);
+        read_config_yaml(yaml).unwrap();
         assert!(Path::new("fake").is_dir());
         fs::remove_dir("fake").unwrap()
     }
@@ -467,26 +2993,57 @@ mod tests {
     #[test]
     fn test_command_line_inputs() {
         let args: Cli = Cli{
+            command: None,
             config: String::new(),
+            strict_config: false,
             reference: String::from("test_data/ecoli.fa"),
             output_dir: String::from("test_data"),
             log_level: String::from("Trace"),
             log_dest: String::new(),
             output_file_prefix: String::from("test"),
+            temp_dir: String::new(),
+            threads: 1,
+            max_memory: Some(String::from("2G")),
+            throttle_mb_per_sec: Some(50.0),
+            read_name_format: String::from("simple"),
+            read_name_prefix: String::from("neat_generated"),
+            read_comment_tags: Vec::new(),
+            truth_comment_tags: false,
+            quality_offset: 33,
+            max_quality: None,
+            global_shuffle: false,
+            sort_reads: false,
+            sample_name: None,
+            library: None,
+            platform: None,
+            platform_unit: None,
+            instrument: None,
+            sample_sex: None,
+            demo: false,
+            error_report: false,
+            replicates: None,
+            random_seed_words: None,
+            reference_cache: None,
+            sequence_dictionary: None,
+            primary_assembly_only: false,
+            only_contig: None,
+            contig_seed: None,
+            region: None,
             minimum_mutations: None,
             read_length: 150,
             coverage: 10,
         };
 
-        let test_config = build_config_from_args(args);
-        assert_eq!(test_config.reference, "test_data/ecoli.fa".to_string())
+        let test_config = build_config_from_args(args).unwrap();
+        assert_eq!(test_config.reference, "test_data/ecoli.fa".to_string());
+        assert_eq!(test_config.max_memory, Some(2 << 30));
+        assert_eq!(test_config.throttle_mb_per_sec, Some(50.0));
     }
 
     #[test]
-    #[should_panic]
     fn test_bad_config_builder() {
         let config = ConfigBuilder::new();
-        config.check_and_print_config();
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
     }
 
     #[test]
@@ -494,26 +3051,55 @@ mod tests {
         let mut config = ConfigBuilder::new();
         config.reference = Some("test_data/H1N1.fa".to_string());
         config.output_dir = PathBuf::from("contig/");
-        config.check_and_print_config();
+        config.check_and_print_config().unwrap();
         fs::remove_dir("contig").unwrap()
     }
 
     #[test]
-    #[should_panic]
     fn test_cl_missing_ref() {
         let args: Cli = Cli{
+            command: None,
             config: String::new(),
+            strict_config: false,
             reference: String::from(""),
             output_dir: String::from("test_dir"),
             log_level: String::from("Trace"),
             log_dest: String::new(),
             output_file_prefix: String::from("test"),
+            temp_dir: String::new(),
+            threads: 1,
+            max_memory: None,
+            throttle_mb_per_sec: None,
+            read_name_format: String::from("simple"),
+            read_name_prefix: String::from("neat_generated"),
+            read_comment_tags: Vec::new(),
+            truth_comment_tags: false,
+            quality_offset: 33,
+            max_quality: None,
+            global_shuffle: false,
+            sort_reads: false,
+            sample_name: None,
+            library: None,
+            platform: None,
+            platform_unit: None,
+            instrument: None,
+            sample_sex: None,
+            demo: false,
+            error_report: false,
+            replicates: None,
+            random_seed_words: None,
+            reference_cache: None,
+            sequence_dictionary: None,
+            primary_assembly_only: false,
+            only_contig: None,
+            contig_seed: None,
+            region: None,
             minimum_mutations: None,
             read_length: 150,
             coverage: 10,
         };
 
-        build_config_from_args(args);
+        assert!(matches!(build_config_from_args(args), Err(NeatError::Config(_))));
     }
 
     #[test]
@@ -521,7 +3107,7 @@ mod tests {
         let mut config = ConfigBuilder::new();
         config.reference = Option::from("test_data/H1N1.fa".to_string());
         config.overwrite_output = true;
-        config.check_and_print_config();
+        config.check_and_print_config().unwrap();
     }
 
     #[test]
@@ -529,15 +3115,15 @@ mod tests {
         let mut config = ConfigBuilder::new();
         config.reference = Option::from("test_data/H1N1.fa".to_string());
         config.paired_ended = true;
-        config.fragment_mean = Some(100.0);
+        config.fragment_mean = Some(300.0);
         config.fragment_st_dev = Some(10.0);
         // tests first branch of if statement for paired_ended & produce_fastq = true
-        config.check_and_print_config();
+        config.check_and_print_config().unwrap();
         // Checks the alternative pe = true, produce_fastq = false
         config.produce_fastq = false;
         // need to produce at least one file or check will panic
         config.produce_fasta = true;
-        config.check_and_print_config();
+        config.check_and_print_config().unwrap();
     }
 
     #[test]
@@ -545,88 +3131,792 @@ mod tests {
         let mut config = ConfigBuilder::new();
         config.reference = Some("test_data/H1N1.fa".to_string());
         config.produce_fasta = true;
-        config.check_and_print_config();
+        config.check_and_print_config().unwrap();
         config.produce_vcf = true;
-        config.check_and_print_config();
+        config.check_and_print_config().unwrap();
         config.produce_bam= true;
-        config.check_and_print_config();
+        config.check_and_print_config().unwrap();
         // If it passes all the checks, we're good.
     }
 
     #[test]
-    #[should_panic]
     fn test_no_files() {
         let mut config = ConfigBuilder::new();
         config.reference = Some("test_data/H1N1.fa".to_string());
         config.produce_fastq = false;
-        config.check_and_print_config();
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
     }
 
     #[test]
-    #[should_panic]
     fn test_no_frag_mean_or_stdev() {
         let mut config = ConfigBuilder::new();
         config.reference = Some("test_data/H1N1.fa".to_string());
         // paired end set to true, by default, fragment mean and st dev are None
         config.paired_ended = true;
-        config.check_and_print_config();
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
     }
 
     #[test]
-    #[should_panic]
     fn test_no_frag_mean() {
         let mut config = ConfigBuilder::new();
         config.reference = Some("test_data/H1N1.fa".to_string());
         config.paired_ended = true;
         config.fragment_st_dev = Some(10.0);
-        config.check_and_print_config();
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_fragment_sanity() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.paired_ended = true;
+        config.fragment_mean = Some(150.0);
+        config.fragment_st_dev = Some(10.0);
+        // Fragments no longer than the reads only make sense reading into the adapters
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.adapter_read_through = true;
+        config.check_and_print_config().unwrap();
+        assert!(fragment_warning(150, 150.0).is_some());
+        assert!(fragment_warning(150, 300.0).is_none());
     }
 
     #[test]
-    #[should_panic]
     fn test_no_stdev() {
         let mut config = ConfigBuilder::new();
         config.reference = Some("test_data/H1N1.fa".to_string());
         config.paired_ended = true;
         config.fragment_mean = Some(100.0);
-        config.check_and_print_config();
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
     }
 
     #[test]
     fn no_output_dir_given() {
         let args: Cli = Cli{
+            command: None,
             config: String::new(),
+            strict_config: false,
             reference: String::from("test_data/H1N1.fa"),
             output_dir: String::new(),
             log_level: String::from("Trace"),
             log_dest: String::new(),
             output_file_prefix: String::from("test"),
+            temp_dir: String::new(),
+            threads: 1,
+            max_memory: None,
+            throttle_mb_per_sec: None,
+            read_name_format: String::from("simple"),
+            read_name_prefix: String::from("neat_generated"),
+            read_comment_tags: Vec::new(),
+            truth_comment_tags: false,
+            quality_offset: 33,
+            max_quality: None,
+            global_shuffle: false,
+            sort_reads: false,
+            sample_name: None,
+            library: None,
+            platform: None,
+            platform_unit: None,
+            instrument: None,
+            sample_sex: None,
+            demo: false,
+            error_report: false,
+            replicates: None,
+            random_seed_words: None,
+            reference_cache: None,
+            sequence_dictionary: None,
+            primary_assembly_only: false,
+            only_contig: None,
+            contig_seed: None,
+            region: None,
             minimum_mutations: None,
             read_length: 150,
             coverage: 10,
         };
 
-        let config = build_config_from_args(args);
+        let config = build_config_from_args(args).unwrap();
         assert_eq!(env::current_dir().unwrap().as_path(), config.output_dir);
     }
 
     #[test]
     fn test_minimum_mutations_and_others() {
         let args: Cli = Cli{
+            command: None,
             config: String::new(),
+            strict_config: false,
             reference: String::from("test_data/H1N1.fa"),
             output_dir: String::new(),
             log_level: String::from("Trace"),
             log_dest: String::new(),
             output_file_prefix: String::from("test"),
+            temp_dir: String::new(),
+            threads: 1,
+            max_memory: None,
+            throttle_mb_per_sec: None,
+            read_name_format: String::from("simple"),
+            read_name_prefix: String::from("neat_generated"),
+            read_comment_tags: Vec::new(),
+            truth_comment_tags: false,
+            quality_offset: 33,
+            max_quality: None,
+            global_shuffle: false,
+            sort_reads: false,
+            sample_name: None,
+            library: None,
+            platform: None,
+            platform_unit: None,
+            instrument: None,
+            sample_sex: None,
+            demo: false,
+            error_report: false,
+            replicates: None,
+            random_seed_words: None,
+            reference_cache: None,
+            sequence_dictionary: None,
+            primary_assembly_only: false,
+            only_contig: None,
+            contig_seed: None,
+            region: None,
             minimum_mutations: Some(10),
             read_length: 120,
             coverage: 13,
         };
 
-        let config = build_config_from_args(args);
+        let config = build_config_from_args(args).unwrap();
         assert_eq!(Some(10), config.minimum_mutations);
         assert_eq!(120, config.read_len);
         assert_eq!(13, config.coverage);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_bad_read_comment_tags() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.read_comment_tags = vec!["BC:Z:ACGTAC".to_string()];
+        config.check_and_print_config().unwrap();
+        config.read_comment_tags.push("barcode".to_string());
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.read_comment_tags.clear();
+        config.read_name_prefix = "two words".to_string();
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_default_sample_name() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        assert_eq!(config.sample_name(), "normal");
+        config.somatic_mutation_rate = Some(0.001);
+        let read_group = config.build().read_group();
+        assert_eq!(read_group.sample, "tumor");
+        assert_eq!(read_group.library, "tumor_lib");
+    }
+
+    #[test]
+    fn test_run_metadata() {
+        let yaml = "test_data/test_run_metadata.yml";
+        fs::write(
+            yaml,
+            "reference: test_data/H1N1.fa\nplatform: element\nplatform_unit: HXXXXDSX2.4\n\
+            instrument: A00123\n",
+        ).unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.read_group().platform, "ELEMENT");
+        let run = config.illumina_run();
+        assert_eq!(
+            (run.instrument.as_str(), run.flowcell.as_str(), run.lane),
+            ("A00123", "HXXXXDSX2", 4)
+        );
+
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.platform = "NANOPORE".to_string();
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.platform = DEFAULT_PLATFORM.to_string();
+        config.instrument = "A00:123".to_string();
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_signature_config() {
+        let yaml = "test_data/test_signatures.yml";
+        fs::write(
+            yaml,
+            "reference: test_data/H1N1.fa\nsomatic_mutation_rate: 0.001\n\
+            somatic_signatures: test_data/H1N1.fa\nsignature_exposures: {SBS1: 0.25, SBS5: 3}\n",
+        ).unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        let config = config.unwrap();
+        assert_eq!(
+            config.signature_exposures,
+            vec![("SBS1".to_string(), 0.25), ("SBS5".to_string(), 3.0)]
+        );
+
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.signature_exposures = vec![("SBS1".to_string(), 1.0)];
+        // Exposures need signatures, and signatures need a somatic layer
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.somatic_signatures = Some("test_data/H1N1.fa".to_string());
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.somatic_mutation_rate = Some(0.001);
+        assert!(config.check_and_print_config().is_ok());
+        config.signature_exposures.push(("SBS5".to_string(), -1.0));
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_fragment_bounds() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.fragment_min = Some(100);
+        // Single ended reads have no fragments to bound
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.paired_ended = true;
+        config.fragment_mean = Some(300.0);
+        config.fragment_st_dev = Some(30.0);
+        assert!(config.check_and_print_config().is_ok());
+        assert_eq!(config.build().fragment_bounds(), (100, usize::MAX));
+
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.paired_ended = true;
+        config.fragment_mean = Some(300.0);
+        config.fragment_st_dev = Some(30.0);
+        config.fragment_min = Some(400);
+        config.fragment_max = Some(200);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.fragment_min = Some(0);
+        config.fragment_max = None;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_quasispecies_config() {
+        let yaml = "test_data/test_quasispecies.yml";
+        fs::write(
+            yaml,
+            "reference: test_data/H1N1.fa\nquasispecies_frequencies: [0.7, 0.2, 0.1]\n\
+            quasispecies_diversity: 0.02\n",
+        ).unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.quasispecies_frequencies, vec![0.7, 0.2, 0.1]);
+        assert_eq!(config.quasispecies_diversity, 0.02);
+        // A haplotype per frequency
+        assert_eq!(config.ploidy, 3);
+
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.quasispecies_frequencies = vec![1.0];
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.quasispecies_frequencies = vec![0.5, 0.0];
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.quasispecies_frequencies = vec![0.5, 0.5];
+        config.quasispecies_diversity = 1.0;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.quasispecies_diversity = 0.02;
+        config.somatic_mutation_rate = Some(0.001);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.somatic_mutation_rate = None;
+        assert!(config.check_and_print_config().is_ok());
+    }
+
+    #[test]
+    fn test_timepoints_config() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.timepoints = Some(1);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.timepoints = Some(4);
+        assert!(config.check_and_print_config().is_ok());
+        config.timepoint_drift = -0.1;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.timepoint_drift = 0.0;
+        config.quasispecies_frequencies = vec![0.5, 0.5];
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+
+        let yaml = "test_data/test_timepoints.yml";
+        fs::write(
+            yaml,
+            "reference: test_data/H1N1.fa\noutput_dir: config_timepoints_out\ntimepoints: 3\n\
+            sweep:\n  coverage: [5, 10]\n",
+        ).unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        fs::remove_dir_all("config_timepoints_out").unwrap();
+        assert!(matches!(config, Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_sample_haplotypes_config() {
+        let yaml = "test_data/test_sample_haplotypes.yml";
+        fs::write(
+            yaml,
+            "reference: test_data/H1N1.fa\n\
+            sample_haplotypes: [test_data/H1N1.fa, test_data/H1N1.fa, test_data/H1N1.fa]\n",
+        ).unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        // A copy of the genome per haplotype
+        assert_eq!(config.unwrap().ploidy, 3);
+
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.sample_haplotypes = vec!["test_data/missing.fa".to_string()];
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.sample_haplotypes = vec!["test_data/H1N1.fa".to_string()];
+        assert!(config.check_and_print_config().is_ok());
+        config.somatic_mutation_rate = Some(0.001);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_liftover_config() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.liftover_chain = Some("test_data/missing.chain".to_string());
+        config.produce_vcf = true;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        // Any file will do, since it is only read by the run
+        config.liftover_chain = Some("test_data/H1N1.fa".to_string());
+        assert!(config.check_and_print_config().is_ok());
+        config.produce_vcf = false;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_capture_model() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.capture_model = true;
+        // Nothing to capture without targets
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.target_bed = Some("test_data/H1N1.fa".to_string());
+        assert!(config.check_and_print_config().is_ok());
+        config.capture_gc_dropout = -1.0;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_low_complexity_config() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.low_complexity_bed = Some("test_data/H1N1.fa".to_string());
+        // The regions do nothing without a fold
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.low_complexity_error_fold = Some(0.5);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.low_complexity_error_fold = Some(5.0);
+        assert!(config.check_and_print_config().is_ok());
+        config.quality_crash_rate = Some(1.5);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.quality_crash_rate = Some(0.1);
+        assert!(config.check_and_print_config().is_ok());
+        config.qc_fail_rate = Some(-0.5);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.qc_fail_rate = Some(0.02);
+        assert!(config.check_and_print_config().is_ok());
+        config.molecule_error_rate = Some(-0.1);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.molecule_error_rate = Some(0.001);
+        assert!(config.check_and_print_config().is_ok());
+    }
+
+    #[test]
+    fn test_max_memory() {
+        let yaml = "test_data/test_max_memory.yml";
+        fs::write(yaml, "reference: test_data/H1N1.fa\nmax_memory: 512M\n").unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        assert_eq!(config.unwrap().max_memory, Some(512 << 20));
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.max_memory = Some(0);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_config_keys() {
+        // The template sets every key there is
+        let template_keys: Vec<&str> = config_keys().collect();
+        assert_eq!(template_keys.len(), 109);
+        assert_eq!(template_keys[0], "reference");
+        // ... and each of them has a setting. Null is never ".", so every arm is reached.
+        for key in template_keys {
+            let result = set_config_value(&mut ConfigBuilder::new(), key, &Value::Null);
+            let unhandled = matches!(&result, Err(NeatError::Config(message))
+                if message.ends_with("has no setting"));
+            assert!(!unhandled, "{} is in the template but has no setting", key);
+        }
+
+        assert_eq!(edit_distance("fragment_stdev", "fragment_st_dev"), 1);
+        assert_eq!(nearest_config_key("fragment_stdev"), Some("fragment_st_dev"));
+        assert_eq!(nearest_config_key("coverge"), Some("coverage"));
+        assert_eq!(nearest_config_key("colour"), None);
+
+        let yaml = "test_data/test_config_keys.yml";
+        fs::write(yaml, "reference: test_data/H1N1.fa\nfragment_stdev: 30\n").unwrap();
+        let lenient = read_config_yaml(yaml.to_string());
+        let strict = read_config_yaml_checked(yaml.to_string(), true);
+        fs::remove_file(yaml).unwrap();
+        assert!(lenient.is_ok());
+        let Err(NeatError::Config(message)) = strict else { panic!("Unknown key accepted") };
+        assert!(message.contains("fragment_stdev (did you mean fragment_st_dev?)"), "{}", message);
+    }
+
+    #[test]
+    fn test_numeric_bounds() {
+        let config = || {
+            let mut config = ConfigBuilder::new();
+            config.reference = Some("test_data/H1N1.fa".to_string());
+            config
+        };
+        let error = |config: ConfigBuilder| match config.check_and_print_config() {
+            Err(NeatError::Config(message)) => message,
+            other => panic!("Out of range value accepted: {:?}", other),
+        };
+        let mut short_reads = config();
+        short_reads.read_len = 10;
+        assert_eq!(error(short_reads), "read_len must be at least 20, got 10");
+        let mut no_coverage = config();
+        no_coverage.coverage = 0;
+        assert_eq!(error(no_coverage), "coverage must be at least 1.");
+        let mut high_rate = config();
+        high_rate.mutation_rate = 1.5;
+        assert_eq!(error(high_rate), "mutation_rate must be between 0 and 1, got 1.5");
+        let mut negative_rate = config();
+        negative_rate.somatic_mutation_rate = Some(-0.01);
+        assert!(error(negative_rate).starts_with("somatic_mutation_rate must be between 0 and 1"));
+        let mut no_copies = config();
+        no_copies.ploidy = 0;
+        assert_eq!(error(no_copies), "ploidy must be at least 1.");
+        let mut flat_fragments = config();
+        flat_fragments.fragment_st_dev = Some(0.0);
+        assert_eq!(error(flat_fragments), "fragment_st_dev must be more than 0, got 0");
+        let mut fine = config();
+        fine.read_len = 20;
+        fine.mutation_rate = 1.0;
+        assert!(fine.check_and_print_config().is_ok());
+    }
+
+    #[test]
+    fn test_throttle_mb_per_sec() {
+        let yaml = "test_data/test_throttle_mb_per_sec.yml";
+        fs::write(yaml, "reference: test_data/H1N1.fa\nthrottle_mb_per_sec: 2.5\n").unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        assert_eq!(config.unwrap().throttle_mb_per_sec, Some(2.5));
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.throttle_mb_per_sec = Some(0.0);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_contig_ploidy() {
+        let yaml = "test_data/test_contig_ploidy.yml";
+        fs::write(
+            yaml,
+            "reference: test_data/H1N1.fa\nploidy: 4\ncontig_ploidy: {H1N1_PB2: 3, 21: 5}\n\
+            haplotype_fasta: true\nproduce_fastq: false\n",
+        ).unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        let config = config.unwrap();
+        assert_eq!(
+            config.contig_ploidy,
+            vec![("H1N1_PB2".to_string(), 3), ("21".to_string(), 5)]
+        );
+        // A fasta per copy is output enough on its own
+        assert!(config.haplotype_fasta);
+    }
+
+    #[test]
+    fn test_clonal_isolate() {
+        let yaml = "test_data/test_clonal_isolate.yml";
+        fs::write(
+            yaml,
+            "reference: test_data/H1N1.fa\nploidy: 1\nsnp_distance: 12\n\
+            plasmid_copy_number: {H1N1_NS: 8, H1N1_MP: 2.5}\n",
+        ).unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.snp_distance, Some(12));
+        assert_eq!(
+            config.plasmid_copy_number,
+            vec![("H1N1_NS".to_string(), 8.0), ("H1N1_MP".to_string(), 2.5)]
+        );
+
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.output_dir = PathBuf::from("clonal_isolate_out");
+        config.snp_distance = Some(12);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.ploidy = 1;
+        // Any file will do, since it is never read
+        config.known_sites_vcf = Some("test_data/H1N1.fa".to_string());
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.known_sites_vcf = None;
+        config.plasmid_copy_number = vec![("H1N1_NS".to_string(), 0.0)];
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.plasmid_copy_number = vec![("H1N1_NS".to_string(), 8.0)];
+        config.check_and_print_config().unwrap();
+        fs::remove_dir_all("clonal_isolate_out").unwrap();
+    }
+
+    #[test]
+    fn test_validate_truth() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.validate_truth = true;
+        // There is no vcf to check
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.produce_benchmark_files = true;
+        assert!(config.check_and_print_config().is_ok());
+    }
+
+    #[test]
+    fn test_duplicate_rate() {
+        let yaml = "test_data/test_duplicate_rate.yml";
+        fs::write(yaml, "reference: test_data/H1N1.fa
+duplicate_rate: 0.2
+optical_duplicate_fraction: 0.3
+optical_duplicate_distance: 2500
+tile_width: 30000
+")
+            .unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        let config = config.unwrap();
+        assert_eq!(config.duplicate_rate, Some(0.2));
+        assert_eq!(config.optical_duplicate_fraction, 0.3);
+        assert_eq!(
+            config.flowcell_geometry(),
+            FlowcellGeometry { tile_width: 30000, optical_distance: Some(2500) }
+        );
+        // Runs without duplicates keep clusters packed
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        assert_eq!(config.build().flowcell_geometry(), FlowcellGeometry::default());
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.optical_duplicate_fraction = 0.5;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.duplicate_rate = Some(0.1);
+        assert!(config.check_and_print_config().is_ok());
+        // No room on the tile for a cluster and its optical duplicates
+        config.tile_width = 200;
+        assert!(config.check_and_print_config().is_err());
+        config.tile_width = 25000;
+        config.duplicate_rate = Some(1.0);
+        assert!(config.check_and_print_config().is_err());
+    }
+
+    #[test]
+    fn test_rng_seed() {
+        let yaml = "test_data/test_rng_seed.yml";
+        for (seed, canonical) in [("12345", "12345"), ("\"12345\"", "12345"),
+            ("\"  Hello \\t Cruel   World \"", "Hello Cruel World")]
+        {
+            fs::write(yaml, format!("reference: test_data/H1N1.fa\nrng_seed: {}\n", seed))
+                .unwrap();
+            let config = read_config_yaml(yaml.to_string());
+            fs::remove_file(yaml).unwrap();
+            assert_eq!(config.unwrap().rng_seed.as_deref(), Some(canonical), "{}", seed);
+        }
+        fs::write(yaml, "reference: test_data/H1N1.fa\nrng_seed: 1.5\n").unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        assert!(matches!(config, Err(NeatError::Config(_))));
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.rng_seed = Some(" \t".to_string());
+        assert!(config.check_and_print_config().is_err());
+        // Word seeds are for runs without a seed
+        config.random_seed_words = Some(4);
+        config.rng_seed = Some("Hello".to_string());
+        assert!(config.check_and_print_config().is_err());
+        config.rng_seed = None;
+        config.check_and_print_config().unwrap();
+        config.random_seed_words = Some(0);
+        assert!(config.check_and_print_config().is_err());
+    }
+
+    #[test]
+    fn test_sample_index() {
+        let yaml = "test_data/test_sample_index.yml";
+        fs::write(yaml, "reference: test_data/H1N1.fa
+sample_index: AACCGGTT+ACGTACGT
+index_pool: [TTTTTTTT+GGGGGGGG, CCCCCCCC+AAAAAAAA]
+index_hopping_rate: 0.02
+")
+            .unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        let config = config.unwrap();
+        let model = config.index_model().unwrap();
+        assert_eq!(model.sample.to_string(), "AACCGGTT+ACGTACGT");
+        assert_eq!(model.pool.len(), 2);
+        assert_eq!(model.hopping_rate, 0.02);
+        assert_eq!(config.fastq_format().sample_index.as_deref(), Some("AACCGGTT+ACGTACGT"));
+
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.index_hopping_rate = Some(0.1);
+        // Nothing to hop from
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.sample_index = Some(SampleIndex::parse("AACCGGTT").unwrap());
+        // Nothing to hop to
+        assert!(config.check_and_print_config().is_err());
+        config.index_pool = vec![SampleIndex::parse("TTTTTTTT+GGGGGGGG").unwrap()];
+        // A dual index in a pool of single indexes
+        assert!(config.check_and_print_config().is_err());
+        config.index_pool = vec![SampleIndex::parse("TTTTTTTT").unwrap()];
+        assert!(config.check_and_print_config().is_ok());
+        config.index_hopping_rate = Some(1.5);
+        assert!(config.check_and_print_config().is_err());
+    }
+
+    #[test]
+    fn test_genotype_frequencies() {
+        let yaml = "test_data/test_genotype_frequencies.yml";
+        fs::write(yaml, "reference: test_data/H1N1.fa
+genotype_frequencies: {1: 0.6, 2: 0.4}
+")
+            .unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        let config = config.unwrap();
+        assert_eq!(
+            config.genotype_model(),
+            Some(GenotypeModel::Dosages(vec![(1, 0.6), (2, 0.4)]))
+        );
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.allele_frequency = Some(0.2);
+        assert!(config.check_and_print_config().is_ok());
+        assert_eq!(config.build().genotype_model(), Some(GenotypeModel::HardyWeinberg(0.2)));
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.allele_frequency = Some(1.5);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.allele_frequency = Some(0.2);
+        config.genotype_frequencies = vec![(1, 1.0)];
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.allele_frequency = None;
+        config.genotype_frequencies = vec![(0, 1.0)];
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.genotype_frequencies = vec![(2, 0.0)];
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_sort_reads() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.sort_reads = true;
+        assert!(config.check_and_print_config().is_ok());
+        // Sorted and shuffled across contigs at once makes no sense
+        config.global_shuffle = true;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_sample_sex() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        assert_eq!(config.sample_sex, Sex::Auto);
+        config.par_bed = Some("test_data/H1N1.fa".to_string());
+        assert!(config.check_and_print_config().is_ok());
+        // Only male samples have PARs
+        config.sample_sex = Sex::Female;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.par_bed = None;
+        config.mt_copy_number = 0.0;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.mt_copy_number = 200.0;
+        config.heteroplasmy_fractions = vec![(0.05, 2.0), (1.0, 1.0)];
+        assert!(config.check_and_print_config().is_ok());
+        config.heteroplasmy_fractions = vec![(0.0, 1.0)];
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.heteroplasmy_fractions = vec![(0.5, 0.0)];
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_bad_pair_loss_rates() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.singleton_rate = Some(0.1);
+        // Single ended reads have no mates to lose
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.paired_ended = true;
+        config.fragment_mean = Some(300.0);
+        config.fragment_st_dev = Some(30.0);
+        assert!(config.check_and_print_config().is_ok());
+        config.discarded_pair_rate = Some(0.95);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.singleton_rate = Some(-0.1);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_cell_free_preset() {
+        let yaml = "test_data/test_cfdna.yml";
+        fs::write(
+            yaml,
+            "reference: test_data/H1N1.fa\ncoverage: 500\npreset: cfdna\n\
+            somatic_mutation_rate: 0.001\ntumor_fraction: 0.005\n",
+        ).unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        fs::remove_file(yaml).unwrap();
+        let config = config.unwrap();
+        // Keys in the file win over the preset, wherever the preset is in the file
+        assert_eq!(config.coverage, 500);
+        assert!(config.paired_ended && config.cell_free_fragments && config.adapter_read_through);
+        assert_eq!(config.fragment_mean, Some(167.0));
+        assert_eq!(config.tumor_fraction, Some(0.005));
+
+        let mut config = ConfigBuilder::new();
+        assert!(config.apply_preset("wgs").is_err());
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.tumor_fraction = Some(0.01);
+        // A tumor fraction needs a tumor
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.somatic_mutation_rate = Some(0.001);
+        assert!(config.check_and_print_config().is_ok());
+        config.tumor_fraction = Some(0.0001);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.tumor_fraction = None;
+        config.cell_free_fragments = true;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_read_sweep() {
+        let yaml = "test_data/test_sweep.yml";
+        let sweep = "reference: test_data/H1N1.fa\noutput_dir: config_sweep_out\n\
+            sweep:\n  coverage: [5, 10]\n  mutation_rate: [0.001, 0.01, 0.1]\n";
+        fs::write(yaml, sweep).unwrap();
+        let config = read_config_yaml(yaml.to_string());
+        let config = config.unwrap();
+        assert_eq!(config.sweep.len(), 6);
+        let point = &config.sweep[4];
+        assert_eq!(point.label, "coverage-10_mutation_rate-0.01");
+        assert_eq!((point.config.coverage, point.config.mutation_rate), (10, 0.01));
+        assert_eq!(point.config.output_dir, PathBuf::from("config_sweep_out/coverage-10_mutation_rate-0.01"));
+        assert!(point.config.sweep.is_empty());
+
+        fs::write(yaml, "reference: test_data/H1N1.fa\nsweep:\n  reference: [test_data/ecoli.fa]\n").unwrap();
+        assert!(matches!(read_config_yaml(yaml.to_string()), Err(NeatError::Config(_))));
+        fs::write(yaml, "reference: test_data/H1N1.fa\nsweep:\n  coverage: 5\n").unwrap();
+        assert!(matches!(read_config_yaml(yaml.to_string()), Err(NeatError::Config(_))));
+        fs::remove_file(yaml).unwrap();
+        fs::remove_dir_all("config_sweep_out").unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+// A coverage profile: the depth wanted over each window of the genome, read from a bedgraph (for
+// example one made from a real sample with `bedtools genomecov -bg` or mosdepth). Only the shape
+// of the profile is used. Its depths are scaled so that it averages the run's coverage, each
+// contig is covered to its deepest window, and fragments are then kept with the probability of
+// their window's depth over that, so the reads follow the profile's ups and downs. Windows the
+// profile doesn't list get no reads, as in a bedgraph.
+
+use std::collections::HashMap;
+use std::fs;
+use super::errors::NeatError;
+
+// Windows deeper than this many times the run's coverage are capped. Such spikes are mostly
+// collapsed repeats, and every contig is covered to its deepest window, so left alone they would
+// multiply the work for a handful of reads.
+const MAX_PROFILE_FOLD: f64 = 5.0;
+
+// (start, end, depth) windows of each contig.
+pub type DepthWindows = HashMap<String, Vec<(usize, usize, f64)>>;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoverageProfile {
+    // windows: Sorted, non overlapping (start, end, depth) spans of each contig, with the depths
+    //     scaled to the run's coverage.
+    windows: DepthWindows,
+}
+
+impl CoverageProfile {
+    pub fn from_windows(mut windows: DepthWindows, coverage: usize) -> Result<Self, NeatError> {
+        // Takes:
+        // windows: (start, end, depth) spans of each contig, in any order, at any depth.
+        // coverage: The average depth the profile is scaled to.
+        // Returns:
+        // The scaled profile, or an error if windows overlap or the profile has no depth at all.
+        let mut total_bases = 0.0;
+        let mut total_depth = 0.0;
+        for (contig, spans) in windows.iter_mut() {
+            spans.sort_by_key(|(start, _, _)| *start);
+            for pair in spans.windows(2) {
+                if pair[1].0 < pair[0].1 {
+                    return Err(NeatError::BedParse(format!(
+                        "overlapping coverage profile windows on {} at {}", contig, pair[1].0
+                    )))
+                }
+            }
+            for (start, end, depth) in spans.iter() {
+                total_bases += (end - start) as f64;
+                total_depth += (end - start) as f64 * depth;
+            }
+        }
+        if total_depth <= 0.0 {
+            return Err(NeatError::BedParse("coverage profile has no depth".to_string()))
+        }
+        let scale = coverage as f64 / (total_depth / total_bases);
+        let max_depth = coverage as f64 * MAX_PROFILE_FOLD;
+        for spans in windows.values_mut() {
+            for (_, _, depth) in spans.iter_mut() {
+                *depth = (*depth * scale).min(max_depth);
+            }
+        }
+        Ok(CoverageProfile { windows })
+    }
+
+    pub fn depth(&self, contig: &str, position: usize) -> f64 {
+        // The depth wanted at this base, 0 if no window covers it.
+        let Some(spans) = self.windows.get(contig) else { return 0.0 };
+        let index = spans.partition_point(|(start, _, _)| *start <= position);
+        match index.checked_sub(1).map(|index| spans[index]) {
+            Some((_, end, depth)) if position < end => depth,
+            _ => 0.0,
+        }
+    }
+
+    pub fn max_depth(&self, contig: &str) -> f64 {
+        // The depth of the deepest window of the contig, which it is covered to before thinning.
+        self.windows
+            .get(contig)
+            .map(|spans| spans.iter().map(|(_, _, depth)| *depth).fold(0.0, f64::max))
+            .unwrap_or(0.0)
+    }
+}
+
+pub fn read_bedgraph(
+    path: &str,
+    contig_lengths: &HashMap<String, usize>,
+) -> Result<DepthWindows, NeatError> {
+    // Takes:
+    // path: A bedgraph: contig, start, end and depth, tab separated. Header, track and comment
+    //     lines are skipped.
+    // contig_lengths: The length of each contig of the reference. Windows on other contigs are
+    //     dropped, and windows running past the end of a contig are cut short.
+    // Returns:
+    // The (start, end, depth) windows of each contig, or an error if a line doesn't parse.
+    let text = fs::read_to_string(path)?;
+    let mut windows = DepthWindows::new();
+    for (line_number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || ["#", "track", "browser"].iter().any(|skip| line.starts_with(skip)) {
+            continue
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let bad_line = || NeatError::BedParse(
+            format!("{} line {}: {:?}", path, line_number + 1, line)
+        );
+        if fields.len() < 4 {
+            return Err(bad_line())
+        }
+        let start: usize = fields[1].trim().parse().map_err(|_| bad_line())?;
+        let end: usize = fields[2].trim().parse().map_err(|_| bad_line())?;
+        let depth: f64 = fields[3].trim().parse().map_err(|_| bad_line())?;
+        if end <= start || !depth.is_finite() || depth < 0.0 {
+            return Err(bad_line())
+        }
+        let Some(length) = contig_lengths.get(fields[0]) else { continue };
+        let end = end.min(*length);
+        if start < end {
+            windows.entry(fields[0].to_string()).or_default().push((start, end, depth));
+        }
+    }
+    Ok(windows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage_profile() {
+        let windows = HashMap::from([
+            ("chr1".to_string(), vec![(100, 200, 30.0), (0, 100, 10.0)]),
+            ("chr2".to_string(), vec![(0, 200, 0.0)]),
+        ]);
+        // Averages 10 over the 400 bases, so is scaled down by 2 to average 5.
+        let profile = CoverageProfile::from_windows(windows, 5).unwrap();
+        assert_eq!(profile.depth("chr1", 50), 5.0);
+        assert_eq!(profile.depth("chr1", 150), 15.0);
+        assert_eq!(profile.depth("chr1", 200), 0.0);
+        assert_eq!(profile.depth("chr2", 50), 0.0);
+        assert_eq!(profile.depth("chr3", 50), 0.0);
+        assert_eq!(profile.max_depth("chr1"), 15.0);
+        assert_eq!(profile.max_depth("chr3"), 0.0);
+        // Spikes are capped
+        let spike = HashMap::from([
+            ("chr1".to_string(), vec![(0, 1000, 1.0), (1000, 1001, 1e6)]),
+        ]);
+        let profile = CoverageProfile::from_windows(spike, 10).unwrap();
+        assert_eq!(profile.max_depth("chr1"), 10.0 * MAX_PROFILE_FOLD);
+
+        let overlapping = HashMap::from([
+            ("chr1".to_string(), vec![(0, 100, 1.0), (50, 150, 1.0)]),
+        ]);
+        assert!(CoverageProfile::from_windows(overlapping, 10).is_err());
+        let empty = HashMap::from([("chr1".to_string(), vec![(0, 100, 0.0)])]);
+        assert!(CoverageProfile::from_windows(empty, 10).is_err());
+    }
+
+    #[test]
+    fn test_read_bedgraph() {
+        let path = "test_data/test_profile.bedgraph";
+        fs::write(
+            path,
+            "track type=bedGraph\nchr1\t0\t100\t12.5\nchr1\t100\t250\t3\nchrUn\t0\t10\t1\n"
+        ).unwrap();
+        let contig_lengths = HashMap::from([("chr1".to_string(), 200)]);
+        let windows = read_bedgraph(path, &contig_lengths);
+        fs::remove_file(path).unwrap();
+        assert_eq!(
+            windows.unwrap(),
+            HashMap::from([("chr1".to_string(), vec![(0, 100, 12.5), (100, 200, 3.0)])])
+        );
+
+        let path = "test_data/test_bad_profile.bedgraph";
+        fs::write(path, "chr1\t0\t100\n").unwrap();
+        let result = read_bedgraph(path, &contig_lengths);
+        fs::remove_file(path).unwrap();
+        assert!(matches!(result, Err(NeatError::BedParse(_))));
+    }
+}
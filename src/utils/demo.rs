@@ -0,0 +1,61 @@
+// Demo mode: a small simulation with a reference built into the binary, so the tool can be tried
+// without any input files. The reference has a few contigs chosen to show the awkward cases:
+//   * demo_chr1: N gaps at the start and in the middle.
+//   * demo_chr2: carries the reverse complement of demo_chr1:601-900, and ends in Ns.
+//   * demo_short: shorter than a read, so it gets no reads.
+//   * demo_masked: a soft-masked (lowercase) stretch in the middle.
+
+use std::fs;
+use std::path::Path;
+use log::info;
+use super::config::{ConfigBuilder, RunConfiguration};
+use super::errors::NeatError;
+use super::file_tools::check_create_dir;
+
+const DEMO_REFERENCE: &str = include_str!("../../data/demo.fa");
+const DEMO_REFERENCE_NAME: &str = "demo_reference.fa";
+
+pub fn demo_config(output_dir: &Path) -> Result<Box<RunConfiguration>, NeatError> {
+    // Writes the demo reference into output_dir and returns a configuration that simulates
+    // paired ended reads, a vcf and a mutated fasta from it. Running the demo again replaces the
+    // previous output.
+    check_create_dir(output_dir)?;
+    let reference = output_dir.join(DEMO_REFERENCE_NAME);
+    fs::write(&reference, DEMO_REFERENCE)?;
+    info!("Demo reference written to {}", reference.display());
+
+    let mut config_builder = ConfigBuilder::new();
+    config_builder.reference = Some(reference.display().to_string());
+    config_builder.read_len = 100;
+    config_builder.coverage = 10;
+    // High enough that every contig gets a handful of variants to look at.
+    config_builder.mutation_rate = 0.005;
+    config_builder.paired_ended = true;
+    config_builder.fragment_mean = Some(300.0);
+    config_builder.fragment_st_dev = Some(30.0);
+    config_builder.produce_fasta = true;
+    config_builder.produce_vcf = true;
+    config_builder.rng_seed = Some("rusty neat demo".to_string());
+    config_builder.overwrite_output = true;
+    config_builder.output_dir = output_dir.to_path_buf();
+    config_builder.output_prefix = String::from("demo");
+    config_builder.check_and_print_config()?;
+    Ok(Box::new(config_builder.build()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::fasta_tools::read_fasta;
+
+    #[test]
+    fn test_demo_config() {
+        let config = demo_config(Path::new("demo_test")).unwrap();
+        let (sequences, contig_order) = read_fasta(&config.reference).unwrap();
+        fs::remove_dir_all("demo_test").unwrap();
+        assert_eq!(contig_order.len(), 4);
+        assert!(sequences.values().any(|sequence| sequence.len() < config.read_len));
+        assert!(sequences.values().any(|sequence| sequence.contains(&4)));
+        assert!(config.paired_ended && config.produce_vcf && config.produce_fasta);
+    }
+}
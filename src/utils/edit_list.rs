@@ -0,0 +1,189 @@
+// The edit list (produce_edit_list): the mutated fasta as a compact binary diff against the
+// reference, for runs that only want the mutation engine's output to drive another read
+// simulator. Only substitutions are simulated, so the mutated genome is the reference with some
+// of its bases swapped, and the list just records where and for what. Positions are stored as
+// the distance from the previous edit of the contig, as LEB128 varints, so a typical edit takes
+// two or three bytes. Applying the list to the reference (apply_edit_list) gives back the
+// mutated fasta.
+//
+// Layout (integers little endian):
+//     magic (8 bytes), number of contigs (u32), then for each contig in reference order:
+//     name length (u32), name, sequence length (u64), number of edits (u64), and the edits in
+//     position order, each the distance from the previous one's position (varint, from 0 for the
+//     first) followed by the new base (A, C, G, T or N).
+
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read, Write};
+use super::nucleotides::{base_to_u8, u8_to_base};
+
+// The last byte is the format version.
+const EDIT_LIST_MAGIC: &[u8; 8] = b"NEATDIF\x01";
+
+// A contig's name, length and edits, as (0-based position, new base) pairs in position order.
+pub type ContigEdits = (String, usize, Vec<(usize, u8)>);
+
+fn write_varint(writer: &mut impl Write, mut value: u64) -> io::Result<()> {
+    // Seven bits to a byte, low bits first, with the high bit set on every byte but the last.
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte])
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value)
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "varint too long in edit list"))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn write_edit_list(
+    writer: &mut impl Write,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    mutated_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+) -> io::Result<usize> {
+    // Takes:
+    // writer: Where to write the edit list.
+    // fasta_map: The reference, keyed by contig name.
+    // mutated_map: The reference with every variant applied.
+    // fasta_order: The contigs to write, in reference order.
+    // Returns:
+    // The number of edits written, or an error if there is a problem writing.
+    writer.write_all(EDIT_LIST_MAGIC)?;
+    writer.write_all(&(fasta_order.len() as u32).to_le_bytes())?;
+    let mut total = 0;
+    for contig in fasta_order {
+        let (reference, mutated) = (&fasta_map[contig], &mutated_map[contig]);
+        let edits: Vec<usize> = (0..reference.len())
+            .filter(|position| reference[*position] != mutated[*position])
+            .collect();
+        writer.write_all(&(contig.len() as u32).to_le_bytes())?;
+        writer.write_all(contig.as_bytes())?;
+        writer.write_all(&(reference.len() as u64).to_le_bytes())?;
+        writer.write_all(&(edits.len() as u64).to_le_bytes())?;
+        let mut previous = 0;
+        for position in &edits {
+            write_varint(writer, (position - previous) as u64)?;
+            writer.write_all(&[u8_to_base(mutated[*position]) as u8])?;
+            previous = *position;
+        }
+        total += edits.len();
+    }
+    Ok(total)
+}
+
+pub fn read_edit_list(reader: &mut impl Read) -> io::Result<Vec<ContigEdits>> {
+    // Reads an edit list back, as each contig's name, length and edits. A file that isn't an edit
+    // list, or is cut short, is an error.
+    let bad_data = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != EDIT_LIST_MAGIC {
+        return Err(bad_data("not a rusty-neat edit list"))
+    }
+    let num_contigs = read_u32(reader)? as usize;
+    let mut contigs = Vec::with_capacity(num_contigs);
+    for _ in 0..num_contigs {
+        let mut name = vec![0u8; read_u32(reader)? as usize];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8(name)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let length = read_u64(reader)? as usize;
+        let num_edits = read_u64(reader)? as usize;
+        let mut edits = Vec::with_capacity(num_edits.min(length));
+        let mut position = 0;
+        for _ in 0..num_edits {
+            position += read_varint(reader)? as usize;
+            let mut base = [0u8; 1];
+            reader.read_exact(&mut base)?;
+            if position >= length {
+                return Err(bad_data("edit past the end of its contig in edit list"))
+            }
+            edits.push((position, base_to_u8(base[0] as char)));
+        }
+        contigs.push((name, length, edits));
+    }
+    Ok(contigs)
+}
+
+pub fn apply_edit_list(
+    fasta_map: &HashMap<String, Vec<u8>>,
+    edit_list: &[ContigEdits],
+) -> io::Result<HashMap<String, Vec<u8>>> {
+    // The contigs of the edit list with their edits applied to the reference. An error if the
+    // reference is missing one of them or has it at another length.
+    let mut mutated_map = HashMap::with_capacity(edit_list.len());
+    for (name, length, edits) in edit_list {
+        let mut sequence = match fasta_map.get(name) {
+            Some(sequence) if sequence.len() == *length => sequence.clone(),
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the reference has no contig {} of length {}", name, length),
+            )),
+        };
+        for (position, base) in edits {
+            sequence[*position] = *base;
+        }
+        mutated_map.insert(name.clone(), sequence);
+    }
+    Ok(mutated_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_list() {
+        // Edits at the start, 200 bases on (a two byte varint) and at the end of chr1, past an N
+        // left alone, and none on chr2
+        let mut chr1 = vec![0; 300];
+        chr1[150] = 4;
+        let fasta_map =
+            HashMap::from([("chr1".to_string(), chr1), ("chr2".to_string(), vec![1, 2, 3])]);
+        let mut mutated_map = fasta_map.clone();
+        for (position, base) in [(0, 2), (200, 3), (299, 1)] {
+            mutated_map.get_mut("chr1").unwrap()[position] = base;
+        }
+        let order = vec!["chr1".to_string(), "chr2".to_string()];
+        let mut edit_list = Vec::new();
+        let edits = write_edit_list(&mut edit_list, &fasta_map, &mutated_map, &order).unwrap();
+        assert_eq!(edits, 3);
+        // The header, both contigs' names and counts, and two bytes an edit (three for the one
+        // 200 bases on)
+        assert_eq!(edit_list.len(), 12 + 2 * (4 + 4 + 16) + 7);
+        let contigs = read_edit_list(&mut edit_list.as_slice()).unwrap();
+        assert_eq!(contigs[0], ("chr1".to_string(), 300, vec![(0, 2), (200, 3), (299, 1)]));
+        assert_eq!(contigs[1], ("chr2".to_string(), 3, Vec::new()));
+        assert_eq!(apply_edit_list(&fasta_map, &contigs).unwrap(), mutated_map);
+
+        assert!(read_edit_list(&mut &edit_list[..edit_list.len() - 1]).is_err());
+        assert!(read_edit_list(&mut &b"NEATREF\x02\0\0\0\0"[..]).is_err());
+        let short = HashMap::from([("chr1".to_string(), vec![0; 10])]);
+        assert!(apply_edit_list(&short, &contigs).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+// Per-cycle error profiles: the substitution rate at each cycle of read 1 and read 2, as measured
+// from real runs (from an alignment, or from FastQC's per base quality). Real error rates climb
+// toward the ends of reads, and more steeply on read 2, and tools that trim or model errors by
+// cycle need reads that do the same. With a profile, each base gets a substitution with its
+// cycle's rate, on top of any the quality model brings, and its quality score is capped at the
+// score that rate stands for, so the ramp shows in the qualities too.
+
+use std::fs;
+use super::errors::NeatError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleErrorProfile {
+    // The substitution rate at each cycle (from the first) of read 1 and of read 2. Cycles past
+    // the end of the table keep the last rate.
+    pub read1: Vec<f64>,
+    pub read2: Vec<f64>,
+}
+
+fn parse_cycles(field: &str) -> Option<(usize, usize)> {
+    // A cycle (12) or a range of cycles (10-14, as FastQC groups them), counted from 1.
+    let (first, last) = match field.split_once('-') {
+        Some((first, last)) => (first.trim().parse().ok()?, last.trim().parse().ok()?),
+        None => {
+            let cycle = field.trim().parse().ok()?;
+            (cycle, cycle)
+        },
+    };
+    (first >= 1 && first <= last).then_some((first, last))
+}
+
+pub fn read_error_profile(path: &str) -> Result<CycleErrorProfile, NeatError> {
+    // Takes:
+    // path: A tab separated table with a line per cycle (or range of cycles): the cycle, its
+    //     substitution rate on read 1 and, optionally, on read 2. Read 2 has read 1's rates if no
+    //     line gives one. Lines starting with # are skipped.
+    // Returns:
+    // The profile, or an error if the table is malformed or its cycles don't run from 1 without
+    // gaps.
+    let text = fs::read_to_string(path)?;
+    let mut read1 = Vec::new();
+    let mut read2 = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty() && !line.starts_with('#')) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let bad_line = || {
+            NeatError::ModelLoad(format!("{}: bad error profile line {:?}", path, line))
+        };
+        let (first, last) = parse_cycles(fields[0]).ok_or_else(bad_line)?;
+        if first != read1.len() + 1 || !(2..=3).contains(&fields.len()) {
+            return Err(bad_line())
+        }
+        let rates = fields[1..]
+            .iter()
+            .map(|field| {
+                field.trim().parse::<f64>().ok().filter(|rate| (0.0..=1.0).contains(rate))
+            })
+            .collect::<Option<Vec<f64>>>()
+            .ok_or_else(bad_line)?;
+        for _ in first..=last {
+            read1.push(rates[0]);
+            if let Some(rate) = rates.get(1) {
+                read2.push(*rate);
+            }
+        }
+    }
+    if read1.is_empty() {
+        return Err(NeatError::ModelLoad(format!("{} has no cycles", path)))
+    }
+    if read2.is_empty() {
+        read2 = read1.clone();
+    } else if read2.len() != read1.len() {
+        return Err(NeatError::ModelLoad(format!(
+            "{} gives read 2 rates for only some cycles", path
+        )))
+    }
+    Ok(CycleErrorProfile { read1, read2 })
+}
+
+impl CycleErrorProfile {
+    pub fn rates(&self, mate: u8, read_length: usize) -> Vec<f64> {
+        // The substitution rate at each cycle of a read of this mate (1 or 2) and length.
+        let rates = if mate == 2 { &self.read2 } else { &self.read1 };
+        let last = rates[rates.len() - 1];
+        (0..read_length).map(|cycle| rates.get(cycle).copied().unwrap_or(last)).collect()
+    }
+}
+
+pub fn capped_quality(quality: u32, rate: f64) -> u32 {
+    // The quality score, no higher than the one whose error rate is rate.
+    if rate <= 0.0 {
+        return quality
+    }
+    quality.min((-10.0 * rate.log10()).round() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_error_profile() {
+        let path = "test_data/test_error_profile.tsv";
+        fs::write(
+            path,
+            "#cycle\tread1\tread2\n1\t0.001\t0.002\n2-3\t0.002\t0.004\n4\t0.01\t0.05\n",
+        )
+        .unwrap();
+        let profile = read_error_profile(path);
+        fs::remove_file(path).unwrap();
+        let profile = profile.unwrap();
+        assert_eq!(profile.read1, vec![0.001, 0.002, 0.002, 0.01]);
+        assert_eq!(profile.rates(2, 6), vec![0.002, 0.004, 0.004, 0.05, 0.05, 0.05]);
+        assert_eq!(profile.rates(1, 2), vec![0.001, 0.002]);
+
+        // Read 1 rates only; a gap in the cycles; a rate that isn't one; read 2 rates on only some
+        // lines; no cycles at all
+        for (table, ok) in [("1\t0.001\n2\t0.01\n", true), ("1\t0.001\n3\t0.01\n", false),
+            ("1\t1.5\n", false), ("1\t0.001\t0.002\n2\t0.01\n", false), ("", false)]
+        {
+            fs::write(path, table).unwrap();
+            let profile = read_error_profile(path);
+            fs::remove_file(path).unwrap();
+            assert_eq!(profile.is_ok(), ok, "{:?}", table);
+        }
+
+        assert_eq!(capped_quality(37, 0.01), 20);
+        assert_eq!(capped_quality(11, 0.01), 11);
+        assert_eq!(capped_quality(37, 0.0), 37);
+    }
+}
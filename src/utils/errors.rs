@@ -0,0 +1,196 @@
+// The error type shared by all of rusty-neat. Anything that can go wrong because of the inputs or
+// the environment (bad config, unreadable fasta, missing model, existing output...) comes back as
+// a NeatError, so the binary can report it cleanly and library users and tests can match on it.
+// Panics are reserved for actual bugs.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_json::json;
+
+// Process exit codes, one per class of failure, so workflow managers can decide whether a retry
+// makes sense without parsing the message.
+pub const EXIT_CONFIG: i32 = 2;
+pub const EXIT_IO: i32 = 3;
+pub const EXIT_INPUT: i32 = 4;
+pub const EXIT_MODEL: i32 = 5;
+pub const EXIT_SIMULATION: i32 = 6;
+// A panic. This is always a bug in rusty-neat and should be reported.
+pub const EXIT_INTERNAL: i32 = 70;
+
+#[derive(Debug)]
+pub enum NeatError {
+    // Reading or writing a file failed.
+    Io(io::Error),
+    // The reference (fasta or 2bit) could not be parsed.
+    FastaParse(String),
+    // A bed file (e.g. the target regions) could not be parsed.
+    BedParse(String),
+    // A gene annotation file (GFF3 or GTF) could not be parsed.
+    AnnotationParse(String),
+    // An input vcf (e.g. the known sites) could not be parsed.
+    VcfParse(String),
+    // An input bam (e.g. the coverage bam) could not be parsed.
+    BamParse(String),
+    // A liftover chain file could not be parsed.
+    ChainParse(String),
+    // The reference doesn't match the sequence dictionary (.dict or .fai) given for it.
+    ReferenceMismatch(String),
+    // The configuration (file, command line or builder) is invalid.
+    Config(String),
+    // A model file could not be read or has the wrong format.
+    ModelLoad(String),
+    // An output file already exists and overwrite_output is off.
+    OutputExists(PathBuf),
+    // There is not enough free space where the output would be written.
+    InsufficientSpace { path: PathBuf, required: u64, available: u64 },
+    // The simulation itself could not produce what was asked of it.
+    Simulation(String),
+}
+
+impl Display for NeatError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NeatError::Io(error) => write!(f, "I/O error: {}", error),
+            NeatError::FastaParse(message) => write!(f, "Problem parsing fasta: {}", message),
+            NeatError::BedParse(message) => write!(f, "Problem parsing bed: {}", message),
+            NeatError::AnnotationParse(message) => write!(
+                f, "Problem parsing gene annotations: {}", message
+            ),
+            NeatError::VcfParse(message) => write!(f, "Problem parsing vcf: {}", message),
+            NeatError::BamParse(message) => write!(f, "Problem parsing bam: {}", message),
+            NeatError::ChainParse(message) => write!(f, "Problem parsing chain file: {}", message),
+            NeatError::ReferenceMismatch(message) => write!(
+                f, "Reference does not match its sequence dictionary: {}", message
+            ),
+            NeatError::Config(message) => write!(f, "Configuration error: {}", message),
+            NeatError::ModelLoad(message) => write!(f, "Problem loading model: {}", message),
+            NeatError::OutputExists(path) => write!(
+                f,
+                "Output file {} already exists (set overwrite_output to replace it)",
+                path.display()
+            ),
+            NeatError::InsufficientSpace { path, required, available } => write!(
+                f,
+                "Not enough free space in {}: need about {} bytes, {} available",
+                path.display(),
+                required,
+                available
+            ),
+            NeatError::Simulation(message) => write!(f, "Simulation error: {}", message),
+        }
+    }
+}
+
+impl NeatError {
+    pub fn class(&self) -> &'static str {
+        // A short, stable name for the kind of failure, used in error reports.
+        match self {
+            NeatError::Config(_) => "config",
+            NeatError::Io(_)
+            | NeatError::OutputExists(_)
+            | NeatError::InsufficientSpace { .. } => "io",
+            NeatError::FastaParse(_)
+            | NeatError::BedParse(_)
+            | NeatError::AnnotationParse(_)
+            | NeatError::VcfParse(_)
+            | NeatError::BamParse(_)
+            | NeatError::ChainParse(_)
+            | NeatError::ReferenceMismatch(_) => "input",
+            NeatError::ModelLoad(_) => "model",
+            NeatError::Simulation(_) => "simulation",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.class() {
+            "config" => EXIT_CONFIG,
+            "io" => EXIT_IO,
+            "input" => EXIT_INPUT,
+            "model" => EXIT_MODEL,
+            _ => EXIT_SIMULATION,
+        }
+    }
+}
+
+pub fn write_error_report(
+    path: &Path,
+    class: &str,
+    exit_code: i32,
+    message: &str,
+) -> io::Result<()> {
+    // Writes a small json file describing a failed run, e.g.
+    //     {"class": "config", "exit_code": 2, "message": "..."}
+    let report = json!({
+        "class": class,
+        "exit_code": exit_code,
+        "message": message,
+    });
+    serde_json::to_writer_pretty(File::create(path)?, &report)?;
+    Ok(())
+}
+
+impl Error for NeatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NeatError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NeatError {
+    fn from(error: io::Error) -> Self {
+        NeatError::Io(error)
+    }
+}
+
+impl From<serde_yaml::Error> for NeatError {
+    fn from(error: serde_yaml::Error) -> Self {
+        NeatError::Config(format!("Could not read values: {}", error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_and_source() {
+        let error = NeatError::from(io::Error::new(io::ErrorKind::NotFound, "gone"));
+        assert_eq!(error.to_string(), "I/O error: gone");
+        assert!(error.source().is_some());
+        let error = NeatError::OutputExists(PathBuf::from("out.vcf"));
+        assert!(error.to_string().starts_with("Output file out.vcf already exists"));
+        assert!(error.source().is_none());
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(NeatError::Config("bad".to_string()).exit_code(), EXIT_CONFIG);
+        assert_eq!(NeatError::OutputExists(PathBuf::from("x")).exit_code(), EXIT_IO);
+        assert_eq!(NeatError::FastaParse("bad".to_string()).exit_code(), EXIT_INPUT);
+        assert_eq!(NeatError::BedParse("bad".to_string()).exit_code(), EXIT_INPUT);
+        assert_eq!(NeatError::AnnotationParse("bad".to_string()).exit_code(), EXIT_INPUT);
+        assert_eq!(NeatError::VcfParse("bad".to_string()).exit_code(), EXIT_INPUT);
+        assert_eq!(NeatError::ChainParse("bad".to_string()).exit_code(), EXIT_INPUT);
+        assert_eq!(NeatError::ReferenceMismatch("bad".to_string()).exit_code(), EXIT_INPUT);
+        assert_eq!(NeatError::ModelLoad("bad".to_string()).exit_code(), EXIT_MODEL);
+        assert_eq!(NeatError::Simulation("bad".to_string()).exit_code(), EXIT_SIMULATION);
+    }
+
+    #[test]
+    fn test_write_error_report() {
+        let error = NeatError::ModelLoad("missing".to_string());
+        let path = Path::new("test_data/test_error.json");
+        write_error_report(path, error.class(), error.exit_code(), &error.to_string()).unwrap();
+        let report: serde_json::Value =
+            serde_json::from_reader(File::open(path).unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(report["class"], "model");
+        assert_eq!(report["exit_code"], EXIT_MODEL);
+        assert_eq!(report["message"], "Problem loading model: missing");
+    }
+}
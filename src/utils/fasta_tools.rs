@@ -1,16 +1,19 @@
 // This library contains tools needed to process fasta files as input and output.
 
 use log::info;
-use std::io;
-use std::io::Write;
-use std::error;
 use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use super::errors::NeatError;
 use std::collections::HashMap;
 use super::file_tools::read_lines;
-use super::file_tools::open_file;
-use super::nucleotides::{u8_to_base, base_to_u8};
+use super::nucleotides::{encode_bases, u8_to_base};
+
+// Contig sequences in the u8 representation, keyed by contig name, on the heap.
+pub type ContigMap = Box<HashMap<String, Vec<u8>>>;
 
-pub fn sequence_array_to_string(input_array: &Vec<u8>) -> String {
+pub fn sequence_array_to_string(input_array: &[u8]) -> String {
     // Converts a sequence vector into a string representing the DNA sequence
     let mut return_string = String::new();
     for num in input_array {
@@ -19,87 +22,279 @@ pub fn sequence_array_to_string(input_array: &Vec<u8>) -> String {
     return_string
 }
 
-pub fn read_fasta(
-    fasta_path: &str
-) -> Result<(Box<HashMap<String, Vec<u8>>>, Vec<String>), io::Error> {
-    // Reads a fasta file and turns it into a HashMap and puts it in the heap
+pub fn read_fasta(fasta_path: &str) -> Result<(ContigMap, Vec<String>), NeatError> {
+    // Reads a fasta file and turns it into a HashMap and puts it in the heap. Contigs are named by
+    // the first word of their header, exactly as written, as samtools and aligners name them, so
+    // assembly contigs (tig00001|arrow|pilon len=1465 ...) keep names that are valid in outputs.
     info!("Reading fasta: {}", fasta_path);
 
     let mut fasta_map: HashMap<String, Vec<u8>> = HashMap::new();
     let mut fasta_order: Vec<String> = Vec::new();
     let mut current_key = String::new();
 
-    let lines = read_lines(fasta_path).unwrap();
+    let lines = read_lines(fasta_path)?;
     let mut temp_seq: Vec<u8> = vec![];
-    lines.for_each(|line| match line {
-        Ok(l) => {
-            if l.starts_with('>') {
-                if !current_key.is_empty() {
-                    fasta_map.entry(current_key.clone()).or_insert(temp_seq.clone());
-                }
-                current_key = String::from(l.strip_prefix('>').unwrap());
-                fasta_order.push(current_key.clone());
-                temp_seq = vec![];
-            } else {
-                for char in l.chars() {
-                    temp_seq.push(base_to_u8(char));
-                }
+    for line in lines {
+        // Lines already come without their \n or \r\n, but a file with a last line ending in a
+        // lone \r would otherwise get it in a contig name or sequence.
+        let line = line?;
+        let l = line.trim_end_matches('\r');
+        if l.starts_with('>') {
+            if !current_key.is_empty() {
+                fasta_map.insert(current_key.clone(), temp_seq);
             }
-        },
-        Err(error) => panic!("Problem reading fasta file: {}", error)
-    });
+            let header = l.strip_prefix('>').unwrap();
+            current_key = String::from(header.split_whitespace().next().unwrap_or_default());
+            if current_key.is_empty() {
+                return Err(NeatError::FastaParse(
+                    format!("Empty contig name in {}", fasta_path)
+                ))
+            }
+            if fasta_map.contains_key(&current_key) {
+                return Err(NeatError::FastaParse(
+                    format!("Duplicate contig name {} in {}", current_key, fasta_path)
+                ))
+            }
+            fasta_order.push(current_key.clone());
+            temp_seq = vec![];
+        } else if current_key.is_empty() {
+            if !l.trim().is_empty() {
+                return Err(NeatError::FastaParse(
+                    format!("Sequence found before the first '>' header in {}", fasta_path)
+                ))
+            }
+        } else {
+            encode_bases(l.as_bytes(), &mut temp_seq);
+        }
+    }
+    if fasta_order.is_empty() {
+        return Err(NeatError::FastaParse(format!("No contigs found in {}", fasta_path)))
+    }
     // Need to pick up the last one
-    fasta_map.entry(current_key.clone()).or_insert(temp_seq.clone());
+    fasta_map.insert(current_key, temp_seq);
     Ok((Box::new(fasta_map), fasta_order))
 }
 
 pub fn write_fasta(
-    fasta_output: &Box<HashMap<String, Vec<u8>>>,
-    fasta_order: &Vec<String>,
-    overwrite_output: bool,
-    output_file: &str,
+    writer: &mut impl Write,
+    fasta_output: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    comment: Option<&str>,
 ) -> io::Result<()> {
     /*
     Takes:
+        writer: where to write the fasta (a file, stdout, an in-memory buffer...)
         fasta_output: the hashmap of mutated sequences with contig names
         fasta_order: A vector with the proper order for the fasta elements
-        output_file: the prefix for the output file name
-        ploidy: the number of copies of each chromosome the simulation is using
+        comment: A description to put after each contig name in its header, if any.
     Returns:
         Errors if there is a problem writing the file, otherwise it returns nothing.
      */
-    // writing fasta output to files
-    let mut output_fasta = format!("{}.fasta", output_file);
-    let mut outfile = open_file(&mut output_fasta, overwrite_output)
-        .expect(&format!("Error opening {}", output_fasta));
     for contig in fasta_order {
         let sequence = &fasta_output[contig];
         // Write contig name
-        writeln!(&mut outfile, ">{}", contig)?;
-        // write sequences[ploid] to this_fasta
-        let mut i = 0;
-        let sequence_to_write: &Vec<u8> = &sequence;
-        while i < sequence_to_write.len() {
-            let mut line = String::new();
-            let mut max: usize = 70;
-            let this_length: usize = sequence_to_write[i..].len();
-            // If we don't have 70 characters, write out whatever is left.
-            if this_length < 70 {
-                max = this_length
-            }
-            for j in 0..max {
-                line += &(u8_to_base(sequence_to_write[i + j]).to_string());
-            }
-            writeln!(&mut outfile, "{}", line)?;
-            i += 70;
+        match comment {
+            Some(comment) => writeln!(writer, ">{} {}", contig, comment)?,
+            None => writeln!(writer, ">{}", contig)?,
+        }
+        // write the sequence, 70 bases per line. The last line gets whatever is left.
+        for chunk in sequence.chunks(70) {
+            writeln!(writer, "{}", sequence_array_to_string(chunk))?;
         }
     };
     Ok(())
 }
 
+pub fn is_primary_assembly(contig: &str) -> bool {
+    // Whether a contig is part of the primary assembly, going by the GRCh38 analysis set naming:
+    // alt haplotypes (_alt), unlocalized (_random) and unplaced (chrUn_) scaffolds, HLA alleles
+    // (HLA-) and decoys (_decoy, chrEBV, hs37d5) are not. Only the first word of the header is
+    // looked at.
+    let name = contig.split_whitespace().next().unwrap_or_default();
+    let secondary = name.ends_with("_alt")
+        || name.ends_with("_random")
+        || name.ends_with("_decoy")
+        || name.starts_with("chrUn_")
+        || name.starts_with("HLA-")
+        || name == "chrEBV"
+        || name == "hs37d5";
+    !secondary
+}
+
+pub fn is_valid_sam_name(name: &str) -> bool {
+    // Whether a contig name is allowed as a reference name by the SAM spec, which leaves out
+    // backslashes, commas, quotes and brackets (among others) and names starting with * or =.
+    // Samtools and some callers reject bams and vcfs with other names.
+    let allowed = |byte: &u8| {
+        byte.is_ascii_graphic() && !b"\\,\"'`()[]{}<>".contains(byte)
+    };
+    match name.as_bytes() {
+        [first, rest @ ..] => allowed(first) && !b"*=".contains(first) && rest.iter().all(allowed),
+        [] => false,
+    }
+}
+
+pub fn read_sequence_dictionary(path: &str) -> Result<Vec<(String, usize)>, NeatError> {
+    // Takes:
+    // path: A sequence dictionary, either a .dict (the @SQ lines of a SAM header, as written by
+    //     Picard CreateSequenceDictionary) or a .fai (samtools faidx).
+    // Returns:
+    // The name and length of every contig, in order.
+    let text = fs::read_to_string(path)?;
+    let bad_line = |line: &str| NeatError::FastaParse(format!("{}: bad line {:?}", path, line));
+    let mut contigs = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (name, length) = if path.ends_with(".dict") {
+            if fields[0] != "@SQ" {
+                continue
+            }
+            let field = |tag: &str| fields.iter().find_map(|field| field.strip_prefix(tag));
+            (field("SN:"), field("LN:"))
+        } else {
+            (fields.first().copied(), fields.get(1).copied())
+        };
+        let length = length.and_then(|length| length.parse::<usize>().ok());
+        match (name, length) {
+            (Some(name), Some(length)) => contigs.push((name.to_string(), length)),
+            _ => return Err(bad_line(line)),
+        }
+    }
+    Ok(contigs)
+}
+
+pub fn check_sequence_dictionary(
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    dictionary: &[(String, usize)],
+) -> Result<(), NeatError> {
+    // Checks that the reference has exactly the contigs of the dictionary, in the same order and
+    // with the same lengths. Only the first word of each fasta header is the contig name, as in
+    // samtools and Picard.
+    let mut problems: Vec<String> = Vec::new();
+    if fasta_order.len() != dictionary.len() {
+        problems.push(format!(
+            "{} contigs in the reference, {} in the dictionary", fasta_order.len(), dictionary.len()
+        ));
+    }
+    for (index, (contig, (name, length))) in fasta_order.iter().zip(dictionary).enumerate() {
+        let contig_name = contig.split_whitespace().next().unwrap_or_default();
+        if contig_name != name {
+            problems.push(format!(
+                "contig {} is {} in the reference but {} in the dictionary",
+                index + 1, contig_name, name
+            ));
+        } else if fasta_map[contig].len() != *length {
+            problems.push(format!(
+                "{} is {} bp in the reference but {} bp in the dictionary",
+                name, fasta_map[contig].len(), length
+            ));
+        }
+    }
+    match problems.is_empty() {
+        true => Ok(()),
+        // The first few are enough to tell what went wrong.
+        false => Err(NeatError::ReferenceMismatch(
+            problems.into_iter().take(5).collect::<Vec<String>>().join("; ")
+        )),
+    }
+}
+
+pub fn parse_region(region: &str) -> Result<(String, usize, usize), NeatError> {
+    // Takes:
+    // region: A region as samtools writes them, contig:start-end, 1-based and inclusive. Commas in
+    //     the numbers are ignored, and contig names may have colons of their own.
+    // Returns:
+    // The contig and the region as a 0-based, half open range.
+    let bad_region = || NeatError::Config(format!(
+        "Bad region {:?}, expected contig:start-end (e.g. chr7:55019017-55211628)", region
+    ));
+    let (contig, range) = region.rsplit_once(':').ok_or_else(bad_region)?;
+    let (start, end) = range.split_once('-').ok_or_else(bad_region)?;
+    let position = |text: &str| text.trim().replace(',', "").parse::<usize>().ok();
+    match (position(start), position(end)) {
+        (Some(start), Some(end)) if !contig.is_empty() && start >= 1 && start <= end => {
+            Ok((contig.to_string(), start - 1, end))
+        },
+        _ => Err(bad_region()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaiEntry {
+    // A contig's line of a samtools faidx index.
+    //
+    // length: The contig's length in bases.
+    // offset: Where its first base is in the fasta, in bytes.
+    // line_bases: Bases on each full line.
+    // line_width: Bytes of each full line, with its line ending.
+    pub length: usize,
+    pub offset: u64,
+    pub line_bases: usize,
+    pub line_width: usize,
+}
+
+pub fn read_fai(path: &str) -> Result<HashMap<String, FaiEntry>, NeatError> {
+    // Reads a samtools faidx index: name, length, offset, bases per line and bytes per line.
+    let text = fs::read_to_string(path)?;
+    let mut entries = HashMap::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let number = |index: usize| fields.get(index).and_then(|field| field.parse::<u64>().ok());
+        match (number(1), number(2), number(3), number(4)) {
+            (Some(length), Some(offset), Some(line_bases), Some(line_width))
+                if line_bases > 0 && line_width > line_bases =>
+            {
+                entries.insert(fields[0].to_string(), FaiEntry {
+                    length: length as usize,
+                    offset,
+                    line_bases: line_bases as usize,
+                    line_width: line_width as usize,
+                });
+            },
+            _ => return Err(NeatError::FastaParse(format!("{}: bad line {:?}", path, line))),
+        }
+    }
+    Ok(entries)
+}
+
+pub fn read_fasta_region(
+    fasta_path: &str,
+    entry: &FaiEntry,
+    start: usize,
+    end: usize,
+) -> Result<Vec<u8>, NeatError> {
+    // Takes:
+    // fasta_path: The fasta the index entry is for.
+    // entry: The contig's entry in the index.
+    // start, end: The region to read, 0-based and half open, within the contig.
+    // Returns:
+    // The contig with only the region's bases read from the fasta, seeking straight to them, and
+    // N everywhere else, so positions in it are positions on the full contig.
+    let byte = |base: usize| {
+        entry.offset
+            + (base / entry.line_bases * entry.line_width + base % entry.line_bases) as u64
+    };
+    let mut fasta = File::open(fasta_path)?;
+    fasta.seek(SeekFrom::Start(byte(start)))?;
+    let mut text = vec![0; (byte(end - 1) - byte(start) + 1) as usize];
+    fasta.read_exact(&mut text)?;
+    text.retain(|character| !character.is_ascii_whitespace());
+    if text.len() != end - start || text.contains(&b'>') {
+        return Err(NeatError::FastaParse(
+            format!("The index of {} doesn't match it; rebuild it with samtools faidx", fasta_path)
+        ))
+    }
+    let mut sequence = vec![4; start];
+    encode_bases(&text, &mut sequence);
+    sequence.resize(entry.length, 4);
+    Ok(sequence)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::nucleotides::base_to_u8;
 
     #[test]
     fn test_conversions() {
@@ -118,32 +313,146 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
+    fn test_read_crlf_fasta() {
+        // Windows line endings, with a stray \r on the unterminated last line.
+        let test_fasta = "test_data/test_crlf.fa";
+        fs::write(test_fasta, ">chr1 first\r\nACGT\r\nNNAC\r\n>chr2\r\nGGCC\r").unwrap();
+        let result = read_fasta(test_fasta);
+        fs::remove_file(test_fasta).unwrap();
+        let (fasta_map, fasta_order) = result.unwrap();
+        assert_eq!(fasta_order, vec!["chr1".to_string(), "chr2".to_string()]);
+        assert_eq!(fasta_map["chr1"], vec![0, 1, 2, 3, 4, 4, 0, 1]);
+        assert_eq!(fasta_map["chr2"], vec![2, 2, 1, 1]);
+    }
+
+    #[test]
+    fn test_read_assembly_fasta() {
+        let test_fasta = "test_data/test_assembly.fa";
+        fs::write(
+            test_fasta,
+            ">tig00000001|arrow|pilon len=8 cov=30.5\nACGTACGT\n>scaffold_2:1-4\tpolished\nGGCC\n",
+        ).unwrap();
+        let result = read_fasta(test_fasta);
+        let names: Vec<&str> = vec!["tig00000001|arrow|pilon", "scaffold_2:1-4"];
+        assert_eq!(result.unwrap().1, names);
+        assert!(names.iter().all(|name| is_valid_sam_name(name)));
+        for name in ["*", "=chr1", "chr{1}", "chr\\1", "chr(1)"] {
+            assert!(!is_valid_sam_name(name));
+        }
+        // Headers that only differ after the first word are the same contig
+        fs::write(test_fasta, ">tig1 len=4\nACGT\n>tig1 len=2\nAC\n").unwrap();
+        let result = read_fasta(test_fasta);
+        fs::remove_file(test_fasta).unwrap();
+        assert!(matches!(result, Err(NeatError::FastaParse(_))));
+    }
+
+    #[test]
     fn test_read_bad_fasta() {
         let test_fasta = "test_data/fake.fasta";
-        read_fasta(test_fasta).unwrap();
+        assert!(matches!(read_fasta(test_fasta), Err(NeatError::Io(_))));
     }
 
     #[test]
-    fn test_write_fasta() -> Result<(), Box<dyn error::Error>> {
+    fn test_read_malformed_fasta() {
+        let test_fasta = "test_data/test_model.json";
+        assert!(matches!(read_fasta(test_fasta), Err(NeatError::FastaParse(_))));
+    }
+
+    #[test]
+    fn test_write_fasta() {
         let seq1: Vec<u8> = vec![0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1];
+        let seq2: Vec<u8> = vec![2; 75];
         let fasta_output: HashMap<String, Vec<u8>> = HashMap::from([
-            (String::from("H1N1_HA"), seq1)
+            (String::from("H1N1_HA"), seq1),
+            (String::from("H1N1_NA"), seq2),
         ]);
-        let fasta_pointer = Box::new(fasta_output);
-        let fasta_order = vec![String::from("H1N1_HA")];
-        let output_file = "test";
-        let test_write = write_fasta(
-            &fasta_pointer,
-            &fasta_order,
-            true,
-            output_file
-        ).unwrap();
-        let file_name = "test.fasta";
-        assert_eq!(test_write, ());
-        let attr = fs::metadata(file_name).unwrap();
-        assert!(attr.len() > 0);
-        fs::remove_file(file_name)?;
-        Ok(())
+        let fasta_order = vec![String::from("H1N1_HA"), String::from("H1N1_NA")];
+        let mut buffer: Vec<u8> = Vec::new();
+        write_fasta(&mut buffer, &fasta_output, &fasta_order, None).unwrap();
+        let expected = format!(
+            ">H1N1_HA\nAAAAAAAACCCCCCCC\n>H1N1_NA\n{}\nGGGGG\n", "G".repeat(70)
+        );
+        assert_eq!(String::from_utf8(buffer).unwrap(), expected);
+        let mut buffer: Vec<u8> = Vec::new();
+        write_fasta(&mut buffer, &fasta_output, &fasta_order[..1], Some("neat_seed=1")).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), ">H1N1_HA neat_seed=1\nAAAAAAAACCCCCCCC\n");
+    }
+
+    #[test]
+    fn test_is_primary_assembly() {
+        for contig in ["chr1", "chrX", "chrM", "2 dna:chromosome"] {
+            assert!(is_primary_assembly(contig));
+        }
+        let secondary = [
+            "chr1_KI270706v1_random",
+            "chr6_GL000250v2_alt",
+            "chrUn_KI270302v1",
+            "chrUn_JTFH01000001v1_decoy",
+            "HLA-A*01:01:01:01",
+            "chrEBV",
+        ];
+        for contig in secondary {
+            assert!(!is_primary_assembly(contig));
+        }
+    }
+
+    #[test]
+    fn test_check_sequence_dictionary() {
+        let (fasta_map, fasta_order) = read_fasta("test_data/H1N1.fa").unwrap();
+        let fai = "test_data/test_H1N1.fa.fai";
+        let lines: Vec<String> = fasta_order
+            .iter()
+            .map(|contig| format!("{}\t{}\t0\t70\t71", contig, fasta_map[contig].len()))
+            .collect();
+        fs::write(fai, lines.join("\n")).unwrap();
+        let dictionary = read_sequence_dictionary(fai);
+        fs::remove_file(fai).unwrap();
+        let dictionary = dictionary.unwrap();
+        assert!(check_sequence_dictionary(&fasta_map, &fasta_order, &dictionary).is_ok());
+        // A truncated last contig, and a missing one
+        let mut truncated = fasta_map.clone();
+        truncated.get_mut("H1N1_PB2").unwrap().truncate(1000);
+        assert!(matches!(
+            check_sequence_dictionary(&truncated, &fasta_order, &dictionary),
+            Err(NeatError::ReferenceMismatch(_))
+        ));
+        assert!(matches!(
+            check_sequence_dictionary(&fasta_map, &fasta_order[..7], &dictionary),
+            Err(NeatError::ReferenceMismatch(_))
+        ));
+
+        let dict = "test_data/test_H1N1.dict";
+        fs::write(dict, "@HD\tVN:1.6\n@SQ\tSN:H1N1_HA\tLN:1701\tM5:abc\tUR:file:H1N1.fa\n").unwrap();
+        let dictionary = read_sequence_dictionary(dict);
+        fs::remove_file(dict).unwrap();
+        assert_eq!(dictionary.unwrap(), vec![("H1N1_HA".to_string(), 1701)]);
+    }
+
+    #[test]
+    fn test_read_fasta_region() {
+        assert_eq!(parse_region("chr7:1,001-2,000").unwrap(), ("chr7".to_string(), 1000, 2000));
+        assert_eq!(parse_region("HLA-A*01:01:1-5").unwrap(), ("HLA-A*01:01".to_string(), 0, 5));
+        for region in ["chr7", "chr7:0-10", "chr7:20-10", ":1-10", "chr7:1-x"] {
+            assert!(matches!(parse_region(region), Err(NeatError::Config(_))), "{}", region);
+        }
+
+        // Lines of 4 bases, with windows line endings on the second contig
+        let fasta = "test_data/test_region.fa";
+        let fai = "test_data/test_region.fa.fai";
+        fs::write(fasta, ">chr1\nACGT\nACGT\nAC\n>chr2 x\r\nGGCC\r\nTTAA\r\nCA\r\n").unwrap();
+        fs::write(fai, "chr1\t10\t6\t4\t5\nchr2\t10\t28\t4\t6\n").unwrap();
+        let entries = read_fai(fai).unwrap();
+        let region = |contig: &str, start: usize, end: usize| {
+            read_fasta_region(fasta, &entries[contig], start, end).unwrap()
+        };
+        assert_eq!(region("chr1", 2, 7), vec![4, 4, 2, 3, 0, 1, 2, 4, 4, 4]);
+        assert_eq!(region("chr2", 0, 10), vec![2, 2, 1, 1, 3, 3, 0, 0, 1, 0]);
+        assert_eq!(region("chr2", 7, 8), vec![4, 4, 4, 4, 4, 4, 4, 0, 4, 4]);
+        // An index for another version of the fasta
+        let stale = FaiEntry { offset: 0, ..entries["chr1"] };
+        let result = read_fasta_region(fasta, &stale, 0, 10);
+        fs::remove_file(fasta).unwrap();
+        fs::remove_file(fai).unwrap();
+        assert!(matches!(result, Err(NeatError::FastaParse(_))));
     }
 }
\ No newline at end of file
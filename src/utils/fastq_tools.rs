@@ -1,118 +1,412 @@
 // This library writes either single ended or paired-ended fastq files.
 
+use std::collections::VecDeque;
+use std::io;
 use std::io::Write;
-use std::{fs, io};
-use simple_rng::Rng;
 
-use super::fasta_tools::sequence_array_to_string;
-use super::file_tools::open_file;
-use super::quality_scores::QualityScoreModel;
+use super::errors::NeatError;
+use super::make_reads::{SimulatedRead, Strand};
 
-fn complement(nucleotide: u8) -> u8 {
-    // 0 = A, 1 = C, 2 = G, 3 = T,
-    // matches with the complement of each nucleotide.
-    return match nucleotide {
-        0 => 3,
-        1 => 2,
-        2 => 1,
-        3 => 0,
-        _ => 4,
+// Fields of the synthesized Illumina (Casava 1.8) read names. The instrument, flowcell and lane
+// are defaults, which the run's instrument and platform unit override (see IlluminaRun).
+pub const ILLUMINA_INSTRUMENT: &str = "NEAT";
+const ILLUMINA_RUN: usize = 1;
+const ILLUMINA_FLOWCELL: &str = "NEATSIM01";
+const ILLUMINA_LANE: usize = 1;
+const ILLUMINA_INDEX: &str = "ATCACG";
+// Tiles are numbered from here, one per contig, and reads are laid out across a tile in rows of
+// DEFAULT_TILE_WIDTH x positions unless the flowcell geometry says otherwise.
+const ILLUMINA_FIRST_TILE: usize = 1101;
+pub const DEFAULT_TILE_WIDTH: usize = 25000;
+const ILLUMINA_MIN_COORDINATE: usize = 1000;
+// The character for each base, indexed by its u8 representation (anything past T is N).
+const BASE_CHARS: &[u8; 5] = b"ACGTN";
+// Quality scores are written as printable ascii, '!' (33) through '~' (126).
+pub const SANGER_QUALITY_OFFSET: u32 = 33;
+const MAX_QUALITY_CHAR: u32 = 126;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadNameFormat {
+    // simple: @neat_generated_<contig>_<template>/<mate>
+    // illumina: @<instrument>:<run>:<flowcell>:<lane>:<tile>:<x>:<y> <mate>:N:0:<index>
+    #[default]
+    Simple,
+    Illumina,
+}
+
+impl ReadNameFormat {
+    pub fn parse(name: &str) -> Result<Self, NeatError> {
+        match name.to_lowercase().as_str() {
+            "simple" => Ok(ReadNameFormat::Simple),
+            "illumina" => Ok(ReadNameFormat::Illumina),
+            _ => Err(NeatError::Config(format!(
+                "Unknown read_name_format {}, expected simple or illumina", name
+            ))),
+        }
     }
 }
 
-fn reverse_complement(sequence: &Vec<u8>) -> Vec<u8> {
-    // Returns the reverse complement of a vector of u8's representing a DNA sequence.
-    let length = sequence.len();
-    let mut rev_comp = Vec::new();
-    for i in (0..length).rev() {
-        rev_comp.push(complement(sequence[i]))
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowcellGeometry {
+    // Where the clusters of Illumina read names are put on a tile.
+    //
+    // tile_width: The x positions in a row of the tile. Clusters fill a tile row by row.
+    // optical_distance: For runs that simulate duplicates, how close (in pixels, along both x
+    //     and y) an optical duplicate is to its original. Clusters are then spaced
+    //     2 * optical_distance + 1 apart, so optical duplicates fit beside their original and
+    //     no other cluster is ever that close. None packs clusters side by side.
+    pub tile_width: usize,
+    pub optical_distance: Option<usize>,
+}
+
+impl Default for FlowcellGeometry {
+    fn default() -> Self {
+        FlowcellGeometry { tile_width: DEFAULT_TILE_WIDTH, optical_distance: None }
+    }
+}
+
+impl FlowcellGeometry {
+    pub fn cluster_spacing(&self) -> usize {
+        // The pixels between neighbouring clusters, along x and y.
+        self.optical_distance.map_or(1, |distance| 2 * distance + 1)
+    }
+
+    pub fn location(&self, contig_index: usize, template: usize) -> (usize, usize, usize) {
+        // The tile, x and y of a template's cluster. They come from the contig index and
+        // template number, so names are unique and the same on every run.
+        let spacing = self.cluster_spacing();
+        let per_row = (self.tile_width / spacing).max(1);
+        (
+            ILLUMINA_FIRST_TILE + contig_index,
+            ILLUMINA_MIN_COORDINATE + (template % per_row) * spacing,
+            ILLUMINA_MIN_COORDINATE + (template / per_row) * spacing,
+        )
     }
-    rev_comp
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IlluminaRun {
+    // The run fields of Illumina read names.
+    //
+    // instrument: The instrument ID.
+    // flowcell: The flowcell ID.
+    // lane: The flowcell lane.
+    pub instrument: String,
+    pub flowcell: String,
+    pub lane: usize,
+}
+
+impl Default for IlluminaRun {
+    fn default() -> Self {
+        IlluminaRun {
+            instrument: ILLUMINA_INSTRUMENT.to_string(),
+            flowcell: ILLUMINA_FLOWCELL.to_string(),
+            lane: ILLUMINA_LANE,
+        }
+    }
+}
+
+impl IlluminaRun {
+    pub fn new(instrument: &str, platform_unit: Option<&str>) -> Self {
+        // Takes:
+        // instrument: The instrument ID.
+        // platform_unit: The read group's PU. If it is Illumina's <flowcell>.<lane>[.<barcode>],
+        //     the names are on that flowcell and lane, so they agree with the bam header.
+        // Returns:
+        // The run, on the default flowcell and lane if the platform unit doesn't give them.
+        let mut run = IlluminaRun { instrument: instrument.to_string(), ..Default::default() };
+        let mut fields = platform_unit.unwrap_or_default().split('.');
+        if let (Some(flowcell), Some(Ok(lane))) = (fields.next(), fields.next().map(str::parse)) {
+            if !flowcell.is_empty() && !flowcell.contains(':') {
+                run.flowcell = flowcell.to_string();
+                run.lane = lane;
+            }
+        }
+        run
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FastqFormat {
+    // How fastq records are written, apart from the read itself.
+    //
+    // name_format: The layout of the name (see ReadNameFormat).
+    // comment_tags: SAM-style tags (e.g. BC:Z:ACGTAC) appended to every header, tab separated,
+    //     so aligners that keep comments (bwa mem -C) carry them into the alignments.
+    // truth_tags: Also append where each read came from: XC:Z:<contig> XP:i:<1-based start>
+    //     XS:A:<+ or ->.
+    // quality_offset: Added to each quality score to get its character. 33 (Sanger/Illumina 1.8+)
+    //     or 64 (Illumina 1.3-1.7).
+    // max_quality: Scores above this are written as this. Scores are always capped so that the
+    //     character stays printable.
+    // trim_threshold: Also append XT:i:<length>, how much of the read a quality trimmer working
+    //     back from its end should keep: up to and including its last base scoring at least
+    //     this (as written), 0 if none does.
+    // sample_index: The index Illumina names end with (i7, or i7+i5). ATCACG if not set.
+    pub name_format: ReadNameFormat,
+    pub comment_tags: Vec<String>,
+    pub truth_tags: bool,
+    pub quality_offset: u32,
+    pub max_quality: Option<u32>,
+    pub trim_threshold: Option<u32>,
+    pub sample_index: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PairedRecordCounts {
+    // The number of records written to the read 1 and read 2 fastq files. Paired ended output is
+    // only usable while these match, with the nth record of each file being mates.
+    pub r1: usize,
+    pub r2: usize,
+}
+
+impl PairedRecordCounts {
+    pub fn add(&mut self, other: PairedRecordCounts) {
+        self.r1 += other.r1;
+        self.r2 += other.r2;
+    }
+}
+
+pub fn pair_mismatch(
+    counts: &PairedRecordCounts,
+    r1_name: Option<&str>,
+    r2_name: Option<&str>,
+) -> NeatError {
+    // The error for read 1 and read 2 records that aren't mates, or a record with no mate at all
+    // (None for the file that ran out). Aligners pair reads by position in the files, so output
+    // like this is never written.
+    NeatError::Simulation(format!(
+        "Read 1 and read 2 fastq records out of step after {} read 1 and {} read 2 records: \
+        read 1 has {}, read 2 has {}",
+        counts.r1,
+        counts.r2,
+        r1_name.unwrap_or("no more records"),
+        r2_name.unwrap_or("no more records"),
+    ))
+}
+
+pub fn fastq_record_name(record: &[u8]) -> &[u8] {
+    // The read name of a fastq record (or its header line): the header up to the first
+    // whitespace, without the @ or the /1 or /2 of simple names, so mates have the same name.
+    let header = record.strip_prefix(b"@").unwrap_or(record);
+    let end = header.iter().position(|byte| byte.is_ascii_whitespace()).unwrap_or(header.len());
+    let name = &header[..end];
+    name.strip_suffix(b"/1").or_else(|| name.strip_suffix(b"/2")).unwrap_or(name)
+}
+
+impl Default for FastqFormat {
+    fn default() -> Self {
+        FastqFormat {
+            name_format: ReadNameFormat::Simple,
+            comment_tags: Vec::new(),
+            truth_tags: false,
+            quality_offset: SANGER_QUALITY_OFFSET,
+            max_quality: None,
+            trim_threshold: None,
+            sample_index: None,
+        }
+    }
+}
+
+pub fn check_quality_encoding(offset: u32, max_quality: Option<u32>) -> Result<(), NeatError> {
+    // The offset has to give a printable character for Q0, and the cap (if any) has to as well.
+    if !(SANGER_QUALITY_OFFSET..=MAX_QUALITY_CHAR).contains(&offset) {
+        return Err(NeatError::Config(format!(
+            "quality_offset {} must be between {} and {}", offset, SANGER_QUALITY_OFFSET, MAX_QUALITY_CHAR
+        )))
+    }
+    if let Some(max_quality) = max_quality {
+        if offset + max_quality > MAX_QUALITY_CHAR {
+            return Err(NeatError::Config(format!(
+                "max_quality {} can't be written with quality_offset {} (highest is {})",
+                max_quality, offset, MAX_QUALITY_CHAR - offset
+            )))
+        }
+    }
+    Ok(())
+}
+
+pub fn check_comment_tag(tag: &str) -> Result<(), NeatError> {
+    // SAM optional fields look like TG:T:VALUE, with a two character tag starting with a letter
+    // and one of the SAM type codes.
+    let parts: Vec<&str> = tag.splitn(3, ':').collect();
+    let valid = parts.len() == 3
+        && parts[0].len() == 2
+        && parts[0].chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && parts[0].chars().all(|c| c.is_ascii_alphanumeric())
+        && ["A", "i", "f", "Z", "H", "B"].contains(&parts[1])
+        && !parts[2].is_empty()
+        && !tag.contains(char::is_whitespace);
+    if valid {
+        Ok(())
+    } else {
+        Err(NeatError::Config(format!(
+            "Comment tag {} is not a SAM-style tag (like BC:Z:ACGTAC)", tag
+        )))
+    }
+}
+
+pub fn illumina_read_name(run: &IlluminaRun, (tile, x, y): (usize, usize, usize)) -> String {
+    // Synthesizes the name part of an Illumina read header for a cluster of this run at this
+    // tile, x and y (see FlowcellGeometry::location).
+    format!(
+        "{}:{}:{}:{}:{}:{}:{}",
+        run.instrument, ILLUMINA_RUN, run.flowcell, run.lane, tile, x, y,
+    )
+}
+
+pub fn complement(nucleotide: u8) -> u8 {
+    // 0 = A, 1 = C, 2 = G, 3 = T,
+    // matches with the complement of each nucleotide. Written without a match so the loop in
+    // reverse_complement has no branches and the compiler can vectorize it.
+    if nucleotide < 4 { 3 - nucleotide } else { 4 }
+}
+
+pub fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    // Returns the reverse complement of a vector of u8's representing a DNA sequence.
+    sequence.iter().rev().map(|&nucleotide| complement(nucleotide)).collect()
 }
 
 pub fn write_fastq(
-    fastq_filename: &str,
-    overwrite_output: bool,
-    paired_ended: bool,
-    dataset: Vec<&Vec<u8>>,
-    dataset_order: Vec<usize>,
-    quality_score_model: QualityScoreModel,
-    mut rng: &mut Rng,
-) -> io::Result<()> {
+    r1_writer: &mut impl Write,
+    r2_writer: &mut impl Write,
+    reads: Vec<&SimulatedRead>,
+    fastq_format: &FastqFormat,
+) -> Result<PairedRecordCounts, NeatError> {
     // Takes:
-    // fastq_filename: prefix for the output fastq files.
-    // paired_ended: boolean to set paired ended mode on or off.
-    // dataset: List of u8 vectors representing dna sequences.
+    // r1_writer: Where to write read 1 (and single ended reads).
+    // r2_writer: Where to write read 2 of each pair. Single ended runs can pass io::sink().
+    // reads: The reads to write, in output order. Mate 1 reads go to r1_writer and mate 2 reads
+    //     go to r2_writer, so pairs should be kept in the same relative order.
+    // fastq_format: How header lines and quality scores are written. The name format should
+    //     match the format the reads were named with.
     // returns:
-    // Error if there is a problem or else nothing.
+    // The number of records written to each file, or an error if there is a problem or the
+    // mates of the two files would fall out of step.
     //
-    // Writes fastq files. At the moment, it only writes out single r1 file, but will eventually write
-    // out r1 and r2 files.
-
-    // name_prefix is for the prefix for the read names. Reads are numbered in output order
-    // (Although this feature is currently untested and unknown).
-    // (May need sorting.)
-    let name_prefix = "neat_generated_".to_string();
-    let mut filename1 = String::from(fastq_filename) + "_r1.fastq";
-    // open the file and append lines
-    let mut outfile1 = open_file(&mut filename1, overwrite_output)
-        .expect(&format!("Error opening output {}", filename1));
-    // setting up pairend ended reads For single ended reads, this will go unused.
-    let mut filename2 = String::from(fastq_filename) + "_r2.fastq";
-    // open the second file and append lines
-    let mut outfile2 = open_file(&mut filename2, overwrite_output)
-        .expect(&format!("Error opening output {}", filename2));
-    // write sequences. Orderd index is used for numbering, while read_index is from the shuffled
-    // index array from a previous step
-    for (order_index, read_index) in dataset_order.iter().enumerate() {
-        let sequence = dataset[*read_index].clone();
-        // This assumes that the sequence length is the correct length at this point.
-        let read_length = sequence.len() as u32;
-        // Need to convert the raw scores to a string
-        let quality_scores = quality_score_model.generate_quality_scores(
-            read_length as usize, &mut rng
-        );
-        // sequence name
-        writeln!(&mut outfile1, "@{}{}/1", name_prefix.clone(), order_index + 1)?;
-        // Array as a string
-        writeln!(&mut outfile1, "{}", sequence_array_to_string(&sequence))?;
-        // The stupid plus sign
-        writeln!(&mut outfile1, "+")?;
-        // Qual score of all F's for the whole thing.
-        writeln!(&mut outfile1, "{}", quality_scores_to_str(quality_scores))?;
-        if paired_ended {
-            // Need a quality score for this read as well
-            let quality_scores = quality_score_model.generate_quality_scores(
-                read_length as usize, &mut rng
-            );
-            // sequence name
-            writeln!(&mut outfile2, "@{}{}/2", name_prefix.clone(), order_index + 1)?;
-            // Array as a string
-            writeln!(&mut outfile2, "{}", sequence_array_to_string(&reverse_complement(&sequence)))?;
-            // The stupid plus sign
-            writeln!(&mut outfile2, "+")?;
-            // Qual score of all F's for the whole thing.
-            writeln!(&mut outfile2, "{}", quality_scores_to_str(quality_scores))?;
+    // Writes fastq records. Reads already carry their sequence in sequencing orientation and
+    // their quality scores, so this just formats them. Each record is assembled in one buffer,
+    // reused for every read, and written with a single write_all. Every mate 2 read has to be
+    // the mate of the oldest mate 1 read still waiting for one, and once there are any, every
+    // mate 1 read has to get its mate.
+    let mut record: Vec<u8> = Vec::new();
+    let mut counts = PairedRecordCounts::default();
+    let mut waiting: VecDeque<&str> = VecDeque::new();
+    for read in reads {
+        if read.mate == 2 {
+            let mate = waiting.pop_front();
+            if mate != Some(read.name.as_str()) {
+                return Err(pair_mismatch(&counts, mate, Some(&read.name)))
+            }
+            write_fastq_record(r2_writer, read, fastq_format, &mut record)?;
+            counts.r2 += 1;
+        } else {
+            write_fastq_record(r1_writer, read, fastq_format, &mut record)?;
+            counts.r1 += 1;
+            waiting.push_back(&read.name);
         }
     };
-    if !paired_ended {
-        fs::remove_file(filename2)?;
+    if counts.r2 > 0 {
+        if let Some(name) = waiting.front() {
+            return Err(pair_mismatch(&counts, Some(name), None))
+        }
     }
-    Ok(())
+    Ok(counts)
 }
 
-fn quality_scores_to_str(array: Vec<u32>) -> String {
-    let mut score_text = String::new();
-    for score in array {
-        score_text += &(((score + 33) as u8) as char).to_string();
+pub fn write_fastq_record(
+    writer: &mut impl Write,
+    read: &SimulatedRead,
+    fastq_format: &FastqFormat,
+    record: &mut Vec<u8>,
+) -> io::Result<()> {
+    // Takes:
+    // writer: Where to write the record.
+    // read: The read to write.
+    // fastq_format: How the header line and quality scores are written.
+    // record: Scratch space for assembling the record. Whatever is in it is discarded; passing
+    //     the same buffer for every read saves allocating one each time.
+    // Returns:
+    // Error if there is a problem or else nothing.
+    record.clear();
+    // sequence name
+    match fastq_format.name_format {
+        ReadNameFormat::Simple => write!(record, "@{}/{}", read.name, read.mate)?,
+        // The filter field is Y for reads that failed the quality filter
+        ReadNameFormat::Illumina => write!(
+            record,
+            "@{} {}:{}:0:{}",
+            read.name,
+            read.mate,
+            if read.qc_fail { 'Y' } else { 'N' },
+            fastq_format.sample_index.as_deref().unwrap_or(ILLUMINA_INDEX)
+        )?,
+    }
+    // comments
+    for tag in &fastq_format.comment_tags {
+        record.push(b'\t');
+        record.extend_from_slice(tag.as_bytes());
+    }
+    // Duplex UMIs go where aligners (e.g. bwa mem -C) copy them into the bam
+    if let Some(duplex) = &read.duplex {
+        write!(record, "\tRX:Z:{}", duplex.umi)?;
+    }
+    if fastq_format.truth_tags {
+        let strand = match read.strand {
+            Strand::Forward => '+',
+            Strand::Reverse => '-',
+        };
+        write!(record, "\tXC:Z:{}\tXP:i:{}\tXS:A:{}", read.contig, read.start + 1, strand)?;
+        if let Some(duplex) = &read.duplex {
+            write!(record, "\tMI:Z:{}", duplex.molecule_id)?;
+        }
     }
-    score_text
+    if let Some(threshold) = fastq_format.trim_threshold {
+        let max_quality = capped_quality(fastq_format.quality_offset, fastq_format.max_quality);
+        let trimmed_length = read.quals
+            .iter()
+            .rposition(|score| *score.min(&max_quality) >= threshold)
+            .map_or(0, |index| index + 1);
+        write!(record, "\tXT:i:{}", trimmed_length)?;
+    }
+    record.push(b'\n');
+    // The bases, as text
+    record.extend(read.seq.iter().map(|&base| BASE_CHARS[base.min(4) as usize]));
+    // The stupid plus sign
+    record.extend_from_slice(b"\n+\n");
+    // Quality scores, encoded as text.
+    push_quality_scores(
+        record, &read.quals, fastq_format.quality_offset, fastq_format.max_quality
+    );
+    record.push(b'\n');
+    writer.write_all(record)
+}
+
+fn capped_quality(offset: u32, max_quality: Option<u32>) -> u32 {
+    // The highest score written, given the offset and any cap.
+    max_quality.unwrap_or(MAX_QUALITY_CHAR - offset).min(MAX_QUALITY_CHAR - offset)
+}
+
+fn push_quality_scores(record: &mut Vec<u8>, array: &[u32], offset: u32, max_quality: Option<u32>) {
+    // Appends the quality scores as text, capped so every character is printable.
+    let max_quality = capped_quality(offset, max_quality);
+    record.extend(array.iter().map(|score| (score.min(&max_quality) + offset) as u8));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::Path;
+    use super::super::make_reads::DuplexTag;
+
+    fn test_read(name: &str, seq: Vec<u8>, mate: u8) -> SimulatedRead {
+        SimulatedRead {
+            name: name.to_string(),
+            quals: vec![37; seq.len()],
+            strand: if mate == 2 { Strand::Reverse } else { Strand::Forward },
+            mate,
+            ..SimulatedRead::for_test("chr1", 0, seq)
+        }
+    }
 
     #[test]
     fn test_complement() {
@@ -138,65 +432,155 @@ mod tests {
 
     #[test]
     fn test_write_fastq_single() {
-        let fastq_filename = "test_single";
-        let overwrite_output = true;
-        let paired_ended = false;
-        let seq1 = vec![0, 0, 0, 0, 1, 1, 1, 1];
-        let seq2 = vec![2, 2, 2, 2, 3, 3, 3, 3,];
-        let mut rng = Rng::new_from_seed(vec![
-            "Hello".to_string(),
-            "Cruel".to_string(),
-            "World".to_string(),
-        ]);
-        let dataset = vec![&seq1, &seq2];
-        let dataset_order = vec![1, 0];
-        let quality_score_model = QualityScoreModel::new();
-        write_fastq(
-            fastq_filename,
-            overwrite_output,
-            paired_ended,
-            dataset,
-            dataset_order,
-            quality_score_model,
-            &mut rng,
-        ).unwrap();
-        let outfile1 = Path::new("test_single_r1.fastq");
-        let outfile2 = Path::new("test_single_r2.fastq");
-        assert!(outfile1.exists());
-        assert!(!outfile2.exists());
-        fs::remove_file(outfile1).unwrap();
+        let read1 = test_read("read1", vec![0, 0, 0, 0, 1, 1, 1, 1], 1);
+        let read2 = test_read("read2", vec![2, 2, 2, 2, 3, 3, 3, 3], 1);
+        let mut r1: Vec<u8> = Vec::new();
+        write_fastq(&mut r1, &mut io::sink(), vec![&read2, &read1], &FastqFormat::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(r1).unwrap(),
+            "@read2/1\nGGGGTTTT\n+\nFFFFFFFF\n@read1/1\nAAAACCCC\n+\nFFFFFFFF\n"
+        );
     }
 
     #[test]
     fn test_write_fastq_paired() {
-        let fastq_filename = "test_paired";
-        // might as well test the o_o function as well
-        let overwrite_output = false;
-        let paired_ended = true;
-        let seq1 = vec![0, 0, 0, 0, 1, 1, 1, 1];
-        let seq2 = vec![2, 2, 2, 2, 3, 3, 3, 3,];
-        let mut rng = Rng::new_from_seed(vec![
-            "Hello".to_string(),
-            "Cruel".to_string(),
-            "World".to_string(),
-        ]);
-        let dataset = vec![&seq1, &seq2];
-        let dataset_order = vec![1, 0];
-        let quality_score_model = QualityScoreModel::new();
-        write_fastq(
-            fastq_filename,
-            overwrite_output,
-            paired_ended,
-            dataset,
-            dataset_order,
-            quality_score_model,
-            &mut rng,
-        ).unwrap();
-        let outfile1 = Path::new("test_paired_r1.fastq");
-        let outfile2 = Path::new("test_paired_r2.fastq");
-        assert!(outfile1.exists());
-        assert!(outfile2.exists());
-        fs::remove_file(outfile1).unwrap();
-        fs::remove_file(outfile2).unwrap();
-    }
-}
\ No newline at end of file
+        let read1 = test_read("read1", vec![0, 0, 0, 0, 1, 1, 1, 1], 1);
+        let read2 = test_read("read1", vec![2, 2, 2, 2, 3, 3, 3, 3], 2);
+        let mut r1: Vec<u8> = Vec::new();
+        let mut r2: Vec<u8> = Vec::new();
+        write_fastq(&mut r1, &mut r2, vec![&read1, &read2], &FastqFormat::default()).unwrap();
+        assert_eq!(String::from_utf8(r1).unwrap(), "@read1/1\nAAAACCCC\n+\nFFFFFFFF\n");
+        assert_eq!(String::from_utf8(r2).unwrap(), "@read1/2\nGGGGTTTT\n+\nFFFFFFFF\n");
+    }
+
+    #[test]
+    fn test_write_fastq_pair_check() {
+        let read1 = test_read("read1", vec![0, 0, 0, 0], 1);
+        let mate1 = test_read("read1", vec![3, 3, 3, 3], 2);
+        let read2 = test_read("read2", vec![1, 1, 1, 1], 1);
+        let mate2 = test_read("read2", vec![2, 2, 2, 2], 2);
+        let write = |reads: Vec<&SimulatedRead>| {
+            write_fastq(&mut Vec::new(), &mut Vec::new(), reads, &FastqFormat::default())
+        };
+        // Mates can come after other pairs' read 1, as long as they keep the same order
+        assert_eq!(
+            write(vec![&read1, &read2, &mate1, &mate2]).unwrap(),
+            PairedRecordCounts { r1: 2, r2: 2 }
+        );
+        assert_eq!(write(vec![&read1, &read2]).unwrap(), PairedRecordCounts { r1: 2, r2: 0 });
+        // Out of order mates, a mate with nothing before it and a read 1 left without its mate
+        let message = write(vec![&read1, &read2, &mate2, &mate1]).unwrap_err().to_string();
+        assert!(message.contains("read 1 has read1, read 2 has read2"));
+        assert!(write(vec![&mate1, &read1]).is_err());
+        let message = write(vec![&read1, &mate1, &read2]).unwrap_err().to_string();
+        assert!(message.contains("read 1 has read2, read 2 has no more records"));
+
+        assert_eq!(fastq_record_name(b"@read1/2\tXC:Z:chr1\nACGT\n"), b"read1");
+        assert_eq!(fastq_record_name(b"@NEAT:1:NEATSIM01 1:N:0:ATCACG"), b"NEAT:1:NEATSIM01");
+    }
+
+    #[test]
+    fn test_write_fastq_illumina() {
+        let location = FlowcellGeometry::default().location(2, 25001);
+        let name = illumina_read_name(&IlluminaRun::default(), location);
+        assert_eq!(name, "NEAT:1:NEATSIM01:1:1103:1001:1001");
+        // The flowcell and lane come from an Illumina-style platform unit, if there is one
+        let run = IlluminaRun::new("A00123", Some("HXXXXDSX2.3.ATCACG"));
+        assert_eq!(illumina_read_name(&run, location), "A00123:1:HXXXXDSX2:3:1103:1001:1001");
+        assert_eq!(IlluminaRun::new("NEAT", Some("unit1")), IlluminaRun::default());
+        // Clusters spaced out to leave room for optical duplicates
+        let geometry = FlowcellGeometry { tile_width: 1000, optical_distance: Some(100) };
+        assert_eq!(geometry.location(0, 0), (1101, 1000, 1000));
+        assert_eq!(geometry.location(0, 3), (1101, 1603, 1000));
+        assert_eq!(geometry.location(1, 5), (1102, 1201, 1201));
+        let read1 = test_read(&name, vec![0, 0, 0, 0, 1, 1, 1, 1], 1);
+        let read2 = test_read(&name, vec![2, 2, 2, 2, 3, 3, 3, 3], 2);
+        let mut r1: Vec<u8> = Vec::new();
+        let mut r2: Vec<u8> = Vec::new();
+        let header = FastqFormat { name_format: ReadNameFormat::Illumina, ..Default::default() };
+        write_fastq(&mut r1, &mut r2, vec![&read1, &read2], &header).unwrap();
+        assert!(String::from_utf8(r1).unwrap().starts_with(&format!("@{} 1:N:0:ATCACG\n", name)));
+        assert!(String::from_utf8(r2).unwrap().starts_with(&format!("@{} 2:N:0:ATCACG\n", name)));
+        let failed = SimulatedRead { qc_fail: true, ..read1.clone() };
+        let mut written = Vec::new();
+        write_fastq_record(&mut written, &failed, &header, &mut Vec::new()).unwrap();
+        assert!(String::from_utf8(written).unwrap().starts_with(&format!("@{} 1:Y:0:", name)));
+        assert_eq!(ReadNameFormat::parse("Illumina").unwrap(), ReadNameFormat::Illumina);
+        assert!(ReadNameFormat::parse("casava").is_err());
+    }
+
+    #[test]
+    fn test_write_fastq_comments() {
+        let read = test_read("read1", vec![0, 0, 0, 0], 1);
+        let header = FastqFormat {
+            name_format: ReadNameFormat::Simple,
+            comment_tags: vec!["BC:Z:ACGTAC".to_string()],
+            truth_tags: true,
+            ..Default::default()
+        };
+        let mut r1: Vec<u8> = Vec::new();
+        write_fastq(&mut r1, &mut io::sink(), vec![&read], &header).unwrap();
+        let text = String::from_utf8(r1).unwrap();
+        let header_line = text.lines().next().unwrap();
+        assert_eq!(
+            header_line,
+            format!("@read1/1\tBC:Z:ACGTAC\tXC:Z:{}\tXP:i:{}\tXS:A:+", read.contig, read.start + 1)
+        );
+        let mut duplex_read = read.clone();
+        duplex_read.duplex = Some(DuplexTag {
+            umi: "AACC-GGTT".to_string(),
+            molecule_id: "0_1/A".to_string(),
+        });
+        let mut r1: Vec<u8> = Vec::new();
+        write_fastq(&mut r1, &mut io::sink(), vec![&duplex_read], &header).unwrap();
+        assert!(String::from_utf8(r1).unwrap().starts_with(&format!(
+            "@read1/1\tBC:Z:ACGTAC\tRX:Z:AACC-GGTT\tXC:Z:{}\tXP:i:{}\tXS:A:+\tMI:Z:0_1/A\n",
+            read.contig, read.start + 1
+        )));
+        assert!(check_comment_tag("BC:Z:ACGTAC").is_ok());
+        assert!(check_comment_tag("XH:i:1").is_ok());
+        assert!(check_comment_tag("barcode").is_err());
+        assert!(check_comment_tag("BC:Q:ACGT").is_err());
+        assert!(check_comment_tag("BC:Z:has space").is_err());
+    }
+
+    #[test]
+    fn test_write_fastq_trim_position() {
+        let mut read = test_read("read1", vec![0; 8], 1);
+        read.quals = vec![37, 37, 12, 37, 37, 2, 2, 2];
+        let mut header = FastqFormat { trim_threshold: Some(20), ..Default::default() };
+        let header_line = |header: &FastqFormat, read: &SimulatedRead| {
+            let mut r1: Vec<u8> = Vec::new();
+            write_fastq(&mut r1, &mut io::sink(), vec![read], header).unwrap();
+            String::from_utf8(r1).unwrap().lines().next().unwrap().to_string()
+        };
+        // Trimming stops at the last good base, low bases before it are kept
+        assert_eq!(header_line(&header, &read), "@read1/1\tXT:i:5");
+        read.quals = vec![2; 8];
+        assert_eq!(header_line(&header, &read), "@read1/1\tXT:i:0");
+        // Scores are judged as written, after the cap
+        read.quals = vec![37; 8];
+        header.max_quality = Some(15);
+        assert_eq!(header_line(&header, &read), "@read1/1\tXT:i:0");
+    }
+
+    fn quality_scores_to_str(array: &[u32], offset: u32, max_quality: Option<u32>) -> String {
+        let mut record = Vec::new();
+        push_quality_scores(&mut record, array, offset, max_quality);
+        String::from_utf8(record).unwrap()
+    }
+
+    #[test]
+    fn test_quality_encoding() {
+        let scores = vec![0, 2, 30, 41];
+        assert_eq!(quality_scores_to_str(&scores, 33, None), "!#?J");
+        assert_eq!(quality_scores_to_str(&scores, 64, None), "@B^i");
+        assert_eq!(quality_scores_to_str(&scores, 33, Some(30)), "!#??");
+        // Never past '~', even without a cap
+        assert_eq!(quality_scores_to_str(&scores, 100, None), "df~~");
+        assert!(check_quality_encoding(64, Some(41)).is_ok());
+        assert!(check_quality_encoding(20, None).is_err());
+        assert!(check_quality_encoding(100, Some(41)).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+// FFPE deamination artifacts. Formalin fixation deaminates cytosines to uracil, which library
+// PCR copies as thymine, so reads from a damaged strand show C>T at low allele fractions that
+// look like somatic variants. The damage is on one strand of the original molecule: a C on the
+// top strand shows as C>T in pairs whose read 1 reads the top strand (F1R2), and a C on the bottom
+// strand as G>A in pairs whose read 1 reads the bottom strand (F2R1). That orientation bias is
+// what filters like Mutect2's read orientation model look for. Runs that model it pick artifact
+// sites at C and G bases of the reference, and each fragment over a site, from the strand the
+// site is damaged on, carries the artifact with the site's fraction. Short fragments come from
+// the most damaged DNA, so the fraction scales with the mean fragment length over the fragment's.
+// The sites are written to their own vcf, so they are never mistaken for true variants.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use simple_rng::Rng;
+use super::nucleotides::u8_to_base;
+use super::provenance::Provenance;
+
+// The fraction of fragments of the mean length over a site that carry its artifact, unless set.
+// Artifacts are rare and show up at a few percent, near the limit somatic callers work to.
+pub const DEFAULT_ARTIFACT_FRACTION: f64 = 0.05;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FfpeArtifacts {
+    // sites: The sorted positions of each contig that carry artifacts.
+    // fraction: The fraction of fragments of the mean length over a site that carry it.
+    // mean_fragment: The run's mean fragment length.
+    pub sites: HashMap<String, Vec<usize>>,
+    pub fraction: f64,
+    pub mean_fragment: f64,
+}
+
+pub fn ffpe_artifact_sites(
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    seeds: &[Vec<String>],
+    rate: f64,
+    fraction: f64,
+    mean_fragment: f64,
+) -> FfpeArtifacts {
+    // Takes:
+    // fasta_map: The reference sequences, keyed by contig name.
+    // fasta_order: Contig names in reference order.
+    // seeds: The seed of each contig (see contig_seeds). Sites come from their own stream of it,
+    //     so the reads don't change.
+    // rate: The fraction of bases that are artifact sites. Only C and G bases are kept.
+    // fraction, mean_fragment: As in FfpeArtifacts.
+    // Returns:
+    // The artifact sites of every contig.
+    let mut sites = HashMap::new();
+    for (contig, seed) in fasta_order.iter().zip(seeds) {
+        let sequence = &fasta_map[contig];
+        let mut seed = seed.clone();
+        seed.push("ffpe artifacts".to_string());
+        let mut rng = Rng::new_from_seed(seed);
+        // Sites are a geometric distance apart, so only the sites themselves are drawn.
+        let mut contig_sites = Vec::new();
+        let mut position = 0;
+        loop {
+            let gap = if rate >= 1.0 {
+                1.0
+            } else {
+                ((1.0 - rng.random()).ln() / (1.0 - rate).ln()).floor() + 1.0
+            };
+            position += gap.min(sequence.len() as f64) as usize;
+            if position > sequence.len() {
+                break
+            }
+            if matches!(sequence[position - 1], 1 | 2) {
+                contig_sites.push(position - 1);
+            }
+        }
+        if !contig_sites.is_empty() {
+            sites.insert(contig.clone(), contig_sites);
+        }
+    }
+    FfpeArtifacts { sites, fraction, mean_fragment }
+}
+
+impl FfpeArtifacts {
+    pub fn fragment_rate(&self, fragment_length: usize) -> f64 {
+        // The chance a fragment this long carries the artifact at a site it covers.
+        (self.fraction * self.mean_fragment / fragment_length.max(1) as f64).min(1.0)
+    }
+}
+
+pub fn deaminated_base(base: u8, top_strand: bool) -> Option<u8> {
+    // The base a read of a fragment from this strand shows where it is damaged: C>T if read 1
+    // reads the top strand, G>A if it reads the bottom one. Other bases aren't damaged.
+    match (base, top_strand) {
+        (1, true) => Some(3),
+        (2, false) => Some(0),
+        _ => None,
+    }
+}
+
+pub fn write_artifact_vcf(
+    outfile: &mut impl Write,
+    artifacts: &FfpeArtifacts,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    reference_path: &str,
+    provenance: &Provenance,
+) -> io::Result<()> {
+    // Writes the artifact sites as a vcf, each filtered as ffpe_artifact, with the alt allele
+    // the damage shows as, the orientation of the pairs that show it and the fraction of fragments
+    // of the mean length that do.
+    writeln!(outfile, "##fileformat=VCFv4.1")?;
+    writeln!(outfile, "##reference={}", reference_path)?;
+    writeln!(outfile, "##Generated by rusty-neat")?;
+    write!(outfile, "{}", provenance.vcf_header_lines())?;
+    writeln!(
+        outfile,
+        "##FILTER=<ID=ffpe_artifact,Description=\"FFPE deamination artifact, not a variant\">"
+    )?;
+    writeln!(
+        outfile,
+        "##INFO=<ID=AF,Number=A,Type=Float,Description=\"Fraction of fragments of the mean \
+        length with the artifact\">"
+    )?;
+    writeln!(
+        outfile,
+        "##INFO=<ID=ORIENTATION,Number=1,Type=String,Description=\"Orientation of the pairs \
+        with the artifact (F1R2 or F2R1)\">"
+    )?;
+    writeln!(outfile, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO")?;
+    for contig in fasta_order {
+        let Some(sites) = artifacts.sites.get(contig) else { continue };
+        for position in sites {
+            let reference = fasta_map[contig][*position];
+            let (alt, orientation) = match deaminated_base(reference, true) {
+                Some(alt) => (alt, "F1R2"),
+                None => (deaminated_base(reference, false).unwrap(), "F2R1"),
+            };
+            writeln!(
+                outfile,
+                "{}\t{}\t.\t{}\t{}\t.\tffpe_artifact\tAF={};ORIENTATION={}",
+                contig,
+                position + 1,
+                u8_to_base(reference),
+                u8_to_base(alt),
+                artifacts.fraction,
+                orientation,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::ConfigBuilder;
+
+    #[test]
+    fn test_ffpe_artifact_sites() {
+        let fasta_map = HashMap::from([
+            ("chr1".to_string(), vec![0, 1, 2, 3].repeat(5000)),
+            ("chr2".to_string(), vec![0; 100]),
+        ]);
+        let order = vec!["chr1".to_string(), "chr2".to_string()];
+        let seeds = vec![
+            vec!["1".to_string(), "chr1".to_string()],
+            vec!["2".to_string(), "chr2".to_string()],
+        ];
+        let artifacts = ffpe_artifact_sites(&fasta_map, &order, &seeds, 0.01, 0.1, 300.0);
+        // About 1 in 100 bases, but only the C's and G's, and none of a poly-A
+        let sites = &artifacts.sites["chr1"];
+        assert!((50..150).contains(&sites.len()), "{} sites", sites.len());
+        assert!(sites.iter().all(|position| matches!(fasta_map["chr1"][*position], 1 | 2)));
+        assert!(sites.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(!artifacts.sites.contains_key("chr2"));
+        assert_eq!(artifacts, ffpe_artifact_sites(&fasta_map, &order, &seeds, 0.01, 0.1, 300.0));
+
+        // Shorter fragments carry more artifacts
+        assert_eq!(artifacts.fragment_rate(300), 0.1);
+        assert_eq!(artifacts.fragment_rate(150), 0.2);
+        assert_eq!(deaminated_base(1, true), Some(3));
+        assert_eq!(deaminated_base(1, false), None);
+        assert_eq!(deaminated_base(2, false), Some(0));
+
+        let mut vcf = Vec::new();
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("ref.fa".to_string());
+        let provenance = Provenance::new(&config.build());
+        write_artifact_vcf(&mut vcf, &artifacts, &fasta_map, &order, "ref.fa", &provenance)
+            .unwrap();
+        let vcf = String::from_utf8(vcf).unwrap();
+        let records: Vec<&str> = vcf.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(records.len(), sites.len());
+        assert!(records.iter().all(|record| {
+            record.contains("\tC\tT\t.\tffpe_artifact\tAF=0.1;ORIENTATION=F1R2")
+                || record.contains("\tG\tA\t.\tffpe_artifact\tAF=0.1;ORIENTATION=F2R1")
+        }));
+    }
+}
@@ -2,9 +2,12 @@
 
 use std::fs::File;
 use std::{fs, io};
-use std::io::{BufRead, Error};
-use std::path::Path;
-use log::warn;
+use std::io::{BufRead, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use log::{debug, info, warn};
+use tempfile::TempDir;
+use super::errors::NeatError;
+use super::throttle::{Throttle, ThrottledFile};
 
 pub fn read_lines(filename: &str) -> io::Result<io::Lines<io::BufReader<File>>> {
     // This creates a buffer to read lines
@@ -12,30 +15,137 @@ pub fn read_lines(filename: &str) -> io::Result<io::Lines<io::BufReader<File>>>
     Ok(io::BufReader::new(file).lines())
 }
 
-pub fn open_file(mut filename: &mut str, overwrite_file: bool) -> Result<File, Error> {
-    if overwrite_file && Path::new(filename).exists() {
-        File::options().create(true).write(true).open(&mut filename)
+pub fn open_file(filename: &mut str, overwrite_file: bool) -> Result<File, NeatError> {
+    // Opens a file for output. Unless overwrite_file is set, an existing file is an error rather
+    // than something to clobber.
+    let result = if overwrite_file && Path::new(filename).exists() {
+        File::options().create(true).write(true).truncate(true).open(&*filename)
     } else {
-        File::options().create_new(true).append(true).open(&mut filename)
-    }
+        File::options().create_new(true).append(true).open(&*filename)
+    };
+    result.map_err(|error| match error.kind() {
+        ErrorKind::AlreadyExists => NeatError::OutputExists(PathBuf::from(&*filename)),
+        _ => NeatError::Io(error),
+    })
 }
 
 pub fn check_parent(filename: &str) -> io::Result<&Path> {
     // checks that the parent dir exists and then if so creates the Path object open
-    // and ready to write
+    // and ready to write. A bare file name has an empty parent, which is the current directory.
     let file_path = Path::new(filename);
-    if !file_path.parent().unwrap().exists() {
-        check_create_dir(file_path);
-    };
-    Ok(file_path)
+    match file_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && !parent.is_dir() => {
+            Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("Directory not found: {}", parent.display()),
+            ))
+        },
+        _ => Ok(file_path),
+    }
+}
+
+pub fn output_path(output_dir: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    // The path of an output file, <output_dir>/<prefix><suffix>, joined with the separator of
+    // the platform we're on.
+    output_dir.join(format!("{}{}", prefix, suffix))
 }
 
-pub fn check_create_dir(path_to_check: &Path) {
+pub fn check_create_dir(path_to_check: &Path) -> io::Result<()> {
     if !path_to_check.is_dir() {
         warn!("Directory not found, creating: {:?}", path_to_check);
-        fs::create_dir(path_to_check).expect(
-            "Error creating the directory"
-        );
+        fs::create_dir(path_to_check)?;
+    }
+    Ok(())
+}
+
+pub fn check_free_space(directory: &Path, required: u64) -> Result<(), NeatError> {
+    // Fails early if the filesystem holding directory does not have the required number of bytes
+    // free, rather than running out of space halfway through writing a fastq.
+    let available = fs2::available_space(directory)?;
+    debug!("{} bytes free in {}, need about {}", available, directory.display(), required);
+    if available < required {
+        return Err(NeatError::InsufficientSpace {
+            path: directory.to_path_buf(),
+            required,
+            available,
+        })
+    }
+    Ok(())
+}
+
+pub struct StagedOutputs {
+    // Output files are written into a scratch directory first and only moved to their final
+    // names once everything has been written. The scratch directory is removed when this is
+    // dropped, so a failed (or panicking) run doesn't leave partial files behind.
+    //
+    // scratch: The temporary directory the files are written into.
+    // staged: (scratch path, final path) pairs for each file created.
+    // overwrite_output: Whether existing final files may be replaced.
+    // throttle: The rate every file is written at, for throttled runs (see throttle.rs).
+    scratch: TempDir,
+    staged: Vec<(PathBuf, PathBuf)>,
+    overwrite_output: bool,
+    throttle: Option<Throttle>,
+}
+
+impl StagedOutputs {
+    pub fn new(temp_parent: &Path, overwrite_output: bool) -> Result<Self, NeatError> {
+        let scratch = tempfile::Builder::new()
+            .prefix(".neat_tmp_")
+            .tempdir_in(temp_parent)?;
+        debug!("Staging output in {}", scratch.path().display());
+        Ok(StagedOutputs { scratch, staged: Vec::new(), overwrite_output, throttle: None })
+    }
+
+    pub fn throttled(mut self, throttle: Option<Throttle>) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    pub fn throttle(&self) -> Option<&Throttle> {
+        // For the intermediate files in the scratch directory, so they count against the same
+        // rate.
+        self.throttle.as_ref()
+    }
+
+    pub fn scratch_dir(&self) -> &Path {
+        // For intermediate files that should be cleaned up along with the staged output.
+        self.scratch.path()
+    }
+
+    pub fn create(&mut self, final_path: &Path) -> Result<BufWriter<ThrottledFile>, NeatError> {
+        // Opens the scratch copy of final_path for writing. An existing final file is reported
+        // now, before any work goes into writing it.
+        if !self.overwrite_output && final_path.exists() {
+            return Err(NeatError::OutputExists(final_path.to_path_buf()))
+        }
+        let file_name = final_path.file_name().ok_or_else(|| {
+            NeatError::Config(format!("Output path has no file name: {}", final_path.display()))
+        })?;
+        let staged_path = self.scratch.path().join(file_name);
+        let file = File::options().create_new(true).write(true).open(&staged_path)?;
+        self.staged.push((staged_path, final_path.to_path_buf()));
+        Ok(BufWriter::new(ThrottledFile::new(file, self.throttle.as_ref())))
+    }
+
+    pub fn commit(self) -> Result<(), NeatError> {
+        // Moves every staged file to its final location. If the scratch directory is on another
+        // filesystem, rename fails and we fall back to copying (at the throttle's rate, if any).
+        for (staged_path, final_path) in &self.staged {
+            info!("Writing {}", final_path.display());
+            if fs::rename(staged_path, final_path).is_err() {
+                match &self.throttle {
+                    Some(throttle) => {
+                        let mut final_file = ThrottledFile::create(final_path, Some(throttle))?;
+                        io::copy(&mut File::open(staged_path)?, &mut final_file)?;
+                    },
+                    None => {
+                        fs::copy(staged_path, final_path)?;
+                    },
+                }
+            }
+        }
+        Ok(())
     }
 }
 
@@ -50,9 +160,68 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_check_parent_fail() {
         let filename = "fake/test.fa";
-        check_parent(filename).unwrap();
+        assert!(check_parent(filename).is_err());
+    }
+
+    #[test]
+    fn test_check_parent_bare_name() {
+        // No directory part, so the file goes in the current directory
+        check_parent("test.log").unwrap();
+        assert!(!Path::new("test.log").exists());
+    }
+
+    #[test]
+    fn test_output_path() {
+        let output_dir = Path::new("out").join("run");
+        assert_eq!(
+            output_path(&output_dir, "neat", "_r1.fastq"),
+            Path::new("out").join("run").join("neat_r1.fastq")
+        );
+        // A trailing separator on the directory doesn't double up
+        let output_dir = PathBuf::from(format!("out{}", std::path::MAIN_SEPARATOR));
+        assert_eq!(output_path(&output_dir, "neat", ".vcf"), Path::new("out").join("neat.vcf"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_open_file_exists() {
+        let mut filename = "test_data/H1N1.fa".to_string();
+        let error = open_file(&mut filename, false).unwrap_err();
+        assert!(matches!(error, NeatError::OutputExists(_)));
+    }
+
+    #[test]
+    fn test_check_free_space() {
+        check_free_space(Path::new("test_data"), 1).unwrap();
+        let error = check_free_space(Path::new("test_data"), u64::MAX).unwrap_err();
+        assert!(matches!(error, NeatError::InsufficientSpace { .. }));
+    }
+
+    #[test]
+    fn test_staged_outputs() {
+        use std::io::Write;
+        fs::create_dir("staged_test").unwrap();
+        let final_path = Path::new("staged_test/out.txt");
+        // Dropped without committing: nothing is left behind.
+        {
+            let mut staged = StagedOutputs::new(Path::new("staged_test"), false).unwrap();
+            writeln!(staged.create(final_path).unwrap(), "partial").unwrap();
+        }
+        assert_eq!(fs::read_dir("staged_test").unwrap().count(), 0);
+        // Committed: the file appears under its final name and the scratch dir is gone.
+        let mut staged = StagedOutputs::new(Path::new("staged_test"), false).unwrap();
+        let mut writer = staged.create(final_path).unwrap();
+        writeln!(writer, "done").unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        staged.commit().unwrap();
+        assert_eq!(fs::read_to_string(final_path).unwrap(), "done\n");
+        assert_eq!(fs::read_dir("staged_test").unwrap().count(), 1);
+        // A second run refuses to replace it without overwrite_output.
+        let mut staged = StagedOutputs::new(Path::new("staged_test"), false).unwrap();
+        assert!(matches!(staged.create(final_path), Err(NeatError::OutputExists(_))));
+        drop(staged);
+        fs::remove_dir_all("staged_test").unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+// Synthetic sequence for the gaps of a reference. References leave telomeres, centromeres and
+// other satellite arrays as runs of N, so no reads are ever simulated from them, though real
+// samples are full of them and they are where aligners and callers go wrong the most. Runs that
+// ask for it fill the gaps before anything else is simulated. A gap at either end of a contig gets
+// up to TELOMERE_LENGTH of the telomere repeat against the contig's end (CCCTAA reading in from
+// the start, TTAGGG reading out to the end, as on real chromosomes), and the rest of every gap
+// gets a satellite array built the way alpha satellite is: monomers diverged from one another,
+// strung into a higher-order repeat that is then repeated end to end, with a little divergence
+// between its copies. The monomers are random, not those of a real satellite, and each gap gets
+// its own array. Gaps shorter than MIN_FILLED_GAP are left as N.
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use simple_rng::Rng;
+use super::bed_tools::non_n_regions;
+
+// N runs shorter than this are ambiguous bases, not gaps.
+pub const MIN_FILLED_GAP: usize = 50;
+// Human telomeres are 5 to 15 kb.
+const TELOMERE_LENGTH: usize = 10_000;
+// Telomere repeats, as read along the forward strand at the start and at the end of a contig.
+const START_TELOMERE: [u8; 6] = [1, 1, 1, 3, 0, 0];
+const END_TELOMERE: [u8; 6] = [3, 3, 0, 2, 2, 2];
+// Alpha satellite monomers are 171 bp, 20-40% diverged from one another, and make up higher-order
+// repeats of a few to a few dozen monomers whose copies are only 1-2% diverged.
+const SATELLITE_MONOMER: usize = 171;
+const MONOMERS_PER_REPEAT: usize = 12;
+const MONOMER_DIVERGENCE: f64 = 0.25;
+const REPEAT_DIVERGENCE: f64 = 0.02;
+
+pub const TELOMERE: &str = "telomere";
+pub const SATELLITE: &str = "satellite";
+
+// The filled [start, end) spans of each contig, in order, with what they were filled with.
+pub type FilledGaps = HashMap<String, Vec<(usize, usize, &'static str)>>;
+
+fn diverge(unit: &[u8], rate: f64, rng: &mut Rng) -> Vec<u8> {
+    // A copy of the unit with each base changed to another at the rate.
+    unit.iter()
+        .map(|base| {
+            if rng.gen_bool(rate) { (base + rng.range_i64(1, 4) as u8) % 4 } else { *base }
+        })
+        .collect()
+}
+
+fn satellite_array(length: usize, rng: &mut Rng) -> Vec<u8> {
+    let monomer: Vec<u8> = (0..SATELLITE_MONOMER).map(|_| rng.range_i64(0, 4) as u8).collect();
+    let repeat: Vec<u8> = (0..MONOMERS_PER_REPEAT)
+        .flat_map(|_| diverge(&monomer, MONOMER_DIVERGENCE, rng))
+        .collect();
+    let mut array = Vec::with_capacity(length + repeat.len());
+    while array.len() < length {
+        array.extend(diverge(&repeat, REPEAT_DIVERGENCE, rng));
+    }
+    array.truncate(length);
+    array
+}
+
+pub fn fill_gaps(sequence: &mut [u8], rng: &mut Rng) -> Vec<(usize, usize, &'static str)> {
+    // Takes:
+    // sequence: A contig, in the u8 representation. Its gaps are filled in place.
+    // rng: For the satellite arrays.
+    // Returns:
+    // The spans filled, in order, with what they were filled with.
+    let length = sequence.len();
+    let mut gaps = Vec::new();
+    let mut gap_start = 0;
+    for (start, end) in non_n_regions(sequence).into_iter().chain([(length, length)]) {
+        if start - gap_start >= MIN_FILLED_GAP {
+            gaps.push((gap_start, start));
+        }
+        gap_start = end;
+    }
+
+    let mut filled = Vec::new();
+    for (mut start, mut end) in gaps {
+        let mut end_telomere = None;
+        if start == 0 {
+            start = end.min(TELOMERE_LENGTH);
+            for (position, base) in sequence[..start].iter_mut().enumerate() {
+                *base = START_TELOMERE[position % START_TELOMERE.len()];
+            }
+            filled.push((0, start, TELOMERE));
+        }
+        if end == length && start < end {
+            end = start.max(length.saturating_sub(TELOMERE_LENGTH));
+            // Phased to end on a whole repeat
+            for (position, base) in sequence[end..].iter_mut().enumerate() {
+                let (from_end, repeat) = (length - end - position, END_TELOMERE.len());
+                *base = END_TELOMERE[(repeat - from_end % repeat) % repeat];
+            }
+            end_telomere = Some((end, length, TELOMERE));
+        }
+        if start < end {
+            sequence[start..end].copy_from_slice(&satellite_array(end - start, rng));
+            filled.push((start, end, SATELLITE));
+        }
+        filled.extend(end_telomere);
+    }
+    filled
+}
+
+pub fn write_filled_gaps(
+    writer: &mut impl Write,
+    filled_gaps: &FilledGaps,
+    contig_order: &[String],
+) -> io::Result<()> {
+    // Writes the filled spans as a bed, with what each was filled with as its name.
+    for contig in contig_order {
+        for (start, end, fill) in filled_gaps.get(contig).map_or(&[][..], Vec::as_slice) {
+            writeln!(writer, "{}\t{}\t{}\t{}", contig, start, end, fill)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fill_gaps() {
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Gaps".to_string()]);
+        // A gap at each end, one inside, and a short run of N that isn't a gap
+        let mut sequence = vec![4; 100];
+        sequence.extend(vec![0; 200]);
+        sequence.extend(vec![4; 5000]);
+        sequence.extend(vec![1; 200]);
+        sequence.extend(vec![4; 10]);
+        sequence.extend(vec![2; 200]);
+        sequence.extend(vec![4; 60]);
+        let filled = fill_gaps(&mut sequence, &mut rng);
+        assert_eq!(
+            filled,
+            vec![(0, 100, TELOMERE), (300, 5300, SATELLITE), (5710, 5770, TELOMERE)]
+        );
+        assert_eq!(sequence[..6], START_TELOMERE);
+        assert_eq!(sequence[5764..], END_TELOMERE);
+        assert_eq!(sequence.iter().filter(|base| **base == 4).count(), 10);
+        // The satellite is a repeat, with copies a little apart
+        let repeat = SATELLITE_MONOMER * MONOMERS_PER_REPEAT;
+        let differences = (300..300 + repeat)
+            .filter(|position| sequence[*position] != sequence[position + repeat])
+            .count();
+        assert!(differences > 0 && differences < repeat / 10, "{} differences", differences);
+
+        // A contig that is all gap is telomere at both ends and satellite between
+        let mut gap = vec![4; 25_000];
+        let filled = fill_gaps(&mut gap, &mut rng);
+        assert_eq!(
+            filled,
+            vec![(0, 10_000, TELOMERE), (10_000, 15_000, SATELLITE), (15_000, 25_000, TELOMERE)]
+        );
+        let mut bed = Vec::new();
+        let filled_gaps = HashMap::from([("chr1".to_string(), filled)]);
+        write_filled_gaps(&mut bed, &filled_gaps, &["chr1".to_string()]).unwrap();
+        assert!(String::from_utf8(bed).unwrap().starts_with("chr1\t0\t10000\ttelomere\n"));
+    }
+}
@@ -0,0 +1,140 @@
+// Genotype calls for the truth vcf (genotype_calls). Callers report how sure they are of each
+// genotype (GQ) and how likely every possible genotype is (PL), and plenty of pipelines filter on
+// those rather than on the genotype alone. Each variant gets the depth of the reads written over
+// its site, counted from where they were drawn as the truth pileup counts them (see pileup.rs),
+// how many of them show the reference and the alt base, and the GQ and PL a caller would compute
+// from those counts. So the calls agree with the fastq and bam, and low depth and low allele
+// fraction sites come out with low qualities, as they would from a real caller.
+
+use super::variants::Variant;
+
+// The chance a read shows the wrong base at a site, used to work out the likelihoods. About Q20,
+// the usual rate for a caller's base quality filter.
+const SEQUENCING_ERROR_RATE: f64 = 0.01;
+
+// GQ is capped here, as GATK does.
+const MAX_GENOTYPE_QUALITY: u32 = 99;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteCall {
+    // depth: The number of reads over the site.
+    // ref_depth: How many of them show the reference base.
+    // alt_depth: How many of them show the alt base.
+    // genotype_quality: GQ, the phred scaled chance the most likely genotype is wrong.
+    // likelihoods: PL, the phred scaled likelihood of each genotype (0, 1, ... alt copies),
+    //     relative to the most likely one.
+    pub depth: usize,
+    pub ref_depth: usize,
+    pub alt_depth: usize,
+    pub genotype_quality: u32,
+    pub likelihoods: Vec<u32>,
+}
+
+impl SiteCall {
+    pub fn format_fields(&self) -> String {
+        // The DP, AD, GQ and PL values of the vcf sample column.
+        let likelihoods: Vec<String> = self.likelihoods.iter().map(|pl| pl.to_string()).collect();
+        format!(
+            "{}:{},{}:{}:{}",
+            self.depth,
+            self.ref_depth,
+            self.alt_depth,
+            self.genotype_quality,
+            likelihoods.join(",")
+        )
+    }
+}
+
+pub fn genotype_likelihoods(depth: usize, alt_depth: usize, ploidy: usize) -> (u32, Vec<u32>) {
+    // Takes:
+    // depth: The reads over the site.
+    // alt_depth: How many of them show the alt base.
+    // ploidy: The number of copies of the site.
+    // Returns:
+    // GQ and PL, from the binomial likelihood of the alt reads under each genotype.
+    let log_likelihoods: Vec<f64> = (0..=ploidy)
+        .map(|alt_copies| {
+            let fraction = alt_copies as f64 / ploidy as f64;
+            let alt_chance = fraction * (1.0 - SEQUENCING_ERROR_RATE)
+                + (1.0 - fraction) * SEQUENCING_ERROR_RATE;
+            alt_depth as f64 * alt_chance.log10()
+                + (depth - alt_depth) as f64 * (1.0 - alt_chance).log10()
+        })
+        .collect();
+    let best = log_likelihoods.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let likelihoods: Vec<u32> = log_likelihoods
+        .iter()
+        .map(|log_likelihood| (-10.0 * (log_likelihood - best)).round() as u32)
+        .collect();
+    let mut sorted = likelihoods.clone();
+    sorted.sort();
+    let genotype_quality = sorted.get(1).copied().unwrap_or(0).min(MAX_GENOTYPE_QUALITY);
+    (genotype_quality, likelihoods)
+}
+
+pub fn call_site(variant: &Variant, counts: &[u32; 5]) -> SiteCall {
+    // Takes:
+    // variant: A variant with its genotype assigned.
+    // counts: The reads over its site showing each base (A, C, G, T and N).
+    // Returns:
+    // The depth, reference and alt reads of the site, with the GQ and PL they give. Reads showing
+    // another base (sequencing errors) count towards the depth but say nothing about the genotype.
+    let depth = counts.iter().sum::<u32>() as usize;
+    let ref_depth = counts[variant.reference.min(4) as usize] as usize;
+    let alt_depth = counts[variant.alt.min(4) as usize] as usize;
+    let ploidy = variant.genotype.len().max(1);
+    let (genotype_quality, likelihoods) =
+        genotype_likelihoods(ref_depth + alt_depth, alt_depth, ploidy);
+    SiteCall { depth, ref_depth, alt_depth, genotype_quality, likelihoods }
+}
+
+pub fn call_sites(variants: &mut [Variant], site_counts: &[[u32; 5]]) {
+    // Gives each of a contig's variants its SiteCall, from the base counts at its site (in the
+    // same order).
+    for (variant, counts) in variants.iter_mut().zip(site_counts) {
+        variant.call = Some(call_site(variant, counts));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::variants::VariantOrigin;
+
+    #[test]
+    fn test_genotype_likelihoods() {
+        // Half the reads are alt: clearly heterozygous
+        let (genotype_quality, likelihoods) = genotype_likelihoods(40, 20, 2);
+        assert_eq!(likelihoods[1], 0);
+        assert!(likelihoods[0] > 99 && likelihoods[2] > 99);
+        assert_eq!(genotype_quality, 99);
+        // A couple of reads can't tell much apart
+        let (genotype_quality, likelihoods) = genotype_likelihoods(2, 2, 2);
+        assert_eq!(likelihoods[2], 0);
+        assert!(genotype_quality < 20);
+        // No reads, no information
+        assert_eq!(genotype_likelihoods(0, 0, 2), (0, vec![0, 0, 0]));
+    }
+
+    #[test]
+    fn test_call_sites() {
+        // A C>A heterozygous site, a somatic T>G site at a low fraction, and one with no reads
+        let mut heterozygous = Variant::new(10, 0, 1, VariantOrigin::Germline);
+        heterozygous.genotype = vec![0, 1];
+        let mut somatic = Variant::new(20, 2, 3, VariantOrigin::Somatic);
+        somatic.genotype = vec![1, 0];
+        let mut uncovered = Variant::new(30, 3, 0, VariantOrigin::Germline);
+        uncovered.genotype = vec![1, 1];
+        let mut variants = vec![heterozygous, somatic, uncovered];
+        call_sites(&mut variants, &[[18, 20, 1, 0, 1], [0, 0, 3, 37, 0], [0; 5]]);
+        let calls: Vec<&SiteCall> =
+            variants.iter().map(|variant| variant.call.as_ref().unwrap()).collect();
+        // The error base and the N count towards the depth only
+        assert_eq!((calls[0].depth, calls[0].ref_depth, calls[0].alt_depth), (40, 20, 18));
+        assert_eq!(calls[0].likelihoods.iter().position(|pl| *pl == 0), Some(1));
+        // A somatic variant in a few of the reads looks nothing like a heterozygous one
+        assert_eq!(calls[1].likelihoods.iter().position(|pl| *pl == 0), Some(0));
+        assert_eq!(calls[1].format_fields().split(':').nth(1), Some("37,3"));
+        assert_eq!(calls[2].format_fields(), "0:0,0:0:0,0,0");
+    }
+}
@@ -0,0 +1,481 @@
+// Reads gene annotations (GFF3 or GTF) and places positions in them: which kind of region a base
+// is in, for region-specific mutation rates, and what a SNP does to the transcript it lands in,
+// for the truth vcf. Coordinates are converted to 0-based, half open [start, end) spans, as in
+// bed_tools.
+
+use std::collections::HashMap;
+use std::fs;
+use super::errors::NeatError;
+use super::variants::{Variant, VariantAnnotation, VariantEffect};
+
+// The standard genetic code. Codons are indexed 16 * first + 4 * second + third, with the bases
+// numbered as in nucleotides (A=0, C=1, G=2, T=3), so AAA is first and TTT last.
+const GENETIC_CODE: &[u8; 64] =
+    b"KNKNTTTTRSRSIIMIQHQHPPPPRRRRLLLLEDEDAAAAGGGGVVVV*Y*YSSSS*CWCLFLF";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Intergenic,
+    Intron,
+    Exon,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Transcript {
+    // gene: The name of the gene the transcript belongs to.
+    // reverse: Whether the transcript is on the minus strand.
+    // exons: The exons, sorted by position. Transcripts annotated with CDS features only (as in
+    //     most prokaryote annotations) use those as their exons.
+    // cds: The coding parts of the exons, sorted by position.
+    // phase: Bases to skip at the start of the coding sequence (in transcript order) before the
+    //     first whole codon, from the phase column of the first CDS.
+    pub gene: String,
+    pub reverse: bool,
+    pub exons: Vec<(usize, usize)>,
+    pub cds: Vec<(usize, usize)>,
+    pub phase: usize,
+}
+
+impl Transcript {
+    pub fn start(&self) -> usize {
+        self.exons.first().map_or(0, |exon| exon.0)
+    }
+
+    pub fn end(&self) -> usize {
+        self.exons.last().map_or(0, |exon| exon.1)
+    }
+
+    fn coding_offset(&self, position: usize) -> Option<usize> {
+        // How far into the coding sequence (in transcript order) this position is, if it is
+        // coding at all.
+        let mut offset = 0;
+        for &(start, end) in self.cds_in_transcript_order() {
+            if (start..end).contains(&position) {
+                return Some(offset + match self.reverse {
+                    false => position - start,
+                    true => end - 1 - position,
+                })
+            }
+            offset += end - start;
+        }
+        None
+    }
+
+    fn coding_position(&self, mut offset: usize) -> Option<usize> {
+        // The reverse of coding_offset: where the base this far into the coding sequence is.
+        for &(start, end) in self.cds_in_transcript_order() {
+            if offset < end - start {
+                return Some(match self.reverse {
+                    false => start + offset,
+                    true => end - 1 - offset,
+                })
+            }
+            offset -= end - start;
+        }
+        None
+    }
+
+    fn cds_in_transcript_order(&self) -> Box<dyn Iterator<Item = &(usize, usize)> + '_> {
+        match self.reverse {
+            false => Box::new(self.cds.iter()),
+            true => Box::new(self.cds.iter().rev()),
+        }
+    }
+
+    fn effect(&self, sequence: &[u8], position: usize, alt: u8) -> VariantEffect {
+        // Takes:
+        // sequence: The reference sequence of the transcript's contig.
+        // position: Where the SNP is.
+        // alt: The alternate base.
+        // Returns:
+        // What the SNP does to this transcript, on its own, against the reference codon.
+        if !self.exons.iter().any(|(start, end)| (*start..*end).contains(&position)) {
+            return VariantEffect::Intron
+        }
+        let offset = match self.coding_offset(position) {
+            Some(offset) => offset,
+            None if self.cds.is_empty() => return VariantEffect::NoncodingExon,
+            None => return VariantEffect::Utr,
+        };
+        if offset < self.phase {
+            return VariantEffect::Coding
+        }
+        let codon_start = offset - (offset - self.phase) % 3;
+        // The codon is read off the transcript's own strand.
+        let strand_base = |base: u8| if self.reverse && base < 4 { 3 - base } else { base };
+        let mut reference_codon = [0u8; 3];
+        let mut alt_codon = [0u8; 3];
+        for index in 0..3 {
+            let Some(base_position) = self.coding_position(codon_start + index) else {
+                return VariantEffect::Coding
+            };
+            let base = if base_position == position { alt } else { sequence[base_position] };
+            reference_codon[index] = strand_base(sequence[base_position]);
+            alt_codon[index] = strand_base(base);
+        }
+        match (translate(reference_codon), translate(alt_codon)) {
+            (Some(reference_amino), Some(alt_amino)) if reference_amino == alt_amino => {
+                VariantEffect::Synonymous
+            },
+            (Some(b'*'), Some(_)) => VariantEffect::StopLost,
+            (Some(_), Some(b'*')) => VariantEffect::Nonsense,
+            (Some(_), Some(_)) => VariantEffect::Missense,
+            _ => VariantEffect::Coding,
+        }
+    }
+}
+
+// The transcripts of each contig, keyed by contig name and sorted by start.
+pub type Annotations = HashMap<String, Vec<Transcript>>;
+
+pub struct RegionRates<'a> {
+    // Mutation rates that differ between exons, introns and intergenic regions.
+    //
+    // annotations: The transcripts that define the regions.
+    // exon, intron, intergenic: The mutation rate in each kind of region.
+    pub annotations: &'a Annotations,
+    pub exon: f64,
+    pub intron: f64,
+    pub intergenic: f64,
+}
+
+impl RegionRates<'_> {
+    pub fn base_rates(&self, contig: &str, length: usize) -> Vec<f64> {
+        // The mutation rate of every base of a contig.
+        base_regions(contig_transcripts(self.annotations, contig), length)
+            .into_iter()
+            .map(|region| match region {
+                Region::Exon => self.exon,
+                Region::Intron => self.intron,
+                Region::Intergenic => self.intergenic,
+            })
+            .collect()
+    }
+}
+
+fn translate(codon: [u8; 3]) -> Option<u8> {
+    // The amino acid (one letter code, * for stop) a codon codes for, or None if it has an N.
+    if codon.iter().any(|base| *base > 3) {
+        return None
+    }
+    let index = 16 * codon[0] as usize + 4 * codon[1] as usize + codon[2] as usize;
+    Some(GENETIC_CODE[index])
+}
+
+fn parse_attributes(text: &str) -> HashMap<&str, &str> {
+    // GFF3 attributes are key=value pairs and GTF ones key "value" pairs, both separated by ;.
+    text.split(';')
+        .filter_map(|attribute| {
+            let attribute = attribute.trim();
+            let (key, value) = attribute
+                .split_once('=')
+                .or_else(|| attribute.split_once(' '))?;
+            Some((key.trim(), value.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct TranscriptParts {
+    // The exon and CDS lines of one transcript, as they are read.
+    contig: String,
+    reverse: bool,
+    gene: Option<String>,
+    exons: Vec<(usize, usize)>,
+    cds: Vec<(usize, usize, usize)>,
+}
+
+pub fn read_annotations(path: &str) -> Result<Annotations, NeatError> {
+    // Takes:
+    // path: A GFF3 or GTF file. Exon and CDS lines are grouped into transcripts by their
+    //     transcript_id (GTF) or Parent (GFF3); every other line is only used to find gene names.
+    // Returns:
+    // The transcripts of each contig, or an error if the file can't be read or a line doesn't
+    // parse.
+    let text = fs::read_to_string(path)?;
+    let mut parts: HashMap<String, TranscriptParts> = HashMap::new();
+    // Transcripts in the order they were first seen, so the result doesn't depend on hashing.
+    let mut transcript_order: Vec<String> = Vec::new();
+    // For GFF3, the name and parent of every feature with an ID, to find a transcript's gene.
+    let mut features: HashMap<String, (String, Option<String>)> = HashMap::new();
+    for (line_number, line) in text.lines().enumerate() {
+        // GFF3 files can carry their sequences at the end.
+        if line.starts_with("##FASTA") {
+            break
+        }
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue
+        }
+        let bad_line = || NeatError::AnnotationParse(
+            format!("{} line {}: {:?}", path, line_number + 1, line)
+        );
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 9 {
+            return Err(bad_line())
+        }
+        let start: usize = fields[3].trim().parse().map_err(|_| bad_line())?;
+        let end: usize = fields[4].trim().parse().map_err(|_| bad_line())?;
+        if start == 0 || end < start {
+            return Err(bad_line())
+        }
+        let attributes = parse_attributes(fields[8]);
+        let gene = ["gene_name", "gene", "gene_id"]
+            .iter()
+            .find_map(|key| attributes.get(key))
+            .map(|gene| gene.to_string());
+        if let Some(id) = attributes.get("ID") {
+            let name = attributes
+                .get("Name")
+                .map(|name| name.to_string())
+                .or(gene.clone())
+                .unwrap_or(id.to_string());
+            let parent = attributes.get("Parent").map(|parent| parent.to_string());
+            features.insert(id.to_string(), (name, parent));
+        }
+        let feature = fields[2];
+        if feature != "exon" && feature != "CDS" {
+            continue
+        }
+        let transcripts = ["transcript_id", "Parent", "ID"]
+            .iter()
+            .find_map(|key| attributes.get(key))
+            .ok_or_else(bad_line)?;
+        for transcript in transcripts.split(',') {
+            let transcript_parts = parts.entry(transcript.to_string()).or_insert_with(|| {
+                transcript_order.push(transcript.to_string());
+                TranscriptParts {
+                    contig: fields[0].to_string(),
+                    reverse: fields[6] == "-",
+                    ..Default::default()
+                }
+            });
+            if transcript_parts.gene.is_none() {
+                transcript_parts.gene = gene.clone();
+            }
+            // GFF coordinates are 1-based and inclusive.
+            match feature {
+                "exon" => transcript_parts.exons.push((start - 1, end)),
+                _ => transcript_parts.cds.push((start - 1, end, fields[7].parse().unwrap_or(0))),
+            }
+        }
+    }
+
+    let mut annotations = Annotations::new();
+    for id in transcript_order {
+        let mut transcript_parts = parts.remove(&id).unwrap();
+        let gene = transcript_parts.gene.take().unwrap_or_else(|| gene_name(&features, &id));
+        transcript_parts.cds.sort_unstable();
+        let cds: Vec<(usize, usize)> = transcript_parts
+            .cds
+            .iter()
+            .map(|(start, end, _)| (*start, *end))
+            .collect();
+        let first_cds = match transcript_parts.reverse {
+            false => transcript_parts.cds.first(),
+            true => transcript_parts.cds.last(),
+        };
+        let mut exons = match transcript_parts.exons.is_empty() {
+            true => cds.clone(),
+            false => transcript_parts.exons,
+        };
+        exons.sort_unstable();
+        annotations.entry(transcript_parts.contig).or_default().push(Transcript {
+            gene,
+            reverse: transcript_parts.reverse,
+            exons,
+            cds,
+            phase: first_cds.map_or(0, |cds| cds.2),
+        });
+    }
+    for transcripts in annotations.values_mut() {
+        transcripts.sort_by_key(|transcript| transcript.start());
+    }
+    Ok(annotations)
+}
+
+fn gene_name(features: &HashMap<String, (String, Option<String>)>, id: &str) -> String {
+    // The name of the top feature above a GFF3 transcript (its gene), or the transcript's own ID
+    // if it has no features above it. Gives up after a few levels, in case of a loop.
+    let mut name = id.to_string();
+    let mut current = id;
+    for _ in 0..8 {
+        match features.get(current) {
+            Some((feature_name, parent)) => {
+                name = feature_name.clone();
+                match parent {
+                    Some(parent) => current = parent,
+                    None => break,
+                }
+            },
+            None => break,
+        }
+    }
+    name
+}
+
+pub fn contig_transcripts<'a>(annotations: &'a Annotations, contig: &str) -> &'a [Transcript] {
+    // The transcripts of a contig. Only the first word of a fasta header is the contig name, as
+    // in samtools and Picard.
+    let name = contig.split_whitespace().next().unwrap_or_default();
+    annotations.get(name).map(Vec::as_slice).unwrap_or_default()
+}
+
+pub fn base_regions(transcripts: &[Transcript], length: usize) -> Vec<Region> {
+    // The kind of region each base of a contig is in. A base in an exon of any transcript is
+    // exonic, and otherwise a base within a transcript is intronic.
+    let mut regions = vec![Region::Intergenic; length];
+    for transcript in transcripts {
+        let end = transcript.end().min(length);
+        for region in regions.iter_mut().take(end).skip(transcript.start()) {
+            *region = Region::Intron;
+        }
+    }
+    for (start, end) in transcripts.iter().flat_map(|transcript| &transcript.exons) {
+        for region in regions.iter_mut().take((*end).min(length)).skip(*start) {
+            *region = Region::Exon;
+        }
+    }
+    regions
+}
+
+pub fn annotate_variants(
+    variant_locations: &mut HashMap<String, Vec<Variant>>,
+    reference: &HashMap<String, Vec<u8>>,
+    annotations: &Annotations,
+) {
+    // Takes:
+    // variant_locations: The variants of each contig. Those inside a transcript get an
+    //     annotation with the gene and the effect.
+    // reference: The reference sequences, keyed by contig name.
+    // annotations: The transcripts of each contig.
+    //
+    // Where transcripts overlap, the variant gets the most severe of its effects on them.
+    for (contig, variants) in variant_locations.iter_mut() {
+        let transcripts = contig_transcripts(annotations, contig);
+        let longest = transcripts
+            .iter()
+            .map(|transcript| transcript.end() - transcript.start())
+            .max()
+            .unwrap_or(0);
+        for variant in variants.iter_mut() {
+            // Transcripts are sorted by start, so only the ones just before the variant can
+            // reach it.
+            let position = variant.position;
+            let last = transcripts.partition_point(|transcript| transcript.start() <= position);
+            variant.annotation = transcripts[..last]
+                .iter()
+                .rev()
+                .take_while(|transcript| transcript.start() + longest > position)
+                .filter(|transcript| position < transcript.end())
+                .map(|transcript| VariantAnnotation {
+                    gene: transcript.gene.clone(),
+                    effect: transcript.effect(&reference[contig], position, variant.alt),
+                })
+                .max_by_key(|annotation| annotation.effect);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::variants::VariantOrigin;
+
+    #[test]
+    fn test_read_annotations() {
+        let gff = "test_data/test_annotation.gff3";
+        fs::write(gff, "##gff-version 3\n\
+            chr1\ttest\tgene\t11\t40\t.\t+\t.\tID=gene1;Name=ABC\n\
+            chr1\ttest\tmRNA\t11\t40\t.\t+\t.\tID=tx1;Parent=gene1\n\
+            chr1\ttest\texon\t11\t20\t.\t+\t.\tParent=tx1\n\
+            chr1\ttest\texon\t31\t40\t.\t+\t.\tParent=tx1\n\
+            chr1\ttest\tCDS\t14\t20\t.\t+\t0\tParent=tx1\n\
+            chr1\ttest\tCDS\t31\t35\t.\t+\t2\tParent=tx1\n").unwrap();
+        let annotations = read_annotations(gff);
+        fs::remove_file(gff).unwrap();
+        let transcript = &annotations.unwrap()["chr1"][0];
+        assert_eq!(transcript.gene, "ABC");
+        assert_eq!(transcript.exons, vec![(10, 20), (30, 40)]);
+        assert_eq!(transcript.cds, vec![(13, 20), (30, 35)]);
+        assert_eq!((transcript.start(), transcript.end()), (10, 40));
+
+        let gtf = "test_data/test_annotation.gtf";
+        fs::write(gtf, "chr2\ttest\tCDS\t5\t10\t.\t-\t0\tgene_id \"G2\"; transcript_id \"T2\";\n")
+            .unwrap();
+        let annotations = read_annotations(gtf);
+        fs::remove_file(gtf).unwrap();
+        let transcript = &annotations.unwrap()["chr2"][0];
+        assert_eq!(transcript.gene, "G2");
+        assert!(transcript.reverse);
+        assert_eq!(transcript.exons, vec![(4, 10)]);
+        assert!(matches!(
+            read_annotations("test_data/H1N1.fa"), Err(NeatError::AnnotationParse(_))
+        ));
+    }
+
+    #[test]
+    fn test_base_regions() {
+        let transcript = Transcript {
+            exons: vec![(2, 4), (6, 8)],
+            ..Default::default()
+        };
+        let regions = base_regions(&[transcript], 10);
+        assert_eq!(regions[1], Region::Intergenic);
+        assert_eq!(regions[2], Region::Exon);
+        assert_eq!(regions[5], Region::Intron);
+        assert_eq!(regions[8], Region::Intergenic);
+    }
+
+    #[test]
+    fn test_annotate_variants() {
+        // ATG GCA TGG AAA TAA on the plus strand, then the same on the minus strand.
+        let coding: Vec<u8> = vec![0, 3, 2, 2, 1, 0, 3, 2, 2, 0, 0, 0, 3, 0, 0];
+        let mut sequence = coding.clone();
+        sequence.extend(coding.iter().rev().map(|base| 3 - base));
+        let reference = HashMap::from([("chr1".to_string(), sequence)]);
+        let annotations = Annotations::from([("chr1".to_string(), vec![
+            Transcript {
+                gene: "PLUS".to_string(),
+                exons: vec![(0, 15)],
+                cds: vec![(0, 15)],
+                ..Default::default()
+            },
+            Transcript {
+                gene: "MINUS".to_string(),
+                reverse: true,
+                exons: vec![(15, 30)],
+                cds: vec![(15, 30)],
+                ..Default::default()
+            },
+        ])]);
+        // GCA -> GCC (Ala, synonymous), TGG -> TGA (Trp to stop), TAA -> CAA (stop lost),
+        // AAA -> ACA (Lys to Thr)
+        let plus = [(5, 1), (8, 0), (12, 1), (10, 1)];
+        let mut variant_locations = HashMap::from([("chr1".to_string(), plus
+            .iter()
+            .flat_map(|(position, alt)| [
+                Variant::new(*position, *alt, coding[*position], VariantOrigin::Germline),
+                Variant::new(29 - position, 3 - alt, 3 - coding[*position], VariantOrigin::Germline),
+            ])
+            .collect::<Vec<Variant>>()
+        )]);
+        annotate_variants(&mut variant_locations, &reference, &annotations);
+        let effects: Vec<(&str, VariantEffect)> = variant_locations["chr1"]
+            .iter()
+            .map(|variant| {
+                let annotation = variant.annotation.as_ref().unwrap();
+                (annotation.gene.as_str(), annotation.effect)
+            })
+            .collect();
+        let expected = [
+            VariantEffect::Synonymous,
+            VariantEffect::Nonsense,
+            VariantEffect::StopLost,
+            VariantEffect::Missense,
+        ];
+        for (index, effect) in expected.iter().enumerate() {
+            assert_eq!(effects[2 * index], ("PLUS", *effect));
+            assert_eq!(effects[2 * index + 1], ("MINUS", *effect));
+        }
+    }
+}
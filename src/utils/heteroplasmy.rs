@@ -0,0 +1,106 @@
+// Mitochondrial heteroplasmy: a cell holds hundreds of copies of the mitochondrial genome, and a
+// variant can be on any fraction of them. Runs that model it give each mitochondrial variant a
+// fraction drawn from the configured distribution. The variant stays on the single mitochondrial
+// haplotype, and each fragment drawn over it carries the reference base instead with probability
+// 1 - fraction, so its reads show it at about that fraction. The truth vcf gives each fraction
+// as the variant's AF.
+
+use std::collections::HashMap;
+use simple_rng::{DiscreteDistribution, Rng};
+use super::ploidy::PloidyMap;
+use super::variants::Variant;
+
+// The heteroplasmic variants of each contig, sorted by position: (position, reference base,
+// fraction of copies carrying the variant).
+pub type HeteroplasmicSites = HashMap<String, Vec<(usize, u8, f64)>>;
+
+pub fn assign_heteroplasmy(
+    variant_locations: &mut HashMap<String, Vec<Variant>>,
+    fasta_order: &[String],
+    ploidy_map: &PloidyMap,
+    fractions: &[(f64, f64)],
+    rng: &mut Rng,
+) {
+    // Takes:
+    // variant_locations: The variants of each contig. Those on the mitochondrial genome get
+    //     their heteroplasmy filled in.
+    // fasta_order: Contig names in reference order. Fractions are drawn in this order, so a seed
+    //     always gives the same fractions.
+    // ploidy_map: To tell which contig is the mitochondrial genome.
+    // fractions: (fraction, weight) pairs. Each variant gets one of the fractions, drawn by
+    //     weight.
+    // rng: A random number generator for this run.
+    let weights: Vec<f64> = fractions.iter().map(|(_, weight)| *weight).collect();
+    let distribution = DiscreteDistribution::new(&weights, false);
+    for contig in fasta_order.iter().filter(|contig| ploidy_map.is_mitochondrial(contig)) {
+        let Some(variants) = variant_locations.get_mut(contig) else { continue };
+        for variant in variants.iter_mut() {
+            variant.heteroplasmy = Some(fractions[distribution.sample(rng)].0);
+        }
+    }
+}
+
+pub fn heteroplasmic_sites(
+    variant_locations: &HashMap<String, Vec<Variant>>,
+) -> HeteroplasmicSites {
+    // The variants on only some of the copies of their contig, for the reads. Homoplasmic ones
+    // (a fraction of 1) are on every read already.
+    variant_locations
+        .iter()
+        .map(|(contig, variants)| {
+            let sites: Vec<(usize, u8, f64)> = variants
+                .iter()
+                .filter_map(|variant| match variant.heteroplasmy {
+                    Some(fraction) if fraction < 1.0 => {
+                        Some((variant.position, variant.reference, fraction))
+                    },
+                    _ => None,
+                })
+                .collect();
+            (contig.clone(), sites)
+        })
+        .filter(|(_, sites)| !sites.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ploidy::Sex;
+    use super::super::variants::VariantOrigin;
+
+    #[test]
+    fn test_assign_heteroplasmy() {
+        let fasta_map = HashMap::from([
+            ("chr1".to_string(), vec![0; 100]),
+            ("chrM".to_string(), vec![0; 100]),
+        ]);
+        let ploidy_map = PloidyMap::for_sample(Sex::Female, 2, 100.0, &fasta_map, None).unwrap();
+        let mut variant_locations = HashMap::from([
+            ("chr1".to_string(), vec![Variant::new(10, 1, 0, VariantOrigin::Germline)]),
+            ("chrM".to_string(), (0..100)
+                .map(|position| Variant::new(position, 2, 0, VariantOrigin::Germline))
+                .collect::<Vec<Variant>>()),
+        ]);
+        let order = vec!["chr1".to_string(), "chrM".to_string()];
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Heteroplasmy".to_string()]);
+        assign_heteroplasmy(
+            &mut variant_locations, &order, &ploidy_map, &[(0.1, 3.0), (1.0, 1.0)], &mut rng
+        );
+        // Only the mitochondrial variants get a fraction, mostly the heavier one
+        assert_eq!(variant_locations["chr1"][0].heteroplasmy, None);
+        let low = variant_locations["chrM"]
+            .iter()
+            .filter(|variant| variant.heteroplasmy == Some(0.1))
+            .count();
+        assert!(low > 60 && low < 90);
+        assert!(variant_locations["chrM"].iter().all(|variant| variant.heteroplasmy.is_some()));
+        // Only the heteroplasmic ones need anything done to the reads
+        let sites = heteroplasmic_sites(&variant_locations);
+        assert_eq!(sites.len(), 1);
+        assert_eq!(sites["chrM"].len(), low);
+        assert!(sites["chrM"].iter().all(|(_, reference, fraction)| {
+            *reference == 0 && *fraction == 0.1
+        }));
+    }
+}
@@ -0,0 +1,159 @@
+// Index (barcode) reads, for runs that write I1 and I2 fastqs to test demultiplexers. Every
+// template truly comes from the simulated sample, whose index is given as i7 or i7+i5 (the
+// bcl2fastq sample sheet notation). Index hopping is simulated against a pool of the other
+// libraries sequenced alongside it: a hopped template reads another library's i7 (or, for dual
+// indexes, one of the two indexes of another library), so a demultiplexer sends it to that
+// library or to undetermined. Index reads get quality scores and sequencing errors like any other
+// read, so mismatch tolerances get tested too.
+
+use simple_rng::Rng;
+use super::errors::NeatError;
+use super::make_reads::{add_sequencing_errors, SimulatedRead};
+use super::nucleotides::{encode_bases, u8_to_base};
+use super::quality_scores::QualityScoreModel;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleIndex {
+    // The bases of a library's i7 index and, for dual indexes, its i5, as they are read.
+    pub i7: Vec<u8>,
+    pub i5: Option<Vec<u8>>,
+}
+
+impl SampleIndex {
+    pub fn parse(text: &str) -> Result<Self, NeatError> {
+        // Reads an index written as ACGTACGT, or ACGTACGT+TTGGCCAA for dual indexes.
+        let bases = |part: &str| -> Result<Vec<u8>, NeatError> {
+            if part.is_empty() || !part.bytes().all(|base| b"ACGT".contains(&base)) {
+                return Err(NeatError::Config(format!(
+                    "Index {} must be A, C, G and T bases, as i7 or i7+i5", text
+                )))
+            }
+            let mut sequence = Vec::new();
+            encode_bases(part.as_bytes(), &mut sequence);
+            Ok(sequence)
+        };
+        match text.split_once('+') {
+            Some((i7, i5)) => Ok(SampleIndex { i7: bases(i7)?, i5: Some(bases(i5)?) }),
+            None => Ok(SampleIndex { i7: bases(text)?, i5: None }),
+        }
+    }
+
+    pub fn is_dual(&self) -> bool {
+        self.i5.is_some()
+    }
+}
+
+impl std::fmt::Display for SampleIndex {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let text = |bases: &[u8]| bases.iter().map(|base| u8_to_base(*base)).collect::<String>();
+        match &self.i5 {
+            Some(i5) => write!(formatter, "{}+{}", text(&self.i7), text(i5)),
+            None => write!(formatter, "{}", text(&self.i7)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexModel {
+    // sample: The index of the simulated sample.
+    // pool: The indexes of the other libraries in the pool, all single or all dual like sample.
+    // hopping_rate: The fraction of templates that read a pool library's index.
+    pub sample: SampleIndex,
+    pub pool: Vec<SampleIndex>,
+    pub hopping_rate: f64,
+}
+
+impl IndexModel {
+    pub fn index_reads(
+        &self,
+        template: &SimulatedRead,
+        quality_score_model: &QualityScoreModel,
+        rng: &mut Rng,
+    ) -> (Vec<SimulatedRead>, bool) {
+        // Takes:
+        // template: Read 1 of the template the index reads go with.
+        // quality_score_model: The model for the index reads' quality scores.
+        // rng: The rng for the template's contig.
+        // Returns:
+        // The I1 read and, for dual indexes, the I2 read, named like the template, and whether
+        // the template hopped to another library's index.
+        let mut observed = self.sample.clone();
+        // A template can only hop to another library's index if the pool has one.
+        let hopped = self.hopping_rate > 0.0 && !self.pool.is_empty()
+            && rng.gen_bool(self.hopping_rate);
+        if hopped {
+            let other = &self.pool[rng.rand_u32() as usize % self.pool.len()];
+            if observed.is_dual() && rng.gen_bool(0.5) {
+                observed.i5 = other.i5.clone();
+            } else {
+                observed.i7 = other.i7.clone();
+            }
+        }
+        let reads = [Some(observed.i7), observed.i5]
+            .into_iter()
+            .flatten()
+            .map(|mut seq| {
+                let quals = quality_score_model.generate_quality_scores(seq.len(), rng);
+                add_sequencing_errors(&mut seq, &quals, rng);
+                SimulatedRead {
+                    name: template.name.clone(),
+                    seq,
+                    quals,
+                    contig: template.contig.clone(),
+                    start: template.start,
+                    strand: template.strand,
+                    mate: 1,
+                    adapter_length: 0,
+                    duplex: None,
+                    qc_fail: template.qc_fail,
+                }
+            })
+            .collect();
+        (reads, hopped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_reads() {
+        let sample = SampleIndex::parse("AACCGGTT+ACGTACGT").unwrap();
+        assert_eq!(sample.to_string(), "AACCGGTT+ACGTACGT");
+        assert!(SampleIndex::parse("AACCNGTT").is_err());
+        assert!(SampleIndex::parse("AACC+").is_err());
+        let other = SampleIndex::parse("TTTTTTTT+GGGGGGGG").unwrap();
+        let pool = vec![other.clone()];
+        let model = IndexModel { sample: sample.clone(), pool, hopping_rate: 0.2 };
+        let template = SimulatedRead {
+            name: "read1".to_string(),
+            quals: vec![40; 10],
+            ..SimulatedRead::for_test("chr1", 5, vec![0; 10])
+        };
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Index".to_string()]);
+        let mut hops = 0;
+        for _ in 0..1000 {
+            let (reads, hopped) = model.index_reads(&template, &quality_model, &mut rng);
+            assert_eq!(reads.len(), 2);
+            assert!(reads.iter().all(|read| read.name == "read1" && read.seq.len() == 8));
+            // Hopping swaps one index for the other library's, never both. Reads are told apart
+            // by which library's index they are closer to, as errors may hit any of them.
+            let closer_to_sample = |read: &SimulatedRead, index: &[u8], other: &[u8]| {
+                let matches = |expected: &[u8]| {
+                    read.seq.iter().zip(expected).filter(|(base, expected)| base == expected).count()
+                };
+                matches(index) > matches(other)
+            };
+            let i7_kept = closer_to_sample(&reads[0], &sample.i7, &other.i7);
+            let i5_kept = closer_to_sample(
+                &reads[1], sample.i5.as_ref().unwrap(), other.i5.as_ref().unwrap()
+            );
+            assert_eq!(i7_kept && i5_kept, !hopped);
+            assert!(i7_kept || i5_kept);
+            hops += hopped as usize;
+        }
+        assert!((150..250).contains(&hops), "{} hops", hops);
+    }
+}
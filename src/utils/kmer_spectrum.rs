@@ -0,0 +1,109 @@
+// K-mer spectra of the simulated reads. Genome size and heterozygosity estimators (GenomeScope
+// and the like) work from a histogram of how many distinct k-mers appear once, twice, and so on
+// in the reads, which normally takes a counting pass over the fastq. Runs that ask for one count
+// the k-mers of each read as it is written instead. Like jellyfish with -C, a k-mer and its
+// reverse complement are counted as one (the smaller of the two, packed two bits a base), so the
+// spectrum doesn't depend on which strand a read came from. K-mers with an N in them aren't
+// counted. Each shard keeps its own counts, which are added together once every shard is done.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::Write;
+
+// The most bases a packed k-mer can hold.
+pub const MAX_KMER_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KmerCounts {
+    // k: The k-mer length.
+    // counts: How many times each canonical k-mer was seen, keyed by its packed bases.
+    pub k: usize,
+    pub counts: HashMap<u64, u32>,
+}
+
+impl KmerCounts {
+    pub fn new(k: usize) -> Self {
+        assert!((1..=MAX_KMER_SIZE).contains(&k), "k-mer size must be from 1 to 32");
+        KmerCounts { k, counts: HashMap::new() }
+    }
+
+    pub fn add_sequence(&mut self, sequence: &[u8]) {
+        // Counts every k-mer of the sequence (in the u8 representation), rolling the k-mer and its
+        // reverse complement along it a base at a time.
+        let mask = if self.k == MAX_KMER_SIZE { u64::MAX } else { (1 << (2 * self.k)) - 1 };
+        let shift = 2 * (self.k - 1);
+        let mut forward: u64 = 0;
+        let mut reverse: u64 = 0;
+        // Bases since the last N
+        let mut run = 0;
+        for base in sequence {
+            if *base > 3 {
+                run = 0;
+                continue
+            }
+            forward = ((forward << 2) | *base as u64) & mask;
+            reverse = (reverse >> 2) | ((3 - *base as u64) << shift);
+            run += 1;
+            if run >= self.k {
+                *self.counts.entry(forward.min(reverse)).or_insert(0) += 1;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: KmerCounts) {
+        // Adds another set of counts of the same k to these.
+        for (kmer, count) in other.counts {
+            *self.counts.entry(kmer).or_insert(0) += count;
+        }
+    }
+
+    pub fn histogram(&self) -> BTreeMap<u32, u64> {
+        // The number of distinct k-mers seen each number of times.
+        let mut histogram = BTreeMap::new();
+        for count in self.counts.values() {
+            *histogram.entry(*count).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    pub fn write_histogram(&self, outfile: &mut impl Write) -> io::Result<()> {
+        // Writes the histogram as jellyfish histo does, which GenomeScope reads: a line per count,
+        // in increasing order, with the count and the number of k-mers seen that many times.
+        for (count, kmers) in self.histogram() {
+            writeln!(outfile, "{} {}", count, kmers)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kmer_counts() {
+        // ACGTT has the 3-mers ACG, CGT and GTT. ACG and CGT are each other's reverse complement,
+        // so they count as one.
+        let mut counts = KmerCounts::new(3);
+        counts.add_sequence(&[0, 1, 2, 3, 3]);
+        assert_eq!(counts.counts.len(), 2);
+        // Its reverse complement, AACGT, adds to the same k-mers; the N breaks up the 3-mers
+        counts.add_sequence(&[0, 0, 1, 2, 3]);
+        counts.add_sequence(&[0, 1, 4, 2, 3, 3]);
+        assert_eq!(counts.counts.values().sum::<u32>(), 7);
+        assert_eq!(counts.histogram(), BTreeMap::from([(3, 1), (4, 1)]));
+
+        let mut other = KmerCounts::new(3);
+        other.add_sequence(&[3, 3, 3]);
+        counts.merge(other);
+        let mut histogram = Vec::new();
+        counts.write_histogram(&mut histogram).unwrap();
+        assert_eq!(String::from_utf8(histogram).unwrap(), "1 1\n3 1\n4 1\n");
+
+        // The longest k-mers fill the packed k-mer
+        let mut longest = KmerCounts::new(MAX_KMER_SIZE);
+        let sequence: Vec<u8> = (0..40).map(|index| (index % 4) as u8).collect();
+        longest.add_sequence(&sequence);
+        assert_eq!(longest.counts.values().sum::<u32>(), 9);
+    }
+}
@@ -0,0 +1,149 @@
+// Known variant sites, read from a vcf like dbSNP. A fraction of the germline variants can be put
+// at these sites (with one of their alleles) instead of at random positions, and keep their IDs
+// in the truth vcf. Annotation pipelines, and tools that mask known sites (like base quality
+// score recalibration), then see a mix of known and novel variants, as they would in a real
+// sample. Only SNVs are used, since only SNVs are simulated.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use flate2::read::MultiGzDecoder;
+use log::warn;
+use super::errors::NeatError;
+use super::nucleotides::base_to_u8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnownSite {
+    // position: 0-based position of the site in the contig.
+    // alts: The u8 representations of the site's SNV alleles.
+    // id: The site's ID (e.g. an rs number), if it has one.
+    pub position: usize,
+    pub alts: Vec<u8>,
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KnownSites {
+    // sites: The known sites of each contig, sorted by position.
+    // fraction: The fraction of germline variants placed at known sites.
+    pub sites: HashMap<String, Vec<KnownSite>>,
+    pub fraction: f64,
+}
+
+pub fn read_known_sites(
+    path: &str,
+    reference: &HashMap<String, Vec<u8>>,
+) -> Result<HashMap<String, Vec<KnownSite>>, NeatError> {
+    // Takes:
+    // path: A vcf, plain or gzipped (bgzip is a kind of gzip).
+    // reference: The reference sequences, keyed by contig name. Records on other contigs are
+    //     dropped, as are records whose REF doesn't match the reference and alleles that aren't
+    //     SNVs.
+    // Returns:
+    // The SNV sites of each contig, sorted by position, one per position, or an error if a
+    // record doesn't parse.
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = match path.ends_with(".gz") {
+        true => Box::new(MultiGzDecoder::new(file)),
+        false => Box::new(file),
+    };
+    let mut sites: HashMap<String, Vec<KnownSite>> = HashMap::new();
+    let mut mismatched = 0;
+    for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue
+        }
+        let fields: Vec<&str> = line.splitn(6, '\t').collect();
+        let bad_line = || NeatError::VcfParse(
+            format!("{} line {}: {:?}", path, line_number + 1, line)
+        );
+        if fields.len() < 5 {
+            return Err(bad_line())
+        }
+        let position: usize = fields[1].parse().map_err(|_| bad_line())?;
+        let Some(sequence) = reference.get(fields[0]) else { continue };
+        if fields[3].len() != 1 || position == 0 || position > sequence.len() {
+            continue
+        }
+        let reference_base = base_to_u8(fields[3].chars().next().unwrap());
+        if reference_base > 3 || reference_base != sequence[position - 1] {
+            mismatched += 1;
+            continue
+        }
+        let alts: Vec<u8> = fields[4]
+            .split(',')
+            .filter(|alt| alt.len() == 1)
+            .map(|alt| base_to_u8(alt.chars().next().unwrap()))
+            .filter(|alt| *alt < 4 && *alt != reference_base)
+            .collect();
+        if alts.is_empty() {
+            continue
+        }
+        let id = match fields[2] {
+            "." | "" => None,
+            id => Some(id.to_string()),
+        };
+        sites.entry(fields[0].to_string()).or_default().push(KnownSite {
+            position: position - 1,
+            alts,
+            id,
+        });
+    }
+    if mismatched > 0 {
+        warn!("Skipped {} records of {} whose REF doesn't match the reference", mismatched, path);
+    }
+    for contig_sites in sites.values_mut() {
+        contig_sites.sort_by_key(|site| site.position);
+        contig_sites.dedup_by_key(|site| site.position);
+    }
+    Ok(sites)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    const KNOWN_SITES: &str = "##fileformat=VCFv4.2\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+        chr1\t3\trs3\tG\tA,T,GT\t.\t.\t.\n\
+        chr1\t2\trs2\tC\tT\t.\t.\t.\n\
+        chr1\t2\trs2b\tC\tG\t.\t.\t.\n\
+        chr1\t4\t.\tT\tC\t.\t.\t.\n\
+        chr1\t5\trs5\tG\tT\t.\t.\t.\n\
+        chr1\t1\trs1\tAC\tA\t.\t.\t.\n\
+        chr2\t1\trs9\tA\tC\t.\t.\t.\n";
+
+    #[test]
+    fn test_read_known_sites() {
+        let reference = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3, 0])]);
+        let path = "test_data/test_known_sites.vcf";
+        fs::write(path, KNOWN_SITES).unwrap();
+        let sites = read_known_sites(path, &reference);
+        fs::remove_file(path).unwrap();
+        // Indels, mismatched REFs, contigs not in the reference and repeat positions are dropped
+        assert_eq!(sites.unwrap(), HashMap::from([("chr1".to_string(), vec![
+            KnownSite { position: 1, alts: vec![3], id: Some("rs2".to_string()) },
+            KnownSite { position: 2, alts: vec![0, 3], id: Some("rs3".to_string()) },
+            KnownSite { position: 3, alts: vec![1], id: None },
+        ])]));
+
+        let path = "test_data/test_known_sites.vcf.gz";
+        let mut encoder = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        encoder.write_all(KNOWN_SITES.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let sites = read_known_sites(path, &reference);
+        fs::remove_file(path).unwrap();
+        assert_eq!(sites.unwrap()["chr1"].len(), 3);
+
+        let path = "test_data/test_bad_known_sites.vcf";
+        fs::write(path, "chr1\tone\trs1\tA\tC\n").unwrap();
+        let result = read_known_sites(path, &reference);
+        fs::remove_file(path).unwrap();
+        assert!(matches!(result, Err(NeatError::VcfParse(_))));
+    }
+}
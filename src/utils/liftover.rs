@@ -0,0 +1,249 @@
+// Liftover of the truth to a second assembly, so a dataset simulated on one build (say GRCh38)
+// can be benchmarked against pipelines that run on another (GRCh37) without a separate liftover
+// step. The mapping comes from a UCSC chain file (as used by liftOver and CrossMap, plain or
+// gzipped) from the simulated reference to the other assembly. Each chain aligns a stretch of a
+// reference contig to one of the other assembly's contigs, on either strand, as gapless blocks;
+// where chains overlap on the reference the higher scoring one wins, as liftOver does. A variant
+// lifts to the matching position of the block it is in, with its bases complemented if the block
+// is on the minus strand, and variants outside every block (in gaps of the alignment, or on
+// contigs the chains don't cover) are left out of the lifted truth.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use flate2::read::MultiGzDecoder;
+use super::errors::NeatError;
+use super::fastq_tools::complement;
+use super::variants::Variant;
+
+#[derive(Debug, Clone, PartialEq)]
+struct ChainBlock {
+    // A gapless block of a chain, from its start (the key it is stored under) to end on the
+    // reference contig, and from target_start on the other assembly's contig (numbered in order
+    // of first appearance), counted along its minus strand if reverse is set.
+    end: usize,
+    target: usize,
+    target_start: usize,
+    reverse: bool,
+}
+
+// A chain's score, reference contig and blocks (with their starts), as read.
+type Chain = (f64, String, Vec<(usize, ChainBlock)>);
+
+#[derive(Debug, Default)]
+pub struct ChainMap {
+    // blocks: The blocks of each reference contig, keyed by their start. They don't overlap.
+    // targets: The other assembly's contigs the chains reach, with their lengths, in order of
+    //     first appearance.
+    blocks: HashMap<String, BTreeMap<usize, ChainBlock>>,
+    targets: Vec<(String, usize)>,
+}
+
+impl ChainMap {
+    fn insert(&mut self, contig: &str, start: usize, block: ChainBlock) {
+        // Adds a block, keeping only the parts not already covered by a block from a higher
+        // scoring chain.
+        let blocks = self.blocks.entry(contig.to_string()).or_default();
+        let mut cursor = start;
+        if let Some((_, previous)) = blocks.range(..=cursor).next_back() {
+            cursor = cursor.max(previous.end);
+        }
+        let mut pieces = Vec::new();
+        if cursor < block.end {
+            for (existing_start, existing) in blocks.range(cursor..block.end) {
+                if *existing_start > cursor {
+                    pieces.push((cursor, *existing_start));
+                }
+                cursor = cursor.max(existing.end);
+            }
+        }
+        if cursor < block.end {
+            pieces.push((cursor, block.end));
+        }
+        for (piece_start, piece_end) in pieces {
+            blocks.insert(piece_start, ChainBlock {
+                end: piece_end,
+                target_start: block.target_start + piece_start - start,
+                ..block.clone()
+            });
+        }
+    }
+
+    pub fn lift(&self, contig: &str, position: usize) -> Option<(&str, usize, bool)> {
+        // The contig and 0-based position of the other assembly a reference position maps to,
+        // and whether it maps to the minus strand. None if it is in no block.
+        let (start, block) = self.blocks.get(contig)?.range(..=position).next_back()?;
+        if position >= block.end {
+            return None
+        }
+        let (name, length) = &self.targets[block.target];
+        let offset = block.target_start + position - start;
+        match block.reverse {
+            true => Some((name, length - 1 - offset, true)),
+            false => Some((name, offset, false)),
+        }
+    }
+
+    pub fn lift_variants(
+        &self,
+        variant_locations: &HashMap<String, Vec<Variant>>,
+        contig_order: &[String],
+    ) -> (HashMap<String, Vec<Variant>>, Vec<String>, usize) {
+        // Takes:
+        // variant_locations: The variants of each reference contig.
+        // contig_order: The reference contigs to lift, in order.
+        // Returns:
+        // The variants that lift, keyed by the other assembly's contigs and sorted by position,
+        // those contigs in order of first appearance in the chain file, and how many variants
+        // didn't lift.
+        let mut lifted: HashMap<String, Vec<Variant>> = HashMap::new();
+        let mut unlifted = 0;
+        for contig in contig_order {
+            for variant in variant_locations.get(contig).into_iter().flatten() {
+                let Some((target, position, reverse)) = self.lift(contig, variant.position) else {
+                    unlifted += 1;
+                    continue
+                };
+                let mut lifted_variant = variant.clone();
+                lifted_variant.position = position;
+                if reverse {
+                    lifted_variant.reference = complement(variant.reference);
+                    lifted_variant.alt = complement(variant.alt);
+                }
+                lifted.entry(target.to_string()).or_default().push(lifted_variant);
+            }
+        }
+        for variants in lifted.values_mut() {
+            variants.sort_by_key(|variant| variant.position);
+        }
+        let order = self.targets
+            .iter()
+            .filter(|(name, _)| lifted.contains_key(name))
+            .map(|(name, _)| name.clone())
+            .collect();
+        (lifted, order, unlifted)
+    }
+}
+
+pub fn read_chain(path: &str) -> Result<ChainMap, NeatError> {
+    // Reads a chain file, plain or gzipped, into a ChainMap, or returns an error if a line
+    // doesn't parse.
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = match path.ends_with(".gz") {
+        true => Box::new(MultiGzDecoder::new(file)),
+        false => Box::new(file),
+    };
+    // The chains are added in order of score once all are read.
+    let mut chains: Vec<Chain> = Vec::new();
+    let mut target_index: HashMap<String, usize> = HashMap::new();
+    let mut chain_map = ChainMap::default();
+    // The current chain's positions on the reference and the other assembly
+    let mut current: Option<(usize, usize, usize, bool)> = None;
+    for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let bad_line = || NeatError::ChainParse(
+            format!("{} line {}: {:?}", path, line_number + 1, line)
+        );
+        if fields.is_empty() || fields[0].starts_with('#') {
+            continue
+        }
+        if fields[0] == "chain" {
+            // chain score tName tSize tStrand tStart tEnd qName qSize qStrand qStart qEnd id
+            if fields.len() < 12 || fields[4] != "+" {
+                return Err(bad_line())
+            }
+            let number = |index: usize| fields[index].parse::<usize>().map_err(|_| bad_line());
+            let score: f64 = fields[1].parse().map_err(|_| bad_line())?;
+            let target = *target_index.entry(fields[7].to_string()).or_insert_with(|| {
+                chain_map.targets.push((fields[7].to_string(), 0));
+                chain_map.targets.len() - 1
+            });
+            chain_map.targets[target].1 = number(8)?;
+            let reverse = match fields[9] {
+                "+" => false,
+                "-" => true,
+                _ => return Err(bad_line()),
+            };
+            current = Some((number(5)?, number(10)?, target, reverse));
+            chains.push((score, fields[2].to_string(), Vec::new()));
+            continue
+        }
+        // size [dt dq]: an aligned block, then the gaps before the next one
+        let (Some((start, target_start, target, reverse)), Some(chain)) =
+            (current.as_mut(), chains.last_mut())
+        else {
+            return Err(bad_line())
+        };
+        let numbers = fields
+            .iter()
+            .map(|field| field.parse::<usize>().map_err(|_| bad_line()))
+            .collect::<Result<Vec<usize>, NeatError>>()?;
+        if numbers.len() != 1 && numbers.len() != 3 {
+            return Err(bad_line())
+        }
+        let size = numbers[0];
+        chain.2.push((*start, ChainBlock {
+            end: *start + size,
+            target: *target,
+            target_start: *target_start,
+            reverse: *reverse,
+        }));
+        *start += size + numbers.get(1).unwrap_or(&0);
+        *target_start += size + numbers.get(2).unwrap_or(&0);
+    }
+    chains.sort_by(|first, second| second.0.total_cmp(&first.0));
+    for (_, contig, blocks) in chains {
+        for (start, block) in blocks {
+            chain_map.insert(&contig, start, block);
+        }
+    }
+    Ok(chain_map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use super::super::variants::VariantOrigin;
+
+    #[test]
+    fn test_liftover() {
+        let chain = "test_data/test_liftover.chain";
+        // chr1 0..100 is on old_1 from 20, with a 10 base gap on chr1 at 50..60 and a 5 base
+        // one on old_1; chr1 200..230 is on the minus strand of old_2, which is 100 long; and a
+        // lower scoring chain over chr1 10..40 and 210..240 only counts past the others' reach.
+        fs::write(
+            chain,
+            "chain 1000 chr1 1000 + 0 100 old_1 500 + 20 115 1\n50 10 5\n40\n\n\
+            chain 500 chr1 1000 + 200 230 old_2 100 - 0 30 2\n30\n\n\
+            chain 10 chr1 1000 + 10 240 old_3 300 + 0 230 3\n30 170 170\n30\n",
+        ).unwrap();
+        let chain_map = read_chain(chain);
+        fs::remove_file(chain).unwrap();
+        let chain_map = chain_map.unwrap();
+        assert_eq!(chain_map.lift("chr1", 5), Some(("old_1", 25, false)));
+        assert_eq!(chain_map.lift("chr1", 55), None);
+        assert_eq!(chain_map.lift("chr1", 60), Some(("old_1", 75, false)));
+        assert_eq!(chain_map.lift("chr1", 200), Some(("old_2", 99, true)));
+        // The lower scoring chain only where nothing else is
+        assert_eq!(chain_map.lift("chr1", 30), Some(("old_1", 50, false)));
+        assert_eq!(chain_map.lift("chr1", 235), Some(("old_3", 225, false)));
+        assert_eq!(chain_map.lift("chr2", 5), None);
+
+        let variants = HashMap::from([(
+            "chr1".to_string(),
+            vec![
+                Variant::new(5, 2, 0, VariantOrigin::Germline),
+                Variant::new(55, 2, 0, VariantOrigin::Germline),
+                Variant::new(201, 2, 0, VariantOrigin::Germline),
+            ],
+        )]);
+        let (lifted, order, unlifted) = chain_map.lift_variants(&variants, &["chr1".to_string()]);
+        assert_eq!(order, vec!["old_1", "old_2"]);
+        assert_eq!(unlifted, 1);
+        assert_eq!(lifted["old_1"][0].position, 25);
+        let reverse = &lifted["old_2"][0];
+        assert_eq!((reverse.position, reverse.reference, reverse.alt), (98, 3, 1));
+    }
+}
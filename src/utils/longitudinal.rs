@@ -0,0 +1,143 @@
+// Longitudinal samples: a series of samples taken from one evolving population over time, such as
+// a tumor under treatment or a chronic viral infection, to benchmark tools that track clones and
+// variants between timepoints. The population is a set of clones, each a haplotype of a
+// quasispecies (see quasispecies.rs): clone 0 is the reference (normal cells, or the founding
+// virus), and a new clone arises at every timepoint, descended from one of those before it, with
+// SNPs of its own on top of everything it inherits. Between timepoints every clone's share of
+// the population drifts by a random fitness, and the new clone takes up a share of its own, so
+// later samples carry the earlier variants at shifted frequencies along with new ones. Every
+// timepoint is run with the same seed, so they share the genealogy and SNPs and only differ in
+// the clones' shares (clones that haven't arisen yet have none, and their SNPs are left out of
+// the sample). A table of every variant's frequency at each timepoint links them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::Write;
+use simple_rng::{NormalDistribution, Rng};
+use super::nucleotides::u8_to_base;
+use super::variants::Variant;
+
+// A new clone makes up between these shares of the sample it arises in.
+const MIN_EMERGENCE: f64 = 0.05;
+const MAX_EMERGENCE: f64 = 0.3;
+
+pub fn clone_frequencies(timepoints: usize, drift: f64, rng: &mut Rng) -> Vec<Vec<f64>> {
+    // Takes:
+    // timepoints: How many samples the series has.
+    // drift: The standard deviation of the logarithm of the fitness every clone's share is
+    //     multiplied by between timepoints. 0 keeps the clones' shares in proportion.
+    // rng: For the fitnesses and the shares new clones arise at.
+    // Returns:
+    // The share of each clone in each sample, summing to 1, with a share for every clone of the
+    // series (timepoints + 1 of them, 0 for those that haven't arisen yet).
+    let fitness = (drift > 0.0).then(|| NormalDistribution::new(0.0, drift));
+    let mut weights = vec![1.0];
+    let mut frequencies = Vec::with_capacity(timepoints);
+    for _ in 0..timepoints {
+        if let Some(fitness) = &fitness {
+            for weight in weights.iter_mut() {
+                *weight *= fitness.sample(rng).exp();
+            }
+        }
+        let share = MIN_EMERGENCE + rng.random() * (MAX_EMERGENCE - MIN_EMERGENCE);
+        let total: f64 = weights.iter().sum();
+        weights.push(total * share / (1.0 - share));
+        let total: f64 = weights.iter().sum();
+        let mut shares: Vec<f64> = weights.iter().map(|weight| weight / total).collect();
+        shares.resize(timepoints + 1, 0.0);
+        frequencies.push(shares);
+    }
+    frequencies
+}
+
+pub fn write_timepoint_table(
+    writer: &mut impl Write,
+    timepoints: &[&HashMap<String, Vec<Variant>>],
+    contig_order: &[String],
+) -> io::Result<()> {
+    // Takes:
+    // writer: Where to write the table.
+    // timepoints: The variants of each sample, in order.
+    // contig_order: The contigs in reference order.
+    // Returns:
+    // Error if there is a problem writing, or else nothing. Every variant of the series gets a
+    // line, with the first timepoint it is in and its frequency in each sample (0 where it isn't
+    // in the sample).
+    write!(writer, "#contig\tposition\tref\talt\tfirst_timepoint")?;
+    for timepoint in 1..=timepoints.len() {
+        write!(writer, "\tt{}", timepoint)?;
+    }
+    writeln!(writer)?;
+    for contig in contig_order {
+        let mut series: BTreeMap<usize, (u8, u8, Vec<f64>)> = BTreeMap::new();
+        for (timepoint, variants) in timepoints.iter().enumerate() {
+            for variant in variants.get(contig).into_iter().flatten() {
+                let (_, _, frequencies) = series
+                    .entry(variant.position)
+                    .or_insert_with(|| {
+                        (variant.reference, variant.alt, vec![0.0; timepoints.len()])
+                    });
+                frequencies[timepoint] = variant.frequency.unwrap_or(0.0);
+            }
+        }
+        for (position, (reference, alt, frequencies)) in series {
+            let first = frequencies.iter().position(|frequency| *frequency > 0.0).unwrap_or(0);
+            write!(
+                writer,
+                "{}\t{}\t{}\t{}\tt{}",
+                contig, position + 1, u8_to_base(reference), u8_to_base(alt), first + 1
+            )?;
+            for frequency in frequencies {
+                write!(writer, "\t{:.4}", frequency)?;
+            }
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::variants::VariantOrigin;
+
+    #[test]
+    fn test_clone_frequencies() {
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Timepoints".to_string()]);
+        let frequencies = clone_frequencies(4, 0.5, &mut rng);
+        assert_eq!(frequencies.len(), 4);
+        for (timepoint, shares) in frequencies.iter().enumerate() {
+            assert_eq!(shares.len(), 5);
+            assert!((shares.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+            // Every clone up to this timepoint's is in the sample, and none after it
+            assert!(shares[..timepoint + 2].iter().all(|share| *share > 0.0));
+            assert!(shares[timepoint + 2..].iter().all(|share| *share == 0.0));
+            let emerging = shares[timepoint + 1];
+            assert!((MIN_EMERGENCE..=MAX_EMERGENCE).contains(&emerging));
+        }
+        // Without drift the clones keep their proportions
+        let steady = clone_frequencies(3, 0.0, &mut rng);
+        let ratio = |shares: &[f64]| shares[1] / shares[0];
+        assert!((ratio(&steady[0]) - ratio(&steady[2])).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_write_timepoint_table() {
+        let variant = |position, frequency| {
+            let mut variant = Variant::new(position, 2, 0, VariantOrigin::Germline);
+            variant.frequency = Some(frequency);
+            variant
+        };
+        let first = HashMap::from([("virus".to_string(), vec![variant(9, 0.2)])]);
+        let second =
+            HashMap::from([("virus".to_string(), vec![variant(4, 0.1), variant(9, 0.35)])]);
+        let mut table = Vec::new();
+        write_timepoint_table(&mut table, &[&first, &second], &["virus".to_string()]).unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "#contig\tposition\tref\talt\tfirst_timepoint\tt1\tt2\n\
+            virus\t5\tA\tG\tt2\t0.0000\t0.1000\n\
+            virus\t10\tA\tG\tt1\t0.2000\t0.3500\n"
+        );
+    }
+}
@@ -0,0 +1,83 @@
+// Low-complexity regions: homopolymers and short tandem repeats. Sequencers slip and lose phase
+// in them, so real reads carry far more errors there than elsewhere, and those errors make up most
+// of a caller's false positives. Runs that model this either read the regions from a bed or find
+// them in the reference; bases of reads that fall in them then get lower quality scores and
+// sequencing errors to match.
+
+use std::collections::HashMap;
+use super::bed_tools::{merge_regions, Regions};
+
+// A run of one base at least this long is a homopolymer.
+const MIN_HOMOPOLYMER: usize = 6;
+
+// Repeats of a 2 to MAX_REPEAT_UNIT base unit at least this long are tandem repeats.
+const MIN_TANDEM_REPEAT: usize = 12;
+const MAX_REPEAT_UNIT: usize = 4;
+
+// Lowered quality scores don't go below this, the lowest score Illumina reports for called bases.
+const MIN_LOW_COMPLEXITY_QUALITY: u32 = 2;
+
+pub fn find_low_complexity(sequence: &[u8]) -> Vec<(usize, usize)> {
+    // Returns the [start, end) spans of the sequence that are homopolymers or tandem repeats,
+    // sorted and merged. N's never count as a repeat.
+    let mut regions = Vec::new();
+    for unit in 1..=MAX_REPEAT_UNIT.min(sequence.len()) {
+        let min_length = if unit == 1 { MIN_HOMOPOLYMER } else { MIN_TANDEM_REPEAT };
+        // Every base from run_start + unit up to position repeats the base a unit before it.
+        let mut run_start = 0;
+        for position in unit..=sequence.len() {
+            let repeats = position < sequence.len()
+                && sequence[position] < 4
+                && sequence[position] == sequence[position - unit];
+            if !repeats {
+                if position - run_start >= min_length {
+                    regions.push((run_start, position));
+                }
+                run_start = position + 1 - unit;
+            }
+        }
+    }
+    merge_regions(&mut regions);
+    regions
+}
+
+pub fn find_all_low_complexity(sequences: &HashMap<String, Vec<u8>>) -> Regions {
+    // The low-complexity regions of every contig.
+    sequences
+        .iter()
+        .map(|(contig, sequence)| (contig.clone(), find_low_complexity(sequence)))
+        .collect()
+}
+
+pub fn lowered_quality(quality: u32, error_fold: f64) -> u32 {
+    // The quality score whose error rate is error_fold times that of quality.
+    let drop = (10.0 * error_fold.log10()).round() as u32;
+    quality.saturating_sub(drop).max(MIN_LOW_COMPLEXITY_QUALITY.min(quality))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_low_complexity() {
+        // A 7 base poly-A, a CA repeat 12 bases long, and a 5 base poly-T that is too short.
+        let sequence: Vec<u8> = [
+            vec![2, 3], vec![0; 7], vec![2], vec![1, 0].repeat(6), vec![2], vec![3; 5], vec![2],
+        ].concat();
+        assert_eq!(find_low_complexity(&sequence), vec![(2, 9), (10, 22)]);
+        // Runs of N aren't repeats, and a repeat running into the end still counts
+        assert!(find_low_complexity(&[4; 20]).is_empty());
+        assert_eq!(find_low_complexity(&[1, 0, 0, 0, 0, 0, 0]), vec![(1, 7)]);
+        assert!(find_low_complexity(&[0, 0]).is_empty());
+    }
+
+    #[test]
+    fn test_lowered_quality() {
+        // 10 times the errors is 10 points of quality
+        assert_eq!(lowered_quality(30, 10.0), 20);
+        assert_eq!(lowered_quality(30, 1.0), 30);
+        assert_eq!(lowered_quality(5, 100.0), 2);
+        assert_eq!(lowered_quality(1, 100.0), 1);
+    }
+}
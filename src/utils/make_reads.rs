@@ -4,23 +4,105 @@
 // read coverage. Generate reads uses this to create a list of coordinates to take slices from
 // the mutated fasta file. These will either be read-length fragments or fragment model length
 // fragments.
-use std::collections::{HashSet, VecDeque};
-use simple_rng::{NormalDistribution, Rng};
+//
+// ReadGenerator wraps the same machinery in an iterator. It walks the contigs in order and yields
+// SimulatedReads (sequence, qualities and truth position) one at a time, so the caller decides
+// where the reads go.
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use simple_rng::{DiscreteDistribution, NormalDistribution, Rng};
+use super::fastq_tools::{
+    complement, illumina_read_name, reverse_complement, FlowcellGeometry, IlluminaRun,
+    ReadNameFormat
+};
+use super::nucleotides::{base_to_u8, u8_to_base};
+use super::ploidy::PloidyMap;
+use super::quality_scores::QualityScoreModel;
+use super::errors::NeatError;
+use super::bed_tools::{intersect_regions, non_n_regions, Regions};
+use super::coverage_profile::CoverageProfile;
+use super::capture::CaptureModel;
+use super::low_complexity::lowered_quality;
+use super::error_profile::{capped_quality, CycleErrorProfile};
+use super::heteroplasmy::HeteroplasmicSites;
+use super::ffpe::{deaminated_base, FfpeArtifacts};
+
+// What each read of a pair sequences once it runs off the end of a fragment shorter than the read:
+// the TruSeq adapter and the flowcell oligo after it. Past those the sequencer has nothing left to
+// read, which comes out as a run of A's.
+const READ1_ADAPTER: &str = "AGATCGGAAGAGCACACGTCTGAACTCCAGTCACATCACGATCTCGTATGCCGTCTTCTGCTTG";
+const READ2_ADAPTER: &str = "AGATCGGAAGAGCGTCGTGTAGGGAAAGAGTGTAGATCTCGGTGGTCGCCGTATCATT";
+
+// Cell-free DNA fragment lengths: the share of fragments wrapped around one nucleosome (with its
+// linker) and around two, with the mean and spread of each. The rest are cut inside the nucleosome,
+// at one of the first few helical turns (10.4 bp each) below the mononucleosome peak.
+const CELL_FREE_MONONUCLEOSOME: (f64, f64, f64) = (0.80, 167.0, 10.0);
+const CELL_FREE_DINUCLEOSOME: (f64, f64, f64) = (0.10, 334.0, 20.0);
+const HELICAL_TURN: f64 = 10.4;
+const CELL_FREE_TURNS: u32 = 6;
+const CELL_FREE_TURN_ST_DEV: f64 = 2.0;
+
+// Duplex UMIs are a random sequence of this many bases on each end of a source molecule.
+const UMI_LENGTH: usize = 8;
+
+// Fragment lengths outside the bounds are drawn again, up to this many times. Bounds that far out
+// in a tail of the distribution rarely get a length inside them, so the last draw is then clamped
+// to the bounds instead of looping on.
+const MAX_FRAGMENT_DRAWS: usize = 100;
+
+// The bounds of fragment lengths when none are set: any length of at least one base.
+pub const DEFAULT_FRAGMENT_BOUNDS: (usize, usize) = (1, usize::MAX);
+
+// Bases past a quality crash are all given this score, the lowest Illumina reports for called
+// bases, and sequencing errors to match.
+const CRASH_QUALITY: u32 = 2;
+
+// Reads that fail QC have every base's quality score lowered to this many times its error rate,
+// and sequencing errors to match, as for the dim or mixed clusters the chastity filter rejects.
+const QC_FAIL_ERROR_FOLD: f64 = 30.0;
+
+fn bounded_fragment_length(
+    mut draw: impl FnMut(&mut Rng) -> f64,
+    (min, max): (usize, usize),
+    rng: &mut Rng,
+) -> usize {
+    // Draws fragment lengths until one (rounded to a whole base) is within min..=max. Lengths from
+    // a normal distribution can come out below the read length, at zero, or negative, and
+    // rounding those straight to a usize gives nonsense sizes.
+    let mut length = 0.0;
+    for _ in 0..MAX_FRAGMENT_DRAWS {
+        length = draw(rng).round();
+        if length >= min as f64 && length <= max as f64 {
+            return length as usize
+        }
+    }
+    length.clamp(min as f64, max as f64) as usize
+}
+
+fn adapter_bases(mate: u8, length: usize) -> Vec<u8> {
+    // The first length bases read through into the adapter of read 1 or read 2.
+    let adapter = if mate == 2 { READ2_ADAPTER } else { READ1_ADAPTER };
+    adapter
+        .chars()
+        .map(base_to_u8)
+        .chain(std::iter::repeat(0))
+        .take(length)
+        .collect()
+}
 
 fn cover_dataset(
     span_length: usize,
     read_length: usize,
-    mut fragment_pool: Vec<usize>,
+    mut fragment_length: impl FnMut(&mut Rng) -> usize,
     coverage: usize,
     rng: &mut Rng,
 ) -> Vec<(usize, usize)> {
     // Takes:
     // span_length: Total number of bases in the sequence
     // read_length: The length of the reads for this run
-    // fragment_pool: a vector of sizes for the fragments. If empty, it will instead be filled
-    // by the read_length (single ended reads)
-    // paired_ended: true or false if the run is paired ended mode or not.
+    // fragment_length: draws the size of the next fragment. For single ended reads this is just
+    // the read_length.
     // coverage: The coverage depth for the reads
+    // rng: the random number generator for the contig, which fragment_length draws from too.
     // Returns:
     // A vector of tuples (usize, usize), denoting the start and end positions of the fragment of
     // DNA that was sequenced.
@@ -30,18 +112,12 @@ fn cover_dataset(
     // And picks those coordinates for a second read. Once the tail of the read is past the end,
     // we start over again at 0.
     //
+    // Fragment sizes are drawn as they are needed rather than from a pool sampled up front, which
+    // would hold a few sizes for every base of the contig.
+    //
     // Reads that will be start and end of the fragment.
     let mut read_set: Vec<(usize, usize)> = vec![];
 
-    let mut cover_fragment_pool: VecDeque<usize>;
-    if fragment_pool.is_empty() {
-        // set the shuffled fragment pool just equal to an instance of read_length
-        cover_fragment_pool = VecDeque::from([read_length]);
-    } else {
-        // shuffle the fragment pool
-        rng.shuffle_in_place(&mut fragment_pool);
-        cover_fragment_pool = VecDeque::from(fragment_pool)
-    }
     // Gap size to keep track of how many uncovered bases we have per layer, to help decide if we
     // need more layers
     let mut gap_size: usize = 0;
@@ -50,8 +126,7 @@ fn cover_dataset(
     let mut start: usize = 0;
     // create coverage number of layers
     while layer_count <= coverage {
-        let fragment_length = cover_fragment_pool[0];
-        cover_fragment_pool.push_back(fragment_length);
+        let fragment_length = fragment_length(rng);
         let temp_end = start+fragment_length;
         if temp_end > span_length {
             // TODO some variation on this modulo idea will work for bacterial reads
@@ -102,7 +177,7 @@ pub fn generate_reads(
     mean: Option<f64>,
     st_dev: Option<f64>,
     mut rng: &mut Rng,
-) -> Result<Box<HashSet<Vec<u8>>>, &'static str>{
+) -> Result<Box<HashSet<Vec<u8>>>, NeatError>{
     // Takes:
     // mutated_sequence: a vector of u8's representing the mutated sequence.
     // read_length: the length ef the reads for this run
@@ -114,16 +189,13 @@ pub fn generate_reads(
     // This takes a mutated sequence and produces a set of reads based on the mutated sequence. For
     // paired ended reads, this will generate a set of reads from each end, by taking the reverse
     // complement int the output
-    let mut fragment_pool: Vec<usize> = Vec::new();
-    if paired_ended {
-        let num_frags = (mutated_sequence.len() / read_length) * (coverage * 2);
-        let fragment_distribution = NormalDistribution::new(mean.unwrap(), st_dev.unwrap());
-        // add fragments to the fragment pool
-        for _ in 0..num_frags {
-            let frag = fragment_distribution.sample(&mut rng).round() as usize;
-            fragment_pool.push(frag);
-        }
-    }
+    let fragment_model = match (paired_ended, mean.zip(st_dev)) {
+        (true, Some((mean, st_dev))) => Some(FragmentModel::Normal { mean, st_dev }),
+        (true, None) => return Err(NeatError::Config(
+            "Paired ended reads need a fragment mean and standard deviation".to_string()
+        )),
+        (false, _) => None,
+    };
     // set up some defaults and storage
     let mut read_set: HashSet<Vec<u8>> = HashSet::new();
     // length of the mutated sequence
@@ -132,7 +204,10 @@ pub fn generate_reads(
     let read_positions: Vec<(usize, usize)> = cover_dataset(
         seq_len,
         *read_length,
-        fragment_pool,
+        |rng| match fragment_model {
+            Some(model) => model.sample_length(DEFAULT_FRAGMENT_BOUNDS, rng),
+            None => *read_length,
+        },
         *coverage,
         &mut rng,
     );
@@ -142,22 +217,943 @@ pub fn generate_reads(
     }
     // puts the reads in the heap.
     if read_set.is_empty() {
-        Err("No reads generated")
+        Err(NeatError::Simulation("No reads generated".to_string()))
     } else {
         Ok(Box::new(read_set))
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strand {
+    Forward,
+    Reverse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FragmentModel {
+    // Fragment lengths for paired ended reads.
+    //
+    // Normal: Drawn from a normal distribution with this mean and standard deviation.
+    // CellFree: Drawn from the nucleosomal pattern of cell-free DNA.
+    Normal { mean: f64, st_dev: f64 },
+    CellFree,
+}
+
+impl FragmentModel {
+    pub fn sample_length(&self, bounds: (usize, usize), rng: &mut Rng) -> usize {
+        // Draws one fragment length within bounds (see bounded_fragment_length). Cell-free DNA
+        // lengths have a peak around 167 bp, a smaller one around 334 bp, and a ~10 bp ladder of
+        // shorter fragments.
+        match *self {
+            FragmentModel::Normal { mean, st_dev } => {
+                let distribution = NormalDistribution::new(mean, st_dev);
+                bounded_fragment_length(|rng| distribution.sample(rng), bounds, rng)
+            },
+            FragmentModel::CellFree => {
+                let (mono_share, mono_mean, mono_st_dev) = CELL_FREE_MONONUCLEOSOME;
+                let (di_share, di_mean, di_st_dev) = CELL_FREE_DINUCLEOSOME;
+                bounded_fragment_length(
+                    |rng| {
+                        let draw = rng.random();
+                        let (mean, st_dev) = if draw < mono_share {
+                            (mono_mean, mono_st_dev)
+                        } else if draw < mono_share + di_share {
+                            (di_mean, di_st_dev)
+                        } else {
+                            let turns = 1 + rng.rand_u32() % CELL_FREE_TURNS;
+                            (mono_mean - HELICAL_TURN * turns as f64, CELL_FREE_TURN_ST_DEV)
+                        };
+                        NormalDistribution::new(mean, st_dev).sample(rng)
+                    },
+                    bounds,
+                    rng,
+                )
+            },
+        }
+    }
+}
+
+pub struct FragmentLengths {
+    // Fragment lengths as measured from real data (see bam_profile.rs), drawn as often as they
+    // were seen.
+    //
+    // lengths: Each length seen.
+    // distribution: How often each of them was.
+    // mean, st_dev: Their mean and standard deviation, for what needs the lengths summed up.
+    lengths: Vec<usize>,
+    distribution: DiscreteDistribution,
+    pub mean: f64,
+    pub st_dev: f64,
+}
+
+impl FragmentLengths {
+    pub fn from_counts(counts: &BTreeMap<usize, u64>) -> Option<Self> {
+        // The lengths, from the number of fragments of each length. None if there are none.
+        let total: u64 = counts.values().sum();
+        if total == 0 {
+            return None
+        }
+        let mean = counts
+            .iter()
+            .map(|(length, count)| *length as f64 * *count as f64)
+            .sum::<f64>()
+            / total as f64;
+        let variance = counts
+            .iter()
+            .map(|(length, count)| (*length as f64 - mean).powi(2) * *count as f64)
+            .sum::<f64>()
+            / total as f64;
+        let weights: Vec<f64> = counts.values().map(|count| *count as f64).collect();
+        Some(FragmentLengths {
+            lengths: counts.keys().copied().collect(),
+            distribution: DiscreteDistribution::new(&weights, false),
+            mean,
+            st_dev: variance.sqrt(),
+        })
+    }
+
+    pub fn sample_length(&self, bounds: (usize, usize), rng: &mut Rng) -> usize {
+        // Draws one fragment length within bounds (see bounded_fragment_length).
+        bounded_fragment_length(
+            |rng| self.lengths[self.distribution.sample(rng)] as f64, bounds, rng
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplexTag {
+    // Identifies the source molecule of a read in duplex mode.
+    //
+    // umi: RX, the UMIs of the molecule, read 1's first ("AAAA-CCCC"). The two strands of a
+    //     molecule carry the same UMIs in opposite order.
+    // molecule_id: MI, the molecule and strand the read came from ("<molecule>/A" for the top
+    //     strand, "<molecule>/B" for the bottom).
+    pub umi: String,
+    pub molecule_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedRead {
+    // A single read, with the truth information about where it came from.
+    //
+    // name: Read name, shared by both mates of a pair.
+    // seq: The read sequence, in the u8 representation, as it would be sequenced.
+    // quals: The quality score for each base.
+    // contig: The contig the read was drawn from.
+    // start: 0-based position of the leftmost base of the read on the contig.
+    // strand: Forward if seq reads along the contig, Reverse if seq is the reverse complement.
+    // mate: 1 for single ended reads and read 1 of a pair, 2 for read 2 of a pair.
+    // adapter_length: How many bases at the end of seq are adapter, read after the fragment ran
+    //     out. Only the bases before them come from the contig.
+    // duplex: The UMIs and source molecule, for reads made in duplex mode.
+    // qc_fail: Whether the read failed the sequencer's quality filter (both mates of a pair do).
+    pub name: String,
+    pub seq: Vec<u8>,
+    pub quals: Vec<u32>,
+    pub contig: String,
+    pub start: usize,
+    pub strand: Strand,
+    pub mate: u8,
+    pub adapter_length: usize,
+    pub duplex: Option<DuplexTag>,
+    pub qc_fail: bool,
+}
+
+impl SimulatedRead {
+    pub fn aligned_length(&self) -> usize {
+        // The number of bases that come from the contig, starting at start.
+        self.seq.len() - self.adapter_length
+    }
+}
+
+#[cfg(test)]
+impl SimulatedRead {
+    pub(crate) fn for_test(contig: &str, start: usize, seq: Vec<u8>) -> Self {
+        // A forward read 1 with quality 30 throughout and nothing else set. Tests override what
+        // they need with struct update syntax.
+        SimulatedRead {
+            name: "read".to_string(),
+            quals: vec![30; seq.len()],
+            seq,
+            contig: contig.to_string(),
+            start,
+            strand: Strand::Forward,
+            mate: 1,
+            adapter_length: 0,
+            duplex: None,
+            qc_fail: false,
+        }
+    }
+}
+
+pub struct ReadGenerator<'a> {
+    // Lazily generates reads from a set of (mutated) sequences. Fragment positions are computed
+    // one contig at a time, and reads (with quality scores) are only built as they are requested.
+    sequences: &'a HashMap<String, Vec<u8>>,
+    contig_order: &'a [String],
+    read_length: usize,
+    coverage: usize,
+    fragment_model: Option<FragmentModel>,
+    fragment_lengths: Option<&'a FragmentLengths>,
+    fragment_bounds: (usize, usize),
+    adapter_read_through: bool,
+    duplex_family_size: Option<usize>,
+    duplicates: Option<(f64, f64)>,
+    targets: Option<&'a Regions>,
+    coverage_profile: Option<&'a CoverageProfile>,
+    capture_model: Option<&'a CaptureModel>,
+    low_complexity: Option<(&'a Regions, f64)>,
+    quality_crash_rate: Option<f64>,
+    qc_fail_rate: Option<f64>,
+    molecule_error_rate: Option<f64>,
+    error_profile: Option<&'a CycleErrorProfile>,
+    haplotypes: Option<&'a [HashMap<String, Vec<u8>>]>,
+    haplotype_weights: Option<DiscreteDistribution>,
+    ploidy_map: Option<&'a PloidyMap>,
+    heteroplasmy: Option<&'a HeteroplasmicSites>,
+    ffpe: Option<&'a FfpeArtifacts>,
+    germline: Option<&'a [HashMap<String, Vec<u8>>]>,
+    tumor_fraction: f64,
+    quality_score_model: &'a QualityScoreModel,
+    // The contig's rng. Draws for an optional feature (failed reads, haplotypes, FFPE damage,
+    // molecule errors, and lost mates and index hopping downstream) are only made in runs that
+    // use it, so a run without the feature gets the same reads from a seed as it did before the
+    // feature was added. New draws should keep to that.
+    rng: &'a mut Rng,
+    name_prefix: String,
+    name_format: ReadNameFormat,
+    contig_index_offset: usize,
+    flowcell: FlowcellGeometry,
+    illumina_run: IlluminaRun,
+    // Iteration state: the next contig to start, the remaining fragments of the current contig
+    // (and the depth it was covered to), and the reads made but not yet handed out.
+    next_contig: usize,
+    current_contig: Option<&'a String>,
+    current_coverage: usize,
+    fragments: VecDeque<(usize, usize)>,
+    pending: VecDeque<SimulatedRead>,
+    template_count: usize,
+    molecule_count: usize,
+    from_germline: bool,
+    haplotype: usize,
+    // The heteroplasmic sites the current fragment has the reference base at, with that base.
+    wild_type: Vec<(usize, u8)>,
+    // The FFPE artifact sites the current fragment is damaged at, with the base its reads show.
+    deaminated: Vec<(usize, u8)>,
+    // The positions the current fragment was damaged at before sequencing, with the base its
+    // reads show there.
+    damaged: Vec<(usize, u8)>,
+    // For an optical duplicate, the template it duplicates and its cluster's offset from that
+    // template's cluster.
+    optical_origin: Option<(usize, (usize, usize))>,
+    // Whether the current template failed QC.
+    qc_fail: bool,
+}
+
+impl<'a> ReadGenerator<'a> {
+    pub fn new(
+        sequences: &'a HashMap<String, Vec<u8>>,
+        contig_order: &'a [String],
+        read_length: usize,
+        coverage: usize,
+        quality_score_model: &'a QualityScoreModel,
+        rng: &'a mut Rng,
+    ) -> Self {
+        // Sets up a single ended generator. Use paired_ended to switch on paired ended mode.
+        ReadGenerator {
+            sequences,
+            contig_order,
+            read_length,
+            coverage,
+            fragment_model: None,
+            fragment_lengths: None,
+            fragment_bounds: DEFAULT_FRAGMENT_BOUNDS,
+            adapter_read_through: false,
+            duplex_family_size: None,
+            duplicates: None,
+            targets: None,
+            coverage_profile: None,
+            capture_model: None,
+            low_complexity: None,
+            quality_crash_rate: None,
+            qc_fail_rate: None,
+            molecule_error_rate: None,
+            error_profile: None,
+            haplotypes: None,
+            haplotype_weights: None,
+            ploidy_map: None,
+            heteroplasmy: None,
+            ffpe: None,
+            germline: None,
+            tumor_fraction: 1.0,
+            quality_score_model,
+            rng,
+            name_prefix: "neat_generated_".to_string(),
+            name_format: ReadNameFormat::Simple,
+            contig_index_offset: 0,
+            flowcell: FlowcellGeometry::default(),
+            illumina_run: IlluminaRun::default(),
+            next_contig: 0,
+            current_contig: None,
+            current_coverage: coverage,
+            fragments: VecDeque::new(),
+            pending: VecDeque::new(),
+            template_count: 0,
+            molecule_count: 0,
+            from_germline: false,
+            haplotype: 0,
+            wild_type: Vec::new(),
+            deaminated: Vec::new(),
+            damaged: Vec::new(),
+            optical_origin: None,
+            qc_fail: false,
+        }
+    }
+
+    pub fn paired_ended(mut self, fragment_mean: f64, fragment_st_dev: f64) -> Self {
+        // Fragments are drawn from a normal distribution with this mean and standard deviation,
+        // and each fragment yields a read 1 (forward) and a read 2 (reverse) from its two ends.
+        self.fragment_model = Some(FragmentModel::Normal {
+            mean: fragment_mean,
+            st_dev: fragment_st_dev,
+        });
+        self
+    }
+
+    pub fn cell_free(mut self) -> Self {
+        // Paired ended reads from cell-free DNA fragments (see FragmentModel::sample_length).
+        self.fragment_model = Some(FragmentModel::CellFree);
+        self
+    }
+
+    pub fn fragment_lengths(mut self, fragment_lengths: &'a FragmentLengths) -> Self {
+        // Paired ended fragments are drawn from these lengths instead of the fragment model.
+        self.fragment_lengths = Some(fragment_lengths);
+        self
+    }
+
+    pub fn fragment_bounds(mut self, min: usize, max: usize) -> Self {
+        // Fragment lengths outside min..=max are drawn again (see bounded_fragment_length).
+        // Fragments longer than a contig or target region can't be placed in it and are left out;
+        // reads from fragments shorter than the read length stop at the fragment's end, or read
+        // into the adapter.
+        self.fragment_bounds = (min, max);
+        self
+    }
+
+    pub fn targets(mut self, targets: &'a Regions) -> Self {
+        // Only covers these regions of each contig (e.g., a capture panel), each to the full
+        // coverage. Contigs without targets get no reads.
+        self.targets = Some(targets);
+        self
+    }
+
+    pub fn coverage_profile(mut self, coverage_profile: &'a CoverageProfile) -> Self {
+        // Shapes the coverage along each contig to follow the profile, instead of covering it
+        // evenly. The profile's depths replace the generator's coverage.
+        self.coverage_profile = Some(coverage_profile);
+        self
+    }
+
+    pub fn capture_model(mut self, capture_model: &'a CaptureModel) -> Self {
+        // Thins the fragments of each target region as a hybrid capture would (see CaptureModel),
+        // instead of covering every target evenly. The targets should be padded by at least the
+        // model's shoulder, so there are fragments there to keep.
+        self.capture_model = Some(capture_model);
+        self
+    }
+
+    pub fn low_complexity(mut self, regions: &'a Regions, error_fold: f64) -> Self {
+        // Read bases from these regions (homopolymers and tandem repeats, see low_complexity.rs)
+        // get error_fold times the error rate their quality scores would give: the scores are
+        // lowered to match, and sequencing errors are drawn at the lowered scores.
+        self.low_complexity = Some((regions, error_fold));
+        self
+    }
+
+    pub fn quality_crash(mut self, rate: f64) -> Self {
+        // This fraction of reads has its quality crash partway through the second half of the
+        // read, as when a cluster loses phase: every base from there on gets CRASH_QUALITY and
+        // sequencing errors drawn at it.
+        self.quality_crash_rate = Some(rate);
+        self
+    }
+
+    pub fn qc_fail(mut self, rate: f64) -> Self {
+        // This fraction of templates fails the sequencer's quality filter: their reads are marked
+        // as QC failures, and every base gets a low quality score (see QC_FAIL_ERROR_FOLD).
+        self.qc_fail_rate = Some(rate);
+        self
+    }
+
+    pub fn molecule_errors(mut self, rate: f64) -> Self {
+        // Each base of a fragment is damaged before sequencing with this probability, changing it
+        // to one of the other three bases. Every read of the fragment shows the damage, so the
+        // mates of a pair agree on it where they overlap, while their sequencing errors are their
+        // own.
+        self.molecule_error_rate = Some(rate);
+        self
+    }
+
+    pub fn error_profile(mut self, error_profile: &'a CycleErrorProfile) -> Self {
+        // Every read base gets a substitution with its cycle's rate in the profile, and a quality
+        // score no higher than that rate stands for, so errors ramp up along the reads as they do
+        // on real runs.
+        self.error_profile = Some(error_profile);
+        self
+    }
+
+    pub fn haplotypes(mut self, haplotypes: &'a [HashMap<String, Vec<u8>>]) -> Self {
+        // Draws each fragment from one of these copies of the genome, picked at random, instead of
+        // from the generator's sequences. A variant on only some of the copies then only shows up
+        // in some of the reads.
+        self.haplotypes = Some(haplotypes);
+        self
+    }
+
+    pub fn haplotype_weights(mut self, weights: &[f64]) -> Self {
+        // Picks the haplotype of each fragment by these weights, one per haplotype, instead of
+        // equally, as for the haplotypes of a quasispecies at their frequencies.
+        self.haplotype_weights = Some(DiscreteDistribution::new(weights, false));
+        self
+    }
+
+    pub fn ploidy_map(mut self, ploidy_map: &'a PloidyMap) -> Self {
+        // Where the sample has fewer copies of the genome than there are haplotypes, fragments
+        // are only drawn from the copies it has, and proportionally fewer of them.
+        self.ploidy_map = Some(ploidy_map);
+        self
+    }
+
+    pub fn heteroplasmy(mut self, sites: &'a HeteroplasmicSites) -> Self {
+        // Each fragment over one of these sites carries its variant with probability the site's
+        // fraction, and the reference base otherwise (see heteroplasmy.rs).
+        self.heteroplasmy = Some(sites);
+        self
+    }
+
+    pub fn ffpe_artifacts(mut self, artifacts: &'a FfpeArtifacts) -> Self {
+        // Each paired ended template reads the top or the bottom strand of its fragment (read 1
+        // forward or in reverse), equally likely, and fragments from a damaged strand show the
+        // artifact at the sites they cover with their rate (see ffpe.rs).
+        self.ffpe = Some(artifacts);
+        self
+    }
+
+    pub fn tumor_fraction(
+        mut self,
+        germline_haplotypes: &'a [HashMap<String, Vec<u8>>],
+        tumor_fraction: f64,
+    ) -> Self {
+        // Each fragment comes from the tumor (the generator's sequences) with probability
+        // tumor_fraction, and otherwise from normal cells, which only carry the germline variants.
+        // There is one set of germline sequences per haplotype.
+        self.germline = Some(germline_haplotypes);
+        self.tumor_fraction = tumor_fraction;
+        self
+    }
+
+    pub fn adapter_read_through(mut self, adapter_read_through: bool) -> Self {
+        // Reads from fragments shorter than the read length keep going into the adapter, so every
+        // read is full length. Otherwise they stop at the end of the fragment.
+        self.adapter_read_through = adapter_read_through;
+        self
+    }
+
+    pub fn duplex(mut self, family_size: usize) -> Self {
+        // Each fragment becomes a source molecule, tagged with a pair of UMIs, that is sequenced
+        // family_size times from each strand. Every copy gets its own quality scores and
+        // sequencing errors, so duplex consensus callers have something to correct. Needs paired
+        // ended mode.
+        self.duplex_family_size = Some(family_size);
+        self
+    }
+
+    pub fn duplicates(mut self, rate: f64, optical_fraction: f64) -> Self {
+        // Each template is followed by a duplicate of its fragment with probability rate, and
+        // each duplicate by another with the same probability, so about rate of all templates
+        // are duplicates. A duplicate is optical with probability optical_fraction: its cluster
+        // is beside the original's, within the flowcell's optical distance. The rest are PCR
+        // duplicates, with clusters of their own. Not for duplex mode, whose families already
+        // are copies of one molecule.
+        self.duplicates = Some((rate, optical_fraction));
+        self
+    }
+
+    pub fn flowcell(mut self, geometry: FlowcellGeometry) -> Self {
+        // Where the clusters of Illumina read names are put.
+        self.flowcell = geometry;
+        self
+    }
+
+    pub fn illumina_run(mut self, run: IlluminaRun) -> Self {
+        // The instrument, flowcell and lane in Illumina read names.
+        self.illumina_run = run;
+        self
+    }
+
+    pub fn name_prefix(mut self, name_prefix: &str) -> Self {
+        // Simple read names are this prefix followed by the template number.
+        self.name_prefix = name_prefix.to_string();
+        self
+    }
+
+    pub fn name_format(mut self, name_format: ReadNameFormat, contig_index_offset: usize) -> Self {
+        // Illumina names encode the contig's index; contig_index_offset is the index of the
+        // first contig in contig_order, for generators that only see part of the reference.
+        self.name_format = name_format;
+        self.contig_index_offset = contig_index_offset;
+        self
+    }
+
+    fn load_next_contig(&mut self) -> bool {
+        // Computes the fragment positions of the next contig that has any. Returns false when
+        // there are no contigs left.
+        while self.next_contig < self.contig_order.len() {
+            let contig = &self.contig_order[self.next_contig];
+            self.next_contig += 1;
+            // Reads only come from the called parts of the contig, and each is covered to the full
+            // depth: covering the whole contig would spread the layers over its N gaps too.
+            let called = non_n_regions(&self.sequences[contig]);
+            let regions = match self.targets {
+                Some(targets) => intersect_regions(
+                    targets.get(contig).map_or(&[], Vec::as_slice), &called
+                ),
+                None => called,
+            };
+            // With a coverage profile, the contig is covered to its deepest window and the
+            // fragments are thinned down to the profile as they are handed out. A capture model
+            // likewise covers it to its best target.
+            let mut depth = match self.coverage_profile {
+                Some(profile) => profile.max_depth(contig),
+                None => self.coverage as f64,
+            };
+            if let Some(capture_model) = self.capture_model {
+                depth *= capture_model.max_efficiency(contig);
+            }
+            // The mitochondrial genome is read far deeper than its single copy would be. Where
+            // some contigs have more copies than the ploidy, every contig is covered deeper, so
+            // that each copy still gets the depth of one copy of the rest of the genome (the
+            // others are thinned down to their copies as their fragments are handed out).
+            if let Some(ploidy_map) = self.ploidy_map {
+                depth *= ploidy_map.depth_fold(contig);
+                let all_copies = self.haplotypes.map_or(1, |haplotypes| haplotypes.len());
+                if all_copies > ploidy_map.ploidy() {
+                    depth *= all_copies as f64 / ploidy_map.ploidy() as f64;
+                }
+            }
+            let coverage = depth.ceil() as usize;
+            if coverage == 0 {
+                continue
+            }
+            let mut positions: Vec<(usize, usize)> = Vec::new();
+            for (region_start, region_end) in regions {
+                let span_length = region_end - region_start;
+                if span_length < self.read_length {
+                    continue
+                }
+                let (model, lengths, bounds, read_length) = (
+                    self.fragment_model, self.fragment_lengths, self.fragment_bounds,
+                    self.read_length,
+                );
+                let next_fragment = |rng: &mut Rng| match (model, lengths) {
+                    (Some(_), Some(lengths)) => lengths.sample_length(bounds, rng),
+                    (Some(model), None) => model.sample_length(bounds, rng),
+                    (None, _) => read_length,
+                };
+                positions.extend(
+                    cover_dataset(span_length, read_length, next_fragment, coverage, self.rng)
+                        .into_iter()
+                        .map(|(start, end)| (start + region_start, end + region_start))
+                );
+            }
+            if positions.is_empty() {
+                continue
+            }
+            self.current_contig = Some(contig);
+            self.current_coverage = coverage;
+            self.fragments = VecDeque::from(positions);
+            return true
+        }
+        false
+    }
+
+    fn make_read(
+        &mut self,
+        contig: &str,
+        start: usize,
+        end: usize,
+        strand: Strand,
+        mate: u8,
+    ) -> SimulatedRead {
+        let sequences = self.fragment_sequences();
+        let slice = &sequences[contig][start..end];
+        let mut seq = match strand {
+            Strand::Forward => slice.to_vec(),
+            Strand::Reverse => reverse_complement(slice),
+        };
+        let fragment_bases = self.wild_type.iter().chain(&self.deaminated).chain(&self.damaged);
+        for (position, reference) in fragment_bases {
+            if (start..end).contains(position) {
+                match strand {
+                    Strand::Forward => seq[position - start] = *reference,
+                    Strand::Reverse => seq[end - 1 - position] = complement(*reference),
+                }
+            }
+        }
+        let adapter_length = if self.adapter_read_through {
+            self.read_length.saturating_sub(seq.len())
+        } else {
+            0
+        };
+        seq.extend(adapter_bases(mate, adapter_length));
+        let mut quals = self.quality_score_model.generate_quality_scores(seq.len(), self.rng);
+        let cycle_rates = self.error_profile.map(|profile| profile.rates(mate, seq.len()));
+        if let Some(rates) = &cycle_rates {
+            for (quality, rate) in quals.iter_mut().zip(rates) {
+                *quality = capped_quality(*quality, *rate);
+            }
+        }
+        let mut error_bases = self.low_complexity_bases(contig, start, end, strand);
+        if let Some((_, error_fold)) = self.low_complexity {
+            for index in &error_bases {
+                quals[*index] = lowered_quality(quals[*index], error_fold);
+            }
+        }
+        if self.qc_fail {
+            for quality in quals.iter_mut() {
+                *quality = lowered_quality(*quality, QC_FAIL_ERROR_FOLD);
+            }
+            error_bases = (0..seq.len()).collect();
+        }
+        if let Some(crash_start) = self.quality_crash_start(seq.len()) {
+            quals[crash_start..].fill(CRASH_QUALITY);
+            error_bases.extend(crash_start..seq.len());
+            error_bases.sort_unstable();
+            error_bases.dedup();
+        }
+        if self.duplex_family_size.is_some() {
+            add_sequencing_errors(&mut seq, &quals, self.rng);
+        } else {
+            // Otherwise only the low-complexity bases, those past a crash and those of reads that
+            // fail QC get errors, and the rest those of their cycle.
+            if let Some(rates) = cycle_rates {
+                for (index, rate) in rates.into_iter().enumerate() {
+                    if !error_bases.contains(&index) {
+                        add_substitution(&mut seq[index], rate, self.rng);
+                    }
+                }
+            }
+            for index in error_bases {
+                add_sequencing_error(&mut seq[index], quals[index], self.rng);
+            }
+        }
+        SimulatedRead {
+            name: match self.name_format {
+                ReadNameFormat::Simple => format!("{}{}", self.name_prefix, self.template_count),
+                ReadNameFormat::Illumina => {
+                    // next_contig has already moved past the contig being read
+                    let contig_index = self.contig_index_offset + self.next_contig - 1;
+                    illumina_read_name(&self.illumina_run, match self.optical_origin {
+                        Some((original, (x_offset, y_offset))) => {
+                            let (tile, x, y) = self.flowcell.location(contig_index, original);
+                            (tile, x + x_offset, y + y_offset)
+                        },
+                        None => self.flowcell.location(contig_index, self.template_count),
+                    })
+                },
+            },
+            seq,
+            quals,
+            contig: contig.to_string(),
+            start,
+            strand,
+            mate,
+            adapter_length,
+            duplex: None,
+            qc_fail: self.qc_fail,
+        }
+    }
+
+    fn fragment_sequences(&self) -> &'a HashMap<String, Vec<u8>> {
+        // The copy of the genome the current fragment comes from.
+        match (self.germline, self.haplotypes) {
+            (Some(germline_haplotypes), _) if self.from_germline => {
+                &germline_haplotypes[self.haplotype]
+            },
+            (_, Some(haplotypes)) => &haplotypes[self.haplotype],
+            _ => self.sequences,
+        }
+    }
+
+    fn quality_crash_start(&mut self, read_length: usize) -> Option<usize> {
+        // Where the quality of a read this long crashes, if it does: somewhere in its second half.
+        let rate = self.quality_crash_rate?;
+        if read_length == 0 || !self.rng.gen_bool(rate) {
+            return None
+        }
+        let first = read_length / 2;
+        Some(first + self.rng.rand_u32() as usize % (read_length - first))
+    }
+
+    fn low_complexity_bases(
+        &self,
+        contig: &str,
+        start: usize,
+        end: usize,
+        strand: Strand,
+    ) -> Vec<usize> {
+        // The indexes, along a read of [start, end) of the contig, of its bases from
+        // low-complexity regions. Empty if the generator doesn't model them.
+        let Some((regions, _)) = self.low_complexity else { return Vec::new() };
+        let Some(spans) = regions.get(contig) else { return Vec::new() };
+        let first = spans.partition_point(|(_, span_end)| *span_end <= start);
+        spans[first..]
+            .iter()
+            .take_while(|(span_start, _)| *span_start < end)
+            .flat_map(|(span_start, span_end)| *span_start.max(&start)..*span_end.min(&end))
+            .map(|position| match strand {
+                Strand::Forward => position - start,
+                Strand::Reverse => end - 1 - position,
+            })
+            .collect()
+    }
+
+    fn queue_template(
+        &mut self,
+        contig: &str,
+        (start, end): (usize, usize),
+        read1_strand: Strand,
+        duplex: Option<DuplexTag>,
+    ) {
+        // Makes the read (or read pair) for one template of a fragment. Read 1 comes from the
+        // start of the fragment when it reads forward, and from the end when it reads in reverse
+        // (the bottom strand of a duplex molecule).
+        self.template_count += 1;
+        // Both reads of a template fail the quality filter together.
+        self.qc_fail = self.qc_fail_rate.is_some_and(|rate| self.rng.gen_bool(rate));
+        // Reads never run past the end of their fragment.
+        let read_end = usize::min(start + self.read_length, end);
+        let read2_start = end.saturating_sub(self.read_length).max(start);
+        let mut reads = match (self.fragment_model.is_some(), read1_strand) {
+            (false, _) => vec![self.make_read(contig, start, read_end, Strand::Forward, 1)],
+            (true, Strand::Forward) => vec![
+                self.make_read(contig, start, read_end, Strand::Forward, 1),
+                self.make_read(contig, read2_start, end, Strand::Reverse, 2),
+            ],
+            (true, Strand::Reverse) => vec![
+                self.make_read(contig, read2_start, end, Strand::Reverse, 1),
+                self.make_read(contig, start, read_end, Strand::Forward, 2),
+            ],
+        };
+        for read in &mut reads {
+            read.duplex = duplex.clone();
+        }
+        self.pending.extend(reads);
+    }
+
+    fn queue_duplicates(
+        &mut self,
+        contig: &str,
+        fragment: (usize, usize),
+        read1_strand: Strand,
+        rate: f64,
+        optical_fraction: f64,
+    ) {
+        // Makes the duplicates, if any, of the template just queued. Optical duplicates each get
+        // a different spot in the original's cell of the flowcell lattice, so they never share a
+        // name; once the cell is full, the rest are PCR duplicates.
+        let original = self.template_count;
+        let distance = self.flowcell.optical_distance.unwrap_or(0);
+        let mut offsets: Vec<(usize, usize)> = Vec::new();
+        while self.rng.gen_bool(rate) {
+            let cell_full = offsets.len() + 1 >= (distance + 1) * (distance + 1);
+            if optical_fraction > 0.0 && !cell_full && self.rng.gen_bool(optical_fraction) {
+                let offset = loop {
+                    let x_offset = self.rng.rand_u32() as usize % (distance + 1);
+                    let y_offset = self.rng.rand_u32() as usize % (distance + 1);
+                    if (x_offset, y_offset) != (0, 0) && !offsets.contains(&(x_offset, y_offset)) {
+                        break (x_offset, y_offset)
+                    }
+                };
+                offsets.push(offset);
+                self.optical_origin = Some((original, offset));
+            }
+            self.queue_template(contig, fragment, read1_strand, None);
+            self.optical_origin = None;
+        }
+    }
+
+    fn queue_duplex_family(&mut self, contig: &str, fragment: (usize, usize), family_size: usize) {
+        // Makes family_size read pairs from each strand of one molecule.
+        self.molecule_count += 1;
+        let mut random_umi = || -> String {
+            (0..UMI_LENGTH).map(|_| u8_to_base((self.rng.rand_u32() % 4) as u8)).collect()
+        };
+        let (umi1, umi2) = (random_umi(), random_umi());
+        // next_contig has already moved past the contig being read
+        let molecule = format!(
+            "{}_{}", self.contig_index_offset + self.next_contig - 1, self.molecule_count
+        );
+        let strands = [
+            (Strand::Forward, format!("{}-{}", umi1, umi2), "A"),
+            (Strand::Reverse, format!("{}-{}", umi2, umi1), "B"),
+        ];
+        for (read1_strand, umi, label) in strands {
+            let tag = DuplexTag { umi, molecule_id: format!("{}/{}", molecule, label) };
+            for _ in 0..family_size {
+                self.queue_template(contig, fragment, read1_strand, Some(tag.clone()));
+            }
+        }
+    }
+}
+
+fn add_substitution(base: &mut u8, probability: f64, rng: &mut Rng) {
+    // Swaps the base for one of the other three with this probability.
+    if *base < 4 && rng.random() < probability {
+        *base = (*base + 1 + (rng.rand_u32() % 3) as u8) % 4;
+    }
+}
+
+fn add_sequencing_error(base: &mut u8, quality: u32, rng: &mut Rng) {
+    // Swaps the base with the error probability its quality score stands for (10^(-q/10)).
+    add_substitution(base, 10f64.powf(-(quality as f64) / 10.0), rng);
+}
+
+pub fn add_sequencing_errors(seq: &mut [u8], quals: &[u32], rng: &mut Rng) {
+    // Adds sequencing errors to every base of a read (see add_sequencing_error).
+    for (base, quality) in seq.iter_mut().zip(quals) {
+        add_sequencing_error(base, *quality, rng);
+    }
+}
+
+impl Iterator for ReadGenerator<'_> {
+    type Item = SimulatedRead;
+
+    fn next(&mut self) -> Option<SimulatedRead> {
+        if let Some(read) = self.pending.pop_front() {
+            return Some(read)
+        }
+        let all_copies = self.haplotypes.map_or(1, |haplotypes| haplotypes.len());
+        let (contig, fragment, copies) = loop {
+            if self.fragments.is_empty() && !self.load_next_contig() {
+                return None
+            }
+            let contig = self.current_contig?;
+            let fragment = self.fragments.pop_front()?;
+            // A fragment is kept with the profile's depth at its middle (times its capture rate)
+            // over the depth the contig was covered to.
+            if self.coverage_profile.is_some() || self.capture_model.is_some() {
+                let mut depth = match self.coverage_profile {
+                    Some(profile) => profile.depth(contig, (fragment.0 + fragment.1) / 2),
+                    None => self.coverage as f64,
+                };
+                if let Some(capture_model) = self.capture_model {
+                    depth *= capture_model.fragment_rate(contig, fragment, &self.sequences[contig]);
+                }
+                let keep = (depth / self.current_coverage as f64).min(1.0);
+                if keep < 1.0 && (keep <= 0.0 || !self.rng.gen_bool(keep)) {
+                    continue
+                }
+            }
+            // Parts of the genome with fewer copies than the rest (e.g., a male X) get
+            // proportionally fewer fragments, and none at all if the sample doesn't have them.
+            let copies = self.ploidy_map
+                .map_or(all_copies, |map| map.copies(contig, fragment.0).min(all_copies));
+            if copies == all_copies
+                || (copies > 0 && self.rng.gen_bool(copies as f64 / all_copies as f64)) {
+                break (contig, fragment, copies)
+            }
+        };
+        if self.germline.is_some() {
+            self.from_germline = self.rng.random() >= self.tumor_fraction;
+        }
+        // The copy the fragment is read from, when there is more than one.
+        self.haplotype = 0;
+        if copies > 1 {
+            self.haplotype = match &self.haplotype_weights {
+                Some(weights) if copies == all_copies => weights.sample(self.rng),
+                _ => self.rng.rand_u32() as usize % copies,
+            };
+        }
+        // The heteroplasmic sites the fragment has the reference base at, each by its fraction.
+        self.wild_type.clear();
+        if let Some(sites) = self.heteroplasmy.and_then(|sites| sites.get(contig)) {
+            let first = sites.partition_point(|(position, _, _)| *position < fragment.0);
+            for (position, reference, fraction) in &sites[first..] {
+                if *position >= fragment.1 {
+                    break
+                }
+                if !self.rng.gen_bool(*fraction) {
+                    self.wild_type.push((*position, *reference));
+                }
+            }
+        }
+        // FFPE fragments are read from either strand, and deaminated at artifact sites.
+        self.deaminated.clear();
+        let mut read1_strand = Strand::Forward;
+        if let Some(artifacts) = self.ffpe {
+            if self.rng.gen_bool(0.5) {
+                read1_strand = Strand::Reverse;
+            }
+            if let Some(sites) = artifacts.sites.get(contig) {
+                let rate = artifacts.fragment_rate(fragment.1 - fragment.0);
+                let sequence = &self.fragment_sequences()[contig];
+                let top_strand = read1_strand == Strand::Forward;
+                let first = sites.partition_point(|position| *position < fragment.0);
+                for position in sites[first..].iter().take_while(|position| **position < fragment.1)
+                {
+                    if let Some(base) = deaminated_base(sequence[*position], top_strand) {
+                        if self.rng.gen_bool(rate) {
+                            self.deaminated.push((*position, base));
+                        }
+                    }
+                }
+            }
+        }
+        // The damaged bases are a geometric distance apart, so only they are drawn.
+        self.damaged.clear();
+        if let Some(rate) = self.molecule_error_rate.filter(|rate| *rate > 0.0) {
+            let sequence = &self.fragment_sequences()[contig];
+            let mut position = fragment.0;
+            loop {
+                let gap = if rate >= 1.0 {
+                    1.0
+                } else {
+                    ((1.0 - self.rng.random()).ln() / (1.0 - rate).ln()).floor() + 1.0
+                };
+                position += gap.min((fragment.1 - fragment.0 + 1) as f64) as usize;
+                if position > fragment.1 {
+                    break
+                }
+                let mut base = sequence[position - 1];
+                add_substitution(&mut base, 1.0, self.rng);
+                self.damaged.push((position - 1, base));
+            }
+        }
+        match self.duplex_family_size {
+            Some(family_size) => self.queue_duplex_family(contig, fragment, family_size),
+            None => {
+                self.queue_template(contig, fragment, read1_strand, None);
+                if let Some((rate, optical_fraction)) = self.duplicates {
+                    self.queue_duplicates(contig, fragment, read1_strand, rate, optical_fraction);
+                }
+            },
+        }
+        self.pending.pop_front()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use simple_rng::Rng;
+    use super::super::ploidy::Sex;
 
     #[test]
     fn test_cover_dataset() {
         let span_length = 100;
         let read_length = 10;
-        let fragment_pool = vec![10];
         let coverage = 1;
         let mut rng = Rng::new_from_seed(vec![
             "Hello".to_string(),
@@ -168,7 +1164,7 @@ mod tests {
         let cover = cover_dataset(
             span_length,
             read_length,
-            fragment_pool,
+            |_| 10,
             coverage,
             &mut rng,
         );
@@ -179,7 +1175,6 @@ mod tests {
     fn test_gap_function() {
         let span_length = 100_000;
         let read_length = 100;
-        let fragment_pool = vec![300];
         let coverage = 1;
         let mut rng = Rng::new_from_seed(vec![
             "Hello".to_string(),
@@ -190,7 +1185,7 @@ mod tests {
         let cover = cover_dataset(
             span_length,
             read_length,
-            fragment_pool,
+            |_| 300,
             coverage,
             &mut rng,
         );
@@ -284,4 +1279,654 @@ mod tests {
         println!("{:?}", reads);
         assert!(!reads.unwrap().is_empty())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_generate_reads_paired_without_fragment_model() {
+        let mutated_sequence: Vec<u8> = vec![1; 1000];
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Unpaired".to_string()]);
+        let reads = generate_reads(&mutated_sequence, &100, &1, true, Some(200.0), None, &mut rng);
+        assert!(matches!(reads, Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_read_generator_single() {
+        let sequences = HashMap::from([
+            ("chr1".to_string(), vec![0, 1, 2, 3].repeat(50)),
+            ("chr2".to_string(), vec![3, 3, 1, 1].repeat(50)),
+        ]);
+        let contig_order = vec!["chr1".to_string(), "chr2".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec![
+            "Hello".to_string(),
+            "Cruel".to_string(),
+            "World".to_string(),
+        ]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &sequences, &contig_order, 20, 2, &quality_model, &mut rng,
+        ).collect();
+        assert!(!reads.is_empty());
+        // Contigs are visited in order
+        assert_eq!(reads[0].contig, "chr1");
+        assert_eq!(reads.last().unwrap().contig, "chr2");
+        for read in &reads {
+            assert_eq!(read.seq.len(), 20);
+            assert_eq!(read.quals.len(), 20);
+            assert_eq!(read.mate, 1);
+            assert_eq!(read.strand, Strand::Forward);
+            assert_eq!(read.seq, sequences[&read.contig][read.start..read.start + 20].to_vec());
+        }
+    }
+
+    #[test]
+    fn test_n_gaps() {
+        // Reads never cover the N gap, and both sides of it are covered
+        let sequence = [vec![0, 1, 2, 3].repeat(250), vec![4; 1000], vec![3, 2, 1, 0].repeat(250)]
+            .concat();
+        let sequences = HashMap::from([("chr1".to_string(), sequence)]);
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Gaps".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &sequences, &contig_order, 50, 2, &quality_model, &mut rng,
+        ).collect();
+        for read in &reads {
+            assert!(read.start + 50 <= 1000 || read.start >= 2000);
+        }
+        let before_gap = reads.iter().filter(|read| read.start < 1000).count();
+        assert!(before_gap > 0 && before_gap < reads.len());
+    }
+
+    #[test]
+    fn test_read_generator_paired() {
+        let sequences = HashMap::from([
+            ("chr1".to_string(), vec![0, 0, 1, 2, 3, 3, 2].repeat(500)),
+        ]);
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec![
+            "Hello".to_string(),
+            "Cruel".to_string(),
+            "World".to_string(),
+        ]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &sequences, &contig_order, 50, 2, &quality_model, &mut rng,
+        ).paired_ended(200.0, 1.0).collect();
+        assert!(!reads.is_empty());
+        assert_eq!(reads.len() % 2, 0);
+        for pair in reads.chunks(2) {
+            assert_eq!(pair[0].name, pair[1].name);
+            assert_eq!((pair[0].mate, pair[1].mate), (1, 2));
+            assert_eq!(pair[1].strand, Strand::Reverse);
+            let reference = &sequences["chr1"][pair[1].start..pair[1].start + 50];
+            assert_eq!(pair[1].seq, reverse_complement(reference));
+        }
+    }
+
+    #[test]
+    fn test_adapter_read_through() {
+        let sequences = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3, 3].repeat(400))]);
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Adapters".to_string()]);
+        // Fragments of about 60 bases, read 100 bases from each end
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &sequences, &contig_order, 100, 2, &quality_model, &mut rng,
+        ).paired_ended(60.0, 1.0).adapter_read_through(true).collect();
+        assert!(!reads.is_empty());
+        for pair in reads.chunks(2) {
+            let fragment_length = pair[0].aligned_length();
+            assert_eq!(pair[1].aligned_length(), fragment_length);
+            assert_eq!(pair[1].start, pair[0].start);
+            for read in pair {
+                assert_eq!(read.seq.len(), 100);
+                assert_eq!(read.quals.len(), 100);
+                assert_eq!(read.adapter_length, 100 - fragment_length);
+            }
+            assert_eq!(pair[0].seq[fragment_length..], adapter_bases(1, 100 - fragment_length)[..]);
+            assert_eq!(pair[1].seq[fragment_length..], adapter_bases(2, 100 - fragment_length)[..]);
+        }
+        assert_eq!(adapter_bases(1, 3), vec![0, 2, 0]);
+        assert_eq!(adapter_bases(2, 70)[60..], vec![0; 10][..]);
+    }
+
+    #[test]
+    fn test_duplex_families() {
+        let sequences = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3, 3].repeat(400))]);
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Duplex".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &sequences, &contig_order, 50, 1, &quality_model, &mut rng,
+        ).paired_ended(200.0, 1.0).duplex(3).collect();
+        // Each molecule gives 3 pairs from the top strand, then 3 from the bottom
+        assert_eq!(reads.len() % 12, 0);
+        for family in reads.chunks(12) {
+            let top = family[0].duplex.clone().unwrap();
+            let bottom = family[6].duplex.clone().unwrap();
+            assert!(top.molecule_id.ends_with("/A"));
+            assert_eq!(bottom.molecule_id, top.molecule_id.replace("/A", "/B"));
+            let (umi1, umi2) = top.umi.split_once('-').unwrap();
+            assert_eq!(umi1.len(), UMI_LENGTH);
+            assert_eq!(bottom.umi, format!("{}-{}", umi2, umi1));
+            for (index, pair) in family.chunks(2).enumerate() {
+                assert_eq!(pair[0].name, pair[1].name);
+                let tag = if index < 3 { &top } else { &bottom };
+                assert_eq!(pair[0].duplex.as_ref(), Some(tag));
+                // Bottom strand read 1 comes from the other end of the molecule
+                let read1_strand = if index < 3 { Strand::Forward } else { Strand::Reverse };
+                assert_eq!(pair[0].strand, read1_strand);
+            }
+            // Every copy covers the same fragment
+            assert_eq!(family[6].start, family[1].start);
+            assert_eq!(family[7].start, family[0].start);
+        }
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let sequences = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3, 3].repeat(2000))]);
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Duplicates".to_string()]);
+        let geometry = FlowcellGeometry { tile_width: 1000, optical_distance: Some(10) };
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &sequences, &contig_order, 50, 1, &quality_model, &mut rng,
+        )
+            .paired_ended(200.0, 30.0)
+            .name_format(ReadNameFormat::Illumina, 0)
+            .flowcell(geometry)
+            .duplicates(0.4, 0.5)
+            .collect();
+        let location = |read: &SimulatedRead| -> Vec<usize> {
+            read.name.rsplitn(4, ':').take(3).map(|field| field.parse().unwrap()).collect()
+        };
+        let pairs: Vec<&[SimulatedRead]> = reads.chunks(2).collect();
+        let mut names: Vec<&str> = pairs.iter().map(|pair| pair[0].name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), pairs.len());
+        // Duplicates come right after their original, from the same fragment
+        let (mut duplicates, mut optical) = (0, 0);
+        let mut original = pairs[0];
+        for pair in &pairs[1..] {
+            if (pair[0].start, pair[1].start) != (original[0].start, original[1].start) {
+                original = pair;
+                continue
+            }
+            duplicates += 1;
+            let (here, there) = (location(&pair[0]), location(&original[0]));
+            // y, x, tile: optical duplicates are on the same tile within 10 pixels both ways
+            let near = here[2] == there[2]
+                && here[0].abs_diff(there[0]) <= 10
+                && here[1].abs_diff(there[1]) <= 10;
+            optical += near as usize;
+        }
+        let rate = duplicates as f64 / pairs.len() as f64;
+        assert!((0.3..0.5).contains(&rate), "duplicate rate {}", rate);
+        let optical_fraction = optical as f64 / duplicates as f64;
+        assert!((0.35..0.65).contains(&optical_fraction), "optical fraction {}", optical_fraction);
+    }
+
+    #[test]
+    fn test_add_sequencing_errors() {
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Errors".to_string()]);
+        let mut seq = vec![0, 1, 2, 3, 4].repeat(100);
+        // Quality 0 is always wrong, quality 93 practically never
+        let mut quals = vec![0; 250];
+        quals.extend(vec![93; 250]);
+        add_sequencing_errors(&mut seq, &quals, &mut rng);
+        let original = vec![0, 1, 2, 3, 4].repeat(100);
+        for (index, (base, original_base)) in seq.iter().zip(&original).enumerate() {
+            match (*original_base, index < 250) {
+                (4, _) => assert_eq!(*base, 4),
+                (_, true) => assert_ne!(base, original_base),
+                (_, false) => assert_eq!(base, original_base),
+            }
+        }
+    }
+
+    #[test]
+    fn test_low_complexity_errors() {
+        let sequences = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3].repeat(500))]);
+        let contig_order = vec!["chr1".to_string()];
+        let regions = Regions::from([("chr1".to_string(), vec![(0, 1000)])]);
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Homopolymers".to_string()]);
+        // Errors 10,000 times as often as the quality scores say: nearly every base is wrong
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &sequences, &contig_order, 50, 5, &quality_model, &mut rng,
+        ).low_complexity(&regions, 10_000.0).collect();
+        let original = &sequences["chr1"];
+        let (mut inside, mut inside_errors, mut outside_errors) = (0, 0, 0);
+        for read in &reads {
+            assert_eq!(read.strand, Strand::Forward);
+            for (offset, base) in read.seq.iter().enumerate() {
+                let position = read.start + offset;
+                let error = *base != original[position];
+                if position < 1000 {
+                    inside += 1;
+                    inside_errors += error as usize;
+                    assert!(read.quals[offset] <= 2);
+                } else {
+                    outside_errors += error as usize;
+                }
+            }
+        }
+        assert!(inside_errors > inside / 2);
+        assert_eq!(outside_errors, 0);
+        let generator = ReadGenerator::new(
+            &sequences, &contig_order, 50, 5, &quality_model, &mut rng,
+        ).low_complexity(&regions, 2.0);
+        // Bases 990-1000 are the first 10 of a forward read of 990-1040 and the last of a reverse
+        let forward = generator.low_complexity_bases("chr1", 990, 1040, Strand::Forward);
+        let reverse = generator.low_complexity_bases("chr1", 990, 1040, Strand::Reverse);
+        assert_eq!(forward, (0..10).collect::<Vec<usize>>());
+        assert_eq!(reverse, (40..50).rev().collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_quality_crash() {
+        let sequences = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3].repeat(500))]);
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Crash".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &sequences, &contig_order, 100, 10, &quality_model, &mut rng,
+        ).quality_crash(0.5).collect();
+        let original = &sequences["chr1"];
+        let mut crashed = 0;
+        for read in &reads {
+            // Reads can end in Q2 by chance too, so this may start a little before the crash
+            let crash_start = read.quals.len()
+                - read.quals.iter().rev().take_while(|score| **score == CRASH_QUALITY).count();
+            if crash_start < read.quals.len() {
+                crashed += 1;
+            }
+            // Only crashed bases can be wrong, and crashes come in the second half of the read
+            for (offset, base) in read.seq.iter().enumerate() {
+                if *base != original[read.start + offset] {
+                    assert!(offset >= crash_start && offset >= 50);
+                }
+            }
+        }
+        assert!(crashed > reads.len() / 4 && crashed < reads.len() * 3 / 4);
+    }
+
+    #[test]
+    fn test_qc_fail() {
+        let sequences = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3].repeat(500))]);
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let generate = |rate: Option<f64>| -> Vec<SimulatedRead> {
+            let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "QC".to_string()]);
+            let generator = ReadGenerator::new(
+                &sequences, &contig_order, 100, 10, &quality_model, &mut rng,
+            ).paired_ended(300.0, 30.0);
+            match rate {
+                Some(rate) => generator.qc_fail(rate).collect(),
+                None => generator.collect(),
+            }
+        };
+        let reads = generate(Some(0.2));
+        let failed: Vec<&SimulatedRead> = reads.iter().filter(|read| read.qc_fail).collect();
+        assert!(failed.len() > reads.len() / 10 && failed.len() < reads.len() * 3 / 10);
+        // Mates fail together
+        for pair in reads.chunks(2) {
+            assert_eq!(pair[0].qc_fail, pair[1].qc_fail);
+        }
+        let mean_quality = |reads: &[&SimulatedRead]| {
+            let scores: Vec<u32> = reads.iter().flat_map(|read| read.quals.clone()).collect();
+            scores.iter().sum::<u32>() as f64 / scores.len() as f64
+        };
+        let passed: Vec<&SimulatedRead> = reads.iter().filter(|read| !read.qc_fail).collect();
+        assert!(mean_quality(&failed) + 10.0 < mean_quality(&passed));
+        // Runs that don't fail reads get none
+        assert!(generate(None).iter().all(|read| !read.qc_fail));
+    }
+
+    #[test]
+    fn test_error_profile() {
+        let sequences = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3].repeat(500))]);
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        // No errors for 50 cycles, then a ramp to one error in five bases
+        let rates = [vec![0.0; 50], vec![0.2]].concat();
+        let profile = CycleErrorProfile { read1: rates.clone(), read2: rates };
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Profile".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &sequences, &contig_order, 100, 10, &quality_model, &mut rng,
+        ).error_profile(&profile).collect();
+        let original = &sequences["chr1"];
+        let (mut late_bases, mut late_errors) = (0, 0);
+        for read in &reads {
+            for (offset, base) in read.seq.iter().enumerate() {
+                let error = *base != original[read.start + offset];
+                if offset < 50 {
+                    assert!(!error);
+                } else {
+                    late_bases += 1;
+                    late_errors += error as usize;
+                    assert!(read.quals[offset] <= 7);
+                }
+            }
+        }
+        let error_rate = late_errors as f64 / late_bases as f64;
+        assert!((0.15..0.25).contains(&error_rate), "{}", error_rate);
+    }
+
+    #[test]
+    fn test_fragment_bounds() {
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Bounds".to_string()]);
+        let mut sample = |model: FragmentModel, bounds: (usize, usize)| -> Vec<usize> {
+            (0..200).map(|_| model.sample_length(bounds, &mut rng)).collect()
+        };
+        // Half of these would be negative
+        let sizes = sample(
+            FragmentModel::Normal { mean: 0.0, st_dev: 50.0 }, DEFAULT_FRAGMENT_BOUNDS
+        );
+        assert!(sizes.iter().all(|size| *size >= 1));
+        let sizes = sample(FragmentModel::Normal { mean: 300.0, st_dev: 100.0 }, (250, 350));
+        assert!(sizes.iter().all(|size| (250..=350).contains(size)));
+        // Bounds out of reach of the distribution get clamped lengths
+        let sizes = sample(FragmentModel::Normal { mean: 300.0, st_dev: 1.0 }, (500, 600));
+        assert!(sizes.iter().all(|size| *size == 500));
+
+        // Measured lengths come out as often as they were seen, within the bounds
+        let lengths = FragmentLengths::from_counts(&BTreeMap::from([(100, 1), (200, 3)])).unwrap();
+        assert_eq!((lengths.mean, lengths.st_dev), (175.0, 43.30127018922193));
+        let sizes: Vec<usize> =
+            (0..400).map(|_| lengths.sample_length((1, 500), &mut rng)).collect();
+        let short = sizes.iter().filter(|size| **size == 100).count();
+        let long = sizes.iter().filter(|size| **size == 200).count();
+        assert!(short > 60 && short < 140 && short + long == 400);
+        assert!((0..50).all(|_| lengths.sample_length((150, 500), &mut rng) == 200));
+        assert!(FragmentLengths::from_counts(&BTreeMap::new()).is_none());
+    }
+
+    #[test]
+    fn test_cell_free_lengths() {
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Nucleosomes".to_string()]);
+        let sizes: Vec<usize> = (0..2000)
+            .map(|_| FragmentModel::CellFree.sample_length(DEFAULT_FRAGMENT_BOUNDS, &mut rng))
+            .collect();
+        let mononucleosomal = sizes.iter().filter(|size| (140..200).contains(*size)).count();
+        let dinucleosomal = sizes.iter().filter(|size| (280..400).contains(*size)).count();
+        // The bottom of the 10 bp ladder, well below the mononucleosome peak
+        let short = sizes.iter().filter(|size| **size < 125).count();
+        assert!(mononucleosomal > 1500);
+        assert!(dinucleosomal > 100 && dinucleosomal < 300);
+        assert!(short > 20 && short < 150);
+    }
+
+    #[test]
+    fn test_fragment_lengths_vary() {
+        // Every fragment gets its own draw, rather than a whole region sharing one length
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Lazy".to_string()]);
+        let model = FragmentModel::Normal { mean: 300.0, st_dev: 30.0 };
+        let cover = cover_dataset(
+            10_000,
+            100,
+            |rng| model.sample_length(DEFAULT_FRAGMENT_BOUNDS, rng),
+            5,
+            &mut rng,
+        );
+        let lengths: HashSet<usize> = cover.iter().map(|(start, end)| end - start).collect();
+        assert!(lengths.len() > 20);
+    }
+
+    #[test]
+    fn test_targets_and_tumor_fraction() {
+        let germline = HashMap::from([("chr1".to_string(), vec![0; 5000])]);
+        let tumor = HashMap::from([("chr1".to_string(), vec![3; 5000])]);
+        let contig_order = vec!["chr1".to_string()];
+        let targets = Regions::from([("chr1".to_string(), vec![(1000, 1500), (3000, 3400)])]);
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Panel".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &tumor, &contig_order, 50, 100, &quality_model, &mut rng,
+        )
+            .cell_free()
+            .targets(&targets)
+            .tumor_fraction(std::slice::from_ref(&germline), 0.1)
+            .collect();
+        assert!(!reads.is_empty());
+        for pair in reads.chunks(2) {
+            let start = pair.iter().map(|read| read.start).min().unwrap();
+            let end = pair.iter().map(|read| read.start + read.aligned_length()).max().unwrap();
+            assert!((1000..1500).contains(&start) || (3000..3400).contains(&start));
+            assert!(end <= 1500 || (3000..=3400).contains(&end));
+            // Both mates come from the same source
+            assert_eq!(pair[0].seq[0] == 0, pair[1].seq[0] == 3);
+        }
+        let tumor_reads = reads.iter().filter(|read| read.mate == 1 && read.seq[0] == 3).count();
+        let tumor_share = tumor_reads as f64 / (reads.len() / 2) as f64;
+        assert!(tumor_share > 0.05 && tumor_share < 0.15);
+    }
+
+    #[test]
+    fn test_coverage_profile() {
+        // The first half of chr1 is wanted five times as deep as the second, and chr2 not at all.
+        let sequences = HashMap::from([
+            ("chr1".to_string(), vec![0; 10_000]),
+            ("chr2".to_string(), vec![0; 5_000]),
+        ]);
+        let contig_order = vec!["chr1".to_string(), "chr2".to_string()];
+        let windows = HashMap::from([
+            ("chr1".to_string(), vec![(0, 5_000, 50.0), (5_000, 10_000, 10.0)]),
+        ]);
+        let profile = CoverageProfile::from_windows(windows, 2).unwrap();
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Profile".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &sequences, &contig_order, 100, 2, &quality_model, &mut rng,
+        )
+            .coverage_profile(&profile)
+            .collect();
+        assert!(reads.iter().all(|read| read.contig == "chr1"));
+        let deep = reads.iter().filter(|read| read.start < 5_000).count() as f64;
+        let shallow = reads.len() as f64 - deep;
+        assert!(deep / shallow > 4.0 && deep / shallow < 6.0);
+    }
+
+    #[test]
+    fn test_ploidy_map() {
+        // A male sample: X is haploid outside its PAR, and Y has no reads in its PAR (they come
+        // from X instead).
+        let reference = HashMap::from([
+            ("chrX".to_string(), vec![0; 20_000]),
+            ("chrY".to_string(), vec![1; 20_000]),
+        ]);
+        let haplotypes = vec![reference.clone(), reference.clone()];
+        let pars = Regions::from([
+            ("chrX".to_string(), vec![(0, 10_000)]),
+            ("chrY".to_string(), vec![(0, 10_000)]),
+        ]);
+        let ploidy_map =
+            PloidyMap::for_sample(Sex::Male, 2, 1.0, &reference, Some(&pars)).unwrap();
+        let contig_order = vec!["chrX".to_string(), "chrY".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Sex".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &haplotypes[0], &contig_order, 100, 20, &quality_model, &mut rng,
+        ).haplotypes(&haplotypes).ploidy_map(&ploidy_map).collect();
+        let count = |base: u8, in_par: bool| reads
+            .iter()
+            .filter(|read| read.seq[0] == base && (read.start < 10_000) == in_par)
+            .count();
+        assert_eq!(count(1, true), 0);
+        let x_ratio = count(0, false) as f64 / count(0, true) as f64;
+        assert!(x_ratio > 0.4 && x_ratio < 0.6);
+        let y_ratio = count(1, false) as f64 / count(0, true) as f64;
+        assert!(y_ratio > 0.4 && y_ratio < 0.6);
+    }
+
+    #[test]
+    fn test_mitochondrial_depth() {
+        // Three copies of the mitochondrial genome get reads to about three times the depth.
+        let reference = HashMap::from([("chrM".to_string(), vec![0; 5000])]);
+        let contig_order = vec!["chrM".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let ploidy_map = PloidyMap::for_sample(Sex::Female, 1, 3.0, &reference, None).unwrap();
+        let num_reads = |ploidy_map: &PloidyMap| {
+            let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Mito".to_string()]);
+            ReadGenerator::new(&reference, &contig_order, 100, 4, &quality_model, &mut rng)
+                .ploidy_map(ploidy_map)
+                .count() as f64
+        };
+        let fold = num_reads(&ploidy_map) / num_reads(&PloidyMap::uniform(1));
+        assert!(fold > 2.0 && fold < 4.0, "{}", fold);
+    }
+
+    #[test]
+    fn test_haplotypes() {
+        // A variant on one of two copies turns up in about half the reads over it.
+        let reference = HashMap::from([("chr1".to_string(), vec![0; 2000])]);
+        let mut haplotypes = vec![reference.clone(), reference.clone()];
+        haplotypes[1].get_mut("chr1").unwrap()[1000] = 2;
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Haplotypes".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &haplotypes[0], &contig_order, 100, 200, &quality_model, &mut rng,
+        ).haplotypes(&haplotypes).collect();
+        let covering: Vec<&SimulatedRead> = reads
+            .iter()
+            .filter(|read| read.start <= 1000 && 1000 < read.start + read.aligned_length())
+            .collect();
+        let alt = covering.iter().filter(|read| read.seq[1000 - read.start] == 2).count();
+        let alt_share = alt as f64 / covering.len() as f64;
+        assert!(covering.len() > 50);
+        assert!(alt_share > 0.3 && alt_share < 0.7);
+    }
+
+    #[test]
+    fn test_heteroplasmy() {
+        // A variant on a quarter of the copies of chrM turns up in about a quarter of the
+        // fragments over it, with both mates of a fragment agreeing.
+        let reference = HashMap::from([("chrM".to_string(), vec![0; 2000])]);
+        let mut mutated = reference.clone();
+        mutated.get_mut("chrM").unwrap()[1000] = 2;
+        let sites = HeteroplasmicSites::from([("chrM".to_string(), vec![(1000, 0, 0.25)])]);
+        let contig_order = vec!["chrM".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Heteroplasmy".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &mutated, &contig_order, 100, 200, &quality_model, &mut rng,
+        ).paired_ended(300.0, 30.0).heteroplasmy(&sites).collect();
+        let base_at = |read: &SimulatedRead| {
+            let end = read.start + read.aligned_length();
+            (read.start..end).contains(&1000).then(|| match read.strand {
+                Strand::Forward => read.seq[1000 - read.start],
+                Strand::Reverse => complement(read.seq[end - 1 - 1000]),
+            })
+        };
+        let (mut fragments, mut alt) = (0, 0);
+        for pair in reads.chunks(2) {
+            let bases: Vec<u8> = pair.iter().filter_map(base_at).collect();
+            if let Some(base) = bases.first() {
+                assert!(bases.iter().all(|other| other == base));
+                fragments += 1;
+                alt += (*base == 2) as usize;
+            }
+        }
+        let alt_share = alt as f64 / fragments as f64;
+        assert!(fragments > 50);
+        assert!(alt_share > 0.15 && alt_share < 0.35, "{}", alt_share);
+    }
+
+    #[test]
+    fn test_ffpe_artifacts() {
+        // A C at 1000 and a G at 1001, both damaged in a fifth of the fragments of the mean length
+        let mut reference = HashMap::from([("chr1".to_string(), vec![0; 2000])]);
+        reference.get_mut("chr1").unwrap()[1000..1002].copy_from_slice(&[1, 2]);
+        let artifacts = FfpeArtifacts {
+            sites: HashMap::from([("chr1".to_string(), vec![1000, 1001])]),
+            fraction: 0.2,
+            mean_fragment: 300.0,
+        };
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "FFPE".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &reference, &contig_order, 100, 200, &quality_model, &mut rng,
+        ).paired_ended(300.0, 30.0).ffpe_artifacts(&artifacts).collect();
+        let base_at = |read: &SimulatedRead, position: usize| {
+            let end = read.start + read.aligned_length();
+            (read.start..end).contains(&position).then(|| match read.strand {
+                Strand::Forward => read.seq[position - read.start],
+                Strand::Reverse => complement(read.seq[end - 1 - position]),
+            })
+        };
+        // Per orientation (F1R2, F2R1): fragments over the sites, and those with C>T and G>A
+        let mut fragments = [0; 2];
+        let mut c_to_t = [0; 2];
+        let mut g_to_a = [0; 2];
+        for pair in reads.chunks(2) {
+            let orientation = (pair[0].strand == Strand::Reverse) as usize;
+            let c_bases: Vec<u8> = pair.iter().filter_map(|read| base_at(read, 1000)).collect();
+            let g_bases: Vec<u8> = pair.iter().filter_map(|read| base_at(read, 1001)).collect();
+            // Both mates of a fragment agree, and nothing else changes
+            assert!(c_bases.windows(2).all(|bases| bases[0] == bases[1]));
+            assert!(g_bases.windows(2).all(|bases| bases[0] == bases[1]));
+            assert!(pair.iter().all(|read| {
+                (read.start..read.start + read.aligned_length())
+                    .filter(|position| !matches!(position, 1000 | 1001))
+                    .all(|position| base_at(read, position) == Some(0))
+            }));
+            if let Some(base) = c_bases.first() {
+                fragments[orientation] += 1;
+                c_to_t[orientation] += (*base == 3) as usize;
+            }
+            if let Some(base) = g_bases.first() {
+                g_to_a[orientation] += (*base == 0) as usize;
+            }
+        }
+        assert!(fragments[0] > 50 && fragments[1] > 50);
+        // C>T only in F1R2 pairs and G>A only in F2R1 pairs
+        assert_eq!((c_to_t[1], g_to_a[0]), (0, 0));
+        for (artifacts, fragments) in [(c_to_t[0], fragments[0]), (g_to_a[1], fragments[1])] {
+            let share = artifacts as f64 / fragments as f64;
+            assert!(share > 0.1 && share < 0.35, "{}", share);
+        }
+    }
+
+    #[test]
+    fn test_molecule_errors() {
+        // Fragments of about 150 bp, so the 100 bp mates overlap by about half
+        let reference = HashMap::from([("chr1".to_string(), vec![0; 5000])]);
+        let contig_order = vec!["chr1".to_string()];
+        let quality_model = QualityScoreModel::new();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Molecule".to_string()]);
+        let reads: Vec<SimulatedRead> = ReadGenerator::new(
+            &reference, &contig_order, 100, 20, &quality_model, &mut rng,
+        ).paired_ended(150.0, 10.0).molecule_errors(0.02).collect();
+        let base_at = |read: &SimulatedRead, position: usize| {
+            let end = read.start + read.aligned_length();
+            match read.strand {
+                Strand::Forward => read.seq[position - read.start],
+                Strand::Reverse => complement(read.seq[end - 1 - position]),
+            }
+        };
+        let (mut bases, mut errors, mut overlap_errors) = (0, 0, 0);
+        for pair in reads.chunks(2) {
+            let spans: Vec<(usize, usize)> = pair
+                .iter()
+                .map(|read| (read.start, read.start + read.aligned_length()))
+                .collect();
+            for position in spans[0].0.max(spans[1].0)..spans[0].1.min(spans[1].1) {
+                // The mates agree on every base they share, errors included
+                assert_eq!(base_at(&pair[0], position), base_at(&pair[1], position));
+                overlap_errors += (base_at(&pair[0], position) != 0) as usize;
+            }
+            for read in pair {
+                bases += read.aligned_length();
+                errors += (read.start..read.start + read.aligned_length())
+                    .filter(|position| base_at(read, *position) != 0)
+                    .count();
+            }
+        }
+        let error_rate = errors as f64 / bases as f64;
+        assert!(error_rate > 0.015 && error_rate < 0.025, "{}", error_rate);
+        assert!(overlap_errors > 0);
+    }
+}
@@ -0,0 +1,174 @@
+// A memory budget for the run (max_memory), for cluster nodes that kill jobs going over what they
+// asked for. The reference and the copies of it reads are drawn from have to be held whole, but
+// the rest can be sized down: each worker holds every read of the contig it is on, so the budget
+// limits how many workers run at once, and the shuffle across contigs holds a bucket of templates
+// at a time, so it limits the bucket size, with more of the reads waiting on disk instead. The
+// sizes are estimates, erring high.
+
+use log::{info, warn};
+use super::config::RunConfiguration;
+use super::errors::NeatError;
+use super::read_shards::ContigSequences;
+
+// Bytes per base of a read in memory: the base and its (u32) quality score.
+const BYTES_PER_READ_BASE: usize = 5;
+
+// Bytes per read besides its bases: the read itself, its name and contig, and allocator overhead.
+const READ_OVERHEAD: usize = 160;
+
+// Buckets of the shuffle across contigs get at least this many templates, however tight the
+// budget, so a small budget can't turn the shuffle into thousands of tiny files.
+const MIN_SHUFFLE_BUCKET_TEMPLATES: usize = 1000;
+
+pub fn parse_memory_size(text: &str) -> Result<usize, NeatError> {
+    // Reads a size like 16G, 512M or 64k (powers of 1024, with an optional trailing B). A bare
+    // number is in bytes.
+    let upper = text.trim().to_uppercase();
+    let number = upper.strip_suffix('B').unwrap_or(&upper);
+    let (digits, scale) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1u64 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+    match digits.trim().parse::<f64>() {
+        Ok(size) if size > 0.0 && size.is_finite() => Ok((size * scale as f64) as usize),
+        _ => Err(NeatError::Config(format!(
+            "Can't read memory size {}, expected a size like 16G or 512M", text
+        ))),
+    }
+}
+
+fn template_bytes(config: &RunConfiguration) -> usize {
+    // The memory one template (a read, or a read pair) takes.
+    let reads = if config.paired_ended { 2 } else { 1 };
+    reads * (config.read_len * BYTES_PER_READ_BASE + READ_OVERHEAD)
+}
+
+fn contig_templates(config: &RunConfiguration, length: usize) -> usize {
+    // About how many templates a contig this long gets. Duplex runs sequence each fragment
+    // family_size times from each strand, and duplicates add rate / (1 - rate) templates for
+    // each fragment.
+    let copies = config.duplex_family_size.map_or(1, |family_size| 2 * family_size);
+    let templates = config.coverage * copies * length / config.read_len.max(1) + 1;
+    match config.duplicate_rate {
+        Some(rate) => (templates as f64 / (1.0 - rate)).ceil() as usize,
+        None => templates,
+    }
+}
+
+fn sequence_bytes(sequences: &ContigSequences) -> usize {
+    // The memory the reference and the copies of it held for the reads take, at a byte a base.
+    let genome: usize = sequences.reference.values().map(Vec::len).sum();
+    let copies = 1 + sequences.haplotypes.len() + sequences.germline.map_or(0, <[_]>::len);
+    genome * copies
+}
+
+pub fn worker_count(config: &RunConfiguration, sequences: &ContigSequences) -> usize {
+    // Takes:
+    // config: The run configuration, with the threads asked for and any memory budget.
+    // sequences: The sequences reads are drawn from.
+    // Returns:
+    // How many workers generate reads at once: config.threads, or fewer if that many workers on
+    // the longest contigs would go over the budget. Always at least one.
+    let Some(budget) = config.max_memory else { return config.threads };
+    let fixed = sequence_bytes(sequences);
+    let longest = sequences.order
+        .iter()
+        .map(|contig| sequences.reference[contig].len())
+        .max()
+        .unwrap_or(0);
+    let per_worker = (contig_templates(config, longest) * template_bytes(config)).max(1);
+    if fixed + per_worker > budget {
+        warn!(
+            "The memory budget ({} MB) is too small for this run: the sequences take about {} MB \
+            and the reads of the longest contig about {} MB more",
+            budget >> 20, fixed >> 20, per_worker >> 20
+        );
+    }
+    let workers = (budget.saturating_sub(fixed) / per_worker).clamp(1, config.threads.max(1));
+    if workers < config.threads {
+        info!("Using {} of {} threads to stay within the memory budget", workers, config.threads);
+    }
+    workers
+}
+
+pub fn shuffle_bucket_templates(
+    config: &RunConfiguration,
+    sequences: &ContigSequences,
+    default_templates: usize,
+) -> usize {
+    // How many templates each bucket of the shuffle across contigs holds: default_templates, or
+    // fewer if a bucket that size wouldn't fit in what the sequences leave of the budget.
+    let Some(budget) = config.max_memory else { return default_templates };
+    let available = budget.saturating_sub(sequence_bytes(sequences));
+    (available / template_bytes(config)).clamp(MIN_SHUFFLE_BUCKET_TEMPLATES, default_templates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use super::super::config::ConfigBuilder;
+    use super::super::ploidy::PloidyMap;
+
+    #[test]
+    fn test_parse_memory_size() {
+        assert_eq!(parse_memory_size("16G").unwrap(), 16 << 30);
+        assert_eq!(parse_memory_size("512mb").unwrap(), 512 << 20);
+        assert_eq!(parse_memory_size("1.5K").unwrap(), 1536);
+        assert_eq!(parse_memory_size("4096").unwrap(), 4096);
+        assert!(parse_memory_size("lots").is_err());
+        assert!(parse_memory_size("0G").is_err());
+        assert!(parse_memory_size("G").is_err());
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let reference = HashMap::from([
+            ("chr1".to_string(), vec![0; 1_000_000]),
+            ("chr2".to_string(), vec![0; 10_000]),
+        ]);
+        let haplotypes = vec![reference.clone(), reference.clone()];
+        let order = vec!["chr1".to_string(), "chr2".to_string()];
+        let ploidy_map = PloidyMap::uniform(2);
+        let sequences = ContigSequences {
+            reference: &reference,
+            haplotypes: &haplotypes,
+            order: &order,
+            ploidy_map: &ploidy_map,
+            heteroplasmy: None,
+            germline: None,
+            targets: None,
+            coverage_profile: None,
+            capture_model: None,
+            low_complexity: None,
+            error_profile: None,
+            ffpe: None,
+            fragment_lengths: None,
+            variants: None,
+        };
+        let config = |max_memory: Option<usize>| {
+            let mut config_builder = ConfigBuilder::new();
+            config_builder.reference = Some("test_data/H1N1.fa".to_string());
+            config_builder.threads = 8;
+            config_builder.read_len = 100;
+            config_builder.coverage = 10;
+            config_builder.max_memory = max_memory;
+            config_builder.build()
+        };
+        // Without a budget, nothing changes
+        assert_eq!(worker_count(&config(None), &sequences), 8);
+        assert_eq!(shuffle_bucket_templates(&config(None), &sequences, 150_000), 150_000);
+        // The sequences take about 3 MB, and a worker on chr1 about 63 MB
+        let roomy = config(Some(200 << 20));
+        assert_eq!(worker_count(&roomy, &sequences), 3);
+        assert_eq!(shuffle_bucket_templates(&roomy, &sequences, 150_000), 150_000);
+        assert_eq!(shuffle_bucket_templates(&roomy, &sequences, 500_000), 313_159);
+        // Too small for even one worker: runs on one anyway
+        let tight = config(Some(1 << 20));
+        assert_eq!(worker_count(&tight, &sequences), 1);
+        assert_eq!(shuffle_bucket_templates(&tight, &sequences, 150_000), 1000);
+    }
+}
@@ -0,0 +1,245 @@
+// Model bundles: a directory holding a matched set of models for one platform, so they can be
+// handed around together instead of as loose settings. Each model is a file with a fixed name,
+// and any of them may be left out, in which case the run uses its own settings (or the built in
+// model) for it:
+//   quality.json (or quality.json.gz, see compress-quality-model): a quality score model, as
+//       written by write_out_quality_model.
+//   mutation.json: {"substitutions": [[...], [...], [...], [...]]}, the weights of a germline
+//       A, C, G or T mutating to each of A, C, G and T, with zeros down the diagonal.
+//   error.json: {"read1": [...], "read2": [...]}, the substitution rate at each cycle of read 1
+//       and of read 2 (read 2 uses read 1's if left out), as in error_profile.rs.
+//   fragment.json: {"mean": 300, "st_dev": 30}, the fragment length distribution.
+// All of them are read and checked together, against each other as well as on their own, when
+// the config is checked, and read again at the start of every run, so a bundle can be swapped
+// out between the runs of a sweep or of replicates.
+
+use std::fs;
+use std::path::Path;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use super::error_profile::CycleErrorProfile;
+use super::errors::NeatError;
+use super::nucleotides::NucModel;
+use super::quality_scores::QualityScoreModel;
+
+pub const QUALITY_MODEL_FILE: &str = "quality.json";
+pub const COMPRESSED_QUALITY_MODEL_FILE: &str = "quality.json.gz";
+pub const MUTATION_MODEL_FILE: &str = "mutation.json";
+pub const ERROR_MODEL_FILE: &str = "error.json";
+pub const FRAGMENT_MODEL_FILE: &str = "fragment.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct FragmentModel {
+    pub mean: f64,
+    pub st_dev: f64,
+}
+
+#[derive(Deserialize)]
+struct MutationModelFile {
+    substitutions: Vec<Vec<u32>>,
+}
+
+#[derive(Deserialize)]
+struct ErrorModelFile {
+    read1: Vec<f64>,
+    read2: Option<Vec<f64>>,
+}
+
+#[derive(Debug, Default)]
+pub struct ModelBundle {
+    // The models found in the directory; None for those it doesn't have.
+    pub quality: Option<QualityScoreModel>,
+    pub mutation: Option<NucModel>,
+    pub error: Option<CycleErrorProfile>,
+    pub fragment: Option<FragmentModel>,
+}
+
+fn read_model_file<T: DeserializeOwned>(path: &Path) -> Result<T, NeatError> {
+    let text = fs::read_to_string(path).map_err(|error| {
+        NeatError::ModelLoad(format!("Problem reading {}: {}", path.display(), error))
+    })?;
+    serde_json::from_str(&text).map_err(|error| {
+        NeatError::ModelLoad(format!("Problem with json format in {}: {}", path.display(), error))
+    })
+}
+
+pub fn read_model_bundle(model_dir: &str) -> Result<ModelBundle, NeatError> {
+    // Takes:
+    // model_dir: The directory holding the bundle.
+    // Returns:
+    // The models in it, or an error naming the first file that is malformed, or the files that
+    // don't agree with each other.
+    let dir = Path::new(model_dir);
+    if !dir.is_dir() {
+        return Err(NeatError::ModelLoad(format!("Model directory not found: {}", model_dir)))
+    }
+    let known = [
+        QUALITY_MODEL_FILE,
+        COMPRESSED_QUALITY_MODEL_FILE,
+        MUTATION_MODEL_FILE,
+        ERROR_MODEL_FILE,
+        FRAGMENT_MODEL_FILE,
+    ];
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if !known.contains(&name.as_str()) {
+            warn!("Ignoring {} in the model directory {}", name, model_dir);
+        }
+    }
+    let bad = |file: &str, problem: &str| {
+        NeatError::ModelLoad(format!("{}: {}", dir.join(file).display(), problem))
+    };
+
+    let mut bundle = ModelBundle::default();
+    let quality_path = dir.join(QUALITY_MODEL_FILE);
+    let compressed_path = dir.join(COMPRESSED_QUALITY_MODEL_FILE);
+    if quality_path.is_file() && compressed_path.is_file() {
+        return Err(bad(
+            COMPRESSED_QUALITY_MODEL_FILE,
+            &format!("only one of it and {} can be given", QUALITY_MODEL_FILE),
+        ))
+    }
+    if quality_path.is_file() {
+        let quality = QualityScoreModel::from_json(&quality_path.to_string_lossy())?;
+        quality.check_shape(&quality_path.display().to_string())?;
+        bundle.quality = Some(quality);
+    } else if compressed_path.is_file() {
+        let quality = QualityScoreModel::from_compressed_json(&compressed_path.to_string_lossy())?;
+        quality.check_shape(&compressed_path.display().to_string())?;
+        bundle.quality = Some(quality);
+    }
+
+    let mutation_path = dir.join(MUTATION_MODEL_FILE);
+    if mutation_path.is_file() {
+        let weights = read_model_file::<MutationModelFile>(&mutation_path)?.substitutions;
+        let model = NucModel::from(weights.clone())
+            .map_err(|_| bad(MUTATION_MODEL_FILE, "substitutions must be 4 rows of 4 weights"))?;
+        let valid = weights
+            .iter()
+            .enumerate()
+            .all(|(base, row)| row[base] == 0 && row.iter().any(|weight| *weight > 0));
+        if !valid {
+            return Err(bad(
+                MUTATION_MODEL_FILE,
+                "a base can't mutate to itself, and must mutate to another base",
+            ))
+        }
+        bundle.mutation = Some(model);
+    }
+
+    let error_path = dir.join(ERROR_MODEL_FILE);
+    if error_path.is_file() {
+        let error = read_model_file::<ErrorModelFile>(&error_path)?;
+        let read2 = error.read2.unwrap_or_else(|| error.read1.clone());
+        if error.read1.is_empty() || read2.len() != error.read1.len() {
+            return Err(bad(ERROR_MODEL_FILE, "read1 and read2 need the same, non-zero, cycles"))
+        }
+        if !error.read1.iter().chain(&read2).all(|rate| (0.0..=1.0).contains(rate)) {
+            return Err(bad(ERROR_MODEL_FILE, "rates must be between 0 and 1"))
+        }
+        bundle.error = Some(CycleErrorProfile { read1: error.read1, read2 });
+    }
+
+    let fragment_path = dir.join(FRAGMENT_MODEL_FILE);
+    if fragment_path.is_file() {
+        let fragment: FragmentModel = read_model_file(&fragment_path)?;
+        if fragment.mean <= 0.0 || fragment.st_dev < 0.0 {
+            return Err(bad(FRAGMENT_MODEL_FILE, "mean must be above 0 and st_dev not below it"))
+        }
+        bundle.fragment = Some(fragment);
+    }
+
+    // Models of one platform describe reads of the same length.
+    if let (Some(quality), Some(error)) = (&bundle.quality, &bundle.error) {
+        if quality.assumed_read_length != error.read1.len() {
+            return Err(bad(ERROR_MODEL_FILE, &format!(
+                "has {} cycles, but the quality model is for reads of {}",
+                error.read1.len(), quality.assumed_read_length
+            )))
+        }
+    }
+    if bundle.quality.is_none() && bundle.mutation.is_none() && bundle.error.is_none()
+        && bundle.fragment.is_none()
+    {
+        return Err(NeatError::ModelLoad(format!("No models found in {}", model_dir)))
+    }
+    Ok(bundle)
+}
+
+impl ModelBundle {
+    pub fn log_models(&self, model_dir: &str) {
+        let found: Vec<&str> = [
+            (self.quality.is_some(), "quality"),
+            (self.mutation.is_some(), "mutation"),
+            (self.error.is_some(), "error"),
+            (self.fragment.is_some(), "fragment"),
+        ]
+        .into_iter()
+        .filter(|(found, _)| *found)
+        .map(|(_, name)| name)
+        .collect();
+        info!("Using the {} models of {}", found.join(", "), model_dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_model_bundle() {
+        let dir = "test_data/test_model_bundle";
+        fs::create_dir_all(dir).unwrap();
+        let write = |file: &str, text: &str| fs::write(Path::new(dir).join(file), text).unwrap();
+        let mutation = |diagonal: u32| {
+            serde_json::json!({ "substitutions": [
+                [diagonal, 1, 4, 1], [1, 0, 1, 4], [4, 1, 0, 1], [1, 4, 1, 0]
+            ]})
+            .to_string()
+        };
+        write(MUTATION_MODEL_FILE, &mutation(0));
+        write(FRAGMENT_MODEL_FILE, "{\"mean\": 350, \"st_dev\": 35}");
+        let bundle = read_model_bundle(dir).unwrap();
+        assert!(bundle.mutation.is_some() && bundle.quality.is_none());
+        assert_eq!(bundle.fragment, Some(FragmentModel { mean: 350.0, st_dev: 35.0 }));
+
+        // The error model has to be for the quality model's read length
+        let quality = QualityScoreModel::new();
+        let mut quality_path = format!("{}/{}", dir, QUALITY_MODEL_FILE);
+        quality.write_out_quality_model(&mut quality_path).unwrap();
+        write(ERROR_MODEL_FILE, "{\"read1\": [0.001, 0.01]}");
+        let mismatched = read_model_bundle(dir);
+        let cycles = vec![0.001; quality.assumed_read_length];
+        write(ERROR_MODEL_FILE, &serde_json::json!({ "read1": cycles }).to_string());
+        let matched = read_model_bundle(dir);
+        write(MUTATION_MODEL_FILE, &mutation(1));
+        let self_mutating = read_model_bundle(dir);
+        fs::remove_dir_all(dir).unwrap();
+        assert!(matches!(mismatched, Err(NeatError::ModelLoad(_))));
+        let matched = matched.unwrap();
+        assert_eq!(matched.error.unwrap().read2, cycles);
+        assert!(matched.quality.is_some());
+        assert!(self_mutating.is_err());
+        assert!(read_model_bundle(dir).is_err());
+    }
+    #[test]
+    fn test_malformed_model_bundle() {
+        let dir = "test_data/test_malformed_model_bundle";
+        fs::create_dir_all(dir).unwrap();
+        let mutation_path = Path::new(dir).join(MUTATION_MODEL_FILE);
+        let substitutions = "{\"substitutions\": [[0, 1, 4, 1], [1, 0, 1, 4], [4, 1, 0, 1]]}";
+        fs::write(&mutation_path, substitutions).unwrap();
+        let three_rows = read_model_bundle(dir);
+        fs::remove_file(mutation_path).unwrap();
+        // A quality model with fewer positions than its read length
+        let mut quality = QualityScoreModel::new();
+        quality.weights_from_one.truncate(10);
+        let mut quality_path = format!("{}/{}", dir, QUALITY_MODEL_FILE);
+        quality.write_out_quality_model(&mut quality_path).unwrap();
+        let short_quality = read_model_bundle(dir);
+        fs::remove_dir_all(dir).unwrap();
+        assert!(matches!(three_rows, Err(NeatError::ModelLoad(_))));
+        assert!(matches!(short_quality, Err(NeatError::ModelLoad(_))));
+    }
+}
@@ -3,113 +3,463 @@
 // mutations introduced
 //
 // mutate_sequence adds actual mutations to the fasta sequence
+//
+// Mutation is done in layers. The germline layer is applied to the reference, then an optional
+// somatic layer (with its own rate and spectrum, or a mix of SBS signatures) is applied on top of
+// the germline sequence.
+// Positions touched by an earlier layer are skipped by later ones, so every variant's reference
+// base is still the original reference base.
+//
+// A clonal isolate instead gets an exact number of SNPs (its distance from the reference, as
+// outbreak analyses count it), spread evenly over the genome by place_snps.
+//
+// Once the variants have genotypes, haplotype_sequences builds one copy of the genome per ploid,
+// each with only the variants on that copy, for the reads to be drawn from.
 extern crate simple_rng;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use log::{debug, error, warn};
+use super::fasta_tools::ContigMap;
+use super::gff_tools::RegionRates;
 use super::nucleotides::NucModel;
+use super::signatures::SignatureSpectrum;
+use super::known_sites::{KnownSite, KnownSites};
+use super::ploidy::PloidyMap;
+use super::variants::{Variant, VariantOrigin};
 use simple_rng::{Rng, DiscreteDistribution};
 
+// A mutation layer's output: the mutated sequences and the variants of each contig, both keyed by
+// contig name.
+pub type MutatedLayer = (ContigMap, Box<HashMap<String, Vec<Variant>>>);
+
+#[allow(clippy::too_many_arguments)]
 pub fn mutate_fasta(
     file_struct: &HashMap<String, Vec<u8>>,
+    mutation_rate: f64,
+    region_rates: Option<&RegionRates>,
+    nucleotide_mutation_model: &NucModel,
+    signatures: Option<&SignatureSpectrum>,
+    known_sites: Option<&KnownSites>,
+    origin: VariantOrigin,
+    prior_variants: Option<&HashMap<String, Vec<Variant>>>,
     minimum_mutations: Option<usize>,
-    mut rng: &mut Rng
-) -> (Box<HashMap<String, Vec<u8>>>, Box<HashMap<String, Vec<(usize, u8, u8)>>>) {
+    threads: usize,
+    rng: &mut Rng
+) -> MutatedLayer {
     // Takes:
     // file_struct: a hashmap of contig names (keys) and a vector
     // representing the reference sequence.
+    // mutation_rate: the fraction of bases to mutate in this layer.
+    // region_rates: if set, the rates of exons, introns and intergenic regions, used instead of
+    //      mutation_rate.
+    // nucleotide_mutation_model: the substitution spectrum for this layer.
+    // signatures: if set, a mix of SBS signatures used instead of nucleotide_mutation_model. It
+    //      decides both which positions are mutated and what they mutate to.
+    // known_sites: if set, sites (e.g. from dbSNP) where a fraction of this layer's variants are
+    //      put, with one of the site's alleles and its ID.
+    // origin: the layer these variants belong to (germline or somatic).
+    // prior_variants: variants from a previous layer. Their positions will not be mutated again.
     // minimum_mutations is a usize or None that indicates if there is a requested minimum.
     //      The default is for rusty-neat to allow 0 mutations.
-    // ploidy: The number of copies of the genome within an organism's cells
+    // threads: the number of contigs to mutate at once.
     // rng: random number generator for the run
     //
     // Returns:
     // A tuple with pointers to:
     // A hashmap with keys that are contig names and a vector with the mutated sequence
-    // A vector of tuples containing the location and alt of each variant
+    // A hashmap with keys that are contig names and a vector of the variants in that contig,
+    // sorted by position
+    //
+    // Each contig is mutated with its own rng, seeded from the run's rng. Seeds are drawn with
+    // the contigs in a fixed (sorted) order, since HashMap iteration order is randomized per map,
+    // and a contig's variants depend only on its seed. So the result is the same no matter how
+    // many threads are used or which thread gets which contig.
+    let mut contig_names: Vec<&String> = file_struct.keys().collect();
+    contig_names.sort();
+    let seeds: Vec<Vec<String>> = contig_names
+        .iter()
+        .map(|name| vec![
+            rng.rand_u32().to_string(),
+            rng.rand_u32().to_string(),
+            name.to_string(),
+            format!("{:?}", origin),
+        ])
+        .collect();
+    let next_contig = AtomicUsize::new(0);
+    let num_workers = threads.clamp(1, contig_names.len().max(1));
+    let mutated: Vec<(String, Vec<u8>, Vec<Variant>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_workers)
+            .map(|_| scope.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let index = next_contig.fetch_add(1, Ordering::Relaxed);
+                    if index >= contig_names.len() {
+                        return done
+                    }
+                    let name = contig_names[index];
+                    let mut contig_rng = Rng::new_from_seed(seeds[index].clone());
+                    let (mutated_record, mut contig_mutations) = mutate_contig(
+                        name,
+                        &file_struct[name],
+                        mutation_rate,
+                        region_rates,
+                        nucleotide_mutation_model,
+                        signatures,
+                        known_sites,
+                        origin,
+                        prior_variants,
+                        minimum_mutations,
+                        &mut contig_rng,
+                    );
+                    contig_mutations.sort_by_key(|variant| variant.position);
+                    done.push((name.clone(), mutated_record, contig_mutations));
+                }
+            }))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Mutation worker panicked"))
+            .collect()
+    });
+    let mut return_struct: HashMap<String, Vec<u8>> = HashMap::new(); // the mutated sequences
+    // hashmap with keys of the contig names with a list of variants under the contig.
+    let mut all_variants: HashMap<String, Vec<Variant>> = HashMap::new();
+    for (name, mutated_record, contig_mutations) in mutated {
+        return_struct.insert(name.clone(), mutated_record);
+        all_variants.insert(name, contig_mutations);
+    }
+
+    (Box::new(return_struct), Box::new(all_variants))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn mutate_contig(
+    name: &str,
+    sequence: &[u8],
+    mutation_rate: f64,
+    region_rates: Option<&RegionRates>,
+    nucleotide_mutation_model: &NucModel,
+    signatures: Option<&SignatureSpectrum>,
+    known_sites: Option<&KnownSites>,
+    origin: VariantOrigin,
+    prior_variants: Option<&HashMap<String, Vec<Variant>>>,
+    minimum_mutations: Option<usize>,
+    rng: &mut Rng
+) -> (Vec<u8>, Vec<Variant>) {
+    // Figures out how many variants one contig should get and adds them.
     //
     // This function performs a basic calculation (length x mutation rate +/- a random amount)
     // and chooses that many positions along the sequence to mutate. It then builds a return
     // string that represents the altered sequence and stores all the variants.
-    const MUT_RATE: f64 = 0.01; // will update this with something more elaborate later.
-    let mut return_struct: HashMap<String, Vec<u8>> = HashMap::new(); // the mutated sequences
-    // hashmap with keys of the contig names with a list of positions and alts under the contig.
-    let mut all_variants: HashMap<String, Vec<(usize, u8, u8)>> = HashMap::new();
-    // For each sequence, figure out how many variants it should get and add them
-    for (name, sequence) in file_struct {
-        // The length of this sequence
-        let sequence_length = sequence.len();
-        debug!("Sequence {} is {} bp long", name, sequence_length);
-        // Clone the reference to create mutations
-        // Calculate how many mutations to add
-        let mut rough_num_positions: f64 = sequence_length as f64 * MUT_RATE;
-        // Add or subtract a few extra positions.
-        rough_num_positions += {
-            // A random amount up to 10% of the reads
-            let factor: f64 = rng.random() * 0.10;
-            // 25% of the time subtract, otherwise we'll add.
-            let sign: f64 = if rng.gen_bool(0.25) { -1.0 } else { 1.0 };
-            // add or subtract up to 10% of the reads.
-            rough_num_positions + (sign * factor)
-        };
-        let rounded_num_positions = rough_num_positions.round() as usize;
-        // Round the number of positions to the nearest usize.
-        // If mininum_mutations have been entered, we'll use that, else we'll set that to 0.
-        let mut num_positions = 0;
-        if !minimum_mutations.is_none() {
-            // if a minimum mutations value was entered, then that is the minimum per contig.
-            if Some(rounded_num_positions) < minimum_mutations {
-                num_positions = minimum_mutations.unwrap();
-            } else {
-                num_positions = rounded_num_positions;
-            }
+
+    // The length of this sequence
+    let sequence_length = sequence.len();
+    debug!("Sequence {} is {} bp long", name, sequence_length);
+    // Clone the reference to create mutations
+    // Calculate how many mutations to add. Where the rate varies along the contig, that is the
+    // sum of the rates of its bases.
+    let base_rates = region_rates.map(|rates| rates.base_rates(name, sequence_length));
+    let mut rough_num_positions: f64 = match &base_rates {
+        Some(base_rates) => base_rates.iter().sum(),
+        None => sequence_length as f64 * mutation_rate,
+    };
+    // Add or subtract a few extra positions.
+    rough_num_positions += {
+        // A random amount up to 10% of the reads
+        let factor: f64 = rng.random() * 0.10;
+        // 25% of the time subtract, otherwise we'll add.
+        let sign: f64 = if rng.gen_bool(0.25) { -1.0 } else { 1.0 };
+        // add or subtract up to 10% of the reads.
+        rough_num_positions + (sign * factor)
+    };
+    let rounded_num_positions = rough_num_positions.round() as usize;
+    // Round the number of positions to the nearest usize.
+    // If mininum_mutations have been entered, we'll use that, else we'll set that to 0.
+    let mut num_positions = 0;
+    if let Some(minimum_mutations) = minimum_mutations {
+        // if a minimum mutations value was entered, then that is the minimum per contig.
+        if rounded_num_positions < minimum_mutations {
+            num_positions = minimum_mutations;
         } else {
-            // Else 0 is our minimum
-            if rough_num_positions.round() as usize > 0 {
-                num_positions = rough_num_positions.round() as usize;
+            num_positions = rounded_num_positions;
+        }
+    } else {
+        // Else 0 is our minimum
+        if rough_num_positions.round() as usize > 0 {
+            num_positions = rough_num_positions.round() as usize;
+        }
+    }
+    // Positions mutated by an earlier layer are off limits for this one.
+    let mut skip_positions: HashSet<usize> = match prior_variants {
+        Some(prior) => prior.get(name)
+            .map(|variants| variants.iter().map(|v| v.position).collect())
+            .unwrap_or_default(),
+        None => HashSet::new(),
+    };
+    // With signatures, how likely a position is to be mutated also depends on its context.
+    let weights = match (signatures, base_rates) {
+        (Some(spectrum), base_rates) => {
+            let mut weights = spectrum.position_weights(sequence);
+            for (weight, rate) in weights.iter_mut().zip(base_rates.iter().flatten()) {
+                *weight *= rate;
+            }
+            Some(weights)
+        },
+        (None, base_rates) => base_rates,
+    };
+    // Some of the variants can go at known sites. Those are placed first, and the rest at random.
+    let known_variants = match known_sites
+        .and_then(|known| known.sites.get(name).map(|sites| (sites, known.fraction)))
+    {
+        Some((sites, fraction)) => place_known_sites(
+            sites,
+            (num_positions as f64 * fraction).round() as usize,
+            sequence,
+            &skip_positions,
+            weights.as_deref(),
+            origin,
+            rng,
+        ),
+        None => Vec::new(),
+    };
+    skip_positions.extend(known_variants.iter().map(|variant| variant.position));
+    // Mutates the sequence, using the original
+    let (mut mutated_record, mut sequence_variants) = mutate_sequence(
+        sequence,
+        num_positions - known_variants.len(),
+        nucleotide_mutation_model,
+        signatures,
+        origin,
+        &skip_positions,
+        weights.as_deref(),
+        rng
+    );
+    for variant in known_variants {
+        mutated_record[variant.position] = variant.alt;
+        sequence_variants.push(variant);
+    }
+    (mutated_record, sequence_variants)
+}
+
+fn place_known_sites(
+    sites: &[KnownSite],
+    count: usize,
+    sequence: &[u8],
+    skip_positions: &HashSet<usize>,
+    weights: Option<&[f64]>,
+    origin: VariantOrigin,
+    rng: &mut Rng,
+) -> Vec<Variant> {
+    // Takes:
+    // sites: The known sites of the contig, which must match its sequence.
+    // count: How many variants to put at known sites.
+    // sequence: The contig's sequence.
+    // skip_positions: Positions that may not be mutated.
+    // weights: How likely each position is to be picked, as in mutate_sequence.
+    // origin: The layer the new variants belong to.
+    // rng: random number generator for the contig.
+    // Returns:
+    // count variants (or one at every usable site, if there are fewer), at sites picked by their
+    // weights, each with one of its site's alleles and the site's ID.
+    let usable: Vec<&KnownSite> = sites
+        .iter()
+        .filter(|site| sequence[site.position] < 4 && !skip_positions.contains(&site.position))
+        .filter(|site| weights.is_none_or(|weights| weights[site.position] > 0.0))
+        .collect();
+    let chosen_sites: Vec<&KnownSite> = if count >= usable.len() {
+        usable
+    } else {
+        let site_weights: Vec<f64> = usable
+            .iter()
+            .map(|site| weights.map_or(1.0, |weights| weights[site.position]))
+            .collect();
+        let dist = DiscreteDistribution::new(&site_weights, false);
+        // Repeat draws are thrown back, as in mutate_sequence.
+        let mut chosen: HashSet<usize> = HashSet::with_capacity(count);
+        let mut chosen_sites = Vec::with_capacity(count);
+        while chosen_sites.len() < count {
+            let site = usable[dist.sample(rng)];
+            if chosen.insert(site.position) {
+                chosen_sites.push(site);
+            }
+        }
+        chosen_sites
+    };
+    chosen_sites
+        .into_iter()
+        .map(|site| {
+            let alt = rng.choose(&site.alts);
+            let mut variant = Variant::new(site.position, alt, sequence[site.position], origin);
+            variant.id = site.id.clone();
+            variant
+        })
+        .collect()
+}
+
+pub fn callable_snp_positions(
+    file_struct: &HashMap<String, Vec<u8>>,
+    ploidy_map: &PloidyMap,
+) -> usize {
+    // The number of positions place_snps can put a SNP at: those that aren't N and that the
+    // sample has a copy of.
+    file_struct
+        .iter()
+        .map(|(contig, sequence)| {
+            ploidy_map
+                .copy_spans(contig, sequence.len())
+                .into_iter()
+                .filter(|(_, _, copies)| *copies > 0)
+                .map(|(start, end, _)| {
+                    sequence[start..end].iter().filter(|base| **base < 4).count()
+                })
+                .sum::<usize>()
+        })
+        .sum()
+}
+
+pub fn place_snps(
+    file_struct: &HashMap<String, Vec<u8>>,
+    contig_order: &[String],
+    num_snps: usize,
+    nucleotide_mutation_model: &NucModel,
+    ploidy_map: &PloidyMap,
+    rng: &mut Rng,
+) -> MutatedLayer {
+    // Takes:
+    // file_struct: The reference sequences, keyed by contig name.
+    // contig_order: Contig names in reference order.
+    // num_snps: Exactly how many SNPs to add. There are fewer only if there aren't that many
+    //     callable positions (see callable_snp_positions).
+    // nucleotide_mutation_model: The substitution spectrum to draw new bases from.
+    // ploidy_map: Positions the sample has no copies of are left alone.
+    // rng: random number generator for the run
+    // Returns:
+    // The mutated sequences and the germline variants of each contig, sorted by position, as
+    // mutate_fasta does. Every callable position is equally likely, whatever its contig.
+    let callable = callable_snp_positions(file_struct, ploidy_map);
+    if num_snps > callable {
+        warn!("Only {} positions can take a SNP, so {} were asked for", callable, num_snps);
+    }
+    let num_snps = num_snps.min(callable);
+    // Indexes into the callable positions of the genome, in contig order. Repeat draws are thrown
+    // back, as in mutate_sequence.
+    let mut chosen: HashSet<usize> = HashSet::with_capacity(num_snps);
+    while chosen.len() < num_snps {
+        chosen.insert(rng.range_i64(0, callable as i64) as usize);
+    }
+    let mut chosen: Vec<usize> = chosen.into_iter().collect();
+    chosen.sort_unstable();
+    let mut next = chosen.into_iter().peekable();
+    let mut mutated_map = file_struct.clone();
+    let mut all_variants: HashMap<String, Vec<Variant>> = HashMap::new();
+    let mut index = 0;
+    for contig in contig_order {
+        let sequence = &file_struct[contig];
+        let mutated = mutated_map.get_mut(contig).unwrap();
+        let variants = all_variants.entry(contig.clone()).or_default();
+        for (start, end, copies) in ploidy_map.copy_spans(contig, sequence.len()) {
+            if copies == 0 {
+                continue
+            }
+            for position in (start..end).filter(|position| sequence[*position] < 4) {
+                if next.next_if_eq(&index).is_some() {
+                    let alt = nucleotide_mutation_model.choose_new_nuc(sequence[position], rng);
+                    mutated[position] = alt;
+                    variants.push(
+                        Variant::new(position, alt, sequence[position], VariantOrigin::Germline)
+                    );
+                }
+                index += 1;
             }
         }
-        // Mutates the sequence, using the original
-        let (mutated_record, contig_mutations) = mutate_sequence(
-            &sequence, num_positions, &mut rng
-        );
-        // Add to the return struct and variants map.
-        return_struct.entry(name.clone()).or_insert(mutated_record.clone());
-        all_variants.entry(name.clone()).or_insert(contig_mutations);
     }
+    (Box::new(mutated_map), Box::new(all_variants))
+}
 
-    (Box::new(return_struct), Box::new(all_variants))
+pub fn haplotype_sequences(
+    reference: &HashMap<String, Vec<u8>>,
+    variant_locations: &HashMap<String, Vec<Variant>>,
+    ploidy: usize,
+    include_somatic: bool,
+) -> Vec<HashMap<String, Vec<u8>>> {
+    // Takes:
+    // reference: The original reference sequences, keyed by contig name.
+    // variant_locations: The variants of each contig, with their genotypes assigned.
+    // ploidy: The number of copies of the genome.
+    // include_somatic: Whether somatic variants go on the copies too (false for normal cells).
+    // Returns:
+    // ploidy sets of sequences, the first holding the variants on copy 0 of each contig, and so
+    // on. A homozygous variant is on every copy, a heterozygous one only on some.
+    (0..ploidy)
+        .map(|copy| {
+            reference
+                .iter()
+                .map(|(contig, sequence)| {
+                    let mut haplotype = sequence.clone();
+                    let variants = variant_locations.get(contig).into_iter().flatten();
+                    for variant in variants {
+                        if variant.on_copy(copy) && (include_somatic || !variant.is_somatic()) {
+                            haplotype[variant.position] = variant.alt;
+                        }
+                    }
+                    (contig.clone(), haplotype)
+                })
+                .collect()
+        })
+        .collect()
 }
 
+#[allow(clippy::too_many_arguments)]
 fn mutate_sequence(
-    sequence: &Vec<u8>,
+    sequence: &[u8],
     mut num_positions: usize,
+    nucleotide_mutation_model: &NucModel,
+    signatures: Option<&SignatureSpectrum>,
+    origin: VariantOrigin,
+    skip_positions: &HashSet<usize>,
+    weights: Option<&[f64]>,
     mut rng: &mut Rng
-) -> (Vec<u8>, Vec<(usize, u8, u8)>) {
+) -> (Vec<u8>, Vec<Variant>) {
     // Takes:
     // sequence: A u8 vector representing a sequence of DNA
     // num_positions: The number of mutations to add to this sequence
+    // nucleotide_mutation_model: The substitution spectrum to draw new bases from
+    // signatures: If set, new bases are drawn from the signature mix by each position's context
+    //     instead
+    // origin: The layer the new variants belong to
+    // skip_positions: Positions that may not be mutated (already mutated by a prior layer)
+    // weights: How likely each position is to be picked, relative to the others. Positions
+    //     with a weight of 0 are never picked. None means every position is equally likely.
     // rng: random number generator for the run
     //
     // returns a tuple with:
     // Vec<u8> is the sequence itself
-    // Vec(usize, u8, u8) is the position of the snp and the alt and ref alleles for that snp.
+    // Vec<Variant> holds the position, alt, ref and origin of each snp.
     //
     // Takes a vector of u8's and mutate a few positions at random. Returns the mutated sequence and
-    // a list of tuples with the position and the alts of the SNPs.
+    // a list of the SNPs.
     debug!("Adding {} mutations", num_positions);
-    let mut mutated_record = sequence.clone();
-    // Randomly select num_positions from positions, weighted by gc bias and whatever. For now
-    // all he weights are just equal.
-    let weights = vec![1.0; mutated_record.len()];
+    let mut mutated_record = sequence.to_vec();
+    // Randomly select num_positions from positions, weighted by their mutation rates. Without
+    // rates, all the weights are just equal.
+    let uniform_weights;
+    let weights = match weights {
+        Some(weights) => weights,
+        None => {
+            uniform_weights = vec![1.0; mutated_record.len()];
+            &uniform_weights
+        },
+    };
     // find all non n positions. This gives us a vector of valid indexes. We also build the weighted
     // vector that corresponds to our non-n positions
     let mut non_n_positions: Vec<usize> = Vec::with_capacity(sequence.len());
     let mut pared_weights: Vec<f64> = Vec::with_capacity(sequence.len());
     for (index, base) in mutated_record.iter().enumerate() {
-        if *base != 4 {
+        if *base != 4 && weights[index] > 0.0 && !skip_positions.contains(&index) {
             pared_weights.push(weights[index]);
-            non_n_positions.push(index.clone());
+            non_n_positions.push(index);
         }
     }
 
@@ -117,32 +467,41 @@ fn mutate_sequence(
     let dist = DiscreteDistribution::new(&pared_weights, false);
     // now choose a random selection of num_positions without replacement
     let mut indexes_to_mutate: Vec<usize> = Vec::new();
+    if num_positions == 0 || non_n_positions.is_empty() {
+        return (mutated_record, Vec::new())
+    }
     if num_positions > non_n_positions.len() {
         warn!("Mutating all positions in a sequence (this seems like it shouldn't happen)");
         num_positions = non_n_positions.len();
     }
-    for _ in 0..num_positions {
+    // Repeat draws are thrown back, so each position is mutated at most once.
+    let mut chosen: HashSet<usize> = HashSet::with_capacity(num_positions);
+    while indexes_to_mutate.len() < num_positions {
         let pos = non_n_positions[dist.sample(&mut rng)];
-        indexes_to_mutate.push(pos);
+        if chosen.insert(pos) {
+            indexes_to_mutate.push(pos);
+        }
     }
-    // Build the default mutation model
-    // todo incorporate custom models
-    let nucleotide_mutation_model = NucModel::new();
     // Will hold the variants added to this sequence
-    let mut sequence_variants: Vec<(usize, u8, u8)> = Vec::new();
+    let mut sequence_variants: Vec<Variant> = Vec::new();
     // for each index, picks a new base
     for index in indexes_to_mutate {
         // remember the reference for later.
         let reference_base = sequence[index];
         // pick a new base and assign the position to it.
-        mutated_record[index] = nucleotide_mutation_model.choose_new_nuc(reference_base, &mut rng);
+        mutated_record[index] = match signatures {
+            Some(spectrum) => spectrum.choose_new_nuc(sequence, index, rng),
+            None => nucleotide_mutation_model.choose_new_nuc(reference_base, rng),
+        };
         // This check simply ensures that our model actually mutated the base.
         if mutated_record[index] == reference_base {
             error!("Need to check the code choosing nucleotides");
             panic!("BUG: Mutation model failed to mutate the base. This should not happen.")
         }
         // add the location and alt base for the variant
-        sequence_variants.push((index, mutated_record[index], reference_base))
+        sequence_variants.push(
+            Variant::new(index, mutated_record[index], reference_base, origin)
+        )
     }
     (mutated_record, sequence_variants)
 }
@@ -150,6 +509,8 @@ fn mutate_sequence(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::gff_tools::{Annotations, Transcript};
+    use super::super::signatures::{parse_channel, NUM_CHANNELS};
 
     #[test]
     fn test_mutate_sequence() {
@@ -160,7 +521,16 @@ mod tests {
             "Cruel".to_string(),
             "World".to_string(),
         ]);
-        let mutant = mutate_sequence(&seq1, num_positions, &mut rng);
+        let mutant = mutate_sequence(
+            &seq1,
+            num_positions,
+            &NucModel::new(),
+            None,
+            VariantOrigin::Germline,
+            &HashSet::new(),
+            None,
+            &mut rng
+        );
         assert_eq!(mutant.0.len(), seq1.len());
         assert!(!mutant.1.is_empty());
         assert_eq!(mutant.0[0], 4);
@@ -180,16 +550,262 @@ mod tests {
         ]);
         let mutations = mutate_fasta(
             &file_struct,
+            0.01,
+            None,
+            &NucModel::new(),
+            None,
+            None,
+            VariantOrigin::Germline,
+            None,
             Some(1),
+            1,
             &mut rng,
         );
         assert!(mutations.0.contains_key("chr1"));
         assert!(mutations.1.contains_key("chr1"));
-        let mutation_location = mutations.1["chr1"][0].0;
-        let mutation_alt = mutations.1["chr1"][0].1;
-        let mutation_ref = mutations.1["chr1"][0].2;
+        let mutation_location = mutations.1["chr1"][0].position;
+        let mutation_alt = mutations.1["chr1"][0].alt;
+        let mutation_ref = mutations.1["chr1"][0].reference;
         assert_eq!(mutation_ref, seq[mutation_location]);
-        assert_ne!(mutation_alt, mutation_ref)
+        assert_ne!(mutation_alt, mutation_ref);
+        assert_eq!(mutations.1["chr1"][0].origin, VariantOrigin::Germline);
+    }
+
+    #[test]
+    fn test_mutate_fasta_threads() {
+        let file_struct: HashMap<String, Vec<u8>> = (0..6)
+            .map(|contig| (format!("chr{}", contig), vec![0, 1, 2, 3].repeat(50 + contig)))
+            .collect();
+        let mutate = |threads: usize| {
+            let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Threads".to_string()]);
+            mutate_fasta(
+                &file_struct, 0.05, None, &NucModel::new(), None, None, VariantOrigin::Germline,
+                None, None, threads, &mut rng,
+            )
+        };
+        let single = mutate(1);
+        assert_eq!(single, mutate(3));
+        assert_eq!(single, mutate(8));
+        for variants in single.1.values() {
+            assert!(!variants.is_empty());
+            assert!(variants.windows(2).all(|pair| pair[0].position < pair[1].position));
+        }
+    }
+
+    #[test]
+    fn test_region_rates() {
+        // Only the exon can be mutated, and it gets about its share of variants.
+        let file_struct = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3].repeat(1000))]);
+        let annotations = Annotations::from([("chr1".to_string(), vec![Transcript {
+            exons: vec![(1000, 2000), (3000, 3500)],
+            ..Default::default()
+        }])]);
+        let region_rates = RegionRates {
+            annotations: &annotations,
+            exon: 0.1,
+            intron: 0.0,
+            intergenic: 0.0,
+        };
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Exons".to_string()]);
+        let (_, variants) = mutate_fasta(
+            &file_struct,
+            0.5,
+            Some(&region_rates),
+            &NucModel::new(),
+            None,
+            None,
+            VariantOrigin::Germline,
+            None,
+            None,
+            1,
+            &mut rng,
+        );
+        let variants = &variants["chr1"];
+        assert!(variants.len() > 100 && variants.len() < 400);
+        for variant in variants {
+            let position = variant.position;
+            assert!((1000..2000).contains(&position) || (3000..3500).contains(&position));
+        }
+    }
+
+    #[test]
+    fn test_haplotype_sequences() {
+        let reference = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3, 0, 1])]);
+        let mut homozygous = Variant::new(1, 3, 1, VariantOrigin::Germline);
+        homozygous.genotype = vec![1, 1];
+        let mut heterozygous = Variant::new(2, 0, 2, VariantOrigin::Germline);
+        heterozygous.genotype = vec![0, 1];
+        let mut somatic = Variant::new(4, 2, 0, VariantOrigin::Somatic);
+        somatic.genotype = vec![1, 0];
+        let variants = HashMap::from([
+            ("chr1".to_string(), vec![homozygous, heterozygous, somatic])
+        ]);
+        let tumor = haplotype_sequences(&reference, &variants, 2, true);
+        assert_eq!(tumor[0]["chr1"], vec![0, 3, 2, 3, 2, 1]);
+        assert_eq!(tumor[1]["chr1"], vec![0, 3, 0, 3, 0, 1]);
+        let normal = haplotype_sequences(&reference, &variants, 2, false);
+        assert_eq!(normal[0]["chr1"], vec![0, 3, 2, 3, 0, 1]);
+        assert_eq!(normal[1], tumor[1]);
+    }
+
+    #[test]
+    fn test_somatic_layer() {
+        let seq: Vec<u8> = vec![0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3];
+        let file_struct: HashMap<String, Vec<u8>> = HashMap::from([
+            ("chr1".to_string(), seq.clone())
+        ]);
+        let mut rng = Rng::new_from_seed(vec![
+            "Hello".to_string(),
+            "Cruel".to_string(),
+            "World".to_string(),
+        ]);
+        let (germline_map, germline_variants) = mutate_fasta(
+            &file_struct,
+            0.0,
+            None,
+            &NucModel::new(),
+            None,
+            None,
+            VariantOrigin::Germline,
+            None,
+            Some(8),
+            1,
+            &mut rng,
+        );
+        let (somatic_map, somatic_variants) = mutate_fasta(
+            &germline_map,
+            0.0,
+            None,
+            &NucModel::somatic(),
+            None,
+            None,
+            VariantOrigin::Somatic,
+            Some(&germline_variants),
+            Some(4),
+            1,
+            &mut rng,
+        );
+        let germline_positions: HashSet<usize> = germline_variants["chr1"]
+            .iter()
+            .map(|v| v.position)
+            .collect();
+        assert!(!somatic_variants["chr1"].is_empty());
+        for variant in &somatic_variants["chr1"] {
+            assert!(variant.is_somatic());
+            // Somatic hits never land on a germline site, so ref is still the original base
+            assert!(!germline_positions.contains(&variant.position));
+            assert_eq!(variant.reference, seq[variant.position]);
+            assert_eq!(somatic_map["chr1"][variant.position], variant.alt);
+        }
+        for variant in &germline_variants["chr1"] {
+            assert_eq!(somatic_map["chr1"][variant.position], variant.alt);
+        }
+    }
+
+    #[test]
+    fn test_signature_layer() {
+        // With everything on T[C>T]A, only the C of TCA and the G of TGA (its reverse complement)
+        // are mutated, to T and A.
+        let seq: Vec<u8> = vec![3, 1, 0, 2, 1, 1, 3, 2, 0, 0].repeat(40);
+        let file_struct = HashMap::from([("chr1".to_string(), seq.clone())]);
+        let mut signature = [0.0; NUM_CHANNELS];
+        signature[parse_channel("T[C>T]A").unwrap()] = 1.0;
+        let spectrum = SignatureSpectrum::mix(
+            &HashMap::from([("SBS_TCA".to_string(), signature)]),
+            &[("SBS_TCA".to_string(), 1.0)],
+        ).unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Signatures".to_string()]);
+        let (_, variants) = mutate_fasta(
+            &file_struct,
+            0.05,
+            None,
+            &NucModel::somatic(),
+            Some(&spectrum),
+            None,
+            VariantOrigin::Somatic,
+            None,
+            None,
+            1,
+            &mut rng,
+        );
+        assert!(!variants["chr1"].is_empty());
+        for variant in &variants["chr1"] {
+            let context = &seq[variant.position - 1..variant.position + 2];
+            match variant.reference {
+                1 => assert_eq!((context, variant.alt), (&[3, 1, 0][..], 3)),
+                _ => assert_eq!((context, variant.alt), (&[3, 2, 0][..], 0)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_known_sites() {
+        let seq: Vec<u8> = vec![0, 1, 2, 3].repeat(250);
+        let file_struct = HashMap::from([("chr1".to_string(), seq.clone())]);
+        let sites: Vec<KnownSite> = (0..1000)
+            .step_by(10)
+            .map(|position| KnownSite {
+                position,
+                alts: vec![(seq[position] + 1) % 4],
+                id: Some(format!("rs{}", position)),
+            })
+            .collect();
+        let mutate = |fraction: f64| {
+            let known_sites = KnownSites {
+                sites: HashMap::from([("chr1".to_string(), sites.clone())]),
+                fraction,
+            };
+            let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "dbSNP".to_string()]);
+            let (mutated, variants) = mutate_fasta(
+                &file_struct, 0.04, None, &NucModel::new(), None, Some(&known_sites),
+                VariantOrigin::Germline, None, None, 1, &mut rng,
+            );
+            (mutated["chr1"].clone(), variants["chr1"].clone())
+        };
+        let (mutated, variants) = mutate(1.0);
+        assert!(!variants.is_empty());
+        for variant in &variants {
+            assert_eq!(variant.id, Some(format!("rs{}", variant.position)));
+            assert_eq!(variant.alt, (seq[variant.position] + 1) % 4);
+            assert_eq!(mutated[variant.position], variant.alt);
+        }
+        let (_, variants) = mutate(0.5);
+        let known = variants.iter().filter(|variant| variant.id.is_some()).count();
+        assert!(known > 0 && known < variants.len());
+        let (_, variants) = mutate(0.0);
+        assert!(variants.iter().all(|variant| variant.id.is_none()));
+    }
+
+    #[test]
+    fn test_place_snps() {
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Clone".to_string()]);
+        let file_struct = HashMap::from([
+            ("chromosome".to_string(), vec![0, 1, 2, 3, 4, 4, 0, 1, 2, 3]),
+            ("plasmid".to_string(), vec![3, 2, 1, 0]),
+            ("lost".to_string(), vec![0; 20]),
+        ]);
+        let order = vec!["chromosome".to_string(), "plasmid".to_string(), "lost".to_string()];
+        let ploidy_map = PloidyMap::uniform(1)
+            .with_contig_ploidy(&[("lost".to_string(), 0)], &file_struct)
+            .unwrap();
+        assert_eq!(callable_snp_positions(&file_struct, &ploidy_map), 12);
+        let (mutated, variants) = place_snps(
+            &file_struct, &order, 5, &NucModel::new(), &ploidy_map, &mut rng
+        );
+        assert_eq!(variants.values().map(Vec::len).sum::<usize>(), 5);
+        assert!(variants["lost"].is_empty());
+        for (contig, contig_variants) in variants.iter() {
+            assert!(contig_variants.windows(2).all(|pair| pair[0].position < pair[1].position));
+            for variant in contig_variants {
+                assert!(variant.reference < 4 && variant.alt != variant.reference);
+                assert_eq!(mutated[contig][variant.position], variant.alt);
+            }
+        }
+        // Asking for more than there is mutates every callable position
+        let (_, variants) = place_snps(
+            &file_struct, &order, 20, &NucModel::new(), &ploidy_map, &mut rng
+        );
+        assert_eq!(variants.values().map(Vec::len).sum::<usize>(), 12);
     }
 
     #[test]
@@ -206,7 +822,15 @@ mod tests {
         ]);
         let mutations = mutate_fasta(
             &file_struct,
+            0.01,
+            None,
+            &NucModel::new(),
+            None,
+            None,
+            VariantOrigin::Germline,
+            None,
             None,
+            1,
             &mut rng,
         );
         assert!(mutations.0.contains_key("chr1"));
@@ -8,6 +8,22 @@
 // of the character as built into Rust, but we'd then have to figure out the translations and keep
 // track of extra numbers. So this is intended to simplify everything
 use simple_rng::{DiscreteDistribution, Rng};
+use super::errors::NeatError;
+
+// base_to_u8 for every byte value, so whole lines of a fasta can be encoded with a table lookup
+// per byte. The loop in encode_bases has no branches, which lets the compiler vectorize it.
+const BASE_CODES: [u8; 256] = {
+    let mut codes = [4u8; 256];
+    codes[b'A' as usize] = 0;
+    codes[b'a' as usize] = 0;
+    codes[b'C' as usize] = 1;
+    codes[b'c' as usize] = 1;
+    codes[b'G' as usize] = 2;
+    codes[b'g' as usize] = 2;
+    codes[b'T' as usize] = 3;
+    codes[b't' as usize] = 3;
+    codes
+};
 
 pub fn base_to_u8(char_of_interest: char) -> u8 {
     // This defines the relationship between the 4 possible nucleotides in DNA and
@@ -22,6 +38,12 @@ pub fn base_to_u8(char_of_interest: char) -> u8 {
     }
 }
 
+pub fn encode_bases(text: &[u8], sequence: &mut Vec<u8>) {
+    // Appends the u8 representation of each byte of text to sequence, the same as base_to_u8
+    // would give for each character.
+    sequence.extend(text.iter().map(|&byte| BASE_CODES[byte as usize]));
+}
+
 pub fn u8_to_base(nuc_num: u8) -> char {
     // Canonical conversion from base u8 representation back into the character.
     // We're returning a string instead of a char to facilitate. No attempt to preserve or display
@@ -35,6 +57,11 @@ pub fn u8_to_base(nuc_num: u8) -> char {
     }
 }
 
+pub fn is_gc(nuc_num: u8) -> bool {
+    // Whether the base is a C or a G.
+    matches!(nuc_num, 1 | 2)
+}
+
 #[derive(Debug, Clone)]
 pub struct NucModel {
     // Simple mutation model. The letter in the model represents
@@ -60,28 +87,35 @@ impl NucModel {
         }
     }
 
-    #[allow(dead_code)]
-    // todo, once we have numbers we can implement this.
-    pub fn from(weights: Vec<Vec<u32>>) -> Self {
+    pub fn somatic() -> Self {
+        // Default somatic spectrum. Somatic substitutions in most tissues are dominated by
+        // C>T/G>A transitions (spontaneous deamination), so this model leans much harder on those
+        // than the germline model does.
+        Self {
+            a: vec![0, 12, 72, 16],
+            c: vec![9, 0, 8, 83],
+            g: vec![83, 8, 0, 9],
+            t: vec![16, 72, 12, 0],
+        }
+    }
+
+    pub fn from(weights: Vec<Vec<u32>>) -> Result<Self, NeatError> {
         // Supply a vector of 4 vectors that define the mutation chance
         // from the given base to the other 4 bases.
 
         // First some safety checks. This should be a 4x4 matrix defining mutation from
         // ACGT (top -> down) to ACGT (left -> right)
-        if weights.len() != 4 {
-            panic!("Weights supplied to NucModel is wrong size");
-        }
-        for weight_vec in &weights {
-            if weight_vec.len() != 4 {
-                panic!("Weights supplied to NucModel is wrong size");
-            }
+        if weights.len() != 4 || weights.iter().any(|weight_vec| weight_vec.len() != 4) {
+            return Err(NeatError::ModelLoad(
+                "Weights supplied to NucModel is wrong size".to_string()
+            ))
         }
-        Self {
+        Ok(Self {
             a: weights[0].clone(),
             c: weights[1].clone(),
             g: weights[2].clone(),
             t: weights[3].clone(),
-        }
+        })
     }
 
     pub fn choose_new_nuc(&self, base: u8, rng: &mut Rng) -> u8 {
@@ -107,6 +141,15 @@ impl NucModel {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_encode_bases() {
+        let text = "ACGTacgtNnRY-";
+        let mut sequence = vec![4];
+        encode_bases(text.as_bytes(), &mut sequence);
+        let expected: Vec<u8> = [4].into_iter().chain(text.chars().map(base_to_u8)).collect();
+        assert_eq!(sequence, expected);
+    }
+
     #[test]
     fn test_nuc_model_build() {
         let a_weights = vec![0, 20, 1, 20];
@@ -140,7 +183,7 @@ mod tests {
         ]);
         let test_model = NucModel::from(
             vec![a_weights, c_weights, g_weights, t_weights]
-        );
+        ).unwrap();
         // It actually mutates the base
         assert_ne!(test_model.choose_new_nuc(0, &mut rng), 0);
         assert_ne!(test_model.choose_new_nuc(1, &mut rng), 1);
@@ -151,23 +194,23 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_nuc_model_too_many_vecs() {
         let a_weights = vec![0, 20, 1, 20];
         let c_weights = vec![20, 0, 1, 1];
         let g_weights = vec![1, 1, 0, 20];
         let t_weights = vec![20, 1, 20, 0];
         let u_weights = vec![20, 1, 20, 0];
-        NucModel::from(vec![a_weights, c_weights, g_weights, t_weights, u_weights]);
+        let model = NucModel::from(vec![a_weights, c_weights, g_weights, t_weights, u_weights]);
+        assert!(matches!(model, Err(NeatError::ModelLoad(_))));
     }
 
     #[test]
-    #[should_panic]
     fn test_nuc_model_too_many_bases() {
         let a_weights = vec![0, 20, 1, 20, 1];
         let c_weights = vec![20, 0, 1, 1];
         let g_weights = vec![1, 1, 0, 20];
         let t_weights = vec![20, 1, 20, 0];
-        NucModel::from(vec![a_weights, c_weights, g_weights, t_weights]);
+        let model = NucModel::from(vec![a_weights, c_weights, g_weights, t_weights]);
+        assert!(matches!(model, Err(NeatError::ModelLoad(_))));
     }
 }
\ No newline at end of file
@@ -0,0 +1,180 @@
+// Truth pileups: the depth and base counts of the simulated reads along the genome, taken from
+// where each read was drawn rather than from an alignment, so coverage and allele fraction
+// analyses can be checked against what the reads really hold without aligning them first. Two
+// tracks are written: a bedgraph of the depth (runs of one depth merged and positions without
+// reads left out, as bedtools genomecov -bg writes them), and a table of every position where a
+// read shows a base other than the reference's, with the count of each base there. Reads count
+// as they were written (mates that were lost don't), and adapter bases don't count. Each contig's
+// reads are swept in order of their start, counting bases into a window that moves along the
+// contig, so the counts never take more room than the longest read. The same counts, taken at just
+// the variant sites, give the genotype calls of the truth vcf (see genotype_quality.rs).
+
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+use super::fastq_tools::reverse_complement;
+use super::make_reads::{SimulatedRead, Strand};
+use super::nucleotides::u8_to_base;
+
+pub const ALLELE_COUNTS_HEADER: &str = "#contig\tposition\tref\tdepth\tA\tC\tG\tT\tN";
+
+struct PileupTracks<'a, D: Write, A: Write> {
+    // Where each position's counts go, and the run of depth being built up for the bedgraph.
+    depth_writer: &'a mut D,
+    allele_writer: &'a mut A,
+    contig: &'a str,
+    reference: &'a [u8],
+    depth_run: Option<(usize, usize, u32)>,
+}
+
+impl<D: Write, A: Write> PileupTracks<'_, D, A> {
+    fn add_position(&mut self, position: usize, counts: &[u32; 5]) -> io::Result<()> {
+        let depth = counts.iter().sum();
+        match &mut self.depth_run {
+            Some((_, end, run_depth)) if *end == position && *run_depth == depth => *end += 1,
+            _ => {
+                self.finish_run()?;
+                self.depth_run = Some((position, position + 1, depth));
+            },
+        }
+        let reference_base = self.reference[position];
+        let other_base = counts[..4]
+            .iter()
+            .enumerate()
+            .any(|(base, count)| *count > 0 && base != reference_base as usize);
+        if reference_base < 4 && other_base {
+            write!(
+                self.allele_writer,
+                "{}\t{}\t{}\t{}",
+                self.contig, position + 1, u8_to_base(reference_base), depth
+            )?;
+            for count in counts {
+                write!(self.allele_writer, "\t{}", count)?;
+            }
+            writeln!(self.allele_writer)?;
+        }
+        Ok(())
+    }
+
+    fn finish_run(&mut self) -> io::Result<()> {
+        if let Some((start, end, depth)) = self.depth_run.take() {
+            writeln!(self.depth_writer, "{}\t{}\t{}\t{}", self.contig, start, end, depth)?;
+        }
+        Ok(())
+    }
+}
+
+fn contig_bases(read: &SimulatedRead) -> Vec<u8> {
+    // The bases of the read that come from the contig, as they lie along it.
+    let aligned = &read.seq[..read.aligned_length()];
+    match read.strand {
+        Strand::Forward => aligned.to_vec(),
+        Strand::Reverse => reverse_complement(aligned),
+    }
+}
+
+pub fn write_contig_pileup(
+    depth_writer: &mut impl Write,
+    allele_writer: &mut impl Write,
+    contig: &str,
+    reference: &[u8],
+    reads: &[&SimulatedRead],
+) -> io::Result<()> {
+    // Takes:
+    // depth_writer: Where to write the contig's depth bedgraph lines.
+    // allele_writer: Where to write its lines of the allele count table (see
+    //     ALLELE_COUNTS_HEADER), with 1-based positions.
+    // contig: The contig name.
+    // reference: The contig's reference sequence, in the u8 representation.
+    // reads: Every read written from the contig, in any order.
+    // Returns:
+    // Error if there is a problem writing, or else nothing.
+    let mut reads = reads.to_vec();
+    reads.sort_by_key(|read| read.start);
+    let mut tracks = PileupTracks {
+        depth_writer, allele_writer, contig, reference, depth_run: None
+    };
+    // The counts of each position from window_start on that a read has reached
+    let mut window: VecDeque<[u32; 5]> = VecDeque::new();
+    let mut window_start = 0;
+    for read in reads {
+        // No read still to come reaches back before this one's start.
+        while window_start < read.start {
+            let Some(counts) = window.pop_front() else {
+                window_start = read.start;
+                break
+            };
+            tracks.add_position(window_start, &counts)?;
+            window_start += 1;
+        }
+        for (offset, base) in contig_bases(read).into_iter().enumerate() {
+            if offset == window.len() {
+                window.push_back([0; 5]);
+            }
+            window[offset][base.min(4) as usize] += 1;
+        }
+    }
+    for counts in window {
+        tracks.add_position(window_start, &counts)?;
+        window_start += 1;
+    }
+    tracks.finish_run()
+}
+
+pub fn site_counts(reads: &[&SimulatedRead], positions: &[usize]) -> Vec<[u32; 5]> {
+    // Takes:
+    // reads: Every read written from a contig, in any order.
+    // positions: 0-based positions on the contig, in order.
+    // Returns:
+    // The reads showing each base (A, C, G, T and N) at each of the positions, counted as the
+    // truth pileup counts them.
+    let mut counts = vec![[0; 5]; positions.len()];
+    for read in reads {
+        let bases = contig_bases(read);
+        let first = positions.partition_point(|position| *position < read.start);
+        for (index, position) in positions.iter().enumerate().skip(first) {
+            let Some(base) = bases.get(position - read.start) else { break };
+            counts[index][(*base).min(4) as usize] += 1;
+        }
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_read(
+        start: usize, seq: Vec<u8>, strand: Strand, adapter_length: usize
+    ) -> SimulatedRead {
+        SimulatedRead { strand, adapter_length, ..SimulatedRead::for_test("chr1", start, seq) }
+    }
+
+    #[test]
+    fn test_write_contig_pileup() {
+        let reference = vec![0, 1, 2, 3, 0, 1, 2, 3, 0, 1];
+        // Two reads over 2..6, the reverse one with a C at 3 and two adapter bases on its end
+        // (as sequenced it is the reverse complement of GCAC), and one apart from them at 8..10
+        let forward = test_read(2, vec![2, 3, 0, 1], Strand::Forward, 0);
+        let reverse = test_read(2, vec![2, 3, 2, 1, 0, 0], Strand::Reverse, 2);
+        let apart = test_read(8, vec![0, 1], Strand::Forward, 0);
+        let mut depth = Vec::new();
+        let mut alleles = Vec::new();
+        write_contig_pileup(
+            &mut depth, &mut alleles, "chr1", &reference, &[&apart, &reverse, &forward]
+        ).unwrap();
+        assert_eq!(String::from_utf8(depth).unwrap(), "chr1\t2\t6\t2\nchr1\t8\t10\t1\n");
+        assert_eq!(String::from_utf8(alleles).unwrap(), "chr1\t4\tT\t2\t0\t1\t0\t1\t0\n");
+    }
+
+    #[test]
+    fn test_site_counts() {
+        // The same reads as above, at the T the reverse read shows a C at, a position no read
+        // covers and one only the read apart does
+        let forward = test_read(2, vec![2, 3, 0, 1], Strand::Forward, 0);
+        let reverse = test_read(2, vec![2, 3, 2, 1, 0, 0], Strand::Reverse, 2);
+        let apart = test_read(8, vec![0, 1], Strand::Forward, 0);
+        let counts = site_counts(&[&apart, &reverse, &forward], &[3, 7, 9]);
+        assert_eq!(counts, vec![[0, 1, 0, 1, 0], [0; 5], [0, 1, 0, 0, 0]]);
+    }
+}
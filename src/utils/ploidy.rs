@@ -0,0 +1,367 @@
+// How many copies of each part of the genome the simulated sample has. Everything has `ploidy`
+// copies except the sex chromosomes, which follow the sex of the sample: a female sample has two
+// X chromosomes and no Y, and a male one a single X and Y, except in the pseudoautosomal regions
+// (PARs), which pair up like an autosome. The Y copy of each PAR is simulated on X, as if the Y
+// PARs were masked in the reference (as they are in most analysis sets), so reads from them align
+// to X. The mitochondrial genome is a single sequence, but a cell holds many copies of it, so it
+// is read far deeper than the rest. Contigs can also be given their own number of copies, for
+// aneuploid samples (a trisomy, or a tumor that has lost a chromosome), which overrides all of
+// the above for them. Plasmids are read deeper than the chromosome by their copy number, as the
+// mitochondrial genome is.
+
+use std::collections::HashMap;
+use log::{info, warn};
+use super::bed_tools::Regions;
+use super::errors::NeatError;
+
+// The PARs of the human reference builds, as 0-based, half open spans, keyed by the length of
+// chrX: (build, chrX length, chrX PARs, chrY PARs).
+type BuildPars = (&'static str, usize, [(usize, usize); 2], [(usize, usize); 2]);
+const BUILT_IN_PARS: [BuildPars; 2] = [
+    (
+        "GRCh37",
+        155_270_560,
+        [(60_000, 2_699_520), (154_931_043, 155_260_560)],
+        [(10_000, 2_649_520), (59_034_049, 59_373_566)],
+    ),
+    (
+        "GRCh38",
+        156_040_895,
+        [(10_000, 2_781_479), (155_701_382, 156_030_895)],
+        [(10_000, 2_781_479), (56_887_902, 57_217_415)],
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sex {
+    Female,
+    Male,
+    // Male if the reference has a Y chromosome with any sequence, female if it has none or it is
+    // all N (like the female analysis sets that mask Y).
+    Auto,
+}
+
+impl Sex {
+    pub fn from_name(name: &str) -> Result<Sex, NeatError> {
+        match name.to_lowercase().as_str() {
+            "female" | "f" | "xx" => Ok(Sex::Female),
+            "male" | "m" | "xy" => Ok(Sex::Male),
+            "auto" => Ok(Sex::Auto),
+            _ => Err(NeatError::Config(
+                format!("sample_sex must be male, female or auto, got {:?}", name)
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PloidyMap {
+    // ploidy: The number of copies of anything not listed below.
+    // contigs: Contigs with a different number of copies.
+    // regions: Sorted, non overlapping (start, end, copies) spans of a contig that differ from
+    //     the rest of it.
+    // depth_folds: Contigs a cell has more of than their copies say (the mitochondrial genome
+    //     and plasmids), and how many times more. Their depth scales with copies times this.
+    // mitochondrial: The mitochondrial genome, if the reference has one.
+    ploidy: usize,
+    contigs: HashMap<String, usize>,
+    regions: HashMap<String, Vec<(usize, usize, usize)>>,
+    depth_folds: HashMap<String, f64>,
+    mitochondrial: Option<String>,
+}
+
+impl PloidyMap {
+    pub fn uniform(ploidy: usize) -> Self {
+        // Every part of the genome has the same number of copies.
+        PloidyMap { ploidy, ..Default::default() }
+    }
+
+    pub fn for_sample(
+        sex: Sex,
+        ploidy: usize,
+        mt_copy_number: f64,
+        fasta_map: &HashMap<String, Vec<u8>>,
+        par_regions: Option<&Regions>,
+    ) -> Result<Self, NeatError> {
+        // Takes:
+        // sex: The sex of the sample.
+        // ploidy: The number of copies of the autosomes.
+        // mt_copy_number: The copies of the mitochondrial genome in a cell.
+        // fasta_map: The reference, to find the X, Y and mitochondrial chromosomes (chrX or X,
+        //     chrY or Y, chrM or MT) in.
+        // par_regions: The PARs on X and Y, or None to use those of the reference build (told
+        //     apart by the length of X).
+        // Returns:
+        // The copies of each part of the genome, or an error if the sample is male and the PARs
+        // aren't known. A sample whose sex was inferred gets a haploid X instead of the error.
+        let mut map = PloidyMap::uniform(ploidy);
+        let find = |names: &[&str]| fasta_map.keys().find(|contig| {
+            names.contains(&contig.split_whitespace().next().unwrap_or_default())
+        });
+        let (x, y) = (find(&["chrX", "X"]), find(&["chrY", "Y"]));
+        if let Some(mt) = find(&["chrM", "MT", "chrMT"]) {
+            map.contigs.insert(mt.clone(), 1);
+            map.depth_folds.insert(mt.clone(), mt_copy_number);
+            map.mitochondrial = Some(mt.clone());
+        }
+        let inferred = sex == Sex::Auto;
+        let sex = match sex {
+            Sex::Auto if y.is_some_and(|y| fasta_map[y].iter().any(|base| *base < 4)) => Sex::Male,
+            Sex::Auto => Sex::Female,
+            sex => sex,
+        };
+        if inferred && (x.is_some() || y.is_some()) {
+            info!("Simulating a {:?} sample, from the sex chromosomes of the reference", sex);
+        }
+        if let Some(y) = y {
+            map.contigs.insert(y.clone(), if sex == Sex::Male { 1 } else { 0 });
+        }
+        if sex == Sex::Female {
+            return Ok(map)
+        }
+        let Some(x) = x else { return Ok(map) };
+        let (x_pars, y_pars) = match par_regions {
+            Some(regions) => {
+                let contig_pars = |contig: &str| regions
+                    .iter()
+                    .find(|(name, _)| contig.split_whitespace().next() == Some(name.as_str()))
+                    .map(|(_, spans)| spans.clone())
+                    .unwrap_or_default();
+                (contig_pars(x), y.map(|y| contig_pars(y)).unwrap_or_default())
+            },
+            None => {
+                let length = fasta_map[x].len();
+                let build = BUILT_IN_PARS
+                    .iter()
+                    .find(|(_, x_length, _, _)| *x_length == length);
+                match build {
+                    Some(build) => (build.2.to_vec(), build.3.to_vec()),
+                    None if inferred => {
+                        warn!(
+                            "No built in PARs for a {} bp chrX, so all of it is haploid; set \
+                            par_bed to give the PARs", length
+                        );
+                        (Vec::new(), Vec::new())
+                    },
+                    None => return Err(NeatError::Config(format!(
+                        "No built in PARs for a {} bp chrX (GRCh37 and GRCh38 are known); \
+                        set par_bed", length
+                    ))),
+                }
+            },
+        };
+        map.contigs.insert(x.clone(), 1);
+        map.regions.insert(
+            x.clone(), x_pars.iter().map(|(start, end)| (*start, *end, ploidy)).collect()
+        );
+        if let Some(y) = y {
+            map.regions.insert(
+                y.clone(), y_pars.iter().map(|(start, end)| (*start, *end, 0)).collect()
+            );
+        }
+        Ok(map)
+    }
+
+    pub fn with_contig_ploidy(
+        mut self,
+        overrides: &[(String, usize)],
+        fasta_map: &HashMap<String, Vec<u8>>,
+    ) -> Result<Self, NeatError> {
+        // Gives each named contig (matched on the first word of its name) the copies given for
+        // it, all along it. Returns an error for names that aren't in the reference.
+        for (name, copies) in overrides {
+            let contig = fasta_map
+                .keys()
+                .find(|contig| contig.split_whitespace().next() == Some(name.as_str()))
+                .ok_or_else(|| NeatError::Config(format!(
+                    "contig_ploidy names {}, which isn't in the reference", name
+                )))?;
+            self.contigs.insert(contig.clone(), *copies);
+            self.regions.remove(contig);
+        }
+        Ok(self)
+    }
+
+    pub fn with_copy_numbers(
+        mut self,
+        copy_numbers: &[(String, f64)],
+        fasta_map: &HashMap<String, Vec<u8>>,
+    ) -> Result<Self, NeatError> {
+        // Reads each named contig (matched as in with_contig_ploidy) this many times deeper than
+        // the rest of the genome, as for a plasmid with that many copies per chromosome. Returns
+        // an error for names that aren't in the reference.
+        for (name, copy_number) in copy_numbers {
+            let contig = fasta_map
+                .keys()
+                .find(|contig| contig.split_whitespace().next() == Some(name.as_str()))
+                .ok_or_else(|| NeatError::Config(format!(
+                    "plasmid_copy_number names {}, which isn't in the reference", name
+                )))?;
+            self.depth_folds.insert(contig.clone(), *copy_number);
+        }
+        Ok(self)
+    }
+
+    pub fn ploidy(&self) -> usize {
+        self.ploidy
+    }
+
+    pub fn max_copies(&self) -> usize {
+        // The most copies of anything, which is how many haplotypes the sample needs.
+        let regions = self.regions.values().flatten().map(|(_, _, copies)| *copies);
+        self.contigs.values().copied().chain(regions).fold(self.ploidy, usize::max)
+    }
+
+    pub fn copy_spans(&self, contig: &str, length: usize) -> Vec<(usize, usize, usize)> {
+        // The copies of each part of a contig this long, as [start, end) spans covering all of
+        // it, in order.
+        let contig_copies = *self.contigs.get(contig).unwrap_or(&self.ploidy);
+        let mut spans = Vec::new();
+        let mut position = 0;
+        for (start, end, copies) in self.regions.get(contig).into_iter().flatten() {
+            let (start, end) = ((*start).min(length), (*end).min(length));
+            if position < start {
+                spans.push((position, start, contig_copies));
+            }
+            if start < end {
+                spans.push((start, end, *copies));
+            }
+            position = position.max(end);
+        }
+        if position < length {
+            spans.push((position, length, contig_copies));
+        }
+        spans
+    }
+
+    pub fn copies(&self, contig: &str, position: usize) -> usize {
+        // The number of copies of this base the sample has.
+        if let Some(regions) = self.regions.get(contig) {
+            let index = regions.partition_point(|(start, _, _)| *start <= position);
+            if let Some((_, end, copies)) = index.checked_sub(1).map(|index| regions[index]) {
+                if position < end {
+                    return copies
+                }
+            }
+        }
+        *self.contigs.get(contig).unwrap_or(&self.ploidy)
+    }
+
+    pub fn is_mitochondrial(&self, contig: &str) -> bool {
+        // Whether the contig is the mitochondrial genome.
+        self.mitochondrial.as_deref() == Some(contig)
+    }
+
+    pub fn depth_fold(&self, contig: &str) -> f64 {
+        // How many times deeper the contig is read than its copies alone would make it.
+        *self.depth_folds.get(contig).unwrap_or(&1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uniform() {
+        let map = PloidyMap::uniform(3);
+        assert_eq!(map.copies("chr1", 100), 3);
+    }
+
+    #[test]
+    fn test_for_sample() {
+        let fasta_map = HashMap::from([
+            ("chr1".to_string(), vec![0; 100]),
+            ("chrX".to_string(), vec![0; 100]),
+            ("chrY some description".to_string(), vec![0; 80]),
+        ]);
+        let female = PloidyMap::for_sample(Sex::Female, 2, 100.0, &fasta_map, None).unwrap();
+        assert_eq!(female.copies("chrX", 50), 2);
+        assert_eq!(female.copies("chrY some description", 50), 0);
+        // No built in PARs for a reference this size
+        assert!(PloidyMap::for_sample(Sex::Male, 2, 100.0, &fasta_map, None).is_err());
+        let pars = Regions::from([
+            ("chrX".to_string(), vec![(0, 10), (90, 100)]),
+            ("chrY".to_string(), vec![(0, 10)]),
+        ]);
+        let male = PloidyMap::for_sample(Sex::Male, 2, 100.0, &fasta_map, Some(&pars)).unwrap();
+        assert_eq!(male.copies("chr1", 50), 2);
+        assert_eq!(male.copies("chrX", 5), 2);
+        assert_eq!(male.copies("chrX", 50), 1);
+        assert_eq!(male.copies("chrX", 95), 2);
+        assert_eq!(male.copies("chrY some description", 5), 0);
+        assert_eq!(male.copies("chrY some description", 50), 1);
+        assert_eq!(male.copy_spans("chrX", 100), vec![(0, 10, 2), (10, 90, 1), (90, 100, 2)]);
+        assert_eq!(male.copy_spans("chr1", 100), vec![(0, 100, 2)]);
+        assert_eq!(male.max_copies(), 2);
+        assert!(Sex::from_name("unknown").is_err());
+        assert_eq!(Sex::from_name("XY").unwrap(), Sex::Male);
+    }
+
+    #[test]
+    fn test_contig_ploidy() {
+        let fasta_map = HashMap::from([
+            ("chr1".to_string(), vec![0; 100]),
+            ("chr21 trisomic".to_string(), vec![0; 100]),
+            ("chrX".to_string(), vec![0; 100]),
+        ]);
+        let pars = Regions::from([("chrX".to_string(), vec![(0, 10)])]);
+        let overrides = vec![("chr21".to_string(), 3), ("chrX".to_string(), 2)];
+        let map = PloidyMap::for_sample(Sex::Male, 2, 100.0, &fasta_map, Some(&pars))
+            .unwrap()
+            .with_contig_ploidy(&overrides, &fasta_map)
+            .unwrap();
+        assert_eq!(map.copies("chr1", 50), 2);
+        assert_eq!(map.copies("chr21 trisomic", 50), 3);
+        // The override replaces the male X, PARs and all
+        assert_eq!(map.copy_spans("chrX", 100), vec![(0, 100, 2)]);
+        assert_eq!(map.max_copies(), 3);
+        let unknown = vec![("chr22".to_string(), 3)];
+        assert!(PloidyMap::uniform(2).with_contig_ploidy(&unknown, &fasta_map).is_err());
+    }
+
+    #[test]
+    fn test_copy_numbers() {
+        let fasta_map = HashMap::from([
+            ("chromosome".to_string(), vec![0; 1000]),
+            ("pBR322 plasmid".to_string(), vec![0; 100]),
+        ]);
+        let copy_numbers = vec![("pBR322".to_string(), 20.0)];
+        let map = PloidyMap::uniform(1).with_copy_numbers(&copy_numbers, &fasta_map).unwrap();
+        assert_eq!(map.depth_fold("pBR322 plasmid"), 20.0);
+        assert_eq!(map.depth_fold("chromosome"), 1.0);
+        assert_eq!(map.copies("pBR322 plasmid", 50), 1);
+        assert!(!map.is_mitochondrial("pBR322 plasmid"));
+        let unknown = vec![("pUC19".to_string(), 20.0)];
+        assert!(PloidyMap::uniform(1).with_copy_numbers(&unknown, &fasta_map).is_err());
+    }
+
+    #[test]
+    fn test_auto_sex() {
+        let mut fasta_map = HashMap::from([
+            ("chr1".to_string(), vec![0; 100]),
+            ("chrX".to_string(), vec![0; 100]),
+            ("chrY".to_string(), vec![4; 80]),
+            ("chrM".to_string(), vec![0; 50]),
+        ]);
+        // A masked Y makes a female, and the mitochondrial genome is one deep sequence
+        let female = PloidyMap::for_sample(Sex::Auto, 2, 100.0, &fasta_map, None).unwrap();
+        assert_eq!(female.copies("chrX", 50), 2);
+        assert_eq!(female.copies("chrY", 50), 0);
+        assert_eq!(female.copies("chrM", 10), 1);
+        assert_eq!(female.depth_fold("chrM"), 100.0);
+        assert_eq!(female.depth_fold("chr1"), 1.0);
+        assert!(female.is_mitochondrial("chrM") && !female.is_mitochondrial("chr1"));
+        // Any Y sequence makes a male, with a haploid X when the PARs aren't known
+        fasta_map.get_mut("chrY").unwrap()[40] = 2;
+        let male = PloidyMap::for_sample(Sex::Auto, 2, 100.0, &fasta_map, None).unwrap();
+        assert_eq!(male.copies("chrX", 5), 1);
+        assert_eq!(male.copies("chrY", 50), 1);
+        assert_eq!(male.copies("chr1", 50), 2);
+        // A reference without sex chromosomes is left as it is
+        let plain = HashMap::from([("chr1".to_string(), vec![0; 100])]);
+        let map = PloidyMap::for_sample(Sex::Auto, 2, 100.0, &plain, None).unwrap();
+        assert_eq!(map, PloidyMap::uniform(2));
+        assert_eq!(Sex::from_name("Auto").unwrap(), Sex::Auto);
+    }
+}
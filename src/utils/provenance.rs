@@ -0,0 +1,180 @@
+// What made a run's output: the seed, the version of rusty-neat and a hash of the settings. Every
+// output records these (the vcf header, the bam @PG line, the fasta headers and the fastq
+// manifest), so any file can be traced back to the command that generated it and rerun.
+
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use serde_json::json;
+use super::checksums::Fnv1a;
+use super::config::{canonical_seed, RunConfiguration};
+use super::fastq_tools::PairedRecordCounts;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    // seed: The seed string of the run, with its words separated by single spaces (which seeds
+    //     the rng exactly as the original did), or "none" if the run was handed an rng directly.
+    // version: The version of rusty-neat that made the output.
+    // config_hash: The hash of the run's settings (see config_hash).
+    pub seed: String,
+    pub version: &'static str,
+    pub config_hash: u64,
+}
+
+impl Provenance {
+    pub fn new(config: &RunConfiguration) -> Self {
+        let seed = match &config.rng_seed {
+            Some(seed) => canonical_seed(seed),
+            None => "none".to_string(),
+        };
+        Provenance {
+            seed,
+            version: env!("CARGO_PKG_VERSION"),
+            config_hash: config_hash(config),
+        }
+    }
+
+    pub fn config_hash_hex(&self) -> String {
+        format!("{:016x}", self.config_hash)
+    }
+
+    pub fn vcf_header_lines(&self) -> String {
+        format!(
+            "##neat_seed={}\n##neat_version={}\n##neat_config_hash={}\n",
+            self.seed, self.version, self.config_hash_hex()
+        )
+    }
+
+    pub fn fasta_comment(&self) -> String {
+        // The description following a contig name in the fasta header. The seed goes last, as it
+        // may have spaces in it.
+        format!(
+            "neat_version={} neat_config_hash={} neat_seed={}",
+            self.version, self.config_hash_hex(), self.seed
+        )
+    }
+
+    pub fn program_description(&self) -> String {
+        // The DS field of the bam @PG line.
+        format!("seed={}; config_hash={}", self.seed, self.config_hash_hex())
+    }
+
+    pub fn write_manifest(
+        &self,
+        writer: &mut impl Write,
+        reference: &str,
+        fastq_files: &[PathBuf],
+        fastq_records: &PairedRecordCounts,
+        paired_ended: bool,
+    ) -> io::Result<()> {
+        // Writes the fastq manifest: the provenance of the run, the fastq files it wrote (by
+        // file name, so the manifest still holds if the directory is moved) and the number of
+        // records in the read 1 and (for paired ended runs) read 2 files, as json.
+        let files: Vec<String> = fastq_files
+            .iter()
+            .map(|file| {
+                file.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| file.display().to_string())
+            })
+            .collect();
+        let records = if paired_ended {
+            json!({"r1": fastq_records.r1, "r2": fastq_records.r2})
+        } else {
+            json!({"r1": fastq_records.r1})
+        };
+        let manifest = json!({
+            "generator": "rusty-neat",
+            "version": self.version,
+            "seed": self.seed,
+            "config_hash": self.config_hash_hex(),
+            "reference": reference,
+            "fastq_files": files,
+            "fastq_records": records,
+        });
+        serde_json::to_writer_pretty(&mut *writer, &manifest)?;
+        writeln!(writer)
+    }
+}
+
+pub fn config_hash(config: &RunConfiguration) -> u64 {
+    // FNV-1a of the settings that shape the output. The seed is recorded on its own, and where a
+    // run reads and writes its files, how many threads it uses and whether it may overwrite don't
+    // change what is simulated, so those are left out. Replicates, and a run moved to another
+    // machine, then share a hash. So does a run of only one contig with the full run it
+    // regenerates part of.
+    let mut settings = config.clone();
+    settings.rng_seed = None;
+    settings.only_contig = None;
+    settings.contig_seed = None;
+    settings.reference = String::new();
+    settings.reference_cache = None;
+    settings.output_dir = PathBuf::new();
+    settings.temp_dir = None;
+    settings.threads = 0;
+    settings.overwrite_output = false;
+    settings.sweep = Vec::new();
+    let mut hasher = Fnv1a::new();
+    hasher.update(format!("{:?}", settings).as_bytes());
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::ConfigBuilder;
+
+    fn test_config() -> RunConfiguration {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.rng_seed = Some("Hello \t Provenance".to_string());
+        config.build()
+    }
+
+    #[test]
+    fn test_provenance() {
+        let config = test_config();
+        let provenance = Provenance::new(&config);
+        assert_eq!(provenance.seed, "Hello Provenance");
+        assert_eq!(provenance.version, env!("CARGO_PKG_VERSION"));
+        assert!(provenance.vcf_header_lines().starts_with("##neat_seed=Hello Provenance\n"));
+        assert!(provenance.fasta_comment().ends_with(" neat_seed=Hello Provenance"));
+        let mut no_seed = config.clone();
+        no_seed.rng_seed = None;
+        assert_eq!(Provenance::new(&no_seed).seed, "none");
+    }
+
+    #[test]
+    fn test_config_hash() {
+        let config = test_config();
+        // Where the run happens doesn't matter
+        let mut moved = config.clone();
+        moved.reference = "/elsewhere/H1N1.fa".to_string();
+        moved.output_dir = PathBuf::from("/elsewhere");
+        moved.threads = 8;
+        moved.rng_seed = Some("Another seed".to_string());
+        assert_eq!(config_hash(&config), config_hash(&moved));
+        // What is simulated does
+        let mut changed = config.clone();
+        changed.coverage += 1;
+        assert_ne!(config_hash(&config), config_hash(&changed));
+    }
+
+    #[test]
+    fn test_write_manifest() {
+        let provenance = Provenance::new(&test_config());
+        let mut buffer: Vec<u8> = Vec::new();
+        provenance.write_manifest(
+            &mut buffer,
+            "test_data/H1N1.fa",
+            &[PathBuf::from("/out/neat_out_r1.fastq"), PathBuf::from("/out/neat_out_r2.fastq")],
+            &PairedRecordCounts { r1: 120, r2: 120 },
+            true,
+        ).unwrap();
+        let manifest: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+        assert_eq!(manifest["seed"], "Hello Provenance");
+        assert_eq!(manifest["config_hash"], provenance.config_hash_hex());
+        assert_eq!(manifest["fastq_files"], json!(["neat_out_r1.fastq", "neat_out_r2.fastq"]));
+        assert_eq!(manifest["fastq_records"], json!({"r1": 120, "r2": 120}));
+    }
+}
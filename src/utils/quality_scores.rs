@@ -21,10 +21,21 @@
 //   * Assumes a fixed read length, meaning you have to extrapolate for longer read lengths.
 //   * In Python, at least, this was slow, although in retrospect it didn't eat up much memory.
 use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::info;
 use serde::{Deserialize, Serialize};
 use super::file_tools::open_file;
+use super::errors::NeatError;
 use simple_rng::{DiscreteDistribution, Rng};
 
+// The default model, pulled directly from NEAT2.0's original model. It is built into the binary so
+// a run doesn't depend on the directory it is started from.
+const DEFAULT_QUALITY_MODEL: &str = include_str!("../../models/neat_quality_score_model.json");
+
 #[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct QualityScoreModel {
@@ -124,7 +135,7 @@ impl QualityScoreModel {
             self.weights_from_one,
         )
     }
-    pub fn generate_quality_scores(&self, run_read_length: usize, mut rng: &mut Rng) -> Vec<u32> {
+    pub fn generate_quality_scores(&self, run_read_length: usize, rng: &mut Rng) -> Vec<u32> {
         // Generates a list of quality scores of length run_read_length using the model. If the
         // input read length differs, we do some index magic to extrapolate the model
         // run_read_length: The desired read length for the model to generate.
@@ -136,38 +147,23 @@ impl QualityScoreModel {
         let dist = DiscreteDistribution::new(&self.seed_weights, false);
         // sample the scores list with the seed weights applied to generate the first score.
         // Samples an index based on the weights, which then selects the quality score.
-        let seed_score = self.quality_score_options[dist.sample(&mut rng)];
+        let mut previous = dist.sample(rng);
         // Adding the seed score to the list.
-        score_list.push(seed_score);
+        score_list.push(self.quality_score_options[previous]);
         // To map from one length to another, we use the algorithm found in the original NEAT 2.0,
         // adapted to rust. See function for implementation details.
         let indexes: Vec<usize> = self.quality_index_remap(run_read_length);
-        // Sort of annoying, but to account for the remap, in order to get "previous score" from
-        // the score list, we need to know the current index we are filling, absolutely, in cases
-        // of mismatches between model read length and run read length
         // We can skip the first one, since we already generated it above. On loop 1, we will look
         // at that seed score to get our first set of weights.
-        let mut current_index = 1;
         for i in indexes {
-            // The weight at this index is the score weights for position i, given a
-            // previous score of score_list[i-1]
-
-            // First get the index of the previous score from the original scores list.
-            // This will match the index in the score weights table that corresponds to that score.
-            let score_position = self.quality_score_options.iter().position(
-                |&x| x == score_list[current_index-1]
-            ).unwrap();
-            // Now we have an index (in the default case 0..<4) of a vector for the position, based
-            // on the previous score.
-            let weights: &Vec<u32> = self.weights_from_one.get(i)
-                .expect("Error with quality score remap index.")
-                .get(score_position)
-                .expect("Error finding weights vector");
+            // The weight at this index is the score weights for position i, given the index of
+            // the previous score in the scores list. Every model is checked when it is loaded
+            // (check_shape) to have a row for each position and previous score.
+            let weights: &Vec<u32> = &self.weights_from_one[i][previous];
             // Now we build the dist and sample as above.
             let dist = DiscreteDistribution::new(weights, false);
-            let score = self.quality_score_options[dist.sample(&mut rng)];
-            score_list.push(score);
-            current_index += 1;
+            previous = dist.sample(rng);
+            score_list.push(self.quality_score_options[previous]);
         }
         score_list
     }
@@ -207,12 +203,283 @@ impl QualityScoreModel {
             indexes
         }
     }
-    pub fn write_out_quality_model(&self, filename: &mut str) -> serde_json::Result<()> {
+    pub fn write_out_quality_model(&self, filename: &mut str) -> Result<(), NeatError> {
         // Uses the serde_json crate to write out the json form of the model. This will help us
         // create base datasets from old neat data, and give us a way to write out models that are
         // generated from user data.
-        let fileout = open_file(filename, false).unwrap();
-        serde_json::to_writer(fileout, self)
+        let fileout = open_file(filename, false)?;
+        serde_json::to_writer(fileout, self).map_err(|error| NeatError::Io(error.into()))
+    }
+
+    pub fn from_json(filename: &str) -> Result<Self, NeatError> {
+        // Reads a model written by write_out_quality_model.
+        let file = fs::File::open(filename).map_err(|error| {
+            NeatError::ModelLoad(format!("Problem reading {}: {}", filename, error))
+        })?;
+        serde_json::from_reader(file).map_err(|error| {
+            NeatError::ModelLoad(format!("Problem with json file format in {}: {}", filename, error))
+        })
+    }
+
+    pub fn default_model() -> Self {
+        Self::from_json_str(DEFAULT_QUALITY_MODEL).expect("The built in quality model is invalid")
+    }
+
+    pub fn from_json_str(model_json: &str) -> Result<Self, NeatError> {
+        // Same as from_json, for a model that is already in memory (e.g., embedded at build time).
+        serde_json::from_str(model_json).map_err(|error| {
+            NeatError::ModelLoad(format!("Problem with json model format: {}", error))
+        })
+    }
+
+    pub fn from_neat_json(filename: &str) -> Result<Self, NeatError> {
+        // Reads the quality stats from an original NEAT 2.0 model, converted to json, and turns
+        // them into a model in our own format.
+        let file = fs::File::open(filename).map_err(|error| {
+            NeatError::ModelLoad(format!("Problem reading {}: {}", filename, error))
+        })?;
+        let quality_raw: QualityRaw = serde_json::from_reader(file).map_err(|error| {
+            NeatError::ModelLoad(format!("Problem with json file format in {}: {}", filename, error))
+        })?;
+        Ok(quality_raw.convert_to_weights())
+    }
+
+    pub fn check_shape(&self, source: &str) -> Result<(), NeatError> {
+        // Checks the model has a seed weight for each score, and a row of weights for each
+        // previous score at every position generate_quality_scores can look up, so a malformed
+        // model is an error before a run starts rather than a panic partway through the reads.
+        // source: Where the model came from, for the error.
+        let num_scores = self.quality_score_options.len();
+        let bad = |problem: String| NeatError::ModelLoad(format!("{}: {}", source, problem));
+        if num_scores == 0 || self.seed_weights.len() != num_scores {
+            return Err(bad(format!(
+                "{} seed weights for {} quality scores",
+                self.seed_weights.len(),
+                num_scores,
+            )))
+        }
+        // The remap looks up positions 1 to one less than the assumed read length, and position
+        // 1 even for a model of one base.
+        let positions = self.assumed_read_length.max(2);
+        if self.weights_from_one.len() < positions {
+            return Err(bad(format!(
+                "weights for {} positions, but a read length of {}",
+                self.weights_from_one.len(),
+                self.assumed_read_length,
+            )))
+        }
+        for (position, rows) in self.weights_from_one.iter().enumerate().take(positions).skip(1) {
+            if rows.len() != num_scores || rows.iter().any(|row| row.len() != num_scores) {
+                return Err(bad(format!(
+                    "position {} needs {} rows of {} weights",
+                    position,
+                    num_scores,
+                    num_scores,
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn weight_rows(&mut self) -> impl Iterator<Item = &mut Vec<u32>> {
+        // The seed weights, then every row of weights_from_one.
+        std::iter::once(&mut self.seed_weights).chain(self.weights_from_one.iter_mut().flatten())
+    }
+
+    pub fn prune(&mut self, min_fraction: f64) -> usize {
+        // Drops (zeroes) the weights under min_fraction of their row's total. A trained model has
+        // a weight for every score after every score at every position, most of them next to
+        // nothing, and those are never sampled in practice. The largest weight of a row is always
+        // kept, so every row can still be sampled. Returns the number of weights dropped.
+        let mut dropped = 0;
+        for row in self.weight_rows() {
+            let total: f64 = row.iter().map(|weight| *weight as f64).sum();
+            let largest = row.iter().copied().max().unwrap_or(0);
+            for weight in row.iter_mut() {
+                if *weight > 0 && *weight < largest && (*weight as f64) < min_fraction * total {
+                    *weight = 0;
+                    dropped += 1;
+                }
+            }
+        }
+        dropped
+    }
+
+    pub fn quantize(&mut self, bits: u32) {
+        // Rescales each row so its largest weight is the largest number that fits in bits bits,
+        // rounding the rest. Weights only matter relative to the rest of their row, and a few
+        // bits keep the probabilities to within a fraction of a percent of the largest. Weights
+        // that round to zero are kept at one, so quantizing never drops a score; that's prune's
+        // job.
+        let levels = ((1u64 << bits.clamp(1, 32)) - 1) as f64;
+        for row in self.weight_rows() {
+            let largest = row.iter().copied().max().unwrap_or(0);
+            if largest == 0 {
+                continue
+            }
+            for weight in row.iter_mut().filter(|weight| **weight > 0) {
+                *weight = ((*weight as f64 * levels / largest as f64).round() as u32).max(1);
+            }
+        }
+    }
+
+    pub fn write_out_compressed_model(&self, filename: &str) -> Result<(), NeatError> {
+        // Writes the model as gzipped json, with only the non-zero weights of each row, so a
+        // pruned model takes a fraction of the room. Read it back with from_compressed_json.
+        let file = BufWriter::new(open_file(&mut filename.to_string(), false)?);
+        let mut encoder = GzEncoder::new(file, Compression::best());
+        serde_json::to_writer(&mut encoder, &SparseModel::from(self))
+            .map_err(|error| NeatError::Io(error.into()))?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    pub fn from_compressed_json(filename: &str) -> Result<Self, NeatError> {
+        // Reads a model written by write_out_compressed_model. The model is parsed as it is
+        // decompressed, so the json is never held in memory whole.
+        let file = fs::File::open(filename).map_err(|error| {
+            NeatError::ModelLoad(format!("Problem reading {}: {}", filename, error))
+        })?;
+        let reader = BufReader::new(MultiGzDecoder::new(file));
+        let sparse: SparseModel = serde_json::from_reader(reader).map_err(|error| {
+            NeatError::ModelLoad(format!("Problem with compressed model {}: {}", filename, error))
+        })?;
+        sparse.into_model().ok_or_else(|| {
+            NeatError::ModelLoad(format!("{} has weights past the end of a row", filename))
+        })
+    }
+}
+
+pub fn compress_quality_model(
+    input: &str,
+    output: &str,
+    min_fraction: f64,
+    bits: u32,
+) -> Result<(), NeatError> {
+    // Takes:
+    // input: A model written by write_out_quality_model.
+    // output: Where to write the compressed model.
+    // min_fraction, bits: As in prune and quantize.
+    // Prunes and quantizes the model, then writes it compressed.
+    let mut model = QualityScoreModel::from_json(input)?;
+    let num_weights: usize = model.weight_rows().map(|row| row.len()).sum();
+    let dropped = model.prune(min_fraction);
+    model.quantize(bits);
+    info!("Dropped {} of {} weights, quantized to {} bits", dropped, num_weights, bits);
+    model.write_out_compressed_model(output)?;
+    let size = |path: &str| fs::metadata(path).map(|metadata| metadata.len() >> 10);
+    info!("Compressed {} ({} KB) to {} ({} KB)", input, size(input)?, output, size(output)?);
+    Ok(())
+}
+
+// A weight row with only its non-zero weights, as (index, weight) pairs, for compressed models.
+#[derive(Serialize, Deserialize)]
+struct SparseRow {
+    length: usize,
+    weights: Vec<(usize, u32)>,
+}
+
+impl SparseRow {
+    fn from_row(row: &[u32]) -> Self {
+        SparseRow {
+            length: row.len(),
+            weights: row
+                .iter()
+                .enumerate()
+                .filter(|(_, weight)| **weight > 0)
+                .map(|(index, weight)| (index, *weight))
+                .collect(),
+        }
+    }
+
+    fn into_row(self) -> Option<Vec<u32>> {
+        let mut row = vec![0; self.length];
+        for (index, weight) in self.weights {
+            *row.get_mut(index)? = weight;
+        }
+        Some(row)
+    }
+}
+
+// The compressed form of a QualityScoreModel.
+#[derive(Serialize, Deserialize)]
+struct SparseModel {
+    quality_score_options: Vec<u32>,
+    binned_scores: bool,
+    assumed_read_length: usize,
+    seed_weights: SparseRow,
+    weights_from_one: Vec<Vec<SparseRow>>,
+}
+
+impl From<&QualityScoreModel> for SparseModel {
+    fn from(model: &QualityScoreModel) -> Self {
+        SparseModel {
+            quality_score_options: model.quality_score_options.clone(),
+            binned_scores: model.binned_scores,
+            assumed_read_length: model.assumed_read_length,
+            seed_weights: SparseRow::from_row(&model.seed_weights),
+            weights_from_one: model.weights_from_one
+                .iter()
+                .map(|position| position.iter().map(|row| SparseRow::from_row(row)).collect())
+                .collect(),
+        }
+    }
+}
+
+impl SparseModel {
+    fn into_model(self) -> Option<QualityScoreModel> {
+        Some(QualityScoreModel {
+            quality_score_options: self.quality_score_options,
+            binned_scores: self.binned_scores,
+            assumed_read_length: self.assumed_read_length,
+            seed_weights: self.seed_weights.into_row()?,
+            weights_from_one: self.weights_from_one
+                .into_iter()
+                .map(|position| position.into_iter().map(SparseRow::into_row).collect())
+                .collect::<Option<_>>()?,
+        })
+    }
+}
+
+// The quality score stats as stored by the original (Python) NEAT 2.0. The probabilities are
+// converted to integer weights so they can be fed into a DiscreteDistribution.
+#[derive(Serialize, Deserialize)]
+struct QualityRaw {
+    seed_stats: Vec<f64>,
+    stats_from_one: Vec<Vec<Vec<f64>>>
+}
+
+impl QualityRaw {
+    fn convert_to_weights(&self) -> QualityScoreModel {
+        let mut seed_weights: Vec<u32> = Vec::new();
+        for item in &self.seed_stats {
+            let weight = ((item * 10e5).round() as u64 % (u32::MAX as u64)) as u32;
+            seed_weights.push(weight);
+        }
+        let mut weights_from_one = Vec::new();
+        for i in 0..self.stats_from_one.len() {
+            let mut position_arrays = Vec::new();
+            for j in 0..self.stats_from_one[i].len() {
+                let mut score_arrays = Vec::new();
+                for k in 0..self.stats_from_one[i][j].len() {
+                    let weight = (self.stats_from_one[i][j][k] * 10e5).round() as u32;
+                    score_arrays.push(weight);
+                }
+                position_arrays.push(score_arrays.clone())
+            }
+            weights_from_one.push(position_arrays.clone())
+        }
+        let assumed_read_length = seed_weights.len();
+        let quality_score_options: Vec<u32> = (0..42).collect();
+        let binned_scores = false;
+
+        QualityScoreModel {
+            quality_score_options,
+            binned_scores,
+            assumed_read_length,
+            seed_weights,
+            weights_from_one,
+        }
     }
 }
 
@@ -359,6 +626,81 @@ mod tests {
         scores.iter().map(|x| assert!(model.quality_score_options.contains(x))).collect()
     }
 
+    #[test]
+    fn test_read_neat_model_round_trip() {
+        let qual_data = QualityScoreModel::from_neat_json("test_data/test_model.json").unwrap();
+        assert_eq!(qual_data.seed_weights.len(), 42);
+        assert_eq!(qual_data.weights_from_one.len(), 10);
+        assert_eq!(qual_data.weights_from_one[0].len(), 10);
+        assert_eq!(qual_data.weights_from_one[0][0].len(), 10);
+        let mut out_file = "test_data/test.json".to_string();
+        qual_data.write_out_quality_model(&mut out_file).unwrap();
+        let reread = QualityScoreModel::from_json(&out_file);
+        fs::remove_file(out_file).expect("Failed in removing test json file");
+        let reread = reread.unwrap();
+        assert_eq!(reread.seed_weights, qual_data.seed_weights);
+        assert_eq!(reread.weights_from_one, qual_data.weights_from_one);
+        assert_eq!(reread.quality_score_options, qual_data.quality_score_options);
+    }
+
+    #[test]
+    fn test_compressed_model() {
+        let mut model = QualityScoreModel::default_model();
+        let original = model.weights_from_one.clone();
+        let dropped = model.prune(0.001);
+        assert!(dropped > 0);
+        // Every row keeps its largest weight
+        let rows = model.weights_from_one.iter().flatten().zip(original.iter().flatten());
+        for (pruned, original) in rows {
+            assert_eq!(pruned.iter().max(), original.iter().max());
+        }
+        model.quantize(8);
+        assert!(model.weights_from_one.iter().flatten().flatten().all(|weight| *weight <= 255));
+        assert!(model.seed_weights.contains(&255));
+
+        let out_file = "test_data/test_compressed_model.json.gz";
+        model.write_out_compressed_model(out_file).unwrap();
+        let compressed_size = fs::metadata(out_file).unwrap().len();
+        let reread = QualityScoreModel::from_compressed_json(out_file);
+        fs::remove_file(out_file).unwrap();
+        let reread = reread.unwrap();
+        assert_eq!(reread.seed_weights, model.seed_weights);
+        assert_eq!(reread.weights_from_one, model.weights_from_one);
+        assert_eq!(reread.quality_score_options, model.quality_score_options);
+        assert!(compressed_size < DEFAULT_QUALITY_MODEL.len() as u64 / 4);
+        // The pruned model still gives scores from the same options
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Compressed".to_string()]);
+        let scores = reread.generate_quality_scores(151, &mut rng);
+        assert!(scores.iter().all(|score| reread.quality_score_options.contains(score)));
+        assert!(QualityScoreModel::from_compressed_json("test_data/test_model.json").is_err());
+    }
+
+    #[test]
+    fn test_check_shape() {
+        assert!(QualityScoreModel::new().check_shape("default").is_ok());
+        // A seed weight short, a position short, and a row short
+        let mut model = QualityScoreModel::new();
+        model.seed_weights.pop();
+        assert!(matches!(model.check_shape("short seed"), Err(NeatError::ModelLoad(_))));
+        let mut model = QualityScoreModel::new();
+        model.weights_from_one.truncate(100);
+        assert!(matches!(model.check_shape("short read"), Err(NeatError::ModelLoad(_))));
+        let mut model = QualityScoreModel::new();
+        model.weights_from_one[20][3].pop();
+        assert!(matches!(model.check_shape("short row"), Err(NeatError::ModelLoad(_))));
+    }
+
+    #[test]
+    fn test_read_default_model() {
+        let model = QualityScoreModel::from_json("models/neat_quality_score_model.json").unwrap();
+        assert!(!model.quality_score_options.is_empty());
+        assert!(QualityScoreModel::from_json("test_data/H1N1.fa").is_err());
+        assert_eq!(
+            QualityScoreModel::default_model().quality_score_options,
+            model.quality_score_options
+        );
+    }
+
     #[test]
     fn test_quality_scores_vast_difference() {
         let run_read_length = 2000;
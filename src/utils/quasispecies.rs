@@ -0,0 +1,160 @@
+// Viral quasispecies: a host carries a cloud of related viral haplotypes at different
+// frequencies rather than one genome. Runs that simulate one get a haplotype per configured
+// frequency, each simulated as a copy of the genome, related by a random genealogy: haplotype 0
+// is the reference, and every later haplotype descends from one drawn from those before it. Each
+// SNP arises on the branch leading to one haplotype (other than the reference) and is carried by
+// it and everything descended from it, so SNPs are linked the way they are in a real population
+// and haplotypes can be reconstructed from them. Enough SNPs are placed for two haplotypes to
+// differ at about the configured fraction of their bases on average, and each fragment is drawn
+// from a haplotype picked by its frequency. The truth vcf gives each SNP the summed frequency of
+// the haplotypes carrying it as its AF.
+
+use std::collections::HashMap;
+use simple_rng::Rng;
+use super::variants::{GenotypeCounts, Variant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quasispecies {
+    // frequencies: The share of the population each haplotype makes up, summing to 1.
+    // parents: The haplotype each one descends from (None for the reference, haplotype 0).
+    pub frequencies: Vec<f64>,
+    parents: Vec<Option<usize>>,
+}
+
+impl Quasispecies {
+    pub fn new(frequencies: &[f64], rng: &mut Rng) -> Self {
+        // Draws a genealogy for haplotypes at these (relative) frequencies.
+        let total: f64 = frequencies.iter().sum();
+        let parents = (0..frequencies.len())
+            .map(|haplotype| match haplotype {
+                0 => None,
+                _ => Some(rng.range_i64(0, haplotype as i64) as usize),
+            })
+            .collect();
+        Quasispecies {
+            frequencies: frequencies.iter().map(|frequency| frequency / total).collect(),
+            parents,
+        }
+    }
+
+    fn carriers(&self, branch: usize) -> Vec<usize> {
+        // The genotype of a SNP on the branch leading to this haplotype: 1 for it and for every
+        // haplotype descended from it.
+        (0..self.parents.len())
+            .map(|haplotype| {
+                let mut ancestor = Some(haplotype);
+                while let Some(current) = ancestor {
+                    if current == branch {
+                        return 1
+                    }
+                    ancestor = self.parents[current];
+                }
+                0
+            })
+            .collect()
+    }
+
+    fn mean_branch_distance(&self) -> f64 {
+        // The mean number of branches between two haplotypes: how many branches each one splits
+        // the pairs across, summed and spread over the pairs.
+        let count = self.parents.len();
+        let separated: usize = (1..count)
+            .map(|branch| {
+                let below = self.carriers(branch).iter().sum::<usize>();
+                below * (count - below)
+            })
+            .sum();
+        separated as f64 / (count * (count - 1) / 2) as f64
+    }
+
+    pub fn num_snps(&self, diversity: f64, callable: usize) -> usize {
+        // How many SNPs give two haplotypes a mean pairwise diversity of about this over a
+        // genome with this many callable bases, with the SNPs spread evenly over the branches.
+        let per_branch = diversity * callable as f64 / self.mean_branch_distance();
+        (per_branch * (self.parents.len() - 1) as f64).round() as usize
+    }
+
+    pub fn assign_haplotypes(
+        &self,
+        variant_locations: &mut HashMap<String, Vec<Variant>>,
+        fasta_order: &[String],
+        rng: &mut Rng,
+    ) -> GenotypeCounts {
+        // Puts each variant on a branch drawn at random, giving it the genotype and frequency of
+        // the haplotypes below that branch, in reference order so a seed always gives the same
+        // haplotypes. Returns how many variants are on some, and how many on all, haplotypes,
+        // leaving out those only on haplotypes with no share (which aren't in the sample).
+        let mut genotype_counts = GenotypeCounts::default();
+        let branches = self.parents.len() as i64;
+        for contig in fasta_order {
+            let Some(variants) = variant_locations.get_mut(contig) else { continue };
+            for variant in variants.iter_mut() {
+                variant.genotype = self.carriers(rng.range_i64(1, branches) as usize);
+                let frequency = variant.genotype
+                    .iter()
+                    .zip(&self.frequencies)
+                    .map(|(carried, frequency)| *carried as f64 * frequency)
+                    .sum();
+                variant.frequency = Some(frequency);
+                if frequency <= 0.0 {
+                    continue
+                }
+                if variant.genotype.contains(&0) {
+                    genotype_counts.heterozygous += 1;
+                } else {
+                    genotype_counts.homozygous += 1;
+                }
+            }
+        }
+        genotype_counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::variants::VariantOrigin;
+
+    #[test]
+    fn test_quasispecies() {
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Quasispecies".to_string()]);
+        let quasispecies = Quasispecies::new(&[6.0, 2.0, 1.0, 1.0], &mut rng);
+        assert_eq!(quasispecies.frequencies, vec![0.6, 0.2, 0.1, 0.1]);
+        // The reference carries nothing, and every haplotype carries its own branch
+        assert_eq!(quasispecies.carriers(0), vec![1, 1, 1, 1]);
+        for branch in 1..4 {
+            let carriers = quasispecies.carriers(branch);
+            assert_eq!(carriers[0], 0);
+            assert_eq!(carriers[branch], 1);
+        }
+        // In a star each haplotype is a branch from the reference and two from the others, a
+        // mean of 3/2, and in a chain the mean is 5/3
+        let star = Quasispecies {
+            frequencies: vec![0.25; 4], parents: vec![None, Some(0), Some(0), Some(0)]
+        };
+        assert_eq!(star.mean_branch_distance(), 1.5);
+        let chain = Quasispecies {
+            frequencies: vec![0.25; 4], parents: vec![None, Some(0), Some(1), Some(2)]
+        };
+        assert!((chain.mean_branch_distance() - 10.0 / 6.0).abs() < 1e-9);
+        // 1% of 10,000 bases between each pair, 100 / 1.5 on each of the three branches
+        assert_eq!(star.num_snps(0.01, 10_000), 200);
+
+        let mut variant_locations = HashMap::from([(
+            "virus".to_string(),
+            (0..50)
+                .map(|position| Variant::new(position, 0, 1, VariantOrigin::Germline))
+                .collect(),
+        )]);
+        let counts = chain.assign_haplotypes(
+            &mut variant_locations, &["virus".to_string()], &mut rng
+        );
+        assert_eq!(counts.heterozygous, 50);
+        for variant in &variant_locations["virus"] {
+            // Down the chain, a variant is on a haplotype and everything after it
+            let first = variant.genotype.iter().position(|carried| *carried == 1).unwrap();
+            assert!(first > 0 && variant.genotype[first..].iter().all(|carried| *carried == 1));
+            assert_eq!(variant.frequency, Some(0.25 * (4 - first) as f64));
+        }
+    }
+}
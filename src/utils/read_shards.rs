@@ -0,0 +1,1165 @@
+// Reads are generated one contig at a time, each contig with its own rng seeded from the run's
+// rng. The reads for a contig then don't depend on any other contig, so contigs can be handed out
+// to worker threads in any order and the output stays the same. Each worker writes the reads for
+// its contigs to shard files, and the shards are stitched together in reference order at the end.
+// Runs of small contigs (as in assemblies, which can have thousands) share a shard, so there are
+// never many more shards than megabases of reference.
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use log::{debug, info, warn};
+use simple_rng::Rng;
+use super::bed_tools::Regions;
+use super::bam_tools::{write_bam_header, write_bam_records, write_sorted_bam_records, ReadGroup};
+use super::bgzf::{write_bgzf_eof, BgzfWriter};
+use super::capture::CaptureModel;
+use super::config::RunConfiguration;
+use super::coverage_profile::CoverageProfile;
+use super::error_profile::CycleErrorProfile;
+use super::ffpe::FfpeArtifacts;
+use super::errors::NeatError;
+use super::heteroplasmy::HeteroplasmicSites;
+use super::kmer_spectrum::KmerCounts;
+use super::pileup::{site_counts, write_contig_pileup};
+use super::fastq_tools::{
+    fastq_record_name, pair_mismatch, write_fastq, write_fastq_record, PairedRecordCounts,
+};
+use super::make_reads::{FragmentLengths, ReadGenerator, SimulatedRead};
+use super::memory_budget::worker_count;
+use super::ploidy::PloidyMap;
+use super::provenance::Provenance;
+use super::quality_scores::QualityScoreModel;
+use super::summary::{shard_stats, ShardStats};
+use super::throttle::{Throttle, ThrottledFile};
+use super::variants::Variant;
+
+// Templates per bucket of the shuffle across contigs. A bucket is held in memory while it is
+// shuffled, so this bounds what the shuffle needs (around 100 MB for 2x150 bp pairs).
+pub const SHUFFLE_BUCKET_TEMPLATES: usize = 150_000;
+// Buckets are all open while templates are dealt out, so there are never more than this many.
+// Very large runs get larger buckets instead.
+const MAX_SHUFFLE_BUCKETS: usize = 256;
+// Consecutive contigs are put in the same shard until it holds at least this many bases.
+const SHARD_BASES: usize = 1_000_000;
+
+#[derive(Debug)]
+pub struct ReadShard {
+    // The shard files for a run of consecutive contigs, holding the reads of each contig in turn.
+    //
+    // contigs: The indexes of the contigs in the shard, in the contig order.
+    // r1: Fastq holding read 1 of every template (or the only read, for single ended runs).
+    // r2: Fastq holding read 2 of every template, for paired ended runs.
+    // singletons: Fastq holding the reads of pairs that lost a mate, for runs that lose them.
+    // bam: BGZF blocks of bam records for the contigs (no header or end of file marker), for runs
+    //     that produce a bam.
+    // num_templates: The number of reads (or read pairs) in the shard.
+    // fastq_records: The number of records in the r1 and r2 files.
+    // contig_records: The number of r1 records of each contig.
+    // stats: Coverage and fragment lengths of each contig's reads, for the run summary.
+    // index: Fastqs holding the I1 (and I2) read of each r1 record, in the same order, for runs
+    //     with a sample index.
+    // index_hopped: The templates whose index reads hopped to another library's index.
+    // kmers: The k-mer counts of the shard's reads, for runs that write a k-mer spectrum.
+    // pileup: The depth bedgraph and allele count table of the shard's contigs, for runs that
+    //     write a truth pileup.
+    // site_counts: For runs that call genotypes from the reads, the reads showing each base at
+    //     each variant of each of the shard's contigs (see pileup::site_counts).
+    pub contigs: Range<usize>,
+    pub r1: PathBuf,
+    pub r2: Option<PathBuf>,
+    pub singletons: Option<PathBuf>,
+    pub bam: Option<PathBuf>,
+    pub num_templates: usize,
+    pub fastq_records: PairedRecordCounts,
+    pub contig_records: Vec<usize>,
+    pub stats: Vec<ShardStats>,
+    pub index: Vec<PathBuf>,
+    pub index_hopped: usize,
+    pub kmers: Option<KmerCounts>,
+    pub pileup: Option<(PathBuf, PathBuf)>,
+    pub site_counts: Option<Vec<Vec<[u32; 5]>>>,
+}
+
+pub struct ContigSequences<'a> {
+    // The sequences reads are generated from.
+    //
+    // reference: The original reference sequences, keyed by contig name. Aligned output is
+    //     compared against these.
+    // haplotypes: The mutated sequences the reads are drawn from, one set per copy of the
+    //     genome (see haplotype_sequences), keyed by contig name.
+    // order: Contig names in reference order.
+    // ploidy_map: How many of the haplotypes the sample has of each part of the genome.
+    // heteroplasmy: The mitochondrial variants on only some of its copies, for runs that model
+    //     heteroplasmy.
+    // germline: For runs with a tumor fraction, the germline-only copies the rest of the reads
+    //     are drawn from.
+    // targets: For panel runs, the regions to cover.
+    // coverage_profile: For runs with a coverage profile, the depth wanted along each contig.
+    // capture_model: For panel runs with a capture model, how well each fragment is captured.
+    // low_complexity: For runs that raise the error rate in them, the low-complexity regions.
+    // error_profile: For runs with an error profile, the substitution rates by cycle.
+    // ffpe: For runs with FFPE artifacts, the sites damaged.
+    // fragment_lengths: For paired runs with a coverage bam, the fragment lengths it has.
+    // variants: For runs that call genotypes from the reads, the variants of each contig, whose
+    //     sites the reads are counted at.
+    pub reference: &'a HashMap<String, Vec<u8>>,
+    pub haplotypes: &'a [HashMap<String, Vec<u8>>],
+    pub order: &'a [String],
+    pub ploidy_map: &'a PloidyMap,
+    pub heteroplasmy: Option<&'a HeteroplasmicSites>,
+    pub germline: Option<&'a [HashMap<String, Vec<u8>>]>,
+    pub targets: Option<&'a Regions>,
+    pub coverage_profile: Option<&'a CoverageProfile>,
+    pub capture_model: Option<&'a CaptureModel>,
+    pub low_complexity: Option<&'a Regions>,
+    pub error_profile: Option<&'a CycleErrorProfile>,
+    pub ffpe: Option<&'a FfpeArtifacts>,
+    pub fragment_lengths: Option<&'a FragmentLengths>,
+    pub variants: Option<&'a HashMap<String, Vec<Variant>>>,
+}
+
+pub fn contig_seeds(contig_order: &[String], rng: &mut Rng) -> Vec<Vec<String>> {
+    // Draws a seed for each contig, in reference order, from the run's rng. The contig name is
+    // part of the seed, so two contigs never share a stream.
+    contig_order
+        .iter()
+        .map(|contig| vec![
+            rng.rand_u32().to_string(),
+            rng.rand_u32().to_string(),
+            contig.clone(),
+        ])
+        .collect()
+}
+
+pub fn select_contig(
+    config: &RunConfiguration,
+    contig_order: &[String],
+    seeds: &mut [Vec<String>],
+) -> Result<Option<usize>, NeatError> {
+    // Logs the seed of each contig's reads, so one contig can be regenerated on its own, and
+    // for runs of only one contig, finds it and gives it the contig seed, if one was set.
+    // Returns the index of that contig in the contig order.
+    for (contig, seed) in contig_order.iter().zip(seeds.iter()) {
+        info!("Seed of the {} reads: {}", contig, seed[..seed.len() - 1].join(" "));
+    }
+    let Some(only_contig) = &config.only_contig else { return Ok(None) };
+    let index = contig_order
+        .iter()
+        .position(|contig| contig == only_contig)
+        .ok_or_else(|| NeatError::Config(
+            format!("only_contig {} isn't a contig of the reference", only_contig)
+        ))?;
+    if let Some(contig_seed) = &config.contig_seed {
+        let mut seed: Vec<String> = contig_seed.split_whitespace().map(str::to_string).collect();
+        seed.push(only_contig.clone());
+        if seed != seeds[index] {
+            warn!(
+                "The contig seed {} isn't the one this run's seed gives {}, so its reads won't \
+                match the full run's",
+                contig_seed, only_contig
+            );
+        }
+        seeds[index] = seed;
+    }
+    Ok(Some(index))
+}
+
+pub fn generate_contig_reads(
+    config: &RunConfiguration,
+    sequences: &ContigSequences,
+    contig_index: usize,
+    rng: &mut Rng,
+    quality_score_model: &QualityScoreModel,
+) -> Result<Vec<SimulatedRead>, NeatError> {
+    // Takes:
+    // config: the run configuration (read length, coverage, fragment model).
+    // sequences: the sequences to read from, with the contig order and any targets.
+    // contig_index: which contig in the contig order to generate reads for.
+    // rng: the rng for this contig.
+    // quality_score_model: the model used to generate quality scores.
+    // Returns:
+    // The reads for that contig, mates back to back, in generation order. Read names carry the
+    // contig index so they stay unique across contigs. Paired ended runs without a fragment mean
+    // and standard deviation are an error.
+    let mut generator = ReadGenerator::new(
+        &sequences.haplotypes[0],
+        &sequences.order[contig_index..contig_index + 1],
+        config.read_len,
+        config.coverage,
+        quality_score_model,
+        rng,
+    );
+    generator = generator
+        .haplotypes(sequences.haplotypes)
+        .ploidy_map(sequences.ploidy_map)
+        .name_prefix(&format!("{}_{}_", config.read_name_prefix, contig_index))
+        .name_format(config.read_name_format, contig_index)
+        .flowcell(config.flowcell_geometry())
+        .illumina_run(config.illumina_run());
+    if let Some(rate) = config.duplicate_rate {
+        generator = generator.duplicates(rate, config.optical_duplicate_fraction);
+    }
+    if !config.quasispecies_frequencies.is_empty() {
+        generator = generator.haplotype_weights(&config.quasispecies_frequencies);
+    }
+    if let Some(sites) = sequences.heteroplasmy {
+        generator = generator.heteroplasmy(sites);
+    }
+    if let Some(targets) = sequences.targets {
+        generator = generator.targets(targets);
+    }
+    if let Some(coverage_profile) = sequences.coverage_profile {
+        generator = generator.coverage_profile(coverage_profile);
+    }
+    if let Some(capture_model) = sequences.capture_model {
+        generator = generator.capture_model(capture_model);
+    }
+    if let (Some(regions), Some(error_fold)) =
+        (sequences.low_complexity, config.low_complexity_error_fold)
+    {
+        generator = generator.low_complexity(regions, error_fold);
+    }
+    if let Some(crash_rate) = config.quality_crash_rate {
+        generator = generator.quality_crash(crash_rate);
+    }
+    if let Some(qc_fail_rate) = config.qc_fail_rate {
+        generator = generator.qc_fail(qc_fail_rate);
+    }
+    if let Some(error_rate) = config.molecule_error_rate {
+        generator = generator.molecule_errors(error_rate);
+    }
+    if let Some(error_profile) = sequences.error_profile {
+        generator = generator.error_profile(error_profile);
+    }
+    if let (Some(germline), Some(tumor_fraction)) = (sequences.germline, config.tumor_fraction) {
+        generator = generator.tumor_fraction(germline, tumor_fraction);
+    }
+    if config.paired_ended {
+        let (Some(fragment_mean), Some(fragment_st_dev)) =
+            (config.fragment_mean, config.fragment_st_dev)
+        else {
+            return Err(NeatError::Config(
+                "Paired ended reads need a fragment mean and standard deviation".to_string()
+            ))
+        };
+        let (fragment_min, fragment_max) = config.fragment_bounds();
+        generator = generator
+            .paired_ended(fragment_mean, fragment_st_dev)
+            .fragment_bounds(fragment_min, fragment_max)
+            .adapter_read_through(config.adapter_read_through);
+        if config.cell_free_fragments {
+            generator = generator.cell_free();
+        }
+        if let Some(family_size) = config.duplex_family_size {
+            generator = generator.duplex(family_size);
+        }
+        if let Some(artifacts) = sequences.ffpe {
+            generator = generator.ffpe_artifacts(artifacts);
+        }
+        if let Some(fragment_lengths) = sequences.fragment_lengths {
+            generator = generator.fragment_lengths(fragment_lengths);
+        }
+    }
+    Ok(generator.collect())
+}
+
+fn lose_mates<'a>(
+    templates: Vec<&'a [SimulatedRead]>,
+    singleton_rate: f64,
+    discarded_pair_rate: f64,
+    rng: &mut Rng,
+) -> Vec<&'a [SimulatedRead]> {
+    // Simulates pairs lost in library prep: each pair is dropped with probability
+    // discarded_pair_rate, or loses one of its mates (either one, equally likely) with probability
+    // singleton_rate. Returns the templates that are left, in the same order.
+    let mut kept = Vec::with_capacity(templates.len());
+    for template in templates {
+        let draw = rng.random();
+        if draw < discarded_pair_rate {
+            continue
+        } else if draw < discarded_pair_rate + singleton_rate {
+            let survivor = if rng.gen_bool(0.5) { 0 } else { 1 };
+            kept.push(&template[survivor..survivor + 1]);
+        } else {
+            kept.push(template);
+        }
+    }
+    kept
+}
+
+fn shard_contigs(
+    order: &[String],
+    reference: &HashMap<String, Vec<u8>>,
+    shard_bases: usize,
+) -> Vec<Range<usize>> {
+    // Splits the contig order into runs of consecutive contigs, each closed once it holds
+    // shard_bases bases, so a contig at least that long is a shard of its own.
+    let mut shards = Vec::new();
+    let (mut start, mut bases) = (0, 0);
+    for (index, contig) in order.iter().enumerate() {
+        bases += reference[contig].len();
+        if bases >= shard_bases {
+            shards.push(start..index + 1);
+            (start, bases) = (index + 1, 0);
+        }
+    }
+    if start < order.len() {
+        shards.push(start..order.len());
+    }
+    shards
+}
+
+struct ShardWriters {
+    // The open files of a shard, which each of its contigs appends to in turn.
+    r1: BufWriter<ThrottledFile>,
+    r2: Option<BufWriter<ThrottledFile>>,
+    singletons: Option<BufWriter<ThrottledFile>>,
+    bam: Option<BufWriter<ThrottledFile>>,
+    index: Vec<BufWriter<ThrottledFile>>,
+    pileup: Option<(BufWriter<ThrottledFile>, BufWriter<ThrottledFile>)>,
+    // Not files, but counted as the reads are written all the same.
+    kmers: Option<KmerCounts>,
+    site_counts: Option<Vec<Vec<[u32; 5]>>>,
+}
+
+fn write_contig(
+    config: &RunConfiguration,
+    sequences: &ContigSequences,
+    contig_index: usize,
+    seed: &[String],
+    quality_score_model: &QualityScoreModel,
+    writers: &mut ShardWriters,
+) -> Result<(usize, PairedRecordCounts, ShardStats, usize), NeatError> {
+    // Generates the reads for one contig, shuffles them (keeping mates together), or sorts them
+    // by position for runs that sort their reads, and appends them to the shard's files. Returns
+    // the number of templates, the fastq records written, the contig's stats and the number of
+    // templates whose index hopped.
+    let mut rng = Rng::new_from_seed(seed.to_vec());
+    let reads = generate_contig_reads(
+        config, sequences, contig_index, &mut rng, quality_score_model
+    )?;
+    let contig = &sequences.order[contig_index];
+    let reads_per_template = if config.paired_ended { 2 } else { 1 };
+    let mut template_order: Vec<usize> = (0..reads.len() / reads_per_template).collect();
+    if config.sort_reads {
+        // By the leftmost read of each template
+        template_order.sort_by_key(|template| {
+            reads[template * reads_per_template..(template + 1) * reads_per_template]
+                .iter()
+                .map(|read| read.start)
+                .min()
+        });
+    } else if !template_order.is_empty() {
+        rng.shuffle_in_place(&mut template_order);
+    }
+    let mut templates: Vec<&[SimulatedRead]> = template_order
+        .iter()
+        .map(|template| {
+            let start = template * reads_per_template;
+            &reads[start..start + reads_per_template]
+        })
+        .collect();
+    // Mates and whole pairs lost before the reads are written.
+    if config.singleton_rate.is_some() || config.discarded_pair_rate.is_some() {
+        templates = lose_mates(
+            templates,
+            config.singleton_rate.unwrap_or(0.0),
+            config.discarded_pair_rate.unwrap_or(0.0),
+            &mut rng,
+        );
+    }
+    let num_templates = templates.len();
+    let reference = &sequences.reference[contig];
+    let callable_length = reference.iter().filter(|base| **base < 4).count();
+    let stats = shard_stats(
+        contig, reference.len(), callable_length, &templates, config.paired_ended
+    );
+
+    // Mates of complete pairs go to r1 and r2, which have to stay in step, so reads that lost
+    // their mate get a file of their own.
+    let (pairs, singletons): (Vec<&[SimulatedRead]>, Vec<&[SimulatedRead]>) = templates
+        .iter()
+        .partition(|template| template.len() == reads_per_template);
+    let outsets: Vec<&SimulatedRead> = pairs.iter().copied().flatten().collect();
+    let first_read = outsets.first().map(|read| read.name.clone());
+    let header = config.fastq_format();
+    let fastq_records = match writers.r2.as_mut() {
+        Some(r2_writer) => {
+            let fastq_records = write_fastq(&mut writers.r1, r2_writer, outsets, &header)?;
+            // write_fastq checks the mates it was given, but can't tell a paired run that lost
+            // every read 2 from a single ended one.
+            if fastq_records.r1 != fastq_records.r2 {
+                return Err(pair_mismatch(&fastq_records, first_read.as_deref(), None))
+            }
+            fastq_records
+        },
+        None => write_fastq(&mut writers.r1, &mut io::sink(), outsets, &header)?,
+    };
+    // Every read written, whichever file it goes to, but not the index reads.
+    if let Some(kmers) = writers.kmers.as_mut() {
+        for read in templates.iter().copied().flatten() {
+            kmers.add_sequence(&read.seq);
+        }
+    }
+    if let Some((depth_writer, allele_writer)) = writers.pileup.as_mut() {
+        let written: Vec<&SimulatedRead> = templates.iter().copied().flatten().collect();
+        write_contig_pileup(depth_writer, allele_writer, contig, reference, &written)?;
+    }
+    if let (Some(variants), Some(counts)) = (sequences.variants, writers.site_counts.as_mut()) {
+        let written: Vec<&SimulatedRead> = templates.iter().copied().flatten().collect();
+        let positions: Vec<usize> = variants
+            .get(contig)
+            .into_iter()
+            .flatten()
+            .map(|variant| variant.position)
+            .collect();
+        counts.push(site_counts(&written, &positions));
+    }
+    if let Some(singleton_writer) = writers.singletons.as_mut() {
+        let mut record = Vec::new();
+        for read in singletons.iter().copied().flatten() {
+            write_fastq_record(singleton_writer, read, &header, &mut record)?;
+        }
+    }
+    // Index reads go with the templates in r1, drawn after everything else so the other reads
+    // are the same with or without them.
+    let mut index_hopped = 0;
+    if let Some(index_model) = config.index_model() {
+        let mut record = Vec::new();
+        for template in &pairs {
+            let (index_reads, hopped) =
+                index_model.index_reads(&template[0], quality_score_model, &mut rng);
+            for (read, writer) in index_reads.iter().zip(writers.index.iter_mut()) {
+                write_fastq_record(writer, read, &header, &mut record)?;
+            }
+            index_hopped += hopped as usize;
+        }
+    }
+    if let Some(bam_writer) = writers.bam.as_mut() {
+        // Each contig gets blocks of its own, so the bam is the same however contigs are sharded.
+        let mut bam_writer = BgzfWriter::new(bam_writer);
+        let write_records = if config.sort_reads {
+            write_sorted_bam_records
+        } else {
+            write_bam_records
+        };
+        write_records(
+            &mut bam_writer,
+            &templates,
+            contig_index as i32,
+            reference,
+            &config.read_group().id,
+        )?;
+        bam_writer.finish()?;
+    }
+    debug!("Wrote {} templates for {}", num_templates, contig);
+    Ok((num_templates, fastq_records, stats, index_hopped))
+}
+
+fn write_shard(
+    config: &RunConfiguration,
+    sequences: &ContigSequences,
+    contigs: Range<usize>,
+    seeds: &[Vec<String>],
+    quality_score_model: &QualityScoreModel,
+    shard_dir: &Path,
+    throttle: Option<&Throttle>,
+) -> Result<ReadShard, NeatError> {
+    // Writes the reads of a run of contigs to one set of shard files, one contig after another.
+    // Fastq shards are always written; the run only copies them into place if it asked for fastq
+    // files.
+    let shard_file = |suffix: &str| shard_dir.join(format!("shard_{}{}", contigs.start, suffix));
+    let r1 = shard_file("_r1.fastq");
+    let r2 = config.paired_ended.then(|| shard_file("_r2.fastq"));
+    let singletons = config.singleton_rate.is_some().then(|| shard_file("_singletons.fastq"));
+    let bam = config.produce_bam.then(|| shard_file(".bam"));
+    let index: Vec<PathBuf> = match &config.sample_index {
+        Some(sample_index) if sample_index.is_dual() => {
+            vec![shard_file("_I1.fastq"), shard_file("_I2.fastq")]
+        },
+        Some(_) => vec![shard_file("_I1.fastq")],
+        None => Vec::new(),
+    };
+    let pileup = config.truth_pileup
+        .then(|| (shard_file("_depth.bedgraph"), shard_file("_alleles.tsv")));
+    let create_file = |path: &Path| -> io::Result<BufWriter<ThrottledFile>> {
+        Ok(BufWriter::new(ThrottledFile::create(path, throttle)?))
+    };
+    let create = |path: &Option<PathBuf>| -> io::Result<Option<BufWriter<ThrottledFile>>> {
+        path.as_deref().map(create_file).transpose()
+    };
+    let mut writers = ShardWriters {
+        r1: create_file(&r1)?,
+        r2: create(&r2)?,
+        singletons: create(&singletons)?,
+        bam: create(&bam)?,
+        index: index
+            .iter()
+            .map(|path| create_file(path))
+            .collect::<io::Result<_>>()?,
+        pileup: pileup
+            .as_ref()
+            .map(|(depth, alleles)| -> io::Result<_> {
+                Ok((create_file(depth)?, create_file(alleles)?))
+            })
+            .transpose()?,
+        kmers: config.kmer_spectrum.map(KmerCounts::new),
+        site_counts: sequences.variants.map(|_| Vec::with_capacity(contigs.len())),
+    };
+    let mut num_templates = 0;
+    let mut fastq_records = PairedRecordCounts::default();
+    let mut contig_records = Vec::with_capacity(contigs.len());
+    let mut stats = Vec::with_capacity(contigs.len());
+    let mut index_hopped = 0;
+    for contig_index in contigs.clone() {
+        let (contig_templates, contig_fastq_records, contig_stats, hopped) = write_contig(
+            config,
+            sequences,
+            contig_index,
+            &seeds[contig_index],
+            quality_score_model,
+            &mut writers,
+        )?;
+        num_templates += contig_templates;
+        fastq_records.add(contig_fastq_records);
+        contig_records.push(contig_fastq_records.r1);
+        stats.push(contig_stats);
+        index_hopped += hopped;
+    }
+    writers.r1.flush()?;
+    for writer in [writers.r2, writers.singletons, writers.bam].iter_mut().flatten() {
+        writer.flush()?;
+    }
+    writers.index.iter_mut().try_for_each(|writer| writer.flush())?;
+    if let Some((depth_writer, allele_writer)) = writers.pileup.as_mut() {
+        depth_writer.flush()?;
+        allele_writer.flush()?;
+    }
+    Ok(ReadShard {
+        contigs,
+        r1,
+        r2,
+        singletons,
+        bam,
+        num_templates,
+        fastq_records,
+        contig_records,
+        stats,
+        index,
+        index_hopped,
+        kmers: writers.kmers,
+        pileup,
+        site_counts: writers.site_counts,
+    })
+}
+
+pub fn write_read_shards(
+    config: &RunConfiguration,
+    sequences: &ContigSequences,
+    seeds: &[Vec<String>],
+    quality_score_model: &QualityScoreModel,
+    shard_dir: &Path,
+    throttle: Option<&Throttle>,
+) -> Result<Vec<ReadShard>, NeatError> {
+    // Spreads the shards over config.threads workers (fewer if the memory budget calls for it,
+    // see memory_budget.rs). Each worker takes the next shard that
+    // nobody has started, writes it, and keeps its results to itself; the results are put
+    // back in reference order once every worker is done. Returns the shards in reference order.
+    // The workers share the throttle, if any, so the run as a whole keeps to its rate.
+    let next_shard = AtomicUsize::new(0);
+    // A run of only one contig has a shard of just that contig, which keeps its place in the
+    // order, so its read names are the same as in a full run.
+    let shard_ranges = match &config.only_contig {
+        Some(only_contig) => sequences.order
+            .iter()
+            .position(|contig| contig == only_contig)
+            .map(|index| index..index + 1)
+            .into_iter()
+            .collect(),
+        None => shard_contigs(sequences.order, sequences.reference, SHARD_BASES),
+    };
+    let num_workers = worker_count(config, sequences).clamp(1, shard_ranges.len().max(1));
+    let mut finished: Vec<(usize, ReadShard)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_workers)
+            .map(|_| scope.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let shard_index = next_shard.fetch_add(1, Ordering::Relaxed);
+                    let Some(contigs) = shard_ranges.get(shard_index) else {
+                        return Ok(done)
+                    };
+                    let shard = write_shard(
+                        config,
+                        sequences,
+                        contigs.clone(),
+                        seeds,
+                        quality_score_model,
+                        shard_dir,
+                        throttle,
+                    )?;
+                    done.push((shard_index, shard));
+                }
+            }))
+            .collect();
+        let mut finished = Vec::new();
+        for worker in workers {
+            let done: Result<Vec<(usize, ReadShard)>, NeatError> = worker
+                .join()
+                .expect("Read generation worker panicked");
+            finished.extend(done?);
+        }
+        Ok::<_, NeatError>(finished)
+    })?;
+    finished.sort_by_key(|(shard_index, _)| *shard_index);
+    Ok(finished.into_iter().map(|(_, shard)| shard).collect())
+}
+
+pub fn merge_bam_shards(
+    shards: &[ReadShard],
+    contig_order: &[String],
+    contig_lengths: &[usize],
+    read_group: &ReadGroup,
+    provenance: &Provenance,
+    coordinate_sorted: bool,
+    bam_writer: &mut impl Write,
+) -> io::Result<()> {
+    // Writes a complete bam: the header, the records of each shard in the order given, and the
+    // end of file marker. Shards of sorted reads, given in reference order, make a coordinate
+    // sorted bam.
+    let mut header_writer = BgzfWriter::new(&mut *bam_writer);
+    write_bam_header(
+        &mut header_writer, contig_order, contig_lengths, read_group, provenance, coordinate_sorted
+    )?;
+    header_writer.finish()?;
+    for shard in shards {
+        if let Some(bam) = &shard.bam {
+            io::copy(&mut File::open(bam)?, bam_writer)?;
+        }
+    }
+    write_bgzf_eof(bam_writer)
+}
+
+pub fn merge_shards(
+    shards: &[ReadShard],
+    r1_writer: &mut impl Write,
+    r2_writer: &mut impl Write,
+) -> Result<PairedRecordCounts, NeatError> {
+    // Concatenates the shards, in the order given, into the final fastq files. Mates are read
+    // back and written a pair at a time, checking that they still match. Returns the number of
+    // records written to each file.
+    let mut counts = PairedRecordCounts::default();
+    let mut r1_record = Vec::new();
+    let mut r2_record = Vec::new();
+    for shard in shards {
+        let mut r1_reader = BufReader::new(File::open(&shard.r1)?);
+        let mut r2_reader = match &shard.r2 {
+            Some(r2) => Some(BufReader::new(File::open(r2)?)),
+            None => None,
+        };
+        loop {
+            r1_record.clear();
+            let more_r1 = read_fastq_record(&mut r1_reader, &mut r1_record)?;
+            if let Some(r2_reader) = r2_reader.as_mut() {
+                r2_record.clear();
+                let more_r2 = read_fastq_record(r2_reader, &mut r2_record)?;
+                check_mates(
+                    &counts,
+                    more_r1.then_some(r1_record.as_slice()),
+                    more_r2.then_some(r2_record.as_slice()),
+                )?;
+                if more_r2 {
+                    r2_writer.write_all(&r2_record)?;
+                    counts.r2 += 1;
+                }
+            }
+            if !more_r1 {
+                break
+            }
+            r1_writer.write_all(&r1_record)?;
+            counts.r1 += 1;
+        }
+    }
+    Ok(counts)
+}
+
+fn check_mates(
+    counts: &PairedRecordCounts,
+    r1_record: Option<&[u8]>,
+    r2_record: Option<&[u8]>,
+) -> Result<(), NeatError> {
+    // Checks that the next read 1 and read 2 records (None if that file has run out) are mates,
+    // or that both files have run out.
+    let r1_name = r1_record.map(fastq_record_name);
+    let r2_name = r2_record.map(fastq_record_name);
+    if r1_name == r2_name {
+        return Ok(())
+    }
+    let r1_name = r1_name.map(String::from_utf8_lossy);
+    let r2_name = r2_name.map(String::from_utf8_lossy);
+    Err(pair_mismatch(counts, r1_name.as_deref(), r2_name.as_deref()))
+}
+
+fn read_fastq_record(reader: &mut impl BufRead, record: &mut Vec<u8>) -> io::Result<bool> {
+    // Appends the next (four line) fastq record to record. Returns false at the end of the file.
+    for line in 0..4 {
+        if reader.read_until(b'\n', record)? == 0 {
+            return match line {
+                0 => Ok(false),
+                _ => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "fastq record cut short")),
+            }
+        }
+    }
+    Ok(true)
+}
+
+fn fastq_record_ranges(fastq: &[u8]) -> Vec<(usize, usize)> {
+    // The [start, end) of each record of a fastq held in memory.
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let line_ends = fastq.iter().enumerate().filter(|(_, byte)| **byte == b'\n');
+    for (line, (index, _)) in line_ends.enumerate() {
+        if line % 4 == 3 {
+            ranges.push((start, index + 1));
+            start = index + 1;
+        }
+    }
+    ranges
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn shuffle_merge_shards(
+    shards: &[ReadShard],
+    scratch_dir: &Path,
+    throttle: Option<&Throttle>,
+    templates_per_bucket: usize,
+    rng: &mut Rng,
+    r1_writer: &mut impl Write,
+    r2_writer: &mut impl Write,
+    index_writers: &mut [&mut dyn Write],
+) -> Result<PairedRecordCounts, NeatError> {
+    // Takes:
+    // shards: The shards to merge.
+    // scratch_dir: Where to put the bucket files. They are removed once they have been written out.
+    // throttle: The rate to write the bucket files at, for throttled runs.
+    // templates_per_bucket: About how many templates to hold in memory at once.
+    // rng: The rng that decides the order.
+    // r1_writer, r2_writer: Where to write the fastq files.
+    // index_writers: Where to write the I1 (and I2) fastqs, for shards with index reads. They are
+    //     shuffled along with r1.
+    // Returns:
+    // The number of records written to each file, or an error if there is a problem or the
+    // mates fall out of step.
+    //
+    // Like merge_shards, but shuffles the templates across contigs as well as within them,
+    // without holding every read in memory. Each template is dealt to a random bucket file, then
+    // each bucket is read back, shuffled and written out. Both mates of a template go to the same
+    // bucket at the same position, so r1 and r2 stay in step, which is checked as they are dealt
+    // out and again as they are written.
+    let num_templates: usize = shards.iter().map(|shard| shard.num_templates).sum();
+    let num_buckets = num_templates
+        .div_ceil(templates_per_bucket.max(1))
+        .clamp(1, MAX_SHUFFLE_BUCKETS);
+    // Each template has a record in each of files files: r1, r2 for paired runs, then the
+    // index reads.
+    let bucket_path = |bucket: usize, file: usize| {
+        scratch_dir.join(format!("shuffle_{}_r{}.fastq", bucket, file))
+    };
+    let mates = if shards.iter().any(|shard| shard.r2.is_some()) { 2 } else { 1 };
+    let files = mates + index_writers.len();
+    let mut buckets: Vec<Vec<BufWriter<ThrottledFile>>> = (0..num_buckets)
+        .map(|bucket| {
+            (1..=files)
+                .map(|file| {
+                    Ok(BufWriter::new(ThrottledFile::create(&bucket_path(bucket, file), throttle)?))
+                })
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut records = vec![Vec::new(); files];
+    let mut dealt = PairedRecordCounts::default();
+    for shard in shards {
+        let mut readers = vec![BufReader::new(File::open(&shard.r1)?)];
+        if let Some(r2) = &shard.r2 {
+            readers.push(BufReader::new(File::open(r2)?));
+        }
+        for index in shard.index.iter().take(index_writers.len()) {
+            readers.push(BufReader::new(File::open(index)?));
+        }
+        if readers.len() != files {
+            return Err(NeatError::Simulation(format!(
+                "read shard {} is missing its index reads", shard.r1.display()
+            )))
+        }
+        let mut next_template = |records: &mut Vec<Vec<u8>>, dealt: &PairedRecordCounts| {
+            let mut more = Vec::with_capacity(files);
+            for (reader, record) in readers.iter_mut().zip(records.iter_mut()) {
+                record.clear();
+                more.push(read_fastq_record(reader, record)?);
+            }
+            // Read 2 and the index reads all have to go with read 1
+            for file in 1..files {
+                check_mates(
+                    dealt,
+                    more[0].then_some(records[0].as_slice()),
+                    more[file].then_some(records[file].as_slice()),
+                )?;
+            }
+            Ok::<_, NeatError>(more[0])
+        };
+        // One draw per template and one more at the end of each contig, so the order is the
+        // same however the contigs were grouped into shards.
+        for &contig_records in &shard.contig_records {
+            for template in 0..=contig_records {
+                let bucket = ((rng.random() * num_buckets as f64) as usize).min(num_buckets - 1);
+                if template == contig_records {
+                    break
+                }
+                if !next_template(&mut records, &dealt)? {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof, "read shard cut short"
+                    ).into())
+                }
+                for (record, writer) in records.iter().zip(buckets[bucket].iter_mut()) {
+                    writer.write_all(record)?;
+                }
+                dealt.r1 += 1;
+                dealt.r2 += mates - 1;
+            }
+        }
+        if next_template(&mut records, &dealt)? {
+            return Err(NeatError::Simulation(format!(
+                "read shard {} has more records than were written to it", shard.r1.display()
+            )))
+        }
+    }
+    for writers in buckets.iter_mut() {
+        writers.iter_mut().try_for_each(|writer| writer.flush())?;
+    }
+    drop(buckets);
+    let mut counts = PairedRecordCounts::default();
+    for bucket in 0..num_buckets {
+        let fastqs: Vec<Vec<u8>> = (1..=files)
+            .map(|file| fs::read(bucket_path(bucket, file)))
+            .collect::<io::Result<_>>()?;
+        let ranges: Vec<Vec<(usize, usize)>> = fastqs
+            .iter()
+            .map(|fastq| fastq_record_ranges(fastq))
+            .collect();
+        let mut order: Vec<usize> = (0..ranges[0].len()).collect();
+        if !order.is_empty() {
+            rng.shuffle_in_place(&mut order);
+        }
+        for file in 1..files {
+            if ranges[0].len() != ranges[file].len() {
+                let r1_name =
+                    ranges[0].get(ranges[file].len()).map(|&(start, _)| &fastqs[0][start..]);
+                let other_name =
+                    ranges[file].get(ranges[0].len()).map(|&(start, _)| &fastqs[file][start..]);
+                check_mates(&counts, r1_name, other_name)?;
+            }
+        }
+        for template in order {
+            let (start, end) = ranges[0][template];
+            let r1_record = &fastqs[0][start..end];
+            if mates == 2 {
+                let (start, end) = ranges[1][template];
+                let r2_record = &fastqs[1][start..end];
+                check_mates(&counts, Some(r1_record), Some(r2_record))?;
+                r2_writer.write_all(r2_record)?;
+                counts.r2 += 1;
+            }
+            r1_writer.write_all(r1_record)?;
+            counts.r1 += 1;
+            for (file, writer) in (mates..files).zip(index_writers.iter_mut()) {
+                let (start, end) = ranges[file][template];
+                writer.write_all(&fastqs[file][start..end])?;
+            }
+        }
+        for file in 1..=files {
+            fs::remove_file(bucket_path(bucket, file))?;
+        }
+    }
+    Ok(counts)
+}
+
+pub fn merge_index_shards(
+    shards: &[ReadShard],
+    index_writers: &mut [&mut dyn Write],
+) -> io::Result<()> {
+    // Concatenates the index read shards, in the order given, into the final I1 (and I2) fastqs,
+    // which then line up with the r1 fastq merge_shards writes.
+    for shard in shards {
+        for (index, writer) in shard.index.iter().zip(index_writers.iter_mut()) {
+            io::copy(&mut File::open(index)?, writer)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn merge_singleton_shards(shards: &[ReadShard], writer: &mut impl Write) -> io::Result<()> {
+    // Concatenates the singleton shards, in the order given, into the final singletons fastq.
+    for singletons in shards.iter().filter_map(|shard| shard.singletons.as_ref()) {
+        io::copy(&mut File::open(singletons)?, writer)?;
+    }
+    Ok(())
+}
+
+pub fn merge_pileup_shards(
+    shards: &[ReadShard],
+    depth_writer: &mut impl Write,
+    allele_writer: &mut impl Write,
+) -> io::Result<()> {
+    // Concatenates the pileup shards, in the order given, into the final depth bedgraph and
+    // allele count table (whose header the caller writes).
+    for (depth, alleles) in shards.iter().filter_map(|shard| shard.pileup.as_ref()) {
+        io::copy(&mut File::open(depth)?, depth_writer)?;
+        io::copy(&mut File::open(alleles)?, allele_writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use super::super::config::ConfigBuilder;
+    use super::super::fasta_tools::read_fasta;
+
+    fn test_shards(threads: usize, shard_dir: &str) -> Vec<ReadShard> {
+        // threads of 0 writes a shard per contig, one after another, instead of the shards
+        // write_read_shards would make.
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.read_len = 100;
+        config.coverage = 1;
+        config.paired_ended = true;
+        config.fragment_mean = Some(250.0);
+        config.fragment_st_dev = Some(10.0);
+        config.threads = threads;
+        let config = config.build();
+        let (sequences, contig_order) = read_fasta(&config.reference).unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Shards".to_string()]);
+        let seeds = contig_seeds(&contig_order, &mut rng);
+        fs::create_dir(shard_dir).unwrap();
+        let contig_sequences = ContigSequences {
+            reference: &sequences,
+            haplotypes: std::slice::from_ref(&sequences),
+            order: &contig_order,
+            ploidy_map: &PloidyMap::uniform(1),
+            heteroplasmy: None,
+            germline: None,
+            targets: None,
+            coverage_profile: None,
+            capture_model: None,
+            low_complexity: None,
+            error_profile: None,
+            ffpe: None,
+            fragment_lengths: None,
+            variants: None,
+        };
+        let shards = if threads == 0 {
+            (0..contig_order.len())
+                .map(|contig_index| write_shard(
+                    &config,
+                    &contig_sequences,
+                    contig_index..contig_index + 1,
+                    &seeds,
+                    &QualityScoreModel::new(),
+                    Path::new(shard_dir),
+                    None,
+                ).unwrap())
+                .collect()
+        } else {
+            write_read_shards(
+                &config,
+                &contig_sequences,
+                &seeds,
+                &QualityScoreModel::new(),
+                Path::new(shard_dir),
+                None,
+            ).unwrap()
+        };
+        let contigs: Vec<&str> = shards
+            .iter()
+            .flat_map(|shard| shard.stats.iter().map(|stats| stats.coverage.contig.as_str()))
+            .collect();
+        assert_eq!(contigs, contig_order);
+        shards
+    }
+
+    fn sharded_fastq(threads: usize, shard_dir: &str) -> (Vec<u8>, Vec<u8>) {
+        let shards = test_shards(threads, shard_dir);
+        let (mut r1, mut r2) = (Vec::new(), Vec::new());
+        merge_shards(&shards, &mut r1, &mut r2).unwrap();
+        fs::remove_dir_all(shard_dir).unwrap();
+        (r1, r2)
+    }
+
+    fn shuffled_fastq(threads: usize, shard_dir: &str) -> (Vec<u8>, Vec<u8>) {
+        let shards = test_shards(threads, shard_dir);
+        let mut rng = Rng::new_from_seed(vec!["Shuffle".to_string()]);
+        let (mut r1, mut r2) = (Vec::new(), Vec::new());
+        shuffle_merge_shards(
+            &shards, Path::new(shard_dir), None, 10, &mut rng, &mut r1, &mut r2, &mut []
+        ).unwrap();
+        fs::remove_dir_all(shard_dir).unwrap();
+        (r1, r2)
+    }
+
+    #[test]
+    fn test_shards_independent_of_threads() {
+        let single = sharded_fastq(1, "shard_test_single");
+        let multi = sharded_fastq(4, "shard_test_multi");
+        assert!(!single.0.is_empty());
+        assert!(!single.1.is_empty());
+        assert_eq!(single, multi);
+    }
+
+    #[test]
+    fn test_shards_independent_of_grouping() {
+        // H1N1's contigs all fit in one shard, which has to give the same reads, in the same
+        // order, as a shard per contig.
+        let shards = test_shards(1, "shard_test_grouped");
+        fs::remove_dir_all("shard_test_grouped").unwrap();
+        assert_eq!(shards.len(), 1);
+        assert_eq!(shards[0].contig_records.len(), 8);
+        let grouped = sharded_fastq(1, "shard_test_grouped");
+        let per_contig = sharded_fastq(0, "shard_test_per_contig");
+        assert_eq!(grouped, per_contig);
+        let grouped = shuffled_fastq(1, "shard_test_grouped_shuffle");
+        let per_contig = shuffled_fastq(0, "shard_test_per_contig_shuffle");
+        assert_eq!(grouped, per_contig);
+    }
+
+    #[test]
+    fn test_contig_reads_without_fragment_model() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.paired_ended = true;
+        let mut config = config.build();
+        config.fragment_mean = None;
+        let (sequences, contig_order) = read_fasta(&config.reference).unwrap();
+        let contig_sequences = ContigSequences {
+            reference: &sequences,
+            haplotypes: std::slice::from_ref(&sequences),
+            order: &contig_order,
+            ploidy_map: &PloidyMap::uniform(1),
+            heteroplasmy: None,
+            germline: None,
+            targets: None,
+            coverage_profile: None,
+            capture_model: None,
+            low_complexity: None,
+            error_profile: None,
+            ffpe: None,
+            fragment_lengths: None,
+            variants: None,
+        };
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Unpaired".to_string()]);
+        let reads = generate_contig_reads(
+            &config, &contig_sequences, 0, &mut rng, &QualityScoreModel::new()
+        );
+        assert!(matches!(reads, Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_shard_contigs() {
+        let lengths = [("tig1", 400), ("tig2", 700), ("chr1", 2500), ("tig3", 10), ("tig4", 20)];
+        let order: Vec<String> = lengths.iter().map(|(name, _)| name.to_string()).collect();
+        let reference: HashMap<String, Vec<u8>> = lengths
+            .iter()
+            .map(|(name, length)| (name.to_string(), vec![0; *length]))
+            .collect();
+        assert_eq!(shard_contigs(&order, &reference, 1000), vec![0..2, 2..3, 3..5]);
+        assert_eq!(shard_contigs(&order, &reference, 1), vec![0..1, 1..2, 2..3, 3..4, 4..5]);
+        assert_eq!(shard_contigs(&order, &reference, 10_000), vec![0..5]);
+    }
+
+    #[test]
+    fn test_shuffle_merge_shards() {
+        let shard_dir = "shard_test_shuffle";
+        let shards = test_shards(2, shard_dir);
+        let (mut r1, mut r2) = (Vec::new(), Vec::new());
+        let counts = merge_shards(&shards, &mut r1, &mut r2).unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Shuffle".to_string()]);
+        let (mut shuffled_r1, mut shuffled_r2) = (Vec::new(), Vec::new());
+        let shuffled_counts = shuffle_merge_shards(
+            &shards,
+            Path::new(shard_dir),
+            None,
+            10,
+            &mut rng,
+            &mut shuffled_r1,
+            &mut shuffled_r2,
+            &mut [],
+        ).unwrap();
+        let num_templates: usize = shards.iter().map(|shard| shard.num_templates).sum();
+        assert_eq!(counts, PairedRecordCounts { r1: num_templates, r2: num_templates });
+        assert_eq!(shuffled_counts, counts);
+        // Only the shards are left behind
+        let num_files = fs::read_dir(shard_dir).unwrap().count();
+        fs::remove_dir_all(shard_dir).unwrap();
+        assert_eq!(num_files, 2 * shards.len());
+
+        let records = |fastq: &[u8]| -> Vec<Vec<u8>> {
+            fastq_record_ranges(fastq).iter().map(|&(start, end)| fastq[start..end].to_vec()).collect()
+        };
+        let (r1, shuffled_r1, shuffled_r2) = (records(&r1), records(&shuffled_r1), records(&shuffled_r2));
+        // The same reads, mates still in step, in an order that mixes the contigs
+        let mut sorted = shuffled_r1.clone();
+        sorted.sort();
+        let mut expected = r1.clone();
+        expected.sort();
+        assert_eq!(sorted, expected);
+        assert_eq!(shuffled_r2.len(), shuffled_r1.len());
+        for (read1, read2) in shuffled_r1.iter().zip(&shuffled_r2) {
+            let name = |record: &[u8]| record.split(|byte| *byte == b'/').next().unwrap().to_vec();
+            assert_eq!(name(read1), name(read2));
+        }
+        let first_contig = |record: &[u8]| String::from_utf8_lossy(&record[..40]).contains("_0_");
+        let half = shuffled_r1.len() / 2;
+        assert!(shuffled_r1[..half].iter().any(|record| !first_contig(record)));
+        assert_ne!(shuffled_r1, r1);
+    }
+
+    #[test]
+    fn test_merge_shards_pair_check() {
+        let shard_dir = "shard_test_pair_check";
+        let shards = test_shards(1, shard_dir);
+        assert!(shards.iter().all(|shard| shard.fastq_records.r1 == shard.fastq_records.r2));
+        // Lose the last read 2 of the first shard
+        let r2_path = shards[0].r2.as_ref().unwrap();
+        let r2 = fs::read(r2_path).unwrap();
+        let ranges = fastq_record_ranges(&r2);
+        fs::write(r2_path, &r2[..ranges[ranges.len() - 1].0]).unwrap();
+        let last_r1 = {
+            let r1 = fs::read(&shards[0].r1).unwrap();
+            let (start, _) = fastq_record_ranges(&r1)[ranges.len() - 1];
+            String::from_utf8_lossy(fastq_record_name(&r1[start..])).to_string()
+        };
+        let merged = merge_shards(&shards, &mut Vec::new(), &mut Vec::new());
+        let mut rng = Rng::new_from_seed(vec!["Shuffle".to_string()]);
+        let shuffled = shuffle_merge_shards(
+            &shards,
+            Path::new(shard_dir),
+            None,
+            10,
+            &mut rng,
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut [],
+        );
+        fs::remove_dir_all(shard_dir).unwrap();
+        for result in [merged, shuffled] {
+            let message = result.unwrap_err().to_string();
+            assert!(message.contains(&format!("read 1 has {}, read 2 has no more", last_r1)));
+        }
+    }
+}
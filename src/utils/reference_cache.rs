@@ -0,0 +1,301 @@
+// A cache of the parsed reference, so runs on a large reference don't pay for parsing the fasta
+// every time. Bases are packed 2 bits each, with the N runs kept separately (N is the only other
+// value read_fasta produces), and the cache records the size and modification time of the fasta
+// it was made from, so a changed fasta is noticed and the cache rebuilt.
+//
+// Layout (integers little endian):
+//     magic (8 bytes), fasta size (u64), fasta modification time in seconds (u64),
+//     number of contigs (u32), then for each contig in reference order:
+//     name length (u32), name, sequence length (u64), number of N runs (u32),
+//     the N runs as [start, end) pairs (u64, u64), and the packed bases.
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use log::{info, warn};
+use super::bed_tools::non_n_regions;
+use super::errors::NeatError;
+use super::fasta_tools::{parse_region, read_fai, read_fasta, read_fasta_region, ContigMap};
+use super::two_bit::read_two_bit;
+
+// The last byte is the format version. Version 2 caches name contigs by the first word of their
+// header only, so older caches are rebuilt rather than bring back the full headers.
+const CACHE_MAGIC: &[u8; 8] = b"NEATREF\x02";
+
+// The parsed reference, as read_fasta returns it: the sequences keyed by contig name, and the
+// contig names in reference order.
+pub type Reference = (ContigMap, Vec<String>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fingerprint {
+    // What the cache knows about the fasta it was made from.
+    pub size: u64,
+    pub modified: u64,
+}
+
+impl Fingerprint {
+    pub fn of(reference_path: &str) -> io::Result<Fingerprint> {
+        let metadata = fs::metadata(reference_path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Ok(Fingerprint { size: metadata.len(), modified })
+    }
+}
+
+fn pack_bases(sequence: &[u8]) -> Vec<u8> {
+    // Four bases to a byte, first base in the low bits. Ns are packed as A; the N runs put them
+    // back.
+    sequence
+        .chunks(4)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (index, base)| byte | ((base & 3) << (2 * index)))
+        })
+        .collect()
+}
+
+fn unpack_bases(packed: &[u8], length: usize, n_runs: &[(usize, usize)]) -> Vec<u8> {
+    let mut sequence: Vec<u8> = (0..length)
+        .map(|index| (packed[index / 4] >> (2 * (index % 4))) & 3)
+        .collect();
+    for &(start, end) in n_runs {
+        sequence[start..end].fill(4);
+    }
+    sequence
+}
+
+pub fn write_reference_cache(
+    writer: &mut impl Write,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    fingerprint: Fingerprint,
+) -> io::Result<()> {
+    // Takes:
+    // writer: Where to write the cache.
+    // fasta_map: The parsed reference, keyed by contig name.
+    // fasta_order: Contig names in reference order.
+    // fingerprint: The size and modification time of the fasta.
+    // Returns:
+    // Error if there is a problem or else nothing.
+    writer.write_all(CACHE_MAGIC)?;
+    writer.write_all(&fingerprint.size.to_le_bytes())?;
+    writer.write_all(&fingerprint.modified.to_le_bytes())?;
+    writer.write_all(&(fasta_order.len() as u32).to_le_bytes())?;
+    for contig in fasta_order {
+        let sequence = &fasta_map[contig];
+        writer.write_all(&(contig.len() as u32).to_le_bytes())?;
+        writer.write_all(contig.as_bytes())?;
+        writer.write_all(&(sequence.len() as u64).to_le_bytes())?;
+        // The runs of N are the gaps between the non-N regions.
+        let mut n_runs = Vec::new();
+        let mut previous_end = 0;
+        let regions = non_n_regions(sequence);
+        for (start, end) in regions.into_iter().chain([(sequence.len(), sequence.len())]) {
+            if start > previous_end {
+                n_runs.push((previous_end, start));
+            }
+            previous_end = end;
+        }
+        writer.write_all(&(n_runs.len() as u32).to_le_bytes())?;
+        for (start, end) in n_runs {
+            writer.write_all(&(start as u64).to_le_bytes())?;
+            writer.write_all(&(end as u64).to_le_bytes())?;
+        }
+        writer.write_all(&pack_bases(sequence))?;
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_reference_cache(
+    reader: &mut impl Read,
+    fingerprint: Fingerprint,
+) -> io::Result<Option<Reference>> {
+    // Takes:
+    // reader: The cache.
+    // fingerprint: The size and modification time of the fasta as it is now.
+    // Returns:
+    // The reference, as read_fasta would return it, or None if the cache was made from a
+    // different version of the fasta. A file that isn't a cache, or is cut short, is an error.
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != CACHE_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a rusty-neat reference cache"))
+    }
+    let cached = Fingerprint { size: read_u64(reader)?, modified: read_u64(reader)? };
+    if cached != fingerprint {
+        return Ok(None)
+    }
+    let num_contigs = read_u32(reader)? as usize;
+    let mut fasta_map: HashMap<String, Vec<u8>> = HashMap::with_capacity(num_contigs);
+    let mut fasta_order: Vec<String> = Vec::with_capacity(num_contigs);
+    for _ in 0..num_contigs {
+        let mut name = vec![0u8; read_u32(reader)? as usize];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8(name)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let length = read_u64(reader)? as usize;
+        let num_runs = read_u32(reader)? as usize;
+        let mut n_runs = Vec::with_capacity(num_runs);
+        for _ in 0..num_runs {
+            let (start, end) = (read_u64(reader)? as usize, read_u64(reader)? as usize);
+            if start > end || end > length {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData, "bad N run in reference cache"
+                ))
+            }
+            n_runs.push((start, end));
+        }
+        let mut packed = vec![0u8; length.div_ceil(4)];
+        reader.read_exact(&mut packed)?;
+        fasta_map.insert(name.clone(), unpack_bases(&packed, length, &n_runs));
+        fasta_order.push(name);
+    }
+    Ok(Some((Box::new(fasta_map), fasta_order)))
+}
+
+pub fn read_reference(path: &str) -> Result<Reference, NeatError> {
+    // Reads a reference in whichever format it is in: .2bit files by their extension, fasta
+    // otherwise.
+    match path.ends_with(".2bit") {
+        true => read_two_bit(path),
+        false => read_fasta(path),
+    }
+}
+
+pub fn load_reference(
+    reference_path: &str,
+    cache_path: Option<&Path>,
+) -> Result<Reference, NeatError> {
+    // Reads the reference, from the cache if there is an up to date one. Otherwise the fasta is
+    // parsed and, if a cache path was given, the cache is (re)written for next time.
+    let cache_path = match cache_path {
+        Some(cache_path) => cache_path,
+        None => return read_reference(reference_path),
+    };
+    let fingerprint = Fingerprint::of(reference_path)?;
+    if cache_path.is_file() {
+        let mut reader = BufReader::new(File::open(cache_path)?);
+        match read_reference_cache(&mut reader, fingerprint) {
+            Ok(Some(reference)) => {
+                info!("Read reference from cache: {}", cache_path.display());
+                return Ok(reference)
+            },
+            Ok(None) => info!("{} has changed, rebuilding its cache", reference_path),
+            Err(error) => warn!(
+                "Could not read reference cache {} ({}), rebuilding it", cache_path.display(), error
+            ),
+        }
+    }
+    let (fasta_map, fasta_order) = read_reference(reference_path)?;
+    // Written next to the cache and then moved into place, so a run that dies halfway (or another
+    // run reading the cache at the same time) never sees a partial cache.
+    let partial_path = cache_path.with_extension("partial");
+    let mut writer = BufWriter::new(File::create(&partial_path)?);
+    write_reference_cache(&mut writer, &fasta_map, &fasta_order, fingerprint)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&partial_path, cache_path)?;
+    info!("Wrote reference cache: {}", cache_path.display());
+    Ok((fasta_map, fasta_order))
+}
+
+pub fn load_region(
+    reference_path: &str,
+    region: &str,
+    cache_path: Option<&Path>,
+) -> Result<Reference, NeatError> {
+    // Takes:
+    // reference_path: The reference.
+    // region: The region to simulate, as contig:start-end (see parse_region).
+    // cache_path: The reference cache, if the run has one.
+    // Returns:
+    // A reference of just the region's contig, N outside the region. With a samtools faidx index
+    // beside the fasta, only the region's bases are read; otherwise the whole reference is loaded
+    // (from the cache, if there is one) and the rest of it dropped.
+    let (contig, start, end) = parse_region(region)?;
+    let past_end = |length: usize| NeatError::Config(
+        format!("Region {} runs past the end of {}, which is {} bp", region, contig, length)
+    );
+    let missing = || NeatError::Config(
+        format!("Region {} is on a contig not in {}", region, reference_path)
+    );
+    let fai_path = format!("{}.fai", reference_path);
+    let sequence = if !reference_path.ends_with(".2bit") && Path::new(&fai_path).is_file() {
+        info!("Reading {} from {} through its index", region, reference_path);
+        let entries = read_fai(&fai_path)?;
+        let entry = entries.get(&contig).ok_or_else(missing)?;
+        if end > entry.length {
+            return Err(past_end(entry.length))
+        }
+        read_fasta_region(reference_path, entry, start, end)?
+    } else {
+        warn!("{} has no .fai index, so all of it is read to get {}", reference_path, region);
+        let (mut fasta_map, _) = load_reference(reference_path, cache_path)?;
+        let mut sequence = fasta_map.remove(&contig).ok_or_else(missing)?;
+        if end > sequence.len() {
+            return Err(past_end(sequence.len()))
+        }
+        sequence[..start].fill(4);
+        sequence[end..].fill(4);
+        sequence
+    };
+    Ok((Box::new(HashMap::from([(contig.clone(), sequence)])), vec![contig]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_cache_round_trip() {
+        let fasta_map = HashMap::from([
+            ("chr1".to_string(), vec![4, 4, 0, 1, 2, 3, 3, 4, 2]),
+            ("chr2".to_string(), vec![3, 2, 1, 0, 0]),
+        ]);
+        let order = vec!["chr2".to_string(), "chr1".to_string()];
+        let fingerprint = Fingerprint { size: 40, modified: 1_700_000_000 };
+        let mut cache = Vec::new();
+        write_reference_cache(&mut cache, &fasta_map, &order, fingerprint).unwrap();
+        let (cached_map, cached_order) = read_reference_cache(&mut cache.as_slice(), fingerprint)
+            .unwrap()
+            .unwrap();
+        assert_eq!(*cached_map, fasta_map);
+        assert_eq!(cached_order, order);
+        // A different fasta leaves the cache stale, and a cut short cache is an error
+        let changed = Fingerprint { size: 41, ..fingerprint };
+        assert!(read_reference_cache(&mut cache.as_slice(), changed).unwrap().is_none());
+        assert!(read_reference_cache(&mut &cache[..cache.len() - 1], fingerprint).is_err());
+    }
+
+    #[test]
+    fn test_load_reference() {
+        let cache_path = Path::new("test_data/H1N1.fa.neatcache");
+        let from_fasta = read_fasta("test_data/H1N1.fa").unwrap();
+        let built = load_reference("test_data/H1N1.fa", Some(cache_path)).unwrap();
+        assert!(cache_path.is_file());
+        let from_cache = load_reference("test_data/H1N1.fa", Some(cache_path)).unwrap();
+        fs::remove_file(cache_path).unwrap();
+        assert_eq!(built, from_fasta);
+        assert_eq!(from_cache, from_fasta);
+    }
+}
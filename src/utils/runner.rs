@@ -1,105 +1,1570 @@
-use std::collections::HashSet;
-use log::info;
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use chrono::Utc;
+use log::{info, warn};
 use simple_rng::Rng;
-use super::config::RunConfiguration;
-use super::fasta_tools::{read_fasta, write_fasta};
-use super::fastq_tools::write_fastq;
-use super::make_reads::generate_reads;
-use super::mutate::mutate_fasta;
+use super::bam_profile::{read_bam_profile, BamProfile};
+use super::bed_tools::{pad_regions, read_bed, write_confident_regions, Regions};
+use super::config::{canonical_seed, fragment_warning, RunConfiguration};
+use super::coverage_profile::{read_bedgraph, CoverageProfile};
+use super::error_profile::{read_error_profile, CycleErrorProfile};
+use super::ffpe::{ffpe_artifact_sites, write_artifact_vcf, FfpeArtifacts};
+use super::signatures::{read_signatures, SignatureSpectrum};
+use super::known_sites::{read_known_sites, KnownSites};
+use super::capture::CaptureModel;
+use super::longitudinal::{clone_frequencies, write_timepoint_table};
+use super::liftover::{read_chain, ChainMap};
+use super::low_complexity::find_all_low_complexity;
+use super::memory_budget::shuffle_bucket_templates;
+use super::model_dir::{read_model_bundle, ModelBundle};
+use super::genotype_quality::call_sites;
+use super::edit_list::write_edit_list;
+use super::errors::NeatError;
+use super::fasta_tools::{
+    check_sequence_dictionary, is_primary_assembly, is_valid_sam_name, read_fasta,
+    read_sequence_dictionary, write_fasta,
+};
+use super::file_tools::{check_create_dir, check_free_space, output_path, StagedOutputs};
+use super::fastq_tools::PairedRecordCounts;
+use super::gap_fill::{fill_gaps, write_filled_gaps, FilledGaps};
+use super::heteroplasmy::{assign_heteroplasmy, heteroplasmic_sites};
+use super::kmer_spectrum::KmerCounts;
+use super::gff_tools::{annotate_variants, read_annotations, Annotations, RegionRates};
+use super::mutate::{callable_snp_positions, haplotype_sequences, mutate_fasta, place_snps};
+use super::nucleotides::NucModel;
+use super::pileup::ALLELE_COUNTS_HEADER;
+use super::ploidy::PloidyMap;
+use super::provenance::Provenance;
+use super::quasispecies::Quasispecies;
+use super::read_shards::{
+    contig_seeds, merge_bam_shards, merge_index_shards, merge_pileup_shards, merge_shards,
+    merge_singleton_shards, select_contig, shuffle_merge_shards, write_read_shards, ContigSequences,
+    ReadShard, SHUFFLE_BUCKET_TEMPLATES,
+};
+use super::quality_scores::QualityScoreModel;
+use super::reference_cache::{load_reference, load_region, Reference};
+use super::sample_haplotypes::haplotype_variants;
+use super::variants::{assign_genotypes, MutatedReference, Variant, VariantOrigin};
+use super::summary::{run_summary, RunSummary, ShardStats};
+use super::tabix::{write_indexed, TabixFormat};
+use super::truth_check::{check_truth_vcf, TRUTH_CHECK_RECORDS};
+use super::throttle::Throttle;
 use super::vcf_tools::write_vcf;
-use super::read_models::read_quality_score_model_json;
 
-pub fn run_neat(config: Box<RunConfiguration>, mut rng: &mut Rng) -> Result<(), &'static str>{
-    // Create the prefix of the files to write
-    let output_file = format!("{}/{}", config.output_dir.display(), config.output_prefix);
+#[derive(Debug, Default)]
+pub struct RunOutput {
+    // What a run produced, so callers can find the files and inspect the truth data without
+    // re-reading anything from disk.
+    //
+    // fastq_files: The fastq files written (r1, then r2 for paired ended runs, then the
+    //     singletons for runs that lose mates).
+    // index_fastq_files: The I1 (and I2) index read fastqs, for runs with a sample index.
+    // manifest_file: The fastq manifest, recording the fastq files, how many records they hold
+    //     and the seed, version and config hash that made them, if fastq files were written.
+    // fasta_file: The mutated fasta, if one was requested.
+    // haplotype_fasta_files: A fasta per copy of the genome (copy 0 first), if requested.
+    // edit_list_file: The mutated fasta as edits to the reference, if requested.
+    // vcf_file: The truth vcf, if one was requested (bgzipped with bgzip_truth_files).
+    // vcf_index_file: The tabix index of the bgzipped truth vcf.
+    // artifact_vcf_file: The FFPE artifact sites, for runs with a vcf that model them.
+    // bam_file: The reads aligned to the reference at their true positions, if requested.
+    // benchmark_files: The bgzipped truth vcf, its tabix index and the confident regions bed
+    //     (followed by its own index with bgzip_truth_files), if requested.
+    // summary_file: The run summary (coverage, variant and insert size statistics), as json.
+    // multiqc_file: The headline numbers of the summary, as MultiQC custom content.
+    // kmer_histogram_file: The k-mer spectrum of the reads, for runs that ask for one.
+    // lifted_vcf_file: The truth vcf lifted over to another assembly, for runs with a chain file.
+    // pileup_files: The depth bedgraph and allele count table of the reads, for runs that write a
+    //     truth pileup.
+    // filled_gap_files: The bed of the gaps filled with synthetic repeats and the reference with
+    //     them filled, for runs that fill gaps.
+    // variants: Every variant added, keyed by contig name.
+    // num_reads: The number of reads (or read pairs) generated.
+    // fastq_records: The number of records written to the read 1 and read 2 fastq files.
+    // summary: The statistics written to summary_file.
+    pub fastq_files: Vec<PathBuf>,
+    pub index_fastq_files: Vec<PathBuf>,
+    pub manifest_file: Option<PathBuf>,
+    pub fasta_file: Option<PathBuf>,
+    pub haplotype_fasta_files: Vec<PathBuf>,
+    pub edit_list_file: Option<PathBuf>,
+    pub vcf_file: Option<PathBuf>,
+    pub vcf_index_file: Option<PathBuf>,
+    pub lifted_vcf_file: Option<PathBuf>,
+    pub artifact_vcf_file: Option<PathBuf>,
+    pub bam_file: Option<PathBuf>,
+    pub benchmark_files: Vec<PathBuf>,
+    pub summary_file: Option<PathBuf>,
+    pub multiqc_file: Option<PathBuf>,
+    pub kmer_histogram_file: Option<PathBuf>,
+    pub pileup_files: Vec<PathBuf>,
+    pub filled_gap_files: Vec<PathBuf>,
+    pub variants: HashMap<String, Vec<Variant>>,
+    pub num_reads: usize,
+    pub fastq_records: PairedRecordCounts,
+    pub summary: Option<RunSummary>,
+}
+
+pub fn create_rng(rng_seed: Option<&str>) -> Rng {
+    // Generate the RNG used for this run. If no seed was given, we generate a random seed using
+    // the current time. Either way, the seed string is logged so the run can be reproduced.
+    let mut seed_vec: Vec<String> = Vec::new();
+    if let Some(raw_seed) = rng_seed {
+        for seed_term in raw_seed.split_whitespace() {
+            seed_vec.push(seed_term.to_string());
+        }
+        info!("Seed string to regenerate these exact results: {}", canonical_seed(raw_seed));
+    } else {
+        // since no seed was provided, we'll use the datetime stamp
+        info!(
+            "No rng seed provided, using timestamp (and space-separated list of words with simple \
+            characters will also work as a key)"
+        );
+        let timestamp = timestamp_seed();
+        for item in timestamp.split_whitespace() {
+            seed_vec.push(item.to_string());
+        }
+        info!("Seed string to regenerate these exact results: {}", timestamp);
+    }
+    Rng::new_from_seed(seed_vec)
+}
+
+pub fn resolve_seed(config: &mut RunConfiguration) {
+    // Runs that weren't given a seed get one here (see new_seed), rather than in create_rng, so
+    // the seed that was used is in the configuration and gets recorded in the outputs.
+    if config.rng_seed.is_none() {
+        let seed = new_seed(config);
+        info!("No rng seed provided, using {} (rng_seed: {} reproduces this run)", seed, seed);
+        config.rng_seed = Some(seed);
+    }
+}
+
+fn timestamp_seed() -> String {
+    // A seed string made from the current time, for runs that weren't given one.
+    Utc::now().format("%Y %m %d %H %M %S %f").to_string()
+}
+
+// Short, common words that are hard to mishear, for seeds that are read out or typed in by hand.
+const SEED_WORDS: &str = include_str!("../../data/seed_words.txt");
+
+pub fn word_seed(num_words: usize, rng: &mut Rng) -> String {
+    // A seed of num_words words drawn from SEED_WORDS.
+    let words: Vec<&str> = SEED_WORDS.split_whitespace().collect();
+    (0..num_words)
+        .map(|_| words[rng.range_i64(0, words.len() as i64) as usize])
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn new_seed(config: &RunConfiguration) -> String {
+    // The seed of a run that wasn't given one: random_seed_words words, drawn with an rng seeded
+    // from the clock, or else the time itself. Callers log the seed they use.
+    let timestamp = timestamp_seed();
+    let Some(num_words) = config.random_seed_words else {
+        return timestamp
+    };
+    let seed_terms = timestamp.split_whitespace().map(String::from).collect();
+    word_seed(num_words, &mut Rng::new_from_seed(seed_terms))
+}
+
+pub fn replicate_configs(config: &RunConfiguration, replicates: usize) -> Vec<RunConfiguration> {
+    // Takes:
+    // config: The configuration to replicate.
+    // replicates: How many replicates to make.
+    // Returns:
+    // One configuration per replicate, each with a seed derived from the run's seed
+    // ("<seed>.rep1", "<seed>.rep2", ...) and writing into its own subdirectory of the output
+    // directory (rep1, rep2, ...). Any single replicate can then be rerun on its own with its seed.
+    let master_seed = config.rng_seed.clone().unwrap_or_else(|| {
+        let seed = new_seed(config);
+        info!("No rng seed provided, deriving the replicate seeds from {}", seed);
+        seed
+    });
+    (1..=replicates)
+        .map(|replicate| {
+            let mut replicate_config = config.clone();
+            replicate_config.rng_seed = Some(format!("{}.rep{}", master_seed, replicate));
+            replicate_config.output_dir = config.output_dir.join(format!("rep{}", replicate));
+            replicate_config
+        })
+        .collect()
+}
+
+pub fn run_replicates(
+    config: &RunConfiguration,
+    replicates: usize,
+) -> Result<Vec<RunOutput>, NeatError> {
+    // Runs the configuration once per replicate (see replicate_configs), one after the other.
+    if replicates == 0 {
+        return Err(NeatError::Config("replicates must be at least 1.".to_string()))
+    }
+    let mut outputs = Vec::with_capacity(replicates);
+    for (index, replicate_config) in replicate_configs(config, replicates).into_iter().enumerate() {
+        info!(
+            "Running replicate {} of {} into {}",
+            index + 1, replicates, replicate_config.output_dir.display()
+        );
+        check_create_dir(&replicate_config.output_dir)?;
+        let mut rng = create_rng(replicate_config.rng_seed.as_deref());
+        outputs.push(run_neat(Box::new(replicate_config), &mut rng)?);
+    }
+    Ok(outputs)
+}
+
+pub fn timepoint_configs(config: &RunConfiguration, seed: &str) -> Vec<RunConfiguration> {
+    // Takes:
+    // config: The configuration of a longitudinal series.
+    // seed: The seed of the series.
+    // Returns:
+    // One configuration per timepoint (see longitudinal.rs), each a quasispecies of every clone
+    // of the series at that timepoint's shares, run with the series' seed and writing into its
+    // own subdirectory of the output directory (t1, t2, ...). The shares are drawn from their
+    // own rng, so the runs' are the same for every timepoint.
+    let timepoints = config.timepoints.unwrap_or(1);
+    let seed_terms = seed.split_whitespace().map(String::from).chain(["timepoints".to_string()]);
+    let mut rng = Rng::new_from_seed(seed_terms.collect());
+    clone_frequencies(timepoints, config.timepoint_drift, &mut rng)
+        .into_iter()
+        .enumerate()
+        .map(|(index, frequencies)| {
+            let mut timepoint_config = config.clone();
+            timepoint_config.timepoints = None;
+            timepoint_config.rng_seed = Some(seed.to_string());
+            timepoint_config.output_dir = config.output_dir.join(format!("t{}", index + 1));
+            timepoint_config.sample_name = format!("{}_t{}", config.sample_name, index + 1);
+            timepoint_config.ploidy = frequencies.len();
+            timepoint_config.quasispecies_frequencies = frequencies;
+            timepoint_config.snp_distance = Some(config.timepoint_mutations * timepoints);
+            timepoint_config
+        })
+        .collect()
+}
+
+pub fn run_timepoints(config: &RunConfiguration) -> Result<Vec<RunOutput>, NeatError> {
+    // Runs every timepoint of a longitudinal series (see timepoint_configs), one after the other,
+    // reading the reference once for all of them, then writes the table linking their variants
+    // to <output_dir>/<output_prefix>_timepoints.tsv. Returns the outputs in timepoint order.
+    let seed = config.rng_seed.clone().unwrap_or_else(|| {
+        let seed = new_seed(config);
+        info!("No rng seed provided, using {} for every timepoint", seed);
+        seed
+    });
+    info!("Mapping reference fasta file once for the series: {}", &config.reference);
+    let (fasta_map, fasta_order) = load_checked_reference(config)?;
+    let timepoint_configs = timepoint_configs(config, &seed);
+    let timepoints = timepoint_configs.len();
+    let mut outputs = Vec::with_capacity(timepoints);
+    for (index, timepoint_config) in timepoint_configs.into_iter().enumerate() {
+        info!(
+            "Running timepoint {} of {} into {}",
+            index + 1, timepoints, timepoint_config.output_dir.display()
+        );
+        check_create_dir(&timepoint_config.output_dir)?;
+        let mut rng = create_rng(Some(&seed));
+        outputs.push(run_neat_on_reference(
+            Box::new(timepoint_config), &fasta_map, &fasta_order, &mut rng
+        )?);
+    }
+    let mut staged = StagedOutputs::new(&config.output_dir, config.overwrite_output)?;
+    let table_filename = output_path(&config.output_dir, &config.output_prefix, "_timepoints.tsv");
+    {
+        let mut table_writer = staged.create(&table_filename)?;
+        let variants: Vec<&HashMap<String, Vec<Variant>>> =
+            outputs.iter().map(|output| &output.variants).collect();
+        write_timepoint_table(&mut table_writer, &variants, &fasta_order)?;
+        table_writer.flush()?;
+    }
+    staged.commit()?;
+    Ok(outputs)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn mutate_reference(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    annotations: Option<&Annotations>,
+    ploidy_map: &PloidyMap,
+    signatures: Option<&SignatureSpectrum>,
+    known_sites: Option<&KnownSites>,
+    germline_model: &NucModel,
+    rng: &mut Rng,
+) -> MutatedReference {
+    // Applies the germline layer, and the somatic layer if one is configured, to the reference,
+    // then draws a genotype for every variant. With gene annotations, the germline layer can use
+    // a different rate in exons, introns and intergenic regions, and every variant in a
+    // transcript is annotated with its gene and effect. Parts of the genome the sample has no
+    // copies of (per ploidy_map) are left without variants. With signatures, the somatic layer
+    // follows their mix instead of the default somatic spectrum, and with known sites, some of
+    // the germline variants are put at them. Germline substitutions follow germline_model. A
+    // quasispecies gets enough SNPs for its diversity, put on the branches of its genealogy.
+    // Returns the mutated sequences (with every variant, whatever its genotype), the variants,
+    // keyed by contig name and sorted by position (variants are written out in order, and tabix
+    // indexes need them that way), and the genotype counts. None of it depends on config.threads.
+    info!("Mutating reference.");
+    // A contig gets variants for its full length, so a region run, whose contig is N outside the
+    // region, scales its rates down to the region's share of the contig.
+    let rate_fold = match &config.region {
+        Some(_) => {
+            let length: usize = fasta_map.values().map(Vec::len).sum();
+            let called = fasta_map.values().flatten().filter(|base| **base < 4).count();
+            called as f64 / length.max(1) as f64
+        },
+        None => 1.0,
+    };
+    let region_rates = annotations
+        .filter(|_| config.has_region_rates())
+        .map(|annotations| RegionRates {
+            annotations,
+            exon: config.exon_mutation_rate.unwrap_or(config.mutation_rate) * rate_fold,
+            intron: config.intron_mutation_rate.unwrap_or(config.mutation_rate) * rate_fold,
+            intergenic: config.intergenic_mutation_rate.unwrap_or(config.mutation_rate)
+                * rate_fold,
+        });
+    let quasispecies = match config.quasispecies_frequencies.is_empty() {
+        true => None,
+        false => Some(Quasispecies::new(&config.quasispecies_frequencies, rng)),
+    };
+    let snp_count = config.snp_distance.or_else(|| {
+        quasispecies.as_ref().map(|quasispecies| quasispecies.num_snps(
+            config.quasispecies_diversity, callable_snp_positions(fasta_map, ploidy_map)
+        ))
+    });
+    // A clonal isolate gets exactly its distance from the reference instead, and a quasispecies
+    // exactly the SNPs its diversity calls for.
+    let (mut mutated_map, mut variant_locations) = match snp_count {
+        Some(snp_count) => place_snps(
+            fasta_map, fasta_order, snp_count, germline_model, ploidy_map, rng
+        ),
+        None => mutate_fasta(
+            fasta_map,
+            config.mutation_rate * rate_fold,
+            region_rates.as_ref(),
+            germline_model,
+            None,
+            known_sites,
+            VariantOrigin::Germline,
+            None,
+            config.minimum_mutations,
+            config.threads,
+            rng
+        ),
+    };
+
+    // The somatic layer goes on top of the germline sequence, so the reads from this sample carry
+    // both the germline background and the somatic variants.
+    if let Some(somatic_rate) = config.somatic_mutation_rate {
+        info!("Adding somatic mutations.");
+        let (somatic_map, somatic_variants) = mutate_fasta(
+            &mutated_map,
+            somatic_rate * rate_fold,
+            None,
+            &NucModel::somatic(),
+            signatures,
+            None,
+            VariantOrigin::Somatic,
+            Some(&variant_locations),
+            None,
+            config.threads,
+            rng
+        );
+        mutated_map = somatic_map;
+        for (contig, variants) in somatic_variants.into_iter() {
+            let contig_variants = variant_locations.entry(contig).or_default();
+            contig_variants.extend(variants);
+            contig_variants.sort_by_key(|variant| variant.position);
+        }
+    }
+    for (contig, variants) in variant_locations.iter_mut() {
+        let mutated = mutated_map.get_mut(contig).unwrap();
+        variants.retain(|variant| {
+            let present = ploidy_map.copies(contig, variant.position) > 0;
+            if !present {
+                mutated[variant.position] = variant.reference;
+            }
+            present
+        });
+    }
+    let genotype_model = config.genotype_model();
+    let genotype_counts = match &quasispecies {
+        Some(quasispecies) => {
+            let counts = quasispecies.assign_haplotypes(&mut variant_locations, fasta_order, rng);
+            // The SNPs of clones that haven't arisen yet (see longitudinal.rs) aren't in the
+            // sample.
+            for (contig, variants) in variant_locations.iter_mut() {
+                let mutated = mutated_map.get_mut(contig).unwrap();
+                variants.retain(|variant| {
+                    let present = variant.frequency.is_some_and(|frequency| frequency > 0.0);
+                    if !present {
+                        mutated[variant.position] = variant.reference;
+                    }
+                    present
+                });
+            }
+            counts
+        },
+        None => assign_genotypes(
+            &mut variant_locations, fasta_order, ploidy_map, genotype_model.as_ref(), rng
+        ),
+    };
+    if !config.heteroplasmy_fractions.is_empty() {
+        assign_heteroplasmy(
+            &mut variant_locations, fasta_order, ploidy_map, &config.heteroplasmy_fractions, rng
+        );
+    }
+    if let Some(annotations) = annotations {
+        annotate_variants(&mut variant_locations, fasta_map, annotations);
+    }
+    MutatedReference { sequences: mutated_map, variants: variant_locations, genotype_counts }
+}
+
+pub fn run_sweep(config: &RunConfiguration) -> Result<Vec<RunOutput>, NeatError> {
+    // Runs every point of the configuration's sweep, reading the reference once for all of them.
+    // The points are spread over config.threads workers, with the threads shared out evenly
+    // between the points running at once. Every point uses the run's seed, so the points only
+    // differ in the settings being swept. Returns the outputs in sweep order.
+    let seed = config.rng_seed.clone().unwrap_or_else(|| {
+        let seed = new_seed(config);
+        info!("No rng seed provided, using {} for every point of the sweep", seed);
+        seed
+    });
+    info!("Mapping reference fasta file once for the sweep: {}", &config.reference);
+    let (fasta_map, fasta_order) = load_checked_reference(config)?;
+    let points = &config.sweep;
+    let next_point = AtomicUsize::new(0);
+    let num_workers = config.threads.clamp(1, points.len().max(1));
+    let threads_per_point = (config.threads / num_workers).max(1);
+    let mut finished: Vec<(usize, RunOutput)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_workers)
+            .map(|_| scope.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let point_index = next_point.fetch_add(1, Ordering::Relaxed);
+                    if point_index >= points.len() {
+                        return Ok(done)
+                    }
+                    let point = &points[point_index];
+                    info!("Running sweep point {}", point.label);
+                    let mut point_config = point.config.clone();
+                    point_config.rng_seed = Some(seed.clone());
+                    point_config.threads = threads_per_point;
+                    let mut rng = create_rng(Some(&seed));
+                    let output = run_neat_on_reference(
+                        Box::new(point_config), &fasta_map, &fasta_order, &mut rng
+                    )?;
+                    done.push((point_index, output));
+                }
+            }))
+            .collect();
+        let mut finished = Vec::new();
+        for worker in workers {
+            let done: Result<Vec<(usize, RunOutput)>, NeatError> = worker
+                .join()
+                .expect("Sweep worker panicked");
+            finished.extend(done?);
+        }
+        Ok::<_, NeatError>(finished)
+    })?;
+    finished.sort_by_key(|(point_index, _)| *point_index);
+    Ok(finished.into_iter().map(|(_, output)| output).collect())
+}
+
+// Bases added on each side of a target region, so that fragments overlapping the edges of a target
+// are simulated too, as a capture would pull them down. Runs with a capture model add its
+// shoulder on top.
+const TARGET_PADDING: usize = 500;
+
+pub fn load_targets(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+) -> Result<Option<Regions>, NeatError> {
+    // Reads the target bed, if the run has one, and pads its regions. Returns None for whole
+    // genome runs.
+    let target_bed = match &config.target_bed {
+        Some(target_bed) => target_bed,
+        None => return Ok(None),
+    };
+    info!("Reading target regions: {}", target_bed);
+    let contig_lengths: HashMap<String, usize> = fasta_map
+        .iter()
+        .map(|(contig, sequence)| (contig.clone(), sequence.len()))
+        .collect();
+    let padding = match config.capture_model {
+        true => TARGET_PADDING + config.capture_shoulder,
+        false => TARGET_PADDING,
+    };
+    let targets = pad_regions(&read_bed(target_bed)?, padding, &contig_lengths);
+    if targets.values().all(|regions| regions.is_empty()) {
+        return Err(NeatError::BedParse(
+            format!("no regions of {} are on contigs of {}", target_bed, config.reference)
+        ))
+    }
+    Ok(Some(targets))
+}
+
+pub fn load_capture_model(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    seeds: &[Vec<String>],
+) -> Result<Option<CaptureModel>, NeatError> {
+    // Builds the capture model around the unpadded targets, if the run has one. The efficiencies
+    // of the targets come from the first contig's seed, so every contig's reads still depend only
+    // on its own seed.
+    let target_bed = match (&config.target_bed, config.capture_model) {
+        (Some(target_bed), true) => target_bed,
+        _ => return Ok(None),
+    };
+    let contig_lengths: HashMap<String, usize> = fasta_map
+        .iter()
+        .map(|(contig, sequence)| (contig.clone(), sequence.len()))
+        .collect();
+    let targets = pad_regions(&read_bed(target_bed)?, 0, &contig_lengths);
+    let mut capture_seed = seeds[0].clone();
+    capture_seed.push("capture efficiencies".to_string());
+    Ok(Some(CaptureModel::new(
+        &targets,
+        config.capture_shoulder,
+        config.capture_efficiency_st_dev,
+        config.capture_gc_dropout,
+        fasta_order,
+        &mut Rng::new_from_seed(capture_seed),
+    )))
+}
+
+pub fn load_low_complexity(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+) -> Result<Option<Regions>, NeatError> {
+    // The low-complexity regions, for runs that raise the error rate in them: read from the bed
+    // if there is one, otherwise found in the reference.
+    if config.low_complexity_error_fold.is_none() {
+        return Ok(None)
+    }
+    let Some(low_complexity_bed) = &config.low_complexity_bed else {
+        info!("Finding low-complexity regions of the reference");
+        return Ok(Some(find_all_low_complexity(fasta_map)))
+    };
+    info!("Reading low-complexity regions: {}", low_complexity_bed);
+    let contig_lengths: HashMap<String, usize> = fasta_map
+        .iter()
+        .map(|(contig, sequence)| (contig.clone(), sequence.len()))
+        .collect();
+    Ok(Some(pad_regions(&read_bed(low_complexity_bed)?, 0, &contig_lengths)))
+}
+
+pub fn fill_reference_gaps(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    rng: &mut Rng,
+) -> Option<(HashMap<String, Vec<u8>>, FilledGaps)> {
+    // For runs that fill gaps, a copy of the reference with its gaps filled with synthetic
+    // telomere and satellite repeats, and the spans filled. Everything after this simulates on
+    // the filled reference, as if it were the one given.
+    if !config.fill_gaps {
+        return None
+    }
+    let mut filled_map = fasta_map.clone();
+    let mut filled_gaps = FilledGaps::new();
+    for contig in fasta_order {
+        let filled = fill_gaps(filled_map.get_mut(contig).unwrap(), rng);
+        let filled_length: usize = filled.iter().map(|(start, end, _)| end - start).sum();
+        if filled_length > 0 {
+            info!("Filled {} bp of gaps in {} with synthetic repeats", filled_length, contig);
+        }
+        filled_gaps.insert(contig.clone(), filled);
+    }
+    Some((filled_map, filled_gaps))
+}
+
+pub fn load_error_profile(
+    config: &RunConfiguration,
+    models: &ModelBundle,
+) -> Result<Option<CycleErrorProfile>, NeatError> {
+    // Reads the error profile, if the run has one, or else takes the model bundle's.
+    let Some(error_profile) = &config.error_profile else { return Ok(models.error.clone()) };
+    info!("Reading error profile: {}", error_profile);
+    Ok(Some(read_error_profile(error_profile)?))
+}
+
+pub fn load_model_bundle(config: &mut RunConfiguration) -> Result<ModelBundle, NeatError> {
+    // Reads the run's model bundle, if it has one. It is read again for every run, so a bundle
+    // changed between the runs of a sweep or of replicates is picked up. Its fragment model
+    // fills in the fragment settings the run leaves unset.
+    let Some(model_dir) = &config.model_dir else { return Ok(ModelBundle::default()) };
+    let models = read_model_bundle(model_dir)?;
+    models.log_models(model_dir);
+    if let Some(fragment) = models.fragment {
+        config.fragment_mean = config.fragment_mean.or(Some(fragment.mean));
+        config.fragment_st_dev = config.fragment_st_dev.or(Some(fragment.st_dev));
+    }
+    Ok(models)
+}
+
+pub fn ffpe_artifacts(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    seeds: &[Vec<String>],
+) -> Option<FfpeArtifacts> {
+    // Picks the FFPE artifact sites, if the run has them.
+    let rate = config.ffpe_artifact_rate?;
+    let artifacts = ffpe_artifact_sites(
+        fasta_map,
+        fasta_order,
+        seeds,
+        rate,
+        config.ffpe_artifact_fraction,
+        config.fragment_mean?,
+    );
+    let num_sites: usize = artifacts.sites.values().map(Vec::len).sum();
+    info!("Damaged {} FFPE artifact sites", num_sites);
+    Some(artifacts)
+}
+
+pub fn load_coverage_bam(
+    config: &mut RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+) -> Result<Option<BamProfile>, NeatError> {
+    // Reads the coverage bam, if the run has one. Paired runs draw their fragments from its
+    // fragment lengths, and take its fragment mean and standard deviation for everything that
+    // only needs those, keeping the ones set if it has no proper pairs.
+    let coverage_bam = match &config.coverage_bam {
+        Some(coverage_bam) => coverage_bam.clone(),
+        None => return Ok(None),
+    };
+    info!("Reading coverage bam: {}", coverage_bam);
+    let contig_lengths: HashMap<String, usize> = fasta_map
+        .iter()
+        .map(|(contig, sequence)| (contig.clone(), sequence.len()))
+        .collect();
+    let bam_profile = read_bam_profile(&coverage_bam, &contig_lengths)?;
+    if bam_profile.alignments == 0 {
+        return Err(NeatError::BamParse(format!(
+            "no alignments of {} are on contigs of {}", coverage_bam, config.reference
+        )))
+    }
+    if config.paired_ended {
+        match &bam_profile.fragment_lengths {
+            Some(lengths) => {
+                info!(
+                    "Fragment lengths of {}: mean {:.1}, standard deviation {:.1}",
+                    coverage_bam, lengths.mean, lengths.st_dev
+                );
+                config.fragment_mean = Some(lengths.mean);
+                config.fragment_st_dev = Some(lengths.st_dev);
+            },
+            None if config.fragment_mean.is_some() && config.fragment_st_dev.is_some() => warn!(
+                "{} has no proper pairs, so fragment_mean and fragment_st_dev are used",
+                coverage_bam
+            ),
+            None => return Err(NeatError::Config(format!(
+                "{} has no proper pairs to take fragment lengths from; set fragment_mean and \
+                fragment_st_dev", coverage_bam
+            ))),
+        }
+    }
+    Ok(Some(bam_profile))
+}
+
+pub fn load_coverage_profile(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    coverage_bam: Option<&BamProfile>,
+) -> Result<Option<CoverageProfile>, NeatError> {
+    // Reads the coverage profile, if the run has one, or takes it from the coverage bam, scaled
+    // to the run's coverage.
+    if let Some(bam_profile) = coverage_bam {
+        let windows = bam_profile.windows.clone();
+        return Ok(Some(CoverageProfile::from_windows(windows, config.coverage)?))
+    }
+    let coverage_profile = match &config.coverage_profile {
+        Some(coverage_profile) => coverage_profile,
+        None => return Ok(None),
+    };
+    info!("Reading coverage profile: {}", coverage_profile);
+    let contig_lengths: HashMap<String, usize> = fasta_map
+        .iter()
+        .map(|(contig, sequence)| (contig.clone(), sequence.len()))
+        .collect();
+    let windows = read_bedgraph(coverage_profile, &contig_lengths)?;
+    if windows.is_empty() {
+        return Err(NeatError::BedParse(format!(
+            "no windows of {} are on contigs of {}", coverage_profile, config.reference
+        )))
+    }
+    Ok(Some(CoverageProfile::from_windows(windows, config.coverage)?))
+}
+
+pub fn load_signatures(config: &RunConfiguration) -> Result<Option<SignatureSpectrum>, NeatError> {
+    // Mixes the somatic signatures by their exposures, if the run has them.
+    let somatic_signatures = match &config.somatic_signatures {
+        Some(somatic_signatures) => somatic_signatures,
+        None => return Ok(None),
+    };
+    info!("Reading somatic signatures: {}", somatic_signatures);
+    let signatures = read_signatures(somatic_signatures)?;
+    Ok(Some(SignatureSpectrum::mix(&signatures, &config.signature_exposures)?))
+}
+
+pub fn load_known_sites(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+) -> Result<Option<KnownSites>, NeatError> {
+    // Reads the known sites, if the run has them, keeping the SNVs that match the reference.
+    let known_sites_vcf = match &config.known_sites_vcf {
+        Some(known_sites_vcf) => known_sites_vcf,
+        None => return Ok(None),
+    };
+    info!("Reading known sites: {}", known_sites_vcf);
+    let sites = read_known_sites(known_sites_vcf, fasta_map)?;
+    if sites.is_empty() {
+        return Err(NeatError::VcfParse(format!(
+            "no SNVs of {} match contigs of {}", known_sites_vcf, config.reference
+        )))
+    }
+    Ok(Some(KnownSites { sites, fraction: config.known_site_fraction }))
+}
 
+pub fn load_sample_haplotypes(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    annotations: Option<&Annotations>,
+) -> Result<MutatedReference, NeatError> {
+    // Reads the sample's haplotype fastas and takes their differences from the reference as its
+    // variants (see sample_haplotypes.rs), in place of mutate_reference. With gene annotations,
+    // the variants are annotated as simulated ones are.
+    let mut haplotypes = Vec::with_capacity(config.sample_haplotypes.len());
+    for path in &config.sample_haplotypes {
+        let (haplotype, _) = read_fasta(path)?;
+        haplotypes.push((path.clone(), *haplotype));
+    }
+    let mut mutated = haplotype_variants(fasta_map, fasta_order, &haplotypes)?;
+    info!(
+        "Found {} variants in the {} sample haplotypes",
+        mutated.variants.values().map(Vec::len).sum::<usize>(), haplotypes.len()
+    );
+    if let Some(annotations) = annotations {
+        annotate_variants(&mut mutated.variants, fasta_map, annotations);
+    }
+    Ok(mutated)
+}
+
+pub fn load_liftover_chain(config: &RunConfiguration) -> Result<Option<ChainMap>, NeatError> {
+    // Reads the liftover chain file, if the run has one.
+    let Some(liftover_chain) = &config.liftover_chain else { return Ok(None) };
+    info!("Reading liftover chain file: {}", liftover_chain);
+    read_chain(liftover_chain).map(Some)
+}
+
+pub fn load_ploidy_map(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+) -> Result<PloidyMap, NeatError> {
+    // The copies of each part of the genome: config.ploidy of everything but the sex chromosomes,
+    // which follow the sample sex, the mitochondrial genome, and the contigs of contig_ploidy,
+    // with plasmids read deeper by their copy number.
+    let par_regions = match &config.par_bed {
+        Some(par_bed) => Some(read_bed(par_bed)?),
+        None => None,
+    };
+    PloidyMap::for_sample(
+        config.sample_sex, config.ploidy, config.mt_copy_number, fasta_map, par_regions.as_ref()
+    )?
+        .with_contig_ploidy(&config.contig_ploidy, fasta_map)?
+        .with_copy_numbers(&config.plasmid_copy_number, fasta_map)
+}
+
+pub fn load_annotations(config: &RunConfiguration) -> Result<Option<Annotations>, NeatError> {
+    // Reads the gene annotations, if the run has them.
+    match &config.gene_annotation {
+        Some(gene_annotation) => {
+            info!("Reading gene annotations: {}", gene_annotation);
+            Ok(Some(read_annotations(gene_annotation)?))
+        },
+        None => Ok(None),
+    }
+}
+
+// Rough size of everything in a fastq record other than the bases and quality scores (name line,
+// "+" line and newlines).
+const FASTQ_RECORD_OVERHEAD: u64 = 40;
+// Rough size of one vcf record.
+const VCF_RECORD_SIZE: u64 = 100;
+// Rough size of the fixed part of a bam record (before compression, which we ignore).
+const BAM_RECORD_OVERHEAD: u64 = 60;
+
+pub fn estimate_output_size(
+    config: &RunConfiguration,
+    reference_length: u64,
+    covered_length: u64,
+) -> u64 {
+    // Estimates how many bytes the requested outputs will take, given the total length of the
+    // reference and of the part of it covered by reads (smaller for panel runs). This is meant as
+    // a sanity check before a run, not an exact figure.
+    let mut required: u64 = 0;
+    let bases = covered_length * config.coverage as u64;
+    let num_reads = bases / config.read_len.max(1) as u64 + 1;
+    if config.produce_fastq {
+        // A base and a quality score per position
+        required += 2 * bases + num_reads * FASTQ_RECORD_OVERHEAD;
+    }
+    if config.produce_bam {
+        // Half a byte per base and a byte per quality score, plus the shards it is built from
+        required += 2 * (bases + bases / 2 + num_reads * BAM_RECORD_OVERHEAD);
+    }
+    if config.produce_fasta {
+        // 70 bases per line, plus newlines
+        required += reference_length + reference_length / 70 + 1;
+    }
+    if config.haplotype_fasta {
+        // The same again for each copy
+        required += config.ploidy as u64 * (reference_length + reference_length / 70 + 1);
+    }
+    let rate = config.mutation_rate + config.somatic_mutation_rate.unwrap_or(0.0);
+    let num_variants = (reference_length as f64 * rate).ceil() as u64;
+    if config.produce_edit_list {
+        // A few bytes an edit, with room for the contig names
+        required += 4 * num_variants + 1000;
+    }
+    if config.produce_vcf {
+        required += (num_variants + 10) * VCF_RECORD_SIZE;
+    }
+    if config.produce_benchmark_files {
+        // The bgzipped vcf is never bigger than the vcf, and the index and bed are small
+        required += (num_variants + 10) * VCF_RECORD_SIZE + reference_length / 1000 + 1;
+    }
+    required
+}
+
+fn write_indexed_file(
+    outputs: &mut StagedOutputs,
+    text: &[u8],
+    format: TabixFormat,
+    filename: &Path,
+    index_filename: &Path,
+) -> Result<(), NeatError> {
+    // Writes a vcf or bed bgzipped to filename, with its tabix index at index_filename.
+    let mut writer = outputs.create(filename)?;
+    let mut index_writer = outputs.create(index_filename)?;
+    write_indexed(text, format, &mut writer, &mut index_writer)?;
+    writer.flush()?;
+    index_writer.flush()?;
+    Ok(())
+}
+
+// The furthest positions a bam record and a tabix index can hold.
+const BAM_MAX_POSITION: usize = i32::MAX as usize;
+const TABIX_MAX_POSITION: usize = (1 << 29) - 1;
+
+pub fn run_warnings(
+    config: &RunConfiguration,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+) -> Result<Vec<String>, NeatError> {
+    // Checks the configuration against the reference before any work is done. Contigs too long
+    // for the requested outputs are an error. Settings that would give a run few or no reads, or
+    // reads unlike what was most likely meant, are warned about, and the warnings returned for
+    // the run summary.
+    let longest = fasta_order.iter().max_by_key(|contig| fasta_map[*contig].len());
+    if let Some(contig) = longest {
+        let length = fasta_map[contig].len();
+        let mut limits = Vec::new();
+        if config.produce_bam {
+            limits.push((BAM_MAX_POSITION, "a bam"));
+        }
+        if config.produce_benchmark_files || config.bgzip_truth_files {
+            limits.push((TABIX_MAX_POSITION, "a tabix index"));
+        }
+        for (max_position, output) in limits {
+            if length > max_position {
+                return Err(NeatError::Config(format!(
+                    "{} is {} bp, longer than {} can hold ({} bp)",
+                    contig, length, output, max_position
+                )))
+            }
+        }
+    }
+
+    let mut warnings = Vec::new();
+    if config.paired_ended {
+        // The config's own fragment mean was warned about when it was checked; one from a
+        // coverage bam wasn't known yet.
+        let fragment_mean = config.fragment_mean.unwrap_or_default();
+        if let Some(warning) = fragment_warning(config.read_len, fragment_mean) {
+            if config.coverage_bam.is_some() {
+                warn!("{}", warning);
+            }
+            warnings.push(warning);
+        }
+    }
+    let short_contigs = fasta_order
+        .iter()
+        .filter(|contig| fasta_map[*contig].len() < config.read_len)
+        .count();
+    if short_contigs > 0 {
+        let warning = format!(
+            "{} of {} contigs are shorter than read_len ({}) and will get no reads",
+            short_contigs, fasta_order.len(), config.read_len
+        );
+        warn!("{}", warning);
+        warnings.push(warning);
+    }
+    let called_bases: usize = fasta_order
+        .iter()
+        .map(|contig| fasta_map[contig].iter().filter(|base| **base < 4).count())
+        .sum();
+    let expected_reads = config.coverage as f64 * called_bases as f64 / config.read_len as f64;
+    if expected_reads < 1.0 {
+        let warning = format!(
+            "{}X coverage of {} called bases with {} bp reads comes to less than one read",
+            config.coverage, called_bases, config.read_len
+        );
+        warn!("{}", warning);
+        warnings.push(warning);
+    }
+    Ok(warnings)
+}
+
+pub fn load_checked_reference(config: &RunConfiguration) -> Result<Reference, NeatError> {
+    // Reads the reference (from the cache, if there is one), and checks it against the sequence
+    // dictionary if one was given, so a truncated or edited reference is caught before any work.
+    // For primary assembly only runs, the other contigs are then dropped, so nothing downstream
+    // (variants, reads or output headers) sees them. Region runs only load the region's contig.
+    let (mut fasta_map, mut fasta_order) = match &config.region {
+        Some(region) => {
+            load_region(&config.reference, region, config.reference_cache.as_deref())?
+        },
+        None => load_reference(&config.reference, config.reference_cache.as_deref())?,
+    };
+    if let Some(dictionary) = &config.sequence_dictionary {
+        info!("Checking the reference against {}", dictionary);
+        let mut dictionary = read_sequence_dictionary(dictionary)?;
+        if config.region.is_some() {
+            dictionary.retain(|(contig, _)| fasta_map.contains_key(contig));
+        }
+        check_sequence_dictionary(&fasta_map, &fasta_order, &dictionary)?;
+    }
+    if config.primary_assembly_only {
+        let num_contigs = fasta_order.len();
+        fasta_order.retain(|contig| is_primary_assembly(contig));
+        fasta_map.retain(|contig, _| is_primary_assembly(contig));
+        info!("Skipping {} contigs outside the primary assembly", num_contigs - fasta_order.len());
+        if fasta_order.is_empty() {
+            return Err(NeatError::FastaParse(
+                format!("No primary assembly contigs in {}", config.reference)
+            ))
+        }
+    }
+    // Names are passed through as they are, so outputs match the reference exactly, but tools
+    // downstream may reject the ones the SAM spec doesn't allow.
+    let invalid: Vec<&str> = fasta_order
+        .iter()
+        .filter(|contig| !is_valid_sam_name(contig))
+        .map(String::as_str)
+        .collect();
+    if !invalid.is_empty() {
+        warn!(
+            "{} contig names aren't valid SAM reference names and may be rejected by other \
+            tools: {}{}",
+            invalid.len(),
+            invalid[..invalid.len().min(5)].join(", "),
+            if invalid.len() > 5 { ", ..." } else { "" },
+        );
+    }
+    Ok((fasta_map, fasta_order))
+}
+
+pub fn run_neat(
+    config: Box<RunConfiguration>,
+    rng: &mut Rng
+) -> Result<RunOutput, NeatError> {
     // Reading the reference file into memory
     info!("Mapping reference fasta file: {}", &config.reference);
-    let (fasta_map, fasta_order) = read_fasta(&config.reference)
-        .unwrap();
+    let (fasta_map, fasta_order) = load_checked_reference(&config)?;
+    run_neat_on_reference(config, &fasta_map, &fasta_order, rng)
+}
+
+// Where a run's files are staged, and the record of what it has written so far. Each stage of
+// run_neat_on_reference writes through it.
+//
+// output_order: The contigs whose output is written, which is all of them unless the run is of
+//     only one.
+struct RunFiles<'a> {
+    config: &'a RunConfiguration,
+    outputs: StagedOutputs,
+    provenance: Provenance,
+    output_order: Vec<String>,
+    run_output: RunOutput,
+}
+
+impl RunFiles<'_> {
+    fn path(&self, suffix: &str) -> PathBuf {
+        // Output files are named <output_dir>/<output_prefix><suffix>
+        output_path(&self.config.output_dir, &self.config.output_prefix, suffix)
+    }
+}
+
+pub fn run_neat_on_reference(
+    mut config: Box<RunConfiguration>,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    rng: &mut Rng
+) -> Result<RunOutput, NeatError> {
+    // Runs the simulation on a reference that has already been read, so several runs on the same
+    // reference only pay for reading it once.
+    let mut models = load_model_bundle(&mut config)?;
+    let coverage_bam = load_coverage_bam(&mut config, fasta_map)?;
+    let filled_reference = fill_reference_gaps(&config, fasta_map, fasta_order, rng);
+    let fasta_map = filled_reference.as_ref().map_or(fasta_map, |(filled_map, _)| filled_map);
+    let warnings = run_warnings(&config, fasta_map, fasta_order)?;
+
+    // Everything is staged in the temp dir and only moved into the output dir once all of it has
+    // been written, so make sure there is room in both places before doing any work.
+    let temp_parent = config.temp_dir.clone().unwrap_or_else(|| config.output_dir.clone());
+    let reference_length: u64 = fasta_map.values().map(|sequence| sequence.len() as u64).sum();
+    let targets = load_targets(&config, fasta_map)?;
+    let covered_length = match &targets {
+        Some(targets) => targets
+            .values()
+            .flatten()
+            .map(|(start, end)| (end - start) as u64)
+            .sum(),
+        None => reference_length,
+    };
+    let required = estimate_output_size(&config, reference_length, covered_length);
+    check_free_space(&temp_parent, required)?;
+    if temp_parent != config.output_dir {
+        check_free_space(&config.output_dir, required)?;
+    }
+    let outputs = StagedOutputs::new(&temp_parent, config.overwrite_output)?
+        .throttled(config.throttle_mb_per_sec.map(Throttle::new));
 
     // Load models that will be used for the runs.
-    // For now we will use the one supplied, pulled directly from NEAT2.0's original model.
-    let default_quality_score_model_file = "models/neat_quality_score_model.json";
-    let quality_score_model = read_quality_score_model_json(
-        default_quality_score_model_file
-    );
+    // Without a model bundle, we use the one supplied, pulled directly from NEAT2.0's original
+    // model.
+    let quality_score_model =
+        models.quality.take().unwrap_or_else(QualityScoreModel::default_model);
+    let germline_model = models.mutation.clone().unwrap_or_else(NucModel::new);
 
     // Mutating the reference and recording the variant locations.
-    info!("Mutating reference.");
-    let (mutated_map, variant_locations) = mutate_fasta(
-        &fasta_map,
-        config.minimum_mutations,
-        &mut rng
-    );
+    let annotations = load_annotations(&config)?;
+    let ploidy_map = load_ploidy_map(&config, fasta_map)?;
+    let coverage_profile = load_coverage_profile(&config, fasta_map, coverage_bam.as_ref())?;
+    let signatures = load_signatures(&config)?;
+    let known_sites = load_known_sites(&config, fasta_map)?;
+    let liftover = load_liftover_chain(&config)?;
+    let MutatedReference {
+        sequences: mutated_map,
+        variants: mut variant_locations,
+        genotype_counts,
+    } = match config.sample_haplotypes.is_empty() {
+        true => mutate_reference(
+            &config,
+            fasta_map,
+            fasta_order,
+            annotations.as_ref(),
+            &ploidy_map,
+            signatures.as_ref(),
+            known_sites.as_ref(),
+            &germline_model,
+            rng,
+        ),
+        false => load_sample_haplotypes(&config, fasta_map, fasta_order, annotations.as_ref())?,
+    };
+
+    // The reads for each contig come from their own rng, seeded here so that they don't depend
+    // on what else the run's rng is used for.
+    let mut seeds = contig_seeds(fasta_order, rng);
+    let only_contig = select_contig(&config, fasta_order, &mut seeds)?;
+    // Runs of only one contig simulate everything as a full run does, but only write its output.
+    let output_order: Vec<String> = match only_contig {
+        Some(index) => vec![fasta_order[index].clone()],
+        None => fasta_order.to_vec(),
+    };
+    let capture_model = load_capture_model(&config, fasta_map, fasta_order, &seeds)?;
+    let low_complexity = load_low_complexity(&config, fasta_map)?;
+    let error_profile = load_error_profile(&config, &models)?;
+    let ffpe = ffpe_artifacts(&config, fasta_map, fasta_order, &seeds);
+
+    let mut files = RunFiles {
+        config: &config,
+        outputs,
+        provenance: Provenance::new(&config),
+        output_order,
+        run_output: RunOutput::default(),
+    };
+    write_sequence_files(
+        &mut files,
+        fasta_map,
+        &mutated_map,
+        &variant_locations,
+        &ploidy_map,
+        filled_reference.as_ref(),
+    )?;
+
+    let mut shard_stats: Vec<ShardStats> = Vec::new();
+    if config.produce_fastq || config.produce_bam {
+        // Each contig is covered `coverage` number of times, by reads shuffled within the contig
+        // and written to a shard. The shards are then joined in reference order, or shuffled
+        // together for a global shuffle.
+        info!("Generating reads with {} thread(s)", config.threads);
+        // Reads come from copies of the genome with only the variants on that copy, and for runs
+        // with a tumor fraction some come from normal cells, which only have the germline ones.
+        let ploidy = ploidy_map.max_copies();
+        let haplotypes = haplotype_sequences(fasta_map, &variant_locations, ploidy, true);
+        let germline_haplotypes = config.tumor_fraction.map(|_| {
+            haplotype_sequences(fasta_map, &variant_locations, ploidy, false)
+        });
+        let heteroplasmy = heteroplasmic_sites(&variant_locations);
+        let sequences = ContigSequences {
+            reference: fasta_map,
+            haplotypes: &haplotypes,
+            order: fasta_order,
+            ploidy_map: &ploidy_map,
+            heteroplasmy: Some(&heteroplasmy).filter(|sites| !sites.is_empty()),
+            germline: germline_haplotypes.as_deref(),
+            targets: targets.as_ref(),
+            coverage_profile: coverage_profile.as_ref(),
+            capture_model: capture_model.as_ref(),
+            low_complexity: low_complexity.as_ref(),
+            error_profile: error_profile.as_ref(),
+            ffpe: ffpe.as_ref(),
+            fragment_lengths: coverage_bam
+                .as_ref()
+                .and_then(|bam_profile| bam_profile.fragment_lengths.as_ref()),
+            variants: config.genotype_calls.then_some(&*variant_locations),
+        };
+        let mut shards =
+            write_reads(&mut files, &sequences, &seeds, &quality_score_model, rng)?;
+        // The truth vcf's calls come from the reads just written.
+        for shard in shards.iter_mut() {
+            let Some(site_counts) = shard.site_counts.take() else { continue };
+            for (contig_index, counts) in shard.contigs.clone().zip(site_counts) {
+                if let Some(variants) = variant_locations.get_mut(&fasta_order[contig_index]) {
+                    call_sites(variants, &counts);
+                }
+            }
+        }
+        shard_stats = shards.into_iter().flat_map(|shard| shard.stats).collect();
+    }
 
+    if config.produce_vcf || config.produce_benchmark_files {
+        write_truth_files(
+            &mut files,
+            fasta_map,
+            &mutated_map,
+            &variant_locations,
+            liftover.as_ref(),
+            ffpe.as_ref(),
+            targets.as_ref(),
+        )?;
+    }
+
+    let mut summary = run_summary(&shard_stats, &variant_locations, Some(genotype_counts));
+    summary.warnings = warnings;
+    write_summary(&mut files, summary)?;
+
+    let RunFiles { outputs, mut run_output, .. } = files;
+    outputs.commit()?;
+    info!("Processing complete");
+    run_output.variants = *variant_locations;
+    Ok(run_output)
+}
+
+fn write_sequence_files(
+    files: &mut RunFiles,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    mutated_map: &HashMap<String, Vec<u8>>,
+    variant_locations: &HashMap<String, Vec<Variant>>,
+    ploidy_map: &PloidyMap,
+    filled_reference: Option<&(HashMap<String, Vec<u8>>, FilledGaps)>,
+) -> Result<(), NeatError> {
+    // Writes the sequences of the run that don't need reads: the mutated fasta, its edits to the
+    // reference, the filled reference and a fasta per copy of the genome, each if asked for.
+    let config = files.config;
     if config.produce_fasta {
         info!("Outputting fasta file");
+        let fasta_filename = files.path(".fasta");
+        let mut fasta_writer = files.outputs.create(&fasta_filename)?;
         write_fasta(
-            &mutated_map,
-            &fasta_order,
-            config.overwrite_output,
-            &output_file,
-        ).unwrap();
+            &mut fasta_writer,
+            mutated_map,
+            &files.output_order,
+            Some(&files.provenance.fasta_comment()),
+        )?;
+        fasta_writer.flush()?;
+        files.run_output.fasta_file = Some(fasta_filename);
     }
 
-    if config.produce_vcf {
-        info!("Writing vcf file");
-        write_vcf(
-            &variant_locations,
-            &fasta_order,
-            config.ploidy,
-            &config.reference,
-            config.overwrite_output,
-            &output_file,
-            &mut rng
-        ).unwrap();
+    if config.produce_edit_list {
+        info!("Outputting the edits to the reference");
+        let edit_list_filename = files.path(".edits");
+        let mut edit_list_writer = files.outputs.create(&edit_list_filename)?;
+        let edits =
+            write_edit_list(&mut edit_list_writer, fasta_map, mutated_map, &files.output_order)?;
+        edit_list_writer.flush()?;
+        info!("Wrote {} edits", edits);
+        files.run_output.edit_list_file = Some(edit_list_filename);
     }
 
-    let mut read_sets: HashSet<Vec<u8>> = HashSet::new();
-    for (_name, sequence) in mutated_map.iter() {
-        // defined as a set of read sequences that should cover
-        // the mutated sequence `coverage` number of times
-        let data_set = generate_reads(
-            sequence,
-            &config.read_len,
-            &config.coverage,
-            config.paired_ended,
-            config.fragment_mean,
-            config.fragment_st_dev,
-            &mut rng
-        ).unwrap();
+    if let Some((filled_map, filled_gaps)) = filled_reference {
+        // The reads and variants are placed on the filled reference, so it is written alongside
+        // the bed of what was filled, to align the reads to.
+        info!("Outputting the filled gaps and the reference with them filled");
+        let bed_filename = files.path("_filled_gaps.bed");
+        let mut bed_writer = files.outputs.create(&bed_filename)?;
+        write_filled_gaps(&mut bed_writer, filled_gaps, &files.output_order)?;
+        bed_writer.flush()?;
+        let reference_filename = files.path("_filled_reference.fasta");
+        let mut reference_writer = files.outputs.create(&reference_filename)?;
+        write_fasta(&mut reference_writer, filled_map, &files.output_order, None)?;
+        reference_writer.flush()?;
+        files.run_output.filled_gap_files = vec![bed_filename, reference_filename];
+    }
 
-        read_sets.extend(*data_set);
+    if config.haplotype_fasta {
+        // Each copy gets the variants on it. Contigs (or parts of them, like the non-PAR X of a
+        // male) with fewer copies than this one are left out (or masked with N).
+        info!("Outputting a fasta file per copy of the genome");
+        let haplotypes =
+            haplotype_sequences(fasta_map, variant_locations, ploidy_map.max_copies(), true);
+        for (copy, mut haplotype) in haplotypes.into_iter().enumerate() {
+            let mut order: Vec<String> = Vec::new();
+            for contig in &files.output_order {
+                let sequence = haplotype.get_mut(contig).unwrap();
+                let spans = ploidy_map.copy_spans(contig, sequence.len());
+                if spans.iter().all(|(_, _, copies)| *copies <= copy) {
+                    continue
+                }
+                for (start, end, _) in spans.iter().filter(|(_, _, copies)| *copies <= copy) {
+                    sequence[*start..*end].fill(b'N');
+                }
+                order.push(contig.clone());
+            }
+            let fasta_filename = files.path(&format!("_hap{}.fasta", copy));
+            let mut fasta_writer = files.outputs.create(&fasta_filename)?;
+            let comment = files.provenance.fasta_comment();
+            write_fasta(&mut fasta_writer, &haplotype, &order, Some(&comment))?;
+            fasta_writer.flush()?;
+            files.run_output.haplotype_fasta_files.push(fasta_filename);
+        }
     }
+    Ok(())
+}
+
+fn write_reads(
+    files: &mut RunFiles,
+    sequences: &ContigSequences,
+    seeds: &[Vec<String>],
+    quality_score_model: &QualityScoreModel,
+    rng: &mut Rng,
+) -> Result<Vec<ReadShard>, NeatError> {
+    // Generates the reads into shards, then joins the shards into the fastq, bam, k-mer spectrum
+    // and pileup the run asks for.
+    // Returns: The shards, for the counts and stats they hold.
+    let config = files.config;
+    let mut shards = write_read_shards(
+        config,
+        sequences,
+        seeds,
+        quality_score_model,
+        files.outputs.scratch_dir(),
+        files.outputs.throttle(),
+    )?;
+    let num_templates: usize = shards.iter().map(|shard| shard.num_templates).sum();
+    if num_templates == 0 {
+        return Err(NeatError::Simulation("No reads generated".to_string()))
+    }
+    files.run_output.num_reads = num_templates;
 
     if config.produce_fastq {
-        info!("Shuffling output fastq data");
-        let outsets: Box<Vec<&Vec<u8>>> = Box::new(read_sets.iter().collect());
-        let mut outsets_order: Vec<usize> = (0..outsets.len()).collect();
-        rng.shuffle_in_place(&mut outsets_order);
-
-        info!("Writing fastq");
-        write_fastq(
-            &output_file,
-            config.overwrite_output,
-            config.paired_ended,
-            *outsets,
-            outsets_order,
-            quality_score_model,
+        write_fastq(files, &shards, sequences, rng)?;
+    }
+
+    if config.produce_bam {
+        info!("Writing bam");
+        let bam_filename = files.path(".bam");
+        let mut bam_writer = files.outputs.create(&bam_filename)?;
+        let contig_lengths: Vec<usize> = sequences.order
+            .iter()
+            .map(|contig| sequences.reference[contig].len())
+            .collect();
+        merge_bam_shards(
+            &shards,
+            sequences.order,
+            &contig_lengths,
+            &config.read_group(),
+            &files.provenance,
+            config.sort_reads,
+            &mut bam_writer,
+        )?;
+        bam_writer.flush()?;
+        files.run_output.bam_file = Some(bam_filename);
+    }
+    if let Some(k) = config.kmer_spectrum {
+        let mut kmers = KmerCounts::new(k);
+        for shard in shards.iter_mut() {
+            kmers.merge(shard.kmers.take().expect("Shard written without k-mer counts"));
+        }
+        let histogram_filename = files.path("_kmer_histo.txt");
+        let mut histogram_writer = files.outputs.create(&histogram_filename)?;
+        kmers.write_histogram(&mut histogram_writer)?;
+        histogram_writer.flush()?;
+        info!("Counted {} distinct {}-mers in the reads", kmers.counts.len(), k);
+        files.run_output.kmer_histogram_file = Some(histogram_filename);
+    }
+    if config.truth_pileup {
+        let depth_filename = files.path("_depth.bedgraph");
+        let alleles_filename = files.path("_alleles.tsv");
+        let mut depth_writer = files.outputs.create(&depth_filename)?;
+        let mut allele_writer = files.outputs.create(&alleles_filename)?;
+        writeln!(allele_writer, "{}", ALLELE_COUNTS_HEADER)?;
+        merge_pileup_shards(&shards, &mut depth_writer, &mut allele_writer)?;
+        depth_writer.flush()?;
+        allele_writer.flush()?;
+        info!("Wrote truth pileup");
+        files.run_output.pileup_files = vec![depth_filename, alleles_filename];
+    }
+    Ok(shards)
+}
+
+fn write_fastq(
+    files: &mut RunFiles,
+    shards: &[ReadShard],
+    sequences: &ContigSequences,
+    rng: &mut Rng,
+) -> Result<(), NeatError> {
+    // Joins the shards into the fastq files (and index reads), in reference order or shuffled
+    // together, and writes the manifest of them.
+    info!("Writing fastq");
+    let config = files.config;
+    let bucket_templates = shuffle_bucket_templates(config, sequences, SHUFFLE_BUCKET_TEMPLATES);
+    let r1_filename = files.path("_r1.fastq");
+    let mut r1_writer = files.outputs.create(&r1_filename)?;
+    files.run_output.fastq_files.push(r1_filename);
+    let mut index_writers = Vec::new();
+    for read in 1..=shards[0].index.len() {
+        let index_filename = files.path(&format!("_I{}.fastq", read));
+        index_writers.push(files.outputs.create(&index_filename)?);
+        files.run_output.index_fastq_files.push(index_filename);
+    }
+    let mut index_outputs: Vec<&mut dyn Write> = index_writers
+        .iter_mut()
+        .map(|writer| writer as &mut dyn Write)
+        .collect();
+    if config.paired_ended {
+        let r2_filename = files.path("_r2.fastq");
+        let mut r2_writer = files.outputs.create(&r2_filename)?;
+        files.run_output.fastq_files.push(r2_filename);
+        files.run_output.fastq_records = if config.global_shuffle {
+            shuffle_merge_shards(
+                shards,
+                files.outputs.scratch_dir(),
+                files.outputs.throttle(),
+                bucket_templates,
+                rng,
+                &mut r1_writer,
+                &mut r2_writer,
+                &mut index_outputs,
+            )?
+        } else {
+            merge_index_shards(shards, &mut index_outputs)?;
+            merge_shards(shards, &mut r1_writer, &mut r2_writer)?
+        };
+        r2_writer.flush()?;
+        if config.singleton_rate.is_some() {
+            let singletons_filename = files.path("_singletons.fastq");
+            let mut singletons_writer = files.outputs.create(&singletons_filename)?;
+            files.run_output.fastq_files.push(singletons_filename);
+            merge_singleton_shards(shards, &mut singletons_writer)?;
+            singletons_writer.flush()?;
+        }
+    } else if config.global_shuffle {
+        files.run_output.fastq_records = shuffle_merge_shards(
+            shards,
+            files.outputs.scratch_dir(),
+            files.outputs.throttle(),
+            bucket_templates,
             rng,
-        ).unwrap();
-        info!("Processing complete")
+            &mut r1_writer,
+            &mut io::sink(),
+            &mut index_outputs,
+        )?;
+    } else {
+        merge_index_shards(shards, &mut index_outputs)?;
+        files.run_output.fastq_records = merge_shards(shards, &mut r1_writer, &mut io::sink())?;
+    }
+    r1_writer.flush()?;
+    drop(index_outputs);
+    index_writers.iter_mut().try_for_each(|writer| writer.flush())?;
+    let fastq_records = &files.run_output.fastq_records;
+    if !index_writers.is_empty() {
+        let hopped: usize = shards.iter().map(|shard| shard.index_hopped).sum();
+        info!(
+            "Wrote index reads; {} of {} templates read another library's index",
+            hopped, fastq_records.r1
+        );
+    }
+    info!(
+        "Wrote {} read 1 and {} read 2 fastq records", fastq_records.r1, fastq_records.r2
+    );
+    let manifest_filename = files.path("_manifest.json");
+    let mut manifest_writer = files.outputs.create(&manifest_filename)?;
+    files.provenance.write_manifest(
+        &mut manifest_writer,
+        &config.reference,
+        &files.run_output.fastq_files,
+        &files.run_output.fastq_records,
+        config.paired_ended,
+    )?;
+    manifest_writer.flush()?;
+    files.run_output.manifest_file = Some(manifest_filename);
+    Ok(())
+}
+
+fn write_truth_files(
+    files: &mut RunFiles,
+    fasta_map: &HashMap<String, Vec<u8>>,
+    mutated_map: &HashMap<String, Vec<u8>>,
+    variant_locations: &HashMap<String, Vec<Variant>>,
+    liftover: Option<&ChainMap>,
+    ffpe: Option<&FfpeArtifacts>,
+    targets: Option<&Regions>,
+) -> Result<(), NeatError> {
+    // Writes the truth vcf, and what is made from it: the lifted vcf, the FFPE artifact vcf and
+    // the benchmark files. The vcf output and the benchmark files hold the same vcf, so it is
+    // written once, to memory. When the truth files are bgzipped, they share one bgzipped copy.
+    let config = files.config;
+    let mut vcf: Vec<u8> = Vec::new();
+    write_vcf(
+        &mut vcf, variant_locations, &files.output_order, &config.reference, &files.provenance
+    )?;
+    if config.validate_truth {
+        check_truth_vcf(&vcf, fasta_map, mutated_map, TRUTH_CHECK_RECORDS)?;
+    }
+    let indexed_vcf = if config.produce_benchmark_files || config.bgzip_truth_files {
+        let gz_filename = files.path(".vcf.gz");
+        let index_filename = files.path(".vcf.gz.tbi");
+        write_indexed_file(
+            &mut files.outputs, &vcf, TabixFormat::Vcf, &gz_filename, &index_filename
+        )?;
+        Some((gz_filename, index_filename))
+    } else {
+        None
+    };
+    if config.produce_vcf {
+        info!("Writing vcf file");
+        match (&indexed_vcf, config.bgzip_truth_files) {
+            (Some((gz_filename, index_filename)), true) => {
+                files.run_output.vcf_file = Some(gz_filename.clone());
+                files.run_output.vcf_index_file = Some(index_filename.clone());
+            },
+            _ => {
+                let vcf_filename = files.path(".vcf");
+                let mut vcf_writer = files.outputs.create(&vcf_filename)?;
+                vcf_writer.write_all(&vcf)?;
+                vcf_writer.flush()?;
+                files.run_output.vcf_file = Some(vcf_filename);
+            },
+        }
+    }
+    if let (Some(chain_map), true) = (liftover, config.produce_vcf) {
+        info!("Writing the truth vcf lifted over to the other assembly");
+        let (lifted, lifted_order, unlifted) =
+            chain_map.lift_variants(variant_locations, &files.output_order);
+        if unlifted > 0 {
+            warn!("{} variants didn't lift over and are left out of the lifted vcf", unlifted);
+        }
+        let lifted_filename = files.path("_lifted.vcf");
+        let mut lifted_writer = files.outputs.create(&lifted_filename)?;
+        let lifted_reference = format!(
+            "{} lifted over by {}",
+            config.reference, config.liftover_chain.as_deref().unwrap_or_default()
+        );
+        write_vcf(
+            &mut lifted_writer, &lifted, &lifted_order, &lifted_reference, &files.provenance
+        )?;
+        lifted_writer.flush()?;
+        files.run_output.lifted_vcf_file = Some(lifted_filename);
+    }
+    if let (Some(artifacts), true) = (ffpe, config.produce_vcf) {
+        info!("Writing FFPE artifact vcf");
+        let artifact_filename = files.path("_ffpe_artifacts.vcf");
+        let mut artifact_writer = files.outputs.create(&artifact_filename)?;
+        write_artifact_vcf(
+            &mut artifact_writer,
+            artifacts,
+            fasta_map,
+            &files.output_order,
+            &config.reference,
+            &files.provenance,
+        )?;
+        artifact_writer.flush()?;
+        files.run_output.artifact_vcf_file = Some(artifact_filename);
+    }
+    if config.produce_benchmark_files {
+        info!("Writing benchmark files");
+        let (gz_filename, index_filename) = indexed_vcf.unwrap();
+        let mut bed: Vec<u8> = Vec::new();
+        write_confident_regions(
+            &mut bed, fasta_map, &files.output_order, config.read_len, targets
+        )?;
+        files.run_output.benchmark_files = vec![gz_filename, index_filename];
+        if config.bgzip_truth_files {
+            let bed_filename = files.path("_confident.bed.gz");
+            let bed_index_filename = files.path("_confident.bed.gz.tbi");
+            write_indexed_file(
+                &mut files.outputs, &bed, TabixFormat::Bed, &bed_filename, &bed_index_filename
+            )?;
+            files.run_output.benchmark_files.extend([bed_filename, bed_index_filename]);
+        } else {
+            let bed_filename = files.path("_confident.bed");
+            let mut bed_writer = files.outputs.create(&bed_filename)?;
+            bed_writer.write_all(&bed)?;
+            bed_writer.flush()?;
+            files.run_output.benchmark_files.push(bed_filename);
+        }
     }
     Ok(())
 }
 
+fn write_summary(files: &mut RunFiles, summary: RunSummary) -> Result<(), NeatError> {
+    // Logs the run's summary, and writes it as json and as a MultiQC custom content file.
+    summary.log_table();
+    let summary_filename = files.path("_summary.json");
+    let mut summary_writer = files.outputs.create(&summary_filename)?;
+    summary.write_json(&mut summary_writer)?;
+    summary_writer.flush()?;
+    files.run_output.summary_file = Some(summary_filename);
+    let multiqc_filename = files.path("_mqc.json");
+    let mut multiqc_writer = files.outputs.create(&multiqc_filename)?;
+    summary.write_multiqc(&mut multiqc_writer, &files.config.read_group().sample)?;
+    multiqc_writer.flush()?;
+    files.run_output.multiqc_file = Some(multiqc_filename);
+    files.run_output.summary = Some(summary);
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
+    use std::io::Read;
     use std::path::PathBuf;
-    use super::super::config::ConfigBuilder;
+    use flate2::read::MultiGzDecoder;
+    use super::super::config::{ConfigBuilder, SweepPoint};
+    use super::super::edit_list::{apply_edit_list, read_edit_list};
+    use super::super::fasta_tools::read_fasta;
+    use super::super::fastq_tools::{complement, ReadNameFormat};
+    use super::super::index_reads::SampleIndex;
+    use super::super::nucleotides::{encode_bases, u8_to_base};
 
     #[test]
     fn test_runner() {
@@ -142,4 +1607,1306 @@ mod tests {
         ).unwrap();
         fs::remove_dir_all("output").unwrap();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_estimate_output_size() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.produce_fastq = false;
+        config.produce_fasta = true;
+        let config = config.build();
+        assert_eq!(estimate_output_size(&config, 700, 700), 711);
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        let config = config.build();
+        // 10x coverage of 1500 bases in 150 bp reads: 15000 bases, 101 reads
+        assert_eq!(estimate_output_size(&config, 1500, 1500), 30000 + 101 * FASTQ_RECORD_OVERHEAD);
+    }
+
+    #[test]
+    fn test_runner_temp_dir() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.produce_vcf = true;
+        config.output_dir = PathBuf::from("runner_temp_out");
+        config.temp_dir = Some(PathBuf::from("runner_temp_scratch"));
+        config.check_and_print_config().unwrap();
+        let config = config.build();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "World".to_string()]);
+        let output = run_neat(Box::new(config), &mut rng).unwrap();
+        assert!(output.vcf_file.unwrap().starts_with("runner_temp_out"));
+        assert!(output.fastq_files[0].is_file());
+        // The staging directory is cleaned up once the files are in place.
+        assert_eq!(fs::read_dir("runner_temp_scratch").unwrap().count(), 0);
+        fs::remove_dir_all("runner_temp_out").unwrap();
+        fs::remove_dir_all("runner_temp_scratch").unwrap();
+    }
+
+    #[test]
+    fn test_tumor_normal_share_germline() {
+        // A normal run and a tumor run with the same seed get the same germline variants.
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        let normal = config.build();
+        let mut tumor = normal.clone();
+        tumor.somatic_mutation_rate = Some(0.01);
+        let (fasta_map, fasta_order) = read_fasta(&normal.reference).unwrap();
+        let seed = vec!["Tumor".to_string(), "Normal".to_string()];
+        let normal_variants = mutate_reference(
+            &normal, &fasta_map, &fasta_order, None, &PloidyMap::uniform(2), None, None,
+            &NucModel::new(), &mut Rng::new_from_seed(seed.clone())
+        ).variants;
+        let tumor_variants = mutate_reference(
+            &tumor, &fasta_map, &fasta_order, None, &PloidyMap::uniform(2), None, None,
+            &NucModel::new(), &mut Rng::new_from_seed(seed)
+        ).variants;
+        for (contig, variants) in normal_variants.iter() {
+            let mut germline: Vec<&Variant> = tumor_variants[contig]
+                .iter()
+                .filter(|variant| !variant.is_somatic())
+                .collect();
+            let mut expected: Vec<&Variant> = variants.iter().collect();
+            germline.sort_by_key(|variant| variant.position);
+            expected.sort_by_key(|variant| variant.position);
+            assert_eq!(germline, expected);
+        }
+    }
+
+    #[test]
+    fn test_truth_independent_of_threads() {
+        // The truth vcf and mutated fasta of the bundled references come out byte for byte the
+        // same however many threads the run uses.
+        for (reference, somatic_rate) in [("H1N1.fa", None), ("ecoli_50k.fa", Some(0.0005))] {
+            let mut outputs: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+            for threads in 1..=4 {
+                let mut config = ConfigBuilder::new();
+                config.reference = Some(format!("test_data/{}", reference));
+                config.mutation_rate = 0.01;
+                config.somatic_mutation_rate = somatic_rate;
+                config.produce_fastq = false;
+                config.produce_fasta = true;
+                config.produce_vcf = true;
+                config.threads = threads;
+                config.output_dir = PathBuf::from(format!("runner_threads_out_{}", threads));
+                config.check_and_print_config().unwrap();
+                let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Threads".to_string()]);
+                let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+                outputs.push((
+                    fs::read(output.vcf_file.unwrap()).unwrap(),
+                    fs::read(output.fasta_file.unwrap()).unwrap(),
+                ));
+                fs::remove_dir_all(format!("runner_threads_out_{}", threads)).unwrap();
+            }
+            assert!(outputs[0].0.len() > 1000);
+            assert!(outputs.iter().all(|output| *output == outputs[0]), "{}", reference);
+        }
+    }
+
+    #[test]
+    fn test_haplotype_fasta() {
+        // A triploid sample with a tetrasomy of one contig gets four copy fastas, the last with
+        // only that contig, each carrying the variants on its copy.
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.mutation_rate = 0.01;
+        config.ploidy = 3;
+        config.contig_ploidy = vec![("H1N1_NS".to_string(), 4)];
+        config.produce_fastq = false;
+        config.haplotype_fasta = true;
+        config.output_dir = PathBuf::from("runner_haplotype_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Haplotypes".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        assert_eq!(output.haplotype_fasta_files.len(), 4);
+        let (reference, _) = read_fasta("test_data/H1N1.fa").unwrap();
+        for (copy, fasta_file) in output.haplotype_fasta_files.iter().enumerate() {
+            let (haplotype, order) = read_fasta(&fasta_file.display().to_string()).unwrap();
+            let contigs: Vec<&str> = order
+                .iter()
+                .map(|contig| contig.split_whitespace().next().unwrap())
+                .collect();
+            if copy == 3 {
+                assert_eq!(contigs, vec!["H1N1_NS"]);
+            } else {
+                assert_eq!(contigs.len(), 8);
+            }
+            for (contig, name) in order.iter().zip(contigs) {
+                let variants = &output.variants[name];
+                assert_eq!(variants[0].genotype.len(), if name == "H1N1_NS" { 4 } else { 3 });
+                for variant in variants {
+                    let expected = match variant.genotype[copy] {
+                        1 => variant.alt,
+                        _ => reference[name][variant.position],
+                    };
+                    assert_eq!(haplotype[contig][variant.position], expected);
+                }
+            }
+        }
+        fs::remove_dir_all("runner_haplotype_out").unwrap();
+    }
+
+    #[test]
+    fn test_mutations_preset() {
+        // Only the mutated fasta and its edits, which turn the reference into it
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.apply_preset("mutations").unwrap();
+        config.mutation_rate = 0.01;
+        config.output_dir = PathBuf::from("runner_mutations_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Edits".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        assert!(output.fastq_files.is_empty() && output.bam_file.is_none());
+        assert_eq!(output.num_reads, 0);
+        let edit_list = fs::read(output.edit_list_file.unwrap()).unwrap();
+        let contigs = read_edit_list(&mut edit_list.as_slice()).unwrap();
+        let num_edits: usize = contigs.iter().map(|(_, _, edits)| edits.len()).sum();
+        let num_variants: usize = output.variants.values().map(Vec::len).sum();
+        assert!(num_edits > 0);
+        assert_eq!(num_edits, num_variants);
+        let (reference, _) = read_fasta("test_data/H1N1.fa").unwrap();
+        let edited = apply_edit_list(&reference, &contigs).unwrap();
+        let (mutated, order) =
+            read_fasta(&output.fasta_file.unwrap().display().to_string()).unwrap();
+        for contig in &order {
+            let name = contig.split_whitespace().next().unwrap();
+            assert_eq!(edited[name], mutated[contig]);
+        }
+        fs::remove_dir_all("runner_mutations_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_clonal_isolate() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.ploidy = 1;
+        config.snp_distance = Some(25);
+        config.plasmid_copy_number = vec![("H1N1_NS".to_string(), 5.0)];
+        config.coverage = 20;
+        config.produce_vcf = true;
+        config.output_dir = PathBuf::from("runner_clonal_out");
+        config.check_and_print_config().unwrap();
+        let config = config.build();
+        let plasmid_reads = |config: &RunConfiguration| {
+            let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Outbreak".to_string()]);
+            let output = run_neat(Box::new(config.clone()), &mut rng).unwrap();
+            let summary = output.summary.as_ref().unwrap();
+            let plasmid = summary.contigs.iter().find(|contig| contig.contig == "H1N1_NS");
+            (plasmid.unwrap().reads, output)
+        };
+        let (reads, output) = plasmid_reads(&config);
+        // Exactly the distance asked for, every SNP on the one copy
+        let variants: Vec<&Variant> = output.variants.values().flatten().collect();
+        assert_eq!(variants.len(), 25);
+        assert!(variants.iter().all(|variant| variant.genotype == vec![1]));
+        // The plasmid is read about five times deeper than with a single copy
+        let mut single_copy = config.clone();
+        single_copy.plasmid_copy_number.clear();
+        single_copy.overwrite_output = true;
+        let (single_copy_reads, _) = plasmid_reads(&single_copy);
+        fs::remove_dir_all("runner_clonal_out").unwrap();
+        let fold = reads as f64 / single_copy_reads as f64;
+        assert!((3.5..6.5).contains(&fold), "plasmid read {} times deeper", fold);
+    }
+
+    #[test]
+    fn test_runner_quasispecies() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.quasispecies_frequencies = vec![0.6, 0.3, 0.1];
+        config.quasispecies_diversity = 0.005;
+        config.coverage = 100;
+        config.produce_vcf = true;
+        config.truth_pileup = true;
+        config.output_dir = PathBuf::from("runner_quasispecies_out");
+        config.check_and_print_config().unwrap();
+        let config = config.build();
+        assert_eq!(config.ploidy, 3);
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Quasispecies".to_string()]);
+        let output = run_neat(Box::new(config), &mut rng).unwrap();
+        let alleles = fs::read_to_string(&output.pileup_files[1]).unwrap();
+        fs::remove_dir_all("runner_quasispecies_out").unwrap();
+        let counts: HashMap<(String, usize), Vec<usize>> = alleles
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                let position = fields[1].parse::<usize>().unwrap() - 1;
+                let counts = fields[3..].iter().map(|count| count.parse().unwrap()).collect();
+                ((fields[0].to_string(), position), counts)
+            })
+            .collect();
+        // Never on the reference haplotype, and read about as often as its frequency says
+        let (mut checked, mut off) = (0, 0.0);
+        for (contig, variants) in &output.variants {
+            for variant in variants {
+                assert_eq!(variant.genotype.len(), 3);
+                assert_eq!(variant.genotype[0], 0);
+                let frequency = variant.frequency.unwrap();
+                assert!([0.1, 0.3, 0.4, 0.9].iter().any(|share| (frequency - share).abs() < 1e-9));
+                let Some(counts) = counts.get(&(contig.clone(), variant.position)) else {
+                    continue
+                };
+                if counts[0] >= 50 {
+                    let shown = counts[1 + variant.alt as usize] as f64 / counts[0] as f64;
+                    off += (shown - frequency).abs();
+                    checked += 1;
+                }
+            }
+        }
+        assert!(checked > 20, "{} variants checked", checked);
+        let mean_off = off / checked as f64;
+        assert!(mean_off < 0.05, "alt shares off by {} on average", mean_off);
+    }
+
+    #[test]
+    fn test_runner_timepoints() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.timepoints = Some(3);
+        config.timepoint_mutations = 10;
+        config.produce_fastq = false;
+        config.produce_vcf = true;
+        config.rng_seed = Some("Hello Timepoints".to_string());
+        config.output_dir = PathBuf::from("runner_timepoints_out");
+        config.check_and_print_config().unwrap();
+        let outputs = run_timepoints(&config.build()).unwrap();
+        let table = fs::read_to_string("runner_timepoints_out/neat_out_timepoints.tsv").unwrap();
+        assert!(Path::new("runner_timepoints_out/t2/neat_out.vcf").is_file());
+        fs::remove_dir_all("runner_timepoints_out").unwrap();
+        assert_eq!(outputs.len(), 3);
+        let sites = |output: &RunOutput| -> HashMap<(String, usize), Vec<usize>> {
+            output.variants
+                .iter()
+                .flat_map(|(contig, variants)| variants.iter().map(move |variant| {
+                    ((contig.clone(), variant.position), variant.genotype.clone())
+                }))
+                .collect()
+        };
+        // Every clone has arisen by the last timepoint, and each sample keeps the variants of
+        // the one before it, on the same clones
+        let series: Vec<HashMap<(String, usize), Vec<usize>>> = outputs.iter().map(sites).collect();
+        assert_eq!(series[2].len(), 30);
+        for timepoint in 1..3 {
+            assert!(series[timepoint].len() > series[timepoint - 1].len());
+            for (site, genotype) in &series[timepoint - 1] {
+                assert_eq!(&series[timepoint][site], genotype);
+            }
+        }
+        // Only the reference and the first clone are in the first sample
+        for genotype in series[0].values() {
+            assert_eq!(genotype.len(), 4);
+            assert_eq!(&genotype[..2], &[0, 1]);
+        }
+        // The table has a line per variant of the series, giving its frequency in each sample
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], "#contig\tposition\tref\talt\tfirst_timepoint\tt1\tt2\tt3");
+        assert_eq!(lines.len(), 31);
+        for line in &lines[1..] {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let position = fields[1].parse::<usize>().unwrap() - 1;
+            let first = match series[0].contains_key(&(fields[0].to_string(), position)) {
+                true => "t1",
+                false if series[1].contains_key(&(fields[0].to_string(), position)) => "t2",
+                false => "t3",
+            };
+            assert_eq!(fields[4], first);
+            let frequency: f64 = fields[7].parse().unwrap();
+            assert!(frequency > 0.0 && frequency < 1.0);
+        }
+    }
+
+    #[test]
+    fn test_runner_sample_haplotypes() {
+        let (reference, order) = read_fasta("test_data/H1N1.fa").unwrap();
+        let output_dir = PathBuf::from("runner_sample_haplotypes_out");
+        fs::create_dir_all(&output_dir).unwrap();
+        // A het SNV and a hom one on the first contig
+        let contig = &order[0];
+        let flip = |position: usize| (reference[contig][position] + 1) % 4;
+        let mut haplotypes = vec![(*reference).clone(), (*reference).clone()];
+        haplotypes[0].get_mut(contig).unwrap()[100] = flip(100);
+        for haplotype in haplotypes.iter_mut() {
+            haplotype.get_mut(contig).unwrap()[200] = flip(200);
+        }
+        let mut paths = Vec::new();
+        for (copy, haplotype) in haplotypes.iter().enumerate() {
+            let path = output_dir.join(format!("hap{}.fa", copy));
+            write_fasta(&mut fs::File::create(&path).unwrap(), haplotype, &order, None).unwrap();
+            paths.push(path.display().to_string());
+        }
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.sample_haplotypes = paths;
+        config.produce_fastq = false;
+        config.produce_vcf = true;
+        config.haplotype_fasta = true;
+        config.output_dir = output_dir.clone();
+        config.check_and_print_config().unwrap();
+        let config = config.build();
+        assert_eq!(config.ploidy, 2);
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Assembly".to_string()]);
+        let output = run_neat(Box::new(config), &mut rng).unwrap();
+        let sites: Vec<(usize, u8, Vec<usize>)> = output.variants[contig]
+            .iter()
+            .map(|variant| (variant.position, variant.alt, variant.genotype.clone()))
+            .collect();
+        assert_eq!(sites, vec![(100, flip(100), vec![1, 0]), (200, flip(200), vec![1, 1])]);
+        assert_eq!(output.variants.values().map(Vec::len).sum::<usize>(), 2);
+        // The haplotypes the reads come from are the ones given
+        assert_eq!(output.haplotype_fasta_files.len(), 2);
+        for (copy, path) in output.haplotype_fasta_files.iter().enumerate() {
+            let (written, _) = read_fasta(&path.display().to_string()).unwrap();
+            assert_eq!(*written, haplotypes[copy]);
+        }
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_runner_liftover() {
+        let (reference, order) = read_fasta("test_data/H1N1.fa").unwrap();
+        let output_dir = PathBuf::from("runner_liftover_out");
+        fs::create_dir_all(&output_dir).unwrap();
+        // The first contig is 100 bases into old_a, the second is old_b backwards, and the rest
+        // aren't in the other assembly
+        let (first, second) = (&order[0], &order[1]);
+        let (first_length, second_length) = (reference[first].len(), reference[second].len());
+        let chain = output_dir.join("to_old.chain");
+        fs::write(&chain, format!(
+            "chain 100 {} {} + 0 {} old_a {} + 100 {} 1\n{}\n\n\
+            chain 90 {} {} + 0 {} old_b {} - 0 {} 2\n{}\n",
+            first, first_length, first_length, first_length + 100, first_length + 100,
+            first_length, second, second_length, second_length, second_length, second_length,
+            second_length,
+        )).unwrap();
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.mutation_rate = 0.01;
+        config.produce_fastq = false;
+        config.produce_vcf = true;
+        config.liftover_chain = Some(chain.display().to_string());
+        config.output_dir = output_dir.clone();
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Liftover".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        let lifted = fs::read_to_string(output.lifted_vcf_file.as_ref().unwrap()).unwrap();
+        fs::remove_dir_all(&output_dir).unwrap();
+        let records: Vec<Vec<&str>> = lifted
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split('\t').collect())
+            .collect();
+        assert_eq!(records.len(), output.variants[first].len() + output.variants[second].len());
+        for record in records {
+            let position: usize = record[1].parse::<usize>().unwrap() - 1;
+            let reference_base = match record[0] {
+                "old_a" => reference[first][position - 100],
+                "old_b" => complement(reference[second][second_length - 1 - position]),
+                other => panic!("Lifted to {}", other),
+            };
+            assert_eq!(record[3], u8_to_base(reference_base).to_string());
+        }
+    }
+
+    #[test]
+    fn test_runner_benchmark_files() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.produce_fastq = false;
+        config.produce_benchmark_files = true;
+        config.output_dir = PathBuf::from("runner_benchmark_out");
+        config.output_prefix = "truth".to_string();
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Benchmark".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        assert_eq!(output.vcf_file, None);
+        assert_eq!(output.benchmark_files, vec![
+            PathBuf::from("runner_benchmark_out/truth.vcf.gz"),
+            PathBuf::from("runner_benchmark_out/truth.vcf.gz.tbi"),
+            PathBuf::from("runner_benchmark_out/truth_confident.bed"),
+        ]);
+        let bed = fs::read_to_string("runner_benchmark_out/truth_confident.bed").unwrap();
+        assert!(bed.starts_with("H1N1_HA\t0\t1701\n"));
+        fs::remove_dir_all("runner_benchmark_out").unwrap();
+    }
+
+    #[test]
+    fn test_validate_truth() {
+        // The truth vcf of a run with germline and somatic variants matches its sequences.
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/ecoli_50k.fa".to_string());
+        config.mutation_rate = 0.01;
+        config.somatic_mutation_rate = Some(0.001);
+        config.produce_fastq = false;
+        config.produce_vcf = true;
+        config.validate_truth = true;
+        config.output_dir = PathBuf::from("runner_validate_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Validate".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng);
+        fs::remove_dir_all("runner_validate_out").unwrap();
+        assert!(output.unwrap().variants.values().flatten().count() > 400);
+    }
+
+    #[test]
+    fn test_bgzip_truth_files() {
+        // The vcf and the benchmark files share one bgzipped vcf, and the bed gets its own index.
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.produce_fastq = false;
+        config.produce_vcf = true;
+        config.produce_benchmark_files = true;
+        config.bgzip_truth_files = true;
+        config.output_dir = PathBuf::from("runner_bgzip_out");
+        config.output_prefix = "truth".to_string();
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Bgzip".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        assert_eq!(output.vcf_file, Some(PathBuf::from("runner_bgzip_out/truth.vcf.gz")));
+        assert_eq!(
+            output.vcf_index_file,
+            Some(PathBuf::from("runner_bgzip_out/truth.vcf.gz.tbi"))
+        );
+        assert_eq!(output.benchmark_files, vec![
+            PathBuf::from("runner_bgzip_out/truth.vcf.gz"),
+            PathBuf::from("runner_bgzip_out/truth.vcf.gz.tbi"),
+            PathBuf::from("runner_bgzip_out/truth_confident.bed.gz"),
+            PathBuf::from("runner_bgzip_out/truth_confident.bed.gz.tbi"),
+        ]);
+        assert!(!PathBuf::from("runner_bgzip_out/truth.vcf").exists());
+        let mut bed = String::new();
+        MultiGzDecoder::new(fs::File::open(&output.benchmark_files[2]).unwrap())
+            .read_to_string(&mut bed)
+            .unwrap();
+        assert!(bed.starts_with("H1N1_HA\t0\t1701\n"));
+        let mut vcf = String::new();
+        MultiGzDecoder::new(fs::File::open(output.vcf_file.unwrap()).unwrap())
+            .read_to_string(&mut vcf)
+            .unwrap();
+        assert!(vcf.starts_with("##fileformat=VCF"));
+        assert!(output.benchmark_files[3].is_file());
+        fs::remove_dir_all("runner_bgzip_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_summary() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.produce_vcf = true;
+        config.paired_ended = true;
+        config.fragment_mean = Some(300.0);
+        config.fragment_st_dev = Some(30.0);
+        config.output_dir = PathBuf::from("runner_summary_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Summary".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        let summary = output.summary.unwrap();
+        assert_eq!(summary.total_reads, 2 * output.num_reads);
+        assert!(summary.warnings.is_empty());
+        assert_eq!(summary.contigs.len(), 8);
+        assert!(summary.contigs.iter().all(|contig| contig.mean_coverage >= 10.0));
+        let num_variants: usize = output.variants.values().map(Vec::len).sum();
+        assert_eq!(summary.variants.total, num_variants);
+        assert!(summary.variants.heterozygous.is_some());
+        assert!(summary.insert_size.unwrap().mean > 0.0);
+        // H1N1 is a bit under half GC
+        let gc_mean = summary.gc_content.unwrap().mean;
+        assert!(gc_mean > 35.0 && gc_mean < 50.0);
+        let reads: u64 = summary.contigs[0].start_histogram.iter().sum();
+        assert_eq!(reads as usize, summary.contigs[0].reads);
+        let json = fs::read_to_string(output.summary_file.unwrap()).unwrap();
+        assert!(json.contains("\"median_coverage\""));
+        let multiqc = fs::read_to_string(output.multiqc_file.unwrap()).unwrap();
+        assert!(multiqc.contains("\"normal\""));
+        fs::remove_dir_all("runner_summary_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_singletons() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.paired_ended = true;
+        config.fragment_mean = Some(300.0);
+        config.fragment_st_dev = Some(30.0);
+        config.singleton_rate = Some(0.2);
+        config.discarded_pair_rate = Some(0.1);
+        config.produce_bam = true;
+        config.output_dir = PathBuf::from("runner_singletons_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Singletons".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        assert_eq!(output.fastq_files.len(), 3);
+        let r1 = fs::read_to_string(&output.fastq_files[0]).unwrap();
+        let r2 = fs::read_to_string(&output.fastq_files[1]).unwrap();
+        let singletons = fs::read_to_string(&output.fastq_files[2]).unwrap();
+        // r1 and r2 stay in step, and every template left is either a pair or a singleton
+        assert_eq!(r1.lines().count(), r2.lines().count());
+        let fastq_records = PairedRecordCounts {
+            r1: r1.lines().count() / 4,
+            r2: r2.lines().count() / 4,
+        };
+        assert_eq!(output.fastq_records, fastq_records);
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&fs::read(output.manifest_file.unwrap()).unwrap()).unwrap();
+        assert_eq!(manifest["fastq_records"]["r2"], fastq_records.r2);
+        let num_singletons = singletons.lines().count() / 4;
+        assert!(num_singletons > 0);
+        assert_eq!(r1.lines().count() / 4 + num_singletons, output.num_reads);
+        assert_eq!(output.summary.unwrap().singletons, num_singletons);
+        fs::remove_dir_all("runner_singletons_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_index_reads() {
+        for global_shuffle in [false, true] {
+            let mut config = ConfigBuilder::new();
+            config.reference = Some("test_data/H1N1.fa".to_string());
+            config.paired_ended = true;
+            config.fragment_mean = Some(300.0);
+            config.fragment_st_dev = Some(30.0);
+            config.global_shuffle = global_shuffle;
+            config.read_name_format = ReadNameFormat::Illumina;
+            config.sample_index = Some(SampleIndex::parse("AACCGGTT+ACGTACGT").unwrap());
+            config.index_pool = vec![SampleIndex::parse("TTTTTTTT+GGGGGGGG").unwrap()];
+            config.index_hopping_rate = Some(0.1);
+            config.output_dir = PathBuf::from("runner_index_out");
+            config.check_and_print_config().unwrap();
+            let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Index".to_string()]);
+            let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+            assert_eq!(output.index_fastq_files.len(), 2);
+            let r1 = fs::read_to_string(&output.fastq_files[0]).unwrap();
+            assert!(r1.lines().next().unwrap().ends_with(":AACCGGTT+ACGTACGT"));
+            // Every pair has its index reads, in the same order
+            let names = |fastq: &str| -> Vec<String> {
+                fastq.lines().step_by(4).map(|header| {
+                    header.split_whitespace().next().unwrap().to_string()
+                }).collect()
+            };
+            for index_file in &output.index_fastq_files {
+                let index = fs::read_to_string(index_file).unwrap();
+                assert_eq!(names(&index), names(&r1));
+                assert!(index.lines().skip(1).step_by(4).all(|seq| seq.len() == 8));
+            }
+            fs::remove_dir_all("runner_index_out").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_runner_ffpe_artifacts() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.ffpe_artifact_rate = Some(0.001);
+        // Artifacts show by read orientation, so need pairs
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.paired_ended = true;
+        config.fragment_mean = Some(300.0);
+        config.fragment_st_dev = Some(30.0);
+        config.duplex_family_size = Some(2);
+        assert!(config.check_and_print_config().is_err());
+        config.duplex_family_size = None;
+        config.ffpe_artifact_fraction = 0.0;
+        assert!(config.check_and_print_config().is_err());
+        config.ffpe_artifact_fraction = 0.1;
+        config.produce_vcf = true;
+        config.validate_truth = true;
+        config.output_dir = PathBuf::from("runner_ffpe_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "FFPE".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        // The artifacts aren't in the truth vcf, which still matches the sequences
+        let artifact_vcf = fs::read_to_string(output.artifact_vcf_file.unwrap()).unwrap();
+        let records: Vec<&str> =
+            artifact_vcf.lines().filter(|line| !line.starts_with('#')).collect();
+        assert!(!records.is_empty());
+        assert!(records.iter().all(|record| record.contains("\tffpe_artifact\tAF=0.1;")));
+        let truth = fs::read_to_string(output.vcf_file.unwrap()).unwrap();
+        assert!(!truth.contains("ffpe_artifact"));
+        fs::remove_dir_all("runner_ffpe_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_model_dir() {
+        let model_dir = "test_data/test_runner_models";
+        fs::create_dir_all(model_dir).unwrap();
+        // Transitions only, and fragments a paired run would otherwise need settings for
+        fs::write(
+            Path::new(model_dir).join("mutation.json"),
+            "{\"substitutions\": [[0,0,1,0],[0,0,0,1],[1,0,0,0],[0,1,0,0]]}",
+        )
+        .unwrap();
+        fs::write(Path::new(model_dir).join("fragment.json"), "{\"mean\": 300, \"st_dev\": 30}")
+            .unwrap();
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.paired_ended = true;
+        config.mutation_rate = 0.01;
+        config.produce_vcf = true;
+        config.output_dir = PathBuf::from("runner_model_dir_out");
+        assert!(config.check_and_print_config().is_err());
+        config.model_dir = Some(model_dir.to_string());
+        let checked = config.check_and_print_config();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Models".to_string()]);
+        let output = checked.and_then(|_| run_neat(Box::new(config.build()), &mut rng));
+        fs::remove_dir_all(model_dir).unwrap();
+        let output = output.unwrap();
+        assert_eq!(output.fastq_files.len(), 2);
+        let vcf = fs::read_to_string(output.vcf_file.unwrap()).unwrap();
+        let snvs: Vec<(String, String)> = vcf
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (fields[3].to_string(), fields[4].to_string())
+            })
+            .filter(|(reference, alt)| reference.len() == 1 && alt.len() == 1)
+            .collect();
+        assert!(!snvs.is_empty());
+        let transitions = ["AG", "GA", "CT", "TC"];
+        assert!(snvs.iter().all(|(reference, alt)| {
+            transitions.contains(&format!("{}{}", reference, alt).as_str())
+        }));
+        fs::remove_dir_all("runner_model_dir_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_only_contig() {
+        let run = |output_dir: &str, only_contig: Option<&str>, contig_seed: Option<&str>| {
+            let mut config = ConfigBuilder::new();
+            config.reference = Some("test_data/H1N1.fa".to_string());
+            config.truth_comment_tags = true;
+            config.produce_vcf = true;
+            config.output_dir = PathBuf::from(output_dir);
+            config.overwrite_output = true;
+            config.only_contig = only_contig.map(str::to_string);
+            config.contig_seed = contig_seed.map(str::to_string);
+            config.check_and_print_config()?;
+            let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Contig".to_string()]);
+            run_neat(Box::new(config.build()), &mut rng)
+        };
+        let full = run("runner_full_out", None, None).unwrap();
+        let only = run("runner_only_contig_out", Some("H1N1_NP"), None).unwrap();
+        // The contig's reads and variants are the ones it has in the full run
+        let records = |path: &PathBuf, contig: &str| -> Vec<String> {
+            let text = fs::read_to_string(path).unwrap();
+            let lines: Vec<&str> = text.lines().collect();
+            lines
+                .chunks(4)
+                .filter(|record| record[0].contains(&format!("XC:Z:{}", contig)))
+                .map(|record| record.join("\n"))
+                .collect()
+        };
+        let only_reads = records(&only.fastq_files[0], "");
+        assert!(!only_reads.is_empty());
+        assert_eq!(only_reads, records(&full.fastq_files[0], "H1N1_NP"));
+        let variants = |path: &PathBuf| -> Vec<String> {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .filter(|line| line.starts_with("H1N1_NP\t"))
+                .map(str::to_string)
+                .collect()
+        };
+        let only_vcf = fs::read_to_string(only.vcf_file.as_ref().unwrap()).unwrap();
+        assert!(only_vcf.lines().all(|line| line.starts_with('#') || line.starts_with("H1N1_NP")));
+        assert_eq!(
+            variants(only.vcf_file.as_ref().unwrap()), variants(full.vcf_file.as_ref().unwrap())
+        );
+
+        // Another seed gives the contig other reads; contigs must be in the reference, and seeds
+        // only go with them
+        let reseeded = run("runner_only_contig_out", Some("H1N1_NP"), Some("1 2")).unwrap();
+        assert_ne!(records(&reseeded.fastq_files[0], ""), only_reads);
+        let missing = run("runner_only_contig_out", Some("chr1"), None);
+        assert!(matches!(missing, Err(NeatError::Config(_))));
+        assert!(run("runner_only_contig_out", None, Some("1 2")).is_err());
+        fs::remove_dir_all("runner_full_out").unwrap();
+        fs::remove_dir_all("runner_only_contig_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_sorted_reads() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.sort_reads = true;
+        config.truth_comment_tags = true;
+        config.produce_bam = true;
+        config.output_dir = PathBuf::from("runner_sorted_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Sorted".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        // The truth tags give each read's contig and position: contigs in reference order, and
+        // positions in order within each
+        let (_, fasta_order) = read_fasta("test_data/H1N1.fa").unwrap();
+        let r1 = fs::read_to_string(&output.fastq_files[0]).unwrap();
+        let positions: Vec<(usize, usize)> = r1
+            .lines()
+            .step_by(4)
+            .map(|header| {
+                let tags: Vec<&str> = header.split('\t').collect();
+                let contig = tags[1].strip_prefix("XC:Z:").unwrap();
+                let position = tags[2].strip_prefix("XP:i:").unwrap().parse().unwrap();
+                (fasta_order.iter().position(|name| name == contig).unwrap(), position)
+            })
+            .collect();
+        assert!(positions.len() > 1);
+        assert!(positions.windows(2).all(|pair| pair[0] <= pair[1]));
+        let mut bam = Vec::new();
+        MultiGzDecoder::new(fs::File::open(output.bam_file.unwrap()).unwrap())
+            .read_to_end(&mut bam)
+            .unwrap();
+        assert!(String::from_utf8_lossy(&bam[..100]).contains("SO:coordinate"));
+        fs::remove_dir_all("runner_sorted_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_cell_free_panel() {
+        let bed = "test_data/test_panel.bed";
+        fs::write(bed, "H1N1_PB2\t1000\t1100\n").unwrap();
+        let mut config = ConfigBuilder::new();
+        config.apply_preset("cfdna").unwrap();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.coverage = 100;
+        config.somatic_mutation_rate = Some(0.01);
+        config.tumor_fraction = Some(0.01);
+        config.target_bed = Some(bed.to_string());
+        config.produce_benchmark_files = true;
+        config.output_dir = PathBuf::from("runner_panel_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Panel".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng);
+        fs::remove_file(bed).unwrap();
+        let output = output.unwrap();
+        // Only the padded target is covered, and only it is confident
+        let confident = fs::read_to_string(&output.benchmark_files[2]).unwrap();
+        assert_eq!(confident, "H1N1_PB2\t500\t1600\n");
+        for contig in output.summary.unwrap().contigs {
+            assert_eq!(contig.reads > 0, contig.contig == "H1N1_PB2");
+        }
+        fs::remove_dir_all("runner_panel_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_capture_model() {
+        // Reads tail off past the target edges instead of covering the padding evenly.
+        let bed = "test_data/test_capture.bed";
+        fs::write(bed, "H1N1_PB2\t1000\t1400\n").unwrap();
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.read_len = 100;
+        config.coverage = 50;
+        config.target_bed = Some(bed.to_string());
+        config.capture_model = true;
+        config.capture_shoulder = 200;
+        config.produce_benchmark_files = true;
+        config.output_dir = PathBuf::from("runner_capture_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Capture".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng);
+        fs::remove_file(bed).unwrap();
+        let output = output.unwrap();
+        // The padding makes room for the shoulders
+        let confident = fs::read_to_string(&output.benchmark_files[2]).unwrap();
+        assert_eq!(confident, "H1N1_PB2\t300\t2100\n");
+        let summary = output.summary.unwrap();
+        let contig = summary.contigs.iter().find(|contig| contig.contig == "H1N1_PB2").unwrap();
+        let starts_between = |start: usize, end: usize| -> u64 {
+            contig.start_histogram[start / contig.start_bin_size..end / contig.start_bin_size]
+                .iter()
+                .sum()
+        };
+        // Reads only start where their middle is within a shoulder of the target, and fewer the
+        // further out it is
+        assert_eq!(starts_between(0, 700), 0);
+        assert_eq!(starts_between(1600, contig.length), 0);
+        assert!(starts_between(1000, 1300) > 4 * starts_between(600, 850));
+        fs::remove_dir_all("runner_capture_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_coverage_profile() {
+        // Only the windows of the profile get reads.
+        let bedgraph = "test_data/test_runner_profile.bedgraph";
+        fs::write(bedgraph, "H1N1_HA\t0\t800\t40\nH1N1_NA\t0\t1400\t10\n").unwrap();
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.read_len = 100;
+        config.coverage = 2;
+        config.coverage_profile = Some(bedgraph.to_string());
+        config.output_dir = PathBuf::from("runner_profile_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Profile".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng);
+        fs::remove_file(bedgraph).unwrap();
+        let output = output.unwrap();
+        for contig in output.summary.unwrap().contigs {
+            assert_eq!(contig.reads > 0, ["H1N1_HA", "H1N1_NA"].contains(&contig.contig.as_str()));
+        }
+        fs::remove_dir_all("runner_profile_out").unwrap();
+
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.coverage_profile = Some("test_data/profile.bw".to_string());
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_runner_known_sites() {
+        // With every germline variant at a known site, every vcf record has an ID.
+        let (fasta_map, _) = read_fasta("test_data/H1N1.fa").unwrap();
+        let known_sites_vcf = "test_data/test_runner_known_sites.vcf";
+        let mut records = String::from("##fileformat=VCFv4.2\n");
+        for position in (1..fasta_map["H1N1_HA"].len()).step_by(20) {
+            let reference = fasta_map["H1N1_HA"][position];
+            records.push_str(&format!(
+                "H1N1_HA\t{}\trs{}\t{}\t{}\t.\t.\t.\n",
+                position + 1, position, u8_to_base(reference), u8_to_base((reference + 2) % 4)
+            ));
+        }
+        fs::write(known_sites_vcf, records).unwrap();
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.mutation_rate = 0.01;
+        config.known_sites_vcf = Some(known_sites_vcf.to_string());
+        config.known_site_fraction = 1.0;
+        config.produce_fastq = false;
+        config.produce_vcf = true;
+        config.output_dir = PathBuf::from("runner_known_sites_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Known".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng);
+        fs::remove_file(known_sites_vcf).unwrap();
+        let vcf = fs::read_to_string(output.unwrap().vcf_file.unwrap()).unwrap();
+        let records: Vec<&str> = vcf.lines().filter(|line| line.starts_with("H1N1_HA")).collect();
+        assert!(!records.is_empty());
+        for record in records {
+            let fields: Vec<&str> = record.split('\t').collect();
+            let position: usize = fields[1].parse().unwrap();
+            assert_eq!(fields[2], format!("rs{}", position - 1));
+        }
+        fs::remove_dir_all("runner_known_sites_out").unwrap();
+    }
+
+    #[test]
+    fn test_run_replicates() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.coverage = 1;
+        config.rng_seed = Some("Hello Replicates".to_string());
+        config.output_dir = PathBuf::from("runner_replicates_out");
+        config.check_and_print_config().unwrap();
+        let config = config.build();
+        let configs = replicate_configs(&config, 2);
+        assert_eq!(configs[1].rng_seed.as_deref(), Some("Hello Replicates.rep2"));
+        assert_eq!(configs[1].output_dir, PathBuf::from("runner_replicates_out/rep2"));
+        let outputs = run_replicates(&config, 2).unwrap();
+        assert_eq!(outputs.len(), 2);
+        let first = fs::read(&outputs[0].fastq_files[0]).unwrap();
+        let second = fs::read(&outputs[1].fastq_files[0]).unwrap();
+        assert!(outputs[1].fastq_files[0].starts_with("runner_replicates_out/rep2"));
+        assert_ne!(first, second);
+        // A replicate is the same as a plain run with its seed
+        let mut rng = create_rng(configs[0].rng_seed.as_deref());
+        let mut rerun = configs[0].clone();
+        rerun.output_dir = PathBuf::from("runner_replicates_out");
+        let output = run_neat(Box::new(rerun), &mut rng).unwrap();
+        assert_eq!(fs::read(&output.fastq_files[0]).unwrap(), first);
+        assert!(matches!(run_replicates(&config, 0), Err(NeatError::Config(_))));
+        fs::remove_dir_all("runner_replicates_out").unwrap();
+    }
+
+    #[test]
+    fn test_run_sweep() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.rng_seed = Some("Hello Sweep".to_string());
+        config.threads = 2;
+        let base = config.build();
+        let mut sweep_config = base.clone();
+        sweep_config.sweep = [1, 2]
+            .into_iter()
+            .map(|coverage| {
+                let mut point_config = base.clone();
+                point_config.coverage = coverage;
+                point_config.output_dir = PathBuf::from(format!("runner_sweep_out/coverage-{}", coverage));
+                fs::create_dir_all(&point_config.output_dir).unwrap();
+                SweepPoint { label: format!("coverage-{}", coverage), config: point_config }
+            })
+            .collect();
+        let outputs = run_sweep(&sweep_config).unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert!(outputs[0].fastq_files[0].starts_with("runner_sweep_out/coverage-1"));
+        assert!(outputs[0].num_reads < outputs[1].num_reads);
+        // Each point is the same as a plain run of its configuration
+        let mut point_config = sweep_config.sweep[1].config.clone();
+        point_config.output_dir = PathBuf::from("runner_sweep_out");
+        let mut rng = create_rng(point_config.rng_seed.as_deref());
+        let output = run_neat(Box::new(point_config), &mut rng).unwrap();
+        assert_eq!(
+            fs::read(&output.fastq_files[0]).unwrap(),
+            fs::read(&outputs[1].fastq_files[0]).unwrap()
+        );
+        fs::remove_dir_all("runner_sweep_out").unwrap();
+    }
+
+    #[test]
+    fn test_runner_kmer_spectrum() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.paired_ended = true;
+        config.fragment_mean = Some(300.0);
+        config.fragment_st_dev = Some(30.0);
+        config.output_dir = PathBuf::from("runner_kmer_out");
+        config.kmer_spectrum = Some(33);
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.kmer_spectrum = Some(21);
+        config.produce_fastq = false;
+        config.produce_vcf = true;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.produce_fastq = true;
+        config.produce_vcf = false;
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Kmers".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        // The same spectrum as counting the fastqs afterwards
+        let mut counted = KmerCounts::new(21);
+        for fastq in &output.fastq_files {
+            let text = fs::read_to_string(fastq).unwrap();
+            for sequence in text.lines().skip(1).step_by(4) {
+                let mut bases = Vec::new();
+                encode_bases(sequence.as_bytes(), &mut bases);
+                counted.add_sequence(&bases);
+            }
+        }
+        let mut expected = Vec::new();
+        counted.write_histogram(&mut expected).unwrap();
+        let histogram = fs::read_to_string(output.kmer_histogram_file.unwrap()).unwrap();
+        fs::remove_dir_all("runner_kmer_out").unwrap();
+        assert!(!counted.counts.is_empty());
+        assert_eq!(histogram, String::from_utf8(expected).unwrap());
+    }
+
+    #[test]
+    fn test_runner_truth_pileup() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.paired_ended = true;
+        config.fragment_mean = Some(300.0);
+        config.fragment_st_dev = Some(30.0);
+        config.mutation_rate = 0.01;
+        config.output_dir = PathBuf::from("runner_pileup_out");
+        config.truth_pileup = true;
+        config.produce_fastq = false;
+        config.produce_vcf = true;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.produce_fastq = true;
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Pileup".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        let read_bases: usize = output.fastq_files
+            .iter()
+            .map(|fastq| {
+                let text = fs::read_to_string(fastq).unwrap();
+                text.lines().skip(1).step_by(4).map(str::len).sum::<usize>()
+            })
+            .sum();
+        let depth = fs::read_to_string(&output.pileup_files[0]).unwrap();
+        let alleles = fs::read_to_string(&output.pileup_files[1]).unwrap();
+        fs::remove_dir_all("runner_pileup_out").unwrap();
+        // Every base of every read is counted once
+        let mut covered: HashMap<(String, usize), usize> = HashMap::new();
+        let mut pileup_bases = 0;
+        for line in depth.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let start: usize = fields[1].parse().unwrap();
+            let end: usize = fields[2].parse().unwrap();
+            let depth: usize = fields[3].parse().unwrap();
+            pileup_bases += (end - start) * depth;
+            for position in start..end {
+                covered.insert((fields[0].to_string(), position), depth);
+            }
+        }
+        assert_eq!(pileup_bases, read_bases);
+        // Variants with reads over them show their alt in the table (on about half the reads,
+        // since they are all heterozygous here)
+        let allele_lines: HashMap<(String, usize), Vec<&str>> = alleles
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                ((fields[0].to_string(), fields[1].parse::<usize>().unwrap() - 1), fields)
+            })
+            .collect();
+        assert!(alleles.starts_with(ALLELE_COUNTS_HEADER));
+        let (mut checked, mut shown) = (0, 0);
+        for (contig, variants) in &output.variants {
+            for variant in variants {
+                let key = (contig.clone(), variant.position);
+                if covered.get(&key).is_some_and(|depth| *depth >= 10) {
+                    checked += 1;
+                    shown += allele_lines.get(&key).is_some_and(|fields| {
+                        fields[4 + variant.alt as usize].parse::<usize>().unwrap() > 0
+                    }) as usize;
+                }
+            }
+        }
+        assert!(checked > 0 && shown * 10 >= checked * 9, "{} of {} shown", shown, checked);
+    }
+
+    #[test]
+    fn test_runner_genotype_calls() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.coverage = 20;
+        config.mutation_rate = 0.01;
+        config.output_dir = PathBuf::from("runner_genotype_calls_out");
+        config.genotype_calls = true;
+        config.truth_pileup = true;
+        config.produce_fastq = false;
+        assert!(matches!(config.check_and_print_config(), Err(NeatError::Config(_))));
+        config.produce_fastq = true;
+        config.produce_vcf = true;
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Calls".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        let vcf = fs::read_to_string(output.vcf_file.as_ref().unwrap()).unwrap();
+        let depth = fs::read_to_string(&output.pileup_files[0]).unwrap();
+        let alleles = fs::read_to_string(&output.pileup_files[1]).unwrap();
+        fs::remove_dir_all("runner_genotype_calls_out").unwrap();
+        assert!(vcf.contains("##FORMAT=<ID=AD,") && vcf.contains("\tGT:DP:AD:GQ:PL\t"));
+        // Each call counts the same reads the truth pileup does
+        let mut covered: HashMap<(String, usize), usize> = HashMap::new();
+        for line in depth.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let start: usize = fields[1].parse().unwrap();
+            let end: usize = fields[2].parse().unwrap();
+            for position in start..end {
+                covered.insert((fields[0].to_string(), position), fields[3].parse().unwrap());
+            }
+        }
+        let allele_lines: HashMap<(String, usize), Vec<&str>> = alleles
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                ((fields[0].to_string(), fields[1].parse::<usize>().unwrap() - 1), fields)
+            })
+            .collect();
+        let mut shown = 0;
+        for (contig, variants) in &output.variants {
+            for variant in variants {
+                let call = variant.call.as_ref().unwrap();
+                let key = (contig.clone(), variant.position);
+                assert_eq!(call.depth, covered.get(&key).copied().unwrap_or(0));
+                if let Some(fields) = allele_lines.get(&key) {
+                    let count = |base: u8| fields[4 + base as usize].parse::<usize>().unwrap();
+                    assert_eq!(call.ref_depth, count(variant.reference));
+                    assert_eq!(call.alt_depth, count(variant.alt));
+                    shown += 1;
+                }
+            }
+        }
+        assert!(shown > 0);
+    }
+
+    #[test]
+    fn test_runner_region() {
+        // A copy of the reference with a samtools faidx index, and one without
+        let dir = "test_data/test_region_reference";
+        fs::create_dir_all(dir).unwrap();
+        let indexed = format!("{}/indexed.fa", dir);
+        let plain = format!("{}/plain.fa", dir);
+        let text = fs::read_to_string("test_data/H1N1.fa").unwrap();
+        fs::write(&indexed, &text).unwrap();
+        fs::write(&plain, &text).unwrap();
+        // Name, length and offset of each contig, with 70 bases (71 bytes) a line
+        let mut contigs: Vec<(String, usize, usize)> = Vec::new();
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            offset += line.len();
+            match line.strip_prefix('>') {
+                Some(name) => contigs.push((name.trim_end().to_string(), 0, offset)),
+                None => contigs.last_mut().unwrap().1 += line.trim_end().len(),
+            }
+        }
+        let fai: Vec<String> = contigs
+            .iter()
+            .map(|(name, length, offset)| format!("{}\t{}\t{}\t70\t71", name, length, offset))
+            .collect();
+        fs::write(format!("{}.fai", indexed), fai.join("\n")).unwrap();
+
+        let run = |reference: &str, region: &str, output_dir: &str| {
+            let mut config = ConfigBuilder::new();
+            config.reference = Some(reference.to_string());
+            config.region = Some(region.to_string());
+            config.truth_comment_tags = true;
+            config.produce_vcf = true;
+            config.mutation_rate = 0.01;
+            config.output_dir = PathBuf::from(output_dir);
+            config.overwrite_output = true;
+            config.check_and_print_config()?;
+            let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Region".to_string()]);
+            run_neat(Box::new(config.build()), &mut rng)
+        };
+        let from_index = run(&indexed, "H1N1_NP:301-800", "runner_region_out");
+        let from_fasta = run(&plain, "H1N1_NP:301-800", "runner_region_plain_out");
+        let past_end = run(&indexed, "H1N1_NP:301-8000", "runner_region_bad_out").map(|_| ());
+        fs::remove_dir_all(dir).unwrap();
+        let (from_index, from_fasta) = (from_index.unwrap(), from_fasta.unwrap());
+        // Reads and variants only in the region, at the contig's own coordinates
+        let fastq = fs::read_to_string(&from_index.fastq_files[0]).unwrap();
+        let starts: Vec<usize> = fastq
+            .lines()
+            .step_by(4)
+            .map(|header| header.split("XP:i:").nth(1).unwrap().split('\t').next().unwrap())
+            .map(|start| start.parse().unwrap())
+            .collect();
+        assert!(!starts.is_empty());
+        assert!(starts.iter().all(|start| (301..=800).contains(start)));
+        assert!(fastq.lines().step_by(4).all(|header| header.contains("XC:Z:H1N1_NP\t")));
+        let variants = &from_index.variants["H1N1_NP"];
+        assert!(!variants.is_empty() && variants.len() < 20, "{} variants", variants.len());
+        assert!(variants.iter().all(|variant| (300..800).contains(&variant.position)));
+        assert_eq!(fastq, fs::read_to_string(&from_fasta.fastq_files[0]).unwrap());
+        fs::remove_dir_all("runner_region_out").unwrap();
+        fs::remove_dir_all("runner_region_plain_out").unwrap();
+        fs::remove_dir_all("runner_region_bad_out").unwrap();
+        assert!(matches!(past_end, Err(NeatError::Config(_))));
+    }
+
+    #[test]
+    fn test_word_seed() {
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Words".to_string()]);
+        let seed = word_seed(4, &mut rng);
+        let words: Vec<&str> = seed.split(' ').collect();
+        assert_eq!(words.len(), 4);
+        assert!(words.iter().all(|word| SEED_WORDS.lines().any(|line| line == *word)));
+        assert_eq!(SEED_WORDS.lines().count(), 256);
+        // Runs without a seed get words in place of the time, as do their replicates
+        let mut builder = ConfigBuilder::new();
+        builder.reference = Some("test_data/H1N1.fa".to_string());
+        builder.random_seed_words = Some(3);
+        let config = builder.build();
+        let replicates = replicate_configs(&config, 2);
+        let replicate_seed = replicates[1].rng_seed.clone().unwrap();
+        assert!(replicate_seed.ends_with(".rep2") && replicate_seed.split(' ').count() == 3);
+        let mut config = config;
+        resolve_seed(&mut config);
+        assert_eq!(config.rng_seed.unwrap().split(' ').count(), 3);
+    }
+
+    #[test]
+    fn test_run_warnings() {
+        let mut builder = ConfigBuilder::new();
+        builder.reference = Some("test_data/H1N1.fa".to_string());
+        let mut config = builder.build();
+        config.read_len = 100;
+        config.coverage = 10;
+        let order = vec!["chr1".to_string(), "chr2".to_string()];
+        let mut fasta_map = HashMap::from([
+            ("chr1".to_string(), vec![0; 1000]),
+            ("chr2".to_string(), vec![1; 50]),
+        ]);
+        let warnings = run_warnings(&config, &fasta_map, &order).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("1 of 2 contigs are shorter than read_len"));
+        // Paired runs with overlapping mates, and too few bases for a read
+        config.paired_ended = true;
+        config.fragment_mean = Some(150.0);
+        config.coverage = 1;
+        fasta_map.insert("chr1".to_string(), vec![4; 1000]);
+        assert_eq!(run_warnings(&config, &fasta_map, &order).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_runner_fill_gaps() {
+        // Two segments of the reference with gaps at both ends and between them
+        let text = fs::read_to_string("test_data/H1N1.fa").unwrap();
+        let segments: Vec<String> = text
+            .split('>')
+            .skip(1)
+            .take(2)
+            .map(|record| record.lines().skip(1).collect())
+            .collect();
+        let gap = |length: usize| "N".repeat(length);
+        let sequence = [gap(300), segments[0].clone(), gap(2000), segments[1].clone(), gap(300)]
+            .concat();
+        let reference = "test_data/test_gapped.fa";
+        fs::write(reference, format!(">gapped\n{}\n", sequence)).unwrap();
+        let mut config = ConfigBuilder::new();
+        config.reference = Some(reference.to_string());
+        config.output_dir = PathBuf::from("runner_fill_gaps_out");
+        config.truth_comment_tags = true;
+        config.fill_gaps = true;
+        config.region = Some("gapped:1-100".to_string());
+        let with_region = config.check_and_print_config();
+        config.region = None;
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Gaps".to_string()]);
+        let output = run_neat(Box::new(config.build()), &mut rng);
+        fs::remove_file(reference).unwrap();
+        let output = output.unwrap();
+        assert!(matches!(with_region, Err(NeatError::Config(_))));
+        let satellite_start = 300 + segments[0].len();
+        let bed = fs::read_to_string(&output.filled_gap_files[0]).unwrap();
+        assert_eq!(
+            bed,
+            format!(
+                "gapped\t0\t300\ttelomere\n\
+                gapped\t{}\t{}\tsatellite\n\
+                gapped\t{}\t{}\ttelomere\n",
+                satellite_start,
+                satellite_start + 2000,
+                sequence.len() - 300,
+                sequence.len()
+            )
+        );
+        let filled = fs::read_to_string(&output.filled_gap_files[1]).unwrap();
+        assert!(filled.starts_with(">gapped\nCCCTAACCCTAA") && !filled.contains('N'));
+        // Reads come from the satellite, as from the rest of the reference
+        let fastq = fs::read_to_string(&output.fastq_files[0]).unwrap();
+        let from_satellite = fastq
+            .lines()
+            .step_by(4)
+            .map(|header| header.split("XP:i:").nth(1).unwrap().split('\t').next().unwrap())
+            .map(|start| start.parse::<usize>().unwrap())
+            .filter(|start| (satellite_start + 1..satellite_start + 1900).contains(start))
+            .count();
+        fs::remove_dir_all("runner_fill_gaps_out").unwrap();
+        assert!(from_satellite > 0);
+    }
+
+    #[test]
+    fn test_runner_coverage_bam() {
+        // A bam of one run is the coverage bam of the next, which takes its fragment lengths
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.paired_ended = true;
+        config.fragment_mean = Some(250.0);
+        config.fragment_st_dev = Some(20.0);
+        config.produce_bam = true;
+        config.output_dir = PathBuf::from("runner_coverage_bam_source_out");
+        config.check_and_print_config().unwrap();
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Bam".to_string()]);
+        let source = run_neat(Box::new(config.build()), &mut rng).unwrap();
+        let bam = source.bam_file.unwrap().to_string_lossy().to_string();
+
+        let mut twin = ConfigBuilder::new();
+        twin.reference = Some("test_data/H1N1.fa".to_string());
+        twin.paired_ended = true;
+        twin.coverage_bam = Some(bam.clone());
+        twin.output_dir = PathBuf::from("runner_coverage_bam_out");
+        let checked = twin.check_and_print_config();
+        twin.coverage_profile = Some(bam);
+        let both = twin.check_and_print_config();
+        twin.coverage_profile = None;
+        let mut config = twin.build();
+        let contigs = read_fasta(&config.reference).unwrap().0;
+        let profile = load_coverage_bam(&mut config, &contigs);
+        let output = run_neat(Box::new(config.clone()), &mut rng);
+        fs::remove_dir_all("runner_coverage_bam_source_out").unwrap();
+        fs::remove_dir_all("runner_coverage_bam_out").unwrap();
+        checked.unwrap();
+        assert!(matches!(both, Err(NeatError::Config(_))));
+        assert!(profile.unwrap().unwrap().alignments > 0);
+        let mean = config.fragment_mean.unwrap();
+        assert!((mean - 250.0).abs() < 15.0, "fragment mean {}", mean);
+        assert!(!output.unwrap().fastq_files.is_empty());
+    }
+}
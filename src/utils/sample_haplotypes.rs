@@ -0,0 +1,116 @@
+// Sample haplotypes given as fastas: for users who already have the genome they want reads from,
+// such as a phased truth assembly, the sample can be a fasta per copy of the genome instead of
+// being drawn by the mutation engine. The haplotypes have to be on the reference's coordinates
+// (every contig of the reference, at the same length), since the reads, the bam and the truth
+// vcf are all placed on the reference: assemblies with indels or rearrangements against it would
+// need lifting over, which a sample of SNVs can't express. Every position a haplotype has another
+// base than the reference at becomes a variant, with a genotype over the haplotypes, and the reads
+// come from the haplotypes through it as they do for simulated variants. Positions where the
+// reference or a haplotype is N are left alone, as are sites where the haplotypes carry two
+// different alts (a variant has one).
+
+use std::collections::HashMap;
+use log::warn;
+use super::errors::NeatError;
+use super::variants::{GenotypeCounts, MutatedReference, Variant, VariantOrigin};
+
+pub fn haplotype_variants(
+    fasta_map: &HashMap<String, Vec<u8>>,
+    fasta_order: &[String],
+    haplotypes: &[(String, HashMap<String, Vec<u8>>)],
+) -> Result<MutatedReference, NeatError> {
+    // Takes:
+    // fasta_map: The reference, keyed by contig name.
+    // fasta_order: Contig names in reference order.
+    // haplotypes: Each haplotype fasta's path (for errors) and its contigs.
+    // Returns:
+    // As mutate_reference does: the reference with every variant applied, the variants of each
+    // contig in position order, and how many are on some and how many on all the haplotypes. An
+    // error if a haplotype is missing a contig or has it at another length.
+    let mut mutated_map = fasta_map.clone();
+    let mut variant_locations = HashMap::new();
+    let mut genotype_counts = GenotypeCounts::default();
+    let mut multiallelic = 0;
+    for contig in fasta_order {
+        let reference = &fasta_map[contig];
+        let mut contig_haplotypes = Vec::with_capacity(haplotypes.len());
+        for (path, haplotype) in haplotypes {
+            let sequence = haplotype.get(contig).ok_or_else(|| NeatError::FastaParse(format!(
+                "{} has no contig {}, and sample haplotypes need every contig of the reference",
+                path, contig
+            )))?;
+            if sequence.len() != reference.len() {
+                return Err(NeatError::FastaParse(format!(
+                    "{} of {} is {} bases, but {} in the reference. Sample haplotypes have to be \
+                    on the reference's coordinates",
+                    contig, path, sequence.len(), reference.len()
+                )))
+            }
+            contig_haplotypes.push(sequence);
+        }
+        let mutated = mutated_map.get_mut(contig).unwrap();
+        let mut variants = Vec::new();
+        for (position, reference_base) in reference.iter().enumerate() {
+            if *reference_base >= 4 {
+                continue
+            }
+            let bases: Vec<u8> =
+                contig_haplotypes.iter().map(|haplotype| haplotype[position]).collect();
+            let mut alts = bases.iter().filter(|base| **base < 4 && *base != reference_base);
+            let Some(alt) = alts.next().copied() else { continue };
+            if alts.any(|base| *base != alt) {
+                multiallelic += 1;
+                continue
+            }
+            let mut variant = Variant::new(position, alt, *reference_base, VariantOrigin::Germline);
+            variant.genotype = bases.iter().map(|base| usize::from(*base == alt)).collect();
+            if variant.genotype.contains(&0) {
+                genotype_counts.heterozygous += 1;
+            } else {
+                genotype_counts.homozygous += 1;
+            }
+            mutated[position] = alt;
+            variants.push(variant);
+        }
+        variant_locations.insert(contig.clone(), variants);
+    }
+    if multiallelic > 0 {
+        warn!("Left out {} sites where the sample haplotypes carry different alts", multiallelic);
+    }
+    Ok(MutatedReference {
+        sequences: Box::new(mutated_map),
+        variants: Box::new(variant_locations),
+        genotype_counts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haplotype_variants() {
+        let fasta_map = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3, 4, 0])]);
+        let order = vec!["chr1".to_string()];
+        // A het SNV at 1, a hom one at 3, an N on a haplotype at 2, two alts at 5, and an N in
+        // the reference at 4
+        let haplotypes = vec![
+            ("hap1.fa".to_string(), HashMap::from([("chr1".to_string(), vec![0, 2, 4, 0, 1, 1])])),
+            ("hap2.fa".to_string(), HashMap::from([("chr1".to_string(), vec![0, 1, 2, 0, 2, 2])])),
+        ];
+        let mutated = haplotype_variants(&fasta_map, &order, &haplotypes).unwrap();
+        assert_eq!(mutated.sequences["chr1"], vec![0, 2, 2, 0, 4, 0]);
+        let sites: Vec<(usize, u8, Vec<usize>)> = mutated.variants["chr1"]
+            .iter()
+            .map(|variant| (variant.position, variant.alt, variant.genotype.clone()))
+            .collect();
+        assert_eq!(sites, vec![(1, 2, vec![1, 0]), (3, 0, vec![1, 1])]);
+        let counts = mutated.genotype_counts;
+        assert_eq!((counts.heterozygous, counts.homozygous), (1, 1));
+
+        let short = vec![("hap1.fa".to_string(), HashMap::from([("chr1".to_string(), vec![0])]))];
+        assert!(matches!(
+            haplotype_variants(&fasta_map, &order, &short), Err(NeatError::FastaParse(_))
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+// Checks that rusty-neat can run on itself, for users checking an installation and for developers
+// watching for regressions.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use log::{error, info};
+use super::checksums::{checksum_file, checksum_file_skipping};
+use super::config::{ConfigBuilder, RunConfiguration};
+use super::errors::NeatError;
+use super::runner::{create_rng, run_neat, RunOutput};
+
+// Header lines that depend on where a run happened, or on the version of rusty-neat that made it
+// (the config hash changes whenever a setting is added), rather than what it produced. Fasta
+// headers carry both too, so they are left out as well.
+const VCF_LOCATION_HEADERS: &[&str] = &["##reference=", "##neat_version=", "##neat_config_hash="];
+const FASTA_HEADERS: &[&str] = &[">"];
+// Used when the configuration doesn't give a seed, so both runs still share one.
+const DETERMINISM_SEED: &str = "rusty neat determinism check";
+
+pub fn output_checksums(output: &RunOutput) -> Result<BTreeMap<String, u64>, NeatError> {
+    // Checksums every file a run wrote, keyed by file name (not path, so runs written to
+    // different directories can be compared).
+    let mut checksums = BTreeMap::new();
+    let files = output.fastq_files
+        .iter()
+        .chain(output.bam_file.iter())
+        .chain(output.edit_list_file.iter())
+        .chain(output.benchmark_files.iter());
+    for file in files {
+        checksums.insert(file_name(file), checksum_file(file)?);
+    }
+    if let Some(fasta_file) = &output.fasta_file {
+        checksums.insert(file_name(fasta_file), checksum_file_skipping(fasta_file, FASTA_HEADERS)?);
+    }
+    if let Some(vcf_file) = &output.vcf_file {
+        checksums.insert(file_name(vcf_file), checksum_file_skipping(vcf_file, VCF_LOCATION_HEADERS)?);
+    }
+    Ok(checksums)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn checksummed_run(
+    config: &RunConfiguration,
+    threads: usize,
+    scratch: &Path,
+) -> Result<BTreeMap<String, u64>, NeatError> {
+    // One truncated run of config into scratch, returning the checksums of its output.
+    let mut run_config = config.clone();
+    run_config.output_dir = scratch.to_path_buf();
+    run_config.overwrite_output = true;
+    run_config.threads = threads;
+    // One pass over the reference is plenty to catch differences, and keeps this quick.
+    run_config.coverage = run_config.coverage.min(1);
+    run_config.rng_seed = Some(
+        run_config.rng_seed.unwrap_or_else(|| DETERMINISM_SEED.to_string())
+    );
+    let mut rng = create_rng(run_config.rng_seed.as_deref());
+    let output = run_neat(Box::new(run_config), &mut rng)?;
+    output_checksums(&output)
+}
+
+pub fn verify_determinism(
+    config: &RunConfiguration,
+    second_threads: Option<usize>,
+) -> Result<BTreeMap<String, u64>, NeatError> {
+    // Takes:
+    // config: the configuration to check. Coverage is capped at 1 and output goes to temporary
+    //      directories.
+    // second_threads: if set, the second run uses this many threads instead of config.threads.
+    // Returns:
+    // The checksums of the output files if both runs produced identical output, or a Simulation
+    // error naming the files that differ.
+    let scratch_parent = config.temp_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let first_dir = tempfile::Builder::new().prefix(".neat_check_").tempdir_in(&scratch_parent)?;
+    let second_dir = tempfile::Builder::new().prefix(".neat_check_").tempdir_in(&scratch_parent)?;
+
+    info!("Determinism check: first run with {} thread(s)", config.threads);
+    let first = checksummed_run(config, config.threads, first_dir.path())?;
+    let threads = second_threads.unwrap_or(config.threads);
+    info!("Determinism check: second run with {} thread(s)", threads);
+    let second = checksummed_run(config, threads, second_dir.path())?;
+
+    let mut differing: Vec<&str> = Vec::new();
+    for (name, checksum) in &first {
+        info!("  {}: {:016x}", name, checksum);
+        if second.get(name) != Some(checksum) {
+            differing.push(name);
+        }
+    }
+    differing.extend(
+        second.keys().filter(|name| !first.contains_key(*name)).map(|name| name.as_str())
+    );
+    if !differing.is_empty() {
+        return Err(NeatError::Simulation(format!(
+            "Runs with the same seed produced different output: {}",
+            differing.join(", ")
+        )))
+    }
+    info!("Determinism check passed");
+    Ok(first)
+}
+
+struct SelfTestCase {
+    // A fixed simulation with known output.
+    //
+    // name: Used for the reference file name and the output prefix.
+    // reference: The fasta to simulate from, built into the binary.
+    // configure: Sets the options for this case on top of the defaults.
+    // expected: Checksums of each output file.
+    name: &'static str,
+    reference: &'static str,
+    configure: fn(&mut ConfigBuilder),
+    expected: &'static [(&'static str, u64)],
+}
+
+const SELF_TEST_CASES: &[SelfTestCase] = &[
+    SelfTestCase {
+        name: "h1n1_single",
+        reference: include_str!("../../test_data/H1N1.fa"),
+        configure: |config| {
+            config.read_len = 100;
+            config.coverage = 2;
+            config.minimum_mutations = Some(2);
+            config.produce_fasta = true;
+            config.produce_vcf = true;
+            config.rng_seed = Some("rusty neat self test h1n1".to_string());
+        },
+        expected: &[
+            ("h1n1_single.fasta", 0x8f4f351cae617ed3),
+            ("h1n1_single.vcf", 0xd8ef957e5a622c62),
+            ("h1n1_single_r1.fastq", 0xee9f8ebad054c265),
+        ],
+    },
+    SelfTestCase {
+        name: "h1n1_paired",
+        reference: include_str!("../../test_data/H1N1.fa"),
+        configure: |config| {
+            config.read_len = 100;
+            config.coverage = 2;
+            config.paired_ended = true;
+            config.fragment_mean = Some(300.0);
+            config.fragment_st_dev = Some(30.0);
+            config.threads = 2;
+            config.produce_vcf = true;
+            config.rng_seed = Some("rusty neat self test paired".to_string());
+        },
+        expected: &[
+            ("h1n1_paired.vcf", 0x2f34ca9260e5543f),
+            ("h1n1_paired_r1.fastq", 0xaf3e0978684ee8f0),
+            ("h1n1_paired_r2.fastq", 0x55d84f681ea2dd87),
+        ],
+    },
+    SelfTestCase {
+        name: "ecoli_somatic",
+        reference: include_str!("../../test_data/ecoli_50k.fa"),
+        configure: |config| {
+            config.coverage = 1;
+            config.somatic_mutation_rate = Some(0.0005);
+            config.produce_vcf = true;
+            config.rng_seed = Some("rusty neat self test ecoli".to_string());
+        },
+        expected: &[
+            ("ecoli_somatic.vcf", 0xfb57a25e3d47109f),
+            ("ecoli_somatic_r1.fastq", 0x231e15a59c5e617f),
+        ],
+    },
+];
+
+pub fn self_test(scratch_parent: Option<&Path>) -> Result<(), NeatError> {
+    // Runs each of the built in cases and compares the checksums of their output with the known
+    // values. Every mismatch is logged (with the checksum we got, so the table can be updated
+    // when output changes on purpose) before the error is returned.
+    let scratch_parent = scratch_parent
+        .map(Path::to_path_buf)
+        .unwrap_or_else(std::env::temp_dir);
+    let mut failures: Vec<String> = Vec::new();
+    for case in SELF_TEST_CASES {
+        info!("Self-test: {}", case.name);
+        let scratch = tempfile::Builder::new().prefix(".neat_self_test_").tempdir_in(&scratch_parent)?;
+        let reference = scratch.path().join(format!("{}.fa", case.name));
+        fs::write(&reference, case.reference)?;
+        let mut config = ConfigBuilder::new();
+        config.reference = Some(reference.display().to_string());
+        config.output_dir = scratch.path().to_path_buf();
+        config.output_prefix = case.name.to_string();
+        (case.configure)(&mut config);
+        config.check_and_print_config()?;
+        let config = config.build();
+        let mut rng = create_rng(config.rng_seed.as_deref());
+        let checksums = output_checksums(&run_neat(Box::new(config), &mut rng)?)?;
+        let expected: BTreeMap<String, u64> = case.expected
+            .iter()
+            .map(|(name, checksum)| (name.to_string(), *checksum))
+            .collect();
+        for (name, checksum) in &checksums {
+            if expected.get(name) == Some(checksum) {
+                info!("  {}: ok", name);
+            } else {
+                error!("  {}: got {:#018x}, expected {:?}", name, checksum, expected.get(name));
+                failures.push(name.clone());
+            }
+        }
+        failures.extend(expected.keys().filter(|name| !checksums.contains_key(*name)).cloned());
+    }
+    if !failures.is_empty() {
+        return Err(NeatError::Simulation(format!(
+            "Self-test output did not match: {}",
+            failures.join(", ")
+        )))
+    }
+    info!("Self-test passed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::config::ConfigBuilder;
+
+    #[test]
+    fn test_verify_determinism() {
+        let mut config = ConfigBuilder::new();
+        config.reference = Some("test_data/H1N1.fa".to_string());
+        config.produce_vcf = true;
+        config.produce_fasta = true;
+        let config = config.build();
+        let checksums = verify_determinism(&config, Some(3)).unwrap();
+        assert_eq!(
+            checksums.keys().collect::<Vec<_>>(),
+            vec!["neat_out.fasta", "neat_out.vcf", "neat_out_r1.fastq"]
+        );
+    }
+
+    #[test]
+    fn test_self_test() {
+        self_test(None).unwrap();
+    }
+}
@@ -0,0 +1,281 @@
+// SBS mutational signatures, as published by COSMIC: how likely a somatic substitution is to fall
+// in each of 96 channels, a pyrimidine (C or T) reference base between its two neighbours,
+// mutating to one of the three other bases (written like A[C>T]G). The somatic layer can use a
+// mix of signatures, weighted by their exposures, in place of its default spectrum. A channel is
+// drawn from the mix, then a position with that channel's context (on either strand), so the
+// somatic variants of each contig follow the mix however common each context is. Tools that
+// deconvolve signatures can then be checked against the exposures that went in.
+
+use std::collections::HashMap;
+use std::fs;
+use simple_rng::{DiscreteDistribution, Rng};
+use super::errors::NeatError;
+
+pub const NUM_CHANNELS: usize = 96;
+
+// The 32 pyrimidine centred trinucleotides, each with 3 possible substitutions.
+const NUM_CONTEXTS: usize = 32;
+
+pub type Signature = [f64; NUM_CHANNELS];
+
+fn complement(base: u8) -> u8 {
+    3 - base
+}
+
+fn context_index(sequence: &[u8], position: usize) -> Option<(usize, bool)> {
+    // The pyrimidine centred context of a position, as an index into the 32 contexts (reference
+    // base, then left and right neighbours), and whether the context is read off the other strand
+    // (for A and G reference bases). None at the ends of the sequence and next to Ns.
+    let left = *sequence.get(position.checked_sub(1)?)?;
+    let reference = *sequence.get(position)?;
+    let right = *sequence.get(position + 1)?;
+    if left > 3 || reference > 3 || right > 3 {
+        return None
+    }
+    let (left, reference, right, flipped) = match reference {
+        1 | 3 => (left, reference, right, false),
+        _ => (complement(right), complement(reference), complement(left), true),
+    };
+    let pyrimidine = if reference == 1 { 0 } else { 1 };
+    Some((pyrimidine * 16 + left as usize * 4 + right as usize, flipped))
+}
+
+fn channel_index(context: usize, substitution: usize) -> usize {
+    // The channel of one of the 3 substitutions of a context. Channels run through the
+    // substitutions (C>A, C>G, C>T, T>A, T>C, T>G) and within each through the 16 neighbours.
+    ((context / 16) * 3 + substitution) * 16 + context % 16
+}
+
+fn substitution_base(context: usize, substitution: usize) -> u8 {
+    // The new base of a substitution of a context, on the pyrimidine strand.
+    let reference = if context < 16 { 1 } else { 3 };
+    [0u8, 1, 2, 3].into_iter().filter(|base| *base != reference).nth(substitution).unwrap()
+}
+
+pub fn parse_channel(channel: &str) -> Option<usize> {
+    // The index of a channel written like A[C>T]G.
+    let bytes = channel.trim().as_bytes();
+    if bytes.len() != 7 || bytes[1] != b'[' || bytes[3] != b'>' || bytes[5] != b']' {
+        return None
+    }
+    let base = |byte: u8| "ACGT".bytes().position(|b| b == byte.to_ascii_uppercase());
+    let (left, reference) = (base(bytes[0])?, base(bytes[2])?);
+    let (alt, right) = (base(bytes[4])?, base(bytes[6])?);
+    if reference == alt || (reference != 1 && reference != 3) {
+        return None
+    }
+    let context = if reference == 1 { 0 } else { 16 } + left * 4 + right;
+    let substitution = (0..3).find(|&index| substitution_base(context, index) == alt as u8)?;
+    Some(channel_index(context, substitution))
+}
+
+pub fn read_signatures(path: &str) -> Result<HashMap<String, Signature>, NeatError> {
+    // Takes:
+    // path: A tab separated table of signatures, as COSMIC distributes them: a header naming each
+    //     signature (e.g. Type, SBS1, SBS5, ...), then a row per channel, the channel (A[C>A]A)
+    //     followed by its weight in each signature.
+    // Returns:
+    // Each signature by name, or an error if the table is malformed or doesn't have all 96
+    // channels.
+    let text = fs::read_to_string(path)?;
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header = lines.next()
+        .ok_or_else(|| NeatError::ModelLoad(format!("{} is empty", path)))?;
+    let names: Vec<String> = header
+        .split('\t')
+        .skip(1)
+        .map(|name| name.trim().to_string())
+        .collect();
+    if names.is_empty() {
+        return Err(NeatError::ModelLoad(format!("{} names no signatures", path)))
+    }
+    let mut signatures: Vec<Signature> = vec![[0.0; NUM_CHANNELS]; names.len()];
+    let mut seen = [false; NUM_CHANNELS];
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let bad_line = || NeatError::ModelLoad(format!("{}: bad signature line {:?}", path, line));
+        let channel = parse_channel(fields[0]).ok_or_else(bad_line)?;
+        if fields.len() != names.len() + 1 || seen[channel] {
+            return Err(bad_line())
+        }
+        seen[channel] = true;
+        for (signature, field) in signatures.iter_mut().zip(&fields[1..]) {
+            let weight: f64 = field.trim().parse().map_err(|_| bad_line())?;
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(bad_line())
+            }
+            signature[channel] = weight;
+        }
+    }
+    let missing = seen.iter().filter(|seen| !**seen).count();
+    if missing > 0 {
+        return Err(NeatError::ModelLoad(format!("{} is missing {} channels", path, missing)))
+    }
+    Ok(names.into_iter().zip(signatures).collect())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureSpectrum {
+    // channels: The chance of a substitution falling in each channel, summing to 1.
+    channels: Signature,
+}
+
+impl SignatureSpectrum {
+    pub fn mix(
+        signatures: &HashMap<String, Signature>,
+        exposures: &[(String, f64)],
+    ) -> Result<Self, NeatError> {
+        // Takes:
+        // signatures: The signatures to mix, by name.
+        // exposures: The weight of each signature in the mix. They needn't sum to 1.
+        // Returns:
+        // The mixed spectrum, or an error if a signature isn't in the table or the mix is empty.
+        let mut channels = [0.0; NUM_CHANNELS];
+        for (name, exposure) in exposures {
+            let signature = signatures.get(name).ok_or_else(|| NeatError::ModelLoad(
+                format!("signature {} is not in the signature file", name)
+            ))?;
+            for (channel, weight) in channels.iter_mut().zip(signature.iter()) {
+                *channel += exposure * weight;
+            }
+        }
+        let total: f64 = channels.iter().sum();
+        if total <= 0.0 {
+            return Err(NeatError::ModelLoad("the signature mix has no weight".to_string()))
+        }
+        channels.iter_mut().for_each(|channel| *channel /= total);
+        Ok(SignatureSpectrum { channels })
+    }
+
+    pub fn channel(&self, channel: usize) -> f64 {
+        self.channels[channel]
+    }
+
+    pub fn position_weights(&self, sequence: &[u8]) -> Vec<f64> {
+        // How likely each position of the sequence is to be mutated: the weight of its context in
+        // the spectrum, shared among every position with that context. Drawing positions by these
+        // weights is the same as drawing a channel from the spectrum and then one of the positions
+        // with its context. Positions with no context (the ends, and bases next to an N) get 0.
+        let contexts: Vec<Option<usize>> = (0..sequence.len())
+            .map(|position| context_index(sequence, position).map(|(context, _)| context))
+            .collect();
+        let mut counts = [0usize; NUM_CONTEXTS];
+        for context in contexts.iter().flatten() {
+            counts[*context] += 1;
+        }
+        let context_weights: Vec<f64> = (0..NUM_CONTEXTS)
+            .map(|context| match counts[context] {
+                0 => 0.0,
+                count => (0..3)
+                    .map(|substitution| self.channels[channel_index(context, substitution)])
+                    .sum::<f64>() / count as f64,
+            })
+            .collect();
+        contexts
+            .iter()
+            .map(|context| context.map_or(0.0, |context| context_weights[context]))
+            .collect()
+    }
+
+    pub fn choose_new_nuc(&self, sequence: &[u8], position: usize, rng: &mut Rng) -> u8 {
+        // Picks the new base of a position (which must have a weight in position_weights) by the
+        // weights of its context's three substitutions, on the position's own strand.
+        let (context, flipped) = context_index(sequence, position)
+            .expect("BUG: signature mutation at a position without a context");
+        let weights: Vec<f64> = (0..3)
+            .map(|substitution| self.channels[channel_index(context, substitution)])
+            .collect();
+        let dist = DiscreteDistribution::new(&weights, false);
+        let alt = substitution_base(context, dist.sample(rng));
+        if flipped { complement(alt) } else { alt }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASES: [char; 4] = ['A', 'C', 'G', 'T'];
+
+    fn write_signature_file(path: &str) {
+        // SBS_CT puts everything on C>T in a TCN context, SBS_FLAT is flat.
+        let mut text = String::from("Type\tSBS_CT\tSBS_FLAT\n");
+        for (reference, alts) in [('C', "AGT"), ('T', "ACG")] {
+            for alt in alts.chars() {
+                for left in BASES {
+                    for right in BASES {
+                        let on_ct = reference == 'C' && alt == 'T' && left == 'T';
+                        let ct = if on_ct { 0.25 } else { 0.0 };
+                        text.push_str(&format!(
+                            "{}[{}>{}]{}\t{}\t{}\n", left, reference, alt, right, ct, 1.0 / 96.0
+                        ));
+                    }
+                }
+            }
+        }
+        fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn test_parse_channel() {
+        assert_eq!(parse_channel("A[C>A]A"), Some(0));
+        assert_eq!(parse_channel("T[C>T]G"), Some(2 * 16 + 3 * 4 + 2));
+        assert_eq!(parse_channel("T[T>G]T"), Some(95));
+        assert_eq!(parse_channel("A[G>A]A"), None);
+        assert_eq!(parse_channel("A[C>C]A"), None);
+        assert_eq!(parse_channel("AC>AA"), None);
+        // Every channel has its own index
+        let mut indexes: Vec<usize> = Vec::new();
+        for context in 0..NUM_CONTEXTS {
+            for substitution in 0..3 {
+                indexes.push(channel_index(context, substitution));
+            }
+        }
+        indexes.sort();
+        assert_eq!(indexes, (0..NUM_CHANNELS).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_read_signatures() {
+        let path = "test_data/test_signatures.tsv";
+        write_signature_file(path);
+        let signatures = read_signatures(path);
+        fs::write(path, "Type\tSBS1\nA[C>A]A\t1.0\n").unwrap();
+        let missing = read_signatures(path);
+        fs::remove_file(path).unwrap();
+        let signatures = signatures.unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(signatures["SBS_CT"][parse_channel("T[C>T]A").unwrap()], 0.25);
+        assert_eq!(signatures["SBS_CT"][parse_channel("A[C>T]A").unwrap()], 0.0);
+        assert!(matches!(missing, Err(NeatError::ModelLoad(_))));
+    }
+
+    #[test]
+    fn test_signature_spectrum() {
+        let path = "test_data/test_signature_spectrum.tsv";
+        write_signature_file(path);
+        let signatures = read_signatures(path).unwrap();
+        fs::remove_file(path).unwrap();
+        let exposures = vec![("SBS_CT".to_string(), 3.0), ("SBS_FLAT".to_string(), 1.0)];
+        let spectrum = SignatureSpectrum::mix(&signatures, &exposures).unwrap();
+        let ct_channel = parse_channel("T[C>T]A").unwrap();
+        assert!((spectrum.channel(ct_channel) - (0.75 * 0.25 + 0.25 / 96.0)).abs() < 1e-12);
+        let unknown = vec![("SBS99".to_string(), 1.0)];
+        assert!(SignatureSpectrum::mix(&signatures, &unknown).is_err());
+
+        // T C A, and its reverse complement T G A, share a context. The ends have none.
+        let sequence: Vec<u8> = vec![3, 1, 0, 3, 2, 0, 4, 1, 1];
+        let only_ct = SignatureSpectrum::mix(&signatures, &[("SBS_CT".to_string(), 1.0)]).unwrap();
+        let weights = only_ct.position_weights(&sequence);
+        assert_eq!(weights[0], 0.0);
+        assert!((weights[1] - 0.125).abs() < 1e-12);
+        assert!((weights[4] - 0.125).abs() < 1e-12);
+        assert_eq!(weights[2], 0.0);
+        assert_eq!(weights[7], 0.0);
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Signatures".to_string()]);
+        for _ in 0..20 {
+            assert_eq!(only_ct.choose_new_nuc(&sequence, 1, &mut rng), 3);
+            assert_eq!(only_ct.choose_new_nuc(&sequence, 4, &mut rng), 0);
+        }
+    }
+}
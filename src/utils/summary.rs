@@ -0,0 +1,606 @@
+// Summarizes what a run produced: the coverage the reads actually reached, the variants added, the
+// fragment sizes, the GC content of the reads and where along each contig they start, for a quick
+// check that a simulation came out the way it was configured.
+// The summary is written as <prefix>_summary.json and logged as a table. The headline numbers also
+// go to <prefix>_mqc.json, as MultiQC custom content, so simulated samples show up in the general
+// statistics table of a pipeline's MultiQC report next to the real ones.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::Write;
+use log::info;
+use serde::Serialize;
+use serde_json::json;
+use super::make_reads::SimulatedRead;
+use super::nucleotides::is_gc;
+use super::variants::{GenotypeCounts, Variant};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContigCoverage {
+    // contig: The contig name.
+    // length: Its length in bases.
+    // reads: The number of reads drawn from it.
+    // mean_coverage: Read bases over contig length.
+    // callable_length: The bases of the contig that aren't N, which are all reads can come from.
+    // callable_coverage: Read bases over callable length, the depth the called genome reached.
+    // median_coverage: The median depth over every base of the contig.
+    // start_bin_size: The width of the bins of start_histogram, in bases.
+    // start_histogram: How many reads start in each bin along the contig, which shows what the
+    //     coverage, target and bias models did to where reads land.
+    pub contig: String,
+    pub length: usize,
+    pub reads: usize,
+    pub mean_coverage: f64,
+    pub callable_length: usize,
+    pub callable_coverage: f64,
+    pub median_coverage: u32,
+    pub start_bin_size: usize,
+    pub start_histogram: Vec<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InsertSizeSummary {
+    // The distribution of fragment (template) lengths of paired ended reads.
+    pub count: u64,
+    pub mean: f64,
+    pub st_dev: f64,
+    pub median: usize,
+    pub min: usize,
+    pub max: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GcSummary {
+    // The GC content of the reads, in percent of their called bases.
+    pub count: u64,
+    pub mean: f64,
+    pub st_dev: f64,
+    pub histogram: BTreeMap<u32, u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VariantSummary {
+    // total: Every variant added.
+    // germline / somatic: The variants from each mutation layer.
+    // by_type: Counts per variant type (SNP, ...).
+    // by_size: Counts per variant length, in bases.
+    // transitions / transversions / ts_tv: Base changes of the SNPs, and their ratio.
+    // heterozygous / homozygous / het_hom: Genotype counts from the vcf, when one was written.
+    pub total: usize,
+    pub germline: usize,
+    pub somatic: usize,
+    pub by_type: BTreeMap<String, usize>,
+    pub by_size: BTreeMap<usize, usize>,
+    pub transitions: usize,
+    pub transversions: usize,
+    pub ts_tv: Option<f64>,
+    pub heterozygous: Option<usize>,
+    pub homozygous: Option<usize>,
+    pub het_hom: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunSummary {
+    // total_reads: Every read generated (both mates of a pair count).
+    // singletons: Reads whose mate was lost, for paired ended runs that lose mates.
+    // contigs: Coverage per contig, in reference order. Empty if no reads were generated.
+    // variants: What the mutation step did.
+    // insert_size: Fragment lengths, for paired ended runs.
+    // gc_content: GC content of the reads. None if no reads were generated.
+    // warnings: What the checks of the configuration against the reference warned about before
+    //     the run, which may explain numbers that look off.
+    pub total_reads: usize,
+    pub singletons: usize,
+    pub contigs: Vec<ContigCoverage>,
+    pub variants: VariantSummary,
+    pub insert_size: Option<InsertSizeSummary>,
+    pub gc_content: Option<GcSummary>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardStats {
+    // The read statistics of one contig, gathered where its reads are generated.
+    pub coverage: ContigCoverage,
+    pub singletons: usize,
+    pub insert_sizes: BTreeMap<usize, u64>,
+    pub gc_content: BTreeMap<u32, u64>,
+}
+
+// Each contig's read starts are counted in this many bins, whatever its length, so the report
+// stays small for large genomes.
+const START_HISTOGRAM_BINS: usize = 100;
+// Contigs listed in the logged summary table. Assemblies can have thousands; the json summary
+// still has every one.
+const LOGGED_CONTIGS: usize = 50;
+
+fn read_gc_percent(read: &SimulatedRead) -> Option<u32> {
+    // The GC content of the bases a read took from the contig (not its adapter), in whole
+    // percent. N's don't count either way. None if the read has no called bases.
+    let bases = &read.seq[..read.aligned_length()];
+    let called = bases.iter().filter(|base| **base < 4).count();
+    if called == 0 {
+        return None
+    }
+    let gc = bases.iter().filter(|base| is_gc(**base)).count();
+    Some((100.0 * gc as f64 / called as f64).round() as u32)
+}
+
+fn start_histogram(length: usize, reads: &[&SimulatedRead]) -> (usize, Vec<u64>) {
+    // Counts the reads starting in each of (up to) START_HISTOGRAM_BINS equal bins of the contig.
+    // Returns the bin size and the counts.
+    let bin_size = length.div_ceil(START_HISTOGRAM_BINS).max(1);
+    let mut counts = vec![0; length.div_ceil(bin_size)];
+    for read in reads {
+        if let Some(count) = counts.get_mut(read.start / bin_size) {
+            *count += 1;
+        }
+    }
+    (bin_size, counts)
+}
+
+fn depth_histogram(length: usize, reads: &[&SimulatedRead]) -> BTreeMap<u32, u64> {
+    // Counts how many bases of the contig are covered to each depth, by sweeping over the places
+    // where reads start and end.
+    let mut events: Vec<(usize, i32)> = Vec::with_capacity(reads.len() * 2);
+    for read in reads {
+        events.push((read.start, 1));
+        events.push(((read.start + read.aligned_length()).min(length), -1));
+    }
+    events.sort_unstable();
+    let mut histogram: BTreeMap<u32, u64> = BTreeMap::new();
+    let mut depth: i32 = 0;
+    let mut position = 0;
+    for (event_position, change) in events {
+        if event_position > position {
+            *histogram.entry(depth as u32).or_default() += (event_position - position) as u64;
+            position = event_position;
+        }
+        depth += change;
+    }
+    if length > position {
+        *histogram.entry(0).or_default() += (length - position) as u64;
+    }
+    histogram
+}
+
+fn histogram_median<T: Copy + Default>(histogram: &BTreeMap<T, u64>) -> T {
+    // The lower median of the values counted in the histogram.
+    let total: u64 = histogram.values().sum();
+    let mut seen = 0;
+    for (value, count) in histogram {
+        seen += count;
+        if seen * 2 >= total {
+            return *value
+        }
+    }
+    T::default()
+}
+
+pub fn shard_stats(
+    contig: &str,
+    length: usize,
+    callable_length: usize,
+    templates: &[&[SimulatedRead]],
+    paired: bool,
+) -> ShardStats {
+    // Takes:
+    // contig: The contig the reads came from.
+    // length: The contig's length.
+    // callable_length: How many of its bases aren't N.
+    // templates: Every read from the contig, one slice per template.
+    // paired: Whether the reads are paired ended.
+    // Returns:
+    // The coverage and start positions of the contig, the GC content histogram of its reads and,
+    // for paired ended reads, the number of singletons and the fragment length histogram of the
+    // complete pairs.
+    let reads: Vec<&SimulatedRead> = templates.iter().copied().flatten().collect();
+    // Adapter bases don't cover anything.
+    let read_bases: usize = reads.iter().map(|read| read.aligned_length()).sum();
+    let (start_bin_size, start_histogram) = start_histogram(length, &reads);
+    let coverage = ContigCoverage {
+        contig: contig.to_string(),
+        length,
+        reads: reads.len(),
+        mean_coverage: if length > 0 { read_bases as f64 / length as f64 } else { 0.0 },
+        callable_length,
+        callable_coverage: if callable_length > 0 {
+            read_bases as f64 / callable_length as f64
+        } else {
+            0.0
+        },
+        median_coverage: histogram_median(&depth_histogram(length, &reads)),
+        start_bin_size,
+        start_histogram,
+    };
+    let mut gc_content: BTreeMap<u32, u64> = BTreeMap::new();
+    for gc in reads.iter().filter_map(|read| read_gc_percent(read)) {
+        *gc_content.entry(gc).or_default() += 1;
+    }
+    let mut singletons = 0;
+    let mut insert_sizes: BTreeMap<usize, u64> = BTreeMap::new();
+    if paired {
+        for template in templates {
+            if template.len() < 2 {
+                singletons += template.len();
+                continue
+            }
+            let start = template.iter().map(|read| read.start).min().unwrap_or(0);
+            let end = template
+                .iter()
+                .map(|read| read.start + read.aligned_length())
+                .max()
+                .unwrap_or(0);
+            *insert_sizes.entry(end - start).or_default() += 1;
+        }
+    }
+    ShardStats { coverage, singletons, insert_sizes, gc_content }
+}
+
+fn histogram_moments<T: Copy + Into<f64>>(histogram: &BTreeMap<T, u64>) -> (u64, f64, f64) {
+    // The count, mean and standard deviation of the values counted in the histogram.
+    let count: u64 = histogram.values().sum();
+    if count == 0 {
+        return (0, 0.0, 0.0)
+    }
+    let mean = histogram
+        .iter()
+        .map(|(value, number)| (*value).into() * *number as f64)
+        .sum::<f64>() / count as f64;
+    let variance = histogram
+        .iter()
+        .map(|(value, number)| ((*value).into() - mean).powi(2) * *number as f64)
+        .sum::<f64>() / count as f64;
+    (count, mean, variance.sqrt())
+}
+
+fn insert_size_summary(histogram: &BTreeMap<usize, u64>) -> Option<InsertSizeSummary> {
+    let sizes: BTreeMap<u32, u64> = histogram
+        .iter()
+        .map(|(size, count)| (*size as u32, *count))
+        .collect();
+    let (count, mean, st_dev) = histogram_moments(&sizes);
+    if count == 0 {
+        return None
+    }
+    Some(InsertSizeSummary {
+        count,
+        mean,
+        st_dev,
+        median: histogram_median(histogram),
+        min: *histogram.keys().next()?,
+        max: *histogram.keys().next_back()?,
+    })
+}
+
+fn gc_summary(histogram: BTreeMap<u32, u64>) -> Option<GcSummary> {
+    let (count, mean, st_dev) = histogram_moments(&histogram);
+    if count == 0 {
+        return None
+    }
+    Some(GcSummary { count, mean, st_dev, histogram })
+}
+
+fn is_transition(reference: u8, alt: u8) -> bool {
+    // A <-> G and C <-> T, with A=0, C=1, G=2, T=3.
+    matches!((reference, alt), (0, 2) | (2, 0) | (1, 3) | (3, 1))
+}
+
+fn ratio(numerator: usize, denominator: usize) -> Option<f64> {
+    if denominator == 0 { None } else { Some(numerator as f64 / denominator as f64) }
+}
+
+pub fn variant_summary(
+    variants: &HashMap<String, Vec<Variant>>,
+    genotypes: Option<GenotypeCounts>,
+) -> VariantSummary {
+    let all: Vec<&Variant> = variants.values().flatten().collect();
+    let somatic = all.iter().filter(|variant| variant.is_somatic()).count();
+    // Every variant is currently a SNP.
+    let transitions = all
+        .iter()
+        .filter(|variant| is_transition(variant.reference, variant.alt))
+        .count();
+    let mut by_type = BTreeMap::new();
+    let mut by_size = BTreeMap::new();
+    if !all.is_empty() {
+        by_type.insert("SNP".to_string(), all.len());
+        by_size.insert(1, all.len());
+    }
+    VariantSummary {
+        total: all.len(),
+        germline: all.len() - somatic,
+        somatic,
+        by_type,
+        by_size,
+        transitions,
+        transversions: all.len() - transitions,
+        ts_tv: ratio(transitions, all.len() - transitions),
+        heterozygous: genotypes.map(|counts| counts.heterozygous),
+        homozygous: genotypes.map(|counts| counts.homozygous),
+        het_hom: genotypes.and_then(|counts| ratio(counts.heterozygous, counts.homozygous)),
+    }
+}
+
+pub fn run_summary(
+    shard_stats: &[ShardStats],
+    variants: &HashMap<String, Vec<Variant>>,
+    genotypes: Option<GenotypeCounts>,
+) -> RunSummary {
+    // Puts the per-contig statistics (in reference order) together with the variant summary.
+    let mut insert_sizes: BTreeMap<usize, u64> = BTreeMap::new();
+    let mut gc_content: BTreeMap<u32, u64> = BTreeMap::new();
+    for stats in shard_stats {
+        for (size, count) in &stats.insert_sizes {
+            *insert_sizes.entry(*size).or_default() += count;
+        }
+        for (gc, count) in &stats.gc_content {
+            *gc_content.entry(*gc).or_default() += count;
+        }
+    }
+    RunSummary {
+        total_reads: shard_stats.iter().map(|stats| stats.coverage.reads).sum(),
+        singletons: shard_stats.iter().map(|stats| stats.singletons).sum(),
+        contigs: shard_stats.iter().map(|stats| stats.coverage.clone()).collect(),
+        variants: variant_summary(variants, genotypes),
+        insert_size: insert_size_summary(&insert_sizes),
+        gc_content: gc_summary(gc_content),
+        warnings: Vec::new(),
+    }
+}
+
+fn format_ratio(ratio: Option<f64>) -> String {
+    ratio.map(|ratio| format!("{:.2}", ratio)).unwrap_or_else(|| "n/a".to_string())
+}
+
+impl RunSummary {
+    pub fn write_json(&self, writer: &mut impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *writer, self)?;
+        writeln!(writer)
+    }
+
+    pub fn mean_coverage(&self) -> f64 {
+        // Coverage over every contig together, weighting each by its length.
+        let length: usize = self.contigs.iter().map(|contig| contig.length).sum();
+        if length == 0 {
+            return 0.0
+        }
+        self.contigs
+            .iter()
+            .map(|contig| contig.mean_coverage * contig.length as f64)
+            .sum::<f64>() / length as f64
+    }
+
+    pub fn callable_coverage(&self) -> f64 {
+        // Coverage over the called (non-N) bases of every contig together.
+        let length: usize = self.contigs.iter().map(|contig| contig.callable_length).sum();
+        if length == 0 {
+            return 0.0
+        }
+        self.contigs
+            .iter()
+            .map(|contig| contig.callable_coverage * contig.callable_length as f64)
+            .sum::<f64>() / length as f64
+    }
+
+    pub fn write_multiqc(&self, writer: &mut impl Write, sample: &str) -> io::Result<()> {
+        // Takes:
+        // writer: Where to write the MultiQC custom content (<prefix>_mqc.json).
+        // sample: The sample name to report the run under.
+        // Returns:
+        // Error if there is a problem or else nothing.
+        //
+        // MultiQC picks up any file ending in _mqc.json. Metrics without a value for this run
+        // (e.g., insert size for single ended reads) are left out rather than reported as zero.
+        let mut metrics = serde_json::Map::new();
+        metrics.insert("total_reads".to_string(), json!(self.total_reads));
+        metrics.insert("mean_coverage".to_string(), json!(self.mean_coverage()));
+        metrics.insert("callable_coverage".to_string(), json!(self.callable_coverage()));
+        metrics.insert("variants".to_string(), json!(self.variants.total));
+        metrics.insert("somatic_variants".to_string(), json!(self.variants.somatic));
+        if let Some(ts_tv) = self.variants.ts_tv {
+            metrics.insert("ts_tv".to_string(), json!(ts_tv));
+        }
+        if let Some(het_hom) = self.variants.het_hom {
+            metrics.insert("het_hom".to_string(), json!(het_hom));
+        }
+        if let Some(insert_size) = &self.insert_size {
+            metrics.insert("mean_insert_size".to_string(), json!(insert_size.mean));
+        }
+        if let Some(gc_content) = &self.gc_content {
+            metrics.insert("mean_gc".to_string(), json!(gc_content.mean));
+        }
+        let report = json!({
+            "id": "rusty_neat",
+            "section_name": "rusty-neat",
+            "description": "Statistics of the simulated reads and truth variants.",
+            "plot_type": "generalstats",
+            "pconfig": [
+                {"total_reads": {"title": "Simulated reads", "format": "{:,.0f}"}},
+                {"mean_coverage": {"title": "Mean coverage", "suffix": "X", "format": "{:,.1f}"}},
+                {"callable_coverage": {
+                    "title": "Callable coverage", "suffix": "X", "format": "{:,.1f}"
+                }},
+                {"variants": {"title": "Truth variants", "format": "{:,.0f}"}},
+                {"somatic_variants": {"title": "Somatic variants", "format": "{:,.0f}"}},
+                {"ts_tv": {"title": "Ts/Tv", "format": "{:,.2f}"}},
+                {"het_hom": {"title": "Het/Hom", "format": "{:,.2f}"}},
+                {"mean_insert_size": {"title": "Insert size", "suffix": " bp", "format": "{:,.0f}"}},
+                {"mean_gc": {"title": "GC", "suffix": "%", "format": "{:,.1f}"}},
+            ],
+            "data": {sample: metrics},
+        });
+        serde_json::to_writer_pretty(&mut *writer, &report)?;
+        writeln!(writer)
+    }
+
+    pub fn log_table(&self) {
+        // Logs the summary as a table, one line per contig up to LOGGED_CONTIGS of them.
+        info!("Run summary:");
+        if !self.contigs.is_empty() {
+            info!("  {:<24} {:>12} {:>10} {:>8} {:>8}", "contig", "length", "reads", "mean", "median");
+            for contig in self.contigs.iter().take(LOGGED_CONTIGS) {
+                info!(
+                    "  {:<24} {:>12} {:>10} {:>8.2} {:>8}",
+                    contig.contig,
+                    contig.length,
+                    contig.reads,
+                    contig.mean_coverage,
+                    contig.median_coverage
+                );
+            }
+            if self.contigs.len() > LOGGED_CONTIGS {
+                info!("  ... and {} more contigs", self.contigs.len() - LOGGED_CONTIGS);
+            }
+        }
+        info!("  total reads: {}", self.total_reads);
+        info!(
+            "  coverage: {:.2}X overall, {:.2}X over the {} non-N bases",
+            self.mean_coverage(),
+            self.callable_coverage(),
+            self.contigs.iter().map(|contig| contig.callable_length).sum::<usize>()
+        );
+        if self.singletons > 0 {
+            info!("  singletons: {}", self.singletons);
+        }
+        let variants = &self.variants;
+        info!(
+            "  variants: {} ({} germline, {} somatic)",
+            variants.total, variants.germline, variants.somatic
+        );
+        for (variant_type, count) in &variants.by_type {
+            info!("    {}: {}", variant_type, count);
+        }
+        info!(
+            "  Ts/Tv: {} ({} transitions, {} transversions)",
+            format_ratio(variants.ts_tv), variants.transitions, variants.transversions
+        );
+        if let (Some(heterozygous), Some(homozygous)) = (variants.heterozygous, variants.homozygous) {
+            info!(
+                "  het/hom: {} ({} het, {} hom)",
+                format_ratio(variants.het_hom), heterozygous, homozygous
+            );
+        }
+        if let Some(insert_size) = &self.insert_size {
+            info!(
+                "  insert size: mean {:.1}, sd {:.1}, median {}, range {}-{}",
+                insert_size.mean, insert_size.st_dev, insert_size.median, insert_size.min, insert_size.max
+            );
+        }
+        if let Some(gc_content) = &self.gc_content {
+            info!("  read GC: mean {:.1}%, sd {:.1}%", gc_content.mean, gc_content.st_dev);
+        }
+        for warning in &self.warnings {
+            info!("  warning: {}", warning);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::make_reads::Strand;
+    use super::super::variants::VariantOrigin;
+
+    fn test_read(start: usize, length: usize, mate: u8) -> SimulatedRead {
+        SimulatedRead {
+            strand: if mate == 2 { Strand::Reverse } else { Strand::Forward },
+            mate,
+            ..SimulatedRead::for_test("chr1", start, vec![0; length])
+        }
+    }
+
+    #[test]
+    fn test_shard_stats() {
+        // Two pairs on a 100 base contig: fragments 0-50 and 40-100
+        let reads = vec![
+            test_read(0, 20, 1), test_read(30, 20, 2),
+            test_read(40, 20, 1), test_read(80, 20, 2),
+        ];
+        let stats = shard_stats("chr1", 100, 80, &[&reads[..2], &reads[2..]], true);
+        assert_eq!(stats.coverage.reads, 4);
+        assert_eq!(stats.coverage.mean_coverage, 0.8);
+        // 20 of the bases are N
+        assert_eq!(stats.coverage.callable_coverage, 1.0);
+        // 30 bases uncovered, 10 (40-50) covered twice and the rest once
+        let all: Vec<&SimulatedRead> = reads.iter().collect();
+        assert_eq!(depth_histogram(100, &all), BTreeMap::from([(0, 30), (1, 60), (2, 10)]));
+        assert_eq!(stats.coverage.median_coverage, 1);
+        assert_eq!(stats.insert_sizes, BTreeMap::from([(50, 1), (60, 1)]));
+        let insert_size = insert_size_summary(&stats.insert_sizes).unwrap();
+        assert_eq!(insert_size.mean, 55.0);
+        assert_eq!(insert_size.st_dev, 5.0);
+        assert_eq!((insert_size.min, insert_size.max), (50, 60));
+        assert_eq!(stats.coverage.start_bin_size, 1);
+        assert_eq!(stats.coverage.start_histogram.len(), 100);
+        assert_eq!(stats.coverage.start_histogram[40], 1);
+        // Singletons count towards coverage but have no fragment length
+        let stats = shard_stats("chr1", 100, 100, &[&reads[..2], &reads[2..3]], true);
+        assert_eq!((stats.coverage.reads, stats.singletons), (3, 1));
+        assert_eq!(stats.insert_sizes, BTreeMap::from([(50, 1)]));
+    }
+
+    #[test]
+    fn test_gc_and_starts() {
+        let mut at_rich = test_read(0, 10, 1);
+        at_rich.seq = vec![0, 3, 0, 3, 0, 3, 0, 3, 1, 2];
+        let mut gc_rich = test_read(950, 10, 1);
+        gc_rich.seq = vec![1, 2, 1, 2, 1, 2, 4, 4, 0, 3];
+        // The adapter bases don't count
+        gc_rich.adapter_length = 2;
+        assert_eq!(read_gc_percent(&at_rich), Some(20));
+        assert_eq!(read_gc_percent(&gc_rich), Some(100));
+        let mut no_bases = test_read(0, 4, 1);
+        no_bases.seq = vec![4; 4];
+        assert_eq!(read_gc_percent(&no_bases), None);
+
+        let stats = shard_stats("chr1", 1000, 1000, &[&[at_rich, gc_rich, no_bases]], false);
+        assert_eq!(stats.gc_content, BTreeMap::from([(20, 1), (100, 1)]));
+        assert_eq!(stats.coverage.start_bin_size, 10);
+        assert_eq!(stats.coverage.start_histogram[0], 2);
+        assert_eq!(stats.coverage.start_histogram[95], 1);
+        let summary = run_summary(&[stats], &HashMap::new(), None);
+        let gc_content = summary.gc_content.unwrap();
+        assert_eq!((gc_content.count, gc_content.mean, gc_content.st_dev), (2, 60.0, 40.0));
+    }
+
+    #[test]
+    fn test_variant_summary() {
+        let variants = HashMap::from([("chr1".to_string(), vec![
+            Variant::new(1, 2, 0, VariantOrigin::Germline),
+            Variant::new(5, 3, 1, VariantOrigin::Germline),
+            Variant::new(9, 1, 0, VariantOrigin::Somatic),
+        ])]);
+        let genotypes = GenotypeCounts { heterozygous: 2, homozygous: 1 };
+        let summary = variant_summary(&variants, Some(genotypes));
+        assert_eq!((summary.total, summary.germline, summary.somatic), (3, 2, 1));
+        assert_eq!((summary.transitions, summary.transversions), (2, 1));
+        assert_eq!(summary.ts_tv, Some(2.0));
+        assert_eq!(summary.het_hom, Some(2.0));
+        assert_eq!(variant_summary(&variants, None).het_hom, None);
+    }
+
+    #[test]
+    fn test_write_multiqc() {
+        let reads = vec![test_read(0, 20, 1), test_read(10, 20, 1)];
+        let summary = run_summary(
+            &[shard_stats("chr1", 40, 20, &[&reads[..1], &reads[1..]], false)],
+            &HashMap::from([("chr1".to_string(), vec![Variant::new(1, 2, 0, VariantOrigin::Germline)])]),
+            None,
+        );
+        assert_eq!(summary.mean_coverage(), 1.0);
+        let mut buffer = Vec::new();
+        summary.write_multiqc(&mut buffer, "normal").unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+        assert_eq!(report["plot_type"], "generalstats");
+        let metrics = &report["data"]["normal"];
+        assert_eq!(metrics["total_reads"], 2);
+        assert_eq!(metrics["variants"], 1);
+        assert_eq!(metrics["callable_coverage"], 2.0);
+        // No genotypes, transversions or pairs to report
+        assert!(metrics.get("het_hom").is_none());
+        assert!(metrics.get("ts_tv").is_none());
+        assert!(metrics.get("mean_insert_size").is_none());
+        assert_eq!(metrics["mean_gc"], 0.0);
+    }
+}
@@ -0,0 +1,266 @@
+// Writes a vcf or bed bgzipped, along with its tabix index
+// (https://samtools.github.io/hts-specs/tabix.pdf), so tools that query by region (hap.py,
+// bcftools, IGV) can use the truth files as they come.
+//
+// The index has, for each contig, the binning index (the chunks of the file holding records in each
+// bin, with bins as in BAM files) and the linear index (the first record overlapping each 16 KB
+// window). Both are built from the virtual offsets of the records as they are compressed.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use super::bgzf::{reg2bin, write_bgzf_eof, BgzfWriter};
+
+// Preset for vcf files: the contig is column 1, the position column 2, and the end comes from the
+// length of the REF allele. Bed files are generic tab-separated files (format 0) with zero-based,
+// half-open coordinates (the UCSC flag), in columns 1 to 3. Lines starting with '#' are skipped.
+const TABIX_FORMAT_VCF: i32 = 2;
+const TABIX_FORMAT_BED: i32 = 0x10000;
+const TABIX_META_CHAR: i32 = b'#' as i32;
+const LINEAR_WINDOW_SHIFT: usize = 14;
+const UNSET_OFFSET: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TabixFormat {
+    Vcf,
+    Bed,
+}
+
+impl TabixFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            TabixFormat::Vcf => "vcf",
+            TabixFormat::Bed => "bed",
+        }
+    }
+
+    fn header(&self) -> [i32; 6] {
+        // The format, the sequence, start and end columns, the meta character and the number of
+        // lines to skip, as the index stores them.
+        match self {
+            TabixFormat::Vcf => [TABIX_FORMAT_VCF, 1, 2, 0, TABIX_META_CHAR, 0],
+            TabixFormat::Bed => [TABIX_FORMAT_BED, 1, 2, 3, TABIX_META_CHAR, 0],
+        }
+    }
+
+    fn span(&self, fields: &[&str]) -> Result<(usize, usize), String> {
+        // The zero-based, half-open span of a record.
+        let parse = |field: &str| {
+            field.parse::<usize>().map_err(|_| format!("Bad {} position: {}", self.name(), field))
+        };
+        match self {
+            TabixFormat::Vcf if fields.len() >= 4 => {
+                let start = parse(fields[1])?.saturating_sub(1);
+                Ok((start, start + fields[3].len()))
+            },
+            TabixFormat::Bed if fields.len() >= 3 => Ok((parse(fields[1])?, parse(fields[2])?)),
+            _ => Err(format!("Malformed {} record: {}", self.name(), fields.join("\t"))),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ContigIndex {
+    // bins: The chunks (start and end virtual offsets) of records in each bin.
+    // linear: The lowest virtual offset of a record overlapping each window.
+    bins: BTreeMap<u32, Vec<(u64, u64)>>,
+    linear: Vec<u64>,
+}
+
+impl ContigIndex {
+    fn add(&mut self, start: usize, end: usize, record_start: u64, record_end: u64) {
+        // Records a line covering [start, end) on the contig, found between the two offsets.
+        let chunks = self.bins.entry(reg2bin(start, end) as u32).or_default();
+        match chunks.last_mut() {
+            // Records next to each other in the file share a chunk.
+            Some(chunk) if chunk.1 == record_start => chunk.1 = record_end,
+            _ => chunks.push((record_start, record_end)),
+        }
+        let first_window = start >> LINEAR_WINDOW_SHIFT;
+        let last_window = (end.max(start + 1) - 1) >> LINEAR_WINDOW_SHIFT;
+        if self.linear.len() <= last_window {
+            self.linear.resize(last_window + 1, UNSET_OFFSET);
+        }
+        for window in first_window..=last_window {
+            self.linear[window] = self.linear[window].min(record_start);
+        }
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&(self.bins.len() as i32).to_le_bytes())?;
+        for (bin, chunks) in &self.bins {
+            writer.write_all(&bin.to_le_bytes())?;
+            writer.write_all(&(chunks.len() as i32).to_le_bytes())?;
+            for (chunk_start, chunk_end) in chunks {
+                writer.write_all(&chunk_start.to_le_bytes())?;
+                writer.write_all(&chunk_end.to_le_bytes())?;
+            }
+        }
+        // Windows without records point at the last record before them, which is always safe to
+        // start reading from.
+        writer.write_all(&(self.linear.len() as i32).to_le_bytes())?;
+        let mut previous = 0;
+        for offset in &self.linear {
+            if *offset != UNSET_OFFSET {
+                previous = *offset;
+            }
+            writer.write_all(&previous.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub fn write_indexed_vcf(
+    vcf: &[u8],
+    vcf_writer: &mut impl Write,
+    index_writer: &mut impl Write,
+) -> io::Result<()> {
+    // Bgzips a vcf (<name>.vcf.gz) and writes its tabix index (<name>.vcf.gz.tbi).
+    write_indexed(vcf, TabixFormat::Vcf, vcf_writer, index_writer)
+}
+
+pub fn write_indexed(
+    text: &[u8],
+    format: TabixFormat,
+    writer: &mut impl Write,
+    index_writer: &mut impl Write,
+) -> io::Result<()> {
+    // Takes:
+    // text: The complete text of a vcf or bed, sorted by contig and position.
+    // format: Which of the two it is.
+    // writer: Where to write the bgzipped file (<name>.gz).
+    // index_writer: Where to write its tabix index (<name>.gz.tbi).
+    // Returns:
+    // Error if there is a problem (including records that aren't sorted) or else nothing.
+    let mut bgzf_writer = BgzfWriter::new(&mut *writer);
+    let mut contig_names: Vec<String> = Vec::new();
+    let mut contigs: Vec<ContigIndex> = Vec::new();
+    let mut last_start = 0;
+    for line in text.split_inclusive(|byte| *byte == b'\n') {
+        let record_start = bgzf_writer.virtual_offset();
+        bgzf_writer.write_all(line)?;
+        if line.starts_with(b"#") || line.trim_ascii().is_empty() {
+            continue
+        }
+        let text = String::from_utf8_lossy(line);
+        let fields: Vec<&str> = text.trim_end().split('\t').collect();
+        let (start, end) = format.span(&fields).map_err(invalid_data)?;
+        if contig_names.last().map(String::as_str) != Some(fields[0]) {
+            if contig_names.iter().any(|name| name == fields[0]) {
+                return Err(invalid_data(format!(
+                    "{} records for {} are not together", format.name(), fields[0]
+                )))
+            }
+            contig_names.push(fields[0].to_string());
+            contigs.push(ContigIndex::default());
+            last_start = 0;
+        }
+        if start < last_start {
+            return Err(invalid_data(format!(
+                "{} records for {} are not sorted", format.name(), fields[0]
+            )))
+        }
+        last_start = start;
+        // Only this line's bytes are in the chunk, even once more are written after it.
+        let record_end = bgzf_writer.virtual_offset();
+        contigs.last_mut().unwrap().add(start, end, record_start, record_end);
+    }
+    bgzf_writer.finish()?;
+    write_bgzf_eof(writer)?;
+
+    let mut index: Vec<u8> = Vec::new();
+    index.extend_from_slice(b"TBI\x01");
+    index.extend_from_slice(&(contig_names.len() as i32).to_le_bytes());
+    for value in format.header() {
+        index.extend_from_slice(&value.to_le_bytes());
+    }
+    let names: Vec<u8> = contig_names
+        .iter()
+        .flat_map(|name| name.bytes().chain([0]))
+        .collect();
+    index.extend_from_slice(&(names.len() as i32).to_le_bytes());
+    index.extend_from_slice(&names);
+    for contig in &contigs {
+        contig.write(&mut index)?;
+    }
+    // No records without coordinates
+    index.extend_from_slice(&0u64.to_le_bytes());
+    let mut index_bgzf = BgzfWriter::new(&mut *index_writer);
+    index_bgzf.write_all(&index)?;
+    index_bgzf.finish()?;
+    write_bgzf_eof(index_writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::MultiGzDecoder;
+
+    fn read_i32(data: &[u8], offset: usize) -> i32 {
+        i32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_write_indexed_vcf() {
+        let vcf = b"##fileformat=VCFv4.1\n\
+            #CHROM\tPOS\tID\tREF\tALT\n\
+            chr1\t10\t.\tA\tG\n\
+            chr1\t20000\t.\tC\tT\n\
+            chr2\t5\t.\tG\tA\n";
+        let (mut compressed, mut index) = (Vec::new(), Vec::new());
+        write_indexed_vcf(vcf, &mut compressed, &mut index).unwrap();
+
+        let mut text = Vec::new();
+        MultiGzDecoder::new(&compressed[..]).read_to_end(&mut text).unwrap();
+        assert_eq!(&text, vcf);
+
+        let mut data = Vec::new();
+        MultiGzDecoder::new(&index[..]).read_to_end(&mut data).unwrap();
+        assert_eq!(&data[..4], b"TBI\x01");
+        assert_eq!(read_i32(&data, 4), 2);
+        assert_eq!(read_i32(&data, 8), TABIX_FORMAT_VCF);
+        assert_eq!(read_i32(&data, 32), 10);
+        assert_eq!(&data[36..46], b"chr1\0chr2\0");
+        // chr1: two records in different 16 KB bins, and a linear index covering both windows
+        assert_eq!(read_i32(&data, 46), 2);
+        assert_eq!(read_i32(&data, 50) as u32, 4681);
+        let first_record = u64::from_le_bytes(data[58..66].try_into().unwrap());
+        assert_eq!(first_record, (b"##fileformat=VCFv4.1\n#CHROM\tPOS\tID\tREF\tALT\n".len()) as u64);
+        let chr1_linear = 46 + 4 + 2 * (4 + 4 + 16);
+        assert_eq!(read_i32(&data, chr1_linear), 2);
+    }
+
+    #[test]
+    fn test_write_indexed_bed() {
+        let bed = b"chr1\t0\t100\nchr1\t20000\t40000\n";
+        let (mut compressed, mut index) = (Vec::new(), Vec::new());
+        write_indexed(bed, TabixFormat::Bed, &mut compressed, &mut index).unwrap();
+        let mut text = Vec::new();
+        MultiGzDecoder::new(&compressed[..]).read_to_end(&mut text).unwrap();
+        assert_eq!(&text, bed);
+        let mut data = Vec::new();
+        MultiGzDecoder::new(&index[..]).read_to_end(&mut data).unwrap();
+        assert_eq!(read_i32(&data, 8), TABIX_FORMAT_BED);
+        assert_eq!(read_i32(&data, 20), 3);
+        assert_eq!(&data[36..41], b"chr1\0");
+        // The second record spans windows 1 and 2, so the linear index has three
+        let chr1_linear = 41 + 4 + 2 * (4 + 4 + 16);
+        assert_eq!(read_i32(&data, chr1_linear), 3);
+        // Beds need an end
+        let bed = b"chr1\t5\n";
+        let result = write_indexed(bed, TabixFormat::Bed, &mut Vec::new(), &mut Vec::new());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_unsorted_vcf() {
+        let vcf = b"chr1\t20\t.\tA\tG\nchr1\t10\t.\tC\tT\n";
+        let result = write_indexed_vcf(vcf, &mut Vec::new(), &mut Vec::new());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
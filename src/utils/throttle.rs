@@ -0,0 +1,120 @@
+// Write throttling (throttle_mb_per_sec), for long runs on shared login or development nodes,
+// where a run writing its fastqs as fast as the disk takes them slows down everyone else on the
+// node. Every file the run writes (the staged outputs, the read shards and the shuffle buckets)
+// goes through one Throttle, shared by the read generation threads, which holds each write back
+// until the bytes written so far fit under the rate. After a pause in writing (while a contig's
+// reads are generated, say) only a short burst is let through at full speed, so the rate holds
+// over any stretch of the run. Writes that don't have to wait still give up the thread's time
+// slice, so the workers yield to other processes between chunks of output.
+
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How much writing a pause earns: this long at the full rate.
+const BURST: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    // bytes_per_second: The rate writes are held to.
+    // paid_until: When the bytes written so far will have been written at that rate, shared by
+    //     every clone of the throttle.
+    bytes_per_second: f64,
+    paid_until: Arc<Mutex<Instant>>,
+}
+
+impl Throttle {
+    pub fn new(mb_per_sec: f64) -> Self {
+        Throttle {
+            bytes_per_second: mb_per_sec * (1 << 20) as f64,
+            paid_until: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn wait(&self, bytes: usize) {
+        // Accounts for bytes written and sleeps until they fit under the rate, or just yields if
+        // they already do.
+        let now = Instant::now();
+        let due = {
+            let mut paid_until = self.paid_until.lock().unwrap();
+            let earliest = now.checked_sub(BURST).unwrap_or(now);
+            *paid_until = (*paid_until).max(earliest)
+                + Duration::from_secs_f64(bytes as f64 / self.bytes_per_second);
+            *paid_until
+        };
+        match due.checked_duration_since(now) {
+            Some(delay) if !delay.is_zero() => thread::sleep(delay),
+            _ => thread::yield_now(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ThrottledFile {
+    // A file whose writes are held to a throttle's rate, or written straight through without one.
+    file: File,
+    throttle: Option<Throttle>,
+}
+
+impl ThrottledFile {
+    pub fn new(file: File, throttle: Option<&Throttle>) -> Self {
+        ThrottledFile { file, throttle: throttle.cloned() }
+    }
+
+    pub fn create(path: &Path, throttle: Option<&Throttle>) -> io::Result<Self> {
+        Ok(ThrottledFile::new(File::create(path)?, throttle))
+    }
+}
+
+impl Write for ThrottledFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        if let Some(throttle) = &self.throttle {
+            throttle.wait(written);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_throttle() {
+        // 8 MB at 16 MB a second, across two threads sharing the throttle, takes half a second
+        // less the burst a fresh throttle allows.
+        let throttle = Throttle::new(16.0);
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                let throttle = &throttle;
+                scope.spawn(move || {
+                    for _ in 0..64 {
+                        throttle.wait(1 << 16);
+                    }
+                });
+            }
+        });
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(490) - BURST, "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+
+        let path = Path::new("test_data/test_throttle.txt");
+        let mut file = ThrottledFile::create(path, Some(&throttle)).unwrap();
+        file.write_all(b"throttled\n").unwrap();
+        file.flush().unwrap();
+        drop(file);
+        assert_eq!(fs::read_to_string(path).unwrap(), "throttled\n");
+        fs::remove_file(path).unwrap();
+    }
+}
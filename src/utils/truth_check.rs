@@ -0,0 +1,119 @@
+// Checks the truth vcf a run wrote against the sequences it came from: every record checked must
+// have the original reference base as its REF and the mutated sequence's base at the same position
+// as its ALT. Only SNPs are simulated, so positions are the same in both sequences. A mismatch
+// means the vcf and the mutated fasta (and the reads made from it) disagree about the truth, so
+// the run fails rather than hand out a benchmark that would score callers wrongly.
+
+use std::collections::HashMap;
+use log::{error, info};
+use super::errors::NeatError;
+use super::nucleotides::u8_to_base;
+
+// Most records checked. Larger vcfs are checked at evenly spaced records, so the check stays
+// quick and uses no randomness.
+pub const TRUTH_CHECK_RECORDS: usize = 10_000;
+// Mismatches listed in the error.
+const REPORTED_MISMATCHES: usize = 5;
+
+fn check_record(
+    line: &str,
+    reference: &HashMap<String, Vec<u8>>,
+    mutated: &HashMap<String, Vec<u8>>,
+) -> Option<String> {
+    // Returns what is wrong with a vcf record, if anything.
+    let fields: Vec<&str> = line.splitn(6, '\t').collect();
+    if fields.len() < 5 {
+        return Some(format!("malformed record {:?}", line))
+    }
+    let (contig, ref_allele, alt_allele) = (fields[0], fields[3], fields[4]);
+    let (Some(original), Some(edited)) = (reference.get(contig), mutated.get(contig)) else {
+        return Some(format!("{} isn't in the reference", contig))
+    };
+    let position = match fields[1].parse::<usize>() {
+        Ok(position) if position >= 1 && position <= original.len().min(edited.len()) => {
+            position - 1
+        },
+        _ => return Some(format!("{}:{} is outside the contig", contig, fields[1])),
+    };
+    let expected_ref = u8_to_base(original[position]).to_string();
+    let expected_alt = u8_to_base(edited[position]).to_string();
+    if ref_allele != expected_ref {
+        return Some(format!(
+            "{}:{} has REF {} but the reference has {}",
+            contig, position + 1, ref_allele, expected_ref
+        ))
+    }
+    if alt_allele != expected_alt {
+        return Some(format!(
+            "{}:{} has ALT {} but the mutated sequence has {}",
+            contig, position + 1, alt_allele, expected_alt
+        ))
+    }
+    None
+}
+
+pub fn check_truth_vcf(
+    vcf: &[u8],
+    reference: &HashMap<String, Vec<u8>>,
+    mutated: &HashMap<String, Vec<u8>>,
+    max_records: usize,
+) -> Result<usize, NeatError> {
+    // Takes:
+    // vcf: The text of the truth vcf, as written.
+    // reference: The original reference sequences, keyed by contig name.
+    // mutated: The sequences with every variant applied (those of the mutated fasta).
+    // max_records: The most records to check, spread evenly over the vcf.
+    // Returns:
+    // The number of records checked, or an error listing the first mismatches found.
+    let text = String::from_utf8_lossy(vcf);
+    let records: Vec<&str> = text
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .collect();
+    let stride = records.len().div_ceil(max_records.max(1)).max(1);
+    let mut checked = 0;
+    let mut mismatches: Vec<String> = Vec::new();
+    for line in records.iter().step_by(stride) {
+        checked += 1;
+        if let Some(mismatch) = check_record(line, reference, mutated) {
+            mismatches.push(mismatch);
+        }
+    }
+    if !mismatches.is_empty() {
+        for mismatch in mismatches.iter().take(REPORTED_MISMATCHES) {
+            error!("Truth vcf mismatch: {}", mismatch);
+        }
+        return Err(NeatError::Simulation(format!(
+            "{} of {} truth vcf records checked don't match the sequences they were made from, \
+            starting with {}", mismatches.len(), checked, mismatches[0]
+        )))
+    }
+    info!("Checked {} of {} truth vcf records against the reference", checked, records.len());
+    Ok(checked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_truth_vcf() {
+        let reference = HashMap::from([("chr1".to_string(), vec![0, 1, 2, 3, 0])]);
+        let mutated = HashMap::from([("chr1".to_string(), vec![0, 3, 2, 3, 2])]);
+        let vcf = b"##fileformat=VCFv4.1\n#CHROM\tPOS\tID\tREF\tALT\n\
+            chr1\t2\t.\tC\tT\t37\tPASS\t.\tGT\t0/1\n\
+            chr1\t5\t.\tA\tG\t37\tPASS\t.\tGT\t1/1\n";
+        assert_eq!(check_truth_vcf(vcf, &reference, &mutated, 100).unwrap(), 2);
+        // Only every other record is checked when at most one may be
+        assert_eq!(check_truth_vcf(vcf, &reference, &mutated, 1).unwrap(), 1);
+
+        // A record shifted by one base
+        let shifted = b"chr1\t3\t.\tC\tT\t37\tPASS\t.\tGT\t0/1\n";
+        let result = check_truth_vcf(shifted, &reference, &mutated, 100);
+        assert!(matches!(result, Err(NeatError::Simulation(_))));
+        let wrong_alt = b"chr1\t2\t.\tC\tG\t37\tPASS\t.\tGT\t0/1\n";
+        assert!(check_truth_vcf(wrong_alt, &reference, &mutated, 100).is_err());
+        let off_end = b"chr1\t6\t.\tA\tG\t37\tPASS\t.\tGT\t0/1\nchr2\t1\t.\tA\tG\n";
+        assert!(check_truth_vcf(off_end, &reference, &mutated, 100).is_err());
+    }
+}
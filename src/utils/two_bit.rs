@@ -0,0 +1,180 @@
+// Reads UCSC .2bit references. The format already stores bases 2 bits each, with the runs of N
+// listed separately, so it loads much faster than a fasta. See
+// https://genome.ucsc.edu/FAQ/FAQformat.html#format7 for the layout.
+//
+// Soft masking (lower case blocks) is ignored, as it is for fasta files.
+
+use std::collections::HashMap;
+use std::fs;
+use log::info;
+use super::errors::NeatError;
+use super::reference_cache::Reference;
+
+const TWO_BIT_SIGNATURE: u32 = 0x1A412743;
+// 2bit codes are T, C, A, G (0 to 3); these are the same bases in NEAT's numbering.
+const TWO_BIT_BASES: [u8; 4] = [3, 1, 0, 2];
+
+struct TwoBitReader<'a> {
+    // Reads the integers of a 2bit file, in whichever byte order the file was written.
+    bytes: &'a [u8],
+    big_endian: bool,
+    path: &'a str,
+}
+
+impl TwoBitReader<'_> {
+    fn error(&self, message: &str) -> NeatError {
+        NeatError::FastaParse(format!("{}: {}", self.path, message))
+    }
+
+    fn slice(&self, offset: usize, length: usize) -> Result<&[u8], NeatError> {
+        offset
+            .checked_add(length)
+            .and_then(|end| self.bytes.get(offset..end))
+            .ok_or_else(|| self.error("file is cut short"))
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32, NeatError> {
+        let bytes: [u8; 4] = self.slice(offset, 4)?.try_into().unwrap();
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u64_at(&self, offset: usize) -> Result<u64, NeatError> {
+        let bytes: [u8; 8] = self.slice(offset, 8)?.try_into().unwrap();
+        Ok(if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+}
+
+pub fn read_two_bit(path: &str) -> Result<Reference, NeatError> {
+    // Reads a .2bit file into the same form read_fasta returns: the sequences keyed by contig
+    // name, and the contig names in file order.
+    info!("Reading 2bit: {}", path);
+    let bytes = fs::read(path)?;
+    parse_two_bit(&bytes, path)
+}
+
+fn parse_two_bit(bytes: &[u8], path: &str) -> Result<Reference, NeatError> {
+    let mut reader = TwoBitReader { bytes, big_endian: false, path };
+    if reader.u32_at(0)? != TWO_BIT_SIGNATURE {
+        reader.big_endian = true;
+        if reader.u32_at(0)? != TWO_BIT_SIGNATURE {
+            return Err(reader.error("not a 2bit file"))
+        }
+    }
+    // Version 1 files use 64 bit offsets, for references over 4 GB.
+    let long_offsets = match reader.u32_at(4)? {
+        0 => false,
+        1 => true,
+        version => return Err(reader.error(&format!("unknown 2bit version {}", version))),
+    };
+    let num_contigs = reader.u32_at(8)? as usize;
+    let mut fasta_map: HashMap<String, Vec<u8>> = HashMap::with_capacity(num_contigs);
+    let mut fasta_order: Vec<String> = Vec::with_capacity(num_contigs);
+    let mut index_offset = 16;
+    for _ in 0..num_contigs {
+        let name_length = reader.slice(index_offset, 1)?[0] as usize;
+        let name = String::from_utf8(reader.slice(index_offset + 1, name_length)?.to_vec())
+            .map_err(|_| reader.error("contig name is not valid text"))?;
+        index_offset += 1 + name_length;
+        let record_offset = if long_offsets {
+            index_offset += 8;
+            reader.u64_at(index_offset - 8)? as usize
+        } else {
+            index_offset += 4;
+            reader.u32_at(index_offset - 4)? as usize
+        };
+        if name.is_empty() || fasta_map.contains_key(&name) {
+            return Err(reader.error(&format!("empty or duplicate contig name {:?}", name)))
+        }
+        let sequence = read_record(&reader, record_offset)?;
+        fasta_map.insert(name.clone(), sequence);
+        fasta_order.push(name);
+    }
+    if fasta_order.is_empty() {
+        return Err(reader.error("no contigs found"))
+    }
+    Ok((Box::new(fasta_map), fasta_order))
+}
+
+fn read_record(reader: &TwoBitReader, offset: usize) -> Result<Vec<u8>, NeatError> {
+    // Reads one sequence record: its length, the N blocks, the (ignored) mask blocks, and the
+    // packed bases, four to a byte with the first base in the high bits.
+    let length = reader.u32_at(offset)? as usize;
+    let num_n_blocks = reader.u32_at(offset + 4)? as usize;
+    let n_starts_offset = offset + 8;
+    let n_sizes_offset = n_starts_offset + 4 * num_n_blocks;
+    let mask_offset = n_sizes_offset + 4 * num_n_blocks;
+    let num_mask_blocks = reader.u32_at(mask_offset)? as usize;
+    // Past the mask starts and sizes, and a reserved word
+    let bases_offset = mask_offset + 4 + 8 * num_mask_blocks + 4;
+    let packed = reader.slice(bases_offset, length.div_ceil(4))?;
+    let mut sequence: Vec<u8> = (0..length)
+        .map(|index| TWO_BIT_BASES[((packed[index / 4] >> (6 - 2 * (index % 4))) & 3) as usize])
+        .collect();
+    for block in 0..num_n_blocks {
+        let start = reader.u32_at(n_starts_offset + 4 * block)? as usize;
+        let size = reader.u32_at(n_sizes_offset + 4 * block)? as usize;
+        sequence
+            .get_mut(start..start + size)
+            .ok_or_else(|| reader.error("N block runs past the end of its sequence"))?
+            .fill(4);
+    }
+    Ok(sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_bit_record(sequence: &str, n_blocks: &[(u32, u32)]) -> Vec<u8> {
+        // A little endian 2bit sequence record, with one mask block.
+        let mut record = Vec::new();
+        record.extend((sequence.len() as u32).to_le_bytes());
+        record.extend((n_blocks.len() as u32).to_le_bytes());
+        n_blocks.iter().for_each(|(start, _)| record.extend(start.to_le_bytes()));
+        n_blocks.iter().for_each(|(_, size)| record.extend(size.to_le_bytes()));
+        record.extend(1u32.to_le_bytes());
+        record.extend(0u32.to_le_bytes());
+        record.extend(2u32.to_le_bytes());
+        record.extend(0u32.to_le_bytes());
+        for chunk in sequence.as_bytes().chunks(4) {
+            let mut byte = 0u8;
+            for (index, base) in chunk.iter().enumerate() {
+                // N is stored as T, with an N block over it
+                let code = match base { b'C' => 1, b'A' => 2, b'G' => 3, _ => 0 };
+                byte |= code << (6 - 2 * index);
+            }
+            record.push(byte);
+        }
+        record
+    }
+
+    #[test]
+    fn test_parse_two_bit() {
+        let records = [two_bit_record("ACGTNNAC", &[(4, 2)]), two_bit_record("GGTCA", &[])];
+        let names = ["chr1", "chrM"];
+        let index_length: usize = names.iter().map(|name| 1 + name.len() + 4).sum();
+        let mut file = Vec::new();
+        file.extend(TWO_BIT_SIGNATURE.to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend(2u32.to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        let mut record_offset = 16 + index_length;
+        for (name, record) in names.iter().zip(&records) {
+            file.push(name.len() as u8);
+            file.extend(name.as_bytes());
+            file.extend((record_offset as u32).to_le_bytes());
+            record_offset += record.len();
+        }
+        records.iter().for_each(|record| file.extend(record));
+        let (fasta_map, fasta_order) = parse_two_bit(&file, "test.2bit").unwrap();
+        assert_eq!(fasta_order, vec!["chr1".to_string(), "chrM".to_string()]);
+        assert_eq!(fasta_map["chr1"], vec![0, 1, 2, 3, 4, 4, 0, 1]);
+        assert_eq!(fasta_map["chrM"], vec![2, 2, 3, 1, 0]);
+
+        assert!(matches!(
+            parse_two_bit(&file[..file.len() - 1], "test.2bit"),
+            Err(NeatError::FastaParse(_))
+        ));
+        assert!(matches!(parse_two_bit(b">chr1\nACGT\n", "test.2bit"), Err(NeatError::FastaParse(_))));
+    }
+}
@@ -0,0 +1,357 @@
+// Variants are the record of what the mutation step did to the reference. Each one carries
+// enough information to write the truth vcf and to trace where the variant came from.
+
+use std::collections::HashMap;
+use serde::Serialize;
+use simple_rng::{DiscreteDistribution, Rng};
+use super::fasta_tools::ContigMap;
+use super::genotype_quality::SiteCall;
+use super::ploidy::PloidyMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariantOrigin {
+    // Inherited variants, present in every cell of the simulated sample.
+    Germline,
+    // Acquired variants layered on top of the germline background (e.g., tumor mutations).
+    Somatic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VariantEffect {
+    // What a variant does to the transcript it lands in, from least to most severe. Only SNPs are
+    // simulated, so there are no frameshifts.
+    Intron,
+    // In an exon of a transcript with no coding sequence.
+    NoncodingExon,
+    // In an exon, outside the coding sequence.
+    Utr,
+    // In the coding sequence, but in a codon the annotation doesn't complete (or with an N in it).
+    Coding,
+    Synonymous,
+    Missense,
+    StopLost,
+    Nonsense,
+}
+
+impl VariantEffect {
+    pub fn as_str(&self) -> &'static str {
+        // The name used in the EFFECT field of the vcf.
+        match self {
+            VariantEffect::Intron => "intron",
+            VariantEffect::NoncodingExon => "noncoding_exon",
+            VariantEffect::Utr => "utr",
+            VariantEffect::Coding => "coding",
+            VariantEffect::Synonymous => "synonymous",
+            VariantEffect::Missense => "missense",
+            VariantEffect::StopLost => "stop_lost",
+            VariantEffect::Nonsense => "nonsense",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantAnnotation {
+    // gene: The gene the variant falls in.
+    // effect: What the variant does to it, against the reference codon.
+    pub gene: String,
+    pub effect: VariantEffect,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    // position: 0-based position of the variant in the contig
+    // alt: the u8 representation of the alternate base
+    // reference: the u8 representation of the reference base
+    // origin: which mutation layer produced this variant
+    // genotype: for each copy of the contig, 1 if the variant is on it and 0 if not. Empty until
+    // assign_genotypes has run.
+    // annotation: for runs with gene annotations, the gene the variant falls in (if any) and its
+    // effect on it.
+    // id: for variants put at a known site, the site's ID (e.g. its rs number).
+    // call: the simulated depth and genotype quality of the site, for the truth vcf. None until
+    // call_sites has run.
+    // heteroplasmy: for mitochondrial variants in runs that model heteroplasmy, the fraction of
+    // the copies of the mitochondrial genome carrying the variant.
+    // frequency: for quasispecies runs, the share of the viral population carrying the variant.
+    pub position: usize,
+    pub alt: u8,
+    pub reference: u8,
+    pub origin: VariantOrigin,
+    pub genotype: Vec<usize>,
+    pub annotation: Option<VariantAnnotation>,
+    pub id: Option<String>,
+    pub call: Option<SiteCall>,
+    pub heteroplasmy: Option<f64>,
+    pub frequency: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct GenotypeCounts {
+    // heterozygous: Variants on some, but not all, copies of the contig.
+    // homozygous: Variants on every copy.
+    pub heterozygous: usize,
+    pub homozygous: usize,
+}
+
+// What the mutation step gives back, whether it drew the variants (mutate_reference) or took them
+// from the sample's haplotypes (haplotype_variants).
+pub struct MutatedReference {
+    // sequences: The reference with every variant applied, whatever its genotype.
+    // variants: The variants, keyed by contig name and sorted by position.
+    // genotype_counts: How many variants are on some, and how many on all, copies.
+    pub sequences: ContigMap,
+    pub variants: Box<HashMap<String, Vec<Variant>>>,
+    pub genotype_counts: GenotypeCounts,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenotypeModel {
+    // How many copies carry each germline variant, as (dosage, weight) pairs. In a diploid, a
+    // dosage of 1 is heterozygous and 2 homozygous for the alt. Hom-ref is implicit, since every
+    // variant is on at least one copy.
+    Dosages(Vec<(usize, f64)>),
+    // The dosages of an allele at this population frequency under Hardy-Weinberg equilibrium,
+    // given that the sample carries it.
+    HardyWeinberg(f64),
+}
+
+impl GenotypeModel {
+    pub fn dosage_weights(&self, copies: usize) -> Vec<f64> {
+        // The weights of a variant being on 1 to `copies` copies of a site with that many. Dosages
+        // of more copies than the site has count as all of them, so a variant that would be
+        // homozygous is hemizygous on a male X.
+        match self {
+            GenotypeModel::Dosages(dosages) => {
+                let mut weights = vec![0.0; copies];
+                for (dosage, weight) in dosages {
+                    weights[(*dosage).clamp(1, copies) - 1] += weight;
+                }
+                weights
+            },
+            GenotypeModel::HardyWeinberg(frequency) => {
+                // Binomial(copies, frequency), without the hom-ref term
+                let mut choices = 1.0;
+                (1..=copies)
+                    .map(|dosage| {
+                        choices *= (copies + 1 - dosage) as f64 / dosage as f64;
+                        choices * frequency.powi(dosage as i32)
+                            * (1.0 - frequency).powi((copies - dosage) as i32)
+                    })
+                    .collect()
+            },
+        }
+    }
+}
+
+impl Variant {
+    pub fn new(position: usize, alt: u8, reference: u8, origin: VariantOrigin) -> Self {
+        Variant {
+            position,
+            alt,
+            reference,
+            origin,
+            genotype: Vec::new(),
+            annotation: None,
+            id: None,
+            call: None,
+            heteroplasmy: None,
+            frequency: None,
+        }
+    }
+
+    pub fn on_copy(&self, copy: usize) -> bool {
+        // Whether the variant is on this copy (haplotype) of its contig.
+        self.genotype.get(copy) == Some(&1)
+    }
+
+    pub fn is_somatic(&self) -> bool {
+        self.origin == VariantOrigin::Somatic
+    }
+}
+
+pub fn assign_genotypes(
+    variant_locations: &mut HashMap<String, Vec<Variant>>,
+    fasta_order: &[String],
+    ploidy_map: &PloidyMap,
+    model: Option<&GenotypeModel>,
+    rng: &mut Rng,
+) -> GenotypeCounts {
+    // Takes:
+    // variant_locations: The variants of each contig. Their genotypes are filled in.
+    // fasta_order: Contig names in reference order. Genotypes are drawn in this order, so a
+    //     seed always gives the same genotypes.
+    // ploidy_map: The number of copies of each part of the genome present in the organism. Every
+    //     variant has to be somewhere the organism has at least one copy.
+    // model: How many copies germline variants are on, if set (see below otherwise).
+    // rng: A random number generator for this run.
+    // Returns:
+    // How many variants were heterozygous and how many homozygous.
+    //
+    // Without a model, in haploid and diploid parts of the genome nearly every germline variant is
+    // on a single copy. Where there are more copies, the number carrying a germline variant is
+    // drawn with weights falling off as 1/k for k copies (as for alleles under neutral drift), so
+    // variants on one copy are the most common but every dosage, up to all of the copies, turns up.
+    let mut genotype_counts = GenotypeCounts::default();
+    let mut dosages: HashMap<usize, DiscreteDistribution> = HashMap::new();
+    for contig in fasta_order {
+        let Some(variants) = variant_locations.get_mut(contig) else { continue };
+        for mutation in variants {
+            let ploidy = ploidy_map.copies(contig, mutation.position);
+            if (model.is_some() || ploidy > 2) && !mutation.is_somatic() {
+                let dosage = dosages.entry(ploidy).or_insert_with(|| {
+                    let weights: Vec<f64> = match model {
+                        Some(model) => model.dosage_weights(ploidy),
+                        None => (1..=ploidy).map(|copies| 1.0 / copies as f64).collect(),
+                    };
+                    DiscreteDistribution::new(&weights, false)
+                });
+                let num_copies = 1 + dosage.sample(rng);
+                let mut copies: Vec<usize> = (0..ploidy).collect();
+                rng.shuffle_in_place(&mut copies);
+                let mut genotype = vec![0; ploidy];
+                for copy in &copies[..num_copies] {
+                    genotype[*copy] = 1;
+                }
+                if num_copies == ploidy {
+                    genotype_counts.homozygous += 1;
+                } else {
+                    genotype_counts.heterozygous += 1;
+                }
+                mutation.genotype = genotype;
+                continue
+            }
+            // We need to enumerate the index list for the genotype
+            let ploid_index: Vec<usize> = (0..ploidy).collect();
+            // If we're going to mutate more than one ploid (i.e. homozygous
+            // for diploid organisms), we must add it to the list.
+            let mut genotype: Vec<usize> = vec![0; ploidy];
+            // By default we'll assume heterozygous (only on one ploid).
+            let mut num_ploids: usize = 1;
+            let is_multiploid = rng.gen_bool(0.001);
+            // If ploidy is only 1, then it doesn't matter. Somatic variants arise in a single
+            // copy, so they are always left heterozygous.
+            if is_multiploid && ploidy > 1 && !mutation.is_somatic() {
+                // Mod a random int by ploidy and add to 1 (since we are modifying at least one
+                // copy). For example, with a ploidy of 2 the right term will produce either
+                // 0 or 1, so we modify either 1 or 2 copies.
+                num_ploids = 1 + rng.rand_int() as usize % ploidy;
+            }
+            for _ in 0..num_ploids {
+                // for each ploid that has the mutation, change one random
+                // genotype to 1, indicating the mutation is on that copy.
+                genotype[rng.choose(&ploid_index)] = 1
+            }
+            if genotype.iter().all(|copy| *copy == 1) {
+                genotype_counts.homozygous += 1;
+            } else {
+                genotype_counts.heterozygous += 1;
+            }
+            mutation.genotype = genotype;
+        }
+    }
+    genotype_counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ploidy::Sex;
+
+    #[test]
+    fn test_variant_origin() {
+        let germline = Variant::new(10, 0, 1, VariantOrigin::Germline);
+        let somatic = Variant::new(12, 3, 1, VariantOrigin::Somatic);
+        assert!(!germline.is_somatic());
+        assert!(somatic.is_somatic());
+        assert_eq!(somatic.position, 12);
+    }
+
+    #[test]
+    fn test_assign_genotypes() {
+        let mut variant_locations = HashMap::from([
+            ("chr1".to_string(), (0..200)
+                .map(|position| Variant::new(position, 0, 1, VariantOrigin::Germline))
+                .collect::<Vec<Variant>>()),
+            ("chr2".to_string(), vec![Variant::new(5, 3, 1, VariantOrigin::Somatic)]),
+        ]);
+        let order = vec!["chr1".to_string(), "chr2".to_string()];
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Genotypes".to_string()]);
+        let counts = assign_genotypes(
+            &mut variant_locations, &order, &PloidyMap::uniform(2), None, &mut rng
+        );
+        assert_eq!(counts.heterozygous + counts.homozygous, 201);
+        for variant in variant_locations.values().flatten() {
+            assert_eq!(variant.genotype.len(), 2);
+            assert!(variant.on_copy(0) || variant.on_copy(1));
+        }
+        let somatic = &variant_locations["chr2"][0];
+        assert_eq!(somatic.genotype.iter().sum::<usize>(), 1);
+
+        // A male X only has one copy outside its PAR, so its variants are haploid there.
+        let fasta_map = HashMap::from([("chrX".to_string(), vec![0; 200])]);
+        let pars = HashMap::from([("chrX".to_string(), vec![(0, 100)])]);
+        let ploidy_map =
+            PloidyMap::for_sample(Sex::Male, 2, 1.0, &fasta_map, Some(&pars)).unwrap();
+        let mut variant_locations = HashMap::from([("chrX".to_string(), vec![
+            Variant::new(50, 1, 0, VariantOrigin::Germline),
+            Variant::new(150, 1, 0, VariantOrigin::Germline),
+        ])]);
+        let order = vec!["chrX".to_string()];
+        assign_genotypes(&mut variant_locations, &order, &ploidy_map, None, &mut rng);
+        assert_eq!(variant_locations["chrX"][0].genotype.len(), 2);
+        assert_eq!(variant_locations["chrX"][1].genotype, vec![1]);
+    }
+
+    #[test]
+    fn test_genotype_model() {
+        let dosages = GenotypeModel::Dosages(vec![(1, 3.0), (2, 1.0)]);
+        assert_eq!(dosages.dosage_weights(2), vec![3.0, 1.0]);
+        // A haploid site only has the one copy to put them on
+        assert_eq!(dosages.dosage_weights(1), vec![4.0]);
+        assert_eq!(dosages.dosage_weights(3), vec![3.0, 1.0, 0.0]);
+        let weights = GenotypeModel::HardyWeinberg(0.5).dosage_weights(2);
+        assert_eq!(weights, vec![0.5, 0.25]);
+
+        // Half hom-alt, as asked for
+        let mut variant_locations = HashMap::from([("chr1".to_string(), (0..2000)
+            .map(|position| Variant::new(position, 0, 1, VariantOrigin::Germline))
+            .collect::<Vec<Variant>>())]);
+        let order = vec!["chr1".to_string()];
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Model".to_string()]);
+        let model = GenotypeModel::Dosages(vec![(1, 1.0), (2, 1.0)]);
+        let counts = assign_genotypes(
+            &mut variant_locations, &order, &PloidyMap::uniform(2), Some(&model), &mut rng
+        );
+        assert_eq!(counts.heterozygous + counts.homozygous, 2000);
+        assert!((900..1100).contains(&counts.homozygous));
+        for variant in &variant_locations["chr1"] {
+            let dosage = variant.genotype.iter().sum::<usize>();
+            assert!((1..=2).contains(&dosage));
+        }
+    }
+
+    #[test]
+    fn test_polyploid_genotypes() {
+        // Tetraploid germline variants come in every dosage, one copy the most often, while
+        // somatic ones stay on a single copy.
+        let mut variant_locations = HashMap::from([("chr1".to_string(), (0..2000)
+            .map(|position| Variant::new(position, 0, 1, VariantOrigin::Germline))
+            .chain([Variant::new(2000, 0, 1, VariantOrigin::Somatic)])
+            .collect::<Vec<Variant>>())]);
+        let order = vec!["chr1".to_string()];
+        let mut rng = Rng::new_from_seed(vec!["Hello".to_string(), "Polyploid".to_string()]);
+        let counts = assign_genotypes(
+            &mut variant_locations, &order, &PloidyMap::uniform(4), None, &mut rng
+        );
+        let mut dosages = [0; 5];
+        for variant in &variant_locations["chr1"][..2000] {
+            assert_eq!(variant.genotype.len(), 4);
+            dosages[variant.genotype.iter().sum::<usize>()] += 1;
+        }
+        assert_eq!(dosages[0], 0);
+        assert!(dosages[1] > dosages[2] && dosages[2] > dosages[3] && dosages[3] > dosages[4]);
+        assert!(dosages[4] > 200);
+        assert_eq!(counts.homozygous, dosages[4]);
+        assert_eq!(variant_locations["chr1"][2000].genotype.iter().sum::<usize>(), 1);
+    }
+}
@@ -4,8 +4,8 @@ use std::collections::HashMap;
 use std::io;
 use std::io::Write;
 use super::nucleotides::u8_to_base;
-use super::file_tools::open_file;
-use simple_rng::Rng;
+use super::provenance::Provenance;
+use super::variants::Variant;
 
 fn genotype_to_string(genotype: Vec<usize>) -> String {
     /*
@@ -20,83 +20,95 @@ fn genotype_to_string(genotype: Vec<usize>) -> String {
 }
 
 pub fn write_vcf(
-    variant_locations: &HashMap<String, Vec<(usize, u8, u8)>>,
-    fasta_order: &Vec<String>,
-    ploidy: usize,
+    outfile: &mut impl Write,
+    variant_locations: &HashMap<String, Vec<Variant>>,
+    fasta_order: &[String],
     reference_path: &str,
-    overwrite_output: bool,
-    output_file_prefix: &str,
-    mut rng: &mut Rng,
+    provenance: &Provenance,
 ) -> io::Result<()> {
     /*
     Takes:
-        variant_locations: A map of contig names keyed to lists of variants in that contig
-            consisting of a tuple of (position, alt base, ref base).
+        outfile: Where to write the vcf (a file, stdout, an in-memory buffer...)
+        variant_locations: A map of contig names keyed to lists of variants in that contig, with
+            their genotypes assigned. Somatic variants are flagged with SOMATIC in the INFO
+            column, heteroplasmic ones get their fraction as AF (quasispecies ones their
+            frequency), and annotated variants get their GENE and EFFECT there. Variants put at
+            known sites get the site's ID, and variants called from the reads (see
+            genotype_quality) get their DP, AD, GQ and PL, which are only declared in the header
+            if there are any.
         fasta_order: A vector of contig names in the order of the reference fasta.
-        ploidy: The number of copies of each chromosome present in the organism
         reference_path: The location of the reference file this vcf is showing variants from.
-        output_file_prefix: The path to the directory and the prefix to use for filenames
-        rng: A random number generator for this run
+        provenance: The seed, version and config hash of the run, for the header.
     Result:
-        Throws and error if there's a problem, or else returns nothing.
+        Throws and error if there's a problem, or else nothing.
      */
-    // set the filename of the output vcf
-    let mut filename = format!("{}.vcf", output_file_prefix);
-    let mut outfile = open_file(&mut filename, overwrite_output)
-        .expect(&format!("Problem opening {} for output.", filename));
     // add the vcf header
-    writeln!(&mut outfile, "##fileformat=VCFv4.1")?;
-    writeln!(&mut outfile, "##reference={}", reference_path)?;
-    writeln!(&mut outfile, "##Generated by rusty-neat")?;
-    writeln!(&mut outfile, "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Total Depth\">")?;
-    writeln!(&mut outfile, "##INFO=<ID=AF,Number=A,Type=Float,Description=\"Allele Frequency\">")?;
-    writeln!(&mut outfile, "##INFO=<ID=VMX,Number=1,Type=String, Description=\"SNP is Missense in these Read Frames\">")?;
-    writeln!(&mut outfile, "##INFO=<ID=VNX,Number=1,Type=String, Description=\"SNP is Nonsense in these Read Frames\">")?;
-    writeln!(&mut outfile, "##INFO=<ID=VFX,Number=1,Type=String,Description=\"Indel Causes Frameshift\">")?;
-    writeln!(&mut outfile, "##ALT=<ID=DEL,Description=\"Deletion\">")?;
-    writeln!(&mut outfile, "##ALT=<ID=DUP,Description=\"Duplication\">")?;
-    writeln!(&mut outfile, "##ALT=<ID=INS,Description=\"Insertion of novel sequence\">")?;
-    writeln!(&mut outfile, "##ALT=<ID=INV,Description=\"Inversion\">")?;
-    writeln!(&mut outfile, "##ALT=<ID=CNV,Description=\"Copy number variable region\">")?;
-    writeln!(&mut outfile, "##ALT=<ID=TRANS,Description=\"Translocation\">")?;
-    writeln!(&mut outfile, "##ALT=<ID=INV-TRANS,Description=\"Inverted translocation\">")?;
-    writeln!(&mut outfile, "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">")?;
+    writeln!(outfile, "##fileformat=VCFv4.1")?;
+    writeln!(outfile, "##reference={}", reference_path)?;
+    writeln!(outfile, "##Generated by rusty-neat")?;
+    write!(outfile, "{}", provenance.vcf_header_lines())?;
+    writeln!(outfile, "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Total Depth\">")?;
+    writeln!(outfile, "##INFO=<ID=AF,Number=A,Type=Float,Description=\"Allele Frequency\">")?;
+    writeln!(outfile, "##INFO=<ID=VMX,Number=1,Type=String, Description=\"SNP is Missense in these Read Frames\">")?;
+    writeln!(outfile, "##INFO=<ID=VNX,Number=1,Type=String, Description=\"SNP is Nonsense in these Read Frames\">")?;
+    writeln!(outfile, "##INFO=<ID=VFX,Number=1,Type=String,Description=\"Indel Causes Frameshift\">")?;
+    writeln!(outfile, "##INFO=<ID=SOMATIC,Number=0,Type=Flag,Description=\"Somatic mutation\">")?;
+    writeln!(outfile, "##INFO=<ID=GENE,Number=1,Type=String,Description=\"Gene the variant is in\">")?;
+    writeln!(outfile, "##INFO=<ID=EFFECT,Number=1,Type=String,Description=\"Effect on the gene (intron, noncoding_exon, utr, coding, synonymous, missense, stop_lost or nonsense)\">")?;
+    writeln!(outfile, "##ALT=<ID=DEL,Description=\"Deletion\">")?;
+    writeln!(outfile, "##ALT=<ID=DUP,Description=\"Duplication\">")?;
+    writeln!(outfile, "##ALT=<ID=INS,Description=\"Insertion of novel sequence\">")?;
+    writeln!(outfile, "##ALT=<ID=INV,Description=\"Inversion\">")?;
+    writeln!(outfile, "##ALT=<ID=CNV,Description=\"Copy number variable region\">")?;
+    writeln!(outfile, "##ALT=<ID=TRANS,Description=\"Translocation\">")?;
+    writeln!(outfile, "##ALT=<ID=INV-TRANS,Description=\"Inverted translocation\">")?;
+    writeln!(outfile, "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">")?;
+    if variant_locations.values().flatten().any(|variant| variant.call.is_some()) {
+        writeln!(outfile, "##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Simulated read depth\">")?;
+        writeln!(outfile, "##FORMAT=<ID=AD,Number=R,Type=Integer,Description=\"Simulated reads for the ref and alt alleles\">")?;
+        writeln!(outfile, "##FORMAT=<ID=GQ,Number=1,Type=Integer,Description=\"Genotype quality from the simulated reads\">")?;
+        writeln!(outfile, "##FORMAT=<ID=PL,Number=G,Type=Integer,Description=\"Phred scaled genotype likelihoods from the simulated reads\">")?;
+    }
     // Add a neat sample column
-    writeln!(&mut outfile, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tNEAT_simulated_sample")?;
+    writeln!(outfile, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tNEAT_simulated_sample")?;
     // insert mutations
     for contig in fasta_order {
         for mutation in &variant_locations[contig] {
-            // If we're going to mutate more than one ploid (i.e. homozygous
-            // for diploid organisms), we must add it to the list.
-            let mut genotype: Vec<usize> = vec![0; ploidy];
-            // We need to enumerate the index list for the genotype
-            let ploid_index: Vec<usize> = (0..ploidy).collect();
-            // By default we'll assume heterozygous (only on one ploid).
-            let mut num_ploids: usize = 1;
-            let is_multiploid = (&mut rng).gen_bool(0.001);
-            // If ploidy is only 1, then it doesn't matter
-            if is_multiploid && ploidy > 1 {
-                // Mod a random int by ploidy and add to 1 (since we are modifying at least one
-                // copy). For example, with a ploidy of 2 the right term will produce either
-                // 0 or 1, so we modify either 1 or 2 copies.
-                num_ploids = 1 + rng.rand_int() as usize % ploidy;
+            let mut info_fields: Vec<String> = Vec::new();
+            if mutation.is_somatic() {
+                info_fields.push("SOMATIC".to_string());
+            }
+            if let Some(fraction) = mutation.heteroplasmy.or(mutation.frequency) {
+                info_fields.push(format!("AF={}", fraction));
             }
-            for _ in 0..num_ploids {
-                // for each ploid that has the mutation, change one random
-                // genotype to 1, indicating the mutation is on that copy.
-                genotype[rng.choose(&ploid_index)] = 1
+            if let Some(annotation) = &mutation.annotation {
+                info_fields.push(format!("GENE={}", annotation.gene));
+                info_fields.push(format!("EFFECT={}", annotation.effect.as_str()));
             }
+            let info = match info_fields.is_empty() {
+                true => ".".to_string(),
+                false => info_fields.join(";"),
+            };
+            // Variants called from the reads get their depths and qualities after the genotype.
+            let (format, call) = match &mutation.call {
+                Some(call) => ("GT:DP:AD:GQ:PL", format!(":{}", call.format_fields())),
+                None => ("GT", String::new()),
+            };
             // Format the output line. Any fields without data will be a simple period. Quality
             // is set to 37 for all these variants.
-            let line = format!("{}\t{}\t.\t{}\t{}\t37\tPASS\t.\tGT\t{}",
+            let line = format!("{}\t{}\t{}\t{}\t{}\t37\tPASS\t{}\t{}\t{}{}",
                                contig,
-                               mutation.0 + 1,
-                               u8_to_base(mutation.2),
-                               u8_to_base(mutation.1),
-                               genotype_to_string(genotype),
+                               mutation.position + 1,
+                               mutation.id.as_deref().unwrap_or("."),
+                               u8_to_base(mutation.reference),
+                               u8_to_base(mutation.alt),
+                               info,
+                               format,
+                               genotype_to_string(mutation.genotype.clone()),
+                               call,
                 );
 
-            writeln!(&mut outfile, "{}", line)?;
+            writeln!(outfile, "{}", line)?;
         }
     };
     Ok(())
@@ -104,9 +116,10 @@ pub fn write_vcf(
 
 #[cfg(test)]
 mod tests {
-    use std::fs;
     use super::*;
-    use std::path::Path;
+    use super::super::config::ConfigBuilder;
+    use super::super::genotype_quality::SiteCall;
+    use super::super::variants::{VariantAnnotation, VariantEffect, VariantOrigin};
 
     #[test]
     fn test_genotype_to_string() {
@@ -116,29 +129,56 @@ mod tests {
 
     #[test]
     fn test_write_vcf() {
+        let mut germline = Variant::new(3, 0, 1, VariantOrigin::Germline);
+        germline.genotype = vec![1, 1];
+        germline.id = Some("rs123".to_string());
+        let mut somatic = Variant::new(7, 1, 2, VariantOrigin::Somatic);
+        somatic.genotype = vec![0, 1];
+        somatic.call = Some(SiteCall {
+            depth: 30,
+            ref_depth: 25,
+            alt_depth: 4,
+            genotype_quality: 12,
+            likelihoods: vec![12, 0, 250],
+        });
+        somatic.annotation = Some(VariantAnnotation {
+            gene: "HA".to_string(),
+            effect: VariantEffect::Missense,
+        });
+        let mut mitochondrial = Variant::new(100, 3, 0, VariantOrigin::Germline);
+        mitochondrial.genotype = vec![1];
+        mitochondrial.heteroplasmy = Some(0.15);
         let variant_locations = HashMap::from([
-            ("chr1".to_string(), vec![(3, 0, 1), (7, 1, 2)])
+            ("chr1".to_string(), vec![germline, somatic]),
+            ("chrM".to_string(), vec![mitochondrial]),
         ]);
-        let fasta_order = vec!["chr1".to_string()];
-        let ploidy = 2;
+        let fasta_order = vec!["chr1".to_string(), "chrM".to_string()];
         let reference_path = "/fake/path/to/H1N1.fa";
-        let overwrite_output = false;
-        let output_file_prefix = "test";
-        let mut rng = simple_rng::Rng::new_from_seed(vec![
-            "Hello".to_string(),
-            "Cruel".to_string(),
-            "World".to_string(),
-        ]);
+        let mut config = ConfigBuilder::new();
+        config.reference = Some(reference_path.to_string());
+        config.rng_seed = Some("Hello VCF".to_string());
+        let provenance = Provenance::new(&config.build());
+        let mut buffer: Vec<u8> = Vec::new();
         write_vcf(
+            &mut buffer,
             &variant_locations,
             &fasta_order,
-            ploidy,
             reference_path,
-            overwrite_output,
-            output_file_prefix,
-            &mut rng,
+            &provenance,
         ).unwrap();
-        assert!(Path::new("test.vcf").exists());
-        fs::remove_file("test.vcf").unwrap();
+        let contents = String::from_utf8(buffer).unwrap();
+        assert!(contents.starts_with("##fileformat=VCFv4.1\n##reference=/fake/path/to/H1N1.fa"));
+        assert!(contents.contains("\n##neat_seed=Hello VCF\n"));
+        assert!(contents.contains(&format!("\n##neat_config_hash={}\n", provenance.config_hash_hex())));
+        assert!(contents.contains("chr1\t4\trs123\tC\tA\t37\tPASS\t.\tGT\t1/1\n"));
+        assert!(contents.contains("chrM\t101\t.\tA\tT\t37\tPASS\tAF=0.15\tGT\t1\n"));
+        assert!(contents.contains("chr1\t8\t.\tG\tC\t37\tPASS\tSOMATIC;GENE=HA;EFFECT=missense\tGT:DP:AD:GQ:PL\t0/1:30:25,4:12:12,0,250\n"));
+        assert!(contents.contains("\n##FORMAT=<ID=AD,"));
+        // Without calls the header only declares GT
+        let mut buffer: Vec<u8> = Vec::new();
+        let uncalled: HashMap<String, Vec<Variant>> =
+            fasta_order.iter().map(|contig| (contig.clone(), Vec::new())).collect();
+        write_vcf(&mut buffer, &uncalled, &fasta_order, reference_path, &provenance).unwrap();
+        assert!(!String::from_utf8(buffer).unwrap().contains("##FORMAT=<ID=AD,"));
     }
 }
\ No newline at end of file